@@ -0,0 +1,3 @@
+ALTER TABLE job_configs
+ADD COLUMN checkpoint_sla_max_duration_micros BIGINT,
+ADD COLUMN checkpoint_sla_max_interval_micros BIGINT;
@@ -168,7 +168,7 @@ WHERE pub_id = :pub_id AND organization_id = :organization_id;
 
 ----------- jobs -----------------------
 
---! update_job(checkpoint_interval_micros?, stop?, parallelism_overrides?)
+--! update_job(checkpoint_interval_micros?, stop?, parallelism_overrides?, unaligned_checkpoints?, checkpoint_sla_max_duration_micros?, checkpoint_sla_max_interval_micros?)
 UPDATE job_configs
 SET
    updated_at = :updated_at,
@@ -176,7 +176,10 @@ SET
 
    stop = COALESCE(:stop, stop),
    checkpoint_interval_micros = COALESCE(:checkpoint_interval_micros, checkpoint_interval_micros),
-   parallelism_overrides = COALESCE(:parallelism_overrides, parallelism_overrides)
+   parallelism_overrides = COALESCE(:parallelism_overrides, parallelism_overrides),
+   unaligned_checkpoints = COALESCE(:unaligned_checkpoints, unaligned_checkpoints),
+   checkpoint_sla_max_duration_micros = COALESCE(:checkpoint_sla_max_duration_micros, checkpoint_sla_max_duration_micros),
+   checkpoint_sla_max_interval_micros = COALESCE(:checkpoint_sla_max_interval_micros, checkpoint_sla_max_interval_micros)
 WHERE id = :job_id AND organization_id = :organization_id;
 
 --! restart_job(mode)
@@ -3,7 +3,7 @@ use crate::queries::api_queries::{
 };
 use arroyo_rpc::api_types::checkpoints::{
     Checkpoint, CheckpointEventSpan, CheckpointSpanType, OperatorCheckpointGroup,
-    SubtaskCheckpointGroup,
+    SubtaskCheckpointGroup, SubtaskStateFile, TableStorageUsage,
 };
 use arroyo_rpc::api_types::pipelines::{JobLogLevel, JobLogMessage, OutputData, StopType};
 use arroyo_rpc::api_types::{
@@ -420,6 +420,18 @@ pub async fn get_checkpoint_details(
                         index: subtask_index.clone(),
                         bytes: subtask_details.bytes.unwrap_or(0),
                         event_spans: get_event_spans(&subtask_details),
+                        files: subtask_details
+                            .files
+                            .iter()
+                            .map(|file| SubtaskStateFile {
+                                table_name: file.table_name.clone(),
+                                path: file.path.clone(),
+                                bytes: file.bytes,
+                            })
+                            .collect(),
+                        omitted_files: subtask_details.omitted_files,
+                        omitted_file_bytes: subtask_details.omitted_file_bytes,
+                        alignment_timed_out: subtask_details.alignment_timed_out,
                     });
                 });
 
@@ -427,6 +439,16 @@ pub async fn get_checkpoint_details(
                 operator_id: operator_id.to_string(),
                 bytes: operator_bytes,
                 subtasks,
+                tables: operator_details
+                    .table_storage
+                    .iter()
+                    .map(|usage| TableStorageUsage {
+                        table_name: usage.table_name.clone(),
+                        files: usage.files,
+                        bytes: usage.bytes,
+                        oldest_epoch: usage.oldest_epoch,
+                    })
+                    .collect(),
             });
         });
 
@@ -319,7 +319,9 @@ impl IntoResponse for HttpError {
         CheckpointSpanType,
         OperatorCheckpointGroupCollection,
         SubtaskCheckpointGroup,
+        SubtaskStateFile,
         OperatorCheckpointGroup,
+        TableStorageUsage,
         ValidateQueryPost,
         QueryValidationResult,
         ValidateUdfPost,
@@ -702,6 +702,13 @@ pub async fn patch_pipeline(
             &stop,
             &interval.map(|i| i.as_micros() as i64),
             &parallelism_overrides,
+            &pipeline_patch.unaligned_checkpoints,
+            &pipeline_patch
+                .checkpoint_sla_max_duration_micros
+                .map(|m| m as i64),
+            &pipeline_patch
+                .checkpoint_sla_max_interval_micros
+                .map(|m| m as i64),
             &job_id,
             &auth_data.organization_id,
         )
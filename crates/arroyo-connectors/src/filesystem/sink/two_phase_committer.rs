@@ -113,6 +113,8 @@ impl<TPC: TwoPhaseCommitter> TwoPhaseCommitterOperator<TPC> {
             subtask_index: ctx.task_info.task_index as u32,
             time: SystemTime::now(),
             event_type: arroyo_rpc::grpc::TaskCheckpointEventType::FinishedCommit.into(),
+            in_flight_records: 0,
+            in_flight_bytes: 0,
         });
         ctx.control_tx
             .send(checkpoint_event)
@@ -145,8 +147,10 @@ impl<TPC: TwoPhaseCommitter> ArrowOperator for TwoPhaseCommitterOperator<TPC> {
                     table_name: "p".into(),
                     description: "pre-commit data".into(),
                     uses_two_phase_commit: true,
+                    ..Default::default()
                 }
                 .encode_to_vec(),
+                ..Default::default()
             },
         );
         tables
@@ -227,7 +231,8 @@ impl<TPC: TwoPhaseCommitter> ArrowOperator for TwoPhaseCommitterOperator<TPC> {
             .expect("should be able to get table");
         recovery_data_state
             .insert(ctx.task_info.task_index, recovery_data)
-            .await;
+            .await
+            .expect("should be able to write recovery data");
         self.pre_commits.clear();
         if pre_commits.is_empty() {
             return;
@@ -242,7 +247,10 @@ impl<TPC: TwoPhaseCommitter> ArrowOperator for TwoPhaseCommitterOperator<TPC> {
                     .expect("should be able to get table");
                 for (key, value) in pre_commits {
                     self.pre_commits.push(value.clone());
-                    pre_commit_state.insert(key, value).await;
+                    pre_commit_state
+                        .insert(key, value)
+                        .await
+                        .expect("should be able to write pre-commit data");
                 }
                 ctx.table_manager
                     .insert_committing_data("p", vec![])
@@ -190,7 +190,8 @@ impl FluvioSourceFunc {
                                 s.insert(*partition, FluvioState {
                                     partition: *partition2,
                                     offset: *offset + 1,
-                                }).await;
+                                }).await
+                                    .expect("should be able to write fluvio state");
                             }
 
                             if self.start_checkpoint(c, ctx).await {
@@ -182,7 +182,8 @@ impl ImpulseSourceFunc {
                         .await
                         .unwrap()
                         .insert(ctx.task_info.task_index, self.state)
-                        .await;
+                        .await
+                        .unwrap();
                     if self.start_checkpoint(c, ctx).await {
                         return SourceFinishType::Immediate;
                     }
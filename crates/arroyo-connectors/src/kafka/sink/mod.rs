@@ -199,7 +199,8 @@ impl ArrowOperator for KafkaSinkFunc {
                 .as_mut()
                 .unwrap()
                 .insert(ctx.task_info.task_index, *next_transaction_index)
-                .await;
+                .await
+                .unwrap();
             self.init_producer(&ctx.task_info)
                 .expect("creating new producer during checkpointing");
         }
@@ -243,6 +244,8 @@ impl ArrowOperator for KafkaSinkFunc {
             subtask_index: ctx.task_info.task_index as u32,
             time: SystemTime::now(),
             event_type: arroyo_rpc::grpc::TaskCheckpointEventType::FinishedCommit.into(),
+            in_flight_records: 0,
+            in_flight_bytes: 0,
         });
         ctx.control_tx
             .send(checkpoint_event)
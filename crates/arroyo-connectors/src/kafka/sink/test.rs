@@ -97,7 +97,8 @@ impl KafkaTopicTester {
             vec![vec![]],
             HashMap::new(),
         )
-        .await;
+        .await
+        .unwrap();
 
         kafka.on_start(&mut ctx).await;
 
@@ -160,6 +161,7 @@ async fn test_kafka_checkpoint_flushes() {
         min_epoch: 0,
         timestamp: SystemTime::now(),
         then_stop: false,
+        unaligned: false,
     };
     sink_with_writes
         .sink
@@ -187,7 +187,8 @@ impl KafkaSourceFunc {
                                 s.insert(*partition, KafkaState {
                                     partition: *partition,
                                     offset: *offset + 1,
-                                }).await;
+                                }).await
+                                    .map_err(|err| UserError::new("failed to write kafka state", err.to_string()))?;
                                 topic_partitions.add_partition_offset(
                                     &self.topic, *partition, Offset::Offset(*offset)).unwrap();
                             }
@@ -100,6 +100,8 @@ impl KafkaTopicTester {
             start_time: to_micros(SystemTime::now()),
             finish_time: to_micros(SystemTime::now()),
             operator_ids: vec![task_info.operator_id.clone()],
+            operator_remappings: vec![],
+            ..Default::default()
         });
 
         let mut ctx = ArrowContext::new(
@@ -124,7 +126,8 @@ impl KafkaTopicTester {
             vec![vec![data_tx]],
             kafka.tables(),
         )
-        .await;
+        .await
+        .unwrap();
 
         tokio::spawn(async move {
             kafka.run(&mut ctx).await;
@@ -265,6 +268,7 @@ async fn test_kafka() {
         min_epoch: 0,
         timestamp: (SystemTime::now()),
         then_stop: false,
+        unaligned: false,
     });
     reader.to_control_tx.send(barrier).await.unwrap();
     let checkpoint_completed = reader.assert_control_checkpoint(1).await;
@@ -285,6 +289,7 @@ async fn test_kafka() {
     StateBackend::write_operator_checkpoint_metadata(OperatorCheckpointMetadata {
         start_time: 0,
         finish_time: 0,
+        backend: StateBackend::name().to_string(),
         table_checkpoint_metadata: single_item_hash_map("k", table_metadata),
         table_configs: subtask_metadata.table_configs,
         operator_metadata: Some(OperatorMetadata {
@@ -295,6 +300,7 @@ async fn test_kafka() {
             max_watermark: Some(0),
             parallelism: 1,
         }),
+        ..Default::default()
     })
     .await
     .unwrap();
@@ -306,6 +312,8 @@ async fn test_kafka() {
         start_time: 0,
         finish_time: 0,
         operator_ids: vec![task_info.operator_id.clone()],
+        operator_remappings: vec![],
+        ..Default::default()
     })
     .await
     .unwrap();
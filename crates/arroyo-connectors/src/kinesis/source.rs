@@ -397,7 +397,7 @@ impl KinesisSourceFunc {
                             debug!("starting checkpointing {}", ctx.task_info.task_index);
                             let s = ctx.table_manager.get_global_keyed_state("k").await.unwrap();
                             for (shard_id, shard_state) in &self.shards {
-                                s.insert(shard_id.clone(), shard_state.clone()).await;
+                                s.insert(shard_id.clone(), shard_state.clone()).await.unwrap();
                             }
                             if self.start_checkpoint(c, ctx).await {
                                 return Ok(SourceFinishType::Immediate);
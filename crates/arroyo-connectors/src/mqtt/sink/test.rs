@@ -92,7 +92,8 @@ impl MqttTopicTester {
             vec![vec![]],
             HashMap::new(),
         )
-        .await;
+        .await
+        .unwrap();
 
         mqtt.on_start(&mut ctx).await;
 
@@ -153,7 +153,8 @@ impl MqttTopicTester {
             vec![vec![data_tx]],
             mqtt.tables(),
         )
-        .await;
+        .await
+        .unwrap();
 
         let subscribed = mqtt.subscribed();
         tokio::spawn(async move {
@@ -303,7 +303,8 @@ impl SourceOperator for NexmarkSourceFunc {
                                     event_count: generator.events_count_so_far as usize,
                                 },
                             )
-                            .await;
+                            .await
+                            .expect("should be able to write nexmark state");
                         debug!("starting checkpointing {}", ctx.task_info.task_index);
                         if self.start_checkpoint(c, ctx).await {
                             return SourceFinishType::Immediate;
@@ -97,7 +97,9 @@ impl PollingHttpSourceFunc {
                     .get_global_keyed_state("s")
                     .await
                     .expect("should be able to get http state");
-                s.insert((), state).await;
+                s.insert((), state)
+                    .await
+                    .expect("should be able to write http state");
 
                 if self.start_checkpoint(c, ctx).await {
                     return Some(SourceFinishType::Immediate);
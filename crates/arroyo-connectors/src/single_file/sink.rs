@@ -86,6 +86,7 @@ impl ArrowOperator for SingleFileSink {
                 self.output_path.clone(),
                 self.file.as_ref().unwrap().metadata().await.unwrap().len(),
             )
-            .await;
+            .await
+            .unwrap();
     }
 }
@@ -97,7 +97,10 @@ impl SingleFileSourceFunc {
                     String,
                     usize,
                 > = ctx.table_manager.get_global_keyed_state("f").await.unwrap();
-                state.insert(self.input_file.clone(), self.lines_read).await;
+                state
+                    .insert(self.input_file.clone(), self.lines_read)
+                    .await
+                    .unwrap();
                 // checkpoint our state
                 if self.start_checkpoint(c, ctx).await {
                     return Some(SourceFinishType::Immediate);
@@ -103,7 +103,9 @@ impl SSESourceFunc {
                     .get_global_keyed_state("e")
                     .await
                     .expect("should be able to get SSE state");
-                s.insert((), self.state.clone()).await;
+                s.insert((), self.state.clone())
+                    .await
+                    .expect("should be able to write SSE state");
 
                 if self.start_checkpoint(c, ctx).await {
                     return Some(SourceFinishType::Immediate);
@@ -88,7 +88,9 @@ impl WebsocketSourceFunc {
                     .get_global_keyed_state("e")
                     .await
                     .expect("couldn't get state for websocket");
-                s.insert((), self.state.clone()).await;
+                s.insert((), self.state.clone())
+                    .await
+                    .expect("couldn't write state for websocket");
 
                 if self.start_checkpoint(c, ctx).await {
                     return Some(SourceFinishType::Immediate);
@@ -1,4 +1,4 @@
---! all_jobs : Job(ttl_micros?, state?, start_time?, finish_time?, tasks?, failure_message?, run_id?, pipeline_path?, wasm_path?)
+--! all_jobs : Job(ttl_micros?, state?, start_time?, finish_time?, tasks?, failure_message?, run_id?, pipeline_path?, wasm_path?, checkpoint_sla_max_duration_micros?, checkpoint_sla_max_interval_micros?)
 SELECT
     job_configs.id as id,
     job_configs.organization_id as org_id,
@@ -19,7 +19,10 @@ SELECT
     wasm_path,
     job_configs.restart_nonce as config_restart_nonce,
     job_statuses.restart_nonce as status_restart_nonce,
-    restart_mode
+    restart_mode,
+    unaligned_checkpoints,
+    checkpoint_sla_max_duration_micros,
+    checkpoint_sla_max_interval_micros
 FROM job_configs
 LEFT JOIN job_statuses ON job_configs.id = job_statuses.id;
 
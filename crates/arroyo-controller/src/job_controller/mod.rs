@@ -8,19 +8,24 @@ use crate::types::public::StopMode as SqlStopMode;
 use anyhow::bail;
 use arroyo_rpc::grpc::{
     worker_grpc_client::WorkerGrpcClient, CheckpointReq, CommitReq, JobFinishedReq,
-    LoadCompactedDataReq, StopExecutionReq, StopMode, TaskCheckpointEventType,
+    LoadCompactedDataReq, OperatorRemapping, StopExecutionReq, StopMode,
 };
 use arroyo_state::{BackingStore, StateBackend};
-use arroyo_types::{to_micros, WorkerId};
+use arroyo_types::{to_micros, u32_config, WorkerId};
 
 use deadpool_postgres::Pool;
 use time::OffsetDateTime;
 
 use arroyo_datastream::logical::LogicalProgram;
 use arroyo_rpc::public_ids::{generate_id, IdTypes};
+use arroyo_state::checkpoint_events::{CheckpointEventBus, CheckpointLifecycleEvent};
+use arroyo_state::checkpoint_sla::CheckpointSlaMonitor;
 use arroyo_state::checkpoint_state::CheckpointState;
-use arroyo_state::parquet::ParquetBackend;
-use tokio::{sync::mpsc::Receiver, task::JoinHandle};
+use arroyo_state::parquet::{ParquetBackend, COMPACTION_INTERVAL_EPOCHS_ENV};
+use tokio::{
+    sync::{broadcast, mpsc::Receiver},
+    task::JoinHandle,
+};
 use tonic::{transport::Channel, Request};
 use tracing::{error, info, warn};
 
@@ -86,6 +91,11 @@ pub struct RunningJobModel {
     workers: HashMap<WorkerId, WorkerStatus>,
     tasks: HashMap<(String, u32), TaskStatus>,
     operator_parallelism: HashMap<String, usize>,
+    // operator remapping applied on restore, recorded in the next checkpoint
+    operator_remappings: Vec<OperatorRemapping>,
+    checkpoint_sla: CheckpointSlaMonitor,
+    // carries the lifecycle events of every checkpoint this controller takes
+    checkpoint_events: CheckpointEventBus,
 }
 
 impl std::fmt::Debug for RunningJobModel {
@@ -173,13 +183,9 @@ impl RunningJobModel {
                                 Self::update_db(checkpoint_state, pool).await?
                             }
                             CheckpointingOrCommittingState::Committing(committing_state) => {
-                                if matches!(c.event_type(), TaskCheckpointEventType::FinishedCommit)
-                                {
-                                    committing_state
-                                        .subtask_committed(c.operator_id.clone(), c.subtask_index);
-                                    self.compact_state().await?;
-                                } else {
-                                    warn!("unexpected checkpoint event type {:?}", c.event_type())
+                                match committing_state.commit_event(c) {
+                                    Ok(()) => self.compact_state().await?,
+                                    Err(e) => warn!("{:?}", e),
                                 }
                             }
                         };
@@ -241,6 +247,24 @@ impl RunningJobModel {
                 reason,
                 ..
             } => {
+                if let Some(CheckpointingOrCommittingState::Checkpointing(checkpoint_state)) =
+                    &mut self.checkpoint_state
+                {
+                    let metadata_written =
+                        checkpoint_state.fail_subtask(&operator_id, subtask_index, &reason)?;
+                    info!(
+                        message = "Checkpoint failed",
+                        job_id = self.job_id,
+                        epoch = self.epoch,
+                        metadata_written,
+                    );
+                    Self::update_checkpoint_in_db(
+                        checkpoint_state,
+                        pool,
+                        DbCheckpointState::failed,
+                    )
+                    .await?;
+                }
                 let key = (operator_id, subtask_index);
                 if let Some(status) = self.tasks.get_mut(&key) {
                     status.state = TaskState::Failed(reason);
@@ -303,6 +327,7 @@ impl RunningJobModel {
         organization_id: &str,
         pool: &Pool,
         then_stop: bool,
+        unaligned: bool,
     ) -> anyhow::Result<()> {
         self.epoch += 1;
 
@@ -310,7 +335,8 @@ impl RunningJobModel {
             message = "Starting checkpointing",
             job_id = self.job_id,
             epoch = self.epoch,
-            then_stop
+            then_stop,
+            unaligned
         );
 
         // TODO: maybe parallelize
@@ -323,6 +349,7 @@ impl RunningJobModel {
                     min_epoch: self.min_epoch,
                     then_stop,
                     is_commit: false,
+                    unaligned,
                 }))
                 .await?;
         }
@@ -344,13 +371,16 @@ impl RunningJobModel {
                 .await?
         };
 
-        let state = CheckpointState::new(
+        let mut state = CheckpointState::new(
             self.job_id.clone(),
             checkpoint_id,
             self.epoch,
             self.min_epoch,
             self.program.tasks_per_operator(),
-        );
+        )?;
+        state.set_operator_remappings(self.operator_remappings.clone());
+        state.set_stateless_operators(self.program.stateless_operators())?;
+        state.set_event_bus(self.checkpoint_events.clone());
 
         self.checkpoint_state = Some(CheckpointingOrCommittingState::Checkpointing(state));
 
@@ -368,6 +398,11 @@ impl RunningJobModel {
             return Ok(());
         }
 
+        let interval = u32_config(COMPACTION_INTERVAL_EPOCHS_ENV, 1).max(1);
+        if self.epoch % interval != 0 {
+            return Ok(());
+        }
+
         info!("Compacting state");
 
         let mut worker_clients: Vec<WorkerGrpcClient<Channel>> =
@@ -402,8 +437,11 @@ impl RunningJobModel {
         if self.checkpoint_state.as_ref().unwrap().done() {
             let state = self.checkpoint_state.take().unwrap();
             match state {
-                CheckpointingOrCommittingState::Checkpointing(checkpointing) => {
+                CheckpointingOrCommittingState::Checkpointing(mut checkpointing) => {
                     checkpointing.save_state().await?;
+                    // only the first checkpoint after the restore records the remapping
+                    self.operator_remappings.clear();
+                    self.checkpoint_sla.checkpoint_completed(&checkpointing);
 
                     let committing_state = checkpointing.committing_state();
                     let duration = checkpointing
@@ -593,6 +631,9 @@ impl JobController {
                     })
                     .collect(),
                 operator_parallelism: program.tasks_per_operator(),
+                operator_remappings: vec![],
+                checkpoint_sla: CheckpointSlaMonitor::new(config.id.clone(), config.checkpoint_sla),
+                checkpoint_events: CheckpointEventBus::default(),
                 program,
             },
             config,
@@ -604,12 +645,53 @@ impl JobController {
         self.model.handle_message(msg, &self.pool).await
     }
 
+    /// Receives the lifecycle events of the checkpoints taken from now on, until the job is
+    /// next rescheduled.
+    pub fn subscribe_checkpoint_events(&self) -> broadcast::Receiver<CheckpointLifecycleEvent> {
+        self.model.checkpoint_events.subscribe()
+    }
+
     pub async fn progress(&mut self) -> anyhow::Result<ControllerProgress> {
         // have any of our workers failed?
         if self.model.failed() {
+            if let Some(CheckpointingOrCommittingState::Checkpointing(checkpointing)) =
+                &self.model.checkpoint_state
+            {
+                self.model.checkpoint_sla.checkpoint_failed(checkpointing);
+            }
             bail!("worker failed");
         }
 
+        let in_progress = match &self.model.checkpoint_state {
+            Some(CheckpointingOrCommittingState::Checkpointing(checkpointing)) => {
+                Some(checkpointing)
+            }
+            _ => None,
+        };
+        self.model
+            .checkpoint_sla
+            .check(in_progress, self.model.epoch + 1);
+
+        if let (Some(timeout), Some(CheckpointingOrCommittingState::Checkpointing(checkpointing))) = (
+            self.config.alignment_timeout,
+            self.model.checkpoint_state.as_mut(),
+        ) {
+            // workers can't yet switch an epoch to unaligned, so stalls are only reported,
+            // here and in the checkpoint's details
+            for (operator_id, subtask_index, aligning_for) in
+                checkpointing.take_stalled_alignments(SystemTime::now(), timeout)
+            {
+                warn!(
+                    message = "Checkpoint alignment exceeded timeout",
+                    job_id = self.config.id,
+                    epoch = checkpointing.epoch(),
+                    operator_id,
+                    subtask_index,
+                    aligning_for_ms = aligning_for.as_millis() as u64
+                );
+            }
+        }
+
         // have any of our tasks finished?
         if self.model.any_finished_sources() {
             return Ok(ControllerProgress::Finishing);
@@ -685,7 +767,12 @@ impl JobController {
     pub async fn checkpoint(&mut self, then_stop: bool) -> anyhow::Result<bool> {
         if self.model.checkpoint_state.is_none() {
             self.model
-                .start_checkpoint(&self.config.organization_id, &self.pool, then_stop)
+                .start_checkpoint(
+                    &self.config.organization_id,
+                    &self.pool,
+                    then_stop,
+                    self.config.unaligned_checkpoints,
+                )
                 .await?;
             Ok(true)
         } else {
@@ -752,6 +839,10 @@ impl JobController {
         }
     }
 
+    pub fn set_operator_remappings(&mut self, operator_remappings: Vec<OperatorRemapping>) {
+        self.model.operator_remappings = operator_remappings;
+    }
+
     pub fn operator_parallelism(&self, op: &str) -> Option<usize> {
         self.model.operator_parallelism.get(op).cloned()
     }
@@ -17,7 +17,12 @@ use arroyo_rpc::grpc::{
 };
 use arroyo_rpc::public_ids::{generate_id, IdTypes};
 use arroyo_server_common::shutdown::ShutdownGuard;
-use arroyo_types::{from_micros, grpc_port, ports, NodeId, WorkerId};
+use arroyo_state::checkpoint_sla::CheckpointSlaConfig;
+use arroyo_types::{
+    from_micros, grpc_port, ports, NodeId, WorkerId, ALLOW_NON_RESTORED_STATE_ENV,
+    CHECKPOINT_ALIGNMENT_TIMEOUT_MS_ENV, CHECKPOINT_SLA_MAX_DURATION_MS_ENV,
+    CHECKPOINT_SLA_MAX_INTERVAL_MS_ENV,
+};
 use deadpool_postgres::Pool;
 use lazy_static::lazy_static;
 use prometheus::{register_gauge, Gauge};
@@ -76,6 +81,45 @@ pub struct JobConfig {
     parallelism_overrides: HashMap<String, usize>,
     restart_nonce: i32,
     restart_mode: RestartMode,
+    checkpoint_sla: CheckpointSlaConfig,
+    alignment_timeout: Option<Duration>,
+    unaligned_checkpoints: bool,
+    allow_non_restored_state: bool,
+}
+
+fn optional_millis_config(var: &str) -> Option<Duration> {
+    env::var(var)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_millis)
+}
+
+// a job's SLA limits are stored with its config; the controller's apply to jobs that don't set
+// them, and a limit of 0 disables it for the job
+fn sla_limit(job_micros: Option<i64>, default_env: &str) -> Option<Duration> {
+    match job_micros {
+        Some(0) => None,
+        Some(micros) => Some(Duration::from_micros(micros as u64)),
+        None => optional_millis_config(default_env),
+    }
+}
+
+fn checkpoint_sla_config(
+    max_duration_micros: Option<i64>,
+    max_interval_micros: Option<i64>,
+) -> CheckpointSlaConfig {
+    CheckpointSlaConfig {
+        max_duration: sla_limit(max_duration_micros, CHECKPOINT_SLA_MAX_DURATION_MS_ENV),
+        max_interval: sla_limit(max_interval_micros, CHECKPOINT_SLA_MAX_INTERVAL_MS_ENV),
+    }
+}
+
+// whether restores may drop the state of operators that were removed from a job is configured
+// on the controller
+fn allow_non_restored_state() -> bool {
+    env::var(ALLOW_NON_RESTORED_STATE_ENV)
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false)
 }
 
 #[derive(Clone, Debug)]
@@ -512,6 +556,15 @@ impl ControllerServer {
                             .collect(),
                         restart_nonce: p.config_restart_nonce,
                         restart_mode: p.restart_mode,
+                        checkpoint_sla: checkpoint_sla_config(
+                            p.checkpoint_sla_max_duration_micros,
+                            p.checkpoint_sla_max_interval_micros,
+                        ),
+                        alignment_timeout: optional_millis_config(
+                            CHECKPOINT_ALIGNMENT_TIMEOUT_MS_ENV,
+                        ),
+                        unaligned_checkpoints: p.unaligned_checkpoints,
+                        allow_non_restored_state: allow_non_restored_state(),
                     };
 
                     let mut jobs = jobs.lock().await;
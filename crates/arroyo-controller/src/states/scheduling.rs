@@ -5,6 +5,7 @@ use std::{
 };
 
 use arroyo_rpc::grpc::{worker_grpc_client::WorkerGrpcClient, StartExecutionReq, TaskAssignment};
+use arroyo_rpc::public_ids::{generate_id, IdTypes};
 use arroyo_types::WorkerId;
 use tokio::{sync::Mutex, task::JoinHandle};
 use tonic::{transport::Channel, Request};
@@ -15,6 +16,10 @@ use arroyo_datastream::logical::LogicalProgram;
 use arroyo_state::{
     committing_state::CommittingState,
     parquet::get_storage_env_vars,
+    remapping::{
+        apply_operator_remapping, find_restorable_checkpoint, reconcile_restored_operators,
+        require_restored_operator_metadata, MissingOperatorMetadata,
+    },
     tables::{global_keyed_map::GlobalKeyedTable, ErasedTable},
     BackingStore, StateBackend,
 };
@@ -23,6 +28,7 @@ use crate::{
     job_controller::JobController,
     queries::controller_queries,
     states::{compiling::Compiling, stop_if_desired_non_running},
+    types::public::LogLevel,
 };
 use crate::{schedulers::SchedulerError, JobMessage};
 use crate::{
@@ -334,6 +340,50 @@ impl State for Scheduling {
                 }
             });
 
+        // an epoch missing some of its metadata, such as one whose writes were interrupted,
+        // can't be restored, so fall back to the latest one before it that can
+        let checkpoint_info = match checkpoint_info {
+            Some(info) => {
+                match find_restorable_checkpoint(&ctx.config.id, info.epoch, info.min_epoch).await {
+                    Ok(Some(epoch)) if epoch == info.epoch => Some(info),
+                    Ok(Some(epoch)) => {
+                        warn!(
+                            message =
+                                "checkpoint can't be restored; falling back to an earlier one",
+                            job_id = ctx.config.id,
+                            epoch = info.epoch,
+                            fallback_epoch = epoch
+                        );
+                        // commits for the earlier epoch finished before the later one was taken
+                        Some(CheckpointInfo {
+                            epoch,
+                            needs_commits: false,
+                            ..info
+                        })
+                    }
+                    Ok(None) => {
+                        return Err(fatal(
+                            "Failed to restore job; no restorable checkpoint found.",
+                            anyhow!(
+                                "no checkpoint from epoch {} back to {} has all of its metadata",
+                                info.epoch,
+                                info.min_epoch
+                            ),
+                        ));
+                    }
+                    Err(e) => {
+                        return Err(ctx.retryable(
+                            self,
+                            "failed to find a restorable checkpoint",
+                            e,
+                            10,
+                        ));
+                    }
+                }
+            }
+            None => None,
+        };
+
         info!("Restoring from {:?}", checkpoint_info);
 
         {
@@ -349,6 +399,7 @@ impl State for Scheduling {
         }
 
         let mut committing_state = None;
+        let mut operator_remappings = vec![];
 
         // clear all of the epochs after the one we're loading so that we don't read in-progress data
         if let Some(CheckpointInfo {
@@ -371,33 +422,106 @@ impl State for Scheduling {
                 return Err(ctx.retryable(self, "failed to prepare checkpoint for loading", e, 10));
             }
             metadata.min_epoch = min_epoch;
+
+            // the stored metadata keeps the original operator ids, so the remapping is applied
+            // to a copy that's used for the restore
+            let mut restored = metadata.clone();
+            restored.operator_remappings.clear();
+            let remappings = match StateBackend::load_operator_remapping(&ctx.config.id, epoch)
+                .await
+            {
+                Ok(remappings) => remappings,
+                Err(e) => {
+                    return Err(ctx.retryable(self, "failed to load operator remapping", e, 10));
+                }
+            };
+            if !remappings.is_empty() {
+                apply_operator_remapping(&mut restored, remappings).map_err(|err| {
+                    fatal("Failed to restore job; invalid operator remapping.", err)
+                })?;
+                operator_remappings = restored.operator_remappings.clone();
+            }
+
+            let job_operator_ids: HashSet<String> = ctx
+                .program
+                .graph
+                .node_weights()
+                .map(|node| node.operator_id.clone())
+                .collect();
+            let non_restored = reconcile_restored_operators(
+                &mut restored,
+                &job_operator_ids,
+                ctx.config.allow_non_restored_state,
+            )
+            .await
+            .map_err(|err| {
+                fatal(
+                    "Failed to restore job; the checkpoint has state for operators that are no \
+                    longer in the pipeline.",
+                    err,
+                )
+            })?;
+            // let the user know what state was left behind
+            for (operator_id, tables) in &non_restored.dropped_operators {
+                let message = format!(
+                    "State of operator {} was not restored, as it is no longer in the pipeline",
+                    operator_id
+                );
+                let details = tables
+                    .iter()
+                    .map(|table| {
+                        format!(
+                            "table {}: {} bytes in {} files",
+                            table.table_name, table.bytes, table.files
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if let Err(e) = controller_queries::create_job_log_message()
+                    .bind(
+                        &c,
+                        &generate_id(IdTypes::JobLogMessage),
+                        &ctx.config.id,
+                        operator_id,
+                        &0,
+                        &LogLevel::warn,
+                        &message,
+                        &details,
+                    )
+                    .one()
+                    .await
+                {
+                    warn!(
+                        message = "failed to record non-restored state",
+                        job_id = ctx.config.id,
+                        operator_id,
+                        error = format!("{:?}", e)
+                    );
+                }
+            }
+
             if needs_commits {
                 let mut commit_subtasks = HashSet::new();
                 let mut committing_data: HashMap<String, HashMap<String, HashMap<u32, Vec<u8>>>> =
                     HashMap::new();
-                for operator_id in &metadata.operator_ids {
+                for operator_id in &restored.operator_ids {
                     let operator_metadata =
-                        StateBackend::load_operator_metadata(&ctx.config.id, operator_id, epoch)
+                        require_restored_operator_metadata(&restored, operator_id)
                             .await
                             .map_err(|err| {
+                                let message = if err.is::<MissingOperatorMetadata>() {
+                                    "missing operator metadata"
+                                } else {
+                                    "failed to read operator metadata"
+                                };
                                 fatal(
                                     format!(
-                                "Failed to restore job; operator metadata for {} not found.",
-                                operator_id
-                            ),
+                                        "Failed to restore job; {} for {}.",
+                                        message, operator_id
+                                    ),
                                     err,
                                 )
                             })?;
-                    let Some(operator_metadata) = operator_metadata else {
-                        return Err(fatal(
-                            "missing operator metadata",
-                            anyhow!(
-                                "operator metadata for {} not found for job {}",
-                                operator_id,
-                                ctx.config.id
-                            ),
-                        ));
-                    };
                     for (table_name, table_metadata) in &operator_metadata.table_checkpoint_metadata
                     {
                         let config =
@@ -420,7 +544,8 @@ impl State for Scheduling {
                                     anyhow!("table type not found"),
                                 ));
                             }
-                            arroyo_rpc::grpc::TableEnum::GlobalKeyValue => {
+                            arroyo_rpc::grpc::TableEnum::GlobalKeyValue
+                            | arroyo_rpc::grpc::TableEnum::KeyTimeMap => {
                                 GlobalKeyedTable::committing_data(config.clone(), table_metadata)
                             }
                             arroyo_rpc::grpc::TableEnum::ExpiringKeyedTimeTable => todo!(),
@@ -556,6 +681,7 @@ impl State for Scheduling {
                 .expect("failed to send commit messages");
         }
 
+        controller.set_operator_remappings(operator_remappings);
         ctx.job_controller = Some(controller);
         Ok(Transition::next(*self, Running {}))
     }
@@ -36,6 +36,13 @@ pub enum OperatorName {
     ConnectorSink,
 }
 
+impl OperatorName {
+    /// Whether operators of this kind never have state to checkpoint.
+    pub fn is_stateless(&self) -> bool {
+        matches!(self, OperatorName::ArrowValue | OperatorName::ArrowKey)
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub enum LogicalEdgeType {
     Forward,
@@ -237,6 +244,15 @@ impl LogicalProgram {
         }
         tasks_per_operator
     }
+
+    /// The ids of the operators that never have state to checkpoint.
+    pub fn stateless_operators(&self) -> HashSet<String> {
+        self.graph
+            .node_weights()
+            .filter(|node| node.operator_name.is_stateless())
+            .map(|node| node.operator_id.clone())
+            .collect()
+    }
 }
 
 impl TryFrom<ArrowProgram> for LogicalProgram {
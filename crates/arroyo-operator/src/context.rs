@@ -1,4 +1,6 @@
 use crate::{server_for_hash_array, RateLimiter};
+use anyhow::Context;
+use anyhow::Context;
 use arrow::array::{make_builder, Array, ArrayBuilder, PrimitiveArray, RecordBatch};
 use arrow::compute::{partition, sort_to_indices, take};
 use arrow::datatypes::{SchemaRef, UInt64Type};
@@ -9,8 +11,10 @@ use arroyo_rpc::formats::{BadData, Format, Framing};
 use arroyo_rpc::grpc::{CheckpointMetadata, TableConfig, TaskCheckpointEventType};
 use arroyo_rpc::schema_resolver::SchemaResolver;
 use arroyo_rpc::{get_hasher, CompactionResult, ControlMessage, ControlResp};
+use arroyo_state::remapping::{
+    is_remapped, require_restored_operator_metadata, validate_restored_tables,
+};
 use arroyo_state::tables::table_manager::TableManager;
-use arroyo_state::{BackingStore, StateBackend};
 use arroyo_types::{
     from_micros, should_flush, ArrowMessage, CheckpointBarrier, SourceError, TaskInfo, UserError,
     Watermark,
@@ -416,7 +420,17 @@ impl ArrowCollector {
     }
 }
 
+fn restore_error(task_info: &TaskInfo) -> String {
+    format!(
+        "failed to restore subtask {} of operator {} in job {}",
+        task_info.task_index, task_info.operator_id, task_info.job_id
+    )
+}
+
 impl ArrowContext {
+    /// Creates the context of a subtask, restoring its state from `restore_from` if it's
+    /// set. Fails if the operator's state can't be restored, as when the checkpoint has no
+    /// metadata for it or the state backend stays unreachable.
     pub async fn new(
         task_info: TaskInfo,
         restore_from: Option<CheckpointMetadata>,
@@ -428,17 +442,20 @@ impl ArrowContext {
         projection: Option<Vec<usize>>,
         out_qs: Vec<Vec<BatchSender>>,
         tables: HashMap<String, TableConfig>,
-    ) -> Self {
+    ) -> anyhow::Result<Self> {
         let (watermark, metadata) = if let Some(metadata) = restore_from {
+            let remapped = is_remapped(&metadata, &task_info.operator_id);
             let (watermark, operator_metadata) = {
-                let metadata = StateBackend::load_operator_metadata(
-                    &task_info.job_id,
-                    &task_info.operator_id,
-                    metadata.epoch,
-                )
-                .await
-                .expect("lookup should succeed")
-                .expect("require metadata");
+                // the controller only restores checkpoints with metadata for every operator
+                let metadata =
+                    require_restored_operator_metadata(&metadata, &task_info.operator_id)
+                        .await
+                        .with_context(|| restore_error(&task_info))?;
+                // state remapped from another operator has to fit the tables this one declares
+                if remapped {
+                    validate_restored_tables(&metadata, &tables)
+                        .with_context(|| restore_error(&task_info))?;
+                }
                 (
                     metadata
                         .operator_metadata
@@ -455,6 +472,8 @@ impl ArrowContext {
             (None, None)
         };
 
+        let task_info = Arc::new(task_info);
+
         let tx_queue_size_gauges = register_queue_gauge(
             "arroyo_worker_tx_queue_size",
             "Size of a tx queue",
@@ -476,14 +495,12 @@ impl ArrowContext {
             &out_qs,
         );
 
-        let task_info = Arc::new(task_info);
-
         let table_manager =
             TableManager::new(task_info.clone(), tables, control_tx.clone(), metadata)
                 .await
-                .expect("should be able to create TableManager");
+                .with_context(|| restore_error(&task_info))?;
 
-        Self {
+        Ok(Self {
             task_info: task_info.clone(),
             control_rx,
             control_tx: control_tx.clone(),
@@ -511,7 +528,7 @@ impl ArrowContext {
             deserializer: None,
             buffered_error: None,
             table_manager,
-        }
+        })
     }
 
     pub fn new_for_test() -> (Self, Receiver<QueueItem>) {
@@ -640,6 +657,8 @@ impl ArrowContext {
                 subtask_index: self.task_info.task_index as u32,
                 time: SystemTime::now(),
                 event_type,
+                in_flight_records: 0,
+                in_flight_bytes: 0,
             }))
             .await
             .unwrap();
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::future::Future;
 use std::ops::Sub;
@@ -9,6 +10,7 @@ use crate::inq_reader::InQReader;
 use arrow::array::types::{TimestampNanosecondType, UInt64Type};
 use arrow::array::{Array, PrimitiveArray, RecordBatch, UInt64Array};
 use arrow::compute::kernels::numeric::{div, rem};
+use arroyo_state::in_flight::InFlightBatches;
 use arroyo_types::{ArrowMessage, CheckpointBarrier, Data, SignalMessage, TaskInfoRef};
 use bincode::{Decode, Encode};
 
@@ -65,10 +67,36 @@ pub enum ControlOutcome {
     Finish,
 }
 
+/// An unaligned checkpoint whose barriers haven't all arrived: the inputs that still have
+/// a barrier in flight, and the records read from them in the meantime.
+#[derive(Debug)]
+pub struct InFlightCapture {
+    barrier: CheckpointBarrier,
+    pending: HashSet<usize>,
+    batches: HashMap<usize, Vec<RecordBatch>>,
+}
+
+impl InFlightCapture {
+    pub fn barrier(&self) -> CheckpointBarrier {
+        self.barrier
+    }
+
+    pub fn into_batches(self) -> Vec<InFlightBatches> {
+        let mut captured: Vec<_> = self
+            .batches
+            .into_iter()
+            .map(|(input, batches)| InFlightBatches { input, batches })
+            .collect();
+        captured.sort_by_key(|c| c.input);
+        captured
+    }
+}
+
 #[derive(Debug)]
 pub struct CheckpointCounter {
     inputs: Vec<Option<u32>>,
     counter: Option<usize>,
+    in_flight: Option<InFlightCapture>,
 }
 
 impl CheckpointCounter {
@@ -76,6 +104,53 @@ impl CheckpointCounter {
         CheckpointCounter {
             inputs: vec![None; size],
             counter: None,
+            in_flight: None,
+        }
+    }
+
+    /// Whether an unaligned checkpoint has been taken and is waiting for the rest of its
+    /// barriers.
+    pub fn is_unaligned(&self) -> bool {
+        self.in_flight.is_some()
+    }
+
+    /// Starts an unaligned checkpoint on the first barrier, which arrived on `idx`. Inputs
+    /// aren't blocked; records read from the other open inputs are captured until their
+    /// barriers arrive.
+    pub fn start_unaligned(
+        &mut self,
+        idx: usize,
+        checkpoint: &CheckpointBarrier,
+        closed: &HashSet<usize>,
+    ) {
+        assert!(self.all_clear() && self.in_flight.is_none());
+        self.in_flight = Some(InFlightCapture {
+            barrier: *checkpoint,
+            pending: (0..self.inputs.len())
+                .filter(|i| *i != idx && !closed.contains(i))
+                .collect(),
+            batches: HashMap::new(),
+        });
+    }
+
+    /// Captures a batch read from `idx` if that input's barrier is still in flight.
+    pub fn capture(&mut self, idx: usize, batch: &RecordBatch) {
+        if let Some(capture) = &mut self.in_flight {
+            if capture.pending.contains(&idx) {
+                capture.batches.entry(idx).or_default().push(batch.clone());
+            }
+        }
+    }
+
+    /// Records that `idx` delivered its barrier or closed, returning the captured records
+    /// once no barriers are left in flight.
+    pub fn mark_in_flight(&mut self, idx: usize) -> Option<InFlightCapture> {
+        let capture = self.in_flight.as_mut()?;
+        capture.pending.remove(&idx);
+        if capture.pending.is_empty() {
+            self.in_flight.take()
+        } else {
+            None
         }
     }
 
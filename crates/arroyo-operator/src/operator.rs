@@ -1,6 +1,6 @@
 use crate::context::{ArrowContext, BatchReceiver};
 use crate::inq_reader::InQReader;
-use crate::{CheckpointCounter, ControlOutcome, SourceFinishType};
+use crate::{CheckpointCounter, ControlOutcome, InFlightCapture, SourceFinishType};
 use arrow::array::RecordBatch;
 use arroyo_metrics::TaskCounters;
 use arroyo_rpc::grpc::{TableConfig, TaskCheckpointEventType};
@@ -118,12 +118,22 @@ impl OperatorNode {
     }
 }
 
-async fn run_checkpoint(checkpoint_barrier: CheckpointBarrier, ctx: &mut ArrowContext) -> bool {
+async fn run_checkpoint(
+    checkpoint_barrier: CheckpointBarrier,
+    unaligned: bool,
+    ctx: &mut ArrowContext,
+) -> bool {
     let watermark = ctx.watermarks.last_present_watermark();
 
-    ctx.table_manager
-        .checkpoint(checkpoint_barrier, watermark)
-        .await;
+    if unaligned {
+        ctx.table_manager
+            .checkpoint_unaligned(checkpoint_barrier, watermark)
+            .await;
+    } else {
+        ctx.table_manager
+            .checkpoint(checkpoint_barrier, watermark)
+            .await;
+    }
 
     ctx.send_checkpoint_event(checkpoint_barrier, TaskCheckpointEventType::FinishedSync)
         .await;
@@ -163,10 +173,34 @@ pub trait SourceOperator: Send + 'static {
         )
         .await;
 
-        run_checkpoint(checkpoint_barrier, ctx).await
+        run_checkpoint(checkpoint_barrier, false, ctx).await
     }
 }
 
+/// Completes an unaligned checkpoint once its last barrier has arrived, by storing the
+/// records read ahead of the barriers along with the checkpoint.
+async fn finish_unaligned_checkpoint(capture: InFlightCapture, ctx: &mut ArrowContext) {
+    let barrier = capture.barrier();
+    let captured = capture.into_batches();
+
+    ctx.control_tx
+        .send(ControlResp::CheckpointEvent(arroyo_rpc::CheckpointEvent {
+            checkpoint_epoch: barrier.epoch,
+            operator_id: ctx.task_info.operator_id.clone(),
+            subtask_index: ctx.task_info.task_index as u32,
+            time: SystemTime::now(),
+            event_type: TaskCheckpointEventType::CapturedInFlight,
+            in_flight_records: captured.iter().map(|c| c.records()).sum(),
+            in_flight_bytes: captured.iter().map(|c| c.bytes()).sum(),
+        }))
+        .await
+        .unwrap();
+
+    ctx.table_manager
+        .write_in_flight(barrier.epoch, captured)
+        .await;
+}
+
 async fn operator_run_behavior(
     this: &mut Box<dyn ArrowOperator + Send>,
     ctx: &mut ArrowContext,
@@ -204,6 +238,15 @@ async fn operator_run_behavior(
         tokio::time::interval(this.tick_interval().unwrap_or(Duration::from_secs(60)));
     interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
+    // records captured by the unaligned checkpoint we restored from were read before
+    // anything still in the inputs
+    for captured in ctx.table_manager.take_in_flight() {
+        for batch in captured.batches {
+            this.process_batch_index(captured.input, in_partitions, batch, ctx)
+                .await;
+        }
+    }
+
     loop {
         let operator_future: OptionFuture<_> = this.future_to_poll().into();
         tokio::select! {
@@ -224,6 +267,7 @@ async fn operator_run_behavior(
                                 TaskCounters::BatchesReceived.for_task(&ctx.task_info, |c| c.inc());
                                 TaskCounters::MessagesReceived.for_task(&ctx.task_info, |c| c.inc_by(record.num_rows() as u64));
                                 TaskCounters::BytesReceived.for_task(&ctx.task_info, |c| c.inc_by(record.get_array_memory_size() as u64));
+                                counter.capture(idx, &record);
                                 this.process_batch_index(idx, in_partitions, record, ctx)
                                     .instrument(tracing::trace_span!("handle_fn",
                                         name,
@@ -347,6 +391,15 @@ pub trait ArrowOperator: Send + 'static {
                     idx
                 );
 
+                if counter.is_unaligned() {
+                    // the checkpoint was taken on the first barrier; this input's records
+                    // are no longer in flight
+                    if let Some(capture) = counter.mark_in_flight(idx) {
+                        finish_unaligned_checkpoint(capture, ctx).await;
+                    }
+                    return ControlOutcome::Continue;
+                }
+
                 if counter.all_clear() {
                     ctx.control_tx
                         .send(ControlResp::CheckpointEvent(arroyo_rpc::CheckpointEvent {
@@ -355,11 +408,42 @@ pub trait ArrowOperator: Send + 'static {
                             subtask_index: ctx.task_info.task_index as u32,
                             time: SystemTime::now(),
                             event_type: TaskCheckpointEventType::StartedAlignment,
+                            in_flight_records: 0,
+                            in_flight_bytes: 0,
                         }))
                         .await
                         .unwrap();
                 }
 
+                // stopping checkpoints stay aligned, so nothing is left in flight when the
+                // operator shuts down
+                if t.unaligned && !t.then_stop && in_partitions > 1 {
+                    debug!(
+                        "Checkpointing unaligned {}-{}-{}",
+                        self.name(),
+                        ctx.task_info.operator_id,
+                        ctx.task_info.task_index
+                    );
+
+                    counter.start_unaligned(idx, t, closed);
+
+                    ctx.send_checkpoint_event(*t, TaskCheckpointEventType::StartedCheckpointing)
+                        .await;
+
+                    self.handle_checkpoint(*t, ctx).await;
+
+                    ctx.send_checkpoint_event(*t, TaskCheckpointEventType::FinishedOperatorSetup)
+                        .await;
+
+                    run_checkpoint(*t, true, ctx).await;
+
+                    // the other inputs may all have closed already
+                    if let Some(capture) = counter.mark_in_flight(idx) {
+                        finish_unaligned_checkpoint(capture, ctx).await;
+                    }
+                    return ControlOutcome::Continue;
+                }
+
                 if counter.mark(idx, &t) {
                     debug!(
                         "Checkpointing {}-{}-{}",
@@ -376,7 +460,7 @@ pub trait ArrowOperator: Send + 'static {
                     ctx.send_checkpoint_event(*t, TaskCheckpointEventType::FinishedOperatorSetup)
                         .await;
 
-                    if run_checkpoint(*t, ctx).await {
+                    if run_checkpoint(*t, false, ctx).await {
                         return ControlOutcome::Stop;
                     }
                 }
@@ -404,12 +488,18 @@ pub trait ArrowOperator: Send + 'static {
             }
             SignalMessage::Stop => {
                 closed.insert(idx);
+                if let Some(capture) = counter.mark_in_flight(idx) {
+                    finish_unaligned_checkpoint(capture, ctx).await;
+                }
                 if closed.len() == in_partitions {
                     return ControlOutcome::StopAndSendStop;
                 }
             }
             SignalMessage::EndOfData => {
                 closed.insert(idx);
+                if let Some(capture) = counter.mark_in_flight(idx) {
+                    finish_unaligned_checkpoint(capture, ctx).await;
+                }
                 if closed.len() == in_partitions {
                     return ControlOutcome::Finish;
                 }
@@ -10,6 +10,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .file_descriptor_set_path(out_dir.join("api_descriptor.bin"))
         .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
         .type_attribute(".", "#[serde(rename_all = \"camelCase\")]")
+        // checkpoint details stored before table storage was reported don't have it
+        .field_attribute(
+            "OperatorCheckpointDetail.table_storage",
+            "#[serde(default)]",
+        )
+        .field_attribute("OperatorCheckpointDetail.failures", "#[serde(default)]")
+        .field_attribute(
+            "OperatorCheckpointDetail.idle_subtasks",
+            "#[serde(default)]",
+        )
+        .field_attribute("TaskCheckpointDetail.files", "#[serde(default)]")
+        .field_attribute("TaskCheckpointDetail.omitted_files", "#[serde(default)]")
+        .field_attribute(
+            "TaskCheckpointDetail.omitted_file_bytes",
+            "#[serde(default)]",
+        )
+        .field_attribute(
+            "TaskCheckpointDetail.alignment_timed_out",
+            "#[serde(default)]",
+        )
         .compile(&["proto/api.proto"], &["proto/"])
         .unwrap();
     Ok(())
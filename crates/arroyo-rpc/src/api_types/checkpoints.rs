@@ -34,6 +34,22 @@ pub struct SubtaskCheckpointGroup {
     pub index: u32,
     pub bytes: u64,
     pub event_spans: Vec<CheckpointEventSpan>,
+    /// The largest of the state files the subtask wrote, up to a limit.
+    pub files: Vec<SubtaskStateFile>,
+    /// How many more files the subtask wrote than are listed, and their total size.
+    pub omitted_files: u64,
+    pub omitted_file_bytes: u64,
+    /// Whether the subtask was still aligning after the job's alignment timeout.
+    pub alignment_timed_out: bool,
+}
+
+/// A state file written by a subtask in a checkpoint.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SubtaskStateFile {
+    pub table_name: String,
+    pub path: String,
+    pub bytes: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
@@ -42,4 +58,15 @@ pub struct OperatorCheckpointGroup {
     pub operator_id: String,
     pub bytes: u64,
     pub subtasks: Vec<SubtaskCheckpointGroup>,
+    pub tables: Vec<TableStorageUsage>,
+}
+
+/// Storage used by a table in a checkpoint, including files retained from earlier epochs.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TableStorageUsage {
+    pub table_name: String,
+    pub files: u64,
+    pub bytes: u64,
+    pub oldest_epoch: Option<u32>,
 }
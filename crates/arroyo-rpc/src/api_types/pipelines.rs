@@ -33,6 +33,13 @@ pub struct PipelinePatch {
     pub parallelism: Option<u64>,
     pub checkpoint_interval_micros: Option<u64>,
     pub stop: Option<StopType>,
+    /// Checkpoint without waiting for barrier alignment, capturing the records still in
+    /// flight instead
+    pub unaligned_checkpoints: Option<bool>,
+    /// Checkpoints must complete within this long of starting; 0 disables the limit
+    pub checkpoint_sla_max_duration_micros: Option<u64>,
+    /// A checkpoint must complete at least this often; 0 disables the limit
+    pub checkpoint_sla_max_interval_micros: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
@@ -84,6 +84,10 @@ pub struct CheckpointEvent {
     pub subtask_index: u32,
     pub time: SystemTime,
     pub event_type: TaskCheckpointEventType,
+    /// Records captured by an unaligned checkpoint and their size in memory, for
+    /// [`TaskCheckpointEventType::CapturedInFlight`] events.
+    pub in_flight_records: u64,
+    pub in_flight_bytes: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -41,7 +41,8 @@ async fn checkpoint(ctx: &mut SmokeTestContext<'_>, epoch: u32) {
         epoch,
         0,
         ctx.tasks_per_operator.clone(),
-    );
+    )
+    .unwrap();
 
     // trigger a checkpoint, pass the messages to the CheckpointState
 
@@ -50,6 +51,7 @@ async fn checkpoint(ctx: &mut SmokeTestContext<'_>, epoch: u32) {
         min_epoch: 0,
         timestamp: SystemTime::now(),
         then_stop: false,
+        unaligned: false,
     };
 
     for source in ctx.engine.source_controls() {
@@ -72,6 +74,8 @@ async fn checkpoint(ctx: &mut SmokeTestContext<'_>, epoch: u32) {
                     subtask_index: c.subtask_index,
                     epoch: c.checkpoint_epoch,
                     event_type: c.event_type as i32,
+                    in_flight_records: c.in_flight_records,
+                    in_flight_bytes: c.in_flight_bytes,
                 };
                 checkpoint_state.checkpoint_event(req).unwrap();
             }
@@ -0,0 +1,203 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use arroyo_types::u32_config;
+use tokio::sync::Notify;
+
+use crate::{StateMessage, TableData};
+
+pub const MAX_PENDING_WRITE_BYTES_ENV: &str = "STATE_MAX_PENDING_WRITE_BYTES";
+
+/// The table writes a subtask has made that its flusher hasn't yet handed to the table
+/// checkpointers, whether still in the write buffer or queued for the flusher. When storage
+/// is slow the flusher falls behind and this grows; once it passes the configured limit,
+/// writes wait in [`WriteBacklog::wait_for_capacity`] until the flusher catches up, so
+/// operators slow down with their storage rather than queueing without bound.
+#[derive(Debug, Default)]
+pub struct WriteBacklog {
+    /// Unset (or 0 in the environment) means writes never wait.
+    max_pending_bytes: Option<u64>,
+    pending_bytes: AtomicU64,
+    // the most pending at any point since the last checkpoint, which is reported with it
+    peak_pending_bytes: AtomicU64,
+    drained: Notify,
+}
+
+impl WriteBacklog {
+    pub fn new(max_pending_bytes: Option<u64>) -> Self {
+        Self {
+            max_pending_bytes,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let max_pending_bytes = u32_config(MAX_PENDING_WRITE_BYTES_ENV, 128 * 1024 * 1024) as u64;
+        Self::new((max_pending_bytes > 0).then_some(max_pending_bytes))
+    }
+
+    pub fn pending_write_bytes(&self) -> u64 {
+        self.pending_bytes.load(Ordering::Acquire)
+    }
+
+    pub fn is_backpressured(&self) -> bool {
+        self.max_pending_bytes
+            .is_some_and(|max| self.pending_write_bytes() >= max)
+    }
+
+    /// Waits until the pending writes are below the limit.
+    pub async fn wait_for_capacity(&self) {
+        loop {
+            // created before checking, so that a drain in between isn't missed
+            let drained = self.drained.notified();
+            if !self.is_backpressured() {
+                return;
+            }
+            drained.await;
+        }
+    }
+
+    pub(crate) fn add(&self, bytes: u64) {
+        let pending = self.pending_bytes.fetch_add(bytes, Ordering::AcqRel) + bytes;
+        self.peak_pending_bytes.fetch_max(pending, Ordering::AcqRel);
+    }
+
+    pub(crate) fn remove(&self, bytes: u64) {
+        // saturating, so that a miscounted write can't wrap the count around
+        let _ = self
+            .pending_bytes
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |pending| {
+                Some(pending.saturating_sub(bytes))
+            });
+        self.drained.notify_waiters();
+    }
+
+    /// The most that was pending since the last call.
+    pub(crate) fn take_peak(&self) -> u64 {
+        self.peak_pending_bytes
+            .swap(self.pending_write_bytes(), Ordering::AcqRel)
+    }
+}
+
+/// The bytes a write counts for in the backlog. Messages other than table writes don't
+/// count.
+pub(crate) fn write_size(message: &StateMessage) -> u64 {
+    match message {
+        StateMessage::TableData { data, .. } => table_data_size(data),
+        StateMessage::TableDataBatch(writes) => writes.iter().map(write_size).sum(),
+        _ => 0,
+    }
+}
+
+pub(crate) fn table_data_size(data: &TableData) -> u64 {
+    match data {
+        TableData::RecordBatch(batch) => batch.get_array_memory_size() as u64,
+        TableData::CommitData { data } => data.len() as u64,
+        TableData::KeyedData { key, value } => (key.len() + value.len()) as u64,
+        TableData::KeyedDelete { key } => key.len() as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::sync::mpsc::{channel, Receiver};
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::write_buffer::{StateSender, WriteBufferConfig};
+
+    fn write(value_len: usize) -> StateMessage {
+        StateMessage::TableData {
+            table: "t".to_string(),
+            data: TableData::KeyedData {
+                key: vec![],
+                value: vec![0; value_len],
+            },
+        }
+    }
+
+    /// Stands in for the flusher against a store that takes `latency` per write.
+    fn drain(mut rx: Receiver<StateMessage>, backlog: Arc<WriteBacklog>, latency: Duration) {
+        tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                tokio::time::sleep(latency).await;
+                backlog.remove(write_size(&message));
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_writes_wait_at_limit_and_resume_when_drained() {
+        let (tx, mut rx) = channel(100);
+        let backlog = Arc::new(WriteBacklog::new(Some(100)));
+        let sender = StateSender::new(
+            tx,
+            WriteBufferConfig {
+                max_writes: 0,
+                max_age: Duration::ZERO,
+            },
+            backlog.clone(),
+        );
+
+        sender.send(write(60)).await.unwrap();
+        assert!(!backlog.is_backpressured());
+        sender.send(write(60)).await.unwrap();
+        assert!(backlog.is_backpressured());
+        assert_eq!(backlog.pending_write_bytes(), 120);
+
+        // nothing is draining, so the next write waits
+        assert!(timeout(Duration::from_millis(50), sender.send(write(10)))
+            .await
+            .is_err());
+
+        rx.recv().await.unwrap();
+        backlog.remove(60);
+        timeout(Duration::from_secs(1), sender.send(write(10)))
+            .await
+            .expect("write should resume once the backlog drains")
+            .unwrap();
+        assert_eq!(backlog.take_peak(), 120);
+    }
+
+    #[tokio::test]
+    async fn test_slow_store_bounds_backlog() {
+        let (tx, rx) = channel(1000);
+        let backlog = Arc::new(WriteBacklog::new(Some(1000)));
+        let sender = StateSender::new(
+            tx,
+            WriteBufferConfig {
+                max_writes: 4,
+                max_age: Duration::from_secs(3600),
+            },
+            backlog.clone(),
+        );
+        drain(rx, backlog.clone(), Duration::from_millis(1));
+
+        for _ in 0..100 {
+            sender.send(write(100)).await.unwrap();
+            // a write is only admitted below the limit, so the backlog never exceeds it by
+            // more than one write
+            assert!(backlog.pending_write_bytes() < 1100);
+        }
+        sender.flush().await.unwrap();
+        timeout(Duration::from_secs(5), async {
+            while backlog.pending_write_bytes() > 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("backlog should drain");
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_backlog_never_waits() {
+        let backlog = WriteBacklog::new(None);
+        backlog.add(u64::MAX / 2);
+        assert!(!backlog.is_backpressured());
+        timeout(Duration::from_millis(50), backlog.wait_for_capacity())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::time::SystemTime;
+
+use arrow_array::RecordBatch;
+use arroyo_types::TaskInfoRef;
+use prometheus::IntCounter;
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use tracing::warn;
+
+use crate::metrics::CHANGELOG_DROPPED_COUNTER;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Insert,
+    Delete,
+    Expire,
+    /// Rows expired before their retention ended, to keep the subtask within its state quota.
+    QuotaExpire,
+}
+
+#[derive(Debug, Clone)]
+pub enum ChangeData {
+    /// A key and value from a global keyed table, encoded with the table's codec. The value
+    /// is unset for deletes.
+    Keyed {
+        key: Vec<u8>,
+        value: Option<Vec<u8>>,
+    },
+    /// Rows from a time-keyed table.
+    Batch(RecordBatch),
+}
+
+/// A single mutation of a table, as seen by the operator that made it.
+#[derive(Debug, Clone)]
+pub struct ChangeEvent {
+    pub table: String,
+    pub kind: ChangeKind,
+    pub timestamp: Option<SystemTime>,
+    pub data: ChangeData,
+}
+
+/// What happens when the changelog channel is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChangelogOverflow {
+    /// Events are held until there is room in the channel, up to `capacity` of them beyond
+    /// it. Mutations made from synchronous methods are delivered at the view's next async
+    /// call (e.g., `flush`). While that many are held, synchronous inserts are refused with
+    /// [`ChangelogFull`] until the view's `wait_for_changelog` has made room; expirations of
+    /// data already in state can't be refused, and are held regardless.
+    #[default]
+    Block,
+    /// Events are dropped and counted in `arroyo_worker_changelog_dropped_events`.
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChangelogConfig {
+    pub capacity: usize,
+    pub overflow: ChangelogOverflow,
+}
+
+impl ChangelogConfig {
+    pub(crate) fn channel(
+        &self,
+        task_info: &TaskInfoRef,
+        table: &str,
+    ) -> (Changelog, Receiver<ChangeEvent>) {
+        let (tx, rx) = mpsc::channel(self.capacity);
+        let dropped = CHANGELOG_DROPPED_COUNTER.with_label_values(&[
+            &task_info.operator_id,
+            &task_info.task_index.to_string(),
+            table,
+        ]);
+        (
+            Changelog {
+                table: table.to_string(),
+                tx,
+                overflow: self.overflow,
+                pending: VecDeque::new(),
+                max_pending: self.capacity,
+                dropped,
+            },
+            rx,
+        )
+    }
+}
+
+/// A synchronous insert was refused because the table's changelog is holding as many events
+/// as it can for a full channel.
+#[derive(Debug, Clone)]
+pub struct ChangelogFull {
+    pub table: String,
+    pub pending: usize,
+}
+
+impl Display for ChangelogFull {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "changelog for table {} is holding {} events for its receiver",
+            self.table, self.pending
+        )
+    }
+}
+
+impl std::error::Error for ChangelogFull {}
+
+/// The sending half of a table's changelog, owned by the table's view. Data restored from
+/// a checkpoint never passes through here, so restores don't replay old changes.
+#[derive(Debug)]
+pub(crate) struct Changelog {
+    table: String,
+    tx: Sender<ChangeEvent>,
+    overflow: ChangelogOverflow,
+    pending: VecDeque<ChangeEvent>,
+    max_pending: usize,
+    dropped: IntCounter,
+}
+
+impl Changelog {
+    /// Fails if the held events are at their limit, so a synchronous insert has to wait for
+    /// [`Changelog::drain`] before it's recorded.
+    pub(crate) fn check_capacity(&self) -> Result<(), ChangelogFull> {
+        if self.overflow == ChangelogOverflow::Block && self.pending.len() >= self.max_pending {
+            return Err(ChangelogFull {
+                table: self.table.clone(),
+                pending: self.pending.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Records an event without waiting. Used from synchronous mutation methods.
+    pub(crate) fn record(
+        &mut self,
+        kind: ChangeKind,
+        timestamp: Option<SystemTime>,
+        data: ChangeData,
+    ) {
+        let event = ChangeEvent {
+            table: self.table.clone(),
+            kind,
+            timestamp,
+            data,
+        };
+        // keep ordering behind anything that's already waiting
+        if !self.pending.is_empty() {
+            self.pending.push_back(event);
+            return;
+        }
+        match self.tx.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(event)) => match self.overflow {
+                ChangelogOverflow::Block => self.pending.push_back(event),
+                ChangelogOverflow::Drop => self.dropped.inc(),
+            },
+            Err(TrySendError::Closed(_)) => {
+                warn!("changelog receiver for table {} was dropped", self.table);
+            }
+        }
+    }
+
+    /// Records an event and waits until it and any held events are in the channel.
+    pub(crate) async fn emit(
+        &mut self,
+        kind: ChangeKind,
+        timestamp: Option<SystemTime>,
+        data: ChangeData,
+    ) {
+        self.record(kind, timestamp, data);
+        self.drain().await;
+    }
+
+    /// Waits for all held events to be sent.
+    pub(crate) async fn drain(&mut self) {
+        while let Some(event) = self.pending.pop_front() {
+            if self.tx.send(event).await.is_err() {
+                warn!("changelog receiver for table {} was dropped", self.table);
+                self.pending.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arroyo_types::get_test_task_info;
+
+    use super::*;
+
+    fn keyed(key: u8) -> ChangeData {
+        ChangeData::Keyed {
+            key: vec![key],
+            value: None,
+        }
+    }
+
+    fn key_of(event: ChangeEvent) -> u8 {
+        match event.data {
+            ChangeData::Keyed { key, .. } => key[0],
+            ChangeData::Batch(_) => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_block_holds_up_to_capacity() {
+        let config = ChangelogConfig {
+            capacity: 2,
+            overflow: ChangelogOverflow::Block,
+        };
+        let (mut changelog, mut rx) = config.channel(&Arc::new(get_test_task_info()), "block");
+
+        // two fit in the channel and two more are held, which is as many as can be
+        for key in 0..4 {
+            changelog.check_capacity().unwrap();
+            changelog.record(ChangeKind::Delete, None, keyed(key));
+        }
+        let full = changelog.check_capacity().unwrap_err();
+        assert_eq!(full.table, "block");
+        assert_eq!(full.pending, 2);
+
+        // draining waits for the receiver to make room, keeping events in order
+        let (_, received) = tokio::join!(changelog.drain(), async {
+            let mut received = vec![];
+            for _ in 0..4 {
+                received.push(key_of(rx.recv().await.unwrap()));
+            }
+            received
+        });
+        assert_eq!(received, vec![0, 1, 2, 3]);
+        changelog.check_capacity().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drop_never_holds_events() {
+        let config = ChangelogConfig {
+            capacity: 1,
+            overflow: ChangelogOverflow::Drop,
+        };
+        let task_info = Arc::new(get_test_task_info());
+        let (mut changelog, mut rx) = config.channel(&task_info, "drop");
+        let dropped = changelog.dropped.get();
+
+        for key in 0..3 {
+            changelog.record(ChangeKind::Delete, None, keyed(key));
+            changelog.check_capacity().unwrap();
+        }
+        changelog.drain().await;
+        assert_eq!(changelog.dropped.get() - dropped, 2);
+        assert_eq!(key_of(rx.recv().await.unwrap()), 0);
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use tokio::sync::broadcast;
+
+use crate::checkpoint_stats::CheckpointStats;
+
+/// How many events a subscriber can fall behind by before it starts missing the oldest.
+pub const DEFAULT_EVENT_CAPACITY: usize = 256;
+
+/// Something that happened to a checkpoint, as seen by the coordinator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckpointLifecycleEvent {
+    /// A subtask has reported on the checkpoint for the first time.
+    Started { job_id: String, epoch: u32 },
+    /// Every subtask of an operator has finished, and its metadata has been written.
+    OperatorFinished {
+        job_id: String,
+        epoch: u32,
+        operator_id: String,
+        duration_micros: u64,
+        bytes: u64,
+    },
+    /// The checkpoint's metadata has been written, so it can be restored.
+    Completed {
+        job_id: String,
+        epoch: u32,
+        stats: CheckpointStats,
+    },
+    /// The checkpoint failed, timed out or was aborted, and will never complete.
+    Failed {
+        job_id: String,
+        epoch: u32,
+        reason: String,
+    },
+}
+
+impl CheckpointLifecycleEvent {
+    pub fn job_id(&self) -> &str {
+        match self {
+            CheckpointLifecycleEvent::Started { job_id, .. }
+            | CheckpointLifecycleEvent::OperatorFinished { job_id, .. }
+            | CheckpointLifecycleEvent::Completed { job_id, .. }
+            | CheckpointLifecycleEvent::Failed { job_id, .. } => job_id,
+        }
+    }
+
+    pub fn epoch(&self) -> u32 {
+        match self {
+            CheckpointLifecycleEvent::Started { epoch, .. }
+            | CheckpointLifecycleEvent::OperatorFinished { epoch, .. }
+            | CheckpointLifecycleEvent::Completed { epoch, .. }
+            | CheckpointLifecycleEvent::Failed { epoch, .. } => *epoch,
+        }
+    }
+}
+
+/// Broadcasts checkpoint lifecycle events to any number of subscribers. Clones share the same
+/// channel, so one bus can carry the events of every checkpoint a job takes.
+///
+/// Sending never waits for subscribers. One that falls more than the capacity behind misses
+/// the oldest events, and its next `recv` returns [`broadcast::error::RecvError::Lagged`]
+/// with how many it missed.
+#[derive(Debug, Clone)]
+pub struct CheckpointEventBus {
+    sender: broadcast::Sender<CheckpointLifecycleEvent>,
+}
+
+impl CheckpointEventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self { sender }
+    }
+
+    /// Receives the events sent from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<CheckpointLifecycleEvent> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) fn send(&self, event: CheckpointLifecycleEvent) {
+        // having no subscribers is the common case, not an error
+        let _ = self.sender.send(event);
+    }
+}
+
+impl Default for CheckpointEventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed(epoch: u32) -> CheckpointLifecycleEvent {
+        CheckpointLifecycleEvent::Failed {
+            job_id: "job".to_string(),
+            epoch,
+            reason: "aborted".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_subscriber_misses_oldest() {
+        let bus = CheckpointEventBus::new(2);
+        // sending without subscribers doesn't fail
+        bus.send(failed(0));
+
+        let mut rx = bus.subscribe();
+        for epoch in 1..=3 {
+            bus.send(failed(epoch));
+        }
+        assert!(matches!(
+            rx.recv().await,
+            Err(broadcast::error::RecvError::Lagged(1))
+        ));
+        assert_eq!(rx.recv().await.unwrap().epoch(), 2);
+        assert_eq!(rx.recv().await.unwrap().epoch(), 3);
+    }
+}
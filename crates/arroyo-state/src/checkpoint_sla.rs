@@ -0,0 +1,399 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tracing::warn;
+
+use crate::checkpoint_state::CheckpointState;
+use crate::metrics::{CHECKPOINT_SLA_BREACHED_GAUGE, CHECKPOINT_SLA_BREACH_COUNTER};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CheckpointSlaConfig {
+    /// Checkpoints must complete within this long of starting.
+    pub max_duration: Option<Duration>,
+    /// A checkpoint must complete at least this often. Failed and aborted checkpoints don't
+    /// count.
+    pub max_interval: Option<Duration>,
+}
+
+impl CheckpointSlaConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.max_duration.is_some() || self.max_interval.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointSla {
+    Duration,
+    Interval,
+}
+
+impl CheckpointSla {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CheckpointSla::Duration => "duration",
+            CheckpointSla::Interval => "interval",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointSlaBreach {
+    pub job_id: String,
+    pub sla: CheckpointSla,
+    /// The epoch that breached the SLA. For interval breaches, this is the epoch that was
+    /// in progress (or next to run) when the breach was detected.
+    pub epoch: u32,
+    pub measured: Duration,
+    pub limit: Duration,
+    /// The operator that took the longest to checkpoint in the epoch, if it got far enough
+    /// for any operator to report.
+    pub slowest_operator: Option<(String, Duration)>,
+    pub detected_at: SystemTime,
+}
+
+/// Receives events from checkpoint coordination.
+pub trait CheckpointObserver: Send + Sync {
+    fn on_sla_breach(&self, breach: &CheckpointSlaBreach);
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CheckpointSlaStatus {
+    pub last_completed: Option<SystemTime>,
+    pub last_duration: Option<Duration>,
+    /// Breaches that haven't yet been resolved by a checkpoint that met the SLA.
+    pub active_breaches: Vec<CheckpointSlaBreach>,
+}
+
+impl CheckpointSlaStatus {
+    pub fn is_healthy(&self) -> bool {
+        self.active_breaches.is_empty()
+    }
+}
+
+/// Tracks a job's checkpoints against its SLA, reporting each breach once to the
+/// registered observers and to the `arroyo_controller_checkpoint_sla_breaches` metric.
+pub struct CheckpointSlaMonitor {
+    job_id: String,
+    config: CheckpointSlaConfig,
+    observers: Vec<Arc<dyn CheckpointObserver>>,
+    // when the current gap between successful checkpoints started
+    interval_start: SystemTime,
+    status: CheckpointSlaStatus,
+}
+
+impl CheckpointSlaMonitor {
+    pub fn new(job_id: String, config: CheckpointSlaConfig) -> Self {
+        for sla in [CheckpointSla::Duration, CheckpointSla::Interval] {
+            CHECKPOINT_SLA_BREACHED_GAUGE
+                .with_label_values(&[&job_id, sla.name()])
+                .set(0);
+        }
+        Self {
+            job_id,
+            config,
+            observers: vec![],
+            interval_start: SystemTime::now(),
+            status: CheckpointSlaStatus::default(),
+        }
+    }
+
+    pub fn add_observer(&mut self, observer: Arc<dyn CheckpointObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn status(&self) -> &CheckpointSlaStatus {
+        &self.status
+    }
+
+    /// Checks in-progress work against the SLA. Should be called periodically so that
+    /// breaches are reported even if checkpoints stall or stop being taken.
+    pub fn check(&mut self, in_progress: Option<&CheckpointState>, next_epoch: u32) {
+        let now = SystemTime::now();
+        if let (Some(limit), Some(checkpoint)) = (self.config.max_duration, in_progress) {
+            let measured = elapsed(checkpoint.start_time(), now);
+            if measured > limit {
+                self.breach(CheckpointSla::Duration, checkpoint, measured, limit, now);
+            }
+        }
+        if let Some(limit) = self.config.max_interval {
+            let measured = elapsed(self.interval_start, now);
+            if measured > limit {
+                let epoch = in_progress.map(|c| c.epoch()).unwrap_or(next_epoch);
+                let slowest_operator = in_progress.and_then(slowest_operator);
+                self.report(CheckpointSlaBreach {
+                    job_id: self.job_id.clone(),
+                    sla: CheckpointSla::Interval,
+                    epoch,
+                    measured,
+                    limit,
+                    slowest_operator,
+                    detected_at: now,
+                });
+            }
+        }
+    }
+
+    /// Records a successfully completed checkpoint, resolving any active breaches if it met
+    /// the SLA.
+    pub fn checkpoint_completed(&mut self, checkpoint: &CheckpointState) {
+        let now = SystemTime::now();
+        let duration = elapsed(checkpoint.start_time(), now);
+        let interval = elapsed(self.interval_start, now);
+        self.status.last_completed = Some(now);
+        self.status.last_duration = Some(duration);
+        self.interval_start = now;
+
+        let mut breached = false;
+        if let Some(limit) = self.config.max_duration {
+            if duration > limit {
+                breached = true;
+                self.breach(CheckpointSla::Duration, checkpoint, duration, limit, now);
+            }
+        }
+        if let Some(limit) = self.config.max_interval {
+            if interval > limit {
+                breached = true;
+                self.breach(CheckpointSla::Interval, checkpoint, interval, limit, now);
+            }
+        }
+        if !breached {
+            self.status.active_breaches.clear();
+            for sla in [CheckpointSla::Duration, CheckpointSla::Interval] {
+                CHECKPOINT_SLA_BREACHED_GAUGE
+                    .with_label_values(&[&self.job_id, sla.name()])
+                    .set(0);
+            }
+        }
+    }
+
+    /// Records a checkpoint that failed or was aborted. It doesn't reset the interval, so a
+    /// run of failures will eventually breach the interval SLA.
+    pub fn checkpoint_failed(&mut self, checkpoint: &CheckpointState) {
+        if let Some(limit) = self.config.max_duration {
+            let now = SystemTime::now();
+            let measured = elapsed(checkpoint.start_time(), now);
+            if measured > limit {
+                self.breach(CheckpointSla::Duration, checkpoint, measured, limit, now);
+            }
+        }
+    }
+
+    fn breach(
+        &mut self,
+        sla: CheckpointSla,
+        checkpoint: &CheckpointState,
+        measured: Duration,
+        limit: Duration,
+        now: SystemTime,
+    ) {
+        self.report(CheckpointSlaBreach {
+            job_id: self.job_id.clone(),
+            sla,
+            epoch: checkpoint.epoch(),
+            measured,
+            limit,
+            slowest_operator: slowest_operator(checkpoint),
+            detected_at: now,
+        });
+    }
+
+    fn report(&mut self, breach: CheckpointSlaBreach) {
+        // periodic checks see the same breach repeatedly; only report it the first time, but
+        // keep the measurement current
+        if let Some(existing) = self
+            .status
+            .active_breaches
+            .iter_mut()
+            .find(|b| b.sla == breach.sla && b.epoch == breach.epoch)
+        {
+            existing.measured = breach.measured;
+            existing.slowest_operator = breach.slowest_operator;
+            return;
+        }
+
+        warn!(
+            message = "Checkpoint SLA breached",
+            job_id = breach.job_id,
+            sla = breach.sla.name(),
+            epoch = breach.epoch,
+            measured_ms = breach.measured.as_millis() as u64,
+            limit_ms = breach.limit.as_millis() as u64,
+            slowest_operator = breach.slowest_operator.as_ref().map(|(id, _)| id.as_str())
+        );
+        CHECKPOINT_SLA_BREACH_COUNTER
+            .with_label_values(&[&self.job_id, breach.sla.name()])
+            .inc();
+        CHECKPOINT_SLA_BREACHED_GAUGE
+            .with_label_values(&[&self.job_id, breach.sla.name()])
+            .set(1);
+        for observer in &self.observers {
+            observer.on_sla_breach(&breach);
+        }
+        self.status.active_breaches.push(breach);
+    }
+}
+
+fn elapsed(start: SystemTime, now: SystemTime) -> Duration {
+    now.duration_since(start).unwrap_or_default()
+}
+
+fn slowest_operator(checkpoint: &CheckpointState) -> Option<(String, Duration)> {
+    let now = arroyo_types::to_micros(SystemTime::now());
+    checkpoint
+        .operator_details
+        .values()
+        .map(|detail| {
+            let finish = detail.finish_time.unwrap_or(now);
+            (
+                detail.operator_id.clone(),
+                Duration::from_micros(finish.saturating_sub(detail.start_time)),
+            )
+        })
+        .max_by_key(|(_, duration)| *duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct RecordingObserver(Mutex<Vec<CheckpointSlaBreach>>);
+
+    impl CheckpointObserver for RecordingObserver {
+        fn on_sla_breach(&self, breach: &CheckpointSlaBreach) {
+            self.0.lock().unwrap().push(breach.clone());
+        }
+    }
+
+    impl RecordingObserver {
+        fn reported(&self) -> Vec<(CheckpointSla, u32)> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|b| (b.sla, b.epoch))
+                .collect()
+        }
+    }
+
+    fn monitor(
+        job_id: &str,
+        config: CheckpointSlaConfig,
+    ) -> (CheckpointSlaMonitor, Arc<RecordingObserver>) {
+        let mut monitor = CheckpointSlaMonitor::new(job_id.to_string(), config);
+        let observer = Arc::new(RecordingObserver::default());
+        monitor.add_observer(observer.clone());
+        (monitor, observer)
+    }
+
+    fn checkpoint(job_id: &str, epoch: u32) -> CheckpointState {
+        CheckpointState::new(
+            job_id.to_string(),
+            epoch as i64,
+            epoch,
+            1,
+            HashMap::from([("op".to_string(), 1)]),
+        )
+        .unwrap()
+    }
+
+    fn breached(job_id: &str, sla: CheckpointSla) -> i64 {
+        CHECKPOINT_SLA_BREACHED_GAUGE
+            .with_label_values(&[job_id, sla.name()])
+            .get()
+    }
+
+    #[test]
+    fn test_duration_breach_is_reported_once_and_resolved() {
+        let job_id = "sla_duration";
+        let (mut monitor, observer) = monitor(
+            job_id,
+            CheckpointSlaConfig {
+                max_duration: Some(Duration::from_millis(50)),
+                max_interval: None,
+            },
+        );
+
+        let slow = checkpoint(job_id, 1);
+        monitor.check(Some(&slow), 1);
+        assert!(monitor.status().is_healthy());
+
+        std::thread::sleep(Duration::from_millis(100));
+        monitor.check(Some(&slow), 1);
+        monitor.check(Some(&slow), 1);
+        assert_eq!(observer.reported(), vec![(CheckpointSla::Duration, 1)]);
+        assert_eq!(breached(job_id, CheckpointSla::Duration), 1);
+
+        // finishing late is the same breach, with its final measurement
+        monitor.checkpoint_completed(&slow);
+        assert_eq!(observer.reported().len(), 1);
+        let status = monitor.status();
+        assert_eq!(status.active_breaches.len(), 1);
+        assert_eq!(
+            Some(status.active_breaches[0].measured),
+            status.last_duration
+        );
+        assert_eq!(breached(job_id, CheckpointSla::Duration), 1);
+
+        // a checkpoint that meets the SLA resolves it
+        monitor.checkpoint_completed(&checkpoint(job_id, 2));
+        assert!(monitor.status().is_healthy());
+        assert_eq!(breached(job_id, CheckpointSla::Duration), 0);
+        assert_eq!(observer.reported().len(), 1);
+    }
+
+    #[test]
+    fn test_failures_dont_reset_the_interval() {
+        let job_id = "sla_interval";
+        let (mut monitor, observer) = monitor(
+            job_id,
+            CheckpointSlaConfig {
+                max_duration: None,
+                max_interval: Some(Duration::from_millis(50)),
+            },
+        );
+
+        monitor.checkpoint_failed(&checkpoint(job_id, 1));
+        std::thread::sleep(Duration::from_millis(100));
+        monitor.checkpoint_failed(&checkpoint(job_id, 2));
+        assert!(monitor.status().is_healthy());
+
+        // with nothing in progress, the breach is charged to the next epoch
+        monitor.check(None, 3);
+        assert_eq!(observer.reported(), vec![(CheckpointSla::Interval, 3)]);
+        assert_eq!(breached(job_id, CheckpointSla::Interval), 1);
+        assert_eq!(monitor.status().last_completed, None);
+
+        // completing it still breached the interval, so it doesn't resolve anything
+        monitor.checkpoint_completed(&checkpoint(job_id, 3));
+        assert_eq!(monitor.status().active_breaches.len(), 1);
+        assert_eq!(breached(job_id, CheckpointSla::Interval), 1);
+        assert!(monitor.status().last_completed.is_some());
+
+        // but it restarted the interval, so the next one is on time
+        monitor.check(None, 4);
+        monitor.checkpoint_completed(&checkpoint(job_id, 4));
+        assert!(monitor.status().is_healthy());
+        assert_eq!(breached(job_id, CheckpointSla::Interval), 0);
+        assert_eq!(observer.reported().len(), 1);
+    }
+
+    #[test]
+    fn test_disabled_sla_never_breaches() {
+        let job_id = "sla_disabled";
+        let config = CheckpointSlaConfig::default();
+        assert!(!config.is_enabled());
+        let (mut monitor, observer) = monitor(job_id, config);
+
+        let checkpoint = checkpoint(job_id, 1);
+        std::thread::sleep(Duration::from_millis(10));
+        monitor.check(Some(&checkpoint), 1);
+        monitor.checkpoint_failed(&checkpoint);
+        monitor.checkpoint_completed(&checkpoint);
+        assert!(monitor.status().is_healthy());
+        assert!(observer.reported().is_empty());
+    }
+}
@@ -1,26 +1,42 @@
 use std::{
-    collections::{HashMap, HashSet},
-    time::SystemTime,
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    fmt::Debug,
+    time::{Duration, SystemTime},
 };
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use arroyo_rpc::grpc::{
     self,
     api::{self, OperatorCheckpointDetail},
-    CheckpointMetadata, OperatorCheckpointMetadata, OperatorMetadata, SubtaskCheckpointMetadata,
-    TableCheckpointMetadata, TableConfig, TableEnum, TableSubtaskCheckpointMetadata,
-    TaskCheckpointCompletedReq, TaskCheckpointEventReq,
+    CheckpointMetadata, ExpiringKeyedTimeTableConfig, GlobalKeyedTableConfig, KeyPartitioning,
+    OperatorCheckpointMetadata, OperatorMetadata, OperatorRemapping, SubtaskCheckpointMetadata,
+    SubtaskInFlightFiles, TableCheckpointMetadata, TableConfig, TableEnum, TableRestoreNote,
+    TableSubtaskCheckpointMetadata, TaskCheckpointCompletedReq, TaskCheckpointEventReq,
 };
 use arroyo_types::{from_micros, to_micros};
-use tracing::{debug, warn};
+use prost::Message;
+use tokio::sync::broadcast;
+use tracing::{debug, info, info_span, warn, Span};
 
 use crate::{
+    checkpoint_events::{CheckpointEventBus, CheckpointLifecycleEvent},
+    checkpoint_stats::{
+        CheckpointProgress, CheckpointStats, OperatorCheckpointProgress, OperatorCheckpointStats,
+        SubtaskCheckpointProgress, SubtaskCheckpointStats,
+    },
     committing_state::CommittingState,
+    identifiers::validate_identifier,
+    metrics::{
+        CHECKPOINTS_COUNTER, CHECKPOINT_BYTES_GAUGE, CHECKPOINT_DURATION_HISTOGRAM,
+        LAST_FAILED_CHECKPOINT_TIMESTAMP_GAUGE, LAST_SUCCESSFUL_CHECKPOINT_EPOCH_GAUGE,
+        LAST_SUCCESSFUL_CHECKPOINT_TIMESTAMP_GAUGE, OPERATOR_CHECKPOINT_SYNC_HISTOGRAM,
+    },
+    parquet::{operator_retained_files, subtask_written_files},
     tables::{
         expiring_time_key_map::ExpiringTimeKeyTable, global_keyed_map::GlobalKeyedTable,
-        ErasedTable,
+        ErasedTable, RetainedFile,
     },
-    BackingStore, StateBackend,
+    BackingStore, StateBackend, StateBackendKind,
 };
 
 #[derive(Debug, Clone)]
@@ -36,6 +52,24 @@ pub struct CheckpointState {
     subtasks_to_commit: HashSet<(String, u32)>,
     // map of operator_id -> table_name -> subtask_index -> Data
     commit_data: HashMap<String, HashMap<String, HashMap<u32, Vec<u8>>>>,
+    operator_remappings: Vec<OperatorRemapping>,
+    // set once a subtask fails, after which the checkpoint can't complete
+    failed: bool,
+    final_stats: Option<CheckpointStats>,
+    // the name this checkpoint is saved under, if it's a savepoint
+    savepoint: Option<String>,
+    // the metadata of the stateless operators that have finished, which is recorded in the
+    // checkpoint's metadata rather than written for each operator
+    stateless_operators: HashMap<String, OperatorMetadata>,
+    // the number of key groups of the job's tables partitioned by key group, which they
+    // must all agree on
+    key_groups: Option<u32>,
+    // open from when the checkpoint starts until its metadata is written, with a child span
+    // for each operator
+    span: Span,
+    events: CheckpointEventBus,
+    // whether the started event has been sent, which comes before any other
+    started_sent: bool,
 
     // Used for the web ui -- eventually should be replaced with some other way of tracking / reporting
     // this data
@@ -46,10 +80,27 @@ pub struct CheckpointState {
 pub struct OperatorState {
     subtasks: usize,
     subtasks_checkpointed: usize,
+    // the subtasks that have finished, so that repeated completions aren't counted twice
+    finished_subtasks: HashSet<u32>,
     pub start_time: Option<SystemTime>,
     pub finish_time: Option<SystemTime>,
     table_state: HashMap<String, TableState>,
-    watermarks: Vec<Option<SystemTime>>,
+    // the watermark of each subtask that has finished; None for idle subtasks
+    watermarks: BTreeMap<u32, Option<SystemTime>>,
+    backend: Option<StateBackendKind>,
+    // bytes written by the subtasks that have finished, in total and per table
+    bytes: u64,
+    table_bytes: HashMap<String, u64>,
+    // records captured by the subtasks that took unaligned checkpoints
+    in_flight: HashMap<u32, SubtaskInFlightFiles>,
+    // the finished subtasks' notes on tables whose restore reset them or skipped data
+    restore_notes: Vec<TableRestoreNote>,
+    // set once the operator's metadata has been written
+    persisted: bool,
+    // declared stateless by the coordinator, so its subtasks can't report tables
+    stateless: bool,
+    // open from when the operator is first heard from until its metadata is written
+    span: Option<Span>,
 }
 
 impl OperatorState {
@@ -57,22 +108,119 @@ impl OperatorState {
         OperatorState {
             subtasks,
             subtasks_checkpointed: 0,
+            finished_subtasks: HashSet::new(),
             start_time: None,
             finish_time: None,
             table_state: HashMap::new(),
-            watermarks: vec![],
+            watermarks: BTreeMap::new(),
+            backend: None,
+            bytes: 0,
+            table_bytes: HashMap::new(),
+            in_flight: HashMap::new(),
+            restore_notes: vec![],
+            persisted: false,
+            stateless: false,
+            span: None,
         }
     }
 
+    // drops what the finished subtasks have reported, which will never be written
+    fn discard(&mut self) {
+        self.table_state.clear();
+        self.in_flight.clear();
+        self.restore_notes.clear();
+        self.watermarks.clear();
+        self.span = None;
+    }
+
+    /// Whether a completion from the subtask is the first, failing if the operator has no
+    /// such subtask.
+    fn is_new_completion(&self, subtask_index: u32) -> Result<bool> {
+        if subtask_index as usize >= self.subtasks {
+            bail!(
+                "received completion for subtask {}, but the operator only has {} subtasks",
+                subtask_index,
+                self.subtasks
+            );
+        }
+        Ok(!self.finished_subtasks.contains(&subtask_index))
+    }
+
     fn finish_subtask(
         &mut self,
         c: SubtaskCheckpointMetadata,
-    ) -> Option<(
-        HashMap<String, TableConfig>,
-        HashMap<String, TableCheckpointMetadata>,
-    )> {
+    ) -> Result<
+        Option<(
+            HashMap<String, TableConfig>,
+            HashMap<String, TableCheckpointMetadata>,
+        )>,
+    > {
+        if self.stateless && !c.table_metadata.is_empty() {
+            bail!(
+                "subtask {} reported checkpoint metadata for tables, but its operator is stateless",
+                c.subtask_index
+            );
+        }
+        let backend = StateBackendKind::from_name(&c.backend)?;
+        match self.backend {
+            None => self.backend = Some(backend),
+            Some(existing) if existing != backend => {
+                bail!(
+                    "subtask {} checkpointed with state backend {}, but other subtasks used {}",
+                    c.subtask_index,
+                    backend.name(),
+                    existing.name()
+                );
+            }
+            Some(_) => {}
+        }
+        if backend == StateBackendKind::Memory && !c.table_metadata.is_empty() {
+            bail!(
+                "subtask {} reported checkpoint metadata for tables with the in-memory backend, \
+                which doesn't write any",
+                c.subtask_index
+            );
+        }
+        for table in c.table_metadata.keys() {
+            if !c.table_configs.contains_key(table) {
+                bail!(
+                    "subtask {} reported checkpoint metadata for table {}, but no config for it",
+                    c.subtask_index,
+                    table
+                );
+            }
+        }
+        for (table, config) in &c.table_configs {
+            let Some(existing) = self.table_state.get(table) else {
+                continue;
+            };
+            let differences = table_config_differences(&existing.table_config, config);
+            if !differences.is_empty() {
+                bail!(
+                    "subtask {} reported a config for table {} that differs from other subtasks in {}",
+                    c.subtask_index,
+                    table,
+                    differences.join(", ")
+                );
+            }
+        }
+        self.finished_subtasks.insert(c.subtask_index);
         self.subtasks_checkpointed += 1;
-        self.watermarks.push(c.watermark.map(|w| from_micros(w)));
+        self.bytes += c.bytes;
+        for (table, bytes) in &c.table_bytes {
+            *self.table_bytes.entry(table.clone()).or_default() += bytes;
+        }
+        if !c.in_flight_files.is_empty() {
+            self.in_flight.insert(
+                c.subtask_index,
+                SubtaskInFlightFiles {
+                    files: c.in_flight_files,
+                },
+            );
+        }
+        self.restore_notes.extend(c.restore_notes);
+        self.watermarks
+            .insert(c.subtask_index, c.watermark.map(from_micros));
         self.start_time = match self.start_time {
             Some(existing_start_time) => Some(existing_start_time.min(from_micros(c.start_time))),
             None => Some(from_micros(c.start_time)),
@@ -84,39 +232,196 @@ impl OperatorState {
             None => Some(from_micros(c.finish_time)),
         };
         for (table, table_metadata) in c.table_metadata {
-            self.table_state
-                .entry(table)
+            let subtask_tables = &mut self
+                .table_state
+                .entry(table.clone())
                 .or_insert_with_key(|key| TableState {
-                    table_config: c
-                        .table_configs
-                        .get(key)
-                        .expect("should have metadata")
-                        .clone(),
+                    table_config: c.table_configs[key].clone(),
                     subtask_tables: HashMap::new(),
                 })
-                .subtask_tables
-                .insert(table_metadata.subtask_index, table_metadata);
+                .subtask_tables;
+            match subtask_tables.entry(table_metadata.subtask_index) {
+                Entry::Occupied(_) => warn!(
+                    message = "Ignoring repeated table metadata for subtask",
+                    table,
+                    subtask_index = table_metadata.subtask_index,
+                    reported_by = c.subtask_index,
+                ),
+                Entry::Vacant(entry) => {
+                    entry.insert(table_metadata);
+                }
+            }
         }
 
         if self.subtasks == self.subtasks_checkpointed {
-            let (table_configs, table_metadatas) = self
-                .table_state
-                .drain()
-                .filter_map(|(table_name, table_state)| {
-                    table_state
-                        .into_table_metadata()
-                        .map(|(table_config, metadata)| {
-                            ((table_name.clone(), table_config), (table_name, metadata))
-                        })
-                })
-                .unzip();
-            Some((table_configs, table_metadatas))
+            let mut table_configs = HashMap::new();
+            let mut table_metadatas = HashMap::new();
+            for (table_name, table_state) in self.table_state.drain() {
+                if let Some((table_config, metadata)) =
+                    table_state.into_table_metadata(&table_name)?
+                {
+                    table_configs.insert(table_name.clone(), table_config);
+                    table_metadatas.insert(table_name, metadata);
+                }
+            }
+            Ok(Some((table_configs, table_metadatas)))
         } else {
-            None
+            Ok(None)
         }
     }
 }
 
+/// How many of the files a subtask wrote are listed in its checkpoint details; the rest are
+/// only counted, to keep the details of operators that write many files small.
+const MAX_DETAIL_FILES_PER_SUBTASK: usize = 20;
+
+/// Lists the largest of `files` in `detail`, summarizing the others.
+fn set_detail_files(
+    detail: &mut api::TaskCheckpointDetail,
+    mut files: Vec<(String, RetainedFile)>,
+) {
+    files.sort_by(|(_, a), (_, b)| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+    let omitted = files.split_off(files.len().min(MAX_DETAIL_FILES_PER_SUBTASK));
+    detail.omitted_files = omitted.len() as u64;
+    detail.omitted_file_bytes = omitted.iter().filter_map(|(_, file)| file.bytes).sum();
+    detail.files = files
+        .into_iter()
+        .map(|(table_name, file)| api::SubtaskStateFile {
+            table_name,
+            path: file.path,
+            bytes: file.bytes,
+        })
+        .collect();
+}
+
+fn unix_seconds(time: SystemTime) -> f64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Describes the fields in which two configs for the same table differ, as `field (a vs b)`.
+fn table_config_differences(a: &TableConfig, b: &TableConfig) -> Vec<String> {
+    fn compare<T: PartialEq + Debug>(differences: &mut Vec<String>, field: &str, a: T, b: T) {
+        if a != b {
+            differences.push(format!("{} ({:?} vs {:?})", field, a, b));
+        }
+    }
+
+    let mut differences = vec![];
+    compare(
+        &mut differences,
+        "table type",
+        a.table_type(),
+        b.table_type(),
+    );
+    compare(
+        &mut differences,
+        "state backend",
+        &a.state_backend,
+        &b.state_backend,
+    );
+    compare(
+        &mut differences,
+        "path prefix",
+        &a.path_prefix,
+        &b.path_prefix,
+    );
+    compare(
+        &mut differences,
+        "value codec",
+        &a.value_codec,
+        &b.value_codec,
+    );
+    compare(
+        &mut differences,
+        "value version",
+        &a.value_version,
+        &b.value_version,
+    );
+    if a.table_type != b.table_type || a.config == b.config {
+        return differences;
+    }
+    match a.table_type() {
+        TableEnum::GlobalKeyValue => match (
+            GlobalKeyedTableConfig::decode(&a.config[..]),
+            GlobalKeyedTableConfig::decode(&b.config[..]),
+        ) {
+            (Ok(a), Ok(b)) => {
+                compare(&mut differences, "table name", &a.table_name, &b.table_name);
+                compare(
+                    &mut differences,
+                    "description",
+                    &a.description,
+                    &b.description,
+                );
+                compare(
+                    &mut differences,
+                    "two-phase commit",
+                    a.uses_two_phase_commit,
+                    b.uses_two_phase_commit,
+                );
+                compare(&mut differences, "broadcast", a.broadcast, b.broadcast);
+                compare(
+                    &mut differences,
+                    "incremental",
+                    a.incremental,
+                    b.incremental,
+                );
+                compare(
+                    &mut differences,
+                    "partitioning",
+                    a.partitioning(),
+                    b.partitioning(),
+                );
+            }
+            _ => differences.push("config".to_string()),
+        },
+        TableEnum::ExpiringKeyedTimeTable => match (
+            ExpiringKeyedTimeTableConfig::decode(&a.config[..]),
+            ExpiringKeyedTimeTableConfig::decode(&b.config[..]),
+        ) {
+            (Ok(a), Ok(b)) => {
+                compare(&mut differences, "table name", &a.table_name, &b.table_name);
+                compare(
+                    &mut differences,
+                    "description",
+                    &a.description,
+                    &b.description,
+                );
+                compare(
+                    &mut differences,
+                    "retention",
+                    a.retention_micros,
+                    b.retention_micros,
+                );
+                compare(
+                    &mut differences,
+                    "retention rules",
+                    &a.retention_rules,
+                    &b.retention_rules,
+                );
+                compare(
+                    &mut differences,
+                    "expiration mode",
+                    a.expiration_mode(),
+                    b.expiration_mode(),
+                );
+                if a.schema != b.schema {
+                    differences.push("schema".to_string());
+                }
+            }
+            _ => differences.push("config".to_string()),
+        },
+        TableEnum::MissingTableType => differences.push("config".to_string()),
+    }
+    // the configs can still differ in fields that this version doesn't know about
+    if differences.is_empty() {
+        differences.push("config".to_string());
+    }
+    differences
+}
+
 #[derive(Debug, Clone)]
 pub struct TableState {
     table_config: TableConfig,
@@ -124,21 +429,33 @@ pub struct TableState {
 }
 
 impl TableState {
-    fn into_table_metadata(self) -> Option<(TableConfig, TableCheckpointMetadata)> {
-        match self.table_config.table_type() {
-            TableEnum::MissingTableType => unreachable!(),
+    /// Merges the metadata the subtasks reported for `table_name`, failing if it can't be
+    /// merged, as when subtasks running a different version of Arroyo reported it.
+    fn into_table_metadata(
+        self,
+        table_name: &str,
+    ) -> Result<Option<(TableConfig, TableCheckpointMetadata)>> {
+        let mut subtasks: Vec<_> = self.subtask_tables.keys().copied().collect();
+        subtasks.sort();
+        let table_type = self.table_config.table_type();
+        let metadata = match table_type {
+            TableEnum::MissingTableType => Err(anyhow!("the table's config has no table type")),
             TableEnum::GlobalKeyValue => GlobalKeyedTable::merge_checkpoint_metadata(
                 self.table_config.clone(),
                 self.subtask_tables,
-            )
-            .expect("should be able to merge checkpoints"),
+            ),
             TableEnum::ExpiringKeyedTimeTable => ExpiringTimeKeyTable::merge_checkpoint_metadata(
                 self.table_config.clone(),
                 self.subtask_tables,
-            )
-            .expect("should be able to merge checkpoint metadatas"),
+            ),
         }
-        .map(|metadata| (self.table_config, metadata))
+        .with_context(|| {
+            format!(
+                "failed to merge the checkpoint metadata of {:?} table {} from subtasks {:?}",
+                table_type, table_name, subtasks
+            )
+        })?;
+        Ok(metadata.map(|metadata| (self.table_config, metadata)))
     }
 }
 
@@ -149,8 +466,13 @@ impl CheckpointState {
         epoch: u32,
         min_epoch: u32,
         tasks_per_operator: HashMap<String, usize>,
-    ) -> Self {
-        Self {
+    ) -> Result<Self> {
+        validate_identifier("job id", &job_id)?;
+        for operator_id in tasks_per_operator.keys() {
+            validate_identifier("operator id", operator_id)?;
+        }
+        let span = info_span!("checkpoint", job_id = %job_id, epoch, checkpoint_id);
+        Ok(Self {
             job_id,
             checkpoint_id,
             epoch,
@@ -164,20 +486,152 @@ impl CheckpointState {
                 .collect(),
             subtasks_to_commit: HashSet::new(),
             commit_data: HashMap::new(),
+            operator_remappings: vec![],
+            failed: false,
+            final_stats: None,
+            savepoint: None,
+            stateless_operators: HashMap::new(),
+            key_groups: None,
+            span,
+            events: CheckpointEventBus::default(),
+            started_sent: false,
             operator_details: HashMap::new(),
+        })
+    }
+
+    /// Creates the state of a checkpoint that's also saved as a savepoint under `name`. Its
+    /// epoch is never cleaned up, so the job can be restored from it by name however many
+    /// checkpoints follow.
+    pub fn new_savepoint(
+        job_id: String,
+        checkpoint_id: i64,
+        epoch: u32,
+        min_epoch: u32,
+        tasks_per_operator: HashMap<String, usize>,
+        name: String,
+    ) -> Result<Self> {
+        validate_identifier("savepoint name", &name)?;
+        let mut state = Self::new(job_id, checkpoint_id, epoch, min_epoch, tasks_per_operator)?;
+        state.savepoint = Some(name);
+        Ok(state)
+    }
+
+    /// Records the operator remapping that was applied when restoring the job, so that it's
+    /// written to this checkpoint's metadata.
+    pub fn set_operator_remappings(&mut self, operator_remappings: Vec<OperatorRemapping>) {
+        self.operator_remappings = operator_remappings;
+    }
+
+    /// Declares operators that have no state. Their subtasks only acknowledge the checkpoint,
+    /// and the metadata restored for them is recorded in the checkpoint's own metadata instead
+    /// of being written separately. Their acknowledgements are still needed to finish it.
+    pub fn set_stateless_operators(
+        &mut self,
+        operator_ids: impl IntoIterator<Item = String>,
+    ) -> Result<()> {
+        for operator_id in operator_ids {
+            self.operator_state
+                .get_mut(&operator_id)
+                .ok_or_else(|| anyhow!("unknown stateless operator {}", operator_id))?
+                .stateless = true;
         }
+        Ok(())
+    }
+
+    /// Sends this checkpoint's lifecycle events on a bus shared with other checkpoints, rather
+    /// than its own.
+    pub fn set_event_bus(&mut self, events: CheckpointEventBus) {
+        self.events = events;
+    }
+
+    /// Receives this checkpoint's lifecycle events from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<CheckpointLifecycleEvent> {
+        self.events.subscribe()
+    }
+
+    fn send_started(&mut self) {
+        if !self.started_sent {
+            self.started_sent = true;
+            self.events.send(CheckpointLifecycleEvent::Started {
+                job_id: self.job_id.clone(),
+                epoch: self.epoch,
+            });
+        }
+    }
+
+    fn send_event(&mut self, event: CheckpointLifecycleEvent) {
+        self.send_started();
+        self.events.send(event);
+    }
+
+    /// The span of an operator's part in the checkpoint, opened when it's first heard from.
+    /// Operators that are unknown or already finished log to the checkpoint's span.
+    fn operator_span(&mut self, operator_id: &str) -> Span {
+        let parent = &self.span;
+        match self.operator_state.get_mut(operator_id) {
+            Some(state) if !state.persisted => state
+                .span
+                .get_or_insert_with(
+                    || info_span!(parent: parent, "operator_checkpoint", operator_id),
+                )
+                .clone(),
+            _ => self.span.clone(),
+        }
+    }
+
+    /// Records the number of key groups of an operator's tables that are partitioned by key
+    /// group, failing if it differs from that of another table.
+    fn record_key_groups(&mut self, table_configs: &HashMap<String, TableConfig>) -> Result<()> {
+        for (table, config) in table_configs {
+            if config.table_type() != TableEnum::GlobalKeyValue {
+                continue;
+            }
+            let config = GlobalKeyedTableConfig::decode(&config.config[..])
+                .map_err(|e| anyhow!("failed to decode config of table {}: {}", table, e))?;
+            if config.partitioning() != KeyPartitioning::KeyGroup {
+                continue;
+            }
+            match self.key_groups {
+                Some(key_groups) if key_groups != config.key_groups => bail!(
+                    "table {} has {} key groups, but the job's other keyed tables have {}",
+                    table,
+                    config.key_groups,
+                    key_groups
+                ),
+                _ => self.key_groups = Some(config.key_groups),
+            }
+        }
+        Ok(())
+    }
+
+    fn since_barrier_micros(&self, time: u64) -> u64 {
+        time.saturating_sub(to_micros(self.start_time))
     }
 
     pub fn checkpoint_id(&self) -> i64 {
         self.checkpoint_id
     }
 
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
     pub fn start_time(&self) -> SystemTime {
         self.start_time
     }
 
+    pub fn savepoint(&self) -> Option<&str> {
+        self.savepoint.as_deref()
+    }
+
     pub fn checkpoint_event(&mut self, c: TaskCheckpointEventReq) -> anyhow::Result<()> {
-        debug!(message = "Checkpoint event", checkpoint_id = self.checkpoint_id, event_type = ?c.event_type(), subtask_index = c.subtask_index, operator_id = ?c.operator_id);
+        if c.epoch != self.epoch {
+            bail!(
+                "received checkpoint event for epoch {} in checkpoint of epoch {}",
+                c.epoch,
+                self.epoch
+            );
+        }
 
         if grpc::TaskCheckpointEventType::FinishedCommit == c.event_type() {
             bail!(
@@ -186,8 +640,19 @@ impl CheckpointState {
             );
         }
 
+        self.send_started();
+        let span = self.operator_span(&c.operator_id);
+        debug!(
+            parent: &span,
+            message = "Checkpoint event",
+            event_type = ?c.event_type(),
+            subtask_index = c.subtask_index,
+            since_barrier_micros = self.since_barrier_micros(c.time),
+        );
+
         // This is all for the UI
-        self.operator_details
+        let detail = self
+            .operator_details
             .entry(c.operator_id.clone())
             .or_insert_with(|| OperatorCheckpointDetail {
                 operator_id: c.operator_id.clone(),
@@ -195,6 +660,11 @@ impl CheckpointState {
                 finish_time: None,
                 has_state: false,
                 tasks: HashMap::new(),
+                table_storage: vec![],
+                failures: vec![],
+                watermark_skew_micros: None,
+                idle_subtasks: vec![],
+                min_watermark_subtask: None,
             })
             .tasks
             .entry(c.subtask_index)
@@ -204,39 +674,160 @@ impl CheckpointState {
                 finish_time: None,
                 bytes: None,
                 events: vec![],
-            })
-            .events
-            .push(api::TaskCheckpointEvent {
-                time: c.time,
-                event_type: match c.event_type() {
-                    grpc::TaskCheckpointEventType::StartedAlignment => {
-                        api::TaskCheckpointEventType::AlignmentStarted
-                    }
-                    grpc::TaskCheckpointEventType::StartedCheckpointing => {
-                        api::TaskCheckpointEventType::CheckpointStarted
-                    }
-                    grpc::TaskCheckpointEventType::FinishedOperatorSetup => {
-                        api::TaskCheckpointEventType::CheckpointOperatorFinished
-                    }
-                    grpc::TaskCheckpointEventType::FinishedSync => {
-                        api::TaskCheckpointEventType::CheckpointSyncFinished
-                    }
-                    grpc::TaskCheckpointEventType::FinishedCommit => {
-                        api::TaskCheckpointEventType::CheckpointPreCommit
-                    }
-                } as i32,
+                storage_backlog_bytes: None,
+                alignment_micros: None,
+                buffered_records: None,
+                buffered_bytes: None,
+                watermark: None,
+                files: vec![],
+                omitted_files: 0,
+                omitted_file_bytes: 0,
+                alignment_timed_out: false,
             });
+        detail.events.push(api::TaskCheckpointEvent {
+            time: c.time,
+            event_type: match c.event_type() {
+                grpc::TaskCheckpointEventType::StartedAlignment => {
+                    api::TaskCheckpointEventType::AlignmentStarted
+                }
+                grpc::TaskCheckpointEventType::StartedCheckpointing => {
+                    api::TaskCheckpointEventType::CheckpointStarted
+                }
+                grpc::TaskCheckpointEventType::FinishedOperatorSetup => {
+                    api::TaskCheckpointEventType::CheckpointOperatorFinished
+                }
+                grpc::TaskCheckpointEventType::FinishedSync => {
+                    api::TaskCheckpointEventType::CheckpointSyncFinished
+                }
+                grpc::TaskCheckpointEventType::FinishedCommit => {
+                    api::TaskCheckpointEventType::CheckpointPreCommit
+                }
+                grpc::TaskCheckpointEventType::CapturedInFlight => {
+                    api::TaskCheckpointEventType::InFlightCaptured
+                }
+            } as i32,
+        });
+
+        // alignment ends when an aligned checkpoint starts, or when an unaligned one has
+        // captured the records that arrived ahead of its last barrier
+        if matches!(
+            c.event_type(),
+            grpc::TaskCheckpointEventType::StartedCheckpointing
+                | grpc::TaskCheckpointEventType::CapturedInFlight
+        ) {
+            if let Some(started) = detail
+                .events
+                .iter()
+                .find(|e| e.event_type() == api::TaskCheckpointEventType::AlignmentStarted)
+            {
+                detail.alignment_micros = Some(c.time.saturating_sub(started.time));
+            }
+        }
+        if c.event_type() == grpc::TaskCheckpointEventType::CapturedInFlight {
+            detail.buffered_records = Some(c.in_flight_records);
+            detail.buffered_bytes = Some(c.in_flight_bytes);
+        }
         Ok(())
     }
 
+    /// Returns subtasks that started aligning for this epoch more than `timeout` before `now`
+    /// and still haven't started checkpointing, along with how long they've been aligning.
+    /// Each subtask is only returned the first time it's found to be stalled, when it's
+    /// marked as having timed out in the checkpoint's details.
+    pub fn take_stalled_alignments(
+        &mut self,
+        now: SystemTime,
+        timeout: Duration,
+    ) -> Vec<(String, u32, Duration)> {
+        let now = to_micros(now);
+        let mut stalled = vec![];
+        for (operator_id, detail) in &mut self.operator_details {
+            for (subtask_index, task) in &mut detail.tasks {
+                if task.alignment_timed_out {
+                    continue;
+                }
+                let mut alignment_started = None;
+                let mut checkpoint_started = false;
+                for event in &task.events {
+                    match event.event_type() {
+                        api::TaskCheckpointEventType::AlignmentStarted => {
+                            alignment_started = Some(event.time);
+                        }
+                        api::TaskCheckpointEventType::CheckpointStarted => {
+                            checkpoint_started = true;
+                        }
+                        _ => {}
+                    }
+                }
+                let Some(alignment_started) = alignment_started else {
+                    continue;
+                };
+                let aligning_for = Duration::from_micros(now.saturating_sub(alignment_started));
+                if !checkpoint_started && aligning_for > timeout {
+                    task.alignment_timed_out = true;
+                    stalled.push((operator_id.clone(), *subtask_index, aligning_for));
+                }
+            }
+        }
+        stalled
+    }
+
     pub async fn checkpoint_finished(&mut self, c: TaskCheckpointCompletedReq) -> Result<()> {
-        debug!(message = "Checkpoint finished", checkpoint_id = self.checkpoint_id, job_id = self.job_id, 
-        epoch = self.epoch, min_epoch = self.min_epoch, operator_id = %c.operator_id, subtask_index = c.metadata.as_ref().unwrap().subtask_index, time = c.time);
+        self.checkpoint_finished_to::<StateBackend>(c).await
+    }
+
+    /// Like [`CheckpointState::checkpoint_finished`], writing operator metadata to a given
+    /// backing store.
+    pub async fn checkpoint_finished_to<B: BackingStore>(
+        &mut self,
+        c: TaskCheckpointCompletedReq,
+    ) -> Result<()> {
+        if self.failed {
+            info!(
+                message = "Ignoring subtask checkpoint for failed checkpoint",
+                job_id = self.job_id,
+                epoch = self.epoch,
+                operator_id = %c.operator_id,
+            );
+            return Ok(());
+        }
+        if c.epoch != self.epoch {
+            bail!(
+                "received checkpoint of epoch {} from operator {} in checkpoint of epoch {}",
+                c.epoch,
+                c.operator_id,
+                self.epoch
+            );
+        }
         // TODO: UI management
         let metadata = c
             .metadata
             .as_ref()
             .ok_or_else(|| anyhow!("missing metadata for operator {}", c.operator_id))?;
+        self.send_started();
+        let span = self.operator_span(&c.operator_id);
+        debug!(
+            parent: &span,
+            message = "Subtask checkpoint finished",
+            subtask_index = metadata.subtask_index,
+            since_barrier_micros = self.since_barrier_micros(c.time),
+        );
+        // workers retry completions that may already have been delivered
+        if !self
+            .operator_state
+            .get(&c.operator_id)
+            .ok_or_else(|| anyhow!("unexpected operator checkpoint {}", c.operator_id))?
+            .is_new_completion(metadata.subtask_index)?
+        {
+            warn!(
+                message = "Ignoring repeated checkpoint completion",
+                job_id = self.job_id,
+                epoch = self.epoch,
+                operator_id = %c.operator_id,
+                subtask_index = metadata.subtask_index,
+            );
+            return Ok(());
+        }
         let detail = self
             .operator_details
             .entry(c.operator_id.clone())
@@ -246,6 +837,11 @@ impl CheckpointState {
                 finish_time: None,
                 has_state: false,
                 tasks: HashMap::new(),
+                table_storage: vec![],
+                failures: vec![],
+                watermark_skew_micros: None,
+                idle_subtasks: vec![],
+                min_watermark_subtask: None,
             })
             .tasks
             .entry(metadata.subtask_index)
@@ -260,37 +856,87 @@ impl CheckpointState {
                     finish_time: None,
                     bytes: None,
                     events: vec![],
+                    storage_backlog_bytes: None,
+                    alignment_micros: None,
+                    buffered_records: None,
+                    buffered_bytes: None,
+                    watermark: None,
+                    files: vec![],
+                    omitted_files: 0,
+                    omitted_file_bytes: 0,
+                    alignment_timed_out: false,
                 }
             });
         detail.bytes = Some(metadata.bytes);
+        match subtask_written_files(metadata, self.epoch) {
+            Ok(files) => set_detail_files(detail, files),
+            Err(e) => warn!(
+                "failed to list the files written by subtask {} of operator {} in epoch {}: {:?}",
+                metadata.subtask_index, c.operator_id, self.epoch, e
+            ),
+        }
+        detail.storage_backlog_bytes = Some(metadata.peak_pending_write_bytes);
+        detail.finish_time = Some(metadata.finish_time);
+        detail.watermark = metadata.watermark;
 
+        let subtask_index = metadata.subtask_index;
+        let finished = self
+            .operator_state
+            .get_mut(&c.operator_id)
+            .ok_or_else(|| anyhow!("unexpected operator checkpoint {}", c.operator_id))?
+            .finish_subtask(
+                c.metadata
+                    .ok_or_else(|| anyhow!("missing metadata for operator {}", c.operator_id))?,
+            );
+        let finished = match finished {
+            Ok(finished) => finished,
+            Err(e) => {
+                // a subtask checkpoint that can't be merged with the others' fails the whole
+                // checkpoint
+                let e = e.context(format!(
+                    "invalid checkpoint for subtask {} of operator {} in epoch {}",
+                    subtask_index, c.operator_id, self.epoch
+                ));
+                self.fail("failed", format!("{:#}", e));
+                return Err(e);
+            }
+        };
+        if let Some((table_configs, _)) = &finished {
+            if let Err(e) = self.record_key_groups(table_configs) {
+                let e = e.context(format!(
+                    "invalid tables for operator {} in epoch {}",
+                    c.operator_id, self.epoch
+                ));
+                self.fail("failed", format!("{:#}", e));
+                return Err(e);
+            }
+        }
         let operator_state = self
             .operator_state
             .get_mut(&c.operator_id)
-            .ok_or_else(|| anyhow!("unexpected operator checkpoint {}", c.operator_id))?;
-        if let Some((table_configs, table_checkpoint_metadata)) = operator_state.finish_subtask(
-            c.metadata
-                .ok_or_else(|| anyhow!("missing metadata for operator {}", c.operator_id))?,
-        ) {
+            .expect("operator state was found above");
+        if let Some((table_configs, table_checkpoint_metadata)) = finished {
             self.operators_checkpointed += 1;
-            // watermarks are None if any subtasks are None.
-            let (min_watermark, max_watermark) =
-                if operator_state.watermarks.iter().any(|w| w.is_none()) {
-                    (None, None)
-                } else {
-                    (
-                        operator_state
-                            .watermarks
-                            .iter()
-                            .map(|w| to_micros(w.unwrap()))
-                            .min(),
-                        operator_state
-                            .watermarks
-                            .iter()
-                            .map(|w| to_micros(w.unwrap()))
-                            .max(),
-                    )
-                };
+            // idle subtasks have no watermark, so only if every subtask is idle is there none
+            let watermarks = operator_state
+                .watermarks
+                .values()
+                .flatten()
+                .map(|w| to_micros(*w));
+            let (min_watermark, max_watermark) = (watermarks.clone().min(), watermarks.max());
+            let idle_subtasks: Vec<_> = operator_state
+                .watermarks
+                .iter()
+                .filter(|(_, w)| w.is_none())
+                .map(|(subtask_index, _)| *subtask_index)
+                .collect();
+            // the lowest-indexed of the subtasks tied for the lowest watermark
+            let min_watermark_subtask = operator_state
+                .watermarks
+                .iter()
+                .filter_map(|(subtask_index, w)| Some((*subtask_index, (*w)?)))
+                .min_by_key(|(_, w)| *w)
+                .map(|(subtask_index, _)| subtask_index);
             for (table, checkpoint_metadata) in table_checkpoint_metadata.iter() {
                 let config = table_configs
                     .get(table)
@@ -314,28 +960,381 @@ impl CheckpointState {
                         .insert(table.clone(), committing_data);
                 }
             }
-            StateBackend::write_operator_checkpoint_metadata(OperatorCheckpointMetadata {
+            let has_state = !table_checkpoint_metadata.is_empty();
+            let mut restore_notes = std::mem::take(&mut operator_state.restore_notes);
+            restore_notes.sort_by(|a, b| {
+                (&a.table_name, a.subtask_index).cmp(&(&b.table_name, b.subtask_index))
+            });
+            for note in &restore_notes {
+                info!(
+                    "subtask {} of operator {} restored table {} from epoch {} with policy {}, \
+                    skipping {} tuples",
+                    note.subtask_index,
+                    c.operator_id,
+                    note.table_name,
+                    note.restored_epoch,
+                    note.policy().as_str_name(),
+                    note.skipped_tuples
+                );
+            }
+            let mut operator_metadata = OperatorCheckpointMetadata {
                 start_time: to_micros(operator_state.start_time.unwrap()),
                 finish_time: to_micros(operator_state.finish_time.unwrap()),
+                backend: operator_state
+                    .backend
+                    .unwrap_or_default()
+                    .name()
+                    .to_string(),
+                has_state,
+                bytes: operator_state.bytes,
+                table_bytes: operator_state.table_bytes.clone(),
                 table_checkpoint_metadata,
                 table_configs,
+                in_flight: std::mem::take(&mut operator_state.in_flight),
+                restore_notes,
                 operator_metadata: Some(OperatorMetadata {
                     job_id: self.job_id.to_string(),
-                    operator_id: c.operator_id,
+                    operator_id: c.operator_id.clone(),
                     epoch: self.epoch,
                     min_watermark,
                     max_watermark,
                     parallelism: operator_state.subtasks_checkpointed as u64,
                 }),
-            })
-            .await
-            .expect("Should be able to write operator checkpoint metadata");
+                ..Default::default()
+            };
+            // incremental tables reference files written in earlier epochs, which count
+            // towards what the checkpoint references but not what it wrote
+            for (table, files) in operator_retained_files(&operator_metadata)? {
+                let bytes = files.iter().filter_map(|file| file.bytes).sum();
+                operator_metadata.referenced_bytes += bytes;
+                operator_metadata
+                    .table_referenced_bytes
+                    .insert(table, bytes);
+            }
+            if let Some(detail) = self.operator_details.get_mut(&c.operator_id) {
+                detail.has_state = has_state;
+                detail.finish_time = Some(operator_metadata.finish_time);
+                detail.watermark_skew_micros =
+                    min_watermark.zip(max_watermark).map(|(min, max)| max - min);
+                detail.idle_subtasks = idle_subtasks;
+                detail.min_watermark_subtask = min_watermark_subtask;
+            }
+            if operator_state.stateless && operator_metadata.in_flight.is_empty() {
+                // only the watermarks need restoring, which go in the checkpoint's metadata
+                self.stateless_operators.insert(
+                    c.operator_id.clone(),
+                    operator_metadata
+                        .operator_metadata
+                        .expect("operator metadata was set above"),
+                );
+            } else {
+                match StateBackend::operator_storage_usage(&operator_metadata).await {
+                    Ok(table_storage) => {
+                        if let Some(detail) = self.operator_details.get_mut(&c.operator_id) {
+                            detail.table_storage = table_storage;
+                        }
+                    }
+                    Err(e) => warn!(
+                        "failed to compute storage usage for operator {} in epoch {}: {:?}",
+                        c.operator_id, self.epoch, e
+                    ),
+                }
+                if let Err(e) = B::write_operator_checkpoint_metadata(operator_metadata).await {
+                    // without the operator's metadata the checkpoint can never be restored
+                    let e = e.context(format!(
+                        "failed to write checkpoint metadata for operator {} in epoch {}",
+                        c.operator_id, self.epoch
+                    ));
+                    self.fail("failed", format!("{:#}", e));
+                    return Err(e);
+                }
+            }
+            operator_state.persisted = true;
+            if let Some(span) = operator_state.span.take() {
+                debug!(
+                    parent: &span,
+                    message = "Operator checkpoint finished",
+                    subtasks = operator_state.subtasks_checkpointed,
+                    stateless = operator_state.stateless,
+                );
+            }
+            let finished = CheckpointLifecycleEvent::OperatorFinished {
+                job_id: self.job_id.clone(),
+                epoch: self.epoch,
+                operator_id: c.operator_id.clone(),
+                duration_micros: to_micros(operator_state.finish_time.unwrap())
+                    .saturating_sub(to_micros(operator_state.start_time.unwrap())),
+                bytes: operator_state.bytes,
+            };
+            self.send_event(finished);
+            if let Some(sync_micros) = self.operator_sync_micros(&c.operator_id) {
+                OPERATOR_CHECKPOINT_SYNC_HISTOGRAM
+                    .with_label_values(&[&self.job_id, &c.operator_id])
+                    .observe(sync_micros as f64 / 1_000_000.0);
+            }
+
+            if self.done() {
+                self.final_stats = Some(self.compute_stats(Some(SystemTime::now())));
+            }
         }
         Ok(())
     }
 
+    /// How far the checkpoint has got, for each operator and subtask. Only what's already
+    /// been reported is read, so it's cheap enough to call whenever the checkpoint is polled.
+    pub fn progress(&self) -> CheckpointProgress {
+        let mut operators: Vec<_> = self
+            .operator_state
+            .iter()
+            .map(|(operator_id, state)| {
+                let tasks = self
+                    .operator_details
+                    .get(operator_id)
+                    .map(|detail| &detail.tasks);
+                let subtask_progress = (0..state.subtasks as u32)
+                    .map(|subtask_index| {
+                        let task = tasks.and_then(|tasks| tasks.get(&subtask_index));
+                        SubtaskCheckpointProgress {
+                            subtask_index,
+                            latest_event: task.and_then(|task| task.events.last()).and_then(
+                                |event| {
+                                    api::TaskCheckpointEventType::try_from(event.event_type).ok()
+                                },
+                            ),
+                            finished: task.is_some_and(|task| task.finish_time.is_some()),
+                        }
+                    })
+                    .collect();
+                OperatorCheckpointProgress {
+                    operator_id: operator_id.clone(),
+                    subtasks: state.subtasks,
+                    subtasks_finished: state.subtasks_checkpointed,
+                    persisted: state.persisted,
+                    subtask_progress,
+                }
+            })
+            .collect();
+        operators.sort_by(|a, b| a.operator_id.cmp(&b.operator_id));
+        CheckpointProgress {
+            epoch: self.epoch,
+            operators,
+        }
+    }
+
+    /// The operators that still have subtasks to finish, ordered by id.
+    pub fn pending_operators(&self) -> Vec<&str> {
+        let mut pending: Vec<_> = self
+            .operator_state
+            .iter()
+            .filter(|(_, state)| state.subtasks_checkpointed < state.subtasks)
+            .map(|(operator_id, _)| operator_id.as_str())
+            .collect();
+        pending.sort();
+        pending
+    }
+
+    /// The longest any of the operator's subtasks took to finish the synchronous part of the
+    /// checkpoint.
+    fn operator_sync_micros(&self, operator_id: &str) -> Option<u64> {
+        let now = to_micros(SystemTime::now());
+        self.operator_details
+            .get(operator_id)?
+            .tasks
+            .values()
+            .filter_map(|task| {
+                SubtaskCheckpointStats::from_detail(operator_id, task, now).sync_micros
+            })
+            .max()
+    }
+
+    /// Statistics for the checkpoint so far, or the final statistics once it's done.
+    pub fn stats(&self) -> CheckpointStats {
+        match &self.final_stats {
+            Some(stats) => stats.clone(),
+            None => self.compute_stats(None),
+        }
+    }
+
+    /// The statistics recorded when the checkpoint finished, if it's done.
+    pub fn final_stats(&self) -> Option<&CheckpointStats> {
+        self.final_stats.as_ref()
+    }
+
+    fn compute_stats(&self, finish_time: Option<SystemTime>) -> CheckpointStats {
+        let now = to_micros(finish_time.unwrap_or_else(SystemTime::now));
+        let mut operator_ids: Vec<_> = self.operator_state.keys().collect();
+        operator_ids.sort();
+
+        let operators: Vec<_> = operator_ids
+            .into_iter()
+            .map(|operator_id| {
+                let operator_state = &self.operator_state[operator_id];
+                let mut subtask_stats: Vec<_> = self
+                    .operator_details
+                    .get(operator_id)
+                    .map(|detail| {
+                        detail
+                            .tasks
+                            .values()
+                            .map(|task| SubtaskCheckpointStats::from_detail(operator_id, task, now))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                subtask_stats.sort_by_key(|s| s.subtask_index);
+                OperatorCheckpointStats {
+                    operator_id: operator_id.clone(),
+                    subtasks: operator_state.subtasks,
+                    subtasks_finished: operator_state.subtasks_checkpointed,
+                    bytes: subtask_stats.iter().filter_map(|s| s.bytes).sum(),
+                    alignment_micros: subtask_stats
+                        .iter()
+                        .filter_map(|s| s.alignment_micros)
+                        .max(),
+                    sync_micros: subtask_stats.iter().filter_map(|s| s.sync_micros).max(),
+                    watermark_skew_micros: subtask_stats
+                        .iter()
+                        .filter_map(|s| s.watermark)
+                        .max()
+                        .zip(subtask_stats.iter().filter_map(|s| s.watermark).min())
+                        .map(|(max, min)| max - min),
+                    idle_subtasks: subtask_stats
+                        .iter()
+                        .filter(|s| s.finished && s.watermark.is_none())
+                        .map(|s| s.subtask_index)
+                        .collect(),
+                    subtask_stats,
+                }
+            })
+            .collect();
+
+        let start_time = to_micros(self.start_time);
+        CheckpointStats {
+            job_id: self.job_id.clone(),
+            epoch: self.epoch,
+            start_time,
+            finish_time: finish_time.map(to_micros),
+            duration_micros: now.saturating_sub(start_time),
+            failed: self.failed,
+            bytes: operators.iter().map(|o| o.bytes).sum(),
+            slowest_subtask: operators
+                .iter()
+                .flat_map(|o| &o.subtask_stats)
+                .max_by_key(|s| s.duration_micros)
+                .cloned(),
+            operators,
+        }
+    }
+
     pub fn done(&self) -> bool {
-        self.operators == self.operators_checkpointed
+        !self.failed && self.operators == self.operators_checkpointed
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Marks the checkpoint as failed because a subtask failed before finishing it, so that
+    /// it will never be done. Returns whether metadata has already been written for any
+    /// operator in this epoch, which will need to be cleaned up.
+    pub fn fail_subtask(
+        &mut self,
+        operator_id: &str,
+        subtask_index: u32,
+        reason: impl Into<String>,
+    ) -> Result<bool> {
+        if !self.operator_state.contains_key(operator_id) {
+            bail!("unexpected failure of operator {}", operator_id);
+        }
+        let reason = reason.into();
+        warn!(
+            message = "Subtask failed during checkpoint",
+            job_id = self.job_id,
+            epoch = self.epoch,
+            operator_id,
+            subtask_index,
+            reason,
+        );
+
+        self.fail(
+            "failed",
+            format!(
+                "subtask {} of operator {} failed: {}",
+                subtask_index, operator_id, reason
+            ),
+        );
+        let time = to_micros(SystemTime::now());
+        self.operator_details
+            .entry(operator_id.to_string())
+            .or_insert_with(|| OperatorCheckpointDetail {
+                operator_id: operator_id.to_string(),
+                start_time: time,
+                finish_time: None,
+                has_state: false,
+                tasks: HashMap::new(),
+                table_storage: vec![],
+                failures: vec![],
+                watermark_skew_micros: None,
+                idle_subtasks: vec![],
+                min_watermark_subtask: None,
+            })
+            .failures
+            .push(api::SubtaskCheckpointFailure {
+                subtask_index,
+                time,
+                reason,
+            });
+
+        Ok(self.operators_checkpointed > 0)
+    }
+
+    /// Fails the checkpoint if it's been in progress for longer than `timeout` as of `now`,
+    /// returning whether it timed out. Like after a subtask failure, it will then never be
+    /// done.
+    pub fn check_timeout(&mut self, now: SystemTime, timeout: Duration) -> bool {
+        if self.failed || self.done() {
+            return false;
+        }
+        let elapsed = now.duration_since(self.start_time).unwrap_or_default();
+        if elapsed <= timeout {
+            return false;
+        }
+        warn!(
+            message = "Checkpoint timed out",
+            job_id = self.job_id,
+            epoch = self.epoch,
+            elapsed = ?elapsed,
+        );
+        self.fail("timed_out", format!("timed out after {:?}", elapsed));
+        true
+    }
+
+    /// Fails the checkpoint because the coordinator has given up on it, such as when a later
+    /// checkpoint is taken in its place.
+    pub fn abort(&mut self, reason: impl Into<String>) {
+        self.fail("failed", reason.into());
+    }
+
+    fn fail(&mut self, result: &str, reason: String) {
+        if !self.failed {
+            CHECKPOINTS_COUNTER
+                .with_label_values(&[&self.job_id, result])
+                .inc();
+            LAST_FAILED_CHECKPOINT_TIMESTAMP_GAUGE
+                .with_label_values(&[&self.job_id])
+                .set(unix_seconds(SystemTime::now()));
+            self.send_event(CheckpointLifecycleEvent::Failed {
+                job_id: self.job_id.clone(),
+                epoch: self.epoch,
+                reason,
+            });
+        }
+        self.failed = true;
+        // operators that already finished have written their metadata; the rest never will
+        for state in self.operator_state.values_mut() {
+            if state.subtasks_checkpointed < state.subtasks {
+                state.discard();
+            }
+        }
     }
 
     pub fn committing_state(&self) -> CommittingState {
@@ -346,9 +1345,32 @@ impl CheckpointState {
         )
     }
 
-    pub async fn save_state(&self) -> Result<()> {
+    pub async fn save_state(&mut self) -> Result<()> {
+        self.save_state_to::<StateBackend>().await
+    }
+
+    /// Like [`CheckpointState::save_state`], writing to a given backing store.
+    ///
+    /// The checkpoint's metadata is what marks it as restorable, so it's only written once every
+    /// operator's metadata has been.
+    pub async fn save_state_to<B: BackingStore>(&mut self) -> Result<()> {
+        if self.failed {
+            bail!("can't write metadata for failed checkpoint {}", self.epoch);
+        }
+        if let Some(operator_id) = self
+            .operator_state
+            .iter()
+            .find(|(_, state)| !state.persisted)
+            .map(|(operator_id, _)| operator_id)
+        {
+            bail!(
+                "can't write metadata for checkpoint {} before operator {} has written its metadata",
+                self.epoch,
+                operator_id
+            );
+        }
         let finish_time = SystemTime::now();
-        StateBackend::write_checkpoint_metadata(CheckpointMetadata {
+        let metadata = CheckpointMetadata {
             job_id: self.job_id.clone(),
             epoch: self.epoch,
             min_epoch: self.min_epoch,
@@ -359,8 +1381,1514 @@ impl CheckpointState {
                 .keys()
                 .map(|key| key.to_string())
                 .collect(),
-        })
-        .await?;
+            operator_remappings: self.operator_remappings.clone(),
+            savepoint: self.savepoint.clone(),
+            stateless_operators: self.stateless_operators.clone(),
+            key_groups: self.key_groups.unwrap_or_default(),
+            ..Default::default()
+        };
+        B::write_checkpoint_metadata(metadata.clone()).await?;
+        // written once the checkpoint is complete, so a listed savepoint can always be restored
+        if self.savepoint.is_some() {
+            B::write_savepoint_metadata(metadata).await?;
+        }
+
+        CHECKPOINT_DURATION_HISTOGRAM
+            .with_label_values(&[&self.job_id])
+            .observe(
+                finish_time
+                    .duration_since(self.start_time)
+                    .unwrap_or_default()
+                    .as_secs_f64(),
+            );
+        CHECKPOINT_BYTES_GAUGE
+            .with_label_values(&[&self.job_id])
+            .set(self.stats().bytes as i64);
+        CHECKPOINTS_COUNTER
+            .with_label_values(&[&self.job_id, "completed"])
+            .inc();
+        // alerts on the age of the last completed checkpoint, which keeps growing while
+        // checkpoints are stuck or failing
+        LAST_SUCCESSFUL_CHECKPOINT_TIMESTAMP_GAUGE
+            .with_label_values(&[&self.job_id])
+            .set(unix_seconds(finish_time));
+        LAST_SUCCESSFUL_CHECKPOINT_EPOCH_GAUGE
+            .with_label_values(&[&self.job_id])
+            .set(self.epoch as i64);
+        debug!(
+            parent: &self.span,
+            message = "Checkpoint metadata written",
+            operators = self.operators,
+            savepoint = ?self.savepoint,
+        );
+        // closes the checkpoint's span
+        self.span = Span::none();
+        let stats = self.stats();
+        self.send_event(CheckpointLifecycleEvent::Completed {
+            job_id: self.job_id.clone(),
+            epoch: self.epoch,
+            stats,
+        });
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use arroyo_rpc::grpc::{
+        GlobalKeyedTableSubtaskCheckpointMetadata, GlobalKeyedTableTaskCheckpointMetadata,
+        InFlightFile, OperatorCommitData, TableRestorePolicy, TaskCheckpointEventType,
+    };
+
+    use super::*;
+    use crate::in_memory::InMemoryBackingStore;
+    use crate::remapping::{
+        find_restorable_checkpoint_from, load_restored_operator_metadata_from,
+        load_savepoint_for_restore_from,
+    };
+    use crate::{global_table_config, with_state_backend};
+
+    /// A completed subtask that wrote `bytes` to table `t`, or no tables at all if `bytes`
+    /// is `None`.
+    fn completed(
+        job_id: &str,
+        operator_id: &str,
+        subtask_index: u32,
+        bytes: Option<u64>,
+    ) -> TaskCheckpointCompletedReq {
+        let mut metadata = SubtaskCheckpointMetadata {
+            subtask_index,
+            backend: StateBackend::name().to_string(),
+            ..Default::default()
+        };
+        if let Some(bytes) = bytes {
+            metadata.bytes = bytes;
+            metadata.table_bytes = HashMap::from([("t".to_string(), bytes)]);
+            metadata.table_metadata = HashMap::from([(
+                "t".to_string(),
+                TableSubtaskCheckpointMetadata {
+                    subtask_index,
+                    table_type: TableEnum::GlobalKeyValue.into(),
+                    data: GlobalKeyedTableSubtaskCheckpointMetadata {
+                        subtask_index,
+                        file: Some(format!("{}-{}", operator_id, subtask_index)),
+                        file_sizes: vec![bytes],
+                        ..Default::default()
+                    }
+                    .encode_to_vec(),
+                },
+            )]);
+            metadata.table_configs = global_table_config("t", "test");
+        }
+        TaskCheckpointCompletedReq {
+            worker_id: 1,
+            time: 0,
+            job_id: job_id.to_string(),
+            operator_id: operator_id.to_string(),
+            epoch: 1,
+            metadata: Some(metadata),
+            needs_commit: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_operator_metadata_sums_subtask_bytes() {
+        let job_id = "checkpoint-state-bytes";
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 2), ("stateless".to_string(), 1)]),
+        )
+        .unwrap();
+        for c in [
+            completed(job_id, "op", 0, Some(100)),
+            completed(job_id, "op", 1, Some(50)),
+            completed(job_id, "stateless", 0, None),
+        ] {
+            state
+                .checkpoint_finished_to::<InMemoryBackingStore>(c)
+                .await
+                .unwrap();
+        }
+        assert!(state.done());
+
+        let metadata = InMemoryBackingStore::load_operator_metadata(job_id, "op", 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(metadata.has_state);
+        assert_eq!(metadata.bytes, 150);
+        assert_eq!(
+            metadata.table_bytes,
+            HashMap::from([("t".to_string(), 150)])
+        );
+        // nothing was carried over, so everything referenced was written this epoch
+        assert_eq!(metadata.referenced_bytes, 150);
+        let detail = &state.operator_details["op"];
+        assert!(detail.has_state);
+        assert_eq!(detail.tasks[&0].bytes, Some(100));
+        assert_eq!(detail.tasks[&1].bytes, Some(50));
+        assert_eq!(
+            detail.tasks[&0].files,
+            vec![api::SubtaskStateFile {
+                table_name: "t".to_string(),
+                path: "op-0".to_string(),
+                bytes: Some(100),
+            }]
+        );
+        assert_eq!(detail.tasks[&0].omitted_files, 0);
+
+        let metadata = InMemoryBackingStore::load_operator_metadata(job_id, "stateless", 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!metadata.has_state);
+        assert_eq!(metadata.bytes, 0);
+        assert!(!state.operator_details["stateless"].has_state);
+    }
+
+    #[test]
+    fn test_detail_files_are_capped() {
+        let mut detail = api::TaskCheckpointDetail::default();
+        let files = (0..MAX_DETAIL_FILES_PER_SUBTASK as u64 + 3)
+            .map(|i| {
+                let file = RetainedFile {
+                    path: format!("file-{}", i),
+                    epoch: 1,
+                    bytes: Some(i),
+                };
+                ("t".to_string(), file)
+            })
+            .collect();
+        set_detail_files(&mut detail, files);
+
+        // the largest files are listed, and the smallest only counted
+        assert_eq!(detail.files.len(), MAX_DETAIL_FILES_PER_SUBTASK);
+        assert_eq!(
+            detail.files[0].bytes,
+            Some(MAX_DETAIL_FILES_PER_SUBTASK as u64 + 2)
+        );
+        assert_eq!(detail.omitted_files, 3);
+        assert_eq!(detail.omitted_file_bytes, 3);
+    }
+
+    #[tokio::test]
+    async fn test_savepoint() {
+        let job_id = "checkpoint-state-savepoint";
+        assert!(CheckpointState::new_savepoint(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 1)]),
+            String::new(),
+        )
+        .is_err());
+
+        let mut state = CheckpointState::new_savepoint(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 1)]),
+            "v1".to_string(),
+        )
+        .unwrap();
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "op", 0, Some(10)))
+            .await
+            .unwrap();
+        assert!(state.done());
+        state.save_state_to::<InMemoryBackingStore>().await.unwrap();
+
+        // cleaning up past the savepoint's epoch leaves it in place
+        let latest = CheckpointMetadata {
+            job_id: job_id.to_string(),
+            epoch: 2,
+            min_epoch: 1,
+            operator_ids: vec!["op".to_string()],
+            ..Default::default()
+        };
+        InMemoryBackingStore::write_checkpoint_metadata(latest.clone())
+            .await
+            .unwrap();
+        InMemoryBackingStore::cleanup_checkpoint(latest, 1, 2)
+            .await
+            .unwrap();
+        assert!(
+            InMemoryBackingStore::load_operator_metadata(job_id, "op", 1)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        let restored = load_savepoint_for_restore_from::<InMemoryBackingStore>(job_id, "v1")
+            .await
+            .unwrap();
+        assert_eq!(restored.epoch, 1);
+        assert_eq!(restored.savepoint.as_deref(), Some("v1"));
+        assert!(
+            load_savepoint_for_restore_from::<InMemoryBackingStore>(job_id, "v2")
+                .await
+                .is_err()
+        );
+
+        // restoring from an earlier savepoint would overwrite a later one
+        InMemoryBackingStore::write_savepoint_metadata(CheckpointMetadata {
+            job_id: job_id.to_string(),
+            epoch: 2,
+            savepoint: Some("v2".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(
+            load_savepoint_for_restore_from::<InMemoryBackingStore>(job_id, "v1")
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            InMemoryBackingStore::list_savepoints(job_id)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|savepoint| savepoint.epoch)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_referenced_bytes_include_retained_files() {
+        let job_id = "checkpoint-state-referenced-bytes";
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            2,
+            1,
+            HashMap::from([("op".to_string(), 2)]),
+        )
+        .unwrap();
+        for subtask_index in 0..2 {
+            // an incremental table that wrote 10 bytes this epoch, and references a
+            // 1000-byte file from the first epoch that both subtasks restored
+            let mut c = completed(job_id, "op", subtask_index, Some(10));
+            c.epoch = 2;
+            let metadata = c.metadata.as_mut().unwrap();
+            let table = metadata.table_metadata.get_mut("t").unwrap();
+            let mut data =
+                GlobalKeyedTableSubtaskCheckpointMetadata::decode(&mut table.data.as_slice())
+                    .unwrap();
+            data.retained_files = vec!["op-base".to_string()];
+            data.retained_file_sizes = HashMap::from([("op-base".to_string(), 1000)]);
+            data.file_epochs =
+                HashMap::from([("op-base".to_string(), 1), (data.file.clone().unwrap(), 2)]);
+            table.data = data.encode_to_vec();
+            state
+                .checkpoint_finished_to::<InMemoryBackingStore>(c)
+                .await
+                .unwrap();
+        }
+
+        let metadata = InMemoryBackingStore::load_operator_metadata(job_id, "op", 2)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.bytes, 20);
+        assert_eq!(metadata.referenced_bytes, 1020);
+        assert_eq!(
+            metadata.table_referenced_bytes,
+            HashMap::from([("t".to_string(), 1020)])
+        );
+        let table = GlobalKeyedTableTaskCheckpointMetadata::decode(
+            &mut metadata.table_checkpoint_metadata["t"].data.as_slice(),
+        )
+        .unwrap();
+        assert_eq!(table.files[0], "op-base");
+        assert_eq!(table.files.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_fail_subtask() {
+        let job_id = "checkpoint-state-failed";
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 2), ("sink".to_string(), 1)]),
+        )
+        .unwrap();
+        for c in [
+            completed(job_id, "sink", 0, Some(10)),
+            completed(job_id, "op", 0, Some(100)),
+        ] {
+            state
+                .checkpoint_finished_to::<InMemoryBackingStore>(c)
+                .await
+                .unwrap();
+        }
+
+        // the sink's metadata has already been written
+        assert!(state.fail_subtask("op", 1, "worker lost").unwrap());
+        assert!(state.failed());
+        assert!(!state.done());
+        let failures = &state.operator_details["op"].failures;
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].subtask_index, 1);
+        assert_eq!(failures[0].reason, "worker lost");
+
+        // a subtask that finishes afterwards doesn't complete the operator
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "op", 1, Some(50)))
+            .await
+            .unwrap();
+        assert!(!state.done());
+        assert!(
+            InMemoryBackingStore::load_operator_metadata(job_id, "op", 1)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        assert!(state.fail_subtask("missing", 0, "worker lost").is_err());
+
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            2,
+            2,
+            1,
+            HashMap::from([("op".to_string(), 1)]),
+        )
+        .unwrap();
+        assert!(!state.fail_subtask("op", 0, "worker lost").unwrap());
+    }
+
+    fn event(
+        operator_id: &str,
+        subtask_index: u32,
+        time: u64,
+        event_type: TaskCheckpointEventType,
+    ) -> TaskCheckpointEventReq {
+        TaskCheckpointEventReq {
+            worker_id: 1,
+            time,
+            job_id: "".to_string(),
+            operator_id: operator_id.to_string(),
+            subtask_index,
+            epoch: 1,
+            event_type: event_type.into(),
+            in_flight_records: 0,
+            in_flight_bytes: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unaligned_checkpoint_details() {
+        let job_id = "checkpoint-state-unaligned";
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 2)]),
+        )
+        .unwrap();
+
+        // subtask 0 checkpoints on its first barrier and captures what arrives before the
+        // last one; subtask 1 waits for alignment
+        for c in [
+            event("op", 0, 1_000, TaskCheckpointEventType::StartedAlignment),
+            event(
+                "op",
+                0,
+                1_100,
+                TaskCheckpointEventType::StartedCheckpointing,
+            ),
+            TaskCheckpointEventReq {
+                in_flight_records: 10,
+                in_flight_bytes: 640,
+                ..event("op", 0, 5_000, TaskCheckpointEventType::CapturedInFlight)
+            },
+            event("op", 1, 1_000, TaskCheckpointEventType::StartedAlignment),
+            event(
+                "op",
+                1,
+                3_000,
+                TaskCheckpointEventType::StartedCheckpointing,
+            ),
+        ] {
+            state.checkpoint_event(c).unwrap();
+        }
+
+        let in_flight = vec![InFlightFile {
+            input: 1,
+            file: "op-0-in-flight".to_string(),
+            records: 10,
+            bytes: 640,
+        }];
+        let mut c = completed(job_id, "op", 0, Some(100));
+        c.metadata.as_mut().unwrap().in_flight_files = in_flight.clone();
+        for c in [c, completed(job_id, "op", 1, Some(100))] {
+            state
+                .checkpoint_finished_to::<InMemoryBackingStore>(c)
+                .await
+                .unwrap();
+        }
+
+        let detail = &state.operator_details["op"];
+        assert_eq!(detail.tasks[&0].alignment_micros, Some(4_000));
+        assert_eq!(detail.tasks[&0].buffered_records, Some(10));
+        assert_eq!(detail.tasks[&0].buffered_bytes, Some(640));
+        assert_eq!(detail.tasks[&1].alignment_micros, Some(2_000));
+        assert_eq!(detail.tasks[&1].buffered_records, None);
+
+        let metadata = InMemoryBackingStore::load_operator_metadata(job_id, "op", 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            metadata.in_flight,
+            HashMap::from([(0, SubtaskInFlightFiles { files: in_flight })])
+        );
+    }
+
+    #[test]
+    fn test_stalled_alignments() {
+        let mut state = CheckpointState::new(
+            "checkpoint-state-stalled".to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 3)]),
+        )
+        .unwrap();
+        for c in [
+            event("op", 0, 1_000, TaskCheckpointEventType::StartedAlignment),
+            event("op", 1, 1_000, TaskCheckpointEventType::StartedAlignment),
+            event(
+                "op",
+                1,
+                1_500,
+                TaskCheckpointEventType::StartedCheckpointing,
+            ),
+            event("op", 2, 8_000, TaskCheckpointEventType::StartedAlignment),
+        ] {
+            state.checkpoint_event(c).unwrap();
+        }
+
+        // only the subtask that's still aligning past the timeout has stalled
+        let now = from_micros(10_000);
+        let timeout = Duration::from_millis(5);
+        assert_eq!(
+            state.take_stalled_alignments(now, timeout),
+            vec![("op".to_string(), 0, Duration::from_millis(9))]
+        );
+        let tasks = &state.operator_details["op"].tasks;
+        assert!(tasks[&0].alignment_timed_out);
+        assert!(!tasks[&1].alignment_timed_out);
+        assert!(!tasks[&2].alignment_timed_out);
+        let stats = state.stats();
+        let timed_out: Vec<_> = stats.operators[0]
+            .subtask_stats
+            .iter()
+            .map(|subtask| subtask.alignment_timed_out)
+            .collect();
+        assert_eq!(timed_out, vec![true, false, false]);
+
+        // each stall is reported once, as others reach the timeout
+        let now = from_micros(20_000);
+        assert_eq!(
+            state.take_stalled_alignments(now, timeout),
+            vec![("op".to_string(), 2, Duration::from_millis(12))]
+        );
+        assert!(state.take_stalled_alignments(now, timeout).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_restore_notes() {
+        let job_id = "checkpoint-state-restore-notes";
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 2)]),
+        )
+        .unwrap();
+        let note = |subtask_index, policy: TableRestorePolicy, skipped_tuples| TableRestoreNote {
+            table_name: "t".to_string(),
+            subtask_index,
+            policy: policy.into(),
+            restored_epoch: 4,
+            skipped_tuples,
+        };
+        let mut first = completed(job_id, "op", 1, Some(100));
+        first.metadata.as_mut().unwrap().restore_notes =
+            vec![note(1, TableRestorePolicy::SkipCorrupt, 3)];
+        let mut second = completed(job_id, "op", 0, Some(100));
+        second.metadata.as_mut().unwrap().restore_notes =
+            vec![note(0, TableRestorePolicy::Reset, 0)];
+        for c in [first, second] {
+            state
+                .checkpoint_finished_to::<InMemoryBackingStore>(c)
+                .await
+                .unwrap();
+        }
+
+        let metadata = InMemoryBackingStore::load_operator_metadata(job_id, "op", 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            metadata.restore_notes,
+            vec![
+                note(0, TableRestorePolicy::Reset, 0),
+                note(1, TableRestorePolicy::SkipCorrupt, 3)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let job_id = "checkpoint-state-stats";
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 2)]),
+        )
+        .unwrap();
+        for (subtask_index, started, synced) in [(0, 1_500, 1_800), (1, 1_200, 1_300)] {
+            for (time, event_type) in [
+                (1_000, TaskCheckpointEventType::StartedAlignment),
+                (started, TaskCheckpointEventType::StartedCheckpointing),
+                (synced, TaskCheckpointEventType::FinishedSync),
+            ] {
+                state
+                    .checkpoint_event(event("op", subtask_index, time, event_type))
+                    .unwrap();
+            }
+        }
+
+        let stats = state.stats();
+        assert_eq!(stats.finish_time, None);
+        assert!(state.final_stats().is_none());
+        let operator = &stats.operators[0];
+        assert_eq!(operator.subtasks, 2);
+        assert_eq!(operator.subtasks_finished, 0);
+        assert_eq!(operator.alignment_micros, Some(500));
+        assert_eq!(operator.sync_micros, Some(300));
+        assert_eq!(operator.subtask_stats[1].alignment_micros, Some(200));
+        assert!(!operator.subtask_stats[1].finished);
+
+        for (subtask_index, finish_time, bytes) in [(0, 2_000, 100), (1, 9_000, 50)] {
+            let mut c = completed(job_id, "op", subtask_index, Some(bytes));
+            c.metadata.as_mut().unwrap().finish_time = finish_time;
+            state
+                .checkpoint_finished_to::<InMemoryBackingStore>(c)
+                .await
+                .unwrap();
+        }
+        assert!(state.done());
+
+        let stats = state.final_stats().unwrap().clone();
+        assert_eq!(state.stats(), stats);
+        assert!(stats.finish_time.is_some());
+        assert!(!stats.failed);
+        assert_eq!(stats.bytes, 150);
+        assert_eq!(stats.operators[0].subtasks_finished, 2);
+        let slowest = stats.slowest_subtask.unwrap();
+        assert_eq!(slowest.subtask_index, 1);
+        assert_eq!(slowest.duration_micros, 8_000);
+        assert!(slowest.finished);
+    }
+
+    #[tokio::test]
+    async fn test_mixed_backends() {
+        let job_id = "checkpoint-state-mixed-backends";
+        let memory_completed = |subtask_index| {
+            let mut c = completed(job_id, "mem", subtask_index, None);
+            let metadata = c.metadata.as_mut().unwrap();
+            metadata.backend = StateBackendKind::Memory.name().to_string();
+            metadata.table_configs =
+                with_state_backend(global_table_config("t", "test"), StateBackendKind::Memory);
+            c
+        };
+        let new_state = || {
+            CheckpointState::new(
+                job_id.to_string(),
+                1,
+                1,
+                1,
+                HashMap::from([("op".to_string(), 1), ("mem".to_string(), 2)]),
+            )
+            .unwrap()
+        };
+
+        let mut state = new_state();
+        for c in [
+            completed(job_id, "op", 0, Some(10)),
+            memory_completed(0),
+            memory_completed(1),
+        ] {
+            state
+                .checkpoint_finished_to::<InMemoryBackingStore>(c)
+                .await
+                .unwrap();
+        }
+        assert!(state.done());
+        state.save_state_to::<InMemoryBackingStore>().await.unwrap();
+
+        let metadata = InMemoryBackingStore::load_operator_metadata(job_id, "op", 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.backend, StateBackend::name());
+        assert!(metadata.table_checkpoint_metadata.contains_key("t"));
+        let metadata = InMemoryBackingStore::load_operator_metadata(job_id, "mem", 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.backend, StateBackendKind::Memory.name());
+        assert!(metadata.table_checkpoint_metadata.is_empty());
+        assert!(metadata.table_configs.contains_key("t"));
+
+        // the subtasks of an operator all use the same backend
+        let mut state = new_state();
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(memory_completed(0))
+            .await
+            .unwrap();
+        let mut c = completed(job_id, "mem", 1, None);
+        c.metadata.as_mut().unwrap().table_configs = global_table_config("t", "test");
+        let err = state
+            .checkpoint_finished_to::<InMemoryBackingStore>(c)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("state backend"), "{:#}", err);
+
+        // and the in-memory backend has no table data to report
+        let mut state = new_state();
+        let mut c = completed(job_id, "mem", 0, Some(10));
+        c.metadata.as_mut().unwrap().backend = StateBackendKind::Memory.name().to_string();
+        let err = state
+            .checkpoint_finished_to::<InMemoryBackingStore>(c)
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("in-memory backend"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_table_configs() {
+        let job_id = "checkpoint-state-mismatched-configs";
+        let new_state = || {
+            CheckpointState::new(
+                job_id.to_string(),
+                1,
+                1,
+                1,
+                HashMap::from([("op".to_string(), 2)]),
+            )
+            .unwrap()
+        };
+
+        let mut state = new_state();
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "op", 0, Some(10)))
+            .await
+            .unwrap();
+        let mut c = completed(job_id, "op", 1, Some(10));
+        let config = c
+            .metadata
+            .as_mut()
+            .unwrap()
+            .table_configs
+            .get_mut("t")
+            .unwrap();
+        config.config = GlobalKeyedTableConfig {
+            table_name: "t".to_string(),
+            description: "test".to_string(),
+            incremental: true,
+            ..Default::default()
+        }
+        .encode_to_vec();
+        config.path_prefix = Some("cold".to_string());
+        let err = state
+            .checkpoint_finished_to::<InMemoryBackingStore>(c)
+            .await
+            .unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("operator op"), "{}", message);
+        assert!(message.contains("table t"), "{}", message);
+        assert!(
+            message.contains("incremental (false vs true)"),
+            "{}",
+            message
+        );
+        assert!(message.contains("path prefix"), "{}", message);
+        assert!(state.failed());
+        assert!(!state.done());
+
+        // table metadata without a config
+        let mut state = new_state();
+        let mut c = completed(job_id, "op", 0, Some(10));
+        c.metadata.as_mut().unwrap().table_configs.clear();
+        assert!(state
+            .checkpoint_finished_to::<InMemoryBackingStore>(c)
+            .await
+            .is_err());
+        assert!(state.failed());
+    }
+
+    #[tokio::test]
+    async fn test_unmergeable_table_metadata() {
+        let job_id = "checkpoint-state-unmergeable-metadata";
+        let new_state = || {
+            CheckpointState::new(
+                job_id.to_string(),
+                1,
+                1,
+                1,
+                HashMap::from([("op".to_string(), 2)]),
+            )
+            .unwrap()
+        };
+        let with_codec = |subtask_index, codec: &str| {
+            let mut c = completed(job_id, "op", subtask_index, Some(10));
+            let table = c
+                .metadata
+                .as_mut()
+                .unwrap()
+                .table_metadata
+                .get_mut("t")
+                .unwrap();
+            table.data = GlobalKeyedTableSubtaskCheckpointMetadata {
+                subtask_index,
+                file: Some(format!("op-{}", subtask_index)),
+                file_sizes: vec![10],
+                value_codec: Some(codec.to_string()),
+                ..Default::default()
+            }
+            .encode_to_vec();
+            c
+        };
+
+        // the subtasks agree on the table's config, but wrote it with different codecs
+        let mut state = new_state();
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(with_codec(0, "bincode"))
+            .await
+            .unwrap();
+        let err = state
+            .checkpoint_finished_to::<InMemoryBackingStore>(with_codec(1, "bincode-fixint"))
+            .await
+            .unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("operator op"), "{}", message);
+        assert!(
+            message.contains("GlobalKeyValue table t from subtasks [0, 1]"),
+            "{}",
+            message
+        );
+        assert!(message.contains("codecs"), "{}", message);
+        assert!(state.failed());
+        assert!(!state.done());
+
+        // a table config from a version that doesn't set the table type
+        let mut state = new_state();
+        let mut completions = vec![];
+        for subtask_index in 0..2 {
+            let mut c = completed(job_id, "op", subtask_index, Some(10));
+            let metadata = c.metadata.as_mut().unwrap();
+            metadata.table_configs.get_mut("t").unwrap().table_type =
+                TableEnum::MissingTableType.into();
+            metadata.table_metadata.get_mut("t").unwrap().table_type =
+                TableEnum::MissingTableType.into();
+            completions.push(c);
+        }
+        let mut completions = completions.into_iter();
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completions.next().unwrap())
+            .await
+            .unwrap();
+        let err = state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completions.next().unwrap())
+            .await
+            .unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains("MissingTableType table t from subtasks [0, 1]"),
+            "{}",
+            message
+        );
+        assert!(message.contains("no table type"), "{}", message);
+        assert!(state.failed());
+    }
+
+    #[tokio::test]
+    async fn test_watermark_skew_and_idle_subtasks() {
+        let job_id = "checkpoint-state-watermarks";
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 3), ("idle".to_string(), 1)]),
+        )
+        .unwrap();
+        // finishing out of order, with the middle subtask idle and the last reporting twice
+        for (operator_id, subtask_index, watermark) in [
+            ("op", 2, Some(4_000)),
+            ("op", 2, Some(500)),
+            ("op", 1, None),
+            ("op", 0, Some(1_000)),
+            ("idle", 0, None),
+        ] {
+            let mut c = completed(job_id, operator_id, subtask_index, None);
+            c.metadata.as_mut().unwrap().watermark = watermark;
+            state
+                .checkpoint_finished_to::<InMemoryBackingStore>(c)
+                .await
+                .unwrap();
+        }
+
+        let metadata = InMemoryBackingStore::load_operator_metadata(job_id, "op", 1)
+            .await
+            .unwrap()
+            .unwrap()
+            .operator_metadata
+            .unwrap();
+        assert_eq!(metadata.min_watermark, Some(1_000));
+        assert_eq!(metadata.max_watermark, Some(4_000));
+        let detail = &state.operator_details["op"];
+        assert_eq!(detail.watermark_skew_micros, Some(3_000));
+        assert_eq!(detail.idle_subtasks, vec![1]);
+        assert_eq!(detail.min_watermark_subtask, Some(0));
+        // the repeated completion is ignored rather than replacing the first
+        assert_eq!(detail.tasks[&2].watermark, Some(4_000));
+
+        let stats = state.stats();
+        let op = stats
+            .operators
+            .iter()
+            .find(|o| o.operator_id == "op")
+            .unwrap();
+        assert_eq!(op.watermark_skew_micros, Some(3_000));
+        assert_eq!(op.idle_subtasks, vec![1]);
+
+        // an operator whose subtasks are all idle has no watermark
+        let metadata = InMemoryBackingStore::load_operator_metadata(job_id, "idle", 1)
+            .await
+            .unwrap()
+            .unwrap()
+            .operator_metadata
+            .unwrap();
+        assert_eq!(metadata.min_watermark, None);
+        assert_eq!(metadata.max_watermark, None);
+        assert_eq!(state.operator_details["idle"].watermark_skew_micros, None);
+        assert_eq!(state.operator_details["idle"].idle_subtasks, vec![0]);
+        assert_eq!(state.operator_details["idle"].min_watermark_subtask, None);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_completion() {
+        let job_id = "checkpoint-state-repeated";
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 2)]),
+        )
+        .unwrap();
+        for _ in 0..2 {
+            state
+                .checkpoint_finished_to::<InMemoryBackingStore>(completed(
+                    job_id,
+                    "op",
+                    0,
+                    Some(100),
+                ))
+                .await
+                .unwrap();
+        }
+        // the repeat didn't stand in for the outstanding subtask
+        assert!(!state.done());
+        assert_eq!(state.pending_operators(), vec!["op"]);
+        assert!(
+            InMemoryBackingStore::load_operator_metadata(job_id, "op", 1)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "op", 2, Some(1)))
+            .await
+            .is_err());
+
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "op", 1, Some(50)))
+            .await
+            .unwrap();
+        assert!(state.done());
+        let metadata = InMemoryBackingStore::load_operator_metadata(job_id, "op", 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.bytes, 150);
+        assert_eq!(metadata.operator_metadata.as_ref().unwrap().parallelism, 2);
+
+        // a repeat after the operator finished leaves its metadata alone
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "op", 1, Some(50)))
+            .await
+            .unwrap();
+        assert!(state.done());
+        assert_eq!(
+            InMemoryBackingStore::load_operator_metadata(job_id, "op", 1)
+                .await
+                .unwrap(),
+            Some(metadata)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_progress() {
+        let job_id = "checkpoint-state-progress";
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 2), ("sink".to_string(), 1)]),
+        )
+        .unwrap();
+        assert_eq!(state.pending_operators(), vec!["op", "sink"]);
+
+        state
+            .checkpoint_event(event(
+                "op",
+                1,
+                1_000,
+                TaskCheckpointEventType::StartedAlignment,
+            ))
+            .unwrap();
+        for c in [
+            completed(job_id, "op", 0, Some(10)),
+            completed(job_id, "sink", 0, None),
+        ] {
+            state
+                .checkpoint_finished_to::<InMemoryBackingStore>(c)
+                .await
+                .unwrap();
+        }
+        assert_eq!(state.pending_operators(), vec!["op"]);
+
+        let progress = state.progress();
+        assert_eq!(progress.epoch, 1);
+        assert_eq!(progress.operators_finished(), 1);
+        let pending: Vec<_> = progress.pending_operators().collect();
+        assert_eq!(pending.len(), 1);
+        let op = pending[0];
+        assert_eq!(op.operator_id, "op");
+        assert_eq!((op.subtasks_finished, op.subtasks), (1, 2));
+        assert!(!op.persisted);
+        assert_eq!(
+            op.pending_subtasks().cloned().collect::<Vec<_>>(),
+            vec![SubtaskCheckpointProgress {
+                subtask_index: 1,
+                latest_event: Some(api::TaskCheckpointEventType::AlignmentStarted),
+                finished: false,
+            }]
+        );
+        assert!(progress.operators[1].persisted);
+
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "op", 1, Some(10)))
+            .await
+            .unwrap();
+        let progress = state.progress();
+        assert_eq!(progress.pending_operators().count(), 0);
+        assert!(progress.operators.iter().all(|o| o.persisted));
+        assert!(state.pending_operators().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_save_state_after_operator_metadata() {
+        let job_id = "save-state-after-operators";
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("a".to_string(), 1), ("b".to_string(), 1)]),
+        )
+        .unwrap();
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "a", 0, None))
+            .await
+            .unwrap();
+
+        // the checkpoint metadata can't be written before every operator's metadata
+        assert!(state.save_state_to::<InMemoryBackingStore>().await.is_err());
+        assert!(
+            InMemoryBackingStore::load_checkpoint_metadata_if_present(job_id, 1)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "b", 0, None))
+            .await
+            .unwrap();
+        state.save_state_to::<InMemoryBackingStore>().await.unwrap();
+        assert!(
+            InMemoryBackingStore::load_checkpoint_metadata_if_present(job_id, 1)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stateless_operators() {
+        let job_id = "checkpoint-state-stateless";
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 1), ("map".to_string(), 2)]),
+        )
+        .unwrap();
+        assert!(state
+            .set_stateless_operators(["unknown".to_string()])
+            .is_err());
+        state.set_stateless_operators(["map".to_string()]).unwrap();
+
+        for (subtask_index, watermark) in [(0, 2_000), (1, 1_000)] {
+            let mut c = completed(job_id, "map", subtask_index, None);
+            c.metadata.as_mut().unwrap().watermark = Some(watermark);
+            state
+                .checkpoint_finished_to::<InMemoryBackingStore>(c)
+                .await
+                .unwrap();
+        }
+        // the stateless operator writes nothing, but still has to acknowledge the checkpoint
+        assert!(
+            InMemoryBackingStore::load_operator_metadata(job_id, "map", 1)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(!state.done());
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "op", 0, Some(10)))
+            .await
+            .unwrap();
+        assert!(state.done());
+        state.save_state_to::<InMemoryBackingStore>().await.unwrap();
+
+        let checkpoint = InMemoryBackingStore::load_checkpoint_metadata(job_id, 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            checkpoint.stateless_operators["map"].min_watermark,
+            Some(1_000)
+        );
+        assert_eq!(
+            find_restorable_checkpoint_from::<InMemoryBackingStore>(job_id, 1, 1)
+                .await
+                .unwrap(),
+            Some(1)
+        );
+        let restored =
+            load_restored_operator_metadata_from::<InMemoryBackingStore>(&checkpoint, "map")
+                .await
+                .unwrap()
+                .unwrap();
+        assert!(!restored.has_state);
+        assert!(restored.table_checkpoint_metadata.is_empty());
+        assert_eq!(
+            restored.operator_metadata.unwrap().max_watermark,
+            Some(2_000)
+        );
+
+        // a stateless operator that reports tables fails the checkpoint
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            2,
+            2,
+            1,
+            HashMap::from([("map".to_string(), 1)]),
+        )
+        .unwrap();
+        state.set_stateless_operators(["map".to_string()]).unwrap();
+        let mut c = completed(job_id, "map", 0, Some(10));
+        c.epoch = 2;
+        assert!(state
+            .checkpoint_finished_to::<InMemoryBackingStore>(c)
+            .await
+            .is_err());
+        assert!(state.failed());
+    }
+
+    #[tokio::test]
+    async fn test_operator_spans() {
+        let job_id = "checkpoint-state-spans";
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("a".to_string(), 1), ("b".to_string(), 1)]),
+        )
+        .unwrap();
+        let now = to_micros(SystemTime::now());
+        for operator_id in ["a", "b"] {
+            state
+                .checkpoint_event(event(
+                    operator_id,
+                    0,
+                    now,
+                    TaskCheckpointEventType::StartedCheckpointing,
+                ))
+                .unwrap();
+        }
+        assert!(state.operator_state["a"].span.is_some());
+        assert!(state.operator_state["b"].span.is_some());
+
+        // each operator's span closes once its metadata is written, whatever the others do
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "a", 0, None))
+            .await
+            .unwrap();
+        assert!(state.operator_state["a"].span.is_none());
+        assert!(state.operator_state["b"].span.is_some());
+        state
+            .checkpoint_event(event("a", 0, now, TaskCheckpointEventType::FinishedSync))
+            .unwrap();
+        assert!(state.operator_state["a"].span.is_none());
+
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "b", 0, None))
+            .await
+            .unwrap();
+        assert!(state.operator_state["b"].span.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_events() {
+        let job_id = "checkpoint-state-events";
+        let bus = CheckpointEventBus::default();
+        let mut rx = bus.subscribe();
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 1)]),
+        )
+        .unwrap();
+        state.set_event_bus(bus.clone());
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "op", 0, Some(10)))
+            .await
+            .unwrap();
+        state.save_state_to::<InMemoryBackingStore>().await.unwrap();
+
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            CheckpointLifecycleEvent::Started {
+                job_id: job_id.to_string(),
+                epoch: 1
+            }
+        );
+        let CheckpointLifecycleEvent::OperatorFinished {
+            operator_id, bytes, ..
+        } = rx.try_recv().unwrap()
+        else {
+            panic!("expected the operator to finish");
+        };
+        assert_eq!((operator_id.as_str(), bytes), ("op", 10));
+        let CheckpointLifecycleEvent::Completed { stats, .. } = rx.try_recv().unwrap() else {
+            panic!("expected the checkpoint to complete");
+        };
+        assert_eq!(stats.bytes, 10);
+        assert!(rx.try_recv().is_err());
+
+        // a checkpoint that fails before any subtask reports still starts first, and only
+        // fails once
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            2,
+            2,
+            1,
+            HashMap::from([("op".to_string(), 1)]),
+        )
+        .unwrap();
+        state.set_event_bus(bus.clone());
+        state.fail_subtask("op", 0, "worker lost").unwrap();
+        state.abort("aborted");
+        assert_eq!(rx.try_recv().unwrap().epoch(), 2);
+        let CheckpointLifecycleEvent::Failed { reason, .. } = rx.try_recv().unwrap() else {
+            panic!("expected the checkpoint to fail");
+        };
+        assert_eq!(reason, "subtask 0 of operator op failed: worker lost");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let job_id = "checkpoint-state-metrics";
+        let completed_count = || {
+            CHECKPOINTS_COUNTER
+                .with_label_values(&[job_id, "completed"])
+                .get()
+        };
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("op".to_string(), 1)]),
+        )
+        .unwrap();
+        for (time, event_type) in [
+            (1_000, TaskCheckpointEventType::StartedCheckpointing),
+            (3_000, TaskCheckpointEventType::FinishedSync),
+        ] {
+            state
+                .checkpoint_event(event("op", 0, time, event_type))
+                .unwrap();
+        }
+        state
+            .checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, "op", 0, Some(100)))
+            .await
+            .unwrap();
+        let sync = OPERATOR_CHECKPOINT_SYNC_HISTOGRAM.with_label_values(&[job_id, "op"]);
+        assert_eq!(sync.get_sample_count(), 1);
+        assert_eq!(sync.get_sample_sum(), 0.002);
+
+        let before_save = unix_seconds(SystemTime::now());
+        state.save_state_to::<InMemoryBackingStore>().await.unwrap();
+        let last_success = LAST_SUCCESSFUL_CHECKPOINT_TIMESTAMP_GAUGE
+            .with_label_values(&[job_id])
+            .get();
+        assert!(last_success >= before_save);
+        assert_eq!(
+            LAST_SUCCESSFUL_CHECKPOINT_EPOCH_GAUGE
+                .with_label_values(&[job_id])
+                .get(),
+            1
+        );
+        let last_failure = || {
+            LAST_FAILED_CHECKPOINT_TIMESTAMP_GAUGE
+                .with_label_values(&[job_id])
+                .get()
+        };
+        assert_eq!(last_failure(), 0.0);
+        assert_eq!(
+            CHECKPOINT_DURATION_HISTOGRAM
+                .with_label_values(&[job_id])
+                .get_sample_count(),
+            1
+        );
+        assert_eq!(
+            CHECKPOINT_BYTES_GAUGE.with_label_values(&[job_id]).get(),
+            100
+        );
+        assert_eq!(completed_count(), 1);
+
+        // a failed checkpoint is only counted once, however many subtasks fail
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            2,
+            2,
+            1,
+            HashMap::from([("op".to_string(), 2)]),
+        )
+        .unwrap();
+        state.fail_subtask("op", 0, "worker lost").unwrap();
+        state.fail_subtask("op", 1, "worker lost").unwrap();
+        assert!(!state.check_timeout(SystemTime::now() + Duration::from_secs(60), Duration::ZERO));
+        assert_eq!(
+            CHECKPOINTS_COUNTER
+                .with_label_values(&[job_id, "failed"])
+                .get(),
+            1
+        );
+        assert!(last_failure() >= last_success);
+
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            3,
+            3,
+            1,
+            HashMap::from([("op".to_string(), 1)]),
+        )
+        .unwrap();
+        let now = state.start_time() + Duration::from_secs(10);
+        assert!(!state.check_timeout(now, Duration::from_secs(30)));
+        assert!(state.check_timeout(now, Duration::from_secs(5)));
+        assert!(state.failed());
+        assert_eq!(
+            CHECKPOINTS_COUNTER
+                .with_label_values(&[job_id, "timed_out"])
+                .get(),
+            1
+        );
+        assert_eq!(completed_count(), 1);
+        // failures leave the last completed checkpoint as it was
+        assert_eq!(
+            LAST_SUCCESSFUL_CHECKPOINT_EPOCH_GAUGE
+                .with_label_values(&[job_id])
+                .get(),
+            1
+        );
+    }
+
+    /// A sink subtask that pre-committed `transaction` to its two-phase-commit table.
+    fn pre_committed(
+        job_id: &str,
+        subtask_index: u32,
+        transaction: &str,
+    ) -> TaskCheckpointCompletedReq {
+        let mut table_configs = global_table_config("commits", "pre-committed transactions");
+        table_configs.get_mut("commits").unwrap().config = GlobalKeyedTableConfig {
+            table_name: "commits".to_string(),
+            description: "pre-committed transactions".to_string(),
+            uses_two_phase_commit: true,
+            ..Default::default()
+        }
+        .encode_to_vec();
+        TaskCheckpointCompletedReq {
+            worker_id: 1,
+            time: 0,
+            job_id: job_id.to_string(),
+            operator_id: "sink".to_string(),
+            epoch: 1,
+            metadata: Some(SubtaskCheckpointMetadata {
+                subtask_index,
+                backend: StateBackend::name().to_string(),
+                table_metadata: HashMap::from([(
+                    "commits".to_string(),
+                    TableSubtaskCheckpointMetadata {
+                        subtask_index,
+                        table_type: TableEnum::GlobalKeyValue.into(),
+                        data: GlobalKeyedTableSubtaskCheckpointMetadata {
+                            subtask_index,
+                            file: Some(format!("commits-{}", subtask_index)),
+                            commit_data: Some(transaction.as_bytes().to_vec()),
+                            file_sizes: vec![10],
+                            ..Default::default()
+                        }
+                        .encode_to_vec(),
+                    },
+                )]),
+                table_configs,
+                ..Default::default()
+            }),
+            needs_commit: true,
+        }
+    }
+
+    /// A transactional sink that commits whatever each subtask pre-committed.
+    #[derive(Default)]
+    struct MockSink {
+        committed: Vec<(u32, String)>,
+    }
+
+    impl MockSink {
+        fn commit(
+            &mut self,
+            subtask_index: u32,
+            commit_data: &HashMap<String, OperatorCommitData>,
+        ) -> TaskCheckpointEventReq {
+            let transaction = &commit_data["sink"].committing_data["commits"]
+                .commit_data_by_subtask[&subtask_index];
+            self.committed.push((
+                subtask_index,
+                String::from_utf8(transaction.clone()).unwrap(),
+            ));
+            TaskCheckpointEventReq {
+                worker_id: 1,
+                time: 0,
+                job_id: "".to_string(),
+                operator_id: "sink".to_string(),
+                subtask_index,
+                epoch: 1,
+                event_type: TaskCheckpointEventType::FinishedCommit.into(),
+                in_flight_records: 0,
+                in_flight_bytes: 0,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_two_phase_commit_sink() {
+        let job_id = "checkpoint-state-commit";
+        let mut state = CheckpointState::new(
+            job_id.to_string(),
+            1,
+            1,
+            1,
+            HashMap::from([("sink".to_string(), 2)]),
+        )
+        .unwrap();
+        for c in [
+            pre_committed(job_id, 0, "txn-a"),
+            pre_committed(job_id, 1, "txn-b"),
+        ] {
+            state
+                .checkpoint_finished_to::<InMemoryBackingStore>(c)
+                .await
+                .unwrap();
+        }
+        assert!(state.done());
+
+        // the pre-commits are persisted with the checkpoint, for recovery
+        let metadata = InMemoryBackingStore::load_operator_metadata(job_id, "sink", 1)
+            .await
+            .unwrap()
+            .unwrap();
+        let table = GlobalKeyedTableTaskCheckpointMetadata::decode(
+            metadata.table_checkpoint_metadata["commits"]
+                .data
+                .as_slice(),
+        )
+        .unwrap();
+        assert_eq!(
+            table.commit_data_by_subtask,
+            HashMap::from([(0, b"txn-a".to_vec()), (1, b"txn-b".to_vec())])
+        );
+
+        let mut committing = state.committing_state();
+        assert!(!committing.done());
+        let commit_data = committing.committing_data();
+        let mut sink = MockSink::default();
+        for subtask_index in [1, 0] {
+            let event = sink.commit(subtask_index, &commit_data);
+            committing.commit_event(event).unwrap();
+        }
+        assert!(committing.done());
+        sink.committed.sort();
+        assert_eq!(
+            sink.committed,
+            vec![(0, "txn-a".to_string()), (1, "txn-b".to_string())]
+        );
+
+        let mut event = sink.commit(0, &commit_data);
+        event.event_type = TaskCheckpointEventType::FinishedSync.into();
+        assert!(committing.commit_event(event).is_err());
+    }
+}
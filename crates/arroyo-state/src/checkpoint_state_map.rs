@@ -0,0 +1,329 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use arroyo_rpc::grpc::{TaskCheckpointCompletedReq, TaskCheckpointEventReq};
+use tracing::{debug, info};
+
+use crate::{checkpoint_state::CheckpointState, BackingStore, StateBackend};
+
+/// The checkpoints of a job that are in flight at the same time, by epoch.
+///
+/// Reports from subtasks are routed to the checkpoint of their epoch, so a slow operator
+/// still finishing one epoch doesn't hold up the next from starting. Checkpoints may finish
+/// in any order, but their metadata is only written once every earlier epoch has either
+/// been written or aborted, so the latest written checkpoint is always one that can be
+/// restored along with everything before it.
+#[derive(Debug)]
+pub struct CheckpointStateMap {
+    max_in_flight: usize,
+    checkpoints: BTreeMap<u32, CheckpointState>,
+    // the epoch of the last checkpoint started, as epochs may only move forward
+    last_epoch: Option<u32>,
+}
+
+impl CheckpointStateMap {
+    /// Creates a map that allows up to `max_in_flight` checkpoints at once; at least one is
+    /// always allowed.
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            max_in_flight: max_in_flight.max(1),
+            checkpoints: BTreeMap::new(),
+            last_epoch: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checkpoints.is_empty()
+    }
+
+    /// The epochs of the checkpoints in flight, in order.
+    pub fn epochs(&self) -> impl Iterator<Item = u32> + '_ {
+        self.checkpoints.keys().copied()
+    }
+
+    pub fn get(&self, epoch: u32) -> Option<&CheckpointState> {
+        self.checkpoints.get(&epoch)
+    }
+
+    pub fn get_mut(&mut self, epoch: u32) -> Option<&mut CheckpointState> {
+        self.checkpoints.get_mut(&epoch)
+    }
+
+    /// Whether another checkpoint can be started without exceeding the limit.
+    pub fn can_start(&self) -> bool {
+        self.checkpoints.len() < self.max_in_flight
+    }
+
+    /// Adds a checkpoint that's been started. Its epoch must be later than that of every
+    /// checkpoint started before it.
+    pub fn start(&mut self, state: CheckpointState) -> Result<()> {
+        if !self.can_start() {
+            bail!(
+                "can't start checkpoint {}, as {} checkpoints are already in flight",
+                state.epoch(),
+                self.checkpoints.len()
+            );
+        }
+        if let Some(last_epoch) = self.last_epoch {
+            if state.epoch() <= last_epoch {
+                bail!(
+                    "can't start checkpoint {} after checkpoint {}",
+                    state.epoch(),
+                    last_epoch
+                );
+            }
+        }
+        self.last_epoch = Some(state.epoch());
+        self.checkpoints.insert(state.epoch(), state);
+        Ok(())
+    }
+
+    /// Records an event in the checkpoint of its epoch. Events for epochs that aren't in
+    /// flight, such as those that were aborted, are ignored.
+    pub fn checkpoint_event(&mut self, c: TaskCheckpointEventReq) -> Result<()> {
+        match self.checkpoints.get_mut(&c.epoch) {
+            Some(state) => state.checkpoint_event(c),
+            None => {
+                debug!(
+                    message = "Ignoring checkpoint event for epoch not in flight",
+                    epoch = c.epoch,
+                    operator_id = %c.operator_id,
+                    subtask_index = c.subtask_index
+                );
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn checkpoint_finished(&mut self, c: TaskCheckpointCompletedReq) -> Result<()> {
+        self.checkpoint_finished_to::<StateBackend>(c).await
+    }
+
+    /// Records a subtask's completion in the checkpoint of its epoch, writing operator
+    /// metadata to a given backing store. Completions for epochs that aren't in flight are
+    /// ignored.
+    pub async fn checkpoint_finished_to<B: BackingStore>(
+        &mut self,
+        c: TaskCheckpointCompletedReq,
+    ) -> Result<()> {
+        match self.checkpoints.get_mut(&c.epoch) {
+            Some(state) => state.checkpoint_finished_to::<B>(c).await,
+            None => {
+                debug!(
+                    message = "Ignoring checkpoint completion for epoch not in flight",
+                    epoch = c.epoch,
+                    operator_id = %c.operator_id
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Fails every checkpoint in flight because a subtask failed, returning the epochs that
+    /// have already written metadata for some operator.
+    pub fn fail_subtask(
+        &mut self,
+        operator_id: &str,
+        subtask_index: u32,
+        reason: &str,
+    ) -> Result<Vec<u32>> {
+        let mut written = vec![];
+        for (epoch, state) in &mut self.checkpoints {
+            if state.fail_subtask(operator_id, subtask_index, reason)? {
+                written.push(*epoch);
+            }
+        }
+        Ok(written)
+    }
+
+    /// Removes a checkpoint that will never complete, so that later epochs can be written.
+    pub fn abort(&mut self, epoch: u32) -> Option<CheckpointState> {
+        let mut state = self.checkpoints.remove(&epoch)?;
+        info!(
+            message = "Aborting checkpoint",
+            epoch,
+            failed = state.failed(),
+            in_flight = self.checkpoints.len()
+        );
+        state.abort("aborted");
+        Some(state)
+    }
+
+    pub async fn save_completed(&mut self) -> Result<Vec<CheckpointState>> {
+        self.save_completed_to::<StateBackend>().await
+    }
+
+    /// Writes the metadata of the earliest checkpoints in flight that are done, in epoch
+    /// order, stopping at the first that isn't. The written checkpoints are removed and
+    /// returned, to be committed if they need it.
+    pub async fn save_completed_to<B: BackingStore>(&mut self) -> Result<Vec<CheckpointState>> {
+        let mut saved = vec![];
+        while let Some(mut entry) = self.checkpoints.first_entry() {
+            if !entry.get().done() {
+                break;
+            }
+            entry.get_mut().save_state_to::<B>().await?;
+            saved.push(entry.remove());
+        }
+        Ok(saved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use arroyo_rpc::grpc::{SubtaskCheckpointMetadata, TaskCheckpointEventType};
+
+    use super::*;
+    use crate::in_memory::InMemoryBackingStore;
+
+    fn checkpoint(job_id: &str, epoch: u32) -> CheckpointState {
+        CheckpointState::new(
+            job_id.to_string(),
+            epoch as i64,
+            epoch,
+            1,
+            HashMap::from([("op".to_string(), 2)]),
+        )
+        .unwrap()
+    }
+
+    fn completed(job_id: &str, epoch: u32, subtask_index: u32) -> TaskCheckpointCompletedReq {
+        TaskCheckpointCompletedReq {
+            worker_id: 1,
+            time: 0,
+            job_id: job_id.to_string(),
+            operator_id: "op".to_string(),
+            epoch,
+            metadata: Some(SubtaskCheckpointMetadata {
+                subtask_index,
+                backend: StateBackend::name().to_string(),
+                ..Default::default()
+            }),
+            needs_commit: false,
+        }
+    }
+
+    fn started(epoch: u32, subtask_index: u32) -> TaskCheckpointEventReq {
+        TaskCheckpointEventReq {
+            worker_id: 1,
+            time: 0,
+            job_id: "".to_string(),
+            operator_id: "op".to_string(),
+            subtask_index,
+            epoch,
+            event_type: TaskCheckpointEventType::StartedCheckpointing.into(),
+            in_flight_records: 0,
+            in_flight_bytes: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interleaved_epochs() {
+        let job_id = "checkpoint-state-map";
+        let mut map = CheckpointStateMap::new(2);
+        map.start(checkpoint(job_id, 1)).unwrap();
+        map.start(checkpoint(job_id, 2)).unwrap();
+        assert!(!map.can_start());
+        assert!(map.start(checkpoint(job_id, 3)).is_err());
+
+        // the second epoch finishes before the first
+        map.checkpoint_event(started(2, 1)).unwrap();
+        map.checkpoint_event(started(1, 0)).unwrap();
+        for c in [
+            completed(job_id, 2, 1),
+            completed(job_id, 1, 0),
+            completed(job_id, 2, 0),
+        ] {
+            map.checkpoint_finished_to::<InMemoryBackingStore>(c)
+                .await
+                .unwrap();
+        }
+        assert!(map.get(2).unwrap().done());
+        assert!(!map.get(1).unwrap().done());
+        assert_eq!(
+            map.get(1).unwrap().operator_details["op"].tasks.len(),
+            1,
+            "the first epoch only saw its own subtask"
+        );
+
+        // but isn't written until the first has been
+        assert!(map
+            .save_completed_to::<InMemoryBackingStore>()
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(InMemoryBackingStore::load_checkpoint_metadata(job_id, 2)
+            .await
+            .is_err());
+
+        map.checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, 1, 1))
+            .await
+            .unwrap();
+        let saved = map
+            .save_completed_to::<InMemoryBackingStore>()
+            .await
+            .unwrap();
+        assert_eq!(
+            saved.iter().map(|state| state.epoch()).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert!(map.is_empty());
+        for epoch in 1..=2 {
+            assert!(
+                InMemoryBackingStore::load_checkpoint_metadata(job_id, epoch)
+                    .await
+                    .is_ok()
+            );
+        }
+
+        // epochs only move forward
+        assert!(map.start(checkpoint(job_id, 2)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_aborted_epoch() {
+        let job_id = "checkpoint-state-map-aborted";
+        let mut map = CheckpointStateMap::new(2);
+        map.start(checkpoint(job_id, 1)).unwrap();
+        map.start(checkpoint(job_id, 2)).unwrap();
+
+        for subtask_index in 0..2 {
+            map.checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, 2, subtask_index))
+                .await
+                .unwrap();
+        }
+        assert!(map
+            .save_completed_to::<InMemoryBackingStore>()
+            .await
+            .unwrap()
+            .is_empty());
+
+        // once the first epoch is aborted, the second can be written
+        assert!(map.abort(1).is_some());
+        map.checkpoint_finished_to::<InMemoryBackingStore>(completed(job_id, 1, 0))
+            .await
+            .unwrap();
+        let saved = map
+            .save_completed_to::<InMemoryBackingStore>()
+            .await
+            .unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].epoch(), 2);
+        assert!(InMemoryBackingStore::load_checkpoint_metadata(job_id, 1)
+            .await
+            .is_err());
+
+        // a failed subtask fails every epoch in flight
+        map.start(checkpoint(job_id, 3)).unwrap();
+        map.start(checkpoint(job_id, 4)).unwrap();
+        assert!(map.fail_subtask("op", 0, "worker lost").unwrap().is_empty());
+        assert!(map.get(3).unwrap().failed());
+        assert!(map.get(4).unwrap().failed());
+    }
+}
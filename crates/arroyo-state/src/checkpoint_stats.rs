@@ -0,0 +1,148 @@
+use arroyo_rpc::grpc::api::{TaskCheckpointDetail, TaskCheckpointEventType};
+use serde::{Deserialize, Serialize};
+
+/// Statistics for a checkpoint, computed from the events and metadata its subtasks report.
+/// Times are in microseconds since the epoch and durations in microseconds; anything still
+/// running is measured up to when the statistics were computed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckpointStats {
+    pub job_id: String,
+    pub epoch: u32,
+    pub start_time: u64,
+    /// When every operator had finished, for a checkpoint that's done.
+    pub finish_time: Option<u64>,
+    pub duration_micros: u64,
+    pub failed: bool,
+    pub bytes: u64,
+    /// Ordered by operator id.
+    pub operators: Vec<OperatorCheckpointStats>,
+    /// The subtask that has taken the longest to checkpoint.
+    pub slowest_subtask: Option<SubtaskCheckpointStats>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperatorCheckpointStats {
+    pub operator_id: String,
+    pub subtasks: usize,
+    pub subtasks_finished: usize,
+    pub bytes: u64,
+    /// The longest alignment of any subtask.
+    pub alignment_micros: Option<u64>,
+    /// The longest synchronous part of any subtask's checkpoint.
+    pub sync_micros: Option<u64>,
+    /// The difference between the latest and earliest watermarks of the finished subtasks
+    /// that had one.
+    pub watermark_skew_micros: Option<u64>,
+    /// Finished subtasks that had no watermark, ordered by subtask index.
+    pub idle_subtasks: Vec<u32>,
+    /// Ordered by subtask index, including only subtasks that have reported.
+    pub subtask_stats: Vec<SubtaskCheckpointStats>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubtaskCheckpointStats {
+    pub operator_id: String,
+    pub subtask_index: u32,
+    pub finished: bool,
+    /// From the subtask's first event until it finished.
+    pub duration_micros: u64,
+    /// From receiving the first barrier until starting to checkpoint, or for unaligned
+    /// checkpoints until the records in flight were captured.
+    pub alignment_micros: Option<u64>,
+    /// Whether the subtask was found still aligning after the job's alignment timeout.
+    pub alignment_timed_out: bool,
+    /// From starting to checkpoint until the synchronous part finished.
+    pub sync_micros: Option<u64>,
+    pub bytes: Option<u64>,
+    /// The subtask's watermark once it finished, or None if it was idle.
+    pub watermark: Option<u64>,
+}
+
+impl SubtaskCheckpointStats {
+    pub(crate) fn from_detail(operator_id: &str, detail: &TaskCheckpointDetail, now: u64) -> Self {
+        let event_time = |event_type: TaskCheckpointEventType| {
+            detail
+                .events
+                .iter()
+                .find(|e| e.event_type() == event_type)
+                .map(|e| e.time)
+        };
+        let sync_micros = match (
+            event_time(TaskCheckpointEventType::CheckpointStarted),
+            event_time(TaskCheckpointEventType::CheckpointSyncFinished),
+        ) {
+            (Some(started), Some(finished)) => Some(finished.saturating_sub(started)),
+            _ => None,
+        };
+
+        Self {
+            operator_id: operator_id.to_string(),
+            subtask_index: detail.subtask_index,
+            finished: detail.finish_time.is_some(),
+            duration_micros: detail
+                .finish_time
+                .unwrap_or(now)
+                .saturating_sub(detail.start_time),
+            alignment_micros: detail.alignment_micros,
+            alignment_timed_out: detail.alignment_timed_out,
+            sync_micros,
+            bytes: detail.bytes,
+            watermark: detail.watermark,
+        }
+    }
+}
+
+/// How far a checkpoint has got, from what its subtasks have reported so far.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckpointProgress {
+    pub epoch: u32,
+    /// Ordered by operator id.
+    pub operators: Vec<OperatorCheckpointProgress>,
+}
+
+impl CheckpointProgress {
+    pub fn operators_finished(&self) -> usize {
+        self.operators.iter().filter(|o| o.finished()).count()
+    }
+
+    /// The operators that still have subtasks to finish.
+    pub fn pending_operators(&self) -> impl Iterator<Item = &OperatorCheckpointProgress> {
+        self.operators.iter().filter(|o| !o.finished())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperatorCheckpointProgress {
+    pub operator_id: String,
+    pub subtasks: usize,
+    pub subtasks_finished: usize,
+    /// Whether the operator's checkpoint metadata has been written.
+    pub persisted: bool,
+    /// Every subtask of the operator, ordered by subtask index.
+    pub subtask_progress: Vec<SubtaskCheckpointProgress>,
+}
+
+impl OperatorCheckpointProgress {
+    pub fn finished(&self) -> bool {
+        self.subtasks_finished == self.subtasks
+    }
+
+    /// The subtasks that haven't finished, which may not have reported anything yet.
+    pub fn pending_subtasks(&self) -> impl Iterator<Item = &SubtaskCheckpointProgress> {
+        self.subtask_progress.iter().filter(|s| !s.finished)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubtaskCheckpointProgress {
+    pub subtask_index: u32,
+    /// The last event the subtask reported, if any.
+    pub latest_event: Option<TaskCheckpointEventType>,
+    pub finished: bool,
+}
@@ -1,6 +1,10 @@
 use std::collections::{HashMap, HashSet};
 
-use arroyo_rpc::grpc::{OperatorCommitData, TableCommitData};
+use anyhow::{bail, Result};
+use arroyo_rpc::grpc::{
+    OperatorCommitData, TableCommitData, TaskCheckpointEventReq, TaskCheckpointEventType,
+};
+use tracing::warn;
 
 pub struct CommittingState {
     checkpoint_id: i64,
@@ -30,6 +34,30 @@ impl CommittingState {
             .remove(&(operator_id, subtask_index));
     }
 
+    /// Handles a checkpoint event received while committing. Only `FinishedCommit` is
+    /// expected, marking the subtask as committed.
+    pub fn commit_event(&mut self, c: TaskCheckpointEventReq) -> Result<()> {
+        if c.event_type() != TaskCheckpointEventType::FinishedCommit {
+            bail!(
+                "unexpected checkpoint event {:?} from subtask {} of operator {} while committing",
+                c.event_type(),
+                c.subtask_index,
+                c.operator_id
+            );
+        }
+        if !self
+            .subtasks_to_commit
+            .contains(&(c.operator_id.clone(), c.subtask_index))
+        {
+            warn!(
+                "received commit from subtask {} of operator {}, which had nothing to commit",
+                c.subtask_index, c.operator_id
+            );
+        }
+        self.subtask_committed(c.operator_id, c.subtask_index);
+        Ok(())
+    }
+
     pub fn done(&self) -> bool {
         self.subtasks_to_commit.is_empty()
     }
@@ -0,0 +1,341 @@
+use std::env;
+use std::fmt::Debug;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+
+use anyhow::{anyhow, bail, Context, Result};
+use arroyo_storage::StorageProvider;
+use bytes::Bytes;
+use once_cell::sync::OnceCell;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::io::AsyncWrite;
+
+/// Where the master key comes from: `env` or `file`. Unset disables encryption.
+pub const STATE_ENCRYPTION_ENV: &str = "STATE_ENCRYPTION";
+/// The hex-encoded 256-bit master key, for the `env` provider.
+pub const STATE_ENCRYPTION_KEY_ENV: &str = "STATE_ENCRYPTION_KEY";
+/// Path to a file holding the hex-encoded master key, for the `file` provider.
+pub const STATE_ENCRYPTION_KEY_FILE_ENV: &str = "STATE_ENCRYPTION_KEY_FILE";
+
+const MAGIC: &[u8; 8] = b"ARROYOE1";
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const WRAPPED_KEY_LEN: usize = NONCE_LEN + KEY_LEN + TAG_LEN;
+
+/// Supplies the master key that wraps the data key of each encrypted state file.
+pub trait KeyProvider: Send + Sync + Debug {
+    fn master_key(&self) -> Result<[u8; KEY_LEN]>;
+}
+
+/// Reads the master key from `STATE_ENCRYPTION_KEY`.
+#[derive(Debug)]
+pub struct EnvKeyProvider;
+
+impl KeyProvider for EnvKeyProvider {
+    fn master_key(&self) -> Result<[u8; KEY_LEN]> {
+        let key = env::var(STATE_ENCRYPTION_KEY_ENV)
+            .map_err(|_| anyhow!("{} is not set", STATE_ENCRYPTION_KEY_ENV))?;
+        decode_key(&key).with_context(|| format!("invalid key in {}", STATE_ENCRYPTION_KEY_ENV))
+    }
+}
+
+/// Reads the master key from a file.
+#[derive(Debug)]
+pub struct FileKeyProvider {
+    pub path: PathBuf,
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn master_key(&self) -> Result<[u8; KEY_LEN]> {
+        let key = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read key file {}", self.path.display()))?;
+        decode_key(&key).with_context(|| format!("invalid key in {}", self.path.display()))
+    }
+}
+
+fn decode_key(hex: &str) -> Result<[u8; KEY_LEN]> {
+    let hex = hex.trim();
+    if hex.len() != KEY_LEN * 2 {
+        bail!(
+            "expected {} hex characters, found {}",
+            KEY_LEN * 2,
+            hex.len()
+        );
+    }
+    let mut key = [0; KEY_LEN];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2).unwrap_or_default(), 16)
+            .map_err(|_| anyhow!("key is not valid hex"))?;
+    }
+    Ok(key)
+}
+
+static KEY_PROVIDER: OnceCell<Option<Box<dyn KeyProvider>>> = OnceCell::new();
+
+/// Sets the provider of the master key, in place of the one configured by
+/// `STATE_ENCRYPTION`. Must be called before any state is read or written.
+pub fn set_key_provider(provider: Box<dyn KeyProvider>) -> Result<()> {
+    KEY_PROVIDER
+        .set(Some(provider))
+        .map_err(|_| anyhow!("the state encryption key provider has already been set"))
+}
+
+fn key_provider() -> Result<Option<&'static dyn KeyProvider>> {
+    let provider = KEY_PROVIDER.get_or_try_init(|| {
+        let provider: Option<Box<dyn KeyProvider>> =
+            match env::var(STATE_ENCRYPTION_ENV).ok().as_deref() {
+                None | Some("") => None,
+                Some("env") => Some(Box::new(EnvKeyProvider)),
+                Some("file") => Some(Box::new(FileKeyProvider {
+                    path: env::var(STATE_ENCRYPTION_KEY_FILE_ENV)
+                        .map_err(|_| anyhow!("{} is not set", STATE_ENCRYPTION_KEY_FILE_ENV))?
+                        .into(),
+                })),
+                Some(other) => bail!(
+                    "unknown state encryption key provider '{}'; expected env or file",
+                    other
+                ),
+            };
+        Ok::<_, anyhow::Error>(provider)
+    })?;
+    Ok(provider.as_deref())
+}
+
+fn seal(key: &[u8], mut data: Vec<u8>) -> Result<Vec<u8>> {
+    let key = LessSafeKey::new(
+        UnboundKey::new(&AES_256_GCM, key).map_err(|_| anyhow!("invalid encryption key"))?,
+    );
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| anyhow!("failed to generate nonce"))?;
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+        .map_err(|_| anyhow!("failed to encrypt state"))?;
+    let mut sealed = Vec::with_capacity(NONCE_LEN + data.len());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&data);
+    Ok(sealed)
+}
+
+fn open(key: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).ok()?);
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return None;
+    }
+    let (nonce, data) = sealed.split_at(NONCE_LEN);
+    let mut data = data.to_vec();
+    let len = key
+        .open_in_place(
+            Nonce::try_assume_unique_for_key(nonce).ok()?,
+            Aad::empty(),
+            &mut data,
+        )
+        .ok()?
+        .len();
+    data.truncate(len);
+    Some(data)
+}
+
+fn encrypt_with(master_key: &[u8], data: Vec<u8>) -> Result<Vec<u8>> {
+    let mut data_key = [0; KEY_LEN];
+    SystemRandom::new()
+        .fill(&mut data_key)
+        .map_err(|_| anyhow!("failed to generate data key"))?;
+    let wrapped_key = seal(master_key, data_key.to_vec())?;
+    let sealed = seal(&data_key, data)?;
+    let mut encrypted = Vec::with_capacity(MAGIC.len() + wrapped_key.len() + sealed.len());
+    encrypted.extend_from_slice(MAGIC);
+    encrypted.extend_from_slice(&wrapped_key);
+    encrypted.extend_from_slice(&sealed);
+    Ok(encrypted)
+}
+
+fn decrypt_with(master_key: Option<&[u8]>, path: &str, data: Bytes) -> Result<Bytes> {
+    let Some(envelope) = data.strip_prefix(MAGIC) else {
+        // written before encryption was enabled
+        return Ok(data);
+    };
+    let Some(master_key) = master_key else {
+        bail!(
+            "{} is encrypted, but no state encryption key is configured (set {})",
+            path,
+            STATE_ENCRYPTION_ENV
+        );
+    };
+    if envelope.len() < WRAPPED_KEY_LEN {
+        bail!("{} is encrypted but truncated", path);
+    }
+    let (wrapped_key, sealed) = envelope.split_at(WRAPPED_KEY_LEN);
+    let data_key = open(master_key, wrapped_key).ok_or_else(|| {
+        anyhow!(
+            "failed to decrypt {}: the configured state encryption key is not the one it was written with",
+            path
+        )
+    })?;
+    let data = open(&data_key, sealed)
+        .ok_or_else(|| anyhow!("failed to decrypt {}: the file is corrupt", path))?;
+    Ok(data.into())
+}
+
+/// Encrypts state written to the checkpoint storage, if encryption is enabled. Each call
+/// uses a new data key, which is wrapped with the master key and stored at the start of the
+/// output.
+pub(crate) fn encrypt(data: Vec<u8>) -> Result<Vec<u8>> {
+    match key_provider()? {
+        Some(provider) => encrypt_with(&provider.master_key()?, data),
+        None => Ok(data),
+    }
+}
+
+/// Decrypts state read from `path`. Data written without encryption is returned as is, so
+/// checkpoints from before encryption was enabled can still be restored.
+pub(crate) fn decrypt(path: &str, data: Bytes) -> Result<Bytes> {
+    if !data.starts_with(MAGIC) {
+        return Ok(data);
+    }
+    let master_key = key_provider()?
+        .map(|provider| provider.master_key())
+        .transpose()?;
+    decrypt_with(master_key.as_ref().map(|key| key.as_slice()), path, data)
+}
+
+/// Reads a state file written by [`state_file_writer`], decrypting it if needed.
+pub(crate) async fn read_state_file(storage: &StorageProvider, path: &str) -> Result<Bytes> {
+    decrypt(path, fetch_state_file(storage, path).await?)
+}
+
+/// Reads a state file written by [`state_file_writer`] as stored, without decrypting it.
+pub(crate) async fn fetch_state_file(storage: &StorageProvider, path: &str) -> Result<Bytes> {
+    let store = storage.get_backing_store();
+    let location = path.to_string().into();
+    storage
+        .retry_policy()
+        .run("get", path, || async {
+            store.get(&location).await?.bytes().await
+        })
+        .await
+        .with_context(|| format!("failed to read {}", path))
+}
+
+/// Opens a streaming writer for a state file, returning it with the id of the multipart
+/// upload it writes to. When encryption is enabled the file is buffered in memory and
+/// encrypted and uploaded as a whole when the writer is shut down, so there's no multipart
+/// upload.
+pub(crate) async fn state_file_writer(
+    storage: &StorageProvider,
+    path: &str,
+) -> Result<(Option<String>, Box<dyn AsyncWrite + Send + Unpin>)> {
+    if key_provider()?.is_some() {
+        let writer = EncryptingWriter {
+            storage: Arc::new(storage.clone()),
+            path: path.to_string(),
+            buffer: vec![],
+            upload: None,
+        };
+        return Ok((None, Box::new(writer)));
+    }
+    let store = storage.get_backing_store();
+    let location = path.to_string().into();
+    let (multipart_id, writer) = storage
+        .retry_policy()
+        .run("initiate multipart upload", path, || {
+            store.put_multipart(&location)
+        })
+        .await?;
+    Ok((Some(multipart_id), writer))
+}
+
+type Upload = Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+
+struct EncryptingWriter {
+    storage: Arc<StorageProvider>,
+    path: String,
+    buffer: Vec<u8>,
+    upload: Option<Upload>,
+}
+
+impl AsyncWrite for EncryptingWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.buffer.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        if self.upload.is_none() {
+            let encrypted = encrypt(std::mem::take(&mut self.buffer))
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            let storage = self.storage.clone();
+            let path = self.path.clone();
+            self.upload = Some(Box::pin(async move {
+                let store = storage.get_backing_store();
+                let location = path.clone().into();
+                let encrypted = Bytes::from(encrypted);
+                storage
+                    .retry_policy()
+                    .run("put", &path, || store.put(&location, encrypted.clone()))
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            }));
+        }
+        self.upload.as_mut().unwrap().as_mut().poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; KEY_LEN] = [7; KEY_LEN];
+
+    #[test]
+    fn test_round_trip() {
+        let encrypted = encrypt_with(&KEY, b"state".to_vec()).unwrap();
+        assert!(encrypted.starts_with(MAGIC));
+        assert_eq!(
+            decrypt_with(Some(&KEY[..]), "f", encrypted.into()).unwrap(),
+            Bytes::from_static(b"state")
+        );
+    }
+
+    #[test]
+    fn test_unencrypted_data_is_unchanged() {
+        let data = Bytes::from_static(b"PAR1 legacy");
+        assert_eq!(decrypt_with(None, "f", data.clone()).unwrap(), data);
+        assert_eq!(
+            decrypt_with(Some(&KEY[..]), "f", data.clone()).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn test_missing_or_wrong_key() {
+        let encrypted: Bytes = encrypt_with(&KEY, b"state".to_vec()).unwrap().into();
+        let missing = decrypt_with(None, "f", encrypted.clone()).unwrap_err();
+        assert!(missing.to_string().contains("no state encryption key"));
+        let wrong = decrypt_with(Some(&[8; KEY_LEN][..]), "f", encrypted).unwrap_err();
+        assert!(wrong
+            .to_string()
+            .contains("not the one it was written with"));
+    }
+
+    #[test]
+    fn test_decode_key() {
+        assert_eq!(decode_key(&"07".repeat(KEY_LEN)).unwrap(), KEY);
+        assert!(decode_key("07").is_err());
+        assert!(decode_key(&"zz".repeat(KEY_LEN)).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use arrow::datatypes::SchemaRef;
+use arrow::json::reader::infer_json_schema_from_iterator;
+use arrow::json::{LineDelimitedWriter, ReaderBuilder};
+use arrow_array::RecordBatch;
+use arroyo_storage::StorageProvider;
+use arroyo_types::{Data, Key};
+use parquet::arrow::AsyncArrowWriter;
+use serde::Serialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::{info, warn};
+
+use crate::inspect::CheckpointInspector;
+
+/// Rows serialized at a time, and in each row group of a Parquet export.
+const EXPORT_BATCH_ROWS: usize = 1024;
+/// Bytes of JSON Lines buffered before they're written out.
+const EXPORT_BUFFER_BYTES: usize = 1024 * 1024;
+
+/// The formats a table can be exported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One JSON object per line.
+    JsonLines,
+    Parquet,
+}
+
+/// What a table export wrote, and what it left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportStats {
+    /// Records written.
+    pub exported: u64,
+    /// Keys whose last entry in the checkpoint deletes them.
+    pub deleted: u64,
+    /// Rows that had expired as of the operator's checkpointed watermark.
+    pub expired: u64,
+    /// Files that weren't read, as every row in them had expired; their rows aren't counted
+    /// in `expired`.
+    pub expired_files: u64,
+}
+
+/// A record of an exported global keyed table.
+#[derive(Serialize)]
+struct KeyedRecord<'a, K, V> {
+    key: &'a K,
+    value: &'a V,
+}
+
+/// Exports a global keyed table of a checkpoint, decoded as `K` and `V`, to `destination`, a
+/// local path or object store URL. Each key that a restore would build is written as a record
+/// with its `key` and `value`, serialized with serde; Parquet columns are derived from how
+/// they serialize.
+///
+/// The table is read into memory as a restore would read it, and written out as it's
+/// serialized.
+pub async fn export_keyed_table<K: Key + Serialize, V: Data + Serialize>(
+    inspector: &CheckpointInspector,
+    operator_id: &str,
+    table_name: &str,
+    format: ExportFormat,
+    destination: &str,
+) -> Result<ExportStats> {
+    let table = inspector
+        .global_keyed_table(operator_id, table_name)
+        .await?;
+    let (data, deleted) = table.read_all_counting_deletes::<K, V>().await?;
+    let records = || data.iter().map(|(key, value)| KeyedRecord { key, value });
+
+    let mut writer = ExportWriter::open(destination).await?;
+    let result = async {
+        match format {
+            ExportFormat::JsonLines => {
+                let mut buf = vec![];
+                for record in records() {
+                    serde_json::to_writer(&mut buf, &record)?;
+                    buf.push(b'\n');
+                    if buf.len() >= EXPORT_BUFFER_BYTES {
+                        writer.write(&buf).await?;
+                        buf.clear();
+                    }
+                }
+                writer.write(&buf).await?;
+                writer.finish().await
+            }
+            ExportFormat::Parquet => {
+                if data.is_empty() {
+                    bail!(
+                        "table {} has no entries to derive Parquet columns from",
+                        table_name
+                    );
+                }
+                let schema = Arc::new(infer_json_schema_from_iterator(records().map(|record| {
+                    serde_json::to_value(record)
+                        .map_err(|e| arrow::error::ArrowError::JsonError(e.to_string()))
+                }))?);
+                let mut decoder = ReaderBuilder::new(schema.clone())
+                    .with_batch_size(EXPORT_BATCH_ROWS)
+                    .build_decoder()?;
+                let mut parquet = writer.parquet(schema)?;
+                let mut chunk = Vec::with_capacity(EXPORT_BATCH_ROWS);
+                let mut records = records().peekable();
+                while let Some(record) = records.next() {
+                    chunk.push(record);
+                    if chunk.len() < EXPORT_BATCH_ROWS && records.peek().is_some() {
+                        continue;
+                    }
+                    decoder.serialize(&chunk)?;
+                    chunk.clear();
+                    if let Some(batch) = decoder.flush()? {
+                        parquet.write(&batch).await?;
+                    }
+                }
+                parquet.close().await?;
+                Ok(())
+            }
+        }
+    }
+    .await;
+    writer.finish_or_abort(result).await?;
+
+    let stats = ExportStats {
+        exported: data.len() as u64,
+        deleted,
+        ..Default::default()
+    };
+    info!(
+        message = "Exported keyed table",
+        operator_id,
+        table_name,
+        destination,
+        exported = stats.exported,
+        deleted = stats.deleted
+    );
+    Ok(stats)
+}
+
+/// Exports an expiring time-keyed table of a checkpoint to `destination`, a local path or
+/// object store URL: every row a restore at the operator's checkpointed watermark would
+/// build, in timestamp order, with the table's columns.
+///
+/// The table is read into memory as a restore would read it, and written out a timestamp at
+/// a time.
+pub async fn export_time_table(
+    inspector: &CheckpointInspector,
+    operator_id: &str,
+    table_name: &str,
+    format: ExportFormat,
+    destination: &str,
+) -> Result<ExportStats> {
+    let (table, watermark) = inspector
+        .expiring_time_key_table(operator_id, table_name)
+        .await?;
+    let restored = table.restored_batches(watermark).await?;
+    let batches = || restored.batches.values().flatten();
+
+    let mut writer = ExportWriter::open(destination).await?;
+    let result = async {
+        match format {
+            ExportFormat::JsonLines => {
+                for batch in batches() {
+                    let mut json = LineDelimitedWriter::new(vec![]);
+                    json.write(batch)?;
+                    json.finish()?;
+                    writer.write(&json.into_inner()).await?;
+                }
+                writer.finish().await
+            }
+            ExportFormat::Parquet => {
+                let mut parquet = writer.parquet(table.memory_schema().schema.clone())?;
+                for batch in batches() {
+                    parquet.write(batch).await?;
+                }
+                parquet.close().await?;
+                Ok(())
+            }
+        }
+    }
+    .await;
+    writer.finish_or_abort(result).await?;
+
+    let stats = ExportStats {
+        exported: batches().map(|batch| batch.num_rows() as u64).sum(),
+        expired: restored.expired_rows,
+        expired_files: restored.expired_files,
+        ..Default::default()
+    };
+    info!(
+        message = "Exported time table",
+        operator_id,
+        table_name,
+        destination,
+        exported = stats.exported,
+        expired = stats.expired,
+        expired_files = stats.expired_files
+    );
+    Ok(stats)
+}
+
+/// Streams an export to its destination as a multipart upload, which is aborted if the
+/// export fails so that no partial file is left behind.
+struct ExportWriter {
+    storage: StorageProvider,
+    // the path in the object store, including any key of the destination's URL
+    path: String,
+    multipart_id: String,
+    writer: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+}
+
+impl ExportWriter {
+    async fn open(destination: &str) -> Result<Self> {
+        let url = if destination.contains("://") {
+            destination.to_string()
+        } else {
+            format!(
+                "file://{}",
+                std::env::current_dir()?.join(destination).display()
+            )
+        };
+        let (directory, file) = url
+            .rsplit_once('/')
+            .filter(|(_, file)| !file.is_empty())
+            .ok_or_else(|| anyhow!("export destination {} must name a file", destination))?;
+        let storage = StorageProvider::for_url(directory)
+            .await
+            .with_context(|| format!("failed to open export destination {}", destination))?;
+        let location = storage.qualify_path(&file.to_string().into());
+        let path = location.to_string();
+        let store = storage.get_backing_store();
+        let (multipart_id, writer) = storage
+            .retry_policy()
+            .run("initiate multipart upload", &path, || {
+                store.put_multipart(&location)
+            })
+            .await?;
+        Ok(Self {
+            storage,
+            path,
+            multipart_id,
+            writer: Some(writer),
+        })
+    }
+
+    fn writer(&mut self) -> Result<&mut Box<dyn AsyncWrite + Send + Unpin>> {
+        self.writer
+            .as_mut()
+            .ok_or_else(|| anyhow!("export to {} was already written", self.path))
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        Ok(self.writer()?.write_all(data).await?)
+    }
+
+    async fn finish(&mut self) -> Result<()> {
+        Ok(self.writer()?.shutdown().await?)
+    }
+
+    /// A Parquet writer of rows with `schema` that writes to the destination, and completes
+    /// the upload when it's closed.
+    fn parquet(
+        &mut self,
+        schema: SchemaRef,
+    ) -> Result<AsyncArrowWriter<Box<dyn AsyncWrite + Send + Unpin>>> {
+        let writer = self
+            .writer
+            .take()
+            .ok_or_else(|| anyhow!("export to {} was already written", self.path))?;
+        Ok(AsyncArrowWriter::try_new(
+            writer,
+            schema,
+            EXPORT_BUFFER_BYTES,
+            None,
+        )?)
+    }
+
+    async fn finish_or_abort(self, result: Result<()>) -> Result<()> {
+        if result.is_err() {
+            if let Err(e) = self
+                .storage
+                .abort_multipart(&self.path.clone().into(), &self.multipart_id)
+                .await
+            {
+                warn!("failed to abort export to {}: {}", self.path, e);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use arroyo_types::to_nanos;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_export_writer() {
+        let root = std::env::temp_dir().join(format!(
+            "arroyo-state-export-tests/{}",
+            to_nanos(SystemTime::now())
+        ));
+        std::fs::create_dir_all(&root).unwrap();
+
+        let written = root.join("written.jsonl");
+        let mut writer = ExportWriter::open(written.to_str().unwrap()).await.unwrap();
+        let result = async {
+            writer.write(b"{\"key\":1}\n").await?;
+            writer.finish().await
+        }
+        .await;
+        writer.finish_or_abort(result).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&written).unwrap(), "{\"key\":1}\n");
+
+        // a failed export leaves nothing behind
+        let failed = root.join("failed.jsonl");
+        let mut writer = ExportWriter::open(failed.to_str().unwrap()).await.unwrap();
+        writer.write(b"partial").await.unwrap();
+        assert!(writer
+            .finish_or_abort(Err(anyhow!("serialization failed")))
+            .await
+            .is_err());
+        assert!(!failed.exists());
+
+        assert!(
+            ExportWriter::open(&format!("file://{}/", root.to_str().unwrap()))
+                .await
+                .is_err()
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
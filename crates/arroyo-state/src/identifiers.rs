@@ -0,0 +1,128 @@
+use std::borrow::Cow;
+
+use anyhow::{anyhow, bail, Result};
+
+/// Job ids, operator ids, and table names longer than this are rejected, as they end up in
+/// object keys and metric labels.
+pub const MAX_IDENTIFIER_LEN: usize = 256;
+
+pub fn validate_identifier(kind: &str, id: &str) -> Result<()> {
+    if id.is_empty() {
+        bail!("{} must not be empty", kind);
+    }
+    if id.len() > MAX_IDENTIFIER_LEN {
+        bail!(
+            "{} is {} bytes long, but may be at most {} bytes",
+            kind,
+            id.len(),
+            MAX_IDENTIFIER_LEN
+        );
+    }
+    Ok(())
+}
+
+fn is_safe(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'-'
+}
+
+/// Escapes an identifier so that it can be used as a single component of a storage path.
+/// Bytes outside of `[A-Za-z0-9_-]` are written as `%XX`, so identifiers made only of those
+/// characters are unchanged. Reversed by [`decode_path_component`].
+pub fn encode_path_component(id: &str) -> Cow<'_, str> {
+    if id.bytes().all(is_safe) {
+        return Cow::Borrowed(id);
+    }
+    let mut encoded = String::with_capacity(id.len() * 3);
+    for b in id.bytes() {
+        if is_safe(b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    Cow::Owned(encoded)
+}
+
+pub fn decode_path_component(component: &str) -> Result<String> {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = component
+                .get(i + 1..i + 3)
+                .ok_or_else(|| anyhow!("truncated escape in path component {}", component))?;
+            decoded.push(
+                u8::from_str_radix(hex, 16).map_err(|_| {
+                    anyhow!("invalid escape %{} in path component {}", hex, component)
+                })?,
+            );
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(String::from_utf8(decoded)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tables::StateFileLayout;
+
+    #[test]
+    fn test_safe_identifiers_are_unchanged() {
+        assert_eq!(encode_path_component("job_123-abc"), "job_123-abc");
+        assert!(matches!(
+            encode_path_component("operator_1"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let long = "x/".repeat(500);
+        for id in [
+            "a/b/c",
+            "../escape",
+            "with space",
+            "tab\there",
+            "100%",
+            "ünïcödé/表",
+            long.as_str(),
+        ] {
+            let encoded = encode_path_component(id);
+            assert!(!encoded.contains('/'), "{} encoded to {}", id, encoded);
+            assert!(!encoded.contains('.'), "{} encoded to {}", id, encoded);
+            assert_eq!(decode_path_component(&encoded).unwrap(), id);
+        }
+    }
+
+    #[test]
+    fn test_invalid_escapes() {
+        assert!(decode_path_component("abc%2").is_err());
+        assert!(decode_path_component("abc%zz").is_err());
+        assert!(decode_path_component("%FF").is_err());
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(validate_identifier("job id", "").is_err());
+        assert!(validate_identifier("job id", &"a".repeat(1000)).is_err());
+        assert!(validate_identifier("job id", &"a".repeat(MAX_IDENTIFIER_LEN)).is_ok());
+        assert!(validate_identifier("table name", "ünïcödé/表").is_ok());
+    }
+
+    #[test]
+    fn test_checkpoint_paths_stay_in_their_prefix() {
+        let path = StateFileLayout::default().path("job/1", "op/../2", "t a", 3, 7, false);
+        assert_eq!(
+            path,
+            "job%2F1/checkpoints/checkpoint-0000007/operator-op%2F%2E%2E%2F2/table-t%20a-003"
+        );
+        let components: Vec<_> = path.split('/').collect();
+        assert_eq!(components.len(), 4);
+        assert_eq!(decode_path_component(components[0]).unwrap(), "job/1");
+    }
+}
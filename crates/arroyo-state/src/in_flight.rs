@@ -0,0 +1,108 @@
+//! Records captured by unaligned checkpoints.
+//!
+//! An operator taking an unaligned checkpoint snapshots its state as soon as the first
+//! barrier arrives, and keeps processing the inputs whose barriers haven't arrived yet. The
+//! records it processes from those inputs before their barriers are part of the checkpoint,
+//! so they're written alongside the subtask's table files and replayed on restore before
+//! the subtask reads its inputs.
+
+use anyhow::{Context, Result};
+use arrow_array::RecordBatch;
+use arroyo_rpc::grpc::InFlightFile;
+use arroyo_storage::StorageProvider;
+use arroyo_types::TaskInfo;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::{
+    arrow::ArrowWriter,
+    file::properties::{EnabledStatistics, WriterProperties},
+};
+
+use crate::encryption::{encrypt, read_state_file};
+use crate::parquet::{operator_path, state_file_compression, upload_part_size};
+use crate::upload_scheduler::UPLOAD_SCHEDULER;
+
+/// The records an operator processed from one input between the first barrier of an
+/// unaligned checkpoint and that input's barrier.
+#[derive(Debug, Clone)]
+pub struct InFlightBatches {
+    pub input: usize,
+    pub batches: Vec<RecordBatch>,
+}
+
+impl InFlightBatches {
+    pub fn records(&self) -> u64 {
+        self.batches.iter().map(|b| b.num_rows() as u64).sum()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.batches
+            .iter()
+            .map(|b| b.get_array_memory_size() as u64)
+            .sum()
+    }
+}
+
+fn in_flight_path(task_info: &TaskInfo, epoch: u32, input: usize) -> String {
+    format!(
+        "{}/in-flight-{:0>3}-{:0>3}",
+        operator_path(&task_info.job_id, epoch, &task_info.operator_id),
+        task_info.task_index,
+        input
+    )
+}
+
+/// Writes the records captured from one input as a single state file.
+pub(crate) async fn write_in_flight(
+    storage: &StorageProvider,
+    task_info: &TaskInfo,
+    epoch: u32,
+    captured: &InFlightBatches,
+) -> Result<InFlightFile> {
+    let path = in_flight_path(task_info, epoch, captured.input);
+    let props = WriterProperties::builder()
+        .set_compression(state_file_compression()?)
+        .set_statistics_enabled(EnabledStatistics::None)
+        .build();
+    let schema = captured.batches[0].schema();
+    let mut writer = ArrowWriter::try_new(Vec::new(), schema, Some(props))?;
+    for batch in &captured.batches {
+        writer.write(batch)?;
+    }
+    let parquet_bytes = encrypt(writer.into_inner()?)?;
+
+    let permit = UPLOAD_SCHEDULER.acquire(task_info).await;
+    let size = parquet_bytes.len() as u64;
+    storage
+        .put_in_parts(&path, parquet_bytes, upload_part_size())
+        .await
+        .with_context(|| format!("failed to write in-flight records to {}", path))?;
+    permit.complete(size);
+
+    Ok(InFlightFile {
+        input: captured.input as u32,
+        file: path,
+        records: captured.records(),
+        bytes: captured.bytes(),
+    })
+}
+
+/// Reads the records a subtask captured in the checkpoint it's restoring from, in the
+/// order they should be replayed.
+pub(crate) async fn load_in_flight(
+    storage: &StorageProvider,
+    files: &[InFlightFile],
+) -> Result<Vec<InFlightBatches>> {
+    let mut captured = vec![];
+    for file in files {
+        let contents = read_state_file(storage, &file.file).await?;
+        let batches = ParquetRecordBatchReaderBuilder::try_new(contents)?
+            .build()?
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("failed to read in-flight records from {}", file.file))?;
+        captured.push(InFlightBatches {
+            input: file.input as usize,
+            batches,
+        });
+    }
+    Ok(captured)
+}
@@ -0,0 +1,491 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, bail, Result};
+use arroyo_rpc::api::TableStorageUsage;
+use arroyo_rpc::grpc::{CheckpointMetadata, OperatorCheckpointMetadata, OperatorRemapping};
+use bytes::{Buf, BufMut, Bytes};
+use once_cell::sync::Lazy;
+use prost::Message;
+
+use crate::parquet::{operator_retained_files, rename_operator_files, retained_files_usage};
+use crate::BackingStore;
+
+/// Everything written to the [`InMemoryBackingStore`], across all jobs.
+#[derive(Default)]
+struct Stored {
+    checkpoints: HashMap<(String, u32), CheckpointMetadata>,
+    operators: HashMap<(String, String, u32), OperatorCheckpointMetadata>,
+    remappings: HashMap<(String, u32), Vec<OperatorRemapping>>,
+    savepoints: HashMap<(String, String), CheckpointMetadata>,
+    // errors the next reads of an operator's metadata fail with
+    failing_reads: HashMap<(String, String, u32), VecDeque<anyhow::Error>>,
+}
+
+static STORED: Lazy<Mutex<Stored>> = Lazy::new(Default::default);
+
+const CHECKPOINT_TAG: u8 = 0;
+const OPERATOR_TAG: u8 = 1;
+const REMAPPING_TAG: u8 = 2;
+const SAVEPOINT_TAG: u8 = 3;
+
+/// A [`BackingStore`] that keeps checkpoint and operator metadata in process memory, for
+/// testing operators and restores without checkpoint storage.
+///
+/// Like every backing store it has no instance state, so what's stored is shared by the
+/// whole process; tests should use a job id of their own. A checkpoint/restore cycle can be
+/// simulated with [`InMemoryBackingStore::snapshot`], [`InMemoryBackingStore::clear`] and
+/// [`InMemoryBackingStore::restore`].
+///
+/// Table data is written by the tables themselves to the checkpoint storage, not through the
+/// backing store, so it isn't held here. Storage usage counts only file sizes recorded in
+/// the metadata, and copying a checkpoint to another job only renames the files it refers to.
+pub struct InMemoryBackingStore;
+
+impl InMemoryBackingStore {
+    /// Supplies the operator remapping for restoring a checkpoint, as the remapping file
+    /// would for a durable store.
+    pub fn set_operator_remapping(job_id: &str, epoch: u32, remappings: Vec<OperatorRemapping>) {
+        STORED
+            .lock()
+            .unwrap()
+            .remappings
+            .insert((job_id.to_string(), epoch), remappings);
+    }
+
+    /// Makes the next reads of the metadata of `operator_id` in checkpoint `epoch` fail with
+    /// `errors`, one read per error, as reads from a store that's unreachable or that holds
+    /// metadata that can't be decoded do.
+    pub fn fail_operator_metadata_reads(
+        job_id: &str,
+        operator_id: &str,
+        epoch: u32,
+        errors: impl IntoIterator<Item = anyhow::Error>,
+    ) {
+        STORED
+            .lock()
+            .unwrap()
+            .failing_reads
+            .entry((job_id.to_string(), operator_id.to_string(), epoch))
+            .or_default()
+            .extend(errors);
+    }
+
+    /// Serializes everything stored for a job.
+    pub fn snapshot(job_id: &str) -> Bytes {
+        let stored = STORED.lock().unwrap();
+        let mut buf = vec![];
+        for ((job, _), metadata) in &stored.checkpoints {
+            if job == job_id {
+                buf.put_u8(CHECKPOINT_TAG);
+                metadata.encode_length_delimited(&mut buf).unwrap();
+            }
+        }
+        for ((job, _, _), metadata) in &stored.operators {
+            if job == job_id {
+                buf.put_u8(OPERATOR_TAG);
+                metadata.encode_length_delimited(&mut buf).unwrap();
+            }
+        }
+        for ((job, epoch), remappings) in &stored.remappings {
+            if job == job_id {
+                // carried in the remapping field of otherwise empty checkpoint metadata
+                buf.put_u8(REMAPPING_TAG);
+                CheckpointMetadata {
+                    job_id: job.clone(),
+                    epoch: *epoch,
+                    operator_remappings: remappings.clone(),
+                    ..Default::default()
+                }
+                .encode_length_delimited(&mut buf)
+                .unwrap();
+            }
+        }
+        for ((job, _), metadata) in &stored.savepoints {
+            if job == job_id {
+                buf.put_u8(SAVEPOINT_TAG);
+                metadata.encode_length_delimited(&mut buf).unwrap();
+            }
+        }
+        buf.into()
+    }
+
+    /// Removes everything stored for a job, as if the process had restarted.
+    pub fn clear(job_id: &str) {
+        let mut stored = STORED.lock().unwrap();
+        stored.checkpoints.retain(|(job, _), _| job != job_id);
+        stored.operators.retain(|(job, _, _), _| job != job_id);
+        stored.remappings.retain(|(job, _), _| job != job_id);
+        stored.savepoints.retain(|(job, _), _| job != job_id);
+        stored.failing_reads.retain(|(job, _, _), _| job != job_id);
+    }
+
+    /// Stores the contents of a [`InMemoryBackingStore::snapshot`], replacing what's stored
+    /// for the job.
+    pub fn restore(job_id: &str, mut snapshot: Bytes) -> Result<()> {
+        let mut restored = Stored::default();
+        while snapshot.has_remaining() {
+            match snapshot.get_u8() {
+                CHECKPOINT_TAG => {
+                    let metadata = CheckpointMetadata::decode_length_delimited(&mut snapshot)?;
+                    restored
+                        .checkpoints
+                        .insert((metadata.job_id.clone(), metadata.epoch), metadata);
+                }
+                OPERATOR_TAG => {
+                    let metadata =
+                        OperatorCheckpointMetadata::decode_length_delimited(&mut snapshot)?;
+                    let operator_metadata = metadata
+                        .operator_metadata
+                        .as_ref()
+                        .ok_or_else(|| anyhow!("missing operator metadata"))?;
+                    restored.operators.insert(
+                        (
+                            operator_metadata.job_id.clone(),
+                            operator_metadata.operator_id.clone(),
+                            operator_metadata.epoch,
+                        ),
+                        metadata,
+                    );
+                }
+                REMAPPING_TAG => {
+                    let metadata = CheckpointMetadata::decode_length_delimited(&mut snapshot)?;
+                    restored.remappings.insert(
+                        (metadata.job_id, metadata.epoch),
+                        metadata.operator_remappings,
+                    );
+                }
+                SAVEPOINT_TAG => {
+                    let metadata = CheckpointMetadata::decode_length_delimited(&mut snapshot)?;
+                    let name = metadata
+                        .savepoint
+                        .clone()
+                        .ok_or_else(|| anyhow!("missing savepoint name"))?;
+                    restored
+                        .savepoints
+                        .insert((metadata.job_id.clone(), name), metadata);
+                }
+                tag => bail!("invalid in-memory store snapshot: unknown entry {}", tag),
+            }
+        }
+        if restored.checkpoints.keys().any(|(job, _)| job != job_id)
+            || restored.operators.keys().any(|(job, _, _)| job != job_id)
+            || restored.remappings.keys().any(|(job, _)| job != job_id)
+            || restored.savepoints.keys().any(|(job, _)| job != job_id)
+        {
+            bail!("snapshot is not of job {}", job_id);
+        }
+
+        Self::clear(job_id);
+        let mut stored = STORED.lock().unwrap();
+        stored.checkpoints.extend(restored.checkpoints);
+        stored.operators.extend(restored.operators);
+        stored.remappings.extend(restored.remappings);
+        stored.savepoints.extend(restored.savepoints);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl BackingStore for InMemoryBackingStore {
+    fn name() -> &'static str {
+        "in-memory"
+    }
+
+    async fn prepare_checkpoint_load(_metadata: &CheckpointMetadata) -> Result<()> {
+        Ok(())
+    }
+
+    async fn load_checkpoint_metadata(job_id: &str, epoch: u32) -> Result<CheckpointMetadata> {
+        STORED
+            .lock()
+            .unwrap()
+            .checkpoints
+            .get(&(job_id.to_string(), epoch))
+            .cloned()
+            .ok_or_else(|| anyhow!("no checkpoint {} for job {}", epoch, job_id))
+    }
+
+    async fn load_checkpoint_metadata_if_present(
+        job_id: &str,
+        epoch: u32,
+    ) -> Result<Option<CheckpointMetadata>> {
+        Ok(STORED
+            .lock()
+            .unwrap()
+            .checkpoints
+            .get(&(job_id.to_string(), epoch))
+            .cloned())
+    }
+
+    async fn list_checkpoint_epochs(job_id: &str) -> Result<Vec<u32>> {
+        let mut epochs: Vec<_> = STORED
+            .lock()
+            .unwrap()
+            .checkpoints
+            .keys()
+            .filter(|(job, _)| job == job_id)
+            .map(|(_, epoch)| *epoch)
+            .collect();
+        epochs.sort_unstable();
+        Ok(epochs)
+    }
+
+    async fn load_operator_remapping(job_id: &str, epoch: u32) -> Result<Vec<OperatorRemapping>> {
+        Ok(STORED
+            .lock()
+            .unwrap()
+            .remappings
+            .get(&(job_id.to_string(), epoch))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn write_operator_remapping(
+        job_id: &str,
+        epoch: u32,
+        remappings: Vec<OperatorRemapping>,
+    ) -> Result<()> {
+        Self::set_operator_remapping(job_id, epoch, remappings);
+        Ok(())
+    }
+
+    async fn load_operator_metadata(
+        job_id: &str,
+        operator_id: &str,
+        epoch: u32,
+    ) -> Result<Option<OperatorCheckpointMetadata>> {
+        let mut stored = STORED.lock().unwrap();
+        let key = (job_id.to_string(), operator_id.to_string(), epoch);
+        if let Some(error) = stored
+            .failing_reads
+            .get_mut(&key)
+            .and_then(|errors| errors.pop_front())
+        {
+            return Err(error);
+        }
+        Ok(stored.operators.get(&key).cloned())
+    }
+
+    async fn write_operator_checkpoint_metadata(
+        metadata: OperatorCheckpointMetadata,
+    ) -> Result<()> {
+        let operator_metadata = metadata
+            .operator_metadata
+            .as_ref()
+            .ok_or_else(|| anyhow!("missing operator metadata"))?;
+        let key = (
+            operator_metadata.job_id.clone(),
+            operator_metadata.operator_id.clone(),
+            operator_metadata.epoch,
+        );
+        STORED.lock().unwrap().operators.insert(key, metadata);
+        Ok(())
+    }
+
+    async fn copy_operator_checkpoint(
+        metadata: OperatorCheckpointMetadata,
+        target_job_id: &str,
+    ) -> Result<OperatorCheckpointMetadata> {
+        // table files aren't held here, so only their paths are moved
+        rename_operator_files(metadata, target_job_id, &|file| {
+            format!("{}/forked/{}", target_job_id, file)
+        })
+    }
+
+    async fn write_checkpoint_metadata(metadata: CheckpointMetadata) -> Result<()> {
+        STORED
+            .lock()
+            .unwrap()
+            .checkpoints
+            .insert((metadata.job_id.clone(), metadata.epoch), metadata);
+        Ok(())
+    }
+
+    async fn write_savepoint_metadata(metadata: CheckpointMetadata) -> Result<()> {
+        let name = metadata
+            .savepoint
+            .clone()
+            .ok_or_else(|| anyhow!("checkpoint {} isn't a savepoint", metadata.epoch))?;
+        STORED
+            .lock()
+            .unwrap()
+            .savepoints
+            .insert((metadata.job_id.clone(), name), metadata);
+        Ok(())
+    }
+
+    async fn load_savepoint_metadata(job_id: &str, name: &str) -> Result<CheckpointMetadata> {
+        STORED
+            .lock()
+            .unwrap()
+            .savepoints
+            .get(&(job_id.to_string(), name.to_string()))
+            .cloned()
+            .ok_or_else(|| anyhow!("job {} has no savepoint named {}", job_id, name))
+    }
+
+    async fn list_savepoints(job_id: &str) -> Result<Vec<CheckpointMetadata>> {
+        let mut savepoints: Vec<_> = STORED
+            .lock()
+            .unwrap()
+            .savepoints
+            .iter()
+            .filter(|((job, _), _)| job == job_id)
+            .map(|(_, metadata)| metadata.clone())
+            .collect();
+        savepoints.sort_by_key(|savepoint| savepoint.epoch);
+        Ok(savepoints)
+    }
+
+    async fn cleanup_checkpoint(
+        mut metadata: CheckpointMetadata,
+        old_min_epoch: u32,
+        new_min_epoch: u32,
+    ) -> Result<()> {
+        {
+            let mut stored = STORED.lock().unwrap();
+            let savepoint_epochs: HashSet<u32> = stored
+                .savepoints
+                .iter()
+                .filter(|((job, _), _)| *job == metadata.job_id)
+                .map(|(_, savepoint)| savepoint.epoch)
+                .collect();
+            for epoch in
+                (old_min_epoch..new_min_epoch).filter(|epoch| !savepoint_epochs.contains(epoch))
+            {
+                stored.checkpoints.remove(&(metadata.job_id.clone(), epoch));
+                for operator_id in &metadata.operator_ids {
+                    stored
+                        .operators
+                        .remove(&(metadata.job_id.clone(), operator_id.clone(), epoch));
+                }
+            }
+        }
+        metadata.min_epoch = new_min_epoch;
+        Self::write_checkpoint_metadata(metadata).await
+    }
+
+    async fn table_storage_usage(
+        job_id: &str,
+        epoch: u32,
+    ) -> Result<HashMap<String, Vec<TableStorageUsage>>> {
+        let metadata = Self::load_checkpoint_metadata(job_id, epoch).await?;
+        let mut usage = HashMap::new();
+        for operator_id in metadata.operator_ids {
+            let Some(operator_metadata) =
+                Self::load_operator_metadata(job_id, &operator_id, epoch).await?
+            else {
+                continue;
+            };
+            let tables = operator_retained_files(&operator_metadata)?
+                .into_iter()
+                .map(|(table_name, files)| {
+                    let bytes = files.iter().filter_map(|file| file.bytes).sum();
+                    retained_files_usage(table_name, &files, bytes)
+                })
+                .collect();
+            usage.insert(operator_id, tables);
+        }
+        Ok(usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arroyo_rpc::grpc::OperatorMetadata;
+
+    use super::*;
+
+    fn operator(job_id: &str, operator_id: &str, epoch: u32) -> OperatorCheckpointMetadata {
+        OperatorCheckpointMetadata {
+            operator_metadata: Some(OperatorMetadata {
+                job_id: job_id.to_string(),
+                operator_id: operator_id.to_string(),
+                epoch,
+                parallelism: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_and_restore() {
+        let job_id = "in-memory-snapshot";
+        for epoch in 1..=2 {
+            InMemoryBackingStore::write_operator_checkpoint_metadata(operator(job_id, "op", epoch))
+                .await
+                .unwrap();
+            InMemoryBackingStore::write_checkpoint_metadata(CheckpointMetadata {
+                job_id: job_id.to_string(),
+                epoch,
+                min_epoch: 1,
+                operator_ids: vec!["op".to_string()],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        let remapping = OperatorRemapping {
+            old_operator_id: "op".to_string(),
+            new_operator_id: "op_v2".to_string(),
+            table_names: HashMap::new(),
+        };
+        InMemoryBackingStore::set_operator_remapping(job_id, 2, vec![remapping.clone()]);
+        InMemoryBackingStore::write_operator_checkpoint_metadata(operator("other", "op", 1))
+            .await
+            .unwrap();
+
+        let snapshot = InMemoryBackingStore::snapshot(job_id);
+        InMemoryBackingStore::clear(job_id);
+        assert!(InMemoryBackingStore::load_checkpoint_metadata(job_id, 2)
+            .await
+            .is_err());
+
+        InMemoryBackingStore::restore(job_id, snapshot).unwrap();
+        let checkpoint = InMemoryBackingStore::load_checkpoint_metadata(job_id, 2)
+            .await
+            .unwrap();
+        assert_eq!(checkpoint.operator_ids, vec!["op".to_string()]);
+        assert_eq!(
+            InMemoryBackingStore::load_operator_metadata(job_id, "op", 1)
+                .await
+                .unwrap(),
+            Some(operator(job_id, "op", 1))
+        );
+        assert_eq!(
+            InMemoryBackingStore::load_operator_remapping(job_id, 2)
+                .await
+                .unwrap(),
+            vec![remapping]
+        );
+        // other jobs are left alone
+        assert!(
+            InMemoryBackingStore::load_operator_metadata("other", "op", 1)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        InMemoryBackingStore::cleanup_checkpoint(checkpoint, 1, 2)
+            .await
+            .unwrap();
+        assert!(InMemoryBackingStore::load_checkpoint_metadata(job_id, 1)
+            .await
+            .is_err());
+        assert_eq!(
+            InMemoryBackingStore::load_operator_metadata(job_id, "op", 1)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            InMemoryBackingStore::load_checkpoint_metadata(job_id, 2)
+                .await
+                .unwrap()
+                .min_epoch,
+            2
+        );
+    }
+}
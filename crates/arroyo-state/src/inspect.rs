@@ -0,0 +1,381 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::{anyhow, bail, Context, Result};
+use arrow_array::RecordBatch;
+use arroyo_rpc::api::TableStorageUsage;
+use arroyo_rpc::grpc::{
+    CheckpointMetadata, OperatorCheckpointMetadata, TableCheckpointMetadata, TableConfig, TableEnum,
+};
+use arroyo_storage::{StorageProvider, StorageProviderRef};
+use arroyo_types::{from_micros, Data, Key, TaskInfo, TaskInfoRef};
+
+use crate::parquet::{
+    load_checkpoint_metadata_in, load_operator_metadata_in, operator_retained_files,
+    retained_files_usage,
+};
+use crate::state_serde::VersionedData;
+use crate::tables::expiring_time_key_map::ExpiringTimeKeyTable;
+use crate::tables::global_keyed_map::{GlobalKeyedTable, StoredEntry};
+use crate::tables::ErasedTable;
+
+/// Reads what a job's checkpoint holds straight from checkpoint storage, without a running
+/// job or any of its operators, for debugging. Nothing is written.
+///
+/// Tables are read the way a restore reads them, as the only subtask of their operator, so
+/// every key is included.
+pub struct CheckpointInspector {
+    storage: StorageProviderRef,
+    checkpoint: CheckpointMetadata,
+}
+
+/// An operator of an inspected checkpoint.
+#[derive(Debug, Clone)]
+pub struct InspectedOperator {
+    pub operator_id: String,
+    /// Whether the operator was declared stateless, and so has no tables.
+    pub stateless: bool,
+    pub parallelism: u64,
+    pub min_watermark: Option<SystemTime>,
+    pub tables: Vec<InspectedTable>,
+}
+
+/// A table of an inspected operator. Its storage counts only file sizes recorded in the
+/// checkpoint.
+#[derive(Debug, Clone)]
+pub struct InspectedTable {
+    pub config: TableConfig,
+    pub storage: TableStorageUsage,
+}
+
+impl InspectedTable {
+    pub fn name(&self) -> &str {
+        &self.storage.table_name
+    }
+
+    pub fn table_type(&self) -> TableEnum {
+        self.config.table_type()
+    }
+}
+
+impl CheckpointInspector {
+    /// Opens epoch `epoch` of a job in the checkpoint storage at `checkpoint_url`, as given
+    /// to the job by `CHECKPOINT_URL`.
+    pub async fn open(checkpoint_url: &str, job_id: &str, epoch: u32) -> Result<Self> {
+        let storage = StorageProvider::for_url(checkpoint_url)
+            .await
+            .with_context(|| format!("failed to open checkpoint storage {}", checkpoint_url))?;
+        Self::with_storage(Arc::new(storage), job_id, epoch).await
+    }
+
+    /// Like [`CheckpointInspector::open`], for a given store.
+    pub async fn with_storage(
+        storage: StorageProviderRef,
+        job_id: &str,
+        epoch: u32,
+    ) -> Result<Self> {
+        let checkpoint = load_checkpoint_metadata_in(&storage, job_id, epoch)
+            .await
+            .with_context(|| format!("failed to load checkpoint {} of job {}", epoch, job_id))?;
+        Ok(Self {
+            storage,
+            checkpoint,
+        })
+    }
+
+    pub fn checkpoint(&self) -> &CheckpointMetadata {
+        &self.checkpoint
+    }
+
+    /// The checkpoint's operators and their tables, in the order the checkpoint lists them.
+    pub async fn operators(&self) -> Result<Vec<InspectedOperator>> {
+        let mut operators = vec![];
+        for operator_id in &self.checkpoint.operator_ids {
+            if let Some(metadata) = self.checkpoint.stateless_operators.get(operator_id) {
+                operators.push(InspectedOperator {
+                    operator_id: operator_id.clone(),
+                    stateless: true,
+                    parallelism: metadata.parallelism,
+                    min_watermark: metadata.min_watermark.map(from_micros),
+                    tables: vec![],
+                });
+                continue;
+            }
+            let metadata = self.operator_metadata(operator_id).await?;
+            let operator_metadata = metadata
+                .operator_metadata
+                .as_ref()
+                .ok_or_else(|| anyhow!("missing operator metadata"))?;
+            let mut tables: Vec<_> = operator_retained_files(&metadata)?
+                .into_iter()
+                .map(|(table_name, files)| InspectedTable {
+                    config: metadata.table_configs[&table_name].clone(),
+                    storage: retained_files_usage(
+                        table_name,
+                        &files,
+                        files.iter().filter_map(|file| file.bytes).sum(),
+                    ),
+                })
+                .collect();
+            tables.sort_by(|a, b| a.name().cmp(b.name()));
+            operators.push(InspectedOperator {
+                operator_id: operator_id.clone(),
+                stateless: false,
+                parallelism: operator_metadata.parallelism,
+                min_watermark: operator_metadata.min_watermark.map(from_micros),
+                tables,
+            });
+        }
+        Ok(operators)
+    }
+
+    /// The checkpoint metadata of one of the checkpoint's operators, as it's stored.
+    pub async fn operator_metadata(&self, operator_id: &str) -> Result<OperatorCheckpointMetadata> {
+        load_operator_metadata_in(
+            &self.storage,
+            &self.checkpoint.job_id,
+            operator_id,
+            self.checkpoint.epoch,
+        )
+        .await?
+        .ok_or_else(|| {
+            anyhow!(
+                "checkpoint {} of job {} has no metadata for operator {}",
+                self.checkpoint.epoch,
+                self.checkpoint.job_id,
+                operator_id
+            )
+        })
+    }
+
+    /// Calls `f` with every entry of a global keyed table's files, undecoded and in the order
+    /// a restore applies them: a later entry for a key replaces an earlier one, and an entry
+    /// without a value deletes the key.
+    pub async fn for_each_entry(
+        &self,
+        operator_id: &str,
+        table_name: &str,
+        f: impl FnMut(StoredEntry<'_>) -> Result<()>,
+    ) -> Result<()> {
+        self.global_keyed_table(operator_id, table_name)
+            .await?
+            .for_each_entry(f)
+            .await
+    }
+
+    /// The contents of a global keyed table, decoded as `K` and `V`, as a restore builds them.
+    pub async fn read_entries<K: Key, V: Data>(
+        &self,
+        operator_id: &str,
+        table_name: &str,
+    ) -> Result<HashMap<K, V>> {
+        self.global_keyed_table(operator_id, table_name)
+            .await?
+            .read_all()
+            .await
+    }
+
+    /// Like [`CheckpointInspector::read_entries`], upgrading values written as an earlier
+    /// version of `V`.
+    pub async fn read_versioned_entries<K: Key, V: VersionedData>(
+        &self,
+        operator_id: &str,
+        table_name: &str,
+    ) -> Result<HashMap<K, V>> {
+        self.global_keyed_table(operator_id, table_name)
+            .await?
+            .read_all_versioned()
+            .await
+    }
+
+    /// The rows of an expiring time-keyed table that a restore at the operator's checkpointed
+    /// watermark would build, bucketed by timestamp.
+    pub async fn read_batches(
+        &self,
+        operator_id: &str,
+        table_name: &str,
+    ) -> Result<BTreeMap<SystemTime, Vec<RecordBatch>>> {
+        let (table, watermark) = self
+            .expiring_time_key_table(operator_id, table_name)
+            .await?;
+        Ok(table.restored_batches(watermark).await?.batches)
+    }
+
+    pub(crate) async fn global_keyed_table(
+        &self,
+        operator_id: &str,
+        table_name: &str,
+    ) -> Result<GlobalKeyedTable> {
+        let metadata = self.operator_metadata(operator_id).await?;
+        let (config, table_metadata) =
+            Self::table(&metadata, table_name, TableEnum::GlobalKeyValue)?;
+        <GlobalKeyedTable as ErasedTable>::from_config(
+            config,
+            self.task_info(operator_id),
+            self.storage.clone(),
+            table_metadata,
+        )
+    }
+
+    /// An expiring time-keyed table of an operator, with the watermark the operator would be
+    /// restored at.
+    pub(crate) async fn expiring_time_key_table(
+        &self,
+        operator_id: &str,
+        table_name: &str,
+    ) -> Result<(ExpiringTimeKeyTable, Option<SystemTime>)> {
+        let metadata = self.operator_metadata(operator_id).await?;
+        let watermark = metadata
+            .operator_metadata
+            .as_ref()
+            .and_then(|operator_metadata| operator_metadata.min_watermark)
+            .map(from_micros);
+        let (config, table_metadata) =
+            Self::table(&metadata, table_name, TableEnum::ExpiringKeyedTimeTable)?;
+        let table = <ExpiringTimeKeyTable as ErasedTable>::from_config(
+            config,
+            self.task_info(operator_id),
+            self.storage.clone(),
+            table_metadata,
+        )?;
+        Ok((table, watermark))
+    }
+
+    fn table(
+        metadata: &OperatorCheckpointMetadata,
+        table_name: &str,
+        table_type: TableEnum,
+    ) -> Result<(TableConfig, Option<TableCheckpointMetadata>)> {
+        let config = metadata
+            .table_configs
+            .get(table_name)
+            .ok_or_else(|| anyhow!("operator has no table {}", table_name))?;
+        if config.table_type() != table_type {
+            bail!(
+                "table {} is a {:?} table, not a {:?} table",
+                table_name,
+                config.table_type(),
+                table_type
+            );
+        }
+        Ok((
+            config.clone(),
+            metadata.table_checkpoint_metadata.get(table_name).cloned(),
+        ))
+    }
+
+    /// The only subtask of the operator, which restores every key.
+    fn task_info(&self, operator_id: &str) -> TaskInfoRef {
+        Arc::new(TaskInfo {
+            job_id: self.checkpoint.job_id.clone(),
+            operator_name: operator_id.to_string(),
+            operator_id: operator_id.to_string(),
+            task_index: 0,
+            parallelism: 1,
+            key_range: 0..=u64::MAX,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arroyo_rpc::grpc::{GlobalKeyedTableTaskCheckpointMetadata, OperatorMetadata};
+    use prost::Message;
+
+    use super::*;
+    use crate::global_table_config;
+    use crate::parquet::{base_path, metadata_path, operator_path};
+    use crate::test_storage::TempStorage;
+
+    #[tokio::test]
+    async fn test_inspect_operators() {
+        let temp_storage = TempStorage::new("inspect-tests").await;
+        let storage = temp_storage.provider();
+        let job_id = "inspected";
+        storage
+            .put(
+                metadata_path(&operator_path(job_id, 1, "op")),
+                OperatorCheckpointMetadata {
+                    operator_metadata: Some(OperatorMetadata {
+                        job_id: job_id.to_string(),
+                        operator_id: "op".to_string(),
+                        epoch: 1,
+                        parallelism: 2,
+                        min_watermark: Some(5),
+                        ..Default::default()
+                    }),
+                    table_configs: global_table_config("t", "test"),
+                    table_checkpoint_metadata: HashMap::from([(
+                        "t".to_string(),
+                        TableCheckpointMetadata {
+                            table_type: TableEnum::GlobalKeyValue.into(),
+                            data: GlobalKeyedTableTaskCheckpointMetadata {
+                                files: vec!["a".to_string(), "b".to_string()],
+                                file_sizes: HashMap::from([
+                                    ("a".to_string(), 10),
+                                    ("b".to_string(), 20),
+                                ]),
+                                ..Default::default()
+                            }
+                            .encode_to_vec(),
+                        },
+                    )]),
+                    ..Default::default()
+                }
+                .encode_to_vec(),
+            )
+            .await
+            .unwrap();
+        storage
+            .put(
+                metadata_path(&base_path(job_id, 1)),
+                CheckpointMetadata {
+                    job_id: job_id.to_string(),
+                    epoch: 1,
+                    min_epoch: 1,
+                    operator_ids: vec!["op".to_string(), "stateless".to_string()],
+                    stateless_operators: HashMap::from([(
+                        "stateless".to_string(),
+                        OperatorMetadata {
+                            parallelism: 3,
+                            ..Default::default()
+                        },
+                    )]),
+                    ..Default::default()
+                }
+                .encode_to_vec(),
+            )
+            .await
+            .unwrap();
+
+        let inspector = CheckpointInspector::with_storage(storage, job_id, 1)
+            .await
+            .unwrap();
+        let operators = inspector.operators().await.unwrap();
+        assert_eq!(operators.len(), 2);
+        assert_eq!(operators[0].operator_id, "op");
+        assert_eq!(operators[0].parallelism, 2);
+        assert_eq!(operators[0].min_watermark, Some(from_micros(5)));
+        assert_eq!(operators[0].tables.len(), 1);
+        let table = &operators[0].tables[0];
+        assert_eq!(table.name(), "t");
+        assert_eq!(table.table_type(), TableEnum::GlobalKeyValue);
+        assert_eq!(table.storage.files, 2);
+        assert_eq!(table.storage.bytes, 30);
+        assert!(operators[1].stateless);
+        assert_eq!(operators[1].parallelism, 3);
+        assert!(operators[1].tables.is_empty());
+
+        let err = inspector.read_batches("op", "t").await.unwrap_err();
+        assert!(err.to_string().contains("not a"));
+        assert!(inspector.operator_metadata("missing").await.is_err());
+        assert!(CheckpointInspector::open(
+            &format!("file://{}", root.to_str().unwrap()),
+            job_id,
+            2
+        )
+        .await
+        .is_err());
+    }
+}
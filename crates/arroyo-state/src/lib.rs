@@ -1,28 +1,60 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use arrow::row::{RowConverter, SortField};
 use arrow_array::RecordBatch;
 use arroyo_rpc::grpc::{
-    CheckpointMetadata, ExpiringKeyedTimeTableConfig, GlobalKeyedTableConfig,
-    OperatorCheckpointMetadata, TableCheckpointMetadata, TableConfig, TableEnum,
+    CheckpointMetadata, ExpirationMode, ExpiringKeyedTimeTableConfig, GlobalKeyedTableConfig,
+    KeyPartitioning, OperatorCheckpointMetadata, OperatorRemapping, RetentionRule,
+    TableCheckpointMetadata, TableConfig, TableEnum, TableRestorePolicy, TableTimestampPolicy,
 };
-use arroyo_types::single_item_hash_map;
+use arroyo_types::{single_item_hash_map, DEFAULT_KEY_GROUPS};
 use async_trait::async_trait;
 use bincode::config::Configuration;
 use bincode::{Decode, Encode};
+use datafusion_common::ScalarValue;
 
+use arroyo_rpc::api::TableStorageUsage;
 use arroyo_rpc::df::ArroyoSchema;
+use arroyo_rpc::get_hasher;
 use prost::Message;
-use std::collections::hash_map::DefaultHasher;
+use state_serde::StateCodec;
 use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
+use std::hash::Hash;
 use std::ops::RangeInclusive;
 use std::time::{Duration, SystemTime};
 
+pub mod backpressure;
+pub mod changelog;
+pub mod checkpoint_events;
+pub mod checkpoint_sla;
 pub mod checkpoint_state;
+pub mod checkpoint_state_map;
+pub mod checkpoint_stats;
 pub mod committing_state;
+pub mod encryption;
+pub mod export;
+pub mod identifiers;
+pub mod in_flight;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod in_memory;
+pub mod inspect;
+pub mod metadata_format;
 mod metrics;
 pub mod parquet;
+pub mod prefetch;
+pub mod quota;
+pub mod remapping;
+pub mod restore_policy;
+pub mod restore_progress;
 pub(crate) mod schemas;
+pub mod state_serde;
+pub(crate) mod store_writes;
 pub mod tables;
+#[cfg(test)]
+mod test_storage;
+pub mod timestamps;
+pub mod upload_scheduler;
+pub mod verify;
+pub mod write_buffer;
 
 pub const BINCODE_CONFIG: Configuration = bincode::config::standard();
 pub const FULL_KEY_RANGE: RangeInclusive<u64> = 0..=u64::MAX;
@@ -31,7 +63,17 @@ pub const FULL_KEY_RANGE: RangeInclusive<u64> = 0..=u64::MAX;
 pub enum StateMessage {
     Checkpoint(CheckpointMessage),
     Compaction(HashMap<String, TableCheckpointMetadata>),
-    TableData { table: String, data: TableData },
+    TableData {
+        table: String,
+        data: TableData,
+    },
+    /// Table writes coalesced by a [`write_buffer::StateSender`], in the order they were made.
+    TableDataBatch(Vec<StateMessage>),
+    /// The records captured for an unaligned checkpoint, once its last barrier has arrived.
+    InFlight {
+        epoch: u32,
+        captured: Vec<in_flight::InFlightBatches>,
+    },
 }
 #[derive(Debug)]
 pub struct CheckpointMessage {
@@ -39,6 +81,8 @@ pub struct CheckpointMessage {
     time: SystemTime,
     watermark: Option<SystemTime>,
     then_stop: bool,
+    // whether the checkpoint is unaligned, so it completes once its in-flight records arrive
+    in_flight: bool,
 }
 
 #[derive(Debug)]
@@ -46,10 +90,128 @@ pub enum TableData {
     RecordBatch(RecordBatch),
     CommitData { data: Vec<u8> },
     KeyedData { key: Vec<u8>, value: Vec<u8> },
+    KeyedDelete { key: Vec<u8> },
 }
 
 pub type StateBackend = parquet::ParquetBackend;
 
+/// The backend an operator's tables are checkpointed to. Selected per operator through the
+/// `state_backend` field of its table configs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum StateBackendKind {
+    /// Table data is written as parquet files to the checkpoint storage.
+    #[default]
+    Parquet,
+    /// Table data is only held in worker memory. Nothing is written at checkpoint time and
+    /// the operator's tables start empty after a restore.
+    Memory,
+}
+
+impl StateBackendKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StateBackendKind::Parquet => StateBackend::name(),
+            StateBackendKind::Memory => "memory",
+        }
+    }
+
+    /// Parses a backend name as recorded in checkpoint metadata. Metadata written before
+    /// backends were recorded has an empty name, which maps to the default.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "" => Ok(Self::default()),
+            "memory" => Ok(StateBackendKind::Memory),
+            name if name == StateBackend::name() => Ok(StateBackendKind::Parquet),
+            name => bail!("unknown state backend '{}'", name),
+        }
+    }
+
+    /// Determines the backend for an operator from its table configs, failing if the
+    /// tables disagree.
+    pub fn for_tables(table_configs: &HashMap<String, TableConfig>) -> Result<Self> {
+        let mut backend = None;
+        for (table, config) in table_configs {
+            let table_backend = Self::from_name(config.state_backend.as_deref().unwrap_or(""))?;
+            match backend {
+                None => backend = Some(table_backend),
+                Some(existing) if existing != table_backend => {
+                    bail!(
+                        "table {} requests state backend {}, but other tables use {}",
+                        table,
+                        table_backend.name(),
+                        existing.name()
+                    );
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(backend.unwrap_or_default())
+    }
+}
+
+/// Sets the state backend on every table config for an operator.
+pub fn with_state_backend(
+    mut table_configs: HashMap<String, TableConfig>,
+    backend: StateBackendKind,
+) -> HashMap<String, TableConfig> {
+    for config in table_configs.values_mut() {
+        config.state_backend = Some(backend.name().to_string());
+    }
+    table_configs
+}
+
+/// Sets the state quota on every table config for an operator, limiting the in-memory size
+/// of its state in place of the worker's quota. See [`quota::StateQuotaConfig`].
+pub fn with_state_quota(
+    mut table_configs: HashMap<String, TableConfig>,
+    quota: quota::StateQuotaConfig,
+) -> HashMap<String, TableConfig> {
+    for config in table_configs.values_mut() {
+        config.state_quota = Some(quota.proto());
+    }
+    table_configs
+}
+
+/// Writes a table's data files under `prefix` in the checkpoint storage rather than with the
+/// rest of the job's files, so that they can be given their own lifecycle rules.
+pub fn with_path_prefix(mut config: TableConfig, prefix: impl Into<String>) -> TableConfig {
+    config.path_prefix = Some(prefix.into());
+    config
+}
+
+/// Encodes a global keyed table's keys and values with `codec` rather than the default
+/// bincode. Tables in the same operator can use different codecs, and a table's codec can
+/// be changed between runs, since restores decode with the codec the checkpoint recorded.
+pub fn with_value_codec(mut config: TableConfig, codec: StateCodec) -> TableConfig {
+    config.value_codec = Some(codec.name().to_string());
+    config
+}
+
+/// Declares the version of a global keyed table's value type, which must match the
+/// [`state_serde::VersionedData::VERSION`] of the type it's accessed with through
+/// `TableManager::get_versioned_global_keyed_state`. Values checkpointed as an earlier
+/// version are upgraded on restore.
+pub fn with_value_version(mut config: TableConfig, version: u32) -> TableConfig {
+    config.value_version = Some(version);
+    config
+}
+
+/// Restores a table with `policy` rather than failing if any of its checkpointed data can't
+/// be decoded. The policy can also be overridden for a single restore through
+/// [`restore_policy::STATE_RESTORE_POLICY_OVERRIDES_ENV`].
+pub fn with_restore_policy(mut config: TableConfig, policy: TableRestorePolicy) -> TableConfig {
+    config.restore_policy = Some(policy.into());
+    config
+}
+
+/// Sets what happens to timestamps inserted into a table that are outside the range the
+/// state layer stores (see [`timestamps::max_state_timestamp`]): by default the insert
+/// fails, and with [`TableTimestampPolicy::Clamp`] they're stored as the nearest one in range.
+pub fn with_timestamp_policy(mut config: TableConfig, policy: TableTimestampPolicy) -> TableConfig {
+    config.timestamp_policy = Some(policy.into());
+    config
+}
+
 pub fn global_table_config(
     name: impl Into<String>,
     description: impl Into<String>,
@@ -62,18 +224,261 @@ pub fn global_table_config(
             config: GlobalKeyedTableConfig {
                 table_name: name,
                 description: description.into(),
-                uses_two_phase_commit: false,
+                ..Default::default()
+            }
+            .encode_to_vec(),
+            ..Default::default()
+        },
+    )
+}
+
+/// Config for a global keyed table that every subtask writes identically, like a set of
+/// rules from a broadcast control stream. Only one copy is kept in the checkpoint, and every
+/// subtask restores all of it, whatever the old and new parallelism.
+pub fn broadcast_table_config(
+    name: impl Into<String>,
+    description: impl Into<String>,
+) -> HashMap<String, TableConfig> {
+    let name = name.into();
+    single_item_hash_map(
+        name.clone(),
+        TableConfig {
+            table_type: TableEnum::GlobalKeyValue.into(),
+            config: GlobalKeyedTableConfig {
+                table_name: name,
+                description: description.into(),
+                broadcast: true,
+                ..Default::default()
+            }
+            .encode_to_vec(),
+            ..Default::default()
+        },
+    )
+}
+
+/// Config for a global keyed table that's checkpointed incrementally. Each epoch writes only
+/// the keys inserted or deleted since the previous checkpoint, and references the files of
+/// earlier epochs for the rest, so large tables that change slowly aren't rewritten every
+/// epoch. Restores read the files oldest first.
+pub fn incremental_table_config(
+    name: impl Into<String>,
+    description: impl Into<String>,
+) -> HashMap<String, TableConfig> {
+    let name = name.into();
+    single_item_hash_map(
+        name.clone(),
+        TableConfig {
+            table_type: TableEnum::GlobalKeyValue.into(),
+            config: GlobalKeyedTableConfig {
+                table_name: name,
+                description: description.into(),
+                incremental: true,
+                ..Default::default()
+            }
+            .encode_to_vec(),
+            ..Default::default()
+        },
+    )
+}
+
+/// Config for a global keyed table holding per-key state. Its keys aren't partitioned, so
+/// every subtask restores all of them; use [`key_group_table_config`] for tables whose keys
+/// the engine routes by.
+pub fn keyed_table_config(
+    name: impl Into<String>,
+    description: impl Into<String>,
+) -> HashMap<String, TableConfig> {
+    let name = name.into();
+    single_item_hash_map(
+        name.clone(),
+        TableConfig {
+            table_type: TableEnum::GlobalKeyValue.into(),
+            config: GlobalKeyedTableConfig {
+                table_name: name,
+                description: description.into(),
+                ..Default::default()
+            }
+            .encode_to_vec(),
+            ..Default::default()
+        },
+    )
+}
+
+/// Config for a global keyed table whose keys are partitioned across subtasks in
+/// [`DEFAULT_KEY_GROUPS`] key groups, by [`hash_key`]. Each key must be the value of the
+/// operator's single key column, as that's when [`hash_key`] agrees with the hash the engine
+/// routes records by. Checkpoints are written a row group per key group, so when a job is
+/// restored at a different parallelism each subtask only reads the groups its key range now
+/// overlaps. The number of key groups can't change once a job has state.
+pub fn key_group_table_config(
+    name: impl Into<String>,
+    description: impl Into<String>,
+) -> HashMap<String, TableConfig> {
+    let name = name.into();
+    single_item_hash_map(
+        name.clone(),
+        TableConfig {
+            table_type: TableEnum::GlobalKeyValue.into(),
+            config: GlobalKeyedTableConfig {
+                table_name: name,
+                description: description.into(),
+                partitioning: KeyPartitioning::KeyGroup.into(),
+                key_groups: DEFAULT_KEY_GROUPS,
+                ..Default::default()
             }
             .encode_to_vec(),
+            ..Default::default()
         },
     )
 }
 
+/// Config for a table holding processing-time timers, which are persisted through a global
+/// keyed table and accessed with `TableManager::get_processing_time_timers`.
+pub fn processing_time_timer_table_config(
+    name: impl Into<String>,
+    description: impl Into<String>,
+) -> HashMap<String, TableConfig> {
+    keyed_table_config(name, description)
+}
+
+/// Config for a table holding at most one value per key and timestamp, which is accessed
+/// with `TableManager::get_key_time_map`. It's declared as a [`TableEnum::KeyTimeMap`], so
+/// its state can't be restored as another kind of table, and is stored in the global keyed
+/// table format.
+pub fn key_time_map_table_config(
+    name: impl Into<String>,
+    description: impl Into<String>,
+) -> HashMap<String, TableConfig> {
+    let mut configs = keyed_table_config(name, description);
+    for config in configs.values_mut() {
+        config.set_table_type(TableEnum::KeyTimeMap);
+    }
+    configs
+}
+
+/// Config for a table holding an append-only list per key, which is persisted through a
+/// global keyed table and accessed with `TableManager::get_keyed_list`.
+pub fn keyed_list_table_config(
+    name: impl Into<String>,
+    description: impl Into<String>,
+) -> HashMap<String, TableConfig> {
+    keyed_table_config(name, description)
+}
+
+/// Config for a table holding a map of inner keys to values per key, which is persisted
+/// through a global keyed table and accessed with `TableManager::get_keyed_map`.
+pub fn keyed_map_table_config(
+    name: impl Into<String>,
+    description: impl Into<String>,
+) -> HashMap<String, TableConfig> {
+    keyed_table_config(name, description)
+}
+
+/// Config for a table holding a single reduced value per key, which is persisted through a
+/// global keyed table and accessed with `TableManager::get_reducing_table`.
+pub fn reducing_table_config(
+    name: impl Into<String>,
+    description: impl Into<String>,
+) -> HashMap<String, TableConfig> {
+    keyed_table_config(name, description)
+}
+
+/// Config for a table holding event-time timers, which are persisted through a global keyed
+/// table and accessed with `TableManager::get_timers`.
+pub fn timer_table_config(
+    name: impl Into<String>,
+    description: impl Into<String>,
+) -> HashMap<String, TableConfig> {
+    keyed_table_config(name, description)
+}
+
+/// Config for a table holding values ordered by key, which is persisted through a global
+/// keyed table and accessed with `TableManager::get_sorted_keyed_table`.
+pub fn sorted_keyed_table_config(
+    name: impl Into<String>,
+    description: impl Into<String>,
+) -> HashMap<String, TableConfig> {
+    keyed_table_config(name, description)
+}
+
 pub fn timestamp_table_config(
     name: impl Into<String>,
     description: impl Into<String>,
     retention: Duration,
     schema: ArroyoSchema,
+) -> TableConfig {
+    expiring_table_config(name, description, retention, vec![], schema)
+}
+
+/// Like [`timestamp_table_config`], but with an ordered list of `(key prefix, retention)`
+/// rules. A prefix holds values for the leading key columns of `schema`, in order, and
+/// matches the keys that start with those values; keys that match no rule use the default
+/// `retention`. Fails if a prefix doesn't fit the schema's key columns.
+pub fn timestamp_table_config_with_retention_rules(
+    name: impl Into<String>,
+    description: impl Into<String>,
+    retention: Duration,
+    retention_rules: Vec<(Vec<ScalarValue>, Duration)>,
+    schema: ArroyoSchema,
+) -> Result<TableConfig> {
+    let retention_rules = retention_rules
+        .into_iter()
+        .map(|(key_prefix, retention)| {
+            Ok(RetentionRule {
+                key_prefix: encode_key_prefix(&schema, &key_prefix)?,
+                retention_micros: retention.as_micros() as u64,
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(expiring_table_config(
+        name,
+        description,
+        retention,
+        retention_rules,
+        schema,
+    ))
+}
+
+/// Row-encodes values for the leading key columns of `schema`, the form in which retention
+/// rules are matched against the keys of a table's rows.
+fn encode_key_prefix(schema: &ArroyoSchema, key_prefix: &[ScalarValue]) -> Result<Vec<u8>> {
+    let key_indices = schema.key_indices.as_deref().unwrap_or_default();
+    if key_prefix.len() > key_indices.len() {
+        bail!(
+            "key prefix has {} values, but the schema only has {} key columns",
+            key_prefix.len(),
+            key_indices.len()
+        );
+    }
+    if key_prefix.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut sort_fields = vec![];
+    let mut columns = vec![];
+    for (value, index) in key_prefix.iter().zip(key_indices) {
+        let field = schema.schema.field(*index);
+        if value.data_type() != *field.data_type() {
+            bail!(
+                "key prefix value {} is a {}, but key column {} is a {}",
+                value,
+                value.data_type(),
+                field.name(),
+                field.data_type()
+            );
+        }
+        sort_fields.push(SortField::new(field.data_type().clone()));
+        columns.push(value.to_array()?);
+    }
+    let rows = RowConverter::new(sort_fields)?.convert_columns(&columns)?;
+    Ok(rows.row(0).as_ref().to_vec())
+}
+
+fn expiring_table_config(
+    name: impl Into<String>,
+    description: impl Into<String>,
+    retention: Duration,
+    retention_rules: Vec<RetentionRule>,
+    schema: ArroyoSchema,
 ) -> TableConfig {
     TableConfig {
         table_type: TableEnum::ExpiringKeyedTimeTable.into(),
@@ -82,8 +487,34 @@ pub fn timestamp_table_config(
             description: description.into(),
             retention_micros: retention.as_micros() as u64,
             schema: Some(schema.try_into().unwrap()),
+            retention_rules,
+            expiration_mode: ExpirationMode::EventTime.into(),
         }
         .encode_to_vec(),
+        ..Default::default()
+    }
+}
+
+/// Like [`timestamp_table_config`], but data expires `retention` after it was inserted, by
+/// wall-clock time, rather than as the watermark advances.
+pub fn processing_time_table_config(
+    name: impl Into<String>,
+    description: impl Into<String>,
+    retention: Duration,
+    schema: ArroyoSchema,
+) -> TableConfig {
+    TableConfig {
+        table_type: TableEnum::ExpiringKeyedTimeTable.into(),
+        config: ExpiringKeyedTimeTableConfig {
+            table_name: name.into(),
+            description: description.into(),
+            retention_micros: retention.as_micros() as u64,
+            schema: Some(schema.try_into().unwrap()),
+            expiration_mode: ExpirationMode::ProcessingTime.into(),
+            ..Default::default()
+        }
+        .encode_to_vec(),
+        ..Default::default()
     }
 }
 
@@ -128,6 +559,26 @@ pub trait BackingStore {
     /// loads the checkpoint metadata for a given job id and epoch
     async fn load_checkpoint_metadata(job_id: &str, epoch: u32) -> Result<CheckpointMetadata>;
 
+    /// loads the checkpoint metadata for a given job id and epoch, if it was written
+    async fn load_checkpoint_metadata_if_present(
+        job_id: &str,
+        epoch: u32,
+    ) -> Result<Option<CheckpointMetadata>>;
+
+    /// lists the epochs of a job that have checkpoint metadata, in order
+    async fn list_checkpoint_epochs(job_id: &str) -> Result<Vec<u32>>;
+
+    /// loads the operator remapping supplied for restoring a given job id and epoch, if any
+    async fn load_operator_remapping(job_id: &str, epoch: u32) -> Result<Vec<OperatorRemapping>>;
+
+    /// stores the operator remapping to apply when restoring a given job id and epoch,
+    /// replacing any that was supplied before
+    async fn write_operator_remapping(
+        job_id: &str,
+        epoch: u32,
+        remappings: Vec<OperatorRemapping>,
+    ) -> Result<()>;
+
     /// loads the operator checkpoint metadata for a given job id, operator id, and epoch
     async fn load_operator_metadata(
         job_id: &str,
@@ -142,19 +593,48 @@ pub trait BackingStore {
     async fn write_operator_checkpoint_metadata(metadata: OperatorCheckpointMetadata)
         -> Result<()>;
 
-    /// writes the checkpoint metadata to the backing store
+    /// copies the files an operator's checkpoint references into the checkpoint of the same
+    /// epoch of `target_job_id`, returning the metadata moved to the target job and referring
+    /// to the copies, to be written with [`BackingStore::write_operator_checkpoint_metadata`].
+    /// Nothing of the source checkpoint is changed.
+    async fn copy_operator_checkpoint(
+        metadata: OperatorCheckpointMetadata,
+        target_job_id: &str,
+    ) -> Result<OperatorCheckpointMetadata>;
+
+    /// writes the checkpoint metadata to the backing store; this marks the checkpoint as
+    /// restorable, so it must only be called once every operator's metadata has been written
     async fn write_checkpoint_metadata(metadata: CheckpointMetadata) -> Result<()>;
 
+    /// writes the metadata of a savepoint under its name, replacing any savepoint of the job
+    /// with the same name. The savepoint's epoch is then never cleaned up.
+    async fn write_savepoint_metadata(metadata: CheckpointMetadata) -> Result<()>;
+
+    /// loads the metadata of a job's savepoint by name
+    async fn load_savepoint_metadata(job_id: &str, name: &str) -> Result<CheckpointMetadata>;
+
+    /// lists a job's savepoints, ordered by epoch
+    async fn list_savepoints(job_id: &str) -> Result<Vec<CheckpointMetadata>>;
+
     /// cleans up a checkpoint by deleting data that is no longer needed
     async fn cleanup_checkpoint(
         metadata: CheckpointMetadata,
         old_min_epoch: u32,
         new_min_epoch: u32,
     ) -> Result<()>;
+
+    /// returns the storage used by each table of each operator in a checkpoint, keyed by
+    /// operator id, including files retained from earlier epochs
+    async fn table_storage_usage(
+        job_id: &str,
+        epoch: u32,
+    ) -> Result<HashMap<String, Vec<TableStorageUsage>>>;
 }
 
+/// The hash that decides which key group, and so which subtask, a key belongs to in
+/// partitioned tables; see [`key_group_table_config`]. It's the hash the engine routes
+/// records by, `create_hashes` with [`get_hasher`], for a key of a single integer, string or
+/// binary column, and like it is seeded the same in every process.
 pub fn hash_key<K: Hash>(key: &K) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    key.hash(&mut hasher);
-    hasher.finish()
+    get_hasher().hash_one(key)
 }
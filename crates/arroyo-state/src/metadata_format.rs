@@ -0,0 +1,202 @@
+use anyhow::{bail, Context, Result};
+use arroyo_rpc::grpc::{
+    CheckpointMetadata, LegacyOperatorCheckpointFields, MetadataFormatVersion,
+    OperatorCheckpointMetadata, TableEnum,
+};
+use prost::Message;
+
+use crate::{global_table_config, StateBackendKind};
+
+/// The version of the checkpoint and operator checkpoint metadata format written by this
+/// version of arroyo. Metadata written before formats were versioned has version 0.
+///
+/// Version 1 added the state backend, sizes and in-flight records to operator metadata,
+/// and requires a table config for every table with checkpoint data.
+pub const METADATA_FORMAT_VERSION: u32 = 1;
+
+fn check_format_version(kind: &str, data: &[u8]) -> Result<()> {
+    let version = MetadataFormatVersion::decode(data)
+        .with_context(|| format!("failed to decode {} metadata", kind))?
+        .format_version;
+    if version > METADATA_FORMAT_VERSION {
+        bail!(
+            "{} metadata was written by a newer version of arroyo, in format version {}; \
+            this version can read format versions up to {}",
+            kind,
+            version,
+            METADATA_FORMAT_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Decodes checkpoint metadata written in this or any earlier format, migrated to the
+/// current format.
+pub fn decode_checkpoint_metadata(data: &[u8]) -> Result<CheckpointMetadata> {
+    check_format_version("checkpoint", data)?;
+    let mut metadata =
+        CheckpointMetadata::decode(data).context("failed to decode checkpoint metadata")?;
+    // nothing was added to checkpoint metadata before version 1 that can't default
+    metadata.format_version = METADATA_FORMAT_VERSION;
+    Ok(metadata)
+}
+
+/// Decodes operator checkpoint metadata written in this or any earlier format, migrated to
+/// the current format.
+pub fn decode_operator_metadata(data: &[u8]) -> Result<OperatorCheckpointMetadata> {
+    check_format_version("operator checkpoint", data)?;
+    let legacy = LegacyOperatorCheckpointFields::decode(data)
+        .context("failed to decode operator checkpoint metadata")?;
+    if !legacy.backend_data.is_empty() {
+        bail!(
+            "operator checkpoint metadata has table data in the legacy format, which can no \
+            longer be restored"
+        );
+    }
+    // legacy table descriptors without any data leave nothing to restore
+    let metadata = OperatorCheckpointMetadata::decode(data)
+        .context("failed to decode operator checkpoint metadata")?;
+    migrate_operator_metadata(metadata)
+}
+
+/// Brings operator metadata decoded from an earlier format up to the current one.
+pub fn migrate_operator_metadata(
+    mut metadata: OperatorCheckpointMetadata,
+) -> Result<OperatorCheckpointMetadata> {
+    if metadata.format_version == 0 {
+        if metadata.backend.is_empty() {
+            metadata.backend = StateBackendKind::default().name().to_string();
+        }
+        metadata.has_state |= !metadata.table_checkpoint_metadata.is_empty();
+        for (table_name, table_metadata) in &metadata.table_checkpoint_metadata {
+            if metadata.table_configs.contains_key(table_name) {
+                continue;
+            }
+            // global tables can be read with a default config; time-keyed tables need the
+            // retention that only their config records
+            match table_metadata.table_type() {
+                TableEnum::GlobalKeyValue => {
+                    metadata
+                        .table_configs
+                        .extend(global_table_config(table_name.clone(), ""));
+                }
+                table_type => bail!(
+                    "can't migrate operator checkpoint metadata: {:?} table {} has no config",
+                    table_type,
+                    table_name
+                ),
+            }
+        }
+    }
+    metadata.format_version = METADATA_FORMAT_VERSION;
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use arroyo_rpc::grpc::{OperatorMetadata, TableCheckpointMetadata};
+
+    use super::*;
+
+    /// Operator metadata as written before formats were versioned, with a table that has
+    /// data but no config.
+    fn unversioned_operator() -> OperatorCheckpointMetadata {
+        OperatorCheckpointMetadata {
+            operator_metadata: Some(OperatorMetadata {
+                job_id: "job".to_string(),
+                operator_id: "op".to_string(),
+                epoch: 1,
+                parallelism: 1,
+                ..Default::default()
+            }),
+            start_time: 1,
+            finish_time: 2,
+            table_checkpoint_metadata: HashMap::from([(
+                "t".to_string(),
+                TableCheckpointMetadata {
+                    table_type: TableEnum::GlobalKeyValue.into(),
+                    data: vec![1, 2, 3],
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_migrate_unversioned_operator_metadata() {
+        let data = unversioned_operator().encode_to_vec();
+        let migrated = decode_operator_metadata(&data).unwrap();
+        assert_eq!(migrated.format_version, METADATA_FORMAT_VERSION);
+        assert_eq!(
+            migrated.backend,
+            StateBackendKind::default().name().to_string()
+        );
+        assert!(migrated.has_state);
+        assert_eq!(migrated.table_configs, global_table_config("t", ""));
+
+        // migrated metadata round-trips unchanged
+        assert_eq!(
+            decode_operator_metadata(&migrated.encode_to_vec()).unwrap(),
+            migrated
+        );
+
+        let mut time_table = unversioned_operator();
+        time_table
+            .table_checkpoint_metadata
+            .get_mut("t")
+            .unwrap()
+            .table_type = TableEnum::ExpiringKeyedTimeTable.into();
+        assert!(decode_operator_metadata(&time_table.encode_to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_legacy_table_data() {
+        let mut data = unversioned_operator().encode_to_vec();
+        LegacyOperatorCheckpointFields {
+            tables: vec![vec![]],
+            backend_data: vec![],
+        }
+        .encode(&mut data)
+        .unwrap();
+        assert!(decode_operator_metadata(&data).is_ok());
+
+        LegacyOperatorCheckpointFields {
+            tables: vec![],
+            backend_data: vec![vec![1]],
+        }
+        .encode(&mut data)
+        .unwrap();
+        let err = decode_operator_metadata(&data).unwrap_err();
+        assert!(err.to_string().contains("legacy format"));
+    }
+
+    #[test]
+    fn test_newer_format_version() {
+        let data = CheckpointMetadata {
+            job_id: "job".to_string(),
+            epoch: 1,
+            format_version: METADATA_FORMAT_VERSION + 1,
+            ..Default::default()
+        }
+        .encode_to_vec();
+        let err = decode_checkpoint_metadata(&data).unwrap_err();
+        assert!(err.to_string().contains("newer version"));
+
+        let mut operator = unversioned_operator();
+        operator.format_version = METADATA_FORMAT_VERSION + 1;
+        assert!(decode_operator_metadata(&operator.encode_to_vec()).is_err());
+
+        let data = CheckpointMetadata {
+            job_id: "job".to_string(),
+            epoch: 1,
+            ..Default::default()
+        }
+        .encode_to_vec();
+        assert_eq!(
+            decode_checkpoint_metadata(&data).unwrap().format_version,
+            METADATA_FORMAT_VERSION
+        );
+    }
+}
@@ -1,5 +1,9 @@
 use lazy_static::lazy_static;
-use prometheus::{register_gauge_vec, GaugeVec};
+use prometheus::{
+    exponential_buckets, register_gauge, register_gauge_vec, register_histogram_vec,
+    register_int_counter, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Gauge, GaugeVec, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+};
 
 lazy_static! {
     pub static ref WORKER_LABELS_NAMES: Vec<&'static str> = vec!["operator_id", "task_id"];
@@ -13,7 +17,205 @@ lazy_static! {
         vec!["operator_id", "task_id", "table_char"];
     pub static ref TABLE_SIZE_GAUGE: GaugeVec = register_gauge_vec!(
         "arroyo_worker_table_size_keys",
-        "Number of keys in the table",
+        "Number of entries in the table: keys for global keyed tables, rows for time-keyed tables",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref STATE_WRITE_LATENCY_HISTOGRAM: HistogramVec = register_histogram_vec!(
+        "arroyo_worker_state_write_seconds",
+        "Time taken by successful writes to the state backend, by the kind of object written",
+        &["operation"],
+        exponential_buckets(0.001, 2.0, 16).unwrap()
+    )
+    .unwrap();
+    pub static ref STATE_WRITES_IN_FLIGHT_GAUGE: IntGauge = register_int_gauge!(
+        "arroyo_worker_state_writes_in_flight",
+        "Number of writes to the state backend this worker has in progress"
+    )
+    .unwrap();
+    pub static ref STATE_WRITE_FAILURES_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_state_write_failures_total",
+        "Number of writes to the state backend that failed, by the kind of object and error",
+        &["operation", "error"]
+    )
+    .unwrap();
+    pub static ref TABLE_BYTES_GAUGE: GaugeVec = register_gauge_vec!(
+        "arroyo_worker_table_size_bytes",
+        "Estimated in-memory size of the table in bytes",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref TABLE_CHECKPOINT_BYTES_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_table_checkpoint_bytes",
+        "Bytes of table data written to checkpoints, as stored (compressed) and before compression (uncompressed)",
+        &["operator_id", "task_id", "table_char", "size"]
+    )
+    .unwrap();
+    pub static ref CHANGELOG_DROPPED_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_changelog_dropped_events",
+        "Number of table changelog events dropped because the channel was full",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref TABLE_SNAPSHOT_AGE_GAUGE: GaugeVec = register_gauge_vec!(
+        "arroyo_worker_table_snapshot_age_seconds",
+        "Age of the latest read replica snapshot of the table, as of its last publish or read",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref UPLOAD_QUEUE_DEPTH_GAUGE: IntGauge = register_int_gauge!(
+        "arroyo_worker_checkpoint_upload_queue_depth",
+        "Number of checkpoint uploads on this worker waiting for an upload slot"
+    )
+    .unwrap();
+    pub static ref UPLOAD_BYTES_COUNTER: IntCounter = register_int_counter!(
+        "arroyo_worker_checkpoint_uploaded_bytes",
+        "Number of bytes of checkpoint data uploaded by this worker"
+    )
+    .unwrap();
+    pub static ref UPLOAD_THROUGHPUT_GAUGE: Gauge = register_gauge!(
+        "arroyo_worker_checkpoint_upload_throughput_bytes",
+        "Throughput in bytes per second of the most recently completed checkpoint upload"
+    )
+    .unwrap();
+    pub static ref CHECKPOINT_SLA_BREACH_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_controller_checkpoint_sla_breaches",
+        "Number of checkpoint SLA breaches",
+        &["job_id", "sla"]
+    )
+    .unwrap();
+    pub static ref CHECKPOINT_SLA_BREACHED_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        "arroyo_controller_checkpoint_sla_breached",
+        "Whether the checkpoint SLA is currently breached (1) or met (0)",
+        &["job_id", "sla"]
+    )
+    .unwrap();
+    pub static ref CHECKPOINT_DURATION_HISTOGRAM: HistogramVec = register_histogram_vec!(
+        "arroyo_controller_checkpoint_duration_seconds",
+        "Time from starting a checkpoint to saving its metadata",
+        &["job_id"],
+        exponential_buckets(0.1, 2.0, 14).unwrap()
+    )
+    .unwrap();
+    pub static ref OPERATOR_CHECKPOINT_SYNC_HISTOGRAM: HistogramVec = register_histogram_vec!(
+        "arroyo_controller_operator_checkpoint_sync_seconds",
+        "Time taken by the slowest subtask of an operator to finish the synchronous part of a checkpoint",
+        &["job_id", "operator_id"],
+        exponential_buckets(0.001, 2.0, 16).unwrap()
+    )
+    .unwrap();
+    pub static ref CHECKPOINT_BYTES_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        "arroyo_controller_checkpoint_bytes",
+        "Bytes of table data written by the job's last completed checkpoint",
+        &["job_id"]
+    )
+    .unwrap();
+    pub static ref CHECKPOINTS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_controller_checkpoints",
+        "Number of checkpoints that ended, by result: completed, failed or timed_out",
+        &["job_id", "result"]
+    )
+    .unwrap();
+    pub static ref LAST_SUCCESSFUL_CHECKPOINT_TIMESTAMP_GAUGE: GaugeVec = register_gauge_vec!(
+        "arroyo_controller_last_successful_checkpoint_timestamp_seconds",
+        "Unix time at which the job's last completed checkpoint finished",
+        &["job_id"]
+    )
+    .unwrap();
+    pub static ref LAST_SUCCESSFUL_CHECKPOINT_EPOCH_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        "arroyo_controller_last_successful_checkpoint_epoch",
+        "Epoch of the job's last completed checkpoint",
+        &["job_id"]
+    )
+    .unwrap();
+    pub static ref LAST_FAILED_CHECKPOINT_TIMESTAMP_GAUGE: GaugeVec = register_gauge_vec!(
+        "arroyo_controller_last_failed_checkpoint_timestamp_seconds",
+        "Unix time at which the job's last failed, aborted or timed out checkpoint ended",
+        &["job_id"]
+    )
+    .unwrap();
+    pub static ref OPERATOR_STATE_BYTES_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        "arroyo_worker_state_bytes",
+        "Estimated in-memory size of the subtask's state, across all of its tables",
+        &WORKER_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref RESTORE_FILES_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_restore_files",
+        "Number of checkpoint files read while restoring the table",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref RESTORE_TUPLES_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_restore_tuples",
+        "Number of entries or rows applied to the table while restoring it",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref RESTORE_BYTES_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_restore_bytes",
+        "Bytes of checkpoint files read while restoring the table, as stored",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref STATE_READ_HITS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_state_read_hits_total",
+        "Number of reads of the table served from its in-memory view",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref STATE_READ_MISSES_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_state_read_misses_total",
+        "Number of reads of the table that had to go to the state backend",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref STATE_READ_MISS_LATENCY_HISTOGRAM: HistogramVec = register_histogram_vec!(
+        "arroyo_worker_state_read_miss_seconds",
+        "Time taken by reads of the table that had to go to the state backend",
+        &TABLE_LABELS_NAMES,
+        exponential_buckets(0.001, 2.0, 16).unwrap()
+    )
+    .unwrap();
+    pub static ref VALUES_EXPIRED_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_values_expired_total",
+        "Number of values removed from the table because they expired",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref VALUES_DELETED_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_values_deleted_total",
+        "Number of single values deleted from the table",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref KEYS_DELETED_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_keys_deleted_total",
+        "Number of keys deleted from the table along with all of their values",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref TIME_RANGES_CLEARED_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_time_ranges_cleared_total",
+        "Number of time ranges of a key's values cleared from the table",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref RESTORE_SKIPPED_TUPLES_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_restore_skipped_tuples",
+        "Number of entries or rows dropped while restoring the table, as they failed to decode",
+        &TABLE_LABELS_NAMES
+    )
+    .unwrap();
+    pub static ref STATE_QUOTA_EXCEEDED_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_state_quota_exceeded",
+        "Number of times the subtask's state exceeded its soft or hard quota",
+        &["operator_id", "task_id", "limit"]
+    )
+    .unwrap();
+    pub static ref STATE_QUOTA_EXPIRED_ROWS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "arroyo_worker_state_quota_expired_rows",
+        "Number of rows expired from the table before their retention ended, to keep the subtask within its state quota",
         &TABLE_LABELS_NAMES
     )
     .unwrap();
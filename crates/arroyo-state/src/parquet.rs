@@ -1,28 +1,212 @@
+use crate::encryption::{decrypt, encrypt, fetch_state_file};
+use crate::identifiers::encode_path_component;
+use crate::metadata_format::{
+    decode_checkpoint_metadata, decode_operator_metadata, METADATA_FORMAT_VERSION,
+};
+use crate::metrics::TABLE_CHECKPOINT_BYTES_COUNTER;
+use crate::remapping::{
+    format_operator_remapping, parse_operator_remapping, OPERATOR_REMAPPING_FILE,
+};
+use crate::store_writes::{timed_write, WriteOperation};
 use crate::tables::expiring_time_key_map::ExpiringTimeKeyTable;
 use crate::tables::global_keyed_map::GlobalKeyedTable;
-use crate::tables::{CompactionConfig, ErasedTable};
+use crate::tables::{CompactionConfig, ErasedTable, RetainedFile};
+use crate::verify::{verify_checkpoint_in, verify_parallelism, CheckpointVerification};
 use crate::BackingStore;
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use arroyo_rpc::api::TableStorageUsage;
 use arroyo_rpc::grpc;
-use arroyo_rpc::grpc::{CheckpointMetadata, OperatorCheckpointMetadata, TableCheckpointMetadata};
+use arroyo_rpc::grpc::{
+    CheckpointMetadata, OperatorCheckpointMetadata, OperatorRemapping, SubtaskCheckpointMetadata,
+    TableCheckpointMetadata,
+};
 use arroyo_storage::StorageProvider;
-use arroyo_types::{CHECKPOINT_URL_ENV, S3_ENDPOINT_ENV, S3_REGION_ENV};
+use arroyo_types::{u32_config, TaskInfo, CHECKPOINT_URL_ENV, S3_ENDPOINT_ENV, S3_REGION_ENV};
+use bytes::Bytes;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
+use parquet::basic::{Compression, ZstdLevel};
 
 use prost::Message;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::env;
 use std::ops::RangeInclusive;
 use std::sync::Arc;
 use std::time::SystemTime;
-use tokio::sync::Mutex;
 use tracing::{debug, info};
 
 pub const FULL_KEY_RANGE: RangeInclusive<u64> = 0..=u64::MAX;
 pub const GENERATIONS_TO_COMPACT: u32 = 1; // only compact generation 0 files
 
+/// Codec for state files: `zstd` (the default), `snappy`, `lz4`, or `none`.
+pub const STATE_COMPRESSION_ENV: &str = "STATE_COMPRESSION";
+/// Compression level for zstd, from 1 to 22.
+pub const STATE_COMPRESSION_LEVEL_ENV: &str = "STATE_COMPRESSION_LEVEL";
+
+/// The compression state files are written with. Parquet records the codec of every column
+/// chunk, so files written with any codec (including older, uncompressed ones) can be read
+/// back, and the codec can change between epochs.
+pub(crate) fn state_file_compression() -> Result<Compression> {
+    let codec = env::var(STATE_COMPRESSION_ENV).unwrap_or_else(|_| "zstd".to_string());
+    Ok(match codec.as_str() {
+        "zstd" => {
+            let level = match env::var(STATE_COMPRESSION_LEVEL_ENV) {
+                Ok(level) => ZstdLevel::try_new(level.parse().with_context(|| {
+                    format!(
+                        "invalid value '{}' for {}",
+                        level, STATE_COMPRESSION_LEVEL_ENV
+                    )
+                })?)?,
+                Err(_) => ZstdLevel::default(),
+            };
+            Compression::ZSTD(level)
+        }
+        "snappy" => Compression::SNAPPY,
+        "lz4" => Compression::LZ4_RAW,
+        "none" => Compression::UNCOMPRESSED,
+        _ => bail!(
+            "unknown state compression '{}'; expected zstd, snappy, lz4, or none",
+            codec
+        ),
+    })
+}
+
+/// Uncompressed size past which a subtask's data for a table is split into another file.
+pub const STATE_FILE_TARGET_SIZE_ENV: &str = "STATE_FILE_TARGET_SIZE_BYTES";
+/// Size of each part of a multipart state file upload; at least 5 MiB.
+pub const STATE_UPLOAD_PART_SIZE_ENV: &str = "STATE_UPLOAD_PART_SIZE_BYTES";
+
+pub(crate) fn state_file_target_size() -> usize {
+    u32_config(STATE_FILE_TARGET_SIZE_ENV, 256 * 1024 * 1024).max(1) as usize
+}
+
+pub(crate) fn upload_part_size() -> usize {
+    u32_config(STATE_UPLOAD_PART_SIZE_ENV, 16 * 1024 * 1024).max(5 * 1024 * 1024) as usize
+}
+
+/// Reports the size of a state file as written and before compression.
+pub(crate) fn record_checkpoint_bytes(
+    task_info: &TaskInfo,
+    table: &str,
+    compressed: u64,
+    uncompressed: u64,
+) {
+    let task_index = task_info.task_index.to_string();
+    for (size, bytes) in [("compressed", compressed), ("uncompressed", uncompressed)] {
+        TABLE_CHECKPOINT_BYTES_COUNTER
+            .with_label_values(&[&task_info.operator_id, &task_index, table, size])
+            .inc_by(bytes);
+    }
+}
+
+/// The size of a state file as stored, read from the store.
+pub(crate) async fn state_file_size(storage: &StorageProvider, path: &str) -> Result<u64> {
+    let store = storage.get_backing_store();
+    let location = path.to_string().into();
+    let meta = storage
+        .retry_policy()
+        .run("head", path, || store.head(&location))
+        .await
+        .with_context(|| format!("failed to get the size of {}", path))?;
+    Ok(meta.size as u64)
+}
+
+/// The files each of an operator's tables retains in a checkpoint, ordered by table name.
+pub(crate) fn operator_retained_files(
+    metadata: &OperatorCheckpointMetadata,
+) -> Result<Vec<(String, Vec<RetainedFile>)>> {
+    let epoch = metadata
+        .operator_metadata
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("missing operator metadata"))?
+        .epoch;
+    let mut tables = vec![];
+    for (table_name, table_metadata) in &metadata.table_checkpoint_metadata {
+        let table_config = metadata
+            .table_configs
+            .get(table_name)
+            .ok_or_else(|| anyhow::anyhow!("missing table config for table {}", table_name))?
+            .clone();
+        let files = match table_config.table_type() {
+            grpc::TableEnum::MissingTableType => bail!("missing table type"),
+            grpc::TableEnum::GlobalKeyValue | grpc::TableEnum::KeyTimeMap => {
+                GlobalKeyedTable::retained_files(table_config, table_metadata.clone(), epoch)?
+            }
+            grpc::TableEnum::ExpiringKeyedTimeTable => {
+                ExpiringTimeKeyTable::retained_files(table_config, table_metadata.clone(), epoch)?
+            }
+        };
+        tables.push((table_name.clone(), files));
+    }
+    tables.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(tables)
+}
+
+/// The files the subtask of `metadata` wrote in `epoch`, with the tables they belong to. Files
+/// the subtask's incremental tables carried over from earlier epochs are left out.
+pub(crate) fn subtask_written_files(
+    metadata: &SubtaskCheckpointMetadata,
+    epoch: u32,
+) -> Result<Vec<(String, RetainedFile)>> {
+    let mut written = vec![];
+    for (table_name, table_metadata) in &metadata.table_metadata {
+        let table_config = metadata
+            .table_configs
+            .get(table_name)
+            .ok_or_else(|| anyhow::anyhow!("missing table config for table {}", table_name))?
+            .clone();
+        let subtask_metadata = HashMap::from([(metadata.subtask_index, table_metadata.clone())]);
+        let files = match table_config.table_type() {
+            grpc::TableEnum::MissingTableType => bail!("missing table type"),
+            grpc::TableEnum::GlobalKeyValue | grpc::TableEnum::KeyTimeMap => {
+                GlobalKeyedTable::merge_checkpoint_metadata(table_config.clone(), subtask_metadata)?
+                    .map(|table| GlobalKeyedTable::retained_files(table_config, table, epoch))
+            }
+            grpc::TableEnum::ExpiringKeyedTimeTable => {
+                ExpiringTimeKeyTable::merge_checkpoint_metadata(
+                    table_config.clone(),
+                    subtask_metadata,
+                )?
+                .map(|table| ExpiringTimeKeyTable::retained_files(table_config, table, epoch))
+            }
+        }
+        .transpose()?
+        .unwrap_or_default();
+        written.extend(
+            files
+                .into_iter()
+                .filter(|file| file.epoch == epoch)
+                .map(|file| (table_name.clone(), file)),
+        );
+    }
+    Ok(written)
+}
+
+pub(crate) fn retained_files_usage(
+    table_name: String,
+    files: &[RetainedFile],
+    bytes: u64,
+) -> TableStorageUsage {
+    TableStorageUsage {
+        table_name,
+        files: files.len() as u64,
+        bytes,
+        oldest_epoch: files.iter().map(|file| file.epoch).min(),
+    }
+}
+
+#[cfg(test)]
+tokio::task_local! {
+    // the store used by the backend within a test, in place of the checkpoint URL
+    static TEST_STORAGE: arroyo_storage::StorageProviderRef;
+}
+
 async fn get_storage_provider() -> anyhow::Result<StorageProvider> {
+    #[cfg(test)]
+    if let Ok(storage) = TEST_STORAGE.try_with(|storage| storage.clone()) {
+        return Ok((*storage).clone());
+    }
+
     // TODO: this should be encoded in the config so that the controller doesn't need
     // to be synchronized with the workers
     let storage_url =
@@ -38,16 +222,130 @@ async fn get_storage_provider() -> anyhow::Result<StorageProvider> {
 
 pub struct ParquetBackend;
 
-fn base_path(job_id: &str, epoch: u32) -> String {
-    format!("{}/checkpoints/checkpoint-{:0>7}", job_id, epoch)
+/// How many epochs the controller waits between compactions of a job's state. Each
+/// compaction rewrites the files of tables that have accumulated enough of them, so a larger
+/// interval trades checkpoint size and restore time for less rewriting.
+pub const COMPACTION_INTERVAL_EPOCHS_ENV: &str = "COMPACTION_INTERVAL_EPOCHS";
+
+fn checkpoints_path(job_id: &str) -> String {
+    format!("{}/checkpoints", encode_path_component(job_id))
+}
+
+pub(crate) fn base_path(job_id: &str, epoch: u32) -> String {
+    format!("{}/checkpoint-{:0>7}", checkpoints_path(job_id), epoch)
 }
 
-fn metadata_path(path: &str) -> String {
+/// Where a job's checkpoint for `epoch` was written before identifiers were escaped in
+/// paths, if that differs from [`base_path`]. Only ids with characters outside
+/// `[A-Za-z0-9_-]` are escaped, so for most jobs there's no separate legacy path.
+fn legacy_base_path(job_id: &str, epoch: u32) -> Option<String> {
+    (encode_path_component(job_id) != job_id)
+        .then(|| format!("{}/checkpoints/checkpoint-{:0>7}", job_id, epoch))
+}
+
+/// Like [`legacy_base_path`], for [`operator_path`].
+fn legacy_operator_path(job_id: &str, epoch: u32, operator: &str) -> Option<String> {
+    (encode_path_component(job_id) != job_id || encode_path_component(operator) != operator).then(
+        || {
+            format!(
+                "{}/checkpoints/checkpoint-{:0>7}/operator-{}",
+                job_id, epoch, operator
+            )
+        },
+    )
+}
+
+/// Reads the metadata object under `path`, or under `legacy_path` for checkpoints written
+/// before identifiers were escaped. Returns the path it was found at, with its contents.
+async fn get_metadata_if_present(
+    storage_client: &StorageProvider,
+    path: &str,
+    legacy_path: Option<String>,
+) -> Result<Option<(String, Bytes)>> {
+    for path in std::iter::once(path.to_string()).chain(legacy_path) {
+        let path = metadata_path(&path);
+        if let Some(data) = storage_client.get_if_present(&path).await? {
+            return Ok(Some((path, data)));
+        }
+    }
+    Ok(None)
+}
+
+fn savepoints_path(job_id: &str) -> String {
+    format!("{}/savepoints", encode_path_component(job_id))
+}
+
+fn savepoint_path(job_id: &str, name: &str) -> String {
+    format!(
+        "{}/{}",
+        savepoints_path(job_id),
+        encode_path_component(name)
+    )
+}
+
+pub(crate) fn metadata_path(path: &str) -> String {
     format!("{}/metadata", path)
 }
 
-fn operator_path(job_id: &str, epoch: u32, operator: &str) -> String {
-    format!("{}/operator-{}", base_path(job_id, epoch), operator)
+/// Loads the checkpoint metadata for a job id and epoch from a given store.
+pub(crate) async fn load_checkpoint_metadata_in(
+    storage_client: &StorageProvider,
+    job_id: &str,
+    epoch: u32,
+) -> Result<CheckpointMetadata> {
+    let path = base_path(job_id, epoch);
+    let (path, data) =
+        match get_metadata_if_present(storage_client, &path, legacy_base_path(job_id, epoch))
+            .await?
+        {
+            Some(found) => found,
+            None => {
+                // fails with the store's error for a missing object
+                let path = metadata_path(&path);
+                let data = storage_client.get(&path).await?;
+                (path, data)
+            }
+        };
+    decode_checkpoint_metadata(&decrypt(&path, data)?)
+}
+
+/// Loads the operator checkpoint metadata for a job id, operator id and epoch from a given
+/// store, if it was written.
+pub(crate) async fn load_operator_metadata_in(
+    storage_client: &StorageProvider,
+    job_id: &str,
+    operator_id: &str,
+    epoch: u32,
+) -> Result<Option<OperatorCheckpointMetadata>> {
+    get_metadata_if_present(
+        storage_client,
+        &operator_path(job_id, epoch, operator_id),
+        legacy_operator_path(job_id, epoch, operator_id),
+    )
+    .await?
+    .map(|(path, data)| decode_operator_metadata(&decrypt(&path, data)?))
+    .transpose()
+}
+
+/// Writes a metadata object. Puts are atomic on every store we support: object stores only
+/// make an object visible once it's fully uploaded, and the local filesystem writes to a
+/// temporary file that's renamed into place. So a write interrupted by a crash leaves the
+/// previous object or none, never a truncated one.
+async fn write_metadata(path: &str, data: Vec<u8>) -> Result<()> {
+    let storage_client = get_storage_provider().await?;
+    timed_write(WriteOperation::Metadata, async {
+        storage_client.put(path, encrypt(data)?).await?;
+        Ok(())
+    })
+    .await
+}
+
+pub(crate) fn operator_path(job_id: &str, epoch: u32, operator: &str) -> String {
+    format!(
+        "{}/operator-{}",
+        base_path(job_id, epoch),
+        encode_path_component(operator)
+    )
 }
 
 #[async_trait::async_trait]
@@ -57,12 +355,84 @@ impl BackingStore for ParquetBackend {
     }
 
     async fn load_checkpoint_metadata(job_id: &str, epoch: u32) -> Result<CheckpointMetadata> {
+        load_checkpoint_metadata_in(&get_storage_provider().await?, job_id, epoch).await
+    }
+
+    async fn load_checkpoint_metadata_if_present(
+        job_id: &str,
+        epoch: u32,
+    ) -> Result<Option<CheckpointMetadata>> {
         let storage_client = get_storage_provider().await?;
-        let data = storage_client
-            .get(&metadata_path(&base_path(job_id, epoch)))
-            .await?;
-        let metadata = CheckpointMetadata::decode(&data[..])?;
-        Ok(metadata)
+        get_metadata_if_present(
+            &storage_client,
+            &base_path(job_id, epoch),
+            legacy_base_path(job_id, epoch),
+        )
+        .await?
+        .map(|(path, data)| decode_checkpoint_metadata(&decrypt(&path, data)?))
+        .transpose()
+    }
+
+    async fn list_checkpoint_epochs(job_id: &str) -> Result<Vec<u32>> {
+        let storage_client = get_storage_provider().await?;
+        let mut prefixes = vec![checkpoints_path(job_id)];
+        // checkpoints written before identifiers were escaped
+        if encode_path_component(job_id) != job_id {
+            prefixes.push(format!("{}/checkpoints", job_id));
+        }
+        let mut epochs = BTreeSet::new();
+        for checkpoints_prefix in prefixes {
+            let prefix = format!("{}/", checkpoints_prefix);
+            epochs.extend(
+                storage_client
+                    .list_prefix(checkpoints_prefix)
+                    .await?
+                    .iter()
+                    .filter_map(|path| {
+                        // only the checkpoint's own metadata, not that of its operators
+                        path.strip_prefix(&prefix)?
+                            .strip_prefix("checkpoint-")?
+                            .strip_suffix("/metadata")?
+                            .parse::<u32>()
+                            .ok()
+                    }),
+            );
+        }
+        Ok(epochs.into_iter().collect())
+    }
+
+    async fn load_operator_remapping(job_id: &str, epoch: u32) -> Result<Vec<OperatorRemapping>> {
+        let storage_client = get_storage_provider().await?;
+        let Some(data) = storage_client
+            .get_if_present(&format!(
+                "{}/{}",
+                base_path(job_id, epoch),
+                OPERATOR_REMAPPING_FILE
+            ))
+            .await?
+        else {
+            return Ok(vec![]);
+        };
+        parse_operator_remapping(std::str::from_utf8(&data)?)
+    }
+
+    async fn write_operator_remapping(
+        job_id: &str,
+        epoch: u32,
+        remappings: Vec<OperatorRemapping>,
+    ) -> Result<()> {
+        // written as text, like a remapping file supplied by hand
+        let storage_client = get_storage_provider().await?;
+        timed_write(WriteOperation::Metadata, async {
+            storage_client
+                .put(
+                    format!("{}/{}", base_path(job_id, epoch), OPERATOR_REMAPPING_FILE),
+                    format_operator_remapping(&remappings).into_bytes(),
+                )
+                .await?;
+            Ok(())
+        })
+        .await
     }
 
     async fn load_operator_metadata(
@@ -70,18 +440,13 @@ impl BackingStore for ParquetBackend {
         operator_id: &str,
         epoch: u32,
     ) -> Result<Option<OperatorCheckpointMetadata>> {
-        let storage_client = get_storage_provider().await?;
-        storage_client
-            .get_if_present(&metadata_path(&operator_path(job_id, epoch, operator_id)))
-            .await?
-            .map(|data| Ok(OperatorCheckpointMetadata::decode(&data[..])?))
-            .transpose()
+        load_operator_metadata_in(&get_storage_provider().await?, job_id, operator_id, epoch).await
     }
 
     async fn write_operator_checkpoint_metadata(
-        metadata: OperatorCheckpointMetadata,
+        mut metadata: OperatorCheckpointMetadata,
     ) -> Result<()> {
-        let storage_client = get_storage_provider().await?;
+        metadata.format_version = METADATA_FORMAT_VERSION;
         let operator_metadata = metadata
             .operator_metadata
             .as_ref()
@@ -91,17 +456,73 @@ impl BackingStore for ParquetBackend {
             operator_metadata.epoch,
             &operator_metadata.operator_id,
         ));
-        storage_client.put(&path, metadata.encode_to_vec()).await?;
-        // TODO: propagate error
-        Ok(())
+        write_metadata(&path, metadata.encode_to_vec()).await
     }
 
-    async fn write_checkpoint_metadata(metadata: CheckpointMetadata) -> Result<()> {
-        debug!("writing checkpoint {:?}", metadata);
+    async fn copy_operator_checkpoint(
+        metadata: OperatorCheckpointMetadata,
+        target_job_id: &str,
+    ) -> Result<OperatorCheckpointMetadata> {
+        let epoch = metadata
+            .operator_metadata
+            .as_ref()
+            .ok_or_else(|| anyhow!("missing operator metadata"))?
+            .epoch;
+        // the copies belong to the target's checkpoint, so they're cleaned up along with it
+        // once no later epoch carries them over
+        let prefix = format!("{}/forked", base_path(target_job_id, epoch));
+        let rename = |file: &str| format!("{}/{}", prefix, file);
         let storage_client = get_storage_provider().await?;
+        for file in referenced_files(&metadata)? {
+            // files are copied as stored, as encryption doesn't depend on their paths
+            let data = fetch_state_file(&storage_client, &file).await?;
+            timed_write(WriteOperation::Copy, async {
+                Ok(storage_client.put(rename(&file), data.to_vec()).await?)
+            })
+            .await?;
+        }
+        rename_operator_files(metadata, target_job_id, &rename)
+    }
+
+    async fn write_checkpoint_metadata(mut metadata: CheckpointMetadata) -> Result<()> {
+        metadata.format_version = METADATA_FORMAT_VERSION;
+        debug!("writing checkpoint {:?}", metadata);
         let path = metadata_path(&base_path(&metadata.job_id, metadata.epoch));
-        storage_client.put(&path, metadata.encode_to_vec()).await?;
-        Ok(())
+        write_metadata(&path, metadata.encode_to_vec()).await
+    }
+
+    async fn write_savepoint_metadata(mut metadata: CheckpointMetadata) -> Result<()> {
+        metadata.format_version = METADATA_FORMAT_VERSION;
+        let name = metadata
+            .savepoint
+            .as_deref()
+            .ok_or_else(|| anyhow!("checkpoint {} isn't a savepoint", metadata.epoch))?;
+        let path = metadata_path(&savepoint_path(&metadata.job_id, name));
+        write_metadata(&path, metadata.encode_to_vec()).await
+    }
+
+    async fn load_savepoint_metadata(job_id: &str, name: &str) -> Result<CheckpointMetadata> {
+        let storage_client = get_storage_provider().await?;
+        let path = metadata_path(&savepoint_path(job_id, name));
+        let data = storage_client
+            .get_if_present(&path)
+            .await?
+            .ok_or_else(|| anyhow!("job {} has no savepoint named {}", job_id, name))?;
+        decode_checkpoint_metadata(&decrypt(&path, data)?)
+    }
+
+    async fn list_savepoints(job_id: &str) -> Result<Vec<CheckpointMetadata>> {
+        let storage_client = get_storage_provider().await?;
+        let mut savepoints = vec![];
+        for path in storage_client.list_prefix(savepoints_path(job_id)).await? {
+            if !path.ends_with("/metadata") {
+                continue;
+            }
+            let data = decrypt(&path, storage_client.get(&path).await?)?;
+            savepoints.push(decode_checkpoint_metadata(&data)?);
+        }
+        savepoints.sort_by_key(|savepoint| savepoint.epoch);
+        Ok(savepoints)
     }
 
     async fn prepare_checkpoint_load(_metadata: &CheckpointMetadata) -> anyhow::Result<()> {
@@ -118,56 +539,66 @@ impl BackingStore for ParquetBackend {
             min_epoch,
             job_id = metadata.job_id
         );
+        Self::cleanup_before(&metadata, old_min_epoch, min_epoch, false).await?;
+        metadata.min_epoch = min_epoch;
+        Self::write_checkpoint_metadata(metadata).await?;
+        Ok(())
+    }
 
-        let mut futures: FuturesUnordered<_> = metadata
-            .operator_ids
-            .iter()
-            .map(|operator_id| {
-                Self::cleanup_operator(
-                    metadata.job_id.clone(),
-                    operator_id.clone(),
-                    old_min_epoch,
-                    min_epoch,
-                )
-            })
-            .collect();
-
-        let storage_client = Mutex::new(get_storage_provider().await?);
-
-        // wait for all of the futures to complete
-        while let Some(result) = futures.next().await {
-            let operator_id = result?;
-
-            for epoch_to_remove in old_min_epoch..min_epoch {
-                let path = metadata_path(&operator_path(
-                    &metadata.job_id,
-                    epoch_to_remove,
-                    &operator_id,
-                ));
-                storage_client.lock().await.delete_if_present(path).await?;
-            }
-            debug!(
-                message = "Finished cleaning operator",
-                job_id = metadata.job_id,
+    async fn table_storage_usage(
+        job_id: &str,
+        epoch: u32,
+    ) -> Result<HashMap<String, Vec<TableStorageUsage>>> {
+        let metadata = Self::load_checkpoint_metadata(job_id, epoch).await?;
+        let mut usage = HashMap::new();
+        for operator_id in metadata.operator_ids {
+            let Some(operator_metadata) =
+                Self::load_operator_metadata(job_id, &operator_id, epoch).await?
+            else {
+                continue;
+            };
+            usage.insert(
                 operator_id,
-                min_epoch
+                Self::operator_storage_usage(&operator_metadata).await?,
             );
         }
-
-        for epoch_to_remove in old_min_epoch..min_epoch {
-            storage_client
-                .lock()
-                .await
-                .delete_if_present(metadata_path(&base_path(&metadata.job_id, epoch_to_remove)))
-                .await?;
-        }
-        metadata.min_epoch = min_epoch;
-        Self::write_checkpoint_metadata(metadata).await?;
-        Ok(())
+        Ok(usage)
     }
 }
 
 impl ParquetBackend {
+    /// Checks that a job's checkpoint could be restored: that every stateful operator it
+    /// lists has metadata, and that every file they reference exists, has its recorded size
+    /// and decodes. Reads up to `CHECKPOINT_VERIFY_PARALLELISM` objects at once and writes
+    /// nothing. Problems are collected into the result rather than returned as errors.
+    pub async fn verify_checkpoint(job_id: &str, epoch: u32) -> Result<CheckpointVerification> {
+        let storage_client = get_storage_provider().await?;
+        Ok(verify_checkpoint_in(&storage_client, job_id, epoch, verify_parallelism()).await)
+    }
+
+    /// The storage used by each of an operator's tables in a checkpoint. Sizes come from the
+    /// checkpoint metadata, or from the store for files written before sizes were recorded.
+    pub async fn operator_storage_usage(
+        metadata: &OperatorCheckpointMetadata,
+    ) -> Result<Vec<TableStorageUsage>> {
+        let storage_client = get_storage_provider().await?;
+        let mut usage = vec![];
+        for (table_name, files) in operator_retained_files(metadata)? {
+            let mut bytes = 0;
+            for RetainedFile {
+                path, bytes: size, ..
+            } in &files
+            {
+                bytes += match size {
+                    Some(size) => *size,
+                    None => state_file_size(&storage_client, path).await?,
+                };
+            }
+            usage.push(retained_files_usage(table_name, &files, bytes));
+        }
+        Ok(usage)
+    }
+
     /// Called after a checkpoint is committed
     pub async fn compact_operator(
         job_id: String,
@@ -178,10 +609,12 @@ impl ParquetBackend {
             .unwrap_or_else(|_| "4".to_string())
             .parse()?;
 
-        let operator_checkpoint_metadata =
-            Self::load_operator_metadata(&job_id, &operator_id, epoch)
-                .await?
-                .expect("expect operator metadata to still be present");
+        let Some(operator_checkpoint_metadata) =
+            Self::load_operator_metadata(&job_id, &operator_id, epoch).await?
+        else {
+            // stateless operators write no metadata of their own, and have nothing to compact
+            return Ok(HashMap::new());
+        };
         let storage_provider = Arc::new(get_storage_provider().await?);
         let compaction_config = CompactionConfig {
             storage_provider,
@@ -200,7 +633,7 @@ impl ParquetBackend {
                 .clone();
             if let Some(compacted_metadata) = match table_metadata.table_type() {
                 grpc::TableEnum::MissingTableType => bail!("should have table type"),
-                grpc::TableEnum::GlobalKeyValue => {
+                grpc::TableEnum::GlobalKeyValue | grpc::TableEnum::KeyTimeMap => {
                     GlobalKeyedTable::compact_data(
                         table_config,
                         &compaction_config,
@@ -225,82 +658,212 @@ impl ParquetBackend {
         Ok(result)
     }
 
-    /// Delete files no longer referenced by the new min epoch
-    pub async fn cleanup_operator(
-        job_id: String,
-        operator_id: String,
+    /// Deletes the table files and metadata of a job's epochs from `old_min_epoch` up to
+    /// `min_epoch`, returning what was deleted. With `dry_run`, nothing is deleted and the
+    /// result is what would have been.
+    ///
+    /// `metadata` is the job's latest completed checkpoint. A file is only deleted if no
+    /// epoch from `min_epoch` through `metadata.epoch` references it, so files carried over
+    /// between epochs survive, as does everything a restore of any retained epoch needs.
+    /// Files of a checkpoint that's still in progress aren't referenced by any earlier epoch
+    /// that isn't also retained, so cleanup can run alongside it. Savepoints are never cleaned
+    /// up: their epochs' metadata is kept, and so is every file they reference.
+    pub async fn cleanup_before(
+        metadata: &CheckpointMetadata,
         old_min_epoch: u32,
-        new_min_epoch: u32,
-    ) -> Result<String> {
-        let operator_metadata = Self::load_operator_metadata(&job_id, &operator_id, new_min_epoch)
+        min_epoch: u32,
+        dry_run: bool,
+    ) -> Result<CleanupPlan> {
+        if min_epoch > metadata.epoch {
+            bail!(
+                "can't clean up job {} below epoch {}, after its latest checkpoint {}",
+                metadata.job_id,
+                min_epoch,
+                metadata.epoch
+            );
+        }
+        let job_id = &metadata.job_id;
+        // savepoints below the min epoch are retained along with everything they reference
+        let savepoint_epochs: BTreeSet<u32> = Self::list_savepoints(job_id)
             .await?
-            .expect("expect new_min_epoch metadata to still be present");
-        let paths_to_keep: HashSet<String> = operator_metadata
-            .table_checkpoint_metadata
+            .into_iter()
+            .map(|savepoint| savepoint.epoch)
+            .filter(|epoch| *epoch < min_epoch)
+            .collect();
+        let mut plan = CleanupPlan::default();
+        let mut futures: FuturesUnordered<_> = metadata
+            .operator_ids
             .iter()
-            .flat_map(|(table_name, metadata)| {
-                let table_config = operator_metadata
-                    .table_configs
-                    .get(table_name)
-                    .unwrap()
-                    .clone();
-                let files = match table_config.table_type() {
-                    grpc::TableEnum::MissingTableType => todo!("should handle error"),
-                    grpc::TableEnum::GlobalKeyValue => {
-                        GlobalKeyedTable::files_to_keep(table_config, metadata.clone()).unwrap()
-                    }
-                    grpc::TableEnum::ExpiringKeyedTimeTable => {
-                        ExpiringTimeKeyTable::files_to_keep(table_config, metadata.clone()).unwrap()
-                    }
-                };
-                files
+            .map(|operator_id| {
+                Self::operator_files_to_delete(
+                    job_id,
+                    operator_id,
+                    old_min_epoch,
+                    min_epoch,
+                    metadata.epoch,
+                    &savepoint_epochs,
+                )
             })
             .collect();
-
-        let mut deleted_paths = HashSet::new();
+        while let Some(files) = futures.next().await {
+            plan.files.extend(files?);
+        }
+        let cleaned_epochs =
+            || (old_min_epoch..min_epoch).filter(|epoch| !savepoint_epochs.contains(epoch));
         let storage_client = get_storage_provider().await?;
+        // metadata written before identifiers were escaped is cleaned up where it was written
+        let mut legacy_metadata = vec![];
+        for operator_id in &metadata.operator_ids {
+            for epoch in cleaned_epochs() {
+                plan.metadata
+                    .push(metadata_path(&operator_path(job_id, epoch, operator_id)));
+                legacy_metadata.extend(legacy_operator_path(job_id, epoch, operator_id));
+            }
+        }
+        for epoch in cleaned_epochs() {
+            plan.metadata.push(metadata_path(&base_path(job_id, epoch)));
+            legacy_metadata.extend(legacy_base_path(job_id, epoch));
+        }
+        for path in legacy_metadata {
+            let path = metadata_path(&path);
+            if storage_client.exists(path.as_str()).await? {
+                plan.metadata.push(path);
+            }
+        }
+        if dry_run {
+            return Ok(plan);
+        }
+
+        // data files go first, so that an interrupted cleanup leaves metadata that finds the
+        // rest when it's run again
+        for path in plan.files.iter().chain(&plan.metadata) {
+            storage_client.delete_if_present(path).await?;
+        }
+        debug!(
+            message = "Finished cleaning",
+            job_id,
+            min_epoch,
+            files = plan.files.len()
+        );
+        Ok(plan)
+    }
 
-        for epoch_to_remove in old_min_epoch..new_min_epoch {
-            let Some(metadata) =
-                Self::load_operator_metadata(&job_id, &operator_id, epoch_to_remove).await?
+    /// The files referenced by an operator's epochs from `old_min_epoch` up to `min_epoch`
+    /// that aren't referenced by any epoch from `min_epoch` through `latest_epoch`, nor by
+    /// any of the savepoint epochs.
+    async fn operator_files_to_delete(
+        job_id: &str,
+        operator_id: &str,
+        old_min_epoch: u32,
+        min_epoch: u32,
+        latest_epoch: u32,
+        savepoint_epochs: &BTreeSet<u32>,
+    ) -> Result<Vec<String>> {
+        let mut retained = HashSet::new();
+        let mut found_retained = false;
+        for epoch in (min_epoch..=latest_epoch).chain(savepoint_epochs.iter().copied()) {
+            if let Some(metadata) = Self::load_operator_metadata(job_id, operator_id, epoch).await?
+            {
+                found_retained = true;
+                retained.extend(referenced_files(&metadata)?);
+            }
+        }
+        if !found_retained {
+            // operators that were remapped after min_epoch have no metadata under their new
+            // id until the restore, so what they still need can't be known
+            debug!(
+                message = "No retained operator metadata, skipping cleanup",
+                operator_id, min_epoch
+            );
+            return Ok(vec![]);
+        }
+
+        let mut to_delete = vec![];
+        for epoch in (old_min_epoch..min_epoch).filter(|epoch| !savepoint_epochs.contains(epoch)) {
+            let Some(metadata) = Self::load_operator_metadata(job_id, operator_id, epoch).await?
             else {
                 continue;
             };
-
-            // delete any files that are not in the new min epoch
-            for file in metadata
-                .table_checkpoint_metadata
-                .iter()
-                // TODO: factor this out
-                .flat_map(|(table_name, metadata)| {
-                    let table_config = operator_metadata
-                        .table_configs
-                        .get(table_name)
-                        .ok_or_else(|| anyhow::anyhow!("missing table config for operator {}, table {}, metadata is {:?}, operator_metadata is {:?}",
-                         operator_id, table_name, metadata, operator_metadata)).unwrap()
-                        .clone();
-                    let files = match table_config.table_type() {
-                        grpc::TableEnum::MissingTableType => todo!("should handle error"),
-                        grpc::TableEnum::GlobalKeyValue => {
-                            GlobalKeyedTable::files_to_keep(table_config, metadata.clone()).unwrap()
-                        }
-                        grpc::TableEnum::ExpiringKeyedTimeTable => {
-                            ExpiringTimeKeyTable::files_to_keep(table_config, metadata.clone())
-                                .unwrap()
-                        }
-                    };
-                    files
-                })
-            {
-                if !paths_to_keep.contains(&file) && !deleted_paths.contains(&file) {
-                    deleted_paths.insert(file.clone());
-                    storage_client.delete_if_present(file).await?;
+            for file in referenced_files(&metadata)? {
+                if retained.insert(file.clone()) {
+                    to_delete.push(file);
                 }
             }
         }
+        Ok(to_delete)
+    }
+}
 
-        Ok(operator_id)
+/// What cleaning up a job's checkpoints below a min epoch deletes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CleanupPlan {
+    /// Table data files referenced only by epochs below the min epoch.
+    pub files: Vec<String>,
+    /// Operator and checkpoint metadata of epochs below the min epoch.
+    pub metadata: Vec<String>,
+}
+
+/// Every file that a restore of the operator's checkpoint would need.
+fn referenced_files(operator_metadata: &OperatorCheckpointMetadata) -> Result<HashSet<String>> {
+    let mut files = HashSet::new();
+    for (table_name, metadata) in &operator_metadata.table_checkpoint_metadata {
+        let table_config = operator_metadata
+            .table_configs
+            .get(table_name)
+            .ok_or_else(|| anyhow!("missing table config for table {}", table_name))?
+            .clone();
+        files.extend(match table_config.table_type() {
+            grpc::TableEnum::MissingTableType => {
+                bail!("missing table type for table {}", table_name)
+            }
+            grpc::TableEnum::GlobalKeyValue | grpc::TableEnum::KeyTimeMap => {
+                GlobalKeyedTable::files_to_keep(table_config, metadata.clone())?
+            }
+            grpc::TableEnum::ExpiringKeyedTimeTable => {
+                ExpiringTimeKeyTable::files_to_keep(table_config, metadata.clone())?
+            }
+        });
+    }
+    for subtask in operator_metadata.in_flight.values() {
+        files.extend(subtask.files.iter().map(|file| file.file.clone()));
     }
+    Ok(files)
+}
+
+/// An operator's checkpoint metadata moved to another job, with every file it references
+/// renamed by `rename`.
+pub(crate) fn rename_operator_files(
+    mut operator_metadata: OperatorCheckpointMetadata,
+    target_job_id: &str,
+    rename: &dyn Fn(&str) -> String,
+) -> Result<OperatorCheckpointMetadata> {
+    for (table_name, metadata) in &mut operator_metadata.table_checkpoint_metadata {
+        let table_config = operator_metadata
+            .table_configs
+            .get(table_name)
+            .ok_or_else(|| anyhow!("missing table config for table {}", table_name))?
+            .clone();
+        *metadata = match table_config.table_type() {
+            grpc::TableEnum::MissingTableType => {
+                bail!("missing table type for table {}", table_name)
+            }
+            grpc::TableEnum::GlobalKeyValue | grpc::TableEnum::KeyTimeMap => {
+                GlobalKeyedTable::rename_files(table_config, metadata.clone(), rename)?
+            }
+            grpc::TableEnum::ExpiringKeyedTimeTable => {
+                ExpiringTimeKeyTable::rename_files(table_config, metadata.clone(), rename)?
+            }
+        };
+    }
+    for subtask in operator_metadata.in_flight.values_mut() {
+        for file in &mut subtask.files {
+            file.file = rename(&file.file);
+        }
+    }
+    if let Some(metadata) = &mut operator_metadata.operator_metadata {
+        metadata.job_id = target_job_id.to_string();
+    }
+    Ok(operator_metadata)
 }
 
 #[derive(Debug)]
@@ -334,3 +897,398 @@ pub fn get_storage_env_vars() -> HashMap<String, String> {
         .filter_map(|&var| env::var(var).ok().map(|v| (var.to_string(), v)))
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::global_table_config;
+    use crate::tables::{state_file_part_path, StateFileLayout};
+    use crate::test_storage::TempStorage;
+
+    fn checkpoint(job_id: &str, epoch: u32, min_epoch: u32) -> CheckpointMetadata {
+        CheckpointMetadata {
+            job_id: job_id.to_string(),
+            epoch,
+            min_epoch,
+            operator_ids: vec!["op".to_string()],
+            format_version: METADATA_FORMAT_VERSION,
+            ..Default::default()
+        }
+    }
+
+    /// The file written for table `t` at `epoch`. The first epoch was written under a
+    /// different layout than later ones.
+    fn table_file(job_id: &str, epoch: u32) -> String {
+        let layout = if epoch == 1 {
+            StateFileLayout::new(
+                "state/{job_id}/{operator_id}/{table}/{epoch}-{subtask}",
+                Some("cold".to_string()),
+            )
+            .unwrap()
+        } else {
+            StateFileLayout::default()
+        };
+        layout.path(job_id, "op", "t", 0, epoch, false)
+    }
+
+    /// The files of table `t` in the checkpoint for `epoch`. The first epoch's file was
+    /// written in two parts, and is still referenced by the third.
+    fn table_files(job_id: &str, epoch: u32) -> Vec<String> {
+        match epoch {
+            1 => vec![
+                table_file(job_id, 1),
+                state_file_part_path(&table_file(job_id, 1), 1),
+            ],
+            3 => vec![table_file(job_id, 1), table_file(job_id, 3)],
+            epoch => vec![table_file(job_id, epoch)],
+        }
+    }
+
+    fn operator(job_id: &str, epoch: u32) -> OperatorCheckpointMetadata {
+        let mut table_configs = global_table_config("t", "test");
+        if epoch == 1 {
+            let config = table_configs.get_mut("t").unwrap();
+            config.path_prefix = Some("cold".to_string());
+        }
+        OperatorCheckpointMetadata {
+            operator_metadata: Some(grpc::OperatorMetadata {
+                job_id: job_id.to_string(),
+                operator_id: "op".to_string(),
+                epoch,
+                parallelism: 1,
+                ..Default::default()
+            }),
+            backend: ParquetBackend::name().to_string(),
+            table_configs,
+            format_version: METADATA_FORMAT_VERSION,
+            table_checkpoint_metadata: HashMap::from([(
+                "t".to_string(),
+                TableCheckpointMetadata {
+                    table_type: grpc::TableEnum::GlobalKeyValue.into(),
+                    data: grpc::GlobalKeyedTableTaskCheckpointMetadata {
+                        files: table_files(job_id, epoch),
+                        commit_data_by_subtask: HashMap::new(),
+                        // as written before sizes were recorded
+                        file_sizes: HashMap::new(),
+                        ..Default::default()
+                    }
+                    .encode_to_vec(),
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_metadata_on_local_filesystem() {
+        let temp_storage = TempStorage::new("parquet-tests").await;
+        TEST_STORAGE
+            .scope(
+                temp_storage.provider(),
+                check_checkpoint_metadata(&temp_storage.provider(), temp_storage.root()),
+            )
+            .await;
+    }
+
+    /// Writes, loads and cleans up checkpoints in `storage`, whose files are under `root`.
+    async fn check_checkpoint_metadata(storage: &StorageProvider, root: &std::path::Path) {
+        let job_id = "job";
+
+        for epoch in 1..=3 {
+            for file in table_files(job_id, epoch) {
+                if !storage.exists(file.as_str()).await.unwrap() {
+                    storage
+                        .put(file, vec![0; 10 * epoch as usize])
+                        .await
+                        .unwrap();
+                }
+            }
+            ParquetBackend::write_operator_checkpoint_metadata(operator(job_id, epoch))
+                .await
+                .unwrap();
+            ParquetBackend::write_checkpoint_metadata(checkpoint(job_id, epoch, 1))
+                .await
+                .unwrap();
+        }
+
+        let restored = ParquetBackend::load_checkpoint_metadata(job_id, 2)
+            .await
+            .unwrap();
+        assert_eq!(restored, checkpoint(job_id, 2, 1));
+        assert_eq!(
+            ParquetBackend::load_operator_metadata(job_id, "op", 2)
+                .await
+                .unwrap(),
+            Some(operator(job_id, 2))
+        );
+        assert_eq!(
+            ParquetBackend::load_checkpoint_metadata_if_present(job_id, 2)
+                .await
+                .unwrap(),
+            Some(checkpoint(job_id, 2, 1))
+        );
+        assert_eq!(
+            ParquetBackend::load_checkpoint_metadata_if_present(job_id, 4)
+                .await
+                .unwrap(),
+            None
+        );
+
+        // writes go to a temporary file that's renamed into place, so nothing else is left
+        // next to the metadata
+        let mut entries = std::fs::read_dir(root.join(base_path(job_id, 2)))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(entries, vec!["metadata", "operator-op"]);
+
+        // the metadata has no sizes, so they're read from the store
+        assert_eq!(
+            ParquetBackend::table_storage_usage(job_id, 2)
+                .await
+                .unwrap(),
+            HashMap::from([(
+                "op".to_string(),
+                vec![TableStorageUsage {
+                    table_name: "t".to_string(),
+                    files: 1,
+                    bytes: 20,
+                    oldest_epoch: Some(2),
+                }]
+            )])
+        );
+
+        // the latest checkpoint can't be cleaned up
+        assert!(
+            ParquetBackend::cleanup_before(&checkpoint(job_id, 3, 1), 1, 4, true)
+                .await
+                .is_err()
+        );
+
+        // a dry run reports what would be deleted without deleting it; the first epoch's
+        // main file is still referenced by the third
+        let part_file = state_file_part_path(&table_file(job_id, 1), 1);
+        let plan = ParquetBackend::cleanup_before(&checkpoint(job_id, 3, 1), 1, 2, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            plan,
+            CleanupPlan {
+                files: vec![part_file.clone()],
+                metadata: vec![
+                    metadata_path(&operator_path(job_id, 1, "op")),
+                    metadata_path(&base_path(job_id, 1)),
+                ],
+            }
+        );
+        assert!(storage.exists(part_file.as_str()).await.unwrap());
+        assert!(ParquetBackend::load_checkpoint_metadata(job_id, 1)
+            .await
+            .is_ok());
+
+        ParquetBackend::cleanup_checkpoint(checkpoint(job_id, 3, 1), 1, 2)
+            .await
+            .unwrap();
+        // files are found through the paths recorded in the metadata, whatever layout wrote
+        // them
+        assert!(!storage.exists(part_file.as_str()).await.unwrap());
+        assert!(storage.exists(table_file(job_id, 1)).await.unwrap());
+        assert!(storage.exists(table_file(job_id, 2)).await.unwrap());
+        assert!(ParquetBackend::load_checkpoint_metadata(job_id, 1)
+            .await
+            .is_err());
+        assert_eq!(
+            ParquetBackend::load_operator_metadata(job_id, "op", 1)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            ParquetBackend::load_checkpoint_metadata(job_id, 3)
+                .await
+                .unwrap()
+                .min_epoch,
+            2
+        );
+
+        // a savepoint of the second epoch survives cleaning up past it
+        let savepoint = CheckpointMetadata {
+            savepoint: Some("before upgrade".to_string()),
+            ..checkpoint(job_id, 2, 1)
+        };
+        ParquetBackend::write_savepoint_metadata(savepoint.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            ParquetBackend::list_savepoints(job_id).await.unwrap(),
+            vec![savepoint.clone()]
+        );
+        assert_eq!(
+            ParquetBackend::load_savepoint_metadata(job_id, "before upgrade")
+                .await
+                .unwrap(),
+            savepoint
+        );
+        assert!(ParquetBackend::load_savepoint_metadata(job_id, "missing")
+            .await
+            .is_err());
+
+        let plan = ParquetBackend::cleanup_before(&checkpoint(job_id, 3, 2), 2, 3, false)
+            .await
+            .unwrap();
+        assert_eq!(plan, CleanupPlan::default());
+        assert!(storage.exists(table_file(job_id, 2)).await.unwrap());
+        assert!(ParquetBackend::load_checkpoint_metadata(job_id, 2)
+            .await
+            .is_ok());
+        assert_eq!(
+            ParquetBackend::load_operator_metadata(job_id, "op", 2)
+                .await
+                .unwrap(),
+            Some(operator(job_id, 2))
+        );
+
+        check_escaped_identifiers(storage, root).await;
+        check_legacy_identifiers(storage).await;
+    }
+
+    /// Writes the table files and metadata of a job's checkpoint for `epoch`.
+    async fn write_checkpoint(storage: &StorageProvider, job_id: &str, epoch: u32) {
+        for file in table_files(job_id, epoch) {
+            if !storage.exists(file.as_str()).await.unwrap() {
+                storage.put(file, vec![0; 10]).await.unwrap();
+            }
+        }
+        ParquetBackend::write_operator_checkpoint_metadata(operator(job_id, epoch))
+            .await
+            .unwrap();
+        ParquetBackend::write_checkpoint_metadata(checkpoint(job_id, epoch, 1))
+            .await
+            .unwrap();
+    }
+
+    /// Checkpoints of a job whose id isn't safe to use in a path as is are written, listed,
+    /// restored and cleaned up under its escaped id.
+    async fn check_escaped_identifiers(storage: &StorageProvider, root: &std::path::Path) {
+        let job_id = "team/job ü";
+        for epoch in 1..=2 {
+            write_checkpoint(storage, job_id, epoch).await;
+        }
+        assert_eq!(
+            ParquetBackend::list_checkpoint_epochs(job_id)
+                .await
+                .unwrap(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            ParquetBackend::load_operator_metadata(job_id, "op", 1)
+                .await
+                .unwrap(),
+            Some(operator(job_id, 1))
+        );
+        // nothing is written outside of the escaped id's prefix
+        assert!(root.join(base_path(job_id, 1)).exists());
+        assert!(!root.join("team").exists());
+        assert!(!root.join("cold/state/team").exists());
+
+        ParquetBackend::cleanup_checkpoint(checkpoint(job_id, 2, 1), 1, 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            ParquetBackend::list_checkpoint_epochs(job_id)
+                .await
+                .unwrap(),
+            vec![2]
+        );
+        assert!(!storage
+            .exists(state_file_part_path(&table_file(job_id, 1), 1).as_str())
+            .await
+            .unwrap());
+        assert_eq!(
+            ParquetBackend::load_operator_metadata(job_id, "op", 1)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            ParquetBackend::load_checkpoint_metadata(job_id, 2)
+                .await
+                .unwrap(),
+            checkpoint(job_id, 2, 2)
+        );
+    }
+
+    /// Checkpoints written before identifiers were escaped are still found where they were
+    /// written, alongside the ones written since.
+    async fn check_legacy_identifiers(storage: &StorageProvider) {
+        let job_id = "legacy.job";
+        for file in table_files(job_id, 1) {
+            storage.put(file, vec![0; 10]).await.unwrap();
+        }
+        let legacy_base = legacy_base_path(job_id, 1).unwrap();
+        let legacy_operator = legacy_operator_path(job_id, 1, "op").unwrap();
+        assert_eq!(legacy_base, "legacy.job/checkpoints/checkpoint-0000001");
+        write_metadata(
+            &metadata_path(&legacy_operator),
+            operator(job_id, 1).encode_to_vec(),
+        )
+        .await
+        .unwrap();
+        write_metadata(
+            &metadata_path(&legacy_base),
+            checkpoint(job_id, 1, 1).encode_to_vec(),
+        )
+        .await
+        .unwrap();
+        write_checkpoint(storage, job_id, 2).await;
+
+        assert_eq!(
+            ParquetBackend::list_checkpoint_epochs(job_id)
+                .await
+                .unwrap(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            ParquetBackend::load_checkpoint_metadata(job_id, 1)
+                .await
+                .unwrap(),
+            checkpoint(job_id, 1, 1)
+        );
+        assert_eq!(
+            ParquetBackend::load_checkpoint_metadata_if_present(job_id, 1)
+                .await
+                .unwrap(),
+            Some(checkpoint(job_id, 1, 1))
+        );
+        assert_eq!(
+            ParquetBackend::load_operator_metadata(job_id, "op", 1)
+                .await
+                .unwrap(),
+            Some(operator(job_id, 1))
+        );
+
+        let plan = ParquetBackend::cleanup_before(&checkpoint(job_id, 2, 1), 1, 2, true)
+            .await
+            .unwrap();
+        assert!(plan.metadata.contains(&metadata_path(&legacy_operator)));
+        assert!(plan.metadata.contains(&metadata_path(&legacy_base)));
+        ParquetBackend::cleanup_checkpoint(checkpoint(job_id, 2, 1), 1, 2)
+            .await
+            .unwrap();
+        assert!(!storage
+            .exists(metadata_path(&legacy_base).as_str())
+            .await
+            .unwrap());
+        assert!(!storage
+            .exists(metadata_path(&legacy_operator).as_str())
+            .await
+            .unwrap());
+        assert_eq!(
+            ParquetBackend::list_checkpoint_epochs(job_id)
+                .await
+                .unwrap(),
+            vec![2]
+        );
+    }
+}
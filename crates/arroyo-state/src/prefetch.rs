@@ -0,0 +1,247 @@
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{Context, Result};
+use arroyo_types::{string_config, u32_config};
+use bytes::Bytes;
+use futures::{Stream, StreamExt, TryStreamExt};
+
+use crate::encryption::decrypt;
+
+/// Number of checkpoint files fetched concurrently when restoring a table.
+pub const RESTORE_PARALLELISM_ENV: &str = "STATE_RESTORE_PARALLELISM";
+/// Directory that fetched files above the spill threshold wait in until they're read.
+/// Defaults to the system temp directory.
+pub const RESTORE_SPILL_DIR_ENV: &str = "STATE_RESTORE_SPILL_DIR";
+/// Fetched files larger than this many bytes are spilled to local disk.
+pub const RESTORE_SPILL_THRESHOLD_ENV: &str = "STATE_RESTORE_SPILL_THRESHOLD_BYTES";
+
+static SPILL_FILE_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone)]
+pub struct PrefetchConfig {
+    pub parallelism: usize,
+    pub spill_dir: PathBuf,
+    pub spill_threshold: usize,
+}
+
+impl PrefetchConfig {
+    pub fn from_env() -> Self {
+        let spill_dir = string_config(RESTORE_SPILL_DIR_ENV, "");
+        Self {
+            parallelism: u32_config(RESTORE_PARALLELISM_ENV, 4).max(1) as usize,
+            spill_dir: if spill_dir.is_empty() {
+                std::env::temp_dir()
+            } else {
+                spill_dir.into()
+            },
+            spill_threshold: u32_config(RESTORE_SPILL_THRESHOLD_ENV, 64 * 1024 * 1024) as usize,
+        }
+    }
+}
+
+/// A fetched file waiting to be read. Spilled files are removed once read or dropped.
+enum Prefetched {
+    Memory(Bytes),
+    Spilled(SpillFile),
+}
+
+struct SpillFile(PathBuf);
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+impl Prefetched {
+    async fn spill_if_large(config: &PrefetchConfig, data: Bytes) -> Result<Self> {
+        if data.len() <= config.spill_threshold {
+            return Ok(Self::Memory(data));
+        }
+        let spill_file = SpillFile(config.spill_dir.join(format!(
+            "arroyo-restore-{}-{}",
+            std::process::id(),
+            SPILL_FILE_ID.fetch_add(1, Ordering::Relaxed)
+        )));
+        tokio::fs::write(&spill_file.0, &data)
+            .await
+            .with_context(|| format!("failed to spill to {}", spill_file.0.display()))?;
+        Ok(Self::Spilled(spill_file))
+    }
+
+    async fn into_bytes(self) -> Result<Bytes> {
+        match self {
+            Self::Memory(data) => Ok(data),
+            Self::Spilled(spill_file) => Ok(tokio::fs::read(&spill_file.0)
+                .await
+                .with_context(|| format!("failed to read spilled {}", spill_file.0.display()))?
+                .into()),
+        }
+    }
+}
+
+/// Fetches state files with `fetch`, up to `config.parallelism` at a time, and yields their
+/// decrypted contents in the order given so that restores can apply them in order while
+/// later files are still downloading. Files larger than the spill threshold wait to be read
+/// on local disk rather than in memory. They're spilled before decryption, so encrypted
+/// state stays encrypted on disk.
+pub(crate) fn prefetch_state_files<F, Fut>(
+    config: PrefetchConfig,
+    files: Vec<String>,
+    fetch: F,
+) -> impl Stream<Item = Result<(String, Bytes)>>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<Bytes>>,
+{
+    let parallelism = config.parallelism.max(1);
+    futures::stream::iter(files)
+        .map(move |file| {
+            let config = config.clone();
+            let fetched = fetch(file.clone());
+            async move {
+                let data = fetched
+                    .await
+                    .with_context(|| format!("failed to read {}", file))?;
+                Ok::<_, anyhow::Error>((file, Prefetched::spill_if_large(&config, data).await?))
+            }
+        })
+        .buffered(parallelism)
+        .and_then(|(file, prefetched)| async move {
+            let data = decrypt(&file, prefetched.into_bytes().await?)?;
+            Ok((file, data))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    /// A store where every read takes `latency`, like a GET against object storage.
+    #[derive(Clone)]
+    struct SlowStore {
+        files: Arc<HashMap<String, Bytes>>,
+        latency: Duration,
+    }
+
+    impl SlowStore {
+        fn new(count: usize, size: usize, latency: Duration) -> Self {
+            let files = (0..count)
+                .map(|i| (format!("file-{}", i), Bytes::from(vec![i as u8; size])))
+                .collect();
+            Self {
+                files: Arc::new(files),
+                latency,
+            }
+        }
+
+        fn names(&self) -> Vec<String> {
+            (0..self.files.len())
+                .map(|i| format!("file-{}", i))
+                .collect()
+        }
+
+        async fn get(self, file: String) -> Result<Bytes> {
+            tokio::time::sleep(self.latency).await;
+            self.files
+                .get(&file)
+                .cloned()
+                .ok_or_else(|| anyhow!("{} not found", file))
+        }
+    }
+
+    fn config(parallelism: usize, spill_threshold: usize) -> PrefetchConfig {
+        PrefetchConfig {
+            parallelism,
+            spill_dir: std::env::temp_dir(),
+            spill_threshold,
+        }
+    }
+
+    async fn restore(store: &SlowStore, config: PrefetchConfig) -> Duration {
+        let start = Instant::now();
+        let restored: Vec<_> =
+            prefetch_state_files(config, store.names(), |file| store.clone().get(file))
+                .try_collect()
+                .await
+                .unwrap();
+        let elapsed = start.elapsed();
+
+        // yielded in order, with the right contents
+        assert_eq!(restored.len(), store.names().len());
+        for (i, (file, data)) in restored.iter().enumerate() {
+            assert_eq!(file, &format!("file-{}", i));
+            assert_eq!(data, &store.files[file]);
+        }
+        elapsed
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_speeds_up_restore_of_small_files() {
+        let store = SlowStore::new(50, 1024, Duration::from_millis(20));
+
+        let sequential = restore(&store, config(1, usize::MAX)).await;
+        let prefetched = restore(&store, config(10, usize::MAX)).await;
+
+        // 50 reads take at least 1s one at a time, and about 100ms 10 at a time
+        assert!(sequential >= Duration::from_millis(1000));
+        assert!(
+            prefetched * 5 < sequential,
+            "prefetching took {:?}, against {:?} sequentially",
+            prefetched,
+            sequential
+        );
+    }
+
+    #[tokio::test]
+    async fn test_large_files_are_spilled_until_read() {
+        let spill_dir = std::env::temp_dir().join(format!(
+            "arroyo-prefetch-test-{}-{}",
+            std::process::id(),
+            SPILL_FILE_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&spill_dir).unwrap();
+        let config = PrefetchConfig {
+            parallelism: 2,
+            spill_dir: spill_dir.clone(),
+            spill_threshold: 512,
+        };
+        let spilled_files = || std::fs::read_dir(&spill_dir).unwrap().count();
+
+        let small = Prefetched::spill_if_large(&config, vec![1; 512].into())
+            .await
+            .unwrap();
+        assert!(matches!(small, Prefetched::Memory(_)));
+        assert_eq!(spilled_files(), 0);
+
+        let large = Prefetched::spill_if_large(&config, vec![2; 513].into())
+            .await
+            .unwrap();
+        assert!(matches!(large, Prefetched::Spilled(_)));
+        assert_eq!(spilled_files(), 1);
+        assert_eq!(large.into_bytes().await.unwrap(), vec![2; 513]);
+        assert_eq!(spilled_files(), 0);
+
+        // dropped without being read, as when a restore fails part way through
+        let abandoned = Prefetched::spill_if_large(&config, vec![3; 1024].into())
+            .await
+            .unwrap();
+        assert_eq!(spilled_files(), 1);
+        drop(abandoned);
+        assert_eq!(spilled_files(), 0);
+
+        let store = SlowStore::new(5, 1024, Duration::from_millis(1));
+        restore(&store, config).await;
+        assert_eq!(spilled_files(), 0);
+
+        std::fs::remove_dir(&spill_dir).unwrap();
+    }
+}
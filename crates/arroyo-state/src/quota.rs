@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Result};
+use arroyo_rpc::grpc::{OperatorStateQuota, StateQuotaAction, TableConfig};
+use arroyo_types::{TaskInfo, TaskInfoRef};
+use prometheus::{Gauge, IntCounter, IntGauge};
+use tracing::{error, warn};
+
+use crate::metrics::{
+    OPERATOR_STATE_BYTES_GAUGE, STATE_QUOTA_EXCEEDED_COUNTER, STATE_QUOTA_EXPIRED_ROWS_COUNTER,
+    TABLE_BYTES_GAUGE, TABLE_SIZE_GAUGE,
+};
+
+pub const STATE_QUOTA_SOFT_LIMIT_BYTES_ENV: &str = "STATE_QUOTA_SOFT_LIMIT_BYTES";
+pub const STATE_QUOTA_HARD_LIMIT_BYTES_ENV: &str = "STATE_QUOTA_HARD_LIMIT_BYTES";
+pub const STATE_QUOTA_ACTION_ENV: &str = "STATE_QUOTA_ACTION";
+
+/// What happens when a subtask's state exceeds its share of the hard limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuotaAction {
+    /// Inserts fail with [`StateQuotaExceeded`] for the operator to handle: the inserts of
+    /// the time-keyed views and of the views over global keyed tables, including
+    /// `GlobalKeyedView::try_insert`, return it. `GlobalKeyedView::insert` can't fail, so it
+    /// writes the value over the limit and logs a warning.
+    #[default]
+    RejectInserts,
+    /// The oldest data in time-keyed tables is expired early to make room. The expired rows
+    /// are sent to the table's changelog as [`ChangeKind::QuotaExpire`] and counted in
+    /// `arroyo_worker_state_quota_expired_rows`. Tables without timestamps reject inserts
+    /// instead.
+    ///
+    /// [`ChangeKind::QuotaExpire`]: crate::changelog::ChangeKind::QuotaExpire
+    ForceExpiration,
+    /// Inserts are rejected as with `RejectInserts`, except that `GlobalKeyedView::insert`
+    /// fails too, with an error the operator should return to fail the job, and restored
+    /// state that's over the limit fails the restore.
+    FailJob,
+}
+
+impl QuotaAction {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "reject" => Ok(QuotaAction::RejectInserts),
+            "expire" => Ok(QuotaAction::ForceExpiration),
+            "fail" => Ok(QuotaAction::FailJob),
+            _ => bail!(
+                "unknown state quota action '{}'; expected reject, expire, or fail",
+                name
+            ),
+        }
+    }
+
+    fn from_proto(action: StateQuotaAction) -> Self {
+        match action {
+            StateQuotaAction::RejectInserts => QuotaAction::RejectInserts,
+            StateQuotaAction::ForceExpiration => QuotaAction::ForceExpiration,
+            StateQuotaAction::FailJob => QuotaAction::FailJob,
+        }
+    }
+
+    fn proto(&self) -> StateQuotaAction {
+        match self {
+            QuotaAction::RejectInserts => StateQuotaAction::RejectInserts,
+            QuotaAction::ForceExpiration => StateQuotaAction::ForceExpiration,
+            QuotaAction::FailJob => StateQuotaAction::FailJob,
+        }
+    }
+}
+
+/// Limits on the in-memory size of an operator's state, summed across all of its tables and
+/// subtasks. Each subtask enforces an equal share of each limit, rounded up, against the
+/// state it holds, so the operator's state can't grow much past the limit however its keys
+/// are spread.
+///
+/// Operators declare their quota on their table configs with [`crate::with_state_quota`].
+/// Those that don't get the worker's, from the `STATE_QUOTA_*` environment variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StateQuotaConfig {
+    /// Exceeding this logs a warning and increments `arroyo_worker_state_quota_exceeded`.
+    pub soft_limit_bytes: Option<usize>,
+    /// Exceeding this applies `action`.
+    pub hard_limit_bytes: Option<usize>,
+    /// Set with `STATE_QUOTA_ACTION` as `reject`, `expire` (which sends the expired rows to
+    /// the changelog) or `fail`.
+    pub action: QuotaAction,
+}
+
+impl StateQuotaConfig {
+    /// The quota declared on an operator's table configs, if any, failing if the tables
+    /// disagree on it. A table without a quota disagrees with one that has one.
+    pub fn for_tables(table_configs: &HashMap<String, TableConfig>) -> Result<Option<Self>> {
+        let mut quota: Option<(&str, Option<Self>)> = None;
+        for (table, config) in table_configs {
+            let table_quota = config.state_quota.as_ref().map(Self::from_proto);
+            match quota {
+                None => quota = Some((table, table_quota)),
+                Some((other, existing)) if existing != table_quota => {
+                    bail!(
+                        "table {} declares state quota {:?}, but table {} declares {:?}",
+                        table,
+                        table_quota,
+                        other,
+                        existing
+                    );
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(quota.and_then(|(_, quota)| quota))
+    }
+
+    pub(crate) fn from_proto(quota: &OperatorStateQuota) -> Self {
+        Self {
+            soft_limit_bytes: quota.soft_limit_bytes.map(|bytes| bytes as usize),
+            hard_limit_bytes: quota.hard_limit_bytes.map(|bytes| bytes as usize),
+            action: QuotaAction::from_proto(quota.action()),
+        }
+    }
+
+    pub(crate) fn proto(&self) -> OperatorStateQuota {
+        OperatorStateQuota {
+            soft_limit_bytes: self.soft_limit_bytes.map(|bytes| bytes as u64),
+            hard_limit_bytes: self.hard_limit_bytes.map(|bytes| bytes as u64),
+            action: self.action.proto().into(),
+        }
+    }
+
+    pub fn from_env() -> Result<Self> {
+        let bytes = |var: &str| -> Result<Option<usize>> {
+            env::var(var)
+                .ok()
+                .map(|v| {
+                    v.parse()
+                        .map_err(|_| anyhow!("invalid value '{}' for {}", v, var))
+                })
+                .transpose()
+        };
+        Ok(Self {
+            soft_limit_bytes: bytes(STATE_QUOTA_SOFT_LIMIT_BYTES_ENV)?,
+            hard_limit_bytes: bytes(STATE_QUOTA_HARD_LIMIT_BYTES_ENV)?,
+            action: env::var(STATE_QUOTA_ACTION_ENV)
+                .ok()
+                .map(|action| QuotaAction::from_name(&action))
+                .transpose()?
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StateQuotaExceeded {
+    pub operator_id: String,
+    pub task_index: usize,
+    /// The operator's hard limit.
+    pub operator_limit_bytes: usize,
+    /// The subtask's share of the operator's hard limit, which it exceeded.
+    pub limit_bytes: usize,
+    /// The size of each of the subtask's tables, largest first.
+    pub table_bytes: Vec<(String, usize)>,
+}
+
+impl StateQuotaExceeded {
+    pub fn total_bytes(&self) -> usize {
+        self.table_bytes.iter().map(|(_, bytes)| bytes).sum()
+    }
+}
+
+impl Display for StateQuotaExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "subtask {} of operator {} has {} bytes of state, exceeding its share of {} bytes of \
+             the operator's limit of {} bytes (",
+            self.task_index,
+            self.operator_id,
+            self.total_bytes(),
+            self.limit_bytes,
+            self.operator_limit_bytes
+        )?;
+        for (i, (table, bytes)) in self.table_bytes.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "table {}: {} bytes", table, bytes)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl std::error::Error for StateQuotaExceeded {}
+
+/// Outcome of checking a table's prospective size against the quota.
+#[derive(Debug)]
+pub(crate) enum QuotaCheck {
+    Ok,
+    /// The table should expire its oldest data until it's using at most this many bytes.
+    Expire {
+        target_bytes: usize,
+    },
+    Exceeded(StateQuotaExceeded),
+}
+
+/// Tracks the size of each of a subtask's tables against its share of the operator's quota.
+/// Shared by the views of every table in the subtask, through its table manager; other
+/// subtasks of the operator have their own.
+#[derive(Debug)]
+pub(crate) struct StateQuota {
+    config: StateQuotaConfig,
+    // the subtask's shares of the operator's limits
+    soft_limit_bytes: Option<usize>,
+    hard_limit_bytes: Option<usize>,
+    task_info: TaskInfoRef,
+    sizes: Mutex<HashMap<String, usize>>,
+    over_soft_limit: Mutex<bool>,
+    // whether inserts that can't be rejected have been written over the hard limit
+    admitted_over_limit: AtomicBool,
+    gauge: IntGauge,
+}
+
+impl StateQuota {
+    /// Returns `None` if the config doesn't set any limits.
+    pub(crate) fn new(task_info: TaskInfoRef, config: StateQuotaConfig) -> Option<Arc<Self>> {
+        if config.soft_limit_bytes.is_none() && config.hard_limit_bytes.is_none() {
+            return None;
+        }
+        let share = |limit: usize| limit.div_ceil(task_info.parallelism.max(1));
+        Some(Arc::new(Self {
+            config,
+            soft_limit_bytes: config.soft_limit_bytes.map(share),
+            hard_limit_bytes: config.hard_limit_bytes.map(share),
+            gauge: OPERATOR_STATE_BYTES_GAUGE
+                .with_label_values(&[&task_info.operator_id, &task_info.task_index.to_string()]),
+            task_info,
+            sizes: Mutex::new(HashMap::new()),
+            over_soft_limit: Mutex::new(false),
+            admitted_over_limit: AtomicBool::new(false),
+        }))
+    }
+
+    /// Checks whether the table may grow to `bytes` without the subtask exceeding its share
+    /// of the hard limit. The
+    /// size isn't recorded until [`StateQuota::record`] is called. Tables that can't expire
+    /// data early reject inserts when the action is [`QuotaAction::ForceExpiration`].
+    pub(crate) fn check(&self, table: &str, bytes: usize, can_expire: bool) -> QuotaCheck {
+        let Some(hard_limit) = self.hard_limit_bytes else {
+            return QuotaCheck::Ok;
+        };
+        let others: usize = self
+            .sizes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(name, _)| name.as_str() != table)
+            .map(|(_, bytes)| bytes)
+            .sum();
+        if others + bytes <= hard_limit {
+            return QuotaCheck::Ok;
+        }
+        self.exceeded_counter("hard").inc();
+        if can_expire && self.config.action == QuotaAction::ForceExpiration {
+            QuotaCheck::Expire {
+                target_bytes: hard_limit.saturating_sub(others),
+            }
+        } else {
+            QuotaCheck::Exceeded(self.exceeded(hard_limit, table, bytes))
+        }
+    }
+
+    /// Records the current size of a table, warning if the subtask is over its share of the
+    /// soft limit.
+    pub(crate) fn record(&self, table: &str, bytes: usize) {
+        let total = {
+            let mut sizes = self.sizes.lock().unwrap();
+            sizes.insert(table.to_string(), bytes);
+            sizes.values().sum::<usize>()
+        };
+        self.gauge.set(total as i64);
+
+        let Some(soft_limit) = self.soft_limit_bytes else {
+            return;
+        };
+        let mut over = self.over_soft_limit.lock().unwrap();
+        if total > soft_limit && !*over {
+            warn!(
+                message = "Operator state exceeds soft quota",
+                operator_id = self.task_info.operator_id,
+                task_index = self.task_info.task_index,
+                bytes = total,
+                soft_limit,
+                operator_soft_limit = self.config.soft_limit_bytes
+            );
+            self.exceeded_counter("soft").inc();
+        }
+        *over = total > soft_limit;
+    }
+
+    /// Whether an insert that can't be rejected may exceed the hard limit, which it may
+    /// unless the action is [`QuotaAction::FailJob`]. The first one admitted is logged.
+    pub(crate) fn admit_insert(&self, exceeded: &StateQuotaExceeded) -> bool {
+        if self.config.action == QuotaAction::FailJob {
+            error!("{}", exceeded);
+            return false;
+        }
+        if !self.admitted_over_limit.swap(true, Ordering::Relaxed) {
+            warn!(
+                "writing state over the hard quota, as the insert can't be rejected: {}",
+                exceeded
+            );
+        }
+        true
+    }
+
+    /// Counts rows expired before their retention ended to stay within the subtask's share of
+    /// the hard limit.
+    pub(crate) fn record_expired(&self, table: &str, rows: usize) {
+        STATE_QUOTA_EXPIRED_ROWS_COUNTER
+            .with_label_values(&[
+                &self.task_info.operator_id,
+                &self.task_info.task_index.to_string(),
+                table,
+            ])
+            .inc_by(rows as u64);
+    }
+
+    /// Applies the configured action to restored state that's already over the hard limit.
+    /// Rejections are deferred to the next insert.
+    pub(crate) fn reject_restored(&self, exceeded: StateQuotaExceeded) -> Result<()> {
+        if self.config.action == QuotaAction::FailJob {
+            bail!("restored state is over quota: {}", exceeded);
+        }
+        warn!("restored state is over quota: {}", exceeded);
+        Ok(())
+    }
+
+    fn exceeded(&self, limit_bytes: usize, table: &str, bytes: usize) -> StateQuotaExceeded {
+        let mut sizes = self.sizes.lock().unwrap().clone();
+        sizes.insert(table.to_string(), bytes);
+        let mut table_bytes: Vec<_> = sizes.into_iter().collect();
+        table_bytes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        StateQuotaExceeded {
+            operator_id: self.task_info.operator_id.clone(),
+            task_index: self.task_info.task_index,
+            operator_limit_bytes: self.config.hard_limit_bytes.unwrap_or(limit_bytes),
+            limit_bytes,
+            table_bytes,
+        }
+    }
+
+    fn exceeded_counter(&self, limit: &str) -> IntCounter {
+        STATE_QUOTA_EXCEEDED_COUNTER.with_label_values(&[
+            &self.task_info.operator_id,
+            &self.task_info.task_index.to_string(),
+            limit,
+        ])
+    }
+}
+
+/// Reports a table's size to its metrics and to the subtask's quota, if it has one.
+#[derive(Debug)]
+pub(crate) struct TableSize {
+    table: String,
+    entries_gauge: Gauge,
+    bytes_gauge: Gauge,
+    quota: Option<Arc<StateQuota>>,
+}
+
+impl TableSize {
+    pub(crate) fn new(task_info: &TaskInfo, table: &str, quota: Option<Arc<StateQuota>>) -> Self {
+        let labels = [
+            task_info.operator_id.as_str(),
+            &task_info.task_index.to_string(),
+            table,
+        ];
+        Self {
+            table: table.to_string(),
+            entries_gauge: TABLE_SIZE_GAUGE.with_label_values(&labels),
+            bytes_gauge: TABLE_BYTES_GAUGE.with_label_values(&labels),
+            quota,
+        }
+    }
+
+    pub(crate) fn check(&self, bytes: usize, can_expire: bool) -> QuotaCheck {
+        match &self.quota {
+            Some(quota) => quota.check(&self.table, bytes, can_expire),
+            None => QuotaCheck::Ok,
+        }
+    }
+
+    pub(crate) fn record(&self, entries: usize, bytes: usize) {
+        self.entries_gauge.set(entries as f64);
+        self.bytes_gauge.set(bytes as f64);
+        if let Some(quota) = &self.quota {
+            quota.record(&self.table, bytes);
+        }
+    }
+
+    pub(crate) fn admit_insert(&self, exceeded: &StateQuotaExceeded) -> bool {
+        match &self.quota {
+            Some(quota) => quota.admit_insert(exceeded),
+            None => true,
+        }
+    }
+
+    pub(crate) fn record_expired(&self, rows: usize) {
+        if let Some(quota) = &self.quota {
+            quota.record_expired(&self.table, rows);
+        }
+    }
+
+    pub(crate) fn reject_restored(&self, exceeded: StateQuotaExceeded) -> Result<()> {
+        match &self.quota {
+            Some(quota) => quota.reject_restored(exceeded),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{global_table_config, with_state_quota};
+
+    #[test]
+    fn test_subtasks_enforce_a_share_of_the_operator_quota() {
+        let mut task_info = TaskInfo::for_test("job", "operator-quota");
+        task_info.parallelism = 3;
+        let quota = StateQuota::new(
+            Arc::new(task_info),
+            StateQuotaConfig {
+                soft_limit_bytes: None,
+                hard_limit_bytes: Some(100),
+                action: QuotaAction::RejectInserts,
+            },
+        )
+        .unwrap();
+
+        // each of the three subtasks may hold a third of the limit, rounded up
+        quota.record("a", 20);
+        assert!(matches!(quota.check("b", 14, false), QuotaCheck::Ok));
+        let QuotaCheck::Exceeded(exceeded) = quota.check("b", 15, false) else {
+            panic!("expected the subtask's share to be exceeded");
+        };
+        assert_eq!(exceeded.limit_bytes, 34);
+        assert_eq!(exceeded.operator_limit_bytes, 100);
+        assert_eq!(
+            exceeded.table_bytes,
+            vec![("a".to_string(), 20), ("b".to_string(), 15)]
+        );
+    }
+
+    #[test]
+    fn test_quota_from_table_configs() {
+        let quota = StateQuotaConfig {
+            soft_limit_bytes: Some(10),
+            hard_limit_bytes: Some(20),
+            action: QuotaAction::FailJob,
+        };
+        let mut table_configs = global_table_config("a", "a");
+        table_configs.extend(global_table_config("b", "b"));
+        assert_eq!(StateQuotaConfig::for_tables(&table_configs).unwrap(), None);
+
+        let mut table_configs = with_state_quota(table_configs, quota);
+        assert_eq!(
+            StateQuotaConfig::for_tables(&table_configs).unwrap(),
+            Some(quota)
+        );
+
+        // every table of an operator has to declare the same quota
+        table_configs.get_mut("b").unwrap().state_quota = None;
+        assert!(StateQuotaConfig::for_tables(&table_configs).is_err());
+        table_configs.get_mut("b").unwrap().state_quota = Some(
+            StateQuotaConfig {
+                hard_limit_bytes: Some(30),
+                ..quota
+            }
+            .proto(),
+        );
+        assert!(StateQuotaConfig::for_tables(&table_configs).is_err());
+    }
+}
@@ -0,0 +1,1524 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+use anyhow::{anyhow, bail, Context, Result};
+use arroyo_rpc::api::TableStorageUsage;
+use arroyo_rpc::grpc::{
+    CheckpointMetadata, ExpiringKeyedTimeTableConfig, GlobalKeyedTableConfig, KeyPartitioning,
+    OperatorCheckpointMetadata, OperatorMetadata, OperatorRemapping, TableConfig, TableEnum,
+};
+use arroyo_storage::RetryPolicy;
+use prost::Message;
+use tracing::{info, warn};
+
+use crate::store_writes::is_transient;
+use crate::{
+    metadata_format::METADATA_FORMAT_VERSION, BackingStore, StateBackend, StateBackendKind,
+};
+
+/// Name of the file in a checkpoint's directory that remaps its operators when it's restored.
+pub const OPERATOR_REMAPPING_FILE: &str = "operator-remapping";
+
+/// Parses an operator remapping file. Each non-empty line maps an old operator id to a new
+/// one, optionally followed by table renames, and `#` starts a comment:
+///
+/// ```text
+/// old_operator_id new_operator_id [old_table=new_table ...]
+/// ```
+pub fn parse_operator_remapping(contents: &str) -> Result<Vec<OperatorRemapping>> {
+    let mut remappings = vec![];
+    let mut old_ids = HashSet::new();
+    let mut new_ids = HashSet::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        let (Some(old_operator_id), Some(new_operator_id)) = (parts.next(), parts.next()) else {
+            bail!(
+                "line {} of operator remapping must contain an old and new operator id",
+                i + 1
+            );
+        };
+        let mut table_names = HashMap::new();
+        for table in parts {
+            let Some((old_table, new_table)) = table.split_once('=') else {
+                bail!(
+                    "invalid table remapping '{}' on line {}; expected old_table=new_table",
+                    table,
+                    i + 1
+                );
+            };
+            if table_names
+                .insert(old_table.to_string(), new_table.to_string())
+                .is_some()
+            {
+                bail!("table {} is remapped twice on line {}", old_table, i + 1);
+            }
+        }
+        if !old_ids.insert(old_operator_id.to_string()) {
+            bail!("operator {} is remapped more than once", old_operator_id);
+        }
+        if !new_ids.insert(new_operator_id.to_string()) {
+            bail!("multiple operators are remapped to {}", new_operator_id);
+        }
+        remappings.push(OperatorRemapping {
+            old_operator_id: old_operator_id.to_string(),
+            new_operator_id: new_operator_id.to_string(),
+            table_names,
+        });
+    }
+    Ok(remappings)
+}
+
+/// Formats operator remappings as a remapping file that [`parse_operator_remapping`] reads
+/// back.
+pub fn format_operator_remapping(remappings: &[OperatorRemapping]) -> String {
+    let mut contents = String::new();
+    for remapping in remappings {
+        contents.push_str(&remapping.old_operator_id);
+        contents.push(' ');
+        contents.push_str(&remapping.new_operator_id);
+        let mut table_names: Vec<_> = remapping.table_names.iter().collect();
+        table_names.sort();
+        for (old_table, new_table) in table_names {
+            contents.push_str(&format!(" {}={}", old_table, new_table));
+        }
+        contents.push('\n');
+    }
+    contents
+}
+
+/// Supplies an operator remapping for restoring a job from `epoch`, for when operators keep
+/// their state but not their ids, such as after a view is renamed. The controller and every
+/// worker apply it when they restore from the epoch (see [`load_checkpoint_for_restore`]), so
+/// the restored operators find their state under the old ids and register it under the new
+/// ones. The first checkpoint after the restore writes everything under the new ids, so the
+/// remapping isn't needed again.
+///
+/// The remapping is checked against the checkpoint before it's stored, and replaces any that
+/// was supplied for the epoch before.
+pub async fn supply_operator_remapping(
+    job_id: &str,
+    epoch: u32,
+    remappings: Vec<OperatorRemapping>,
+) -> Result<()> {
+    supply_operator_remapping_to::<StateBackend>(job_id, epoch, remappings).await
+}
+
+/// Like [`supply_operator_remapping`], in a given backing store.
+pub async fn supply_operator_remapping_to<B: BackingStore>(
+    job_id: &str,
+    epoch: u32,
+    remappings: Vec<OperatorRemapping>,
+) -> Result<()> {
+    let mut metadata = B::load_checkpoint_metadata(job_id, epoch).await?;
+    metadata.operator_remappings.clear();
+    apply_operator_remapping(&mut metadata, remappings.clone())?;
+    info!(
+        message = "Supplying operator remapping for restore",
+        job_id,
+        epoch,
+        remappings = remappings.len()
+    );
+    B::write_operator_remapping(job_id, epoch, remappings).await
+}
+
+/// Loads a checkpoint's metadata for restoring, with any operator remapping supplied for
+/// the restore applied.
+pub async fn load_checkpoint_for_restore(job_id: &str, epoch: u32) -> Result<CheckpointMetadata> {
+    load_checkpoint_for_restore_from::<StateBackend>(job_id, epoch).await
+}
+
+/// Like [`load_checkpoint_for_restore`], from a given backing store.
+pub async fn load_checkpoint_for_restore_from<B: BackingStore>(
+    job_id: &str,
+    epoch: u32,
+) -> Result<CheckpointMetadata> {
+    let mut metadata = B::load_checkpoint_metadata(job_id, epoch).await?;
+    // remappings recorded when this checkpoint was written have already been applied
+    metadata.operator_remappings.clear();
+    let remappings = B::load_operator_remapping(job_id, epoch).await?;
+    if !remappings.is_empty() {
+        info!(
+            message = "Remapping operators for restore",
+            job_id,
+            epoch,
+            remappings = remappings.len()
+        );
+        apply_operator_remapping(&mut metadata, remappings)?;
+    }
+    Ok(metadata)
+}
+
+/// Finds the latest epoch of a job, from `epoch` back to `min_epoch`, that can be restored:
+/// one with checkpoint metadata and metadata for each of its operators. Epochs missing any of
+/// it, such as those whose writes were interrupted, are skipped; errors reading it aren't.
+pub async fn find_restorable_checkpoint(
+    job_id: &str,
+    epoch: u32,
+    min_epoch: u32,
+) -> Result<Option<u32>> {
+    find_restorable_checkpoint_from::<StateBackend>(job_id, epoch, min_epoch).await
+}
+
+/// Like [`find_restorable_checkpoint`], from a given backing store.
+pub async fn find_restorable_checkpoint_from<B: BackingStore>(
+    job_id: &str,
+    epoch: u32,
+    min_epoch: u32,
+) -> Result<Option<u32>> {
+    'epochs: for epoch in (min_epoch..=epoch).rev() {
+        let Some(metadata) = B::load_checkpoint_metadata_if_present(job_id, epoch).await? else {
+            warn!(
+                message = "Skipping checkpoint without metadata",
+                job_id, epoch
+            );
+            continue;
+        };
+        for operator_id in &metadata.operator_ids {
+            if metadata.stateless_operators.contains_key(operator_id) {
+                continue;
+            }
+            if B::load_operator_metadata(job_id, operator_id, epoch)
+                .await?
+                .is_none()
+            {
+                warn!(
+                    message = "Skipping checkpoint missing operator metadata",
+                    job_id, epoch, operator_id
+                );
+                continue 'epochs;
+            }
+        }
+        return Ok(Some(epoch));
+    }
+    Ok(None)
+}
+
+/// Lists the epochs of a job that can be restored with [`load_epoch_for_restore`]: those
+/// that are fully written and haven't been cleaned up, in order.
+pub async fn list_restorable_epochs(job_id: &str) -> Result<Vec<u32>> {
+    list_restorable_epochs_from::<StateBackend>(job_id).await
+}
+
+/// Like [`list_restorable_epochs`], from a given backing store.
+pub async fn list_restorable_epochs_from<B: BackingStore>(job_id: &str) -> Result<Vec<u32>> {
+    let epochs = B::list_checkpoint_epochs(job_id).await?;
+    let Some(&latest) = epochs.last() else {
+        return Ok(vec![]);
+    };
+    let min_epoch = B::load_checkpoint_metadata(job_id, latest).await?.min_epoch;
+    let mut restorable = vec![];
+    for epoch in epochs.into_iter().filter(|epoch| *epoch >= min_epoch) {
+        if find_restorable_checkpoint_from::<B>(job_id, epoch, epoch).await? == Some(epoch) {
+            restorable.push(epoch);
+        }
+    }
+    Ok(restorable)
+}
+
+/// Loads the metadata of a chosen epoch of a job for restoring, like
+/// [`load_checkpoint_for_restore`], to roll the job back past later checkpoints. The epoch
+/// must be fully written, and no earlier than the min epoch of the job's latest checkpoint,
+/// below which checkpoints are cleaned up.
+pub async fn load_epoch_for_restore(job_id: &str, epoch: u32) -> Result<CheckpointMetadata> {
+    load_epoch_for_restore_from::<StateBackend>(job_id, epoch).await
+}
+
+/// Like [`load_epoch_for_restore`], from a given backing store.
+pub async fn load_epoch_for_restore_from<B: BackingStore>(
+    job_id: &str,
+    epoch: u32,
+) -> Result<CheckpointMetadata> {
+    check_restorable_epoch::<B>(job_id, epoch).await?;
+    load_checkpoint_for_restore_from::<B>(job_id, epoch).await
+}
+
+async fn check_restorable_epoch<B: BackingStore>(job_id: &str, epoch: u32) -> Result<()> {
+    let Some(&latest) = B::list_checkpoint_epochs(job_id).await?.last() else {
+        bail!(
+            "can't restore job {} from epoch {}, as it has no checkpoints",
+            job_id,
+            epoch
+        );
+    };
+    if epoch > latest {
+        bail!(
+            "can't restore job {} from epoch {}, after its latest checkpoint {}",
+            job_id,
+            epoch,
+            latest
+        );
+    }
+    let min_epoch = B::load_checkpoint_metadata(job_id, latest).await?.min_epoch;
+    if epoch < min_epoch {
+        bail!(
+            "can't restore job {} from epoch {}, as its files have been cleaned up; the \
+            earliest epoch that can be restored is {}",
+            job_id,
+            epoch,
+            min_epoch
+        );
+    }
+    if find_restorable_checkpoint_from::<B>(job_id, epoch, epoch).await? != Some(epoch) {
+        bail!(
+            "can't restore job {} from epoch {}, as its checkpoint wasn't fully written",
+            job_id,
+            epoch
+        );
+    }
+    Ok(())
+}
+
+/// Copies a restorable epoch of one job to be the first checkpoint of another, new job,
+/// which can then be restored from it like from a checkpoint of its own. The files the
+/// checkpoint references are copied into the new job's checkpoint, so it doesn't depend on
+/// the source job's files, which are never changed or deleted.
+///
+/// Operators are matched by id when the new job is restored, through an operator remapping
+/// supplied for its epoch (see [`load_checkpoint_for_restore`]) and
+/// [`reconcile_restored_operators`], as for any restore.
+pub async fn fork_checkpoint(
+    source_job_id: &str,
+    epoch: u32,
+    target_job_id: &str,
+) -> Result<CheckpointMetadata> {
+    fork_checkpoint_from::<StateBackend>(source_job_id, epoch, target_job_id).await
+}
+
+/// Like [`fork_checkpoint`], in a given backing store.
+pub async fn fork_checkpoint_from<B: BackingStore>(
+    source_job_id: &str,
+    epoch: u32,
+    target_job_id: &str,
+) -> Result<CheckpointMetadata> {
+    if source_job_id == target_job_id {
+        bail!(
+            "can't fork a checkpoint of job {} into the same job",
+            source_job_id
+        );
+    }
+    if let Some(latest) = B::list_checkpoint_epochs(target_job_id).await?.last() {
+        bail!(
+            "can't fork a checkpoint into job {}, as it already has checkpoints, up to epoch {}",
+            target_job_id,
+            latest
+        );
+    }
+    check_restorable_epoch::<B>(source_job_id, epoch).await?;
+
+    let mut metadata = B::load_checkpoint_metadata(source_job_id, epoch).await?;
+    info!(
+        message = "Forking checkpoint",
+        source_job_id,
+        target_job_id,
+        epoch,
+        operators = metadata.operator_ids.len()
+    );
+    for operator_id in &metadata.operator_ids {
+        if let Some(operator_metadata) = metadata.stateless_operators.get_mut(operator_id) {
+            operator_metadata.job_id = target_job_id.to_string();
+            continue;
+        }
+        let operator_metadata = B::load_operator_metadata(source_job_id, operator_id, epoch)
+            .await?
+            .ok_or_else(|| {
+                anyhow!(
+                    "checkpoint {} of job {} is missing metadata for operator {}",
+                    epoch,
+                    source_job_id,
+                    operator_id
+                )
+            })?;
+        let copied = B::copy_operator_checkpoint(operator_metadata, target_job_id).await?;
+        B::write_operator_checkpoint_metadata(copied).await?;
+    }
+
+    // nothing earlier exists for the new job, and its remappings are its own
+    metadata.job_id = target_job_id.to_string();
+    metadata.min_epoch = epoch;
+    metadata.savepoint = None;
+    metadata.operator_remappings.clear();
+    B::write_checkpoint_metadata(metadata.clone()).await?;
+    Ok(metadata)
+}
+
+/// Loads the metadata of a job's savepoint for restoring, like
+/// [`load_checkpoint_for_restore`] for the epoch it was taken at.
+pub async fn load_savepoint_for_restore(job_id: &str, name: &str) -> Result<CheckpointMetadata> {
+    load_savepoint_for_restore_from::<StateBackend>(job_id, name).await
+}
+
+/// Like [`load_savepoint_for_restore`], from a given backing store.
+pub async fn load_savepoint_for_restore_from<B: BackingStore>(
+    job_id: &str,
+    name: &str,
+) -> Result<CheckpointMetadata> {
+    let savepoint = B::load_savepoint_metadata(job_id, name).await?;
+    // the restored job checkpoints from the epoch after the savepoint, which would write over
+    // the epochs of any later savepoint
+    if let Some(later) = B::list_savepoints(job_id)
+        .await?
+        .into_iter()
+        .find(|later| later.epoch > savepoint.epoch)
+    {
+        bail!(
+            "can't restore job {} from savepoint {} at epoch {}, as savepoint {} at epoch {} is later",
+            job_id,
+            name,
+            savepoint.epoch,
+            later.savepoint.unwrap_or_default(),
+            later.epoch
+        );
+    }
+    load_checkpoint_for_restore_from::<B>(job_id, savepoint.epoch).await
+}
+
+/// Renames the operators of a checkpoint that's being restored, and records the remapping in
+/// the metadata so that operators can find their state under the old ids.
+///
+/// Each operator may be remapped once, to an id no other operator is remapped to and that
+/// isn't already in the checkpoint, so that no two operators' state ends up under one id.
+pub fn apply_operator_remapping(
+    metadata: &mut CheckpointMetadata,
+    remappings: Vec<OperatorRemapping>,
+) -> Result<()> {
+    let mut by_old_id: HashMap<&str, &OperatorRemapping> = HashMap::new();
+    let mut by_new_id: HashMap<&str, &OperatorRemapping> = HashMap::new();
+    for remapping in &remappings {
+        if !metadata.operator_ids.contains(&remapping.old_operator_id) {
+            bail!(
+                "cannot remap operator {}, as it is not in checkpoint {}",
+                remapping.old_operator_id,
+                metadata.epoch
+            );
+        }
+        if metadata.operator_ids.contains(&remapping.new_operator_id) {
+            bail!(
+                "cannot remap operator {} to {}, as checkpoint {} already has an operator {}",
+                remapping.old_operator_id,
+                remapping.new_operator_id,
+                metadata.epoch,
+                remapping.new_operator_id
+            );
+        }
+        if let Some(other) = by_old_id.insert(&remapping.old_operator_id, remapping) {
+            bail!(
+                "operator {} is remapped to both {} and {}",
+                remapping.old_operator_id,
+                other.new_operator_id,
+                remapping.new_operator_id
+            );
+        }
+        if let Some(other) = by_new_id.insert(&remapping.new_operator_id, remapping) {
+            bail!(
+                "operators {} and {} are both remapped to {}",
+                other.old_operator_id,
+                remapping.old_operator_id,
+                remapping.new_operator_id
+            );
+        }
+    }
+    let operator_ids: Vec<_> = metadata
+        .operator_ids
+        .iter()
+        .map(|id| match by_old_id.get(id.as_str()) {
+            Some(remapping) => remapping.new_operator_id.clone(),
+            None => id.clone(),
+        })
+        .collect();
+    metadata.operator_ids = operator_ids;
+    metadata.operator_remappings = remappings;
+    Ok(())
+}
+
+/// The state of a checkpoint that a restore leaves behind, because the checkpoint and the job
+/// being restored don't have the same operators.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NonRestoredState {
+    /// Stateful operators in the checkpoint that aren't in the job, with the storage used by
+    /// each of their tables. Their state is dropped.
+    pub dropped_operators: Vec<(String, Vec<TableStorageUsage>)>,
+    /// Operators of the job that aren't in the checkpoint, which start without state.
+    pub new_operators: Vec<String>,
+}
+
+impl NonRestoredState {
+    pub fn is_empty(&self) -> bool {
+        self.dropped_operators.is_empty() && self.new_operators.is_empty()
+    }
+}
+
+/// Matches the operators of a checkpoint that's being restored, after any remapping, against
+/// those of the job it's restored into. Operators that are only in the job start empty.
+/// Stateful operators that are only in the checkpoint fail the restore, unless
+/// `allow_non_restored_state` is set, in which case they're removed from the checkpoint's
+/// operators and their state is dropped. Operators that were stateless when the checkpoint
+/// was taken have nothing to drop, and are always removed.
+pub async fn reconcile_restored_operators(
+    checkpoint: &mut CheckpointMetadata,
+    job_operator_ids: &HashSet<String>,
+    allow_non_restored_state: bool,
+) -> Result<NonRestoredState> {
+    reconcile_restored_operators_from::<StateBackend>(
+        checkpoint,
+        job_operator_ids,
+        allow_non_restored_state,
+    )
+    .await
+}
+
+/// Like [`reconcile_restored_operators`], from a given backing store.
+pub async fn reconcile_restored_operators_from<B: BackingStore>(
+    checkpoint: &mut CheckpointMetadata,
+    job_operator_ids: &HashSet<String>,
+    allow_non_restored_state: bool,
+) -> Result<NonRestoredState> {
+    let mut non_restored = NonRestoredState::default();
+    let mut dropped = vec![];
+    for operator_id in &checkpoint.operator_ids {
+        if job_operator_ids.contains(operator_id) {
+            continue;
+        }
+        let stored_id = stored_operator_id(checkpoint, operator_id);
+        if !checkpoint.stateless_operators.contains_key(stored_id) {
+            dropped.push(operator_id.clone());
+        }
+    }
+    if !dropped.is_empty() {
+        if !allow_non_restored_state {
+            bail!(
+                "checkpoint {} has state for operators that aren't in the job: {}; restore \
+                with non-restored state allowed to drop it",
+                checkpoint.epoch,
+                dropped.join(", ")
+            );
+        }
+        let mut usage = B::table_storage_usage(&checkpoint.job_id, checkpoint.epoch).await?;
+        for operator_id in dropped {
+            let tables = usage
+                .remove(stored_operator_id(checkpoint, &operator_id))
+                .unwrap_or_default();
+            warn!(
+                message = "Dropping state of operator that isn't in the job",
+                job_id = checkpoint.job_id,
+                epoch = checkpoint.epoch,
+                operator_id,
+                tables = ?tables
+                    .iter()
+                    .map(|table| format!("{} ({} bytes)", table.table_name, table.bytes))
+                    .collect::<Vec<_>>()
+            );
+            non_restored.dropped_operators.push((operator_id, tables));
+        }
+    }
+
+    let mut new_operators: Vec<_> = job_operator_ids
+        .iter()
+        .filter(|operator_id| !checkpoint.operator_ids.contains(*operator_id))
+        .cloned()
+        .collect();
+    new_operators.sort();
+    for operator_id in &new_operators {
+        info!(
+            message = "Operator isn't in the checkpoint; starting it without state",
+            job_id = checkpoint.job_id,
+            epoch = checkpoint.epoch,
+            operator_id
+        );
+    }
+    non_restored.new_operators = new_operators;
+
+    checkpoint
+        .operator_ids
+        .retain(|operator_id| job_operator_ids.contains(operator_id));
+    Ok(non_restored)
+}
+
+/// The id an operator's metadata is stored under in a checkpoint, before any remapping.
+fn stored_operator_id<'a>(checkpoint: &'a CheckpointMetadata, operator_id: &'a str) -> &'a str {
+    checkpoint
+        .operator_remappings
+        .iter()
+        .find(|remapping| remapping.new_operator_id == operator_id)
+        .map(|remapping| remapping.old_operator_id.as_str())
+        .unwrap_or(operator_id)
+}
+
+/// Loads the metadata for an operator in a checkpoint that's being restored, following any
+/// remapping recorded by [`apply_operator_remapping`]. The returned metadata uses the new
+/// operator id and table names.
+pub async fn load_restored_operator_metadata(
+    checkpoint: &CheckpointMetadata,
+    operator_id: &str,
+) -> Result<Option<OperatorCheckpointMetadata>> {
+    load_restored_operator_metadata_from::<StateBackend>(checkpoint, operator_id).await
+}
+
+/// Like [`load_restored_operator_metadata`], from a given backing store.
+pub async fn load_restored_operator_metadata_from<B: BackingStore>(
+    checkpoint: &CheckpointMetadata,
+    operator_id: &str,
+) -> Result<Option<OperatorCheckpointMetadata>> {
+    let remapping = checkpoint
+        .operator_remappings
+        .iter()
+        .find(|remapping| remapping.new_operator_id == operator_id);
+    let stored_id = stored_operator_id(checkpoint, operator_id);
+    let stored = match checkpoint.stateless_operators.get(stored_id) {
+        Some(operator_metadata) => Some(stateless_operator_metadata(operator_metadata.clone())),
+        None => B::load_operator_metadata(&checkpoint.job_id, stored_id, checkpoint.epoch)
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to read the metadata of operator {} of job {} in checkpoint {}",
+                    stored_id, checkpoint.job_id, checkpoint.epoch
+                )
+            })?,
+    };
+    let Some(mut metadata) = stored else {
+        return Ok(None);
+    };
+    let Some(remapping) = remapping else {
+        return Ok(Some(metadata));
+    };
+
+    let rename = |table: String| remapping.table_names.get(&table).cloned().unwrap_or(table);
+    for table in remapping.table_names.keys() {
+        if !metadata.table_configs.contains_key(table) {
+            bail!(
+                "cannot remap table {} of operator {}, as it has no such table",
+                table,
+                remapping.old_operator_id
+            );
+        }
+    }
+    metadata.table_configs = metadata
+        .table_configs
+        .into_iter()
+        .map(|(table, config)| (rename(table), config))
+        .collect();
+    metadata.table_checkpoint_metadata = metadata
+        .table_checkpoint_metadata
+        .into_iter()
+        .map(|(table, table_metadata)| (rename(table), table_metadata))
+        .collect();
+    if let Some(operator_metadata) = metadata.operator_metadata.as_mut() {
+        operator_metadata.operator_id = operator_id.to_string();
+    }
+    Ok(Some(metadata))
+}
+
+/// A checkpoint being restored has no metadata for one of its operators, as opposed to having
+/// metadata that can't be read. Callers of [`require_restored_operator_metadata`] can
+/// downcast its errors to this to tell the two apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingOperatorMetadata {
+    pub job_id: String,
+    pub operator_id: String,
+    pub epoch: u32,
+}
+
+impl Display for MissingOperatorMetadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "checkpoint {} of job {} has no metadata for operator {}",
+            self.epoch, self.job_id, self.operator_id
+        )
+    }
+}
+
+impl std::error::Error for MissingOperatorMetadata {}
+
+/// How many times a restore reads an operator's metadata again after the read fails with a
+/// transient storage error. Each storage request is already retried by the storage
+/// provider, so these cover a store that stays unreachable for longer than that.
+const OPERATOR_METADATA_READ_RETRIES: u32 = 3;
+
+/// Like [`load_restored_operator_metadata`], for restores that can't go on without the
+/// operator's metadata: fails with [`MissingOperatorMetadata`] if the checkpoint has none.
+/// Reads that fail with transient storage errors are retried, with backoff, a few times;
+/// other errors, as from metadata that can't be decoded, fail the restore at once.
+pub async fn require_restored_operator_metadata(
+    checkpoint: &CheckpointMetadata,
+    operator_id: &str,
+) -> Result<OperatorCheckpointMetadata> {
+    require_restored_operator_metadata_from::<StateBackend>(checkpoint, operator_id).await
+}
+
+/// Like [`require_restored_operator_metadata`], from a given backing store.
+pub async fn require_restored_operator_metadata_from<B: BackingStore>(
+    checkpoint: &CheckpointMetadata,
+    operator_id: &str,
+) -> Result<OperatorCheckpointMetadata> {
+    let policy = RetryPolicy {
+        max_retries: OPERATOR_METADATA_READ_RETRIES,
+        ..RetryPolicy::from_env()
+    };
+    require_restored_operator_metadata_with::<B>(checkpoint, operator_id, policy).await
+}
+
+async fn require_restored_operator_metadata_with<B: BackingStore>(
+    checkpoint: &CheckpointMetadata,
+    operator_id: &str,
+    policy: RetryPolicy,
+) -> Result<OperatorCheckpointMetadata> {
+    let mut retries = 0;
+    let stored = loop {
+        match load_restored_operator_metadata_from::<B>(checkpoint, operator_id).await {
+            Ok(stored) => break stored,
+            Err(e) if retries < policy.max_retries && is_transient(&e) => {
+                retries += 1;
+                let backoff = policy.backoff(retries);
+                warn!(
+                    message = "retrying read of operator metadata",
+                    job_id = %checkpoint.job_id,
+                    operator_id,
+                    epoch = checkpoint.epoch,
+                    retry = retries,
+                    backoff_ms = backoff.as_millis() as u64,
+                    error = format!("{:#}", e)
+                );
+                tokio::time::sleep(backoff).await;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    stored.ok_or_else(|| {
+        MissingOperatorMetadata {
+            job_id: checkpoint.job_id.clone(),
+            operator_id: operator_id.to_string(),
+            epoch: checkpoint.epoch,
+        }
+        .into()
+    })
+}
+
+/// The metadata restored for an operator that was stateless when the checkpoint was taken:
+/// its watermarks and no tables, so that it starts with empty tables if it now has any.
+fn stateless_operator_metadata(operator_metadata: OperatorMetadata) -> OperatorCheckpointMetadata {
+    OperatorCheckpointMetadata {
+        backend: StateBackendKind::default().name().to_string(),
+        has_state: false,
+        format_version: METADATA_FORMAT_VERSION,
+        operator_metadata: Some(operator_metadata),
+        ..Default::default()
+    }
+}
+
+/// Whether an operator's state is restored through one of the checkpoint's remappings.
+pub fn is_remapped(checkpoint: &CheckpointMetadata, operator_id: &str) -> bool {
+    checkpoint
+        .operator_remappings
+        .iter()
+        .any(|remapping| remapping.new_operator_id == operator_id)
+}
+
+/// Checks that the tables an operator restores through a remapping can be loaded by the
+/// tables it now declares, so that a remapping onto the wrong operator fails before any state
+/// is loaded. Restores without a remapping drop tables that are no longer declared instead,
+/// with [`retain_declared_tables`].
+pub fn validate_restored_tables(
+    restored: &OperatorCheckpointMetadata,
+    table_configs: &HashMap<String, TableConfig>,
+) -> Result<()> {
+    for (table, restored_config) in &restored.table_configs {
+        if !restored.table_checkpoint_metadata.contains_key(table) {
+            continue;
+        }
+        let config = table_configs
+            .get(table)
+            .ok_or_else(|| anyhow!("restored state has table {}, but it's not declared", table))?;
+        if config.table_type() != restored_config.table_type() {
+            bail!(
+                "table {} was restored as {:?}, but is declared as {:?}",
+                table,
+                restored_config.table_type(),
+                config.table_type()
+            );
+        }
+        if config.table_type() == TableEnum::ExpiringKeyedTimeTable {
+            let restored_schema =
+                ExpiringKeyedTimeTableConfig::decode(&restored_config.config[..])?.schema;
+            let schema = ExpiringKeyedTimeTableConfig::decode(&config.config[..])?.schema;
+            if restored_schema != schema {
+                bail!(
+                    "table {} was restored with a different schema than it's declared with",
+                    table
+                );
+            }
+        }
+    }
+    validate_key_groups(restored, table_configs)
+}
+
+/// Checks that restored keyed tables are declared with the key groups they were checkpointed
+/// with. Keys are tagged with their key group and files are organized by it, so the groups
+/// can't be redrawn after the fact, whether or not the operator was remapped.
+pub fn validate_key_groups(
+    restored: &OperatorCheckpointMetadata,
+    table_configs: &HashMap<String, TableConfig>,
+) -> Result<()> {
+    // tables stored in the global keyed table format
+    let global_keyed = |config: &TableConfig| {
+        matches!(
+            config.table_type(),
+            TableEnum::GlobalKeyValue | TableEnum::KeyTimeMap
+        )
+    };
+    for (table, restored_config) in &restored.table_configs {
+        let Some(config) = table_configs.get(table) else {
+            continue;
+        };
+        if !restored.table_checkpoint_metadata.contains_key(table)
+            || !global_keyed(config)
+            || !global_keyed(restored_config)
+        {
+            continue;
+        }
+        let restored_config = GlobalKeyedTableConfig::decode(&restored_config.config[..])?;
+        let config = GlobalKeyedTableConfig::decode(&config.config[..])?;
+        if restored_config.partitioning() == KeyPartitioning::KeyGroup
+            && config.partitioning() == KeyPartitioning::KeyGroup
+            && restored_config.key_groups != config.key_groups
+        {
+            bail!(
+                "table {} was checkpointed with {} key groups, but is declared with {}; \
+                the number of key groups can't be changed once a job has state",
+                table,
+                restored_config.key_groups,
+                config.key_groups
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Leaves out the restored tables that the operator no longer declares, whose state is
+/// dropped, returning their names.
+pub fn retain_declared_tables(
+    restored: &mut OperatorCheckpointMetadata,
+    table_configs: &HashMap<String, TableConfig>,
+) -> Vec<String> {
+    let mut dropped = vec![];
+    restored.table_checkpoint_metadata.retain(|table, _| {
+        let declared = table_configs.contains_key(table);
+        if !declared {
+            dropped.push(table.clone());
+        }
+        declared
+    });
+    dropped.sort();
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use arroyo_rpc::grpc::{
+        GlobalKeyedTableTaskCheckpointMetadata, InFlightFile, OperatorMetadata,
+        SubtaskInFlightFiles, TableCheckpointMetadata,
+    };
+
+    use super::*;
+    use crate::in_memory::InMemoryBackingStore;
+    use crate::tables::global_keyed_map::GlobalKeyedTable;
+    use crate::tables::Table;
+    use crate::{
+        global_table_config, key_group_table_config, key_time_map_table_config, keyed_table_config,
+    };
+
+    #[tokio::test]
+    async fn test_restore_remapped_checkpoint() {
+        let job_id = "remapping-restore";
+        let table_metadata = TableCheckpointMetadata {
+            table_type: TableEnum::GlobalKeyValue.into(),
+            data: GlobalKeyedTableTaskCheckpointMetadata {
+                files: vec!["file".to_string()],
+                ..Default::default()
+            }
+            .encode_to_vec(),
+        };
+        InMemoryBackingStore::write_operator_checkpoint_metadata(OperatorCheckpointMetadata {
+            operator_metadata: Some(OperatorMetadata {
+                job_id: job_id.to_string(),
+                operator_id: "op".to_string(),
+                epoch: 3,
+                parallelism: 1,
+                ..Default::default()
+            }),
+            table_configs: global_table_config("t", "test"),
+            table_checkpoint_metadata: HashMap::from([("t".to_string(), table_metadata.clone())]),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        InMemoryBackingStore::write_checkpoint_metadata(CheckpointMetadata {
+            job_id: job_id.to_string(),
+            epoch: 3,
+            min_epoch: 1,
+            operator_ids: vec!["op".to_string(), "other".to_string()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        InMemoryBackingStore::set_operator_remapping(
+            job_id,
+            3,
+            parse_operator_remapping("op op_v2 t=t2").unwrap(),
+        );
+
+        // restored by a fresh process
+        let snapshot = InMemoryBackingStore::snapshot(job_id);
+        InMemoryBackingStore::clear(job_id);
+        InMemoryBackingStore::restore(job_id, snapshot).unwrap();
+
+        let checkpoint = load_checkpoint_for_restore_from::<InMemoryBackingStore>(job_id, 3)
+            .await
+            .unwrap();
+        assert_eq!(checkpoint.operator_ids, vec!["op_v2", "other"]);
+
+        let restored =
+            load_restored_operator_metadata_from::<InMemoryBackingStore>(&checkpoint, "op_v2")
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(
+            restored.operator_metadata.unwrap().operator_id,
+            "op_v2".to_string()
+        );
+        assert!(restored.table_configs.contains_key("t2"));
+        assert_eq!(
+            restored.table_checkpoint_metadata,
+            HashMap::from([("t2".to_string(), table_metadata)])
+        );
+        assert_eq!(
+            load_restored_operator_metadata_from::<InMemoryBackingStore>(&checkpoint, "other")
+                .await
+                .unwrap(),
+            None
+        );
+        let missing =
+            require_restored_operator_metadata_from::<InMemoryBackingStore>(&checkpoint, "other")
+                .await
+                .unwrap_err();
+        assert_eq!(
+            missing.downcast_ref::<MissingOperatorMetadata>(),
+            Some(&MissingOperatorMetadata {
+                job_id: job_id.to_string(),
+                operator_id: "other".to_string(),
+                epoch: 3,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_require_operator_metadata_retries_transient_errors() {
+        let job_id = "remapping-read-retries";
+        InMemoryBackingStore::write_operator_checkpoint_metadata(OperatorCheckpointMetadata {
+            operator_metadata: Some(OperatorMetadata {
+                job_id: job_id.to_string(),
+                operator_id: "op".to_string(),
+                epoch: 1,
+                parallelism: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let checkpoint = CheckpointMetadata {
+            job_id: job_id.to_string(),
+            epoch: 1,
+            min_epoch: 1,
+            operator_ids: vec!["op".to_string(), "other".to_string()],
+            ..Default::default()
+        };
+        let policy = RetryPolicy {
+            max_retries: 2,
+            base_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            jitter: 0.0,
+        };
+        let transient = || {
+            anyhow::Error::from(arroyo_storage::StorageError::ObjectStore(
+                object_store::Error::Generic {
+                    store: "test",
+                    source: "503 Slow Down".into(),
+                },
+            ))
+        };
+        let require = |operator_id: &'static str| {
+            require_restored_operator_metadata_with::<InMemoryBackingStore>(
+                &checkpoint,
+                operator_id,
+                policy,
+            )
+        };
+
+        // a store that's briefly unreachable is read again
+        InMemoryBackingStore::fail_operator_metadata_reads(
+            job_id,
+            "op",
+            1,
+            [transient(), transient()],
+        );
+        assert_eq!(
+            require("op")
+                .await
+                .unwrap()
+                .operator_metadata
+                .unwrap()
+                .operator_id,
+            "op"
+        );
+
+        // until the retries run out
+        InMemoryBackingStore::fail_operator_metadata_reads(
+            job_id,
+            "op",
+            1,
+            [transient(), transient(), transient()],
+        );
+        let unreachable = require("op").await.unwrap_err();
+        assert!(is_transient(&unreachable), "{:#}", unreachable);
+        assert!(unreachable
+            .downcast_ref::<MissingOperatorMetadata>()
+            .is_none());
+
+        // metadata that can't be read fails the restore without retries, and isn't missing
+        InMemoryBackingStore::fail_operator_metadata_reads(
+            job_id,
+            "op",
+            1,
+            [anyhow!("failed to decode operator metadata"), transient()],
+        );
+        let unreadable = require("op").await.unwrap_err();
+        assert!(
+            format!("{:#}", unreadable).contains("failed to decode"),
+            "{:#}",
+            unreadable
+        );
+        assert!(unreadable
+            .downcast_ref::<MissingOperatorMetadata>()
+            .is_none());
+        InMemoryBackingStore::clear(job_id);
+
+        // while metadata that was never written is missing
+        assert!(require("other")
+            .await
+            .unwrap_err()
+            .downcast_ref::<MissingOperatorMetadata>()
+            .is_some());
+    }
+
+    fn remapping(old_operator_id: &str, new_operator_id: &str) -> OperatorRemapping {
+        OperatorRemapping {
+            old_operator_id: old_operator_id.to_string(),
+            new_operator_id: new_operator_id.to_string(),
+            table_names: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_supply_operator_remapping() {
+        let job_id = "remapping-supplied";
+        InMemoryBackingStore::write_checkpoint_metadata(CheckpointMetadata {
+            job_id: job_id.to_string(),
+            epoch: 2,
+            min_epoch: 1,
+            operator_ids: vec!["a".to_string(), "b".to_string()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // conflicting remappings are rejected, and nothing is stored
+        for (remappings, error) in [
+            (
+                vec![remapping("a", "x"), remapping("b", "x")],
+                "operators a and b are both remapped to x",
+            ),
+            (
+                vec![remapping("a", "b")],
+                "checkpoint 2 already has an operator b",
+            ),
+            (
+                vec![remapping("a", "x"), remapping("a", "y")],
+                "operator a is remapped to both x and y",
+            ),
+            (vec![remapping("c", "x")], "not in checkpoint 2"),
+        ] {
+            let err = supply_operator_remapping_to::<InMemoryBackingStore>(job_id, 2, remappings)
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains(error), "{}", err);
+        }
+        assert!(InMemoryBackingStore::load_operator_remapping(job_id, 2)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut renamed = remapping("a", "a_v2");
+        renamed.table_names = HashMap::from([("t".to_string(), "t2".to_string())]);
+        supply_operator_remapping_to::<InMemoryBackingStore>(job_id, 2, vec![renamed.clone()])
+            .await
+            .unwrap();
+        let checkpoint = load_checkpoint_for_restore_from::<InMemoryBackingStore>(job_id, 2)
+            .await
+            .unwrap();
+        assert_eq!(checkpoint.operator_ids, vec!["a_v2", "b"]);
+        assert_eq!(checkpoint.operator_remappings, vec![renamed.clone()]);
+
+        // stored remappings read back as they were supplied
+        assert_eq!(
+            parse_operator_remapping(&format_operator_remapping(&[
+                renamed.clone(),
+                remapping("b", "b_v2")
+            ]))
+            .unwrap(),
+            vec![renamed, remapping("b", "b_v2")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_find_restorable_checkpoint() {
+        let job_id = "find-restorable-checkpoint";
+        let write_operator = |operator_id: &str, epoch: u32| {
+            InMemoryBackingStore::write_operator_checkpoint_metadata(OperatorCheckpointMetadata {
+                operator_metadata: Some(OperatorMetadata {
+                    job_id: job_id.to_string(),
+                    operator_id: operator_id.to_string(),
+                    epoch,
+                    parallelism: 1,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        };
+        let write_checkpoint = |epoch: u32| {
+            InMemoryBackingStore::write_checkpoint_metadata(CheckpointMetadata {
+                job_id: job_id.to_string(),
+                epoch,
+                min_epoch: 1,
+                operator_ids: vec!["a".to_string(), "b".to_string()],
+                ..Default::default()
+            })
+        };
+
+        // epoch 1 is complete, epoch 2 never wrote its checkpoint metadata, and epoch 3 is
+        // missing the metadata of one of its operators
+        for operator_id in ["a", "b"] {
+            write_operator(operator_id, 1).await.unwrap();
+            write_operator(operator_id, 2).await.unwrap();
+        }
+        write_operator("a", 3).await.unwrap();
+        write_checkpoint(1).await.unwrap();
+        write_checkpoint(3).await.unwrap();
+
+        assert_eq!(
+            find_restorable_checkpoint_from::<InMemoryBackingStore>(job_id, 3, 1)
+                .await
+                .unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            find_restorable_checkpoint_from::<InMemoryBackingStore>(job_id, 3, 2)
+                .await
+                .unwrap(),
+            None
+        );
+
+        write_operator("b", 3).await.unwrap();
+        assert_eq!(
+            find_restorable_checkpoint_from::<InMemoryBackingStore>(job_id, 3, 1)
+                .await
+                .unwrap(),
+            Some(3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_chosen_epoch() {
+        let job_id = "restore-chosen-epoch";
+        assert!(list_restorable_epochs_from::<InMemoryBackingStore>(job_id)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(
+            load_epoch_for_restore_from::<InMemoryBackingStore>(job_id, 1)
+                .await
+                .is_err()
+        );
+
+        // epoch 1 has been cleaned up by epoch 4, and epoch 3 is missing its operator's
+        // metadata
+        for epoch in 1..=4 {
+            if epoch != 3 {
+                InMemoryBackingStore::write_operator_checkpoint_metadata(
+                    OperatorCheckpointMetadata {
+                        operator_metadata: Some(OperatorMetadata {
+                            job_id: job_id.to_string(),
+                            operator_id: "op".to_string(),
+                            epoch,
+                            parallelism: 1,
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+            }
+            InMemoryBackingStore::write_checkpoint_metadata(CheckpointMetadata {
+                job_id: job_id.to_string(),
+                epoch,
+                min_epoch: if epoch == 4 { 2 } else { 1 },
+                operator_ids: vec!["op".to_string()],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            list_restorable_epochs_from::<InMemoryBackingStore>(job_id)
+                .await
+                .unwrap(),
+            vec![2, 4]
+        );
+        let restored = load_epoch_for_restore_from::<InMemoryBackingStore>(job_id, 2)
+            .await
+            .unwrap();
+        assert_eq!(restored.epoch, 2);
+
+        let err = load_epoch_for_restore_from::<InMemoryBackingStore>(job_id, 1)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("cleaned up"));
+        let err = load_epoch_for_restore_from::<InMemoryBackingStore>(job_id, 3)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("fully written"));
+        let err = load_epoch_for_restore_from::<InMemoryBackingStore>(job_id, 5)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("latest checkpoint"));
+    }
+
+    #[tokio::test]
+    async fn test_non_restored_state() {
+        let job_id = "non-restored-state";
+        for operator_id in ["kept", "removed"] {
+            InMemoryBackingStore::write_operator_checkpoint_metadata(OperatorCheckpointMetadata {
+                operator_metadata: Some(OperatorMetadata {
+                    job_id: job_id.to_string(),
+                    operator_id: operator_id.to_string(),
+                    epoch: 1,
+                    parallelism: 1,
+                    ..Default::default()
+                }),
+                table_configs: global_table_config("t", "test"),
+                table_checkpoint_metadata: HashMap::from([(
+                    "t".to_string(),
+                    TableCheckpointMetadata {
+                        table_type: TableEnum::GlobalKeyValue.into(),
+                        data: GlobalKeyedTableTaskCheckpointMetadata {
+                            files: vec![format!("{}-file", operator_id)],
+                            file_sizes: HashMap::from([(format!("{}-file", operator_id), 10)]),
+                            ..Default::default()
+                        }
+                        .encode_to_vec(),
+                    },
+                )]),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        let checkpoint = CheckpointMetadata {
+            job_id: job_id.to_string(),
+            epoch: 1,
+            operator_ids: vec![
+                "kept".to_string(),
+                "removed".to_string(),
+                "stateless".to_string(),
+            ],
+            stateless_operators: HashMap::from([(
+                "stateless".to_string(),
+                OperatorMetadata::default(),
+            )]),
+            ..Default::default()
+        };
+        InMemoryBackingStore::write_checkpoint_metadata(checkpoint.clone())
+            .await
+            .unwrap();
+        let job_operator_ids = HashSet::from(["kept".to_string(), "added".to_string()]);
+
+        // strict restores fail on the removed operator's state
+        let err = reconcile_restored_operators_from::<InMemoryBackingStore>(
+            &mut checkpoint.clone(),
+            &job_operator_ids,
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("removed"));
+        assert!(!err.to_string().contains("stateless"));
+
+        let mut restored = checkpoint.clone();
+        let non_restored = reconcile_restored_operators_from::<InMemoryBackingStore>(
+            &mut restored,
+            &job_operator_ids,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(non_restored.dropped_operators.len(), 1);
+        let (operator_id, tables) = &non_restored.dropped_operators[0];
+        assert_eq!(operator_id, "removed");
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].table_name, "t");
+        assert_eq!(tables[0].bytes, 10);
+        assert_eq!(non_restored.new_operators, vec!["added".to_string()]);
+        assert_eq!(restored.operator_ids, vec!["kept".to_string()]);
+
+        // a job with the same operators leaves nothing behind, in either mode
+        let mut same = checkpoint.clone();
+        let operator_ids = checkpoint.operator_ids.iter().cloned().collect();
+        assert!(reconcile_restored_operators_from::<InMemoryBackingStore>(
+            &mut same,
+            &operator_ids,
+            false
+        )
+        .await
+        .unwrap()
+        .is_empty());
+        assert_eq!(same, checkpoint);
+    }
+
+    #[tokio::test]
+    async fn test_fork_checkpoint() {
+        let source = "fork-source";
+        let target = "fork-target";
+        let source_operator = OperatorCheckpointMetadata {
+            operator_metadata: Some(OperatorMetadata {
+                job_id: source.to_string(),
+                operator_id: "op".to_string(),
+                epoch: 2,
+                parallelism: 1,
+                ..Default::default()
+            }),
+            table_configs: global_table_config("t", "test"),
+            table_checkpoint_metadata: HashMap::from([(
+                "t".to_string(),
+                TableCheckpointMetadata {
+                    table_type: TableEnum::GlobalKeyValue.into(),
+                    data: GlobalKeyedTableTaskCheckpointMetadata {
+                        files: vec!["file".to_string()],
+                        file_sizes: HashMap::from([("file".to_string(), 10)]),
+                        file_epochs: HashMap::from([("file".to_string(), 1)]),
+                        ..Default::default()
+                    }
+                    .encode_to_vec(),
+                },
+            )]),
+            in_flight: HashMap::from([(
+                0,
+                SubtaskInFlightFiles {
+                    files: vec![InFlightFile {
+                        file: "in-flight".to_string(),
+                        ..Default::default()
+                    }],
+                },
+            )]),
+            ..Default::default()
+        };
+        InMemoryBackingStore::write_operator_checkpoint_metadata(source_operator.clone())
+            .await
+            .unwrap();
+        let source_checkpoint = CheckpointMetadata {
+            job_id: source.to_string(),
+            epoch: 2,
+            min_epoch: 1,
+            operator_ids: vec!["op".to_string(), "stateless".to_string()],
+            stateless_operators: HashMap::from([(
+                "stateless".to_string(),
+                OperatorMetadata {
+                    job_id: source.to_string(),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        InMemoryBackingStore::write_checkpoint_metadata(source_checkpoint.clone())
+            .await
+            .unwrap();
+
+        let err = fork_checkpoint_from::<InMemoryBackingStore>(source, 2, source)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("same job"));
+        assert!(
+            fork_checkpoint_from::<InMemoryBackingStore>(source, 3, target)
+                .await
+                .is_err()
+        );
+
+        let forked = fork_checkpoint_from::<InMemoryBackingStore>(source, 2, target)
+            .await
+            .unwrap();
+        assert_eq!(forked.job_id, target);
+        assert_eq!(forked.min_epoch, 2);
+        assert_eq!(forked.stateless_operators["stateless"].job_id, target);
+
+        // the new job restores from its own copies
+        let restored = load_epoch_for_restore_from::<InMemoryBackingStore>(target, 2)
+            .await
+            .unwrap();
+        let operator =
+            load_restored_operator_metadata_from::<InMemoryBackingStore>(&restored, "op")
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(operator.operator_metadata.unwrap().job_id, target);
+        let table = GlobalKeyedTableTaskCheckpointMetadata::decode(
+            operator.table_checkpoint_metadata["t"].data.as_slice(),
+        )
+        .unwrap();
+        let copy = format!("{}/forked/file", target);
+        assert_eq!(table.files, vec![copy.clone()]);
+        assert_eq!(table.file_sizes, HashMap::from([(copy.clone(), 10)]));
+        assert_eq!(table.file_epochs, HashMap::from([(copy, 1)]));
+        assert_eq!(
+            operator.in_flight[&0].files[0].file,
+            format!("{}/forked/in-flight", target)
+        );
+
+        // the source is untouched, and a job can only be forked into once
+        assert_eq!(
+            InMemoryBackingStore::load_operator_metadata(source, "op", 2)
+                .await
+                .unwrap()
+                .unwrap()
+                .table_checkpoint_metadata,
+            source_operator.table_checkpoint_metadata
+        );
+        assert_eq!(
+            InMemoryBackingStore::load_checkpoint_metadata(source, 2)
+                .await
+                .unwrap()
+                .job_id,
+            source
+        );
+        let err = fork_checkpoint_from::<InMemoryBackingStore>(source, 2, target)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("already has checkpoints"));
+    }
+
+    #[test]
+    fn test_key_groups_cant_change() {
+        let restored = OperatorCheckpointMetadata {
+            table_configs: key_group_table_config("t", "test"),
+            table_checkpoint_metadata: HashMap::from([(
+                "t".to_string(),
+                TableCheckpointMetadata {
+                    table_type: TableEnum::GlobalKeyValue.into(),
+                    data: vec![],
+                },
+            )]),
+            ..Default::default()
+        };
+        validate_restored_tables(&restored, &key_group_table_config("t", "test")).unwrap();
+
+        let mut table_configs = key_group_table_config("t", "test");
+        let table_config = table_configs.get_mut("t").unwrap();
+        let mut config = GlobalKeyedTableConfig::decode(&table_config.config[..]).unwrap();
+        config.key_groups *= 2;
+        table_config.config = config.encode_to_vec();
+        let err = validate_restored_tables(&restored, &table_configs).unwrap_err();
+        assert!(err.to_string().contains("key groups"));
+        // which is checked for every restore, remapped or not
+        let err = validate_key_groups(&restored, &table_configs).unwrap_err();
+        assert!(err.to_string().contains("key groups"));
+    }
+
+    #[test]
+    fn test_key_time_maps_restore_as_key_time_maps() {
+        let restored = OperatorCheckpointMetadata {
+            table_configs: key_time_map_table_config("t", "test"),
+            table_checkpoint_metadata: HashMap::from([(
+                "t".to_string(),
+                TableCheckpointMetadata {
+                    table_type: TableEnum::GlobalKeyValue.into(),
+                    data: vec![],
+                },
+            )]),
+            ..Default::default()
+        };
+        validate_restored_tables(&restored, &key_time_map_table_config("t", "test")).unwrap();
+        // they share the global keyed table format, but not their contents
+        let err =
+            validate_restored_tables(&restored, &keyed_table_config("t", "test")).unwrap_err();
+        assert!(err.to_string().contains("KeyTimeMap"), "{}", err);
+        assert!(<GlobalKeyedTable as Table>::stores_table_type(
+            TableEnum::KeyTimeMap
+        ));
+    }
+
+    #[test]
+    fn test_dropped_tables() {
+        let table_metadata = TableCheckpointMetadata {
+            table_type: TableEnum::GlobalKeyValue.into(),
+            data: vec![],
+        };
+        let mut restored = OperatorCheckpointMetadata {
+            table_configs: keyed_table_config("t", "test")
+                .into_iter()
+                .chain(keyed_table_config("dropped", "test"))
+                .collect(),
+            table_checkpoint_metadata: HashMap::from([
+                ("t".to_string(), table_metadata.clone()),
+                ("dropped".to_string(), table_metadata),
+            ]),
+            ..Default::default()
+        };
+        let table_configs = keyed_table_config("t", "test");
+
+        // state remapped onto an operator that doesn't declare one of its tables is refused
+        let err = validate_restored_tables(&restored, &table_configs).unwrap_err();
+        assert!(err.to_string().contains("not declared"), "{}", err);
+
+        // while an operator that's dropped a table restores the rest
+        validate_key_groups(&restored, &table_configs).unwrap();
+        assert_eq!(
+            retain_declared_tables(&mut restored, &table_configs),
+            vec!["dropped".to_string()]
+        );
+        assert_eq!(
+            restored
+                .table_checkpoint_metadata
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["t"]
+        );
+        validate_restored_tables(&restored, &table_configs).unwrap();
+        assert!(retain_declared_tables(&mut restored, &table_configs).is_empty());
+    }
+
+    #[test]
+    fn test_is_remapped() {
+        let checkpoint = CheckpointMetadata {
+            operator_remappings: vec![remapping("old", "new")],
+            ..Default::default()
+        };
+        assert!(is_remapped(&checkpoint, "new"));
+        assert!(!is_remapped(&checkpoint, "old"));
+        assert!(!is_remapped(&checkpoint, "other"));
+    }
+}
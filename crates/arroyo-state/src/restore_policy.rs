@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::env;
+
+use anyhow::{anyhow, Context, Result};
+use arroyo_rpc::grpc::{TableConfig, TableRestoreNote, TableRestorePolicy};
+
+use crate::restore_progress::RestoreProgress;
+
+/// Restore policies that take precedence over the ones tables are configured with, for
+/// restoring a checkpoint whose data for some tables is known to be bad. A JSON object
+/// mapping operator ids to objects that map their table names to a policy name, like
+/// `{"my_operator": {"counts": "reset"}}`.
+pub const STATE_RESTORE_POLICY_OVERRIDES_ENV: &str = "STATE_RESTORE_POLICY_OVERRIDES";
+
+/// Parses a restore policy from its name: `strict`, `skip_corrupt`, or `reset`.
+pub fn restore_policy_from_name(name: &str) -> Result<TableRestorePolicy> {
+    TableRestorePolicy::from_str_name(&name.to_ascii_uppercase()).ok_or_else(|| {
+        anyhow!(
+            "unknown table restore policy '{}'; expected strict, skip_corrupt, or reset",
+            name
+        )
+    })
+}
+
+/// The restore policies set for a restore, rather than in the tables' configs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestorePolicyOverrides {
+    operators: HashMap<String, HashMap<String, TableRestorePolicy>>,
+}
+
+impl RestorePolicyOverrides {
+    pub fn from_env() -> Result<Self> {
+        match env::var(STATE_RESTORE_POLICY_OVERRIDES_ENV) {
+            Ok(overrides) => Self::parse(&overrides)
+                .with_context(|| format!("invalid {}", STATE_RESTORE_POLICY_OVERRIDES_ENV)),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Parses overrides in the format of `STATE_RESTORE_POLICY_OVERRIDES`.
+    pub fn parse(overrides: &str) -> Result<Self> {
+        let operators: HashMap<String, HashMap<String, String>> = serde_json::from_str(overrides)?;
+        Ok(Self {
+            operators: operators
+                .into_iter()
+                .map(|(operator_id, tables)| {
+                    let tables = tables
+                        .into_iter()
+                        .map(|(table, policy)| Ok((table, restore_policy_from_name(&policy)?)))
+                        .collect::<Result<_>>()?;
+                    Ok((operator_id, tables))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// The policy `table_name` of `operator_id` is restored with: its override, if it has
+    /// one, and otherwise the policy in its config.
+    pub fn policy(
+        &self,
+        operator_id: &str,
+        table_name: &str,
+        config: &TableConfig,
+    ) -> TableRestorePolicy {
+        self.operators
+            .get(operator_id)
+            .and_then(|tables| tables.get(table_name))
+            .copied()
+            .unwrap_or_else(|| config.restore_policy())
+    }
+
+    /// The tables of `operator_id` with overrides that aren't among `table_configs`.
+    pub fn unknown_tables<'a>(
+        &'a self,
+        operator_id: &str,
+        table_configs: &HashMap<String, TableConfig>,
+    ) -> Vec<&'a str> {
+        let mut unknown: Vec<_> = self
+            .operators
+            .get(operator_id)
+            .into_iter()
+            .flat_map(|tables| tables.keys())
+            .filter(|table| !table_configs.contains_key(*table))
+            .map(|table| table.as_str())
+            .collect();
+        unknown.sort();
+        unknown
+    }
+}
+
+/// Tracks the tables of a subtask whose restore departed from the checkpoint, to note them
+/// in the metadata of its checkpoints: a reset table in the first checkpoint after the
+/// restore, and a table that dropped data in each checkpoint after more was dropped.
+#[derive(Debug)]
+pub(crate) struct RestoreNotes {
+    subtask_index: u32,
+    restored_epoch: u32,
+    // the tables restored with a policy other than strict
+    policies: HashMap<String, TableRestorePolicy>,
+    progress: RestoreProgress,
+    // the tuples skipped as of the last note for each table that's been noted
+    noted: HashMap<String, u64>,
+}
+
+impl RestoreNotes {
+    pub(crate) fn new(
+        subtask_index: u32,
+        restored_epoch: u32,
+        policies: HashMap<String, TableRestorePolicy>,
+        progress: RestoreProgress,
+    ) -> Self {
+        Self {
+            subtask_index,
+            restored_epoch,
+            policies: policies
+                .into_iter()
+                .filter(|(_, policy)| *policy != TableRestorePolicy::Strict)
+                .collect(),
+            progress,
+            noted: HashMap::new(),
+        }
+    }
+
+    /// The notes that haven't yet been included in a checkpoint, ordered by table.
+    pub(crate) fn take(&mut self) -> Vec<TableRestoreNote> {
+        if self.policies.is_empty() {
+            return vec![];
+        }
+        let tables = self.progress.snapshot().tables;
+        let mut notes = vec![];
+        for (table_name, policy) in &self.policies {
+            let skipped = tables
+                .get(table_name)
+                .map(|stats| stats.skipped)
+                .unwrap_or_default();
+            let due = match (policy, self.noted.get(table_name)) {
+                (TableRestorePolicy::Reset, noted) => noted.is_none(),
+                (_, noted) => skipped > noted.copied().unwrap_or_default(),
+            };
+            if !due {
+                continue;
+            }
+            self.noted.insert(table_name.clone(), skipped);
+            notes.push(TableRestoreNote {
+                table_name: table_name.clone(),
+                subtask_index: self.subtask_index,
+                policy: (*policy).into(),
+                restored_epoch: self.restored_epoch,
+                skipped_tuples: skipped,
+            });
+        }
+        notes.sort_by(|a, b| a.table_name.cmp(&b.table_name));
+        notes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arroyo_types::TaskInfo;
+
+    use super::*;
+    use crate::with_restore_policy;
+
+    #[test]
+    fn test_overrides() {
+        let overrides =
+            RestorePolicyOverrides::parse(r#"{"op": {"counts": "reset", "sums": "SKIP_CORRUPT"}}"#)
+                .unwrap();
+        let config = with_restore_policy(TableConfig::default(), TableRestorePolicy::SkipCorrupt);
+        assert_eq!(
+            overrides.policy("op", "counts", &config),
+            TableRestorePolicy::Reset
+        );
+        assert_eq!(
+            overrides.policy("op", "sums", &TableConfig::default()),
+            TableRestorePolicy::SkipCorrupt
+        );
+        assert_eq!(
+            overrides.policy("op", "other", &config),
+            TableRestorePolicy::SkipCorrupt
+        );
+        assert_eq!(
+            overrides.policy("other", "counts", &TableConfig::default()),
+            TableRestorePolicy::Strict
+        );
+        assert_eq!(
+            overrides.unknown_tables("op", &HashMap::from([("counts".to_string(), config)])),
+            vec!["sums"]
+        );
+
+        assert!(RestorePolicyOverrides::parse(r#"{"op": {"counts": "drop"}}"#).is_err());
+        assert!(RestorePolicyOverrides::parse("op.counts=reset").is_err());
+    }
+
+    #[test]
+    fn test_restore_notes() {
+        let task_info = TaskInfo::for_test("job", "op");
+        let progress = RestoreProgress::new(&task_info);
+        let mut notes = RestoreNotes::new(
+            0,
+            5,
+            HashMap::from([
+                ("counts".to_string(), TableRestorePolicy::Reset),
+                ("sums".to_string(), TableRestorePolicy::SkipCorrupt),
+                ("strict".to_string(), TableRestorePolicy::Strict),
+            ]),
+            progress.clone(),
+        );
+
+        let first = notes.take();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].table_name, "counts");
+        assert_eq!(first[0].policy(), TableRestorePolicy::Reset);
+        assert_eq!(first[0].restored_epoch, 5);
+
+        // skipped tuples are noted once they're found, and again if more are
+        let sums = progress.table("sums");
+        sums.tuples_skipped(3);
+        let second = notes.take();
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].table_name, "sums");
+        assert_eq!(second[0].skipped_tuples, 3);
+        assert!(notes.take().is_empty());
+
+        sums.tuples_skipped(2);
+        assert_eq!(notes.take()[0].skipped_tuples, 5);
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+use arroyo_types::TaskInfo;
+use prometheus::IntCounter;
+
+use crate::metrics::{
+    RESTORE_BYTES_COUNTER, RESTORE_FILES_COUNTER, RESTORE_SKIPPED_TUPLES_COUNTER,
+    RESTORE_TUPLES_COUNTER,
+};
+
+/// How far restoring a table has got.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableRestoreStats {
+    /// Checkpoint files read.
+    pub files: u64,
+    /// Entries of global keyed tables, or rows of time-keyed tables, applied to the table.
+    pub tuples: u64,
+    /// Bytes of the files read, as stored.
+    pub bytes: u64,
+    /// Entries or rows dropped as they failed to decode, by tables restored with
+    /// `TableRestorePolicy::SkipCorrupt`.
+    pub skipped: u64,
+}
+
+impl TableRestoreStats {
+    pub fn merge(&mut self, other: &TableRestoreStats) {
+        self.files += other.files;
+        self.tuples += other.tuples;
+        self.bytes += other.bytes;
+        self.skipped += other.skipped;
+    }
+}
+
+/// The restore progress of each of a subtask's tables, at one point in time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubtaskRestoreProgress {
+    pub operator_id: String,
+    pub task_index: u32,
+    pub tables: BTreeMap<String, TableRestoreStats>,
+}
+
+type ProgressCallback = dyn Fn(&str, &TableRestoreStats) + Send + Sync;
+
+struct Inner {
+    operator_id: String,
+    task_index: u32,
+    tables: Mutex<BTreeMap<String, TableRestoreStats>>,
+    callback: Option<Box<ProgressCallback>>,
+}
+
+/// The progress of restoring a subtask's tables, shared by the tables as they're read. Clones
+/// share the same progress, so a handle kept by whoever created it sees the tables' updates.
+#[derive(Clone)]
+pub struct RestoreProgress {
+    inner: Arc<Inner>,
+}
+
+impl Debug for RestoreProgress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RestoreProgress")
+            .field("operator_id", &self.inner.operator_id)
+            .field("task_index", &self.inner.task_index)
+            .finish()
+    }
+}
+
+impl RestoreProgress {
+    pub fn new(task_info: &TaskInfo) -> Self {
+        Self::build(task_info, None)
+    }
+
+    /// Progress that also calls `callback` with a table's totals every time they change.
+    pub fn with_callback(
+        task_info: &TaskInfo,
+        callback: impl Fn(&str, &TableRestoreStats) + Send + Sync + 'static,
+    ) -> Self {
+        Self::build(task_info, Some(Box::new(callback)))
+    }
+
+    fn build(task_info: &TaskInfo, callback: Option<Box<ProgressCallback>>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                operator_id: task_info.operator_id.clone(),
+                task_index: task_info.task_index as u32,
+                tables: Mutex::new(BTreeMap::new()),
+                callback,
+            }),
+        }
+    }
+
+    /// The handle a table reports its progress to.
+    pub fn table(&self, table_name: &str) -> TableRestoreProgress {
+        let labels = [
+            self.inner.operator_id.as_str(),
+            &self.inner.task_index.to_string(),
+            table_name,
+        ];
+        TableRestoreProgress {
+            progress: self.clone(),
+            table_name: table_name.to_string(),
+            files_counter: RESTORE_FILES_COUNTER.with_label_values(&labels),
+            tuples_counter: RESTORE_TUPLES_COUNTER.with_label_values(&labels),
+            bytes_counter: RESTORE_BYTES_COUNTER.with_label_values(&labels),
+            skipped_counter: RESTORE_SKIPPED_TUPLES_COUNTER.with_label_values(&labels),
+        }
+    }
+
+    pub fn snapshot(&self) -> SubtaskRestoreProgress {
+        SubtaskRestoreProgress {
+            operator_id: self.inner.operator_id.clone(),
+            task_index: self.inner.task_index,
+            tables: self.inner.tables.lock().unwrap().clone(),
+        }
+    }
+
+    fn update(&self, table_name: &str, f: impl FnOnce(&mut TableRestoreStats)) {
+        let stats = {
+            let mut tables = self.inner.tables.lock().unwrap();
+            let stats = tables.entry(table_name.to_string()).or_default();
+            f(stats);
+            *stats
+        };
+        if let Some(callback) = &self.inner.callback {
+            callback(table_name, &stats);
+        }
+    }
+}
+
+/// Reports the restore progress of one table, to its subtask's [`RestoreProgress`] and to
+/// the table's restore metrics.
+#[derive(Debug, Clone)]
+pub struct TableRestoreProgress {
+    progress: RestoreProgress,
+    table_name: String,
+    files_counter: IntCounter,
+    tuples_counter: IntCounter,
+    bytes_counter: IntCounter,
+    skipped_counter: IntCounter,
+}
+
+impl TableRestoreProgress {
+    /// Progress for a table that isn't part of a subtask's shared progress, which is only
+    /// reported to metrics.
+    pub(crate) fn detached(task_info: &TaskInfo, table_name: &str) -> Self {
+        RestoreProgress::new(task_info).table(table_name)
+    }
+
+    /// Records that a file of `bytes` has been read.
+    pub(crate) fn file_read(&self, bytes: u64) {
+        self.files_counter.inc();
+        self.bytes_counter.inc_by(bytes);
+        self.progress.update(&self.table_name, |stats| {
+            stats.files += 1;
+            stats.bytes += bytes;
+        });
+    }
+
+    /// Records that `tuples` entries or rows have been applied to the table.
+    pub(crate) fn tuples_applied(&self, tuples: u64) {
+        if tuples == 0 {
+            return;
+        }
+        self.tuples_counter.inc_by(tuples);
+        self.progress
+            .update(&self.table_name, |stats| stats.tuples += tuples);
+    }
+
+    /// Records that `tuples` entries or rows were dropped, as they failed to decode.
+    pub(crate) fn tuples_skipped(&self, tuples: u64) {
+        if tuples == 0 {
+            return;
+        }
+        self.skipped_counter.inc_by(tuples);
+        self.progress
+            .update(&self.table_name, |stats| stats.skipped += tuples);
+    }
+}
+
+/// The restore progress of an operator, aggregated from the reports of its subtasks, as the
+/// controller aggregates their checkpoint progress.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OperatorRestoreProgress {
+    pub subtasks: HashMap<u32, SubtaskRestoreProgress>,
+}
+
+impl OperatorRestoreProgress {
+    /// Records a subtask's latest progress, which replaces what it reported before.
+    pub fn update(&mut self, progress: SubtaskRestoreProgress) {
+        self.subtasks.insert(progress.task_index, progress);
+    }
+
+    /// The progress of each table, summed across subtasks.
+    pub fn tables(&self) -> BTreeMap<String, TableRestoreStats> {
+        let mut tables: BTreeMap<String, TableRestoreStats> = BTreeMap::new();
+        for subtask in self.subtasks.values() {
+            for (table_name, stats) in &subtask.tables {
+                tables.entry(table_name.clone()).or_default().merge(stats);
+            }
+        }
+        tables
+    }
+
+    /// The progress of every table of every subtask.
+    pub fn total(&self) -> TableRestoreStats {
+        let mut total = TableRestoreStats::default();
+        for stats in self.tables().values() {
+            total.merge(stats);
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operator_progress() {
+        let mut operator = OperatorRestoreProgress::default();
+        for task_index in 0..2 {
+            let mut task_info = TaskInfo::for_test("job", "op");
+            task_info.task_index = task_index;
+            let progress = RestoreProgress::new(&task_info);
+            let table = progress.table("t");
+            table.file_read(100);
+            table.tuples_applied(10);
+            operator.update(progress.snapshot());
+
+            // later reports replace earlier ones
+            table.tuples_applied(5);
+            operator.update(progress.snapshot());
+        }
+        assert_eq!(
+            operator.total(),
+            TableRestoreStats {
+                files: 2,
+                tuples: 30,
+                bytes: 200,
+                skipped: 0,
+            }
+        );
+        assert_eq!(operator.tables().len(), 1);
+    }
+}
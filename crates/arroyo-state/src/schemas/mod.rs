@@ -11,11 +11,11 @@ use arrow_ord::cmp::{gt_eq, lt_eq};
 use arrow_schema::{DataType, Field, Schema};
 use arroyo_rpc::df::{ArroyoSchema, ArroyoSchemaRef};
 use arroyo_rpc::get_hasher;
-use arroyo_types::from_nanos;
 use bincode::config;
 use datafusion_common::{hash_utils::create_hashes, ScalarValue};
 use tracing::warn;
 
+use crate::timestamps::from_timestamp_nanos;
 use crate::{parquet::ParquetStats, DataOperation};
 
 #[allow(unused)]
@@ -130,7 +130,7 @@ impl SchemaWithHashAndOperation {
             .ok_or_else(|| anyhow!("should be able to extract timestamp array"))?;
         let max_timestamp_nanos = max(timestamp_array).expect("should have max timestamp");
         Ok(ParquetStats {
-            max_timestamp: from_nanos(max_timestamp_nanos as u128),
+            max_timestamp: from_timestamp_nanos(max_timestamp_nanos),
             min_routing_key: hash_min,
             max_routing_key: hash_max,
         })
@@ -160,7 +160,7 @@ impl SchemaWithHashAndOperation {
         .unwrap();
 
         let batch_stats = ParquetStats {
-            max_timestamp: from_nanos(max_timestamp_nanos as u128),
+            max_timestamp: from_timestamp_nanos(max_timestamp_nanos),
             min_routing_key: hash_min,
             max_routing_key: hash_max,
         };
@@ -0,0 +1,223 @@
+use anyhow::{bail, Context, Result};
+use arroyo_rpc::grpc::TableConfig;
+use arroyo_types::Data;
+use bincode::{config, Decode, Encode};
+
+/// Encodes the keys and values that views write to global keyed tables, and decodes them
+/// on restore.
+pub trait StateSerde {
+    fn encode<T: Encode>(&self, value: &T) -> Result<Vec<u8>>;
+
+    fn decode<T: Decode>(&self, bytes: &[u8]) -> Result<T>;
+}
+
+/// bincode's standard configuration, with variable-length integers. The encoding used by
+/// every table before codecs were selectable.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeSerde;
+
+impl StateSerde for BincodeSerde {
+    fn encode<T: Encode>(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(bincode::encode_to_vec(value, config::standard())?)
+    }
+
+    fn decode<T: Decode>(&self, bytes: &[u8]) -> Result<T> {
+        Ok(bincode::decode_from_slice(bytes, config::standard())?.0)
+    }
+}
+
+/// bincode with fixed-width little-endian integers, the layout of bincode 1.x, which
+/// readers outside Arroyo can decode without implementing varints.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FixedIntBincodeSerde;
+
+impl StateSerde for FixedIntBincodeSerde {
+    fn encode<T: Encode>(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(bincode::encode_to_vec(value, config::legacy())?)
+    }
+
+    fn decode<T: Decode>(&self, bytes: &[u8]) -> Result<T> {
+        Ok(bincode::decode_from_slice(bytes, config::legacy())?.0)
+    }
+}
+
+/// The codec a table's keys and values are written with, selected per table through
+/// [`TableConfig::value_codec`] and recorded in its checkpoint metadata, so that restores
+/// decode with the codec the data was written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum StateCodec {
+    #[default]
+    Bincode,
+    FixedIntBincode,
+}
+
+impl StateCodec {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StateCodec::Bincode => "bincode",
+            StateCodec::FixedIntBincode => "bincode-fixint",
+        }
+    }
+
+    /// Parses a codec name as recorded in config or checkpoint metadata. An empty name,
+    /// from metadata written before codecs were recorded, maps to the default.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "" | "bincode" => Ok(StateCodec::Bincode),
+            "bincode-fixint" => Ok(StateCodec::FixedIntBincode),
+            name => bail!("unknown state codec '{}'", name),
+        }
+    }
+
+    pub fn for_table(config: &TableConfig) -> Result<Self> {
+        Self::from_name(config.value_codec.as_deref().unwrap_or(""))
+    }
+}
+
+impl StateSerde for StateCodec {
+    fn encode<T: Encode>(&self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            StateCodec::Bincode => BincodeSerde.encode(value),
+            StateCodec::FixedIntBincode => FixedIntBincodeSerde.encode(value),
+        }
+    }
+
+    fn decode<T: Decode>(&self, bytes: &[u8]) -> Result<T> {
+        match self {
+            StateCodec::Bincode => BincodeSerde.decode(bytes),
+            StateCodec::FixedIntBincode => FixedIntBincodeSerde.decode(bytes),
+        }
+    }
+}
+
+/// A state value whose type can change between versions of a job. The version is declared
+/// for its table with [`crate::with_value_version`] and recorded for the files its values are
+/// written to; values written as an earlier version are decoded as that version's type and
+/// upgraded to this one when the table is restored.
+///
+/// Values of types that were never versioned are version 0.
+pub trait VersionedData: Data {
+    /// The version of the type values are written as.
+    const VERSION: u32;
+
+    /// Decodes a value written as an earlier `version` of the type with `codec`, and upgrades
+    /// it to the current version. Typically each supported version decodes its own type and
+    /// converts it with `From`; versions that can no longer be read return an error.
+    fn decode_version(codec: &StateCodec, version: u32, bytes: &[u8]) -> Result<Self>;
+}
+
+/// Decodes a value written as `version` of `V`, upgrading it if it's an earlier version.
+pub fn decode_versioned<V: VersionedData>(
+    codec: &StateCodec,
+    version: u32,
+    bytes: &[u8],
+) -> Result<V> {
+    if version == V::VERSION {
+        return codec.decode(bytes);
+    }
+    if version > V::VERSION {
+        bail!(
+            "value was written as version {} of {}, which is newer than the current version {}",
+            version,
+            std::any::type_name::<V>(),
+            V::VERSION
+        );
+    }
+    V::decode_version(codec, version, bytes).with_context(|| {
+        format!(
+            "failed to upgrade value from version {} of {} to version {}",
+            version,
+            std::any::type_name::<V>(),
+            V::VERSION
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Encode, Decode)]
+    struct SessionV1 {
+        user: String,
+        count: u64,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Encode, Decode)]
+    struct SessionV2 {
+        user: String,
+        count: u64,
+        country: Option<String>,
+    }
+
+    impl From<SessionV1> for SessionV2 {
+        fn from(v1: SessionV1) -> Self {
+            SessionV2 {
+                user: v1.user,
+                count: v1.count,
+                country: None,
+            }
+        }
+    }
+
+    impl VersionedData for SessionV2 {
+        const VERSION: u32 = 2;
+
+        fn decode_version(codec: &StateCodec, version: u32, bytes: &[u8]) -> Result<Self> {
+            match version {
+                1 => Ok(codec.decode::<SessionV1>(bytes)?.into()),
+                version => bail!("version {} of sessions can't be read", version),
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_versioned() {
+        let v1 = SessionV1 {
+            user: "alice".to_string(),
+            count: 3,
+        };
+        let v2 = SessionV2 {
+            user: "bob".to_string(),
+            count: 5,
+            country: Some("nz".to_string()),
+        };
+        for codec in [StateCodec::Bincode, StateCodec::FixedIntBincode] {
+            let old = codec.encode(&v1).unwrap();
+            assert_eq!(
+                decode_versioned::<SessionV2>(&codec, 1, &old).unwrap(),
+                SessionV2 {
+                    user: "alice".to_string(),
+                    count: 3,
+                    country: None,
+                }
+            );
+            let current = codec.encode(&v2).unwrap();
+            assert_eq!(
+                decode_versioned::<SessionV2>(&codec, 2, &current).unwrap(),
+                v2
+            );
+            assert!(decode_versioned::<SessionV2>(&codec, 0, &old).is_err());
+            assert!(decode_versioned::<SessionV2>(&codec, 3, &current).is_err());
+        }
+    }
+
+    #[test]
+    fn test_codecs_round_trip() {
+        let value = ("key".to_string(), vec![1u64, 300, u64::MAX]);
+        for codec in [StateCodec::Bincode, StateCodec::FixedIntBincode] {
+            let encoded = codec.encode(&value).unwrap();
+            assert_eq!(codec.decode::<(String, Vec<u64>)>(&encoded).unwrap(), value);
+            assert_eq!(StateCodec::from_name(codec.name()).unwrap(), codec);
+        }
+
+        // fixed-width integers take all 8 bytes, varints only what they need
+        assert_eq!(StateCodec::Bincode.encode(&1u64).unwrap(), vec![1]);
+        assert_eq!(
+            StateCodec::FixedIntBincode.encode(&1u64).unwrap(),
+            vec![1, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(StateCodec::from_name("").unwrap(), StateCodec::Bincode);
+        assert!(StateCodec::from_name("json").is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use std::future::Future;
+use std::time::Instant;
+
+use anyhow::Result;
+use arroyo_storage::retry::is_retryable;
+use arroyo_storage::StorageError;
+
+use crate::metrics::{
+    STATE_WRITES_IN_FLIGHT_GAUGE, STATE_WRITE_FAILURES_COUNTER, STATE_WRITE_LATENCY_HISTOGRAM,
+};
+
+/// The kinds of objects written to the state backend. Table changes (inserts and deletes
+/// alike) aren't written one by one but batched into the state files of a checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WriteOperation {
+    /// A state file of a table, or of a subtask's in-flight data.
+    StateFile,
+    /// Checkpoint, operator or savepoint metadata, and operator remappings.
+    Metadata,
+    /// A state file copied into another job's checkpoint.
+    Copy,
+}
+
+impl WriteOperation {
+    fn label(&self) -> &'static str {
+        match self {
+            WriteOperation::StateFile => "state_file",
+            WriteOperation::Metadata => "metadata",
+            WriteOperation::Copy => "copy",
+        }
+    }
+}
+
+/// The class of error a write failed with, for `arroyo_worker_state_write_failures_total`:
+/// `transient` for store errors that were retried until the retries ran out, `rejected` for
+/// ones the store would return again, `storage` for other storage errors (like invalid
+/// credentials), and `other` for everything else, like a failure to encrypt the data.
+fn error_class(error: &anyhow::Error) -> &'static str {
+    match storage_error(error) {
+        Some(StorageError::ObjectStore(e)) if is_retryable(e) => "transient",
+        Some(StorageError::ObjectStore(_)) => "rejected",
+        Some(_) => "storage",
+        None => "other",
+    }
+}
+
+fn storage_error(error: &anyhow::Error) -> Option<&StorageError> {
+    error.chain().find_map(|e| e.downcast_ref::<StorageError>())
+}
+
+/// Whether `error` is from a store request that may succeed if it's made again, as when the
+/// store was unreachable or throttled the request.
+pub(crate) fn is_transient(error: &anyhow::Error) -> bool {
+    matches!(storage_error(error), Some(StorageError::ObjectStore(e)) if is_retryable(e))
+}
+
+/// A write to the state backend in progress, counted in the in-flight gauge until it's
+/// dropped. One dropped without being marked as succeeded or failed, as when the task
+/// writing it returned early or was cancelled, counts as a failure of class `abandoned`.
+#[derive(Debug)]
+pub(crate) struct StoreWrite {
+    operation: WriteOperation,
+    started: Instant,
+    finished: bool,
+}
+
+impl StoreWrite {
+    pub(crate) fn start(operation: WriteOperation) -> Self {
+        STATE_WRITES_IN_FLIGHT_GAUGE.inc();
+        Self {
+            operation,
+            started: Instant::now(),
+            finished: false,
+        }
+    }
+
+    pub(crate) fn succeeded(&mut self) {
+        STATE_WRITE_LATENCY_HISTOGRAM
+            .with_label_values(&[self.operation.label()])
+            .observe(self.started.elapsed().as_secs_f64());
+        self.finished = true;
+    }
+
+    pub(crate) fn failed(&mut self, error: &anyhow::Error) {
+        self.fail(error_class(error));
+    }
+
+    fn fail(&mut self, class: &str) {
+        STATE_WRITE_FAILURES_COUNTER
+            .with_label_values(&[self.operation.label(), class])
+            .inc();
+        self.finished = true;
+    }
+}
+
+impl Drop for StoreWrite {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.fail("abandoned");
+        }
+        STATE_WRITES_IN_FLIGHT_GAUGE.dec();
+    }
+}
+
+/// Runs `write`, recording it in the state write metrics.
+pub(crate) async fn timed_write<T>(
+    operation: WriteOperation,
+    write: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let mut tracked = StoreWrite::start(operation);
+    let result = write.await;
+    match &result {
+        Ok(_) => tracked.succeeded(),
+        Err(e) => tracked.failed(e),
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    // stands in for a store whose writes take `latency`
+    async fn slow_put(latency: Duration, fail: bool) -> Result<()> {
+        tokio::time::sleep(latency).await;
+        if fail {
+            Err(anyhow!("write failed"))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_metrics() {
+        let latency = STATE_WRITE_LATENCY_HISTOGRAM.with_label_values(&["copy"]);
+        let failures = STATE_WRITE_FAILURES_COUNTER.with_label_values(&["copy", "other"]);
+        let abandoned = STATE_WRITE_FAILURES_COUNTER.with_label_values(&["copy", "abandoned"]);
+        let (count, sum) = (latency.get_sample_count(), latency.get_sample_sum());
+        let failed = failures.get();
+
+        timed_write(WriteOperation::Copy, async {
+            // other tests may be writing too, so the gauge is at least this write
+            assert!(STATE_WRITES_IN_FLIGHT_GAUGE.get() >= 1);
+            slow_put(Duration::from_millis(50), false).await
+        })
+        .await
+        .unwrap();
+        // other tests may copy files too, so these are lower bounds
+        assert!(latency.get_sample_count() > count);
+        assert!(latency.get_sample_sum() - sum >= 0.05);
+
+        // failed writes are counted by their class
+        timed_write(
+            WriteOperation::Copy,
+            slow_put(Duration::from_millis(50), true),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(failures.get(), failed + 1);
+
+        let abandoned_before = abandoned.get();
+        drop(StoreWrite::start(WriteOperation::Copy));
+        assert_eq!(abandoned.get(), abandoned_before + 1);
+    }
+}
@@ -1,64 +1,440 @@
 use std::{
     collections::{BTreeMap, HashMap, HashSet},
+    ops::RangeBounds,
     sync::Arc,
     time::{Duration, SystemTime},
 };
 
-use anyhow::{anyhow, bail, Ok, Result};
-use arrow::compute::{concat_batches, kernels::aggregate, take};
-use arrow::row::{OwnedRow, Row};
+use anyhow::{anyhow, bail, Context, Ok, Result};
+use arrow::compute::{concat_batches, filter_record_batch, is_not_null, kernels::aggregate, take};
+use arrow::row::{OwnedRow, Row, RowConverter};
 use arrow_array::{
     cast::AsArray,
     types::{TimestampNanosecondType, UInt64Type},
-    PrimitiveArray, RecordBatch,
+    BooleanArray, PrimitiveArray, RecordBatch,
 };
 use arrow_ord::{partition::partition, sort::sort_to_indices};
 use arroyo_rpc::{
     df::server_for_hash_array,
     grpc::{
-        ExpiringKeyedTimeSubtaskCheckpointMetadata, ExpiringKeyedTimeTableCheckpointMetadata,
-        ExpiringKeyedTimeTableConfig, OperatorMetadata, ParquetTimeFile, TableEnum,
+        ExpirationMode, ExpiringKeyedTimeSubtaskCheckpointMetadata,
+        ExpiringKeyedTimeTableCheckpointMetadata, ExpiringKeyedTimeTableConfig, OperatorMetadata,
+        ParquetTimeFile, TableEnum, TableRestorePolicy, TableTimestampPolicy,
     },
     Converter,
 };
 use arroyo_storage::StorageProviderRef;
-use arroyo_types::{from_micros, from_nanos, print_time, server_for_hash, to_micros, TaskInfoRef};
+use arroyo_types::{from_micros, print_time, server_for_hash, to_micros, TaskInfoRef};
 
-use futures::{StreamExt, TryStreamExt};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
 use parquet::{
-    arrow::{async_reader::ParquetObjectReader, AsyncArrowWriter, ParquetRecordBatchStreamBuilder},
-    basic::{Compression, ZstdLevel},
+    arrow::{
+        arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder},
+        AsyncArrowWriter,
+    },
     file::properties::WriterProperties,
 };
-use tokio::{io::AsyncWrite, sync::mpsc::Sender};
+use tokio::io::AsyncWrite;
 
+use crate::quota::{QuotaCheck, TableSize};
+use crate::restore_progress::TableRestoreProgress;
+use crate::timestamps::{
+    check_timestamp, check_timestamp_column, from_timestamp_nanos, time_range_bounds,
+    to_state_micros,
+};
 use crate::{
-    parquet::ParquetStats, schemas::SchemaWithHashAndOperation, CheckpointMessage, StateMessage,
-    TableData,
+    changelog::{ChangeData, ChangeKind, Changelog},
+    encryption::{fetch_state_file, read_state_file, state_file_writer},
+    parquet::{
+        record_checkpoint_bytes, state_file_compression, state_file_size, state_file_target_size,
+        ParquetStats,
+    },
+    prefetch::{prefetch_state_files, PrefetchConfig},
+    schemas::SchemaWithHashAndOperation,
+    state_serde::StateCodec,
+    upload_scheduler::UPLOAD_SCHEDULER,
+    write_buffer::StateSender,
+    CheckpointMessage, StateMessage, TableData,
 };
 use arroyo_rpc::df::{ArroyoSchema, ArroyoSchemaRef};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-use super::{table_checkpoint_path, CompactionConfig, Table, TableEpochCheckpointer};
+use super::{
+    state_file_part_path, CompactionConfig, RetainedFile, StateFileLayout, Table,
+    TableEpochCheckpointer,
+};
+
+fn retention_for_key(
+    retention_rules: &[(Vec<u8>, Duration)],
+    retention: Duration,
+    key: &[u8],
+) -> Duration {
+    retention_rules
+        .iter()
+        .find(|(prefix, _)| key.starts_with(prefix))
+        .map(|(_, retention)| *retention)
+        .unwrap_or(retention)
+}
+
+/// Drops rows of `batch` that are older than their key's retention allows at `watermark`.
+/// The key and timestamp columns are at the same indices in the memory and state schemas, so
+/// `schema` may be either.
+fn filter_by_key_retention(
+    schema: &ArroyoSchema,
+    retention: Duration,
+    retention_rules: &[(Vec<u8>, Duration)],
+    batch: RecordBatch,
+    watermark: SystemTime,
+) -> Result<RecordBatch> {
+    filter_by_key_retention_with_expiry(schema, retention, retention_rules, batch, watermark)
+        .map(|(batch, _)| batch)
+}
+
+/// Like [`filter_by_key_retention`], also returning the watermark up to which the remaining
+/// rows are all retained, if it's known: filtering the batch again before the watermark
+/// passes it drops nothing.
+fn filter_by_key_retention_with_expiry(
+    schema: &ArroyoSchema,
+    retention: Duration,
+    retention_rules: &[(Vec<u8>, Duration)],
+    batch: RecordBatch,
+    watermark: SystemTime,
+) -> Result<(RecordBatch, Option<SystemTime>)> {
+    if retention_rules.is_empty() || batch.num_rows() == 0 {
+        return Ok((batch, None));
+    }
+    let Some(key_indices) = schema.key_indices.as_ref() else {
+        let batch = schema.filter_by_time(batch, Some(retention_cutoff(watermark, retention)))?;
+        return Ok((batch, None));
+    };
+    let key_columns: Vec<_> = key_indices
+        .iter()
+        .map(|index| batch.column(*index).clone())
+        .collect();
+    let converter = RowConverter::new(schema.sort_fields(false))?;
+    let rows = converter.convert_columns(&key_columns)?;
+    let timestamps = schema.timestamp_column(&batch);
+    let mut expiry: Option<SystemTime> = None;
+    let keep: BooleanArray = (0..batch.num_rows())
+        .map(|i| {
+            let retention = retention_for_key(retention_rules, retention, rows.row(i).as_ref());
+            let timestamp = from_timestamp_nanos(timestamps.value(i));
+            let kept = retention_cutoff(watermark, retention) <= timestamp;
+            // a row expires once the watermark is past its timestamp plus its retention
+            if let Some(row_expiry) = timestamp.checked_add(retention).filter(|_| kept) {
+                expiry = Some(expiry.map_or(row_expiry, |expiry| expiry.min(row_expiry)));
+            }
+            Some(kept)
+        })
+        .collect();
+    Ok((filter_record_batch(&batch, &keep)?, expiry))
+}
+
+/// The earliest time retained at `watermark`: data expires when its timestamp is strictly
+/// before the cutoff, so data exactly at it is kept, in memory and in checkpoints alike.
+/// Clamped to the epoch, so that retentions longer than the watermark keep everything rather
+/// than underflowing.
+fn retention_cutoff(watermark: SystemTime, retention: Duration) -> SystemTime {
+    watermark
+        .checked_sub(retention)
+        .filter(|cutoff| *cutoff > SystemTime::UNIX_EPOCH)
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// What a restore reads from the files of an [`ExpiringTimeKeyTable`].
+pub(crate) struct RestoredBatches {
+    /// The rows that haven't expired, bucketed by timestamp, or for processing-time
+    /// expiration by when their file was written.
+    pub batches: BTreeMap<SystemTime, Vec<RecordBatch>>,
+    /// Rows that were read but had expired, and so were left out.
+    pub expired_rows: u64,
+    /// Files that weren't read, as every row in them had expired.
+    pub expired_files: u64,
+}
+
+/// The batches of a restored file of an [`ExpiringTimeKeyTable`], which are decoded as
+/// they're iterated over.
+struct RestoredFileBatches<'a> {
+    table: &'a ExpiringTimeKeyTable,
+    file: String,
+    reader: ParquetRecordBatchReader,
+    projection: Vec<usize>,
+    needs_filtering: bool,
+    file_rows: u64,
+    rows_read: u64,
+    done: bool,
+}
+
+impl Iterator for RestoredFileBatches<'_> {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let Some(batch_result) = self.reader.next() else {
+                self.done = true;
+                break;
+            };
+            let batch = match batch_result
+                .with_context(|| format!("failed to read restored file {}", self.file))
+            {
+                Result::Ok(batch) => batch,
+                Err(e) if self.table.restore_policy == TableRestorePolicy::SkipCorrupt => {
+                    // the reader can't continue past a batch it failed to decode
+                    let skipped = self.file_rows.saturating_sub(self.rows_read);
+                    warn!(
+                        "dropping the last {} rows of {} of table {}, which fail to decode: {:#}",
+                        skipped, self.file, self.table.table_name, e
+                    );
+                    self.table.restore_progress.tuples_skipped(skipped);
+                    self.done = true;
+                    break;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            self.rows_read += batch.num_rows() as u64;
+            match self.table.restored_batch(
+                &self.file,
+                batch,
+                &self.projection,
+                self.needs_filtering,
+            ) {
+                Result::Ok(Some(batch)) => return Some(Ok(batch)),
+                Result::Ok(None) => continue,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ExpiringTimeKeyTable {
     table_name: String,
+    layout: StateFileLayout,
     task_info: TaskInfoRef,
     schema: SchemaWithHashAndOperation,
     retention: Duration,
+    // ordered (key prefix, retention) overrides of the default retention
+    retention_rules: Vec<(Vec<u8>, Duration)>,
+    expiration_mode: ExpirationMode,
     storage_provider: StorageProviderRef,
     checkpoint_files: Vec<ParquetTimeFile>,
+    restore_progress: TableRestoreProgress,
+    restore_policy: TableRestorePolicy,
+    timestamp_policy: TableTimestampPolicy,
 }
 
 impl ExpiringTimeKeyTable {
+    /// The time that retention is measured back from. In processing-time mode this is the
+    /// current wall-clock time, whatever the watermark.
+    fn expiration_time(&self, watermark: Option<SystemTime>) -> Option<SystemTime> {
+        match self.expiration_mode {
+            ExpirationMode::EventTime => watermark,
+            ExpirationMode::ProcessingTime => Some(SystemTime::now()),
+        }
+    }
+
+    /// The retention for a row-encoded key. Rules are evaluated in order and the first
+    /// matching prefix wins; keys that match no rule use the table's default retention.
+    pub(crate) fn retention_for_key(&self, key: &[u8]) -> Duration {
+        retention_for_key(&self.retention_rules, self.retention, key)
+    }
+
+    /// The longest retention of any key. Used wherever data is filtered without looking
+    /// at individual keys, like whole files or batches.
+    pub(crate) fn max_retention(&self) -> Duration {
+        self.retention_rules
+            .iter()
+            .map(|(_, retention)| *retention)
+            .fold(self.retention, Duration::max)
+    }
+
+    /// Drops rows that are older than their key's retention allows. This is a no-op for
+    /// tables without retention rules, as those are filtered by the default cutoff.
+    /// The schema of the table's rows, without the columns added to them in state files.
+    pub(crate) fn memory_schema(&self) -> ArroyoSchemaRef {
+        self.schema.memory_schema()
+    }
+
+    pub(crate) fn filter_by_key_retention(
+        &self,
+        batch: RecordBatch,
+        watermark: Option<SystemTime>,
+    ) -> Result<RecordBatch> {
+        let Some(watermark) = watermark else {
+            return Ok(batch);
+        };
+        filter_by_key_retention(
+            &self.schema.memory_schema(),
+            self.retention,
+            &self.retention_rules,
+            batch,
+            watermark,
+        )
+    }
+
+    fn filter_by_key_retention_with_expiry(
+        &self,
+        batch: RecordBatch,
+        watermark: SystemTime,
+    ) -> Result<(RecordBatch, Option<SystemTime>)> {
+        filter_by_key_retention_with_expiry(
+            &self.schema.memory_schema(),
+            self.retention,
+            &self.retention_rules,
+            batch,
+            watermark,
+        )
+    }
+
+    /// Reads restored checkpoint files, prefetching up to `STATE_RESTORE_PARALLELISM` at a
+    /// time. Each file's batches are filtered to this subtask's key range and stripped of
+    /// their metadata columns, and files are yielded in order so that later writes for a key
+    /// are applied after earlier ones.
+    ///
+    /// Prefetched files are held as their compressed contents, and each file's batches are
+    /// decoded one at a time as they're consumed, so a restore holds at most one decoded
+    /// batch alongside the prefetched files.
+    fn read_restore_files(
+        &self,
+        files: Vec<(String, bool)>,
+    ) -> impl Stream<Item = Result<RestoredFileBatches<'_>>> + '_ {
+        let total = files.len();
+        let (files, needs_filtering): (Vec<_>, Vec<_>) = files.into_iter().unzip();
+        prefetch_state_files(PrefetchConfig::from_env(), files, |file| {
+            let storage_provider = self.storage_provider.clone();
+            async move {
+                fetch_state_file(&storage_provider, &file)
+                    .await
+                    .with_context(|| format!("failed to find restored file {}", file))
+            }
+        })
+        .zip(futures::stream::iter(needs_filtering))
+        .enumerate()
+        .map(move |(i, (contents, needs_filtering))| {
+            let (file, contents) = contents?;
+            self.restore_progress.file_read(contents.len() as u64);
+            debug!(
+                "reading restored file {}/{} of table {}",
+                i + 1,
+                total,
+                self.table_name
+            );
+            self.read_restore_file(file, contents, needs_filtering)
+        })
+    }
+
+    /// Reads a restored file. Tables restored with [`TableRestorePolicy::SkipCorrupt`] drop
+    /// the rows without a timestamp, and the rest of the file from the first batch that fails
+    /// to decode.
+    fn read_restore_file(
+        &self,
+        file: String,
+        contents: Bytes,
+        needs_filtering: bool,
+    ) -> Result<RestoredFileBatches<'_>> {
+        let reader_builder = ParquetRecordBatchReaderBuilder::try_new(contents)
+            .with_context(|| format!("failed to read restored file {}", file))?;
+        // projection to trim the metadata fields. Should probably be factored out.
+        let projection: Vec<_> = (0..(reader_builder.schema().all_fields().len() - 2)).collect();
+        let file_rows = reader_builder.metadata().file_metadata().num_rows() as u64;
+        let reader = reader_builder.build()?;
+        Ok(RestoredFileBatches {
+            table: self,
+            file,
+            reader,
+            projection,
+            needs_filtering,
+            file_rows,
+            rows_read: 0,
+            done: false,
+        })
+    }
+
+    /// Prepares a batch read from a restored file, returning `None` if it has no rows left
+    /// for this subtask.
+    fn restored_batch(
+        &self,
+        file: &str,
+        batch: RecordBatch,
+        projection: &[usize],
+        needs_filtering: bool,
+    ) -> Result<Option<RecordBatch>> {
+        let batch = if needs_filtering {
+            match self
+                .schema
+                .filter_by_hash_index(batch, &self.task_info.key_range)?
+            {
+                None => return Ok(None),
+                Some(filtered_batch) => filtered_batch,
+            }
+        } else {
+            batch
+        };
+        if batch.num_rows() == 0 {
+            return Ok(None);
+        }
+        let mut batch = batch.project(projection)?;
+        if self.restore_policy == TableRestorePolicy::SkipCorrupt {
+            batch = self.drop_rows_without_timestamps(file, batch)?;
+            if batch.num_rows() == 0 {
+                return Ok(None);
+            }
+        }
+        Ok(Some(batch))
+    }
+
+    /// Drops the rows of a restored batch that have no timestamp, and so can't be placed in
+    /// the table.
+    fn drop_rows_without_timestamps(&self, file: &str, batch: RecordBatch) -> Result<RecordBatch> {
+        let timestamps = batch.column(self.schema.timestamp_index());
+        if timestamps.null_count() == 0 {
+            return Ok(batch);
+        }
+        let filtered = filter_record_batch(&batch, &is_not_null(timestamps)?)?;
+        let skipped = (batch.num_rows() - filtered.num_rows()) as u64;
+        warn!(
+            "dropping {} rows of {} of table {} without a timestamp",
+            skipped, file, self.table_name
+        );
+        self.restore_progress.tuples_skipped(skipped);
+        Ok(filtered)
+    }
+
     pub(crate) async fn get_view(
         &self,
-        state_tx: Sender<StateMessage>,
+        state_tx: StateSender,
         watermark: Option<SystemTime>,
     ) -> Result<ExpiringTimeKeyView> {
+        Ok(ExpiringTimeKeyView {
+            flushed_batches_by_max_timestamp: self.restored_batches(watermark).await?.batches,
+            key_retention_expiries: HashMap::new(),
+            parent: self.clone(),
+            batches_to_flush: BTreeMap::new(),
+            state_tx,
+            changelog: None,
+            size: None,
+            rows: 0,
+            size_bytes: 0,
+        })
+    }
+
+    /// The rows of the restored files that haven't expired as of `watermark`, without their
+    /// metadata columns.
+    pub(crate) async fn restored_batches(
+        &self,
+        watermark: Option<SystemTime>,
+    ) -> Result<RestoredBatches> {
+        let watermark = self.expiration_time(watermark);
         let cutoff = watermark
-            .map(|watermark| (watermark - self.retention))
+            .map(|watermark| retention_cutoff(watermark, self.max_retention()))
             .unwrap_or_else(|| SystemTime::UNIX_EPOCH);
         info!(
             "watermark is {:?}, cutoff is {:?}",
@@ -77,54 +453,58 @@ impl ExpiringTimeKeyTable {
                     let needs_hash_filtering = *self.task_info.key_range.end()
                         < file.max_routing_key
                         || *self.task_info.key_range.start() > file.min_routing_key;
-                    Some((file.file.clone(), needs_hash_filtering))
+                    Some((
+                        (file.file.clone(), needs_hash_filtering),
+                        from_micros(file.max_timestamp_micros),
+                    ))
                 } else {
                     None
                 }
             })
             .collect();
+        let expired_files = self
+            .checkpoint_files
+            .iter()
+            .filter(|file| cutoff > from_micros(file.max_timestamp_micros))
+            .count() as u64;
+        let mut expired_rows = 0;
+        let (files, file_times): (Vec<_>, Vec<_>) = files.into_iter().unzip();
 
         let mut data: BTreeMap<SystemTime, Vec<RecordBatch>> = BTreeMap::new();
-        for (file, needs_filtering) in files {
-            let object_meta = self
-                .storage_provider
-                .get_backing_store()
-                .head(&(file.into()))
-                .await?;
-            let object_reader =
-                ParquetObjectReader::new(self.storage_provider.get_backing_store(), object_meta);
-            let reader_builder = ParquetRecordBatchStreamBuilder::new(object_reader).await?;
-            let mut stream = reader_builder.build()?;
-            // projection to trim the metadata fields. Should probably be factored out.
-            let projection: Vec<_> = (0..(stream.schema().all_fields().len() - 2)).collect();
-            while let Some(batch_result) = stream.next().await {
-                let mut batch = batch_result?;
-                if needs_filtering {
-                    match self
-                        .schema
-                        .filter_by_hash_index(batch, &self.task_info.key_range)?
-                    {
-                        None => continue,
-                        Some(filtered_batch) => batch = filtered_batch,
-                    };
+        let mut files = std::pin::pin!(self.read_restore_files(files).enumerate());
+        while let Some((i, batches)) = files.next().await {
+            if self.expiration_mode == ExpirationMode::ProcessingTime {
+                // rows are bucketed by when they were written, which is only known per file
+                let written_at = file_times[i];
+                for batch in batches? {
+                    let batch = batch?;
+                    self.restore_progress
+                        .tuples_applied(batch.num_rows() as u64);
+                    data.entry(written_at).or_default().push(batch);
                 }
+                continue;
+            }
+            for batch in batches? {
+                let batch = batch?;
+                let rows = batch.num_rows();
+                let batch = self.filter_by_key_retention(batch, watermark)?;
+                expired_rows += (rows - batch.num_rows()) as u64;
                 if batch.num_rows() == 0 {
                     continue;
                 }
-                batch = batch.project(&projection)?;
+                self.restore_progress
+                    .tuples_applied(batch.num_rows() as u64);
                 let timestamp_array: &PrimitiveArray<TimestampNanosecondType> = batch
                     .column(self.schema.timestamp_index())
                     .as_primitive_opt()
                     .ok_or_else(|| anyhow!("failed to find timestamp column"))?;
-                let max_timestamp = from_nanos(
+                let max_timestamp = from_timestamp_nanos(
                     aggregate::max(timestamp_array)
-                        .ok_or_else(|| anyhow!("should have max timestamp"))?
-                        as u128,
+                        .ok_or_else(|| anyhow!("should have max timestamp"))?,
                 );
-                let min_timestamp = from_nanos(
+                let min_timestamp = from_timestamp_nanos(
                     aggregate::min(timestamp_array)
-                        .ok_or_else(|| anyhow!("should have min timestamp"))?
-                        as u128,
+                        .ok_or_else(|| anyhow!("should have min timestamp"))?,
                 );
                 let batches = if max_timestamp != min_timestamp {
                     // assume monotonic for now
@@ -135,7 +515,8 @@ impl ExpiringTimeKeyTable {
                         .ranges()
                         .into_iter()
                         .map(|range| {
-                            let timestamp = from_nanos(timestamp_array.value(range.start) as u128);
+                            let timestamp =
+                                from_timestamp_nanos(timestamp_array.value(range.start));
                             (timestamp, batch.slice(range.start, range.end - range.start))
                         })
                         .collect::<Vec<_>>()
@@ -145,26 +526,27 @@ impl ExpiringTimeKeyTable {
                 for (timestamp, batch) in batches {
                     if cutoff <= timestamp {
                         data.entry(timestamp).or_default().push(batch)
+                    } else {
+                        expired_rows += batch.num_rows() as u64;
                     }
                 }
             }
         }
-
-        Ok(ExpiringTimeKeyView {
-            flushed_batches_by_max_timestamp: data,
-            parent: self.clone(),
-            batches_to_flush: BTreeMap::new(),
-            state_tx,
+        Ok(RestoredBatches {
+            batches: data,
+            expired_rows,
+            expired_files,
         })
     }
 
     pub(crate) async fn get_key_time_view(
         &self,
-        state_tx: Sender<StateMessage>,
+        state_tx: StateSender,
         watermark: Option<SystemTime>,
     ) -> Result<KeyTimeView> {
+        let watermark = self.expiration_time(watermark);
         let cutoff = watermark
-            .map(|watermark| (watermark - self.retention))
+            .map(|watermark| retention_cutoff(watermark, self.max_retention()))
             .unwrap_or_else(|| SystemTime::UNIX_EPOCH);
         info!(
             "watermark is {:?}, cutoff is {:?}",
@@ -191,40 +573,23 @@ impl ExpiringTimeKeyTable {
             .collect();
 
         let mut view = KeyTimeView::new(self.clone(), state_tx)?;
-        for (file, needs_filtering) in files {
-            let object_meta = self
-                .storage_provider
-                .get_backing_store()
-                .head(&(file.into()))
-                .await?;
-            let object_reader =
-                ParquetObjectReader::new(self.storage_provider.get_backing_store(), object_meta);
-            let reader_builder = ParquetRecordBatchStreamBuilder::new(object_reader).await?;
-            let mut stream = reader_builder.build()?;
-            // projection to trim the metadata fields. Should probably be factored out.
-            let projection: Vec<_> = (0..(stream.schema().all_fields().len() - 2)).collect();
-            while let Some(batch_result) = stream.next().await {
-                let mut batch = batch_result?;
-                if needs_filtering {
-                    match self
-                        .schema
-                        .filter_by_hash_index(batch, &self.task_info.key_range)?
-                    {
-                        None => continue,
-                        Some(filtered_batch) => batch = filtered_batch,
-                    };
-                }
-                batch = batch.project(&projection)?;
+        let mut files = std::pin::pin!(self.read_restore_files(files));
+        while let Some(batches) = files.next().await {
+            for batch in batches? {
+                let batch = batch?;
                 let timestamp_array: &PrimitiveArray<TimestampNanosecondType> = batch
                     .column(self.schema.timestamp_index())
                     .as_primitive_opt()
                     .ok_or_else(|| anyhow!("failed to find timestamp column"))?;
-                let max_timestamp = from_nanos(
+                let max_timestamp = from_timestamp_nanos(
                     aggregate::max(timestamp_array)
-                        .ok_or_else(|| anyhow!("should have max timestamp"))?
-                        as u128,
+                        .ok_or_else(|| anyhow!("should have max timestamp"))?,
                 );
-                if max_timestamp < cutoff {
+                if max_timestamp < cutoff && self.expiration_mode == ExpirationMode::EventTime {
+                    continue;
+                }
+                let batch = self.filter_by_key_retention(batch, watermark)?;
+                if batch.num_rows() == 0 {
                     continue;
                 }
                 // TODO: more time filtering
@@ -247,6 +612,9 @@ impl Table for ExpiringTimeKeyTable {
 
     fn from_config(
         config: Self::ConfigMessage,
+        layout: StateFileLayout,
+        _codec: StateCodec,
+        _value_version: u32,
         task_info: arroyo_types::TaskInfoRef,
         storage_provider: arroyo_storage::StorageProviderRef,
         checkpoint_message: Option<Self::TableCheckpointMessage>,
@@ -261,13 +629,36 @@ impl Table for ExpiringTimeKeyTable {
         let checkpoint_files = checkpoint_message
             .map(|checkpoint_message| checkpoint_message.files)
             .unwrap_or_default();
+        let expiration_mode = config.expiration_mode();
+        if expiration_mode == ExpirationMode::ProcessingTime && !config.retention_rules.is_empty() {
+            bail!(
+                "table {} uses processing-time expiration, which doesn't support retention rules",
+                config.table_name
+            );
+        }
+        let restore_progress = TableRestoreProgress::detached(&task_info, &config.table_name);
         Ok(Self {
             table_name: config.table_name,
+            layout,
             task_info,
             schema,
             retention: Duration::from_micros(config.retention_micros),
+            retention_rules: config
+                .retention_rules
+                .into_iter()
+                .map(|rule| {
+                    (
+                        rule.key_prefix,
+                        Duration::from_micros(rule.retention_micros),
+                    )
+                })
+                .collect(),
+            expiration_mode,
             storage_provider,
             checkpoint_files,
+            restore_progress,
+            restore_policy: TableRestorePolicy::Strict,
+            timestamp_policy: TableTimestampPolicy::Reject,
         })
     }
 
@@ -298,8 +689,17 @@ impl Table for ExpiringTimeKeyTable {
             .values()
             .filter_map(|metadata| metadata.watermark)
             .min();
-        let cutoff = min_watermark
-            .map(|min_watermark| min_watermark - config.retention_micros)
+        let max_retention_micros = config
+            .retention_rules
+            .iter()
+            .map(|rule| rule.retention_micros)
+            .fold(config.retention_micros, u64::max);
+        let expiration_time = match config.expiration_mode() {
+            ExpirationMode::EventTime => min_watermark,
+            ExpirationMode::ProcessingTime => Some(to_micros(SystemTime::now())),
+        };
+        let cutoff = expiration_time
+            .map(|expiration_time| expiration_time.saturating_sub(max_retention_micros))
             .unwrap_or_default();
         let files: Vec<_> = subtask_metadata
             .into_values()
@@ -344,6 +744,18 @@ impl Table for ExpiringTimeKeyTable {
         self.task_info.clone()
     }
 
+    fn set_restore_progress(&mut self, progress: TableRestoreProgress) {
+        self.restore_progress = progress;
+    }
+
+    fn set_restore_policy(&mut self, policy: TableRestorePolicy) {
+        self.restore_policy = policy;
+    }
+
+    fn set_timestamp_policy(&mut self, policy: TableTimestampPolicy) {
+        self.timestamp_policy = policy;
+    }
+
     fn files_to_keep(
         _config: Self::ConfigMessage,
         checkpoint: Self::TableCheckpointMessage,
@@ -354,6 +766,34 @@ impl Table for ExpiringTimeKeyTable {
             .map(|file: ParquetTimeFile| file.file)
             .collect())
     }
+
+    fn retained_files(
+        _config: Self::ConfigMessage,
+        checkpoint: Self::TableCheckpointMessage,
+        _epoch: u32,
+    ) -> Result<Vec<RetainedFile>> {
+        Ok(checkpoint
+            .files
+            .into_iter()
+            .map(|file| RetainedFile {
+                path: file.file,
+                epoch: file.epoch,
+                bytes: file.size_bytes,
+            })
+            .collect())
+    }
+
+    fn rename_files(
+        _config: Self::ConfigMessage,
+        mut checkpoint: Self::TableCheckpointMessage,
+        rename: &dyn Fn(&str) -> String,
+    ) -> Result<Self::TableCheckpointMessage> {
+        for file in &mut checkpoint.files {
+            file.file = rename(&file.file);
+        }
+        Ok(checkpoint)
+    }
+
     fn apply_compacted_checkpoint(
         &self,
         epoch: u32,
@@ -376,10 +816,15 @@ impl Table for ExpiringTimeKeyTable {
 
     async fn compact_data(
         config: Self::ConfigMessage,
+        layout: &StateFileLayout,
         compaction_config: &CompactionConfig,
         operator_metadata: &OperatorMetadata,
         current_metadata: Self::TableCheckpointMessage,
     ) -> Result<Option<Self::TableCheckpointMessage>> {
+        if config.expiration_mode() == ExpirationMode::ProcessingTime {
+            // files are expired by when they were written, which compaction would lose
+            return Ok(None);
+        }
         let mut epochs_in_generation: HashMap<u64, HashSet<u32>> = HashMap::new();
         let mut files_by_generation: BTreeMap<u64, HashMap<String, ParquetTimeFile>> =
             BTreeMap::new();
@@ -410,11 +855,22 @@ impl Table for ExpiringTimeKeyTable {
             }
             let mut files = TimeTableCompactor::compact_files(
                 config.table_name,
+                layout.clone(),
                 epochs.into_iter().max().unwrap(),
                 generation + 1,
                 compaction_config.storage_provider.clone(),
                 state_schema,
                 Duration::from_micros(config.retention_micros),
+                config
+                    .retention_rules
+                    .into_iter()
+                    .map(|rule| {
+                        (
+                            rule.key_prefix,
+                            Duration::from_micros(rule.retention_micros),
+                        )
+                    })
+                    .collect(),
                 operator_metadata,
                 files_by_generation
                     .remove(&generation)
@@ -434,6 +890,7 @@ impl Table for ExpiringTimeKeyTable {
 
 struct CompactedFileWriter {
     file_name: String,
+    multipart_id: Option<String>,
     schema: SchemaWithHashAndOperation,
     writer: Option<AsyncArrowWriter<Box<dyn AsyncWrite + Send + Unpin>>>,
     parquet_stats: Option<ParquetStats>,
@@ -444,105 +901,130 @@ struct TimeTableCompactor {
     schema: SchemaWithHashAndOperation,
     operator_metadata: OperatorMetadata,
     table: String,
+    layout: StateFileLayout,
     writers: HashMap<usize, CompactedFileWriter>,
 }
 
 impl TimeTableCompactor {
+    /// Rewrites `files` into one file per partition, dropping rows that have passed their
+    /// key's retention at the operator's min watermark.
+    ///
+    /// The new files are only referenced once the returned metadata is checkpointed, and the
+    /// files they replace are deleted by the min epoch cleanup after that, so an interrupted
+    /// compaction leaves the table as it was. If compaction fails, any files it wrote are
+    /// removed.
+    #[allow(clippy::too_many_arguments)]
     async fn compact_files(
         table: String,
+        layout: StateFileLayout,
         epoch: u32,
         generation: u64,
         storage_provider: StorageProviderRef,
         schema: SchemaWithHashAndOperation,
         retention: Duration,
+        retention_rules: Vec<(Vec<u8>, Duration)>,
         operator_metadata: &OperatorMetadata,
         files: HashMap<String, ParquetTimeFile>,
     ) -> Result<Vec<ParquetTimeFile>> {
         let mut compactor = Self {
             table,
+            layout,
             storage_provider,
             schema: schema.clone(),
             operator_metadata: operator_metadata.clone(),
             writers: HashMap::new(),
         };
-        let cutoff = operator_metadata
-            .min_watermark
-            .map(|min_micros| from_micros(min_micros) - retention);
-        for (file_name, file) in files {
-            let max_file_timestamp = from_micros(file.max_timestamp_micros);
-            if cutoff
-                .map(|cutoff| max_file_timestamp < cutoff)
-                .unwrap_or(false)
-            {
-                continue;
-            }
-            let reader = ParquetObjectReader::new(
-                compactor.storage_provider.get_backing_store(),
-                compactor
-                    .storage_provider
-                    .get_backing_store()
-                    .head(&(file_name.clone().into()))
-                    .await?,
-            );
-            let first_partition =
-                server_for_hash(file.min_routing_key, operator_metadata.parallelism as usize);
-            let last_partition =
-                server_for_hash(file.max_routing_key, operator_metadata.parallelism as usize);
-            let multiple_partitions = !(first_partition == last_partition);
-            let reader_builder = ParquetRecordBatchStreamBuilder::new(reader).await?;
-            let mut stream = reader_builder.build()?;
-            // projection to trim the metadata fields. Should probably be factored out.
-            while let Some(batch) = stream.try_next().await? {
-                // Filter by _timestamp field
-                let time_filtered = schema.state_schema().filter_by_time(batch, cutoff)?;
-                if time_filtered.num_rows() == 0 {
+        let watermark = operator_metadata.min_watermark.map(from_micros);
+        let max_retention = retention_rules
+            .iter()
+            .map(|(_, retention)| *retention)
+            .fold(retention, Duration::max);
+        let cutoff = watermark.map(|watermark| retention_cutoff(watermark, max_retention));
+        let rewritten = async {
+            for (file_name, file) in files {
+                let max_file_timestamp = from_micros(file.max_timestamp_micros);
+                if cutoff
+                    .map(|cutoff| max_file_timestamp < cutoff)
+                    .unwrap_or(false)
+                {
                     continue;
                 }
-                if !multiple_partitions {
-                    compactor
-                        .write_batch(first_partition, time_filtered)
-                        .await?;
-                } else {
-                    // this record batch contains data belonging to multiple partitions.
-                    let partitions = server_for_hash_array(
-                        time_filtered
-                            .column(schema.hash_index())
-                            .as_any()
-                            .downcast_ref::<PrimitiveArray<UInt64Type>>()
-                            .unwrap(),
-                        operator_metadata.parallelism as usize,
-                    )?;
-                    let indices = sort_to_indices(&partitions, None, None).unwrap();
-                    let columns = time_filtered
-                        .columns()
-                        .iter()
-                        .map(|c| take(c, &indices, None).unwrap())
-                        .collect();
-                    let sorted =
-                        RecordBatch::try_new(schema.state_schema().schema.clone(), columns)?;
-                    let sorted_keys = take(&partitions, &indices, None)?;
-
-                    let partition = partition(vec![sorted_keys.clone()].as_slice())?;
-                    let typed_keys: &PrimitiveArray<UInt64Type> =
-                        sorted_keys.as_any().downcast_ref().unwrap();
-                    for range in partition.ranges() {
-                        let partition = typed_keys.value(range.start);
+                let contents = read_state_file(&compactor.storage_provider, &file_name).await?;
+                let first_partition =
+                    server_for_hash(file.min_routing_key, operator_metadata.parallelism as usize);
+                let last_partition =
+                    server_for_hash(file.max_routing_key, operator_metadata.parallelism as usize);
+                let multiple_partitions = !(first_partition == last_partition);
+                let reader = ParquetRecordBatchReaderBuilder::try_new(contents)?.build()?;
+                for batch in reader {
+                    let batch = batch?;
+                    // Filter by _timestamp field
+                    let time_filtered = schema.state_schema().filter_by_time(batch, cutoff)?;
+                    let time_filtered = match watermark {
+                        Some(watermark) => filter_by_key_retention(
+                            &schema.state_schema(),
+                            retention,
+                            &retention_rules,
+                            time_filtered,
+                            watermark,
+                        )?,
+                        None => time_filtered,
+                    };
+                    if time_filtered.num_rows() == 0 {
+                        continue;
+                    }
+                    if !multiple_partitions {
                         compactor
-                            .write_batch(
-                                partition as usize,
-                                sorted.slice(range.start, range.end - range.start),
-                            )
-                            .await?
+                            .write_batch(first_partition, time_filtered)
+                            .await?;
+                    } else {
+                        // this record batch contains data belonging to multiple partitions.
+                        let partitions = server_for_hash_array(
+                            time_filtered
+                                .column(schema.hash_index())
+                                .as_any()
+                                .downcast_ref::<PrimitiveArray<UInt64Type>>()
+                                .unwrap(),
+                            operator_metadata.parallelism as usize,
+                        )?;
+                        let indices = sort_to_indices(&partitions, None, None).unwrap();
+                        let columns = time_filtered
+                            .columns()
+                            .iter()
+                            .map(|c| take(c, &indices, None).unwrap())
+                            .collect();
+                        let sorted =
+                            RecordBatch::try_new(schema.state_schema().schema.clone(), columns)?;
+                        let sorted_keys = take(&partitions, &indices, None)?;
+
+                        let partition = partition(vec![sorted_keys.clone()].as_slice())?;
+                        let typed_keys: &PrimitiveArray<UInt64Type> =
+                            sorted_keys.as_any().downcast_ref().unwrap();
+                        for range in partition.ranges() {
+                            let partition = typed_keys.value(range.start);
+                            compactor
+                                .write_batch(
+                                    partition as usize,
+                                    sorted.slice(range.start, range.end - range.start),
+                                )
+                                .await?
+                        }
                     }
                 }
             }
+            Ok(())
+        }
+        .await;
+        if let Err(e) = rewritten {
+            compactor.abort().await;
+            return Err(e);
         }
         compactor.finish(epoch, generation).await
     }
 
     async fn write_batch(&mut self, partition: usize, record_batch: RecordBatch) -> Result<()> {
         if !self.writers.contains_key(&partition) {
-            let file_name = table_checkpoint_path(
+            let file_name = self.layout.path(
                 &self.operator_metadata.job_id,
                 &self.operator_metadata.operator_id,
                 &self.table,
@@ -550,21 +1032,23 @@ impl TimeTableCompactor {
                 self.operator_metadata.epoch,
                 true,
             );
-            let (_multipart_id, async_writer) = self
-                .storage_provider
-                .get_backing_store()
-                .put_multipart(&(file_name.clone().into()))
-                .await?;
+            let (multipart_id, async_writer) =
+                state_file_writer(&self.storage_provider, &file_name).await?;
             let writer = Some(AsyncArrowWriter::try_new(
                 async_writer,
                 self.schema.state_schema().schema.clone(),
                 1_000_0000,
-                None,
+                Some(
+                    WriterProperties::builder()
+                        .set_compression(state_file_compression()?)
+                        .build(),
+                ),
             )?);
             self.writers.insert(
                 partition,
                 CompactedFileWriter {
                     file_name,
+                    multipart_id,
                     schema: self.schema.clone(),
                     writer,
                     parquet_stats: None,
@@ -578,13 +1062,43 @@ impl TimeTableCompactor {
         Ok(())
     }
 
-    async fn finish(self, epoch: u32, generation: u64) -> Result<Vec<ParquetTimeFile>> {
-        let mut results = vec![];
-        for writer in self.writers.into_values() {
-            results.push(writer.finish(epoch, generation).await?);
+    async fn finish(mut self, epoch: u32, generation: u64) -> Result<Vec<ParquetTimeFile>> {
+        let mut results: Vec<ParquetTimeFile> = vec![];
+        let partitions: Vec<_> = self.writers.keys().copied().collect();
+        for partition in partitions {
+            let writer = self
+                .writers
+                .remove(&partition)
+                .expect("partition has a writer");
+            match writer
+                .finish(&self.storage_provider, epoch, generation)
+                .await
+            {
+                Result::Ok(file) => results.push(file),
+                Err(e) => {
+                    for file in results {
+                        delete_file(&self.storage_provider, &file.file).await;
+                    }
+                    self.abort().await;
+                    return Err(e);
+                }
+            }
         }
         Ok(results)
     }
+
+    /// Aborts the uploads of any files still being written.
+    async fn abort(&mut self) {
+        for (_, writer) in self.writers.drain() {
+            if let Some(multipart_id) = writer.multipart_id {
+                abort_upload(&self.storage_provider, &writer.file_name, multipart_id).await;
+            } else {
+                // encrypted files are only uploaded once they're closed, so there's nothing
+                // to abort
+                debug!("dropping unfinished compacted file {}", writer.file_name);
+            }
+        }
+    }
 }
 
 impl CompactedFileWriter {
@@ -601,30 +1115,49 @@ impl CompactedFileWriter {
         Ok(())
     }
 
-    async fn finish(mut self, epoch: u32, generation: u64) -> Result<ParquetTimeFile> {
+    async fn finish(
+        mut self,
+        storage_provider: &StorageProviderRef,
+        epoch: u32,
+        generation: u64,
+    ) -> Result<ParquetTimeFile> {
         let writer = self
             .writer
             .take()
             .ok_or_else(|| anyhow!("unset compacted file writer {}", self.file_name))?;
-        let _closed = writer.close().await?;
+        if let Err(e) = writer.close().await {
+            if let Some(multipart_id) = self.multipart_id {
+                abort_upload(storage_provider, &self.file_name, multipart_id).await;
+            }
+            return Err(e.into());
+        }
         let stats = self.parquet_stats.take().expect("should have stats");
+        let size_bytes = state_file_size(storage_provider, &self.file_name).await?;
         Ok(ParquetTimeFile {
             epoch,
             file: self.file_name,
             min_routing_key: stats.min_routing_key,
             max_routing_key: stats.max_routing_key,
-            max_timestamp_micros: to_micros(stats.max_timestamp),
+            max_timestamp_micros: to_state_micros(stats.max_timestamp),
             generation,
+            size_bytes: Some(size_bytes),
         })
     }
 }
 
 pub struct ExpiringTimeKeyTableCheckpointer {
+    // the epoch's first file; files past the target size are written as parts of it
     file_name: String,
     parent: ExpiringTimeKeyTable,
     epoch: u32,
     writer: Option<AsyncArrowWriter<Box<dyn AsyncWrite + Send + Unpin>>>,
+    // the file being written, and the multipart upload it's written to
+    current_file: Option<(String, Option<String>)>,
+    // estimated uncompressed size of the data written to the current file
+    current_file_bytes: usize,
     parquet_stats: Option<ParquetStats>,
+    written_files: Vec<(String, ParquetStats, u64)>,
+    written_bytes: usize,
     prior_files: Vec<ParquetTimeFile>,
 }
 
@@ -634,7 +1167,7 @@ impl ExpiringTimeKeyTableCheckpointer {
         epoch: u32,
         prior_files: Vec<ParquetTimeFile>,
     ) -> Result<Self> {
-        let file_name = table_checkpoint_path(
+        let file_name = parent.layout.path(
             &parent.task_info.job_id,
             &parent.task_info.operator_id,
             &parent.table_name,
@@ -647,19 +1180,21 @@ impl ExpiringTimeKeyTableCheckpointer {
             parent,
             epoch,
             writer: None,
+            current_file: None,
+            current_file_bytes: 0,
             parquet_stats: None,
+            written_files: vec![],
+            written_bytes: 0,
             prior_files,
         })
     }
     async fn init_writer(&mut self) -> Result<()> {
-        let (_multipart_id, async_writer) = self
-            .parent
-            .storage_provider
-            .get_backing_store()
-            .put_multipart(&self.file_name.clone().into())
-            .await?;
+        let file_name = state_file_part_path(&self.file_name, self.written_files.len());
+        let (multipart_id, async_writer) =
+            state_file_writer(&self.parent.storage_provider, &file_name).await?;
+        self.current_file = Some((file_name, multipart_id));
         let writer_properties = WriterProperties::builder()
-            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .set_compression(state_file_compression()?)
             .build();
         self.writer = Some(AsyncArrowWriter::try_new(
             async_writer,
@@ -669,6 +1204,86 @@ impl ExpiringTimeKeyTableCheckpointer {
         )?);
         Ok(())
     }
+
+    /// Finishes the file being written, if there is one, aborting its upload on failure.
+    async fn close_file(&mut self) -> Result<()> {
+        let (Some(writer), Some((file_name, multipart_id))) =
+            (self.writer.take(), self.current_file.take())
+        else {
+            return Ok(());
+        };
+        self.current_file_bytes = 0;
+        // files stay open for as long as data for the epoch arrives, so only their final
+        // upload takes a slot, as it does for other tables' files
+        let permit = UPLOAD_SCHEDULER.acquire(&self.parent.task_info).await;
+        let file_metadata = match writer.close().await {
+            Result::Ok(file_metadata) => file_metadata,
+            Err(e) => {
+                if let Some(multipart_id) = multipart_id {
+                    abort_upload(&self.parent.storage_provider, &file_name, multipart_id).await;
+                }
+                return Err(e.into());
+            }
+        };
+
+        let stats = self
+            .parquet_stats
+            .take()
+            .expect("should have set parquet stats");
+        let size = state_file_size(&self.parent.storage_provider, &file_name).await?;
+        self.written_bytes += size as usize;
+        record_checkpoint_bytes(
+            &self.parent.task_info,
+            &self.parent.table_name,
+            size,
+            file_metadata
+                .row_groups
+                .iter()
+                .map(|row_group| row_group.total_byte_size)
+                .sum::<i64>() as u64,
+        );
+        permit.complete(size);
+        self.written_files.push((file_name, stats, size));
+        Ok(())
+    }
+}
+
+impl Drop for ExpiringTimeKeyTableCheckpointer {
+    fn drop(&mut self) {
+        // a checkpoint abandoned mid-upload would otherwise leave the upload's parts in the
+        // store, where they're never cleaned up
+        if let (Some(_), Some((file_name, Some(multipart_id)))) =
+            (self.writer.take(), self.current_file.take())
+        {
+            if let Result::Ok(handle) = tokio::runtime::Handle::try_current() {
+                let storage_provider = self.parent.storage_provider.clone();
+                handle.spawn(async move {
+                    abort_upload(&storage_provider, &file_name, multipart_id).await;
+                });
+            }
+        }
+    }
+}
+
+async fn delete_file(storage_provider: &StorageProviderRef, file_name: &str) {
+    warn!("deleting {}", file_name);
+    if let Err(e) = storage_provider.delete_if_present(file_name).await {
+        warn!("failed to delete {}: {}", file_name, e);
+    }
+}
+
+async fn abort_upload(
+    storage_provider: &StorageProviderRef,
+    file_name: &str,
+    multipart_id: String,
+) {
+    warn!("aborting upload of {}", file_name);
+    if let Err(e) = storage_provider
+        .abort_multipart(&file_name.to_string().into(), &multipart_id)
+        .await
+    {
+        warn!("failed to abort upload of {}: {}", file_name, e);
+    }
 }
 
 #[async_trait::async_trait]
@@ -689,6 +1304,10 @@ impl TableEpochCheckpointer for ExpiringTimeKeyTableCheckpointer {
             .expect("writer should be set")
             .write(&annotated_batch)
             .await?;
+        self.current_file_bytes += annotated_batch.get_array_memory_size();
+        if self.current_file_bytes >= state_file_target_size() {
+            self.close_file().await?;
+        }
         Ok(())
     }
 
@@ -696,12 +1315,14 @@ impl TableEpochCheckpointer for ExpiringTimeKeyTableCheckpointer {
         mut self,
         checkpoint: &CheckpointMessage,
     ) -> Result<Option<(Self::SubTableCheckpointMessage, usize)>> {
-        let cutoff = checkpoint
-            .watermark
-            .map(|watermark| to_micros(watermark - self.parent.retention))
+        let expiration_time = match self.parent.expiration_mode {
+            ExpirationMode::EventTime => checkpoint.watermark,
+            ExpirationMode::ProcessingTime => Some(checkpoint.time),
+        };
+        let cutoff = expiration_time
+            .map(|time| to_state_micros(retention_cutoff(time, self.parent.max_retention())))
             .unwrap_or_default();
-        let mut files: Vec<_> = self
-            .prior_files
+        let mut files: Vec<_> = std::mem::take(&mut self.prior_files)
             .into_iter()
             .filter(|file| {
                 // file must have some data greater than the cutoff and routing keys within the range.
@@ -710,27 +1331,21 @@ impl TableEpochCheckpointer for ExpiringTimeKeyTableCheckpointer {
                         && *self.parent.task_info.key_range.end() >= file.min_routing_key)
             })
             .collect();
-        let mut bytes = 0;
-        if let Some(writer) = self.writer.take() {
-            let _result = writer.close().await?;
-
-            let stats = self.parquet_stats.expect("should have set parquet stats");
-            let meta = self
-                .parent
-                .storage_provider
-                .get_backing_store()
-                .head(&(self.file_name.clone().into()))
-                .await?;
-            bytes += meta.size;
-            let file = ParquetTimeFile {
+        self.close_file().await?;
+        for (file_name, stats, size) in std::mem::take(&mut self.written_files) {
+            files.push(ParquetTimeFile {
                 epoch: self.epoch,
-                file: self.file_name,
+                file: file_name,
                 min_routing_key: stats.min_routing_key,
                 max_routing_key: stats.max_routing_key,
-                max_timestamp_micros: to_micros(stats.max_timestamp),
+                // processing-time tables expire whole files by when they were written
+                max_timestamp_micros: match self.parent.expiration_mode {
+                    ExpirationMode::EventTime => to_state_micros(stats.max_timestamp),
+                    ExpirationMode::ProcessingTime => to_micros(checkpoint.time),
+                },
                 generation: 0,
-            };
-            files.push(file)
+                size_bytes: Some(size),
+            });
         }
         if files.is_empty() {
             Ok(None)
@@ -741,7 +1356,7 @@ impl TableEpochCheckpointer for ExpiringTimeKeyTableCheckpointer {
                     watermark: checkpoint.watermark.map(to_micros),
                     files,
                 },
-                bytes,
+                self.written_bytes,
             )))
         }
     }
@@ -786,17 +1401,107 @@ impl ExpiringTimeKeyTableCheckpointer {
 pub struct ExpiringTimeKeyView {
     parent: ExpiringTimeKeyTable,
     flushed_batches_by_max_timestamp: BTreeMap<SystemTime, Vec<RecordBatch>>,
+    // for flushed batches that have been filtered by key retention, the watermark up to which
+    // filtering them again would drop nothing
+    key_retention_expiries: HashMap<SystemTime, SystemTime>,
     batches_to_flush: BTreeMap<SystemTime, Vec<RecordBatch>>,
-    state_tx: Sender<StateMessage>,
+    state_tx: StateSender,
+    changelog: Option<Changelog>,
+    size: Option<TableSize>,
+    // number of rows and in-memory size of the batches, tracked once `size` is set
+    rows: usize,
+    size_bytes: usize,
 }
 
 impl ExpiringTimeKeyView {
+    pub(crate) fn set_changelog(&mut self, changelog: Changelog) {
+        self.changelog = Some(changelog);
+    }
+
+    pub(crate) fn set_size(&mut self, size: TableSize) -> Result<()> {
+        let expire_to = match size.check(self.compute_size(), true) {
+            QuotaCheck::Ok => None,
+            QuotaCheck::Expire { target_bytes } => Some(target_bytes),
+            QuotaCheck::Exceeded(exceeded) => {
+                size.reject_restored(exceeded)?;
+                None
+            }
+        };
+        self.size = Some(size);
+        if let Some(target_bytes) = expire_to {
+            self.force_expiration(target_bytes);
+        }
+        self.record_size();
+        Ok(())
+    }
+
+    /// Recomputes the tracked size from the batches, returning the size in bytes.
+    fn compute_size(&mut self) -> usize {
+        let (rows, size_bytes) = self
+            .flushed_batches_by_max_timestamp
+            .values()
+            .chain(self.batches_to_flush.values())
+            .flatten()
+            .fold((0, 0), |(rows, bytes), batch| {
+                (
+                    rows + batch.num_rows(),
+                    bytes + batch.get_array_memory_size(),
+                )
+            });
+        self.rows = rows;
+        self.size_bytes = size_bytes;
+        size_bytes
+    }
+
+    fn record_size(&self) {
+        if let Some(size) = &self.size {
+            size.record(self.rows, self.size_bytes);
+        }
+    }
+
+    /// Expires the oldest batches until the table is within `target_bytes`, sending them to
+    /// the changelog as [`ChangeKind::QuotaExpire`].
+    fn force_expiration(&mut self, target_bytes: usize) {
+        let mut expired_rows = 0;
+        while self.size_bytes > target_bytes {
+            let Some(oldest) = self.get_min_time() else {
+                break;
+            };
+            expired_rows += self
+                .remove_timestamp(oldest, ChangeKind::QuotaExpire)
+                .iter()
+                .map(|batch| batch.num_rows())
+                .sum::<usize>();
+        }
+        if let Some(size) = &self.size {
+            size.record_expired(expired_rows);
+        }
+        warn!(
+            message = "Expired state early to stay within quota",
+            table = self.parent.table_name,
+            operator_id = self.parent.task_info.operator_id,
+            expired_rows
+        );
+    }
+
+    fn record_change(&mut self, kind: ChangeKind, timestamp: SystemTime, batches: &[RecordBatch]) {
+        if let Some(changelog) = self.changelog.as_mut() {
+            for batch in batches {
+                changelog.record(kind, Some(timestamp), ChangeData::Batch(batch.clone()));
+            }
+        }
+    }
+
     pub async fn flush(&mut self, watermark: Option<SystemTime>) -> Result<()> {
+        let watermark = self.parent.expiration_time(watermark);
         while let Some((max_timestamp, mut batches)) = self.batches_to_flush.pop_first() {
             if watermark
-                .map(|watermark| max_timestamp < watermark - self.parent.retention)
+                .map(|watermark| {
+                    max_timestamp < retention_cutoff(watermark, self.parent.max_retention())
+                })
                 .unwrap_or(false)
             {
+                self.record_change(ChangeKind::Expire, max_timestamp, &batches);
                 continue;
             }
             for batch in &batches {
@@ -807,24 +1512,140 @@ impl ExpiringTimeKeyView {
                     })
                     .await?;
             }
+            self.key_retention_expiries.remove(&max_timestamp);
             self.flushed_batches_by_max_timestamp
                 .entry(max_timestamp)
                 .or_default()
                 .append(&mut batches);
         }
         if let Some(watermark) = watermark {
-            let cutoff = watermark - self.parent.retention;
-            self.flushed_batches_by_max_timestamp =
-                self.flushed_batches_by_max_timestamp.split_off(&cutoff);
+            let cutoff = retention_cutoff(watermark, self.parent.max_retention());
+            let retained = self.flushed_batches_by_max_timestamp.split_off(&cutoff);
+            let expired = std::mem::replace(&mut self.flushed_batches_by_max_timestamp, retained);
+            for (timestamp, batches) in expired {
+                self.record_change(ChangeKind::Expire, timestamp, &batches);
+            }
+            // batches older than the default retention are only kept for keys with a longer
+            // one, and only need filtering again once the watermark passes a row's expiry
+            let default_cutoff = retention_cutoff(watermark, self.parent.retention);
+            for (max_timestamp, batches) in self
+                .flushed_batches_by_max_timestamp
+                .range_mut(..default_cutoff)
+            {
+                if self
+                    .key_retention_expiries
+                    .get(max_timestamp)
+                    .is_some_and(|expiry| watermark <= *expiry)
+                {
+                    continue;
+                }
+                let mut expiry: Option<SystemTime> = None;
+                let mut known = true;
+                for batch in batches.iter_mut() {
+                    let (filtered, batch_expiry) = self
+                        .parent
+                        .filter_by_key_retention_with_expiry(batch.clone(), watermark)?;
+                    if filtered.num_rows() > 0 {
+                        match batch_expiry {
+                            Some(batch_expiry) => {
+                                expiry = Some(
+                                    expiry.map_or(batch_expiry, |expiry| expiry.min(batch_expiry)),
+                                )
+                            }
+                            None => known = false,
+                        }
+                    }
+                    *batch = filtered;
+                }
+                batches.retain(|batch| batch.num_rows() > 0);
+                match expiry.filter(|_| known) {
+                    Some(expiry) => self.key_retention_expiries.insert(*max_timestamp, expiry),
+                    None => self.key_retention_expiries.remove(max_timestamp),
+                };
+            }
+            self.flushed_batches_by_max_timestamp
+                .retain(|_, batches| !batches.is_empty());
+            let flushed = &self.flushed_batches_by_max_timestamp;
+            self.key_retention_expiries
+                .retain(|max_timestamp, _| flushed.contains_key(max_timestamp));
+        }
+        if self.size.is_some() {
+            self.compute_size();
+            self.record_size();
+        }
+        if let Some(changelog) = self.changelog.as_mut() {
+            changelog.drain().await;
         }
         Ok(())
     }
 
-    pub fn insert(&mut self, max_timestamp: SystemTime, batch: RecordBatch) {
+    /// Inserts the batch, unless doing so would put the subtask over its state quota, which
+    /// fails with [`StateQuotaExceeded`]. If the quota is enforced by expiring data, the
+    /// oldest batches are expired to make room.
+    ///
+    /// Timestamps outside the range state can store fail with [`InvalidStateTimestamp`],
+    /// unless the table clamps them into it.
+    ///
+    /// In processing-time mode, `max_timestamp` is ignored and the batch is bucketed by the
+    /// current wall-clock time instead.
+    ///
+    /// If the table's changelog blocks and is holding as many events as it can, the insert
+    /// fails with [`ChangelogFull`]; await [`ExpiringTimeKeyView::wait_for_changelog`] before
+    /// retrying it.
+    ///
+    /// [`StateQuotaExceeded`]: crate::quota::StateQuotaExceeded
+    /// [`InvalidStateTimestamp`]: crate::timestamps::InvalidStateTimestamp
+    /// [`ChangelogFull`]: crate::changelog::ChangelogFull
+    pub fn insert(&mut self, max_timestamp: SystemTime, batch: RecordBatch) -> Result<()> {
+        if let Some(changelog) = &self.changelog {
+            changelog.check_capacity()?;
+        }
+        let max_timestamp = match self.parent.expiration_mode {
+            ExpirationMode::EventTime => check_timestamp(
+                &self.parent.table_name,
+                self.parent.timestamp_policy,
+                max_timestamp,
+            )?,
+            ExpirationMode::ProcessingTime => SystemTime::now(),
+        };
+        let batch = check_timestamp_column(
+            &self.parent.table_name,
+            self.parent.timestamp_policy,
+            batch,
+            self.parent.schema.timestamp_index(),
+        )?;
+        let mut expire_to = None;
+        if let Some(size) = &self.size {
+            let size_bytes = self.size_bytes + batch.get_array_memory_size();
+            match size.check(size_bytes, true) {
+                QuotaCheck::Ok => {}
+                QuotaCheck::Expire { target_bytes } => expire_to = Some(target_bytes),
+                QuotaCheck::Exceeded(exceeded) => return Err(exceeded.into()),
+            }
+            self.rows += batch.num_rows();
+            self.size_bytes = size_bytes;
+        }
+        self.record_change(
+            ChangeKind::Insert,
+            max_timestamp,
+            std::slice::from_ref(&batch),
+        );
         self.batches_to_flush
             .entry(max_timestamp)
             .or_default()
             .push(batch);
+        if let Some(target_bytes) = expire_to {
+            self.force_expiration(target_bytes);
+        }
+        self.record_size();
+        Ok(())
+    }
+
+    /// Waits until the changelog has delivered the events held for synchronous mutations.
+    pub async fn wait_for_changelog(&mut self) {
+        if let Some(changelog) = self.changelog.as_mut() {
+            changelog.drain().await;
+        }
     }
 
     pub fn all_batches_for_watermark(
@@ -833,8 +1654,10 @@ impl ExpiringTimeKeyView {
     ) -> impl Iterator<Item = (&SystemTime, &Vec<RecordBatch>)> {
         // TODO: decide how to manage hash range ownership. Previously this was done by iterating over the contents of the record batch.
         // Should we use statistics?
-        let cutoff = watermark
-            .map(|watermark| watermark - self.parent.retention)
+        let cutoff = self
+            .parent
+            .expiration_time(watermark)
+            .map(|watermark| retention_cutoff(watermark, self.parent.max_retention()))
             .unwrap_or_else(|| SystemTime::UNIX_EPOCH);
         debug!("CUTOFF IS {}", print_time(cutoff));
         let flushed_range = self.flushed_batches_by_max_timestamp.range(cutoff..);
@@ -842,23 +1665,67 @@ impl ExpiringTimeKeyView {
         flushed_range.chain(buffered_range)
     }
 
+    /// Batches for every key whose bucket timestamp falls within `range`, flushed batches
+    /// before buffered ones.
+    pub fn batches_in_time_range<R: RangeBounds<SystemTime> + Clone>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = (&SystemTime, &Vec<RecordBatch>)> {
+        let bounds = time_range_bounds(&range);
+        self.flushed_batches_by_max_timestamp
+            .range(bounds)
+            .chain(self.batches_to_flush.range(bounds))
+    }
+
+    /// Expires every batch that was inserted longer than the retention ago, returning the
+    /// expired batches. For processing-time tables, to be called periodically so that data
+    /// doesn't outlive its retention by up to a checkpoint interval; a no-op for event-time
+    /// tables, which expire as the watermark advances.
+    pub fn expire_processing_time(&mut self) -> Vec<RecordBatch> {
+        if self.parent.expiration_mode != ExpirationMode::ProcessingTime {
+            return vec![];
+        }
+        let cutoff = retention_cutoff(SystemTime::now(), self.parent.max_retention());
+        let mut expired = vec![];
+        while let Some(oldest) = self.get_min_time().filter(|oldest| *oldest < cutoff) {
+            expired.extend(self.expire_timestamp(oldest));
+        }
+        expired
+    }
+
     pub fn expire_timestamp(&mut self, timestamp: SystemTime) -> Vec<RecordBatch> {
+        self.remove_timestamp(timestamp, ChangeKind::Expire)
+    }
+
+    fn remove_timestamp(&mut self, timestamp: SystemTime, kind: ChangeKind) -> Vec<RecordBatch> {
         let flushed_batches = self.flushed_batches_by_max_timestamp.remove(&timestamp);
         let buffered_batches = self.batches_to_flush.remove(&timestamp);
-        match (flushed_batches, buffered_batches) {
+        let expired = match (flushed_batches, buffered_batches) {
             (None, None) => vec![],
             (None, Some(batches)) | (Some(batches), None) => batches,
             (Some(mut flushed_batches), Some(mut buffered_batches)) => {
                 flushed_batches.append(&mut buffered_batches);
                 flushed_batches
             }
+        };
+        self.record_change(kind, timestamp, &expired);
+        if self.size.is_some() {
+            for batch in &expired {
+                self.rows = self.rows.saturating_sub(batch.num_rows());
+                self.size_bytes = self
+                    .size_bytes
+                    .saturating_sub(batch.get_array_memory_size());
+            }
+            self.record_size();
         }
+        expired
     }
 
     pub async fn flush_timestamp(&mut self, bin_start: SystemTime) -> Result<()> {
         let Some(batches_to_flush) = self.batches_to_flush.remove(&bin_start) else {
             return Ok(());
         };
+        self.key_retention_expiries.remove(&bin_start);
         let flushed_vec = self
             .flushed_batches_by_max_timestamp
             .entry(bin_start)
@@ -896,7 +1763,12 @@ pub struct KeyTimeView {
     value_schema: ArroyoSchemaRef,
     // indices of schema that aren't keys, used for projection
     value_indices: Vec<usize>,
-    state_tx: Sender<StateMessage>,
+    state_tx: StateSender,
+    changelog: Option<Changelog>,
+    size: Option<TableSize>,
+    // number of rows and in-memory size of the batches, tracked once `size` is set
+    rows: usize,
+    size_bytes: usize,
 }
 
 #[derive(Debug)]
@@ -905,6 +1777,15 @@ enum BatchData {
     BatchVec(Vec<RecordBatch>),
 }
 
+impl BatchData {
+    fn num_rows(&self) -> usize {
+        match self {
+            BatchData::SingleBatch(batch) => batch.num_rows(),
+            BatchData::BatchVec(batches) => batches.iter().map(|batch| batch.num_rows()).sum(),
+        }
+    }
+}
+
 impl KeyTimeView {
     pub fn get_batch(&mut self, row: Row) -> Result<Option<&RecordBatch>> {
         if !self.keyed_data.contains_key(row.as_ref()) {
@@ -923,6 +1804,24 @@ impl KeyTimeView {
         Ok(Some(single_batch))
     }
 
+    /// Number of distinct keys in the view.
+    pub fn key_count(&self) -> usize {
+        self.keyed_data.len()
+    }
+
+    /// Number of rows stored for the key, across all timestamps.
+    pub fn row_count_for_key(&self, row: Row) -> usize {
+        self.keyed_data
+            .get(row.as_ref())
+            .map(BatchData::num_rows)
+            .unwrap_or_default()
+    }
+
+    /// Number of rows stored for all keys.
+    pub fn total_row_count(&self) -> usize {
+        self.keyed_data.values().map(BatchData::num_rows).sum()
+    }
+
     pub async fn write_batch_to_state(&mut self, batch: RecordBatch) -> Result<()> {
         self.state_tx
             .send(StateMessage::TableData {
@@ -933,7 +1832,59 @@ impl KeyTimeView {
         Ok(())
     }
 
+    pub(crate) fn set_changelog(&mut self, changelog: Changelog) {
+        self.changelog = Some(changelog);
+    }
+
+    pub(crate) fn set_size(&mut self, size: TableSize) -> Result<()> {
+        self.size_bytes = self
+            .keyed_data
+            .values()
+            .map(|data| match data {
+                BatchData::SingleBatch(batch) => batch.get_array_memory_size(),
+                BatchData::BatchVec(batches) => batches
+                    .iter()
+                    .map(|batch| batch.get_array_memory_size())
+                    .sum(),
+            })
+            .sum();
+        if let QuotaCheck::Exceeded(exceeded) = size.check(self.size_bytes, false) {
+            size.reject_restored(exceeded)?;
+        }
+        self.rows = self.total_row_count();
+        size.record(self.rows, self.size_bytes);
+        self.size = Some(size);
+        Ok(())
+    }
+
+    /// Inserts the batch, returning the keys it contained. Fails with [`StateQuotaExceeded`]
+    /// if the insert would put the subtask over its state quota, and with
+    /// [`InvalidStateTimestamp`] if it has timestamps before the epoch and the table doesn't
+    /// clamp them.
+    ///
+    /// [`StateQuotaExceeded`]: crate::quota::StateQuotaExceeded
+    /// [`InvalidStateTimestamp`]: crate::timestamps::InvalidStateTimestamp
     pub async fn insert(&mut self, batch: RecordBatch) -> Result<Vec<OwnedRow>> {
+        let batch = check_timestamp_column(
+            &self.parent.table_name,
+            self.parent.timestamp_policy,
+            batch,
+            self.parent.schema.timestamp_index(),
+        )?;
+        if let Some(size) = &self.size {
+            let size_bytes = self.size_bytes + batch.get_array_memory_size();
+            if let QuotaCheck::Exceeded(exceeded) = size.check(size_bytes, false) {
+                return Err(exceeded.into());
+            }
+            self.rows += batch.num_rows();
+            self.size_bytes = size_bytes;
+            size.record(self.rows, size_bytes);
+        }
+        if let Some(changelog) = self.changelog.as_mut() {
+            changelog
+                .emit(ChangeKind::Insert, None, ChangeData::Batch(batch.clone()))
+                .await;
+        }
         self.state_tx
             .send(StateMessage::TableData {
                 table: self.parent.table_name.to_string(),
@@ -983,7 +1934,7 @@ impl KeyTimeView {
         Ok(rows)
     }
 
-    fn new(parent: ExpiringTimeKeyTable, state_tx: Sender<StateMessage>) -> Result<Self> {
+    fn new(parent: ExpiringTimeKeyTable, state_tx: StateSender) -> Result<Self> {
         let schema = parent.schema.memory_schema();
         let key_converter = schema.converter(false)?;
         let value_schema = Arc::new(schema.schema_without_keys()?);
@@ -1003,6 +1954,742 @@ impl KeyTimeView {
             value_indices,
             value_schema,
             state_tx,
+            changelog: None,
+            size: None,
+            rows: 0,
+            size_bytes: 0,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{StringArray, TimestampNanosecondArray};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use arroyo_rpc::grpc::RetentionRule;
+    use arroyo_types::{to_nanos, TaskInfo};
+    use tokio::sync::mpsc::{channel, Receiver};
+
+    use datafusion_common::ScalarValue;
+    use prost::Message;
+
+    use super::*;
+    use crate::changelog::{ChangelogConfig, ChangelogOverflow};
+    use crate::metrics::{STATE_QUOTA_EXPIRED_ROWS_COUNTER, TABLE_BYTES_GAUGE, TABLE_SIZE_GAUGE};
+    use crate::quota::{QuotaAction, StateQuota, StateQuotaConfig, StateQuotaExceeded};
+    use crate::test_storage::TempStorage;
+    use crate::timestamp_table_config_with_retention_rules;
+    use crate::upload_scheduler::UploadSchedulerConfig;
+
+    fn keyed_schema() -> ArroyoSchema {
+        ArroyoSchema::new_keyed(
+            Arc::new(Schema::new(vec![
+                Field::new("key", DataType::Utf8, false),
+                Field::new(
+                    "_timestamp",
+                    DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    false,
+                ),
+            ])),
+            1,
+            vec![0],
+        )
+    }
+
+    fn batch(schema: &ArroyoSchema, rows: &[(&str, SystemTime)]) -> RecordBatch {
+        RecordBatch::try_new(
+            schema.schema.clone(),
+            vec![
+                Arc::new(StringArray::from_iter_values(
+                    rows.iter().map(|(key, _)| key),
+                )),
+                Arc::new(TimestampNanosecondArray::from_iter_values(
+                    rows.iter().map(|(_, time)| to_nanos(*time) as i64),
+                )),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn table_config(
+        retention: Duration,
+        retention_rules: Vec<RetentionRule>,
+    ) -> ExpiringKeyedTimeTableConfig {
+        ExpiringKeyedTimeTableConfig {
+            table_name: "e".to_string(),
+            description: "e".to_string(),
+            retention_micros: retention.as_micros() as u64,
+            schema: Some(keyed_schema().try_into().unwrap()),
+            retention_rules,
+            expiration_mode: ExpirationMode::EventTime.into(),
+        }
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn test_retention_boundary() {
+        let schema = keyed_schema();
+        let watermark = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let retention = Duration::from_secs(10);
+        let cutoff = retention_cutoff(watermark, retention);
+        assert_eq!(cutoff, SystemTime::UNIX_EPOCH + Duration::from_secs(90));
+
+        let timestamps = [
+            cutoff - Duration::from_nanos(1),
+            cutoff,
+            cutoff + Duration::from_nanos(1),
+        ];
+        let batch = RecordBatch::try_new(
+            schema.schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec!["a"; 3])),
+                Arc::new(TimestampNanosecondArray::from_iter_values(
+                    timestamps.iter().map(|t| to_nanos(*t) as i64),
+                )),
+            ],
+        )
+        .unwrap();
+
+        // filtering by the default cutoff, as restores do, and by a rule's cutoff both keep
+        // the row exactly at the cutoff
+        let filtered = schema.filter_by_time(batch.clone(), Some(cutoff)).unwrap();
+        assert_eq!(filtered.num_rows(), 2);
+        let filtered =
+            filter_by_key_retention(&schema, retention, &[(vec![], retention)], batch, watermark)
+                .unwrap();
+        assert_eq!(
+            schema.timestamp_column(&filtered).value(0),
+            to_nanos(cutoff) as i64
+        );
+        assert_eq!(filtered.num_rows(), 2);
+    }
+
+    #[test]
+    fn test_merge_checkpoint_metadata_with_retention_longer_than_watermark() {
+        let file = |name: &str, max_timestamp| ParquetTimeFile {
+            epoch: 1,
+            file: name.to_string(),
+            min_routing_key: 0,
+            max_routing_key: u64::MAX,
+            max_timestamp_micros: to_micros(max_timestamp),
+            generation: 0,
+            size_bytes: None,
+        };
+        let merged_files = |config| {
+            let subtask_metadata = ExpiringKeyedTimeSubtaskCheckpointMetadata {
+                subtask_index: 0,
+                watermark: Some(to_micros(at(10))),
+                files: vec![file("a", at(1)), file("b", at(8))],
+            };
+            ExpiringTimeKeyTable::merge_checkpoint_metadata(config, [(0, subtask_metadata)].into())
+                .unwrap()
+                .unwrap()
+                .files
+                .into_iter()
+                .map(|file| file.file)
+                .collect::<Vec<_>>()
+        };
+        let hour = Duration::from_secs(60 * 60);
+
+        // retentions longer than the watermark, whether the default or a rule's, keep every
+        // file rather than underflowing
+        assert_eq!(merged_files(table_config(hour, vec![])), vec!["a", "b"]);
+        let rule = RetentionRule {
+            key_prefix: vec![1],
+            retention_micros: hour.as_micros() as u64,
+        };
+        assert_eq!(
+            merged_files(table_config(Duration::from_secs(3), vec![rule])),
+            vec!["a", "b"]
+        );
+        // while a shorter one still drops the files that have expired
+        assert_eq!(
+            merged_files(table_config(Duration::from_secs(3), vec![])),
+            vec!["b"]
+        );
+    }
+
+    fn decoded_rules(config: &ExpiringKeyedTimeTableConfig) -> Vec<(Vec<u8>, Duration)> {
+        config
+            .retention_rules
+            .iter()
+            .map(|rule| {
+                (
+                    rule.key_prefix.clone(),
+                    Duration::from_micros(rule.retention_micros),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_typed_retention_rules() {
+        let schema = keyed_schema();
+        let hour = Duration::from_secs(60 * 60);
+        let config = |prefix| {
+            timestamp_table_config_with_retention_rules(
+                "e",
+                "e",
+                Duration::from_secs(10),
+                vec![(prefix, hour)],
+                keyed_schema(),
+            )
+            .map(|config| ExpiringKeyedTimeTableConfig::decode(&config.config[..]).unwrap())
+        };
+        let config = config(vec![ScalarValue::Utf8(Some("a".to_string()))]).unwrap();
+
+        // the rule matches the key with exactly that value, not others that start with it
+        let filtered = filter_by_key_retention(
+            &schema,
+            Duration::from_secs(10),
+            &decoded_rules(&config),
+            batch(&schema, &[("a", at(1)), ("ab", at(1)), ("b", at(1))]),
+            at(100),
+        )
+        .unwrap();
+        let keys = filtered
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(keys.iter().collect::<Vec<_>>(), vec![Some("a")]);
+
+        // prefixes have to fit the key columns
+        let prefix = vec![ScalarValue::Int64(Some(1))];
+        let err = timestamp_table_config_with_retention_rules(
+            "e",
+            "e",
+            Duration::from_secs(10),
+            vec![(prefix, hour)],
+            keyed_schema(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("key column key"), "{}", err);
+        let prefix = vec![
+            ScalarValue::Utf8(Some("a".to_string())),
+            ScalarValue::Utf8(Some("b".to_string())),
+        ];
+        let err = timestamp_table_config_with_retention_rules(
+            "e",
+            "e",
+            Duration::from_secs(10),
+            vec![(prefix, hour)],
+            keyed_schema(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("1 key columns"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_key_retention_filters_batches_once_rows_expire() {
+        let temp_storage = TempStorage::new("expiring-time-key-tests").await;
+        let task_info = Arc::new(TaskInfo::for_test("job", "expiring-key-retention"));
+        let config = timestamp_table_config_with_retention_rules(
+            "e",
+            "e",
+            Duration::from_secs(10),
+            vec![(
+                vec![ScalarValue::Utf8(Some("a".to_string()))],
+                Duration::from_secs(100),
+            )],
+            keyed_schema(),
+        )
+        .unwrap();
+        let table = ExpiringTimeKeyTable::from_config(
+            ExpiringKeyedTimeTableConfig::decode(&config.config[..]).unwrap(),
+            StateFileLayout::default(),
+            StateCodec::default(),
+            0,
+            task_info,
+            temp_storage.provider(),
+            None,
+        )
+        .unwrap();
+        let (tx, _rx) = channel(100);
+        let mut view = table
+            .get_view(StateSender::unbuffered(tx), None)
+            .await
+            .unwrap();
+        let schema = keyed_schema();
+        view.insert(at(5), batch(&schema, &[("a", at(1)), ("b", at(5))]))
+            .unwrap();
+        view.insert(at(50), batch(&schema, &[("a", at(40))]))
+            .unwrap();
+        let rows = |view: &ExpiringTimeKeyView| {
+            view.flushed_batches_by_max_timestamp
+                .iter()
+                .map(|(time, batches)| {
+                    (
+                        *time,
+                        batches.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // once the older batch is past the default retention, only the key with the longer
+        // one is kept, until the watermark passes its row's expiry
+        view.flush(Some(at(20))).await.unwrap();
+        assert_eq!(rows(&view), vec![(at(5), 1), (at(50), 1)]);
+        assert_eq!(
+            view.key_retention_expiries,
+            HashMap::from([(at(5), at(101))])
+        );
+
+        // batches whose rows haven't reached their expiry aren't filtered again
+        view.flush(Some(at(60))).await.unwrap();
+        assert_eq!(rows(&view), vec![(at(5), 1), (at(50), 1)]);
+        assert_eq!(
+            view.key_retention_expiries,
+            HashMap::from([(at(5), at(101))])
+        );
+
+        view.flush(Some(at(102))).await.unwrap();
+        assert_eq!(rows(&view), vec![(at(50), 1)]);
+        assert_eq!(
+            view.key_retention_expiries,
+            HashMap::from([(at(50), at(140))])
+        );
+    }
+
+    /// The table data sent to `rx`.
+    fn sent_data(rx: &mut Receiver<StateMessage>) -> Vec<TableData> {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|message| match message {
+                StateMessage::TableData { data, .. } => data,
+                message => panic!("unexpected message {:?}", message),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_restore_with_retention_longer_than_watermark() {
+        let temp_storage = TempStorage::new("expiring-time-key-tests").await;
+        let task_info = Arc::new(TaskInfo::for_test("job", "expiring-retention"));
+        let config = table_config(Duration::from_secs(60 * 60), vec![]);
+        let table = |checkpoint| {
+            ExpiringTimeKeyTable::from_config(
+                config.clone(),
+                StateFileLayout::default(),
+                StateCodec::default(),
+                0,
+                task_info.clone(),
+                temp_storage.provider(),
+                checkpoint,
+            )
+            .unwrap()
+        };
+        // event times near the epoch, as in test pipelines, leave the watermark well short
+        // of the retention
+        let watermark = Some(at(3));
+
+        let (tx, mut rx) = channel(100);
+        let mut view = table(None)
+            .get_view(StateSender::unbuffered(tx), None)
+            .await
+            .unwrap();
+        view.insert(at(2), batch(&keyed_schema(), &[("a", at(1)), ("b", at(2))]))
+            .unwrap();
+        view.flush(watermark).await.unwrap();
+        let mut checkpointer = table(None).epoch_checkpointer(1, None).unwrap();
+        for data in sent_data(&mut rx) {
+            checkpointer.insert_data(data).await.unwrap();
+        }
+        let checkpoint = CheckpointMessage {
+            epoch: 1,
+            time: SystemTime::now(),
+            watermark,
+            then_stop: false,
+            in_flight: false,
+        };
+        let (subtask_metadata, _) = checkpointer.finish(&checkpoint).await.unwrap().unwrap();
+        assert_eq!(subtask_metadata.files.len(), 1);
+        let checkpoint = ExpiringTimeKeyTable::merge_checkpoint_metadata(
+            config.clone(),
+            [(0, subtask_metadata)].into(),
+        )
+        .unwrap();
+        let restored = table(checkpoint);
+
+        let restored_batches = restored.restored_batches(watermark).await.unwrap();
+        assert_eq!(restored_batches.expired_files, 0);
+        assert_eq!(restored_batches.expired_rows, 0);
+
+        // both kinds of view restore every row
+        let (tx, _rx) = channel(100);
+        let view = restored
+            .get_view(StateSender::unbuffered(tx), watermark)
+            .await
+            .unwrap();
+        let rows: usize = view
+            .all_batches_for_watermark(watermark)
+            .flat_map(|(_, batches)| batches)
+            .map(|batch| batch.num_rows())
+            .sum();
+        assert_eq!(rows, 2);
+        let (tx, _rx) = channel(100);
+        let key_time_view = restored
+            .get_key_time_view(StateSender::unbuffered(tx), watermark)
+            .await
+            .unwrap();
+        assert_eq!(key_time_view.total_row_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_more_open_files_than_upload_slots() {
+        let temp_storage = TempStorage::new("expiring-time-key-tests").await;
+        let task_info = Arc::new(TaskInfo::for_test("job", "expiring-upload-slots"));
+        let table = ExpiringTimeKeyTable::from_config(
+            table_config(Duration::from_secs(60 * 60), vec![]),
+            StateFileLayout::default(),
+            StateCodec::default(),
+            0,
+            task_info,
+            temp_storage.provider(),
+            None,
+        )
+        .unwrap();
+        let (tx, mut rx) = channel(100);
+        let mut view = table
+            .get_view(StateSender::unbuffered(tx), None)
+            .await
+            .unwrap();
+        view.insert(at(2), batch(&keyed_schema(), &[("a", at(1))]))
+            .unwrap();
+        view.flush(Some(at(3))).await.unwrap();
+        let batches: Vec<_> = sent_data(&mut rx)
+            .into_iter()
+            .map(|data| match data {
+                TableData::RecordBatch(batch) => batch,
+                data => panic!("unexpected data {:?}", data),
+            })
+            .collect();
+
+        // every checkpointer has a file open before any of them finishes, which they can
+        // only do if open files don't hold upload slots
+        let writers = UploadSchedulerConfig::from_env().max_concurrent_uploads as u32 + 2;
+        let mut checkpointers = vec![];
+        for epoch in 1..=writers {
+            let mut checkpointer = table.epoch_checkpointer(epoch, None).unwrap();
+            for batch in &batches {
+                tokio::time::timeout(
+                    Duration::from_secs(10),
+                    checkpointer.insert_data(TableData::RecordBatch(batch.clone())),
+                )
+                .await
+                .expect("writing to a file shouldn't wait for an upload slot")
+                .unwrap();
+            }
+            checkpointers.push(checkpointer);
+        }
+        for (epoch, checkpointer) in (1..=writers).zip(checkpointers) {
+            let checkpoint = CheckpointMessage {
+                epoch,
+                time: SystemTime::now(),
+                watermark: Some(at(3)),
+                then_stop: false,
+                in_flight: false,
+            };
+            let (metadata, _) = checkpointer.finish(&checkpoint).await.unwrap().unwrap();
+            assert_eq!(metadata.files.len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restore_preserves_file_order() {
+        let temp_storage = TempStorage::new("expiring-time-key-tests").await;
+        let task_info = Arc::new(TaskInfo::for_test("job", "expiring-order"));
+        let config = table_config(Duration::from_secs(60 * 60), vec![]);
+        let table = |checkpoint| {
+            ExpiringTimeKeyTable::from_config(
+                config.clone(),
+                StateFileLayout::default(),
+                StateCodec::default(),
+                0,
+                task_info.clone(),
+                temp_storage.provider(),
+                checkpoint,
+            )
+            .unwrap()
+        };
+
+        // each epoch writes its own file, with rows at the same time, more of them than are
+        // prefetched at once
+        let mut previous = None;
+        let mut checkpoint = None;
+        for epoch in 1..=6 {
+            let (tx, mut rx) = channel(100);
+            let mut view = table(None)
+                .get_view(StateSender::unbuffered(tx), None)
+                .await
+                .unwrap();
+            let key = epoch.to_string();
+            view.insert(at(1), batch(&keyed_schema(), &[(key.as_str(), at(1))]))
+                .unwrap();
+            view.flush(Some(at(1))).await.unwrap();
+            let mut checkpointer = table(None).epoch_checkpointer(epoch, previous).unwrap();
+            for data in sent_data(&mut rx) {
+                checkpointer.insert_data(data).await.unwrap();
+            }
+            let message = CheckpointMessage {
+                epoch,
+                time: SystemTime::now(),
+                watermark: Some(at(1)),
+                then_stop: false,
+                in_flight: false,
+            };
+            let (subtask_metadata, _) = checkpointer.finish(&message).await.unwrap().unwrap();
+            checkpoint = ExpiringTimeKeyTable::merge_checkpoint_metadata(
+                config.clone(),
+                [(0, subtask_metadata.clone())].into(),
+            )
+            .unwrap();
+            previous = Some(subtask_metadata);
+        }
+
+        // the rows are restored in the order they were written
+        let mut restored = table(checkpoint)
+            .restored_batches(Some(at(1)))
+            .await
+            .unwrap()
+            .batches;
+        let keys: Vec<_> = restored
+            .remove(&at(1))
+            .unwrap()
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .column(0)
+                    .as_string::<i32>()
+                    .iter()
+                    .map(|key| key.unwrap().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(keys, vec!["1", "2", "3", "4", "5", "6"]);
+        assert!(restored.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_size_gauges_return_to_zero() {
+        let temp_storage = TempStorage::new("expiring-time-key-tests").await;
+        let task_info = Arc::new(TaskInfo::for_test("job", "expiring-size"));
+        let table = ExpiringTimeKeyTable::from_config(
+            table_config(Duration::from_secs(60 * 60), vec![]),
+            StateFileLayout::default(),
+            StateCodec::default(),
+            0,
+            task_info.clone(),
+            temp_storage.provider(),
+            None,
+        )
+        .unwrap();
+        let (tx, _rx) = channel(100);
+        let mut view = table
+            .get_view(StateSender::unbuffered(tx), None)
+            .await
+            .unwrap();
+        view.set_size(TableSize::new(&task_info, "e", None))
+            .unwrap();
+        let labels = [
+            task_info.operator_id.as_str(),
+            &task_info.task_index.to_string(),
+            "e",
+        ];
+        let entries = TABLE_SIZE_GAUGE.with_label_values(&labels);
+        let bytes = TABLE_BYTES_GAUGE.with_label_values(&labels);
+
+        view.insert(at(1), batch(&keyed_schema(), &[("a", at(1)), ("b", at(1))]))
+            .unwrap();
+        view.flush(Some(at(1))).await.unwrap();
+        view.insert(at(2), batch(&keyed_schema(), &[("a", at(2))]))
+            .unwrap();
+        assert_eq!(entries.get(), 3.0);
+        assert!(bytes.get() > 0.0);
+
+        // expiring flushed and buffered rows alike brings both back to zero
+        assert_eq!(view.expire_timestamp(at(1)).len(), 1);
+        assert_eq!(view.expire_timestamp(at(2)).len(), 1);
+        assert_eq!(entries.get(), 0.0);
+        assert_eq!(bytes.get(), 0.0);
+    }
+
+    fn quota(task_info: &TaskInfoRef, hard_limit_bytes: usize, action: QuotaAction) -> TableSize {
+        let quota = StateQuota::new(
+            task_info.clone(),
+            StateQuotaConfig {
+                soft_limit_bytes: None,
+                hard_limit_bytes: Some(hard_limit_bytes),
+                action,
+            },
+        );
+        TableSize::new(task_info, "e", quota)
+    }
+
+    // each of these batches has the same in-memory size
+    fn one_row(time: u64) -> RecordBatch {
+        batch(&keyed_schema(), &[("a", at(time))])
+    }
+
+    #[tokio::test]
+    async fn test_quota_rejects_inserts() {
+        let temp_storage = TempStorage::new("expiring-time-key-tests").await;
+        let task_info = Arc::new(TaskInfo::for_test("job", "expiring-quota-reject"));
+        let table = ExpiringTimeKeyTable::from_config(
+            table_config(Duration::from_secs(60 * 60), vec![]),
+            StateFileLayout::default(),
+            StateCodec::default(),
+            0,
+            task_info.clone(),
+            temp_storage.provider(),
+            None,
+        )
+        .unwrap();
+        let (tx, _rx) = channel(100);
+        let mut view = table
+            .get_view(StateSender::unbuffered(tx), None)
+            .await
+            .unwrap();
+        let batch_bytes = one_row(1).get_array_memory_size();
+        view.set_size(quota(
+            &task_info,
+            2 * batch_bytes + batch_bytes / 2,
+            QuotaAction::RejectInserts,
+        ))
+        .unwrap();
+
+        view.insert(at(1), one_row(1)).unwrap();
+        view.insert(at(2), one_row(2)).unwrap();
+        let err = view.insert(at(3), one_row(3)).unwrap_err();
+        let exceeded = err.downcast_ref::<StateQuotaExceeded>().unwrap();
+        assert_eq!(exceeded.total_bytes(), 3 * batch_bytes);
+        assert_eq!(view.rows, 2);
+        assert!(view.batches_to_flush.get(&at(3)).is_none());
+
+        // expiring data makes room again
+        view.expire_timestamp(at(1));
+        view.insert(at(3), one_row(3)).unwrap();
+        assert_eq!(view.rows, 2);
+    }
+
+    #[tokio::test]
+    async fn test_quota_force_expiration() {
+        let temp_storage = TempStorage::new("expiring-time-key-tests").await;
+        let task_info = Arc::new(TaskInfo::for_test("job", "expiring-quota-expire"));
+        let table = ExpiringTimeKeyTable::from_config(
+            table_config(Duration::from_secs(60 * 60), vec![]),
+            StateFileLayout::default(),
+            StateCodec::default(),
+            0,
+            task_info.clone(),
+            temp_storage.provider(),
+            None,
+        )
+        .unwrap();
+        let (tx, _rx) = channel(100);
+        let mut view = table
+            .get_view(StateSender::unbuffered(tx), None)
+            .await
+            .unwrap();
+        let (changelog, mut changes) = ChangelogConfig {
+            capacity: 100,
+            overflow: ChangelogOverflow::Block,
+        }
+        .channel(&task_info, "e");
+        view.set_changelog(changelog);
+        let batch_bytes = one_row(1).get_array_memory_size();
+        view.set_size(quota(
+            &task_info,
+            2 * batch_bytes + batch_bytes / 2,
+            QuotaAction::ForceExpiration,
+        ))
+        .unwrap();
+        let expired_rows = STATE_QUOTA_EXPIRED_ROWS_COUNTER.with_label_values(&[
+            task_info.operator_id.as_str(),
+            &task_info.task_index.to_string(),
+            "e",
+        ]);
+
+        view.insert(at(1), one_row(1)).unwrap();
+        view.flush(Some(at(1))).await.unwrap();
+        view.insert(at(2), one_row(2)).unwrap();
+        view.insert(at(3), one_row(3)).unwrap();
+        assert_eq!(view.rows, 2);
+        assert_eq!(view.get_min_time(), Some(at(2)));
+        assert_eq!(expired_rows.get(), 1);
+
+        view.wait_for_changelog().await;
+        let mut events = vec![];
+        while let Result::Ok(event) = changes.try_recv() {
+            events.push((event.kind, event.timestamp));
+        }
+        assert_eq!(
+            events,
+            vec![
+                (ChangeKind::Insert, Some(at(1))),
+                (ChangeKind::Insert, Some(at(2))),
+                (ChangeKind::Insert, Some(at(3))),
+                (ChangeKind::QuotaExpire, Some(at(1))),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_over_quota() {
+        let temp_storage = TempStorage::new("expiring-time-key-tests").await;
+        for action in [
+            QuotaAction::RejectInserts,
+            QuotaAction::ForceExpiration,
+            QuotaAction::FailJob,
+        ] {
+            let task_info = Arc::new(TaskInfo::for_test("job", "expiring-quota-restore"));
+            let table = ExpiringTimeKeyTable::from_config(
+                table_config(Duration::from_secs(60 * 60), vec![]),
+                StateFileLayout::default(),
+                StateCodec::default(),
+                0,
+                task_info.clone(),
+                temp_storage.provider(),
+                None,
+            )
+            .unwrap();
+            let (tx, _rx) = channel(100);
+            let mut view = table
+                .get_view(StateSender::unbuffered(tx), None)
+                .await
+                .unwrap();
+            // stands in for restored data, which is loaded before the quota is attached
+            for time in 1..=3 {
+                view.insert(at(time), one_row(time)).unwrap();
+            }
+            let batch_bytes = one_row(1).get_array_memory_size();
+            let restored =
+                view.set_size(quota(&task_info, 2 * batch_bytes + batch_bytes / 2, action));
+
+            match action {
+                QuotaAction::RejectInserts => {
+                    // the restore keeps everything, but nothing more fits
+                    restored.unwrap();
+                    assert_eq!(view.rows, 3);
+                    assert!(view.insert(at(4), one_row(4)).is_err());
+                }
+                QuotaAction::ForceExpiration => {
+                    restored.unwrap();
+                    assert_eq!(view.rows, 2);
+                    assert_eq!(view.get_min_time(), Some(at(2)));
+                }
+                QuotaAction::FailJob => {
+                    let err = restored.unwrap_err();
+                    assert!(
+                        err.to_string().contains("restored state is over quota"),
+                        "{}",
+                        err
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,34 +1,49 @@
-use crate::{CheckpointMessage, StateMessage, TableData};
-use anyhow::{anyhow, bail, Result};
+use crate::changelog::{ChangeData, ChangeKind, Changelog};
+use crate::encryption::encrypt;
+use crate::parquet::{
+    record_checkpoint_bytes, state_file_compression, state_file_target_size, upload_part_size,
+};
+use crate::prefetch::{prefetch_state_files, PrefetchConfig};
+use crate::quota::{QuotaCheck, StateQuotaExceeded, TableSize};
+use crate::restore_progress::TableRestoreProgress;
+use crate::state_serde::{decode_versioned, StateCodec, StateSerde, VersionedData};
+use crate::tables::replica::Replica;
+use crate::tables::{split_key_group, tag_key_group, TableReads};
+use crate::upload_scheduler::UPLOAD_SCHEDULER;
+use crate::write_buffer::StateSender;
+use crate::{hash_key, CheckpointMessage, StateMessage, TableData};
+use anyhow::{anyhow, bail, Context, Result};
 use arrow_array::{BinaryArray, RecordBatch};
 use arrow_schema::{DataType, Field, Schema};
 use arroyo_rpc::grpc::{
-    GlobalKeyedTableSubtaskCheckpointMetadata, GlobalKeyedTableTaskCheckpointMetadata,
-    OperatorMetadata, TableEnum,
+    FileKeyGroups, GlobalKeyedTableConfig, GlobalKeyedTableSubtaskCheckpointMetadata,
+    GlobalKeyedTableTaskCheckpointMetadata, KeyPartitioning, OperatorMetadata, TableEnum,
+    TableRestorePolicy, TableTimestampPolicy,
 };
 use arroyo_storage::StorageProviderRef;
-use arroyo_types::{to_micros, Data, Key, TaskInfoRef};
-use bincode::config;
+use arroyo_types::{key_groups_for_range, Data, Key, TaskInfo, TaskInfoRef};
+use futures::TryStreamExt;
 
 use once_cell::sync::Lazy;
 use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
 use parquet::{
     arrow::ArrowWriter,
-    basic::ZstdLevel,
     file::properties::{EnabledStatistics, WriterProperties},
 };
-use tracing::info;
+use tracing::{info, warn};
 
 use std::iter::Zip;
+use std::ops::{Bound, Range};
 
-use std::time::SystemTime;
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
     sync::Arc,
 };
-use tokio::sync::mpsc::Sender;
 
-use super::{table_checkpoint_path, CompactionConfig, Table, TableEpochCheckpointer};
+use super::{
+    state_file_part_path, CompactionConfig, RetainedFile, StateFileLayout, Table,
+    TableEpochCheckpointer,
+};
 static GLOBAL_KEY_VALUE_SCHEMA: Lazy<Arc<Schema>> = Lazy::new(|| {
     let fields = vec![
         Field::new("key", DataType::Binary, false), // non-nullable BinaryArray for 'key'
@@ -36,13 +51,95 @@ static GLOBAL_KEY_VALUE_SCHEMA: Lazy<Arc<Schema>> = Lazy::new(|| {
     ];
     Arc::new(Schema::new(fields))
 });
+// files of incremental tables, where a null value records that the key was deleted
+static GLOBAL_KEY_DELTA_SCHEMA: Lazy<Arc<Schema>> = Lazy::new(|| {
+    let fields = vec![
+        Field::new("key", DataType::Binary, false),
+        Field::new("value", DataType::Binary, true),
+    ];
+    Arc::new(Schema::new(fields))
+});
 
 #[derive(Debug, Clone)]
 pub struct GlobalKeyedTable {
     table_name: String,
+    layout: StateFileLayout,
     pub task_info: TaskInfoRef,
     storage_provider: StorageProviderRef,
     pub files: Vec<String>,
+    // what new writes are encoded with, from the table config
+    codec: StateCodec,
+    // what the restored files were encoded with, from the checkpoint
+    restored_codec: StateCodec,
+    incremental: bool,
+    partitioning: Partitioning,
+    // the key groups of the restored files' row groups, for files whose keys are tagged
+    // with their key group
+    file_key_groups: HashMap<String, FileKeyGroups>,
+    // the version of the value type new writes are, from the table config
+    value_version: u32,
+    // the version the values of each restored file were written as, if not 0
+    file_value_versions: HashMap<String, u32>,
+    restore_progress: TableRestoreProgress,
+    restore_policy: TableRestorePolicy,
+    timestamp_policy: TableTimestampPolicy,
+}
+
+/// How a table's keys are assigned to subtasks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Partitioning {
+    /// Every subtask restores every key.
+    None,
+    /// Keys belong to the subtask whose key range holds their hash, as the engine routes
+    /// them, and are stored by key group, out of this many, so that a subtask only reads
+    /// the groups overlapping its key range.
+    KeyGroups(u32),
+}
+
+impl Partitioning {
+    fn from_config(config: &GlobalKeyedTableConfig) -> Result<Self> {
+        if config.broadcast {
+            return Ok(Partitioning::None);
+        }
+        Ok(match config.partitioning() {
+            KeyPartitioning::Unpartitioned => Partitioning::None,
+            KeyPartitioning::KeyGroup if config.key_groups == 0 => bail!(
+                "table {} is partitioned by key group, but has no key groups",
+                config.table_name
+            ),
+            KeyPartitioning::KeyGroup => Partitioning::KeyGroups(config.key_groups),
+        })
+    }
+
+    /// Whether the subtask owns the keys with hash `hash`.
+    fn owns(&self, task_info: &TaskInfo, hash: u64) -> bool {
+        match self {
+            Partitioning::None => true,
+            Partitioning::KeyGroups(_) => task_info.key_range.contains(&hash),
+        }
+    }
+
+    /// The files of a checkpoint that the subtask needs to restore: for partitioned tables,
+    /// those that may hold keys it owns.
+    fn files_to_restore(
+        &self,
+        task_info: &TaskInfo,
+        checkpoint: &GlobalKeyedTableTaskCheckpointMetadata,
+    ) -> Vec<String> {
+        match self {
+            Partitioning::None => checkpoint.files.clone(),
+            Partitioning::KeyGroups(key_groups) => files_for_key_groups(
+                checkpoint.files.clone(),
+                &checkpoint.file_key_groups,
+                &owned_key_groups(task_info, *key_groups),
+            ),
+        }
+    }
+}
+
+/// The key groups that hold the keys of a subtask's key range.
+fn owned_key_groups(task_info: &TaskInfo, key_groups: u32) -> Range<u32> {
+    key_groups_for_range(&task_info.key_range, key_groups)
 }
 
 impl GlobalKeyedTable {
@@ -71,30 +168,455 @@ impl GlobalKeyedTable {
     }
     pub async fn memory_view<K: Key, V: Data>(
         &self,
-        state_tx: Sender<StateMessage>,
+        state_tx: StateSender,
     ) -> anyhow::Result<GlobalKeyedView<K, V>> {
+        Ok(self.view(self.read_all().await?, state_tx))
+    }
+
+    /// Like [`GlobalKeyedTable::memory_view`], upgrading values that were checkpointed as an
+    /// earlier version of `V`.
+    pub async fn versioned_memory_view<K: Key, V: VersionedData>(
+        &self,
+        state_tx: StateSender,
+    ) -> anyhow::Result<GlobalKeyedView<K, V>> {
+        Ok(self.view(self.read_all_versioned().await?, state_tx))
+    }
+
+    fn view<K: Key, V: Data>(
+        &self,
+        data: HashMap<K, V>,
+        state_tx: StateSender,
+    ) -> GlobalKeyedView<K, V> {
+        let mut view =
+            GlobalKeyedView::new(self.table_name.to_string(), data, self.codec, state_tx);
+        if let Some(key_groups) = self.key_groups() {
+            view.set_key_groups(key_groups);
+        }
+        view
+    }
+
+    /// The codec that views of this table should encode their writes with.
+    pub fn codec(&self) -> StateCodec {
+        self.codec
+    }
+
+    /// The number of key groups the table is partitioned into, which views of it tag their
+    /// keys with, if it's partitioned by key group.
+    pub fn key_groups(&self) -> Option<u32> {
+        match self.partitioning {
+            Partitioning::KeyGroups(key_groups) => Some(key_groups),
+            _ => None,
+        }
+    }
+
+    /// How views of the table handle timestamps outside the range state can store.
+    pub(crate) fn timestamp_policy(&self) -> TableTimestampPolicy {
+        self.timestamp_policy
+    }
+
+    /// Reads and decodes every key-value pair in the restored files, with the codec recorded
+    /// in the checkpoint. If a key appears more than once, the last value read wins, and keys
+    /// whose last entry is a delete are left out. Partitioned tables leave out the keys whose
+    /// [`hash_key`] belongs to another subtask.
+    pub(crate) async fn read_all<K: Key, V: Data>(&self) -> anyhow::Result<HashMap<K, V>> {
+        self.read_all_merged(|existing, value| *existing = value)
+            .await
+    }
+
+    /// Like [`GlobalKeyedTable::read_all`], but values for a key that appears more than once
+    /// are combined with `merge`, in the order the files are listed in the checkpoint. Each
+    /// file is read once, however many times the checkpoint lists it.
+    pub(crate) async fn read_all_merged<K: Key, V: Data>(
+        &self,
+        merge: impl FnMut(&mut V, V),
+    ) -> anyhow::Result<HashMap<K, V>> {
+        self.read_partitioned_merged(
+            merge,
+            hash_key::<K>,
+            |version, bytes| self.decode_value(version, bytes),
+            None,
+        )
+        .await
+    }
+
+    /// Like [`GlobalKeyedTable::read_all`], also returning how many keys were left out
+    /// because their last entry is a delete.
+    pub(crate) async fn read_all_counting_deletes<K: Key, V: Data>(
+        &self,
+    ) -> anyhow::Result<(HashMap<K, V>, u64)> {
+        let mut deleted = HashSet::new();
+        let data = self
+            .read_partitioned_merged(
+                |existing, value| *existing = value,
+                hash_key::<K>,
+                |version, bytes| self.decode_value(version, bytes),
+                Some(&mut deleted),
+            )
+            .await?;
+        Ok((data, deleted.len() as u64))
+    }
+
+    /// Like [`GlobalKeyedTable::read_all`], but values written as an earlier version of `V`
+    /// are upgraded to the current one. The table must be declared with `V`'s version.
+    pub(crate) async fn read_all_versioned<K: Key, V: VersionedData>(
+        &self,
+    ) -> anyhow::Result<HashMap<K, V>> {
+        if V::VERSION != self.value_version {
+            bail!(
+                "table {} is declared with value version {}, but accessed as version {} of {}",
+                self.table_name,
+                self.value_version,
+                V::VERSION,
+                std::any::type_name::<V>()
+            );
+        }
+        self.read_partitioned_merged(
+            |existing, value| *existing = value,
+            hash_key::<K>,
+            |version, bytes| decode_versioned(&self.restored_codec, version, bytes),
+            None,
+        )
+        .await
+    }
+
+    /// Like [`GlobalKeyedTable::read_all`], for tables whose keys are partitioned by only
+    /// part of the key, which `key_hash` hashes.
+    pub(crate) async fn read_partitioned<K: Key, V: Data>(
+        &self,
+        key_hash: impl Fn(&K) -> u64,
+    ) -> anyhow::Result<HashMap<K, V>> {
+        self.read_partitioned_merged(
+            |existing, value| *existing = value,
+            key_hash,
+            |version, bytes| self.decode_value(version, bytes),
+            None,
+        )
+        .await
+    }
+
+    /// The restored value of `key`, if it has one, reading the restored files for only its
+    /// entries. Like a restore, a corrupt entry fails the read unless the table is restored
+    /// with [`TableRestorePolicy::SkipCorrupt`].
+    pub(crate) async fn read_key<K: Key, V: Data>(&self, key: &K) -> anyhow::Result<Option<V>> {
+        let encoded = self.restored_codec.encode(key)?;
+        let mut value = None;
+        let mut skipped = 0;
+        self.for_each_entry(|entry| {
+            if entry.key != encoded.as_slice() {
+                return Ok(());
+            }
+            let Some(bytes) = entry.value else {
+                value = None;
+                return Ok(());
+            };
+            match self
+                .decode_value(entry.value_version, bytes)
+                .with_context(|| format!("failed to decode value in {}", entry.file))
+            {
+                Ok(decoded) => value = Some(decoded),
+                Err(e) => return self.skip_corrupt_entry(&mut skipped, e),
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(value)
+    }
+
+    /// Decodes a restored value that was written as `version` of its type, which without
+    /// an upgrade must be the version the table is declared with.
+    fn decode_value<V: Data>(&self, version: u32, bytes: &[u8]) -> Result<V> {
+        if version != self.value_version {
+            bail!(
+                "table {} has values written as version {} of their type, but is declared with \
+                version {}; they can only be restored through a VersionedData value type",
+                self.table_name,
+                version,
+                self.value_version
+            );
+        }
+        self.restored_codec.decode(bytes)
+    }
+
+    /// Reads the restored files, combining values for a key that appears more than once with
+    /// `merge` and, for partitioned tables, keeping only the keys whose `key_hash` this
+    /// subtask owns. Values are decoded with `decode_value`, given the version their file was
+    /// written as. Keys whose last entry is a delete are collected in `deleted`, if given.
+    ///
+    /// Tables restored with [`TableRestorePolicy::SkipCorrupt`] drop the entries whose key or
+    /// value fails to decode, leaving their key as the earlier entries left it.
+    async fn read_partitioned_merged<K: Key, V: Data>(
+        &self,
+        mut merge: impl FnMut(&mut V, V),
+        key_hash: impl Fn(&K) -> u64,
+        decode_value: impl Fn(u32, &[u8]) -> Result<V>,
+        mut deleted: Option<&mut HashSet<K>>,
+    ) -> anyhow::Result<HashMap<K, V>> {
         let mut data = HashMap::new();
-        for file in &self.files {
-            let contents = self.storage_provider.get(file).await?;
-            let reader = ParquetRecordBatchReaderBuilder::try_new(contents)?.build()?;
-            for batch in reader {
-                for (key, value) in self.get_key_value_iterator(&batch?)?.into_iter() {
-                    let key =
-                        key.ok_or_else(|| anyhow!("unexpected null key from record batch"))?;
-                    let value =
-                        value.ok_or_else(|| anyhow!("unexpected null value from record batch"))?;
-                    data.insert(
-                        bincode::decode_from_slice(key, config::standard())?.0,
-                        bincode::decode_from_slice(value, config::standard())?.0,
+        let mut skipped = 0;
+        self.for_each_entry(|entry| {
+            let key: K = match self
+                .restored_codec
+                .decode(entry.key)
+                .with_context(|| format!("failed to decode key in {}", entry.file))
+            {
+                Ok(key) => key,
+                Err(e) => return self.skip_corrupt_entry(&mut skipped, e),
+            };
+            if !self.partitioning.owns(&self.task_info, key_hash(&key)) {
+                // belongs to another subtask at the current parallelism
+                return Ok(());
+            }
+            let Some(value) = entry.value else {
+                // a delete, written by incremental tables
+                data.remove(&key);
+                if let Some(deleted) = deleted.as_deref_mut() {
+                    deleted.insert(key);
+                }
+                return Ok(());
+            };
+            let value = match decode_value(entry.value_version, value)
+                .with_context(|| format!("failed to decode value in {}", entry.file))
+            {
+                Ok(value) => value,
+                Err(e) => return self.skip_corrupt_entry(&mut skipped, e),
+            };
+            if let Some(deleted) = deleted.as_deref_mut() {
+                deleted.remove(&key);
+            }
+            merge_entry(&mut data, key, value, &mut merge);
+            Ok(())
+        })
+        .await?;
+        if skipped > 0 {
+            warn!(
+                "dropped {} entries of table {} that failed to decode",
+                skipped, self.table_name
+            );
+            self.restore_progress.tuples_skipped(skipped);
+        }
+        Ok(data)
+    }
+
+    /// Drops a restored entry that failed to decode with `error`, if the table's restore
+    /// policy allows it, and otherwise fails with the error.
+    fn skip_corrupt_entry(&self, skipped: &mut u64, error: anyhow::Error) -> Result<()> {
+        if self.restore_policy != TableRestorePolicy::SkipCorrupt {
+            return Err(error);
+        }
+        if *skipped == 0 {
+            warn!(
+                "dropping entries of table {} that fail to decode, starting with: {:#}",
+                self.table_name, error
+            );
+        }
+        *skipped += 1;
+        Ok(())
+    }
+
+    /// Calls `f` with every entry of the restored files, undecoded, in the order a restore
+    /// applies them: the order they were written in, with files in the order of the epochs
+    /// that wrote them and each file's entries in the order they're stored. Of files written
+    /// a row group per key group, only the row groups of the groups overlapping this
+    /// subtask's key range are read. Files are prefetched, up to `STATE_RESTORE_PARALLELISM` at a time.
+    pub(crate) async fn for_each_entry(
+        &self,
+        mut f: impl FnMut(StoredEntry<'_>) -> Result<()>,
+    ) -> Result<()> {
+        let mut files = std::pin::pin!(prefetch_state_files(
+            PrefetchConfig::from_env(),
+            self.files.clone(),
+            |file| {
+                let storage_provider = self.storage_provider.clone();
+                async move {
+                    storage_provider
+                        .get(file)
+                        .await
+                        .map_err(anyhow::Error::from)
+                }
+            }
+        ));
+        while let Some((file, contents)) = files.try_next().await? {
+            self.restore_progress.file_read(contents.len() as u64);
+            let mut builder = ParquetRecordBatchReaderBuilder::try_new(contents)?;
+            // keys in files with recorded key groups are tagged with their group
+            let file_key_groups = self.file_key_groups.get(&file);
+            if let (Some(file_key_groups), Partitioning::KeyGroups(key_groups)) =
+                (file_key_groups, self.partitioning)
+            {
+                let row_group_key_groups = &file_key_groups.row_group_key_groups;
+                if row_group_key_groups.len() != builder.metadata().num_row_groups() {
+                    bail!(
+                        "{} has {} row groups, but its checkpoint records key groups for {}",
+                        file,
+                        builder.metadata().num_row_groups(),
+                        row_group_key_groups.len()
                     );
                 }
+                let owned = owned_key_groups(&self.task_info, key_groups);
+                builder = builder.with_row_groups(
+                    row_group_key_groups
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, key_group)| owned.contains(key_group))
+                        .map(|(row_group, _)| row_group)
+                        .collect(),
+                );
+            }
+            let reader = builder.build()?;
+            let value_version = self
+                .file_value_versions
+                .get(&file)
+                .copied()
+                .unwrap_or_default();
+            for batch in reader {
+                let batch = batch.with_context(|| format!("failed to read {}", file))?;
+                let mut applied = 0;
+                for (key, value) in self.get_key_value_iterator(&batch)?.into_iter() {
+                    let key = key.ok_or_else(|| anyhow!("unexpected null key in {}", file))?;
+                    let (key_group, key) = if file_key_groups.is_some() {
+                        let (key_group, key) = split_key_group(key)
+                            .with_context(|| format!("failed to read key in {}", file))?;
+                        (Some(key_group), key)
+                    } else {
+                        (None, key)
+                    };
+                    f(StoredEntry {
+                        file: &file,
+                        key_group,
+                        key,
+                        value,
+                        value_version,
+                    })?;
+                    applied += 1;
+                }
+                self.restore_progress.tuples_applied(applied);
             }
         }
-        Ok(GlobalKeyedView {
-            table_name: self.table_name.to_string(),
-            data,
-            state_tx,
+        Ok(())
+    }
+}
+
+/// An entry as it's stored in a global keyed table's checkpoint files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoredEntry<'a> {
+    /// The file the entry was read from.
+    pub file: &'a str,
+    /// The key group the key was tagged with, for tables partitioned by key group.
+    pub key_group: Option<u32>,
+    /// The encoded key, without its key group tag.
+    pub key: &'a [u8],
+    /// The encoded value, or `None` if the entry deletes the key.
+    pub value: Option<&'a [u8]>,
+    /// The version of the value type the value was written as.
+    pub value_version: u32,
+}
+
+/// The files a subtask's checkpoint references for a table, in order: those retained from
+/// earlier epochs, then those it wrote.
+fn subtask_files(
+    subtask_meta: GlobalKeyedTableSubtaskCheckpointMetadata,
+) -> impl Iterator<Item = String> {
+    subtask_meta
+        .retained_files
+        .into_iter()
+        .chain(subtask_meta.file)
+        .chain(subtask_meta.split_files)
+}
+
+/// The sizes of the files a subtask's checkpoint references, for those whose size was recorded.
+fn subtask_file_sizes(
+    subtask_meta: &GlobalKeyedTableSubtaskCheckpointMetadata,
+) -> impl Iterator<Item = (String, u64)> + '_ {
+    subtask_meta
+        .file
+        .iter()
+        .chain(&subtask_meta.split_files)
+        .cloned()
+        .zip(subtask_meta.file_sizes.iter().copied())
+        .chain(
+            subtask_meta
+                .retained_file_sizes
+                .iter()
+                .map(|(file, size)| (file.clone(), *size)),
+        )
+}
+
+/// The files a subtask owning the key groups `owned` needs to restore of a table partitioned
+/// by key group: those with a row group in one of them. Files without recorded key groups
+/// may hold any key.
+fn files_for_key_groups(
+    files: Vec<String>,
+    file_key_groups: &HashMap<String, FileKeyGroups>,
+    owned: &Range<u32>,
+) -> Vec<String> {
+    files
+        .into_iter()
+        .filter(|file| {
+            file_key_groups.get(file).map_or(true, |file_key_groups| {
+                file_key_groups
+                    .row_group_key_groups
+                    .iter()
+                    .any(|key_group| owned.contains(key_group))
+            })
         })
+        .collect()
+}
+
+/// Orders an incremental table's files by the epoch they were written in, keeping the
+/// order within an epoch. Files of other tables have no recorded epochs and are left in
+/// the order they're listed.
+///
+/// Every write of a state file has its own path, so a file listed more than once, as when
+/// it's referenced by more than one subtask or its write was retried, is kept only where
+/// it's first listed. Entries written more than once on purpose, like the same value
+/// inserted twice into a reducing table, are in different files and are all kept.
+fn in_epoch_order(files: Vec<String>, file_epochs: &HashMap<String, u32>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut files: Vec<_> = files
+        .into_iter()
+        .filter(|file| seen.insert(file.clone()))
+        .collect();
+    if file_epochs.is_empty() {
+        return files;
+    }
+    files.sort_by_key(|file| file_epochs.get(file).copied().unwrap_or_default());
+    files
+}
+
+/// The codec recorded by the subtasks that wrote a table, failing if they disagree.
+fn subtasks_codec<'a>(
+    table_name: &str,
+    subtask_metadata: impl Iterator<Item = &'a GlobalKeyedTableSubtaskCheckpointMetadata>,
+) -> Result<Option<String>> {
+    let mut codec = None;
+    for subtask_meta in subtask_metadata {
+        let subtask_codec =
+            StateCodec::from_name(subtask_meta.value_codec.as_deref().unwrap_or(""))?;
+        match codec {
+            None => codec = Some(subtask_codec),
+            Some(existing) if existing != subtask_codec => bail!(
+                "subtasks of table {} wrote with codecs {} and {}",
+                table_name,
+                existing.name(),
+                subtask_codec.name()
+            ),
+            Some(_) => {}
+        }
+    }
+    Ok(codec.map(|codec| codec.name().to_string()))
+}
+
+pub(crate) fn merge_entry<K: Key, V>(
+    data: &mut HashMap<K, V>,
+    key: K,
+    value: V,
+    merge: &mut impl FnMut(&mut V, V),
+) {
+    match data.entry(key) {
+        std::collections::hash_map::Entry::Occupied(mut e) => merge(e.get_mut(), value),
+        std::collections::hash_map::Entry::Vacant(e) => {
+            e.insert(value);
+        }
     }
 }
 
@@ -111,31 +633,94 @@ impl Table for GlobalKeyedTable {
     fn epoch_checkpointer(
         &self,
         epoch: u32,
-        _previous_metadata: Option<Self::TableSubtaskCheckpointMetadata>,
+        previous_metadata: Option<Self::TableSubtaskCheckpointMetadata>,
     ) -> Result<Self::Checkpointer> {
+        // an incremental checkpoint references everything the previous one did
+        let previous = previous_metadata
+            .filter(|_| self.incremental)
+            .unwrap_or_default();
+        let retained_file_sizes = subtask_file_sizes(&previous).collect();
+        let retained_file_epochs = previous.file_epochs.clone();
+        let retained_file_key_groups = previous.file_key_groups.clone();
+        let retained_file_value_versions = previous.file_value_versions.clone();
+        let retained_files = subtask_files(previous).collect();
         Ok(Self::Checkpointer {
             table_name: self.table_name.clone(),
+            layout: self.layout.clone(),
             epoch,
             task_info: self.task_info.clone(),
             storage_provider: self.storage_provider.clone(),
             commit_data: None,
             latest_values: BTreeMap::new(),
+            codec: self.codec,
+            incremental: self.incremental,
+            retained_files,
+            retained_file_sizes,
+            retained_file_epochs,
+            retained_file_key_groups,
+            key_groups: self.key_groups(),
+            retained_file_value_versions,
+            value_version: self.value_version,
         })
     }
 
     fn from_config(
         config: Self::ConfigMessage,
+        layout: StateFileLayout,
+        codec: StateCodec,
+        value_version: u32,
         task_info: TaskInfoRef,
         storage_provider: StorageProviderRef,
         checkpoint_message: Option<Self::TableCheckpointMessage>,
     ) -> anyhow::Result<Self> {
+        let restored_codec = StateCodec::from_name(
+            checkpoint_message
+                .as_ref()
+                .and_then(|checkpoint| checkpoint.value_codec.as_deref())
+                .unwrap_or(""),
+        )?;
+        let checkpoint = checkpoint_message.unwrap_or_default();
+        // the files of an incremental table are all read with one codec, so new writes keep
+        // the codec of the files they'll be read with
+        let codec = if config.incremental && !checkpoint.files.is_empty() {
+            restored_codec
+        } else {
+            codec
+        };
+        let partitioning = Partitioning::from_config(&config)?;
+        // entries are applied in the order they were written, so that a delete is applied
+        // after the writes it replaces, whatever order the checkpoint lists the files in
+        let files = in_epoch_order(
+            partitioning.files_to_restore(&task_info, &checkpoint),
+            &checkpoint.file_epochs,
+        );
+        let file_key_groups = checkpoint
+            .file_key_groups
+            .into_iter()
+            .filter(|(file, _)| files.contains(file))
+            .collect();
+        let file_value_versions = checkpoint
+            .file_value_versions
+            .into_iter()
+            .filter(|(file, _)| files.contains(file))
+            .collect();
+        let restore_progress = TableRestoreProgress::detached(&task_info, &config.table_name);
         Ok(Self {
             table_name: config.table_name,
+            layout,
             task_info,
             storage_provider,
-            files: checkpoint_message
-                .map(|checkpoint| checkpoint.files)
-                .unwrap_or_default(),
+            files,
+            codec,
+            restored_codec,
+            incremental: config.incremental,
+            partitioning,
+            file_key_groups,
+            value_version,
+            file_value_versions,
+            restore_progress,
+            restore_policy: TableRestorePolicy::Strict,
+            timestamp_policy: TableTimestampPolicy::Reject,
         })
     }
 
@@ -146,54 +731,217 @@ impl Table for GlobalKeyedTable {
         if subtask_metadata.is_empty() {
             // TODO: maybe this should fail? These tables should emit on every epoch, and there should always be at least one value.
             Ok(None)
+        } else if config.broadcast {
+            // every subtask wrote the same contents, so keep the lowest subtask's copy
+            let (canonical_index, canonical) = subtask_metadata
+                .iter()
+                .min_by_key(|(subtask_index, _)| **subtask_index)
+                .expect("not empty");
+            let divergent: Vec<_> = subtask_metadata
+                .iter()
+                .filter(|(_, subtask_meta)| subtask_meta.content_hash != canonical.content_hash)
+                .map(|(subtask_index, _)| *subtask_index)
+                .collect();
+            if !divergent.is_empty() {
+                warn!(
+                    "broadcast table {} differs between subtasks {:?} and subtask {}; keeping subtask {}'s contents",
+                    config.table_name, divergent, canonical_index, canonical_index
+                );
+            }
+            Ok(Some(GlobalKeyedTableTaskCheckpointMetadata {
+                file_sizes: subtask_file_sizes(canonical).collect(),
+                files: in_epoch_order(
+                    subtask_files(canonical.clone()).collect(),
+                    &canonical.file_epochs,
+                ),
+                commit_data_by_subtask: HashMap::new(),
+                value_codec: canonical.value_codec.clone(),
+                file_epochs: canonical.file_epochs.clone(),
+                file_key_groups: canonical.file_key_groups.clone(),
+                file_value_versions: canonical.file_value_versions.clone(),
+            }))
         } else if config.uses_two_phase_commit {
+            let value_codec = subtasks_codec(&config.table_name, subtask_metadata.values())?;
             let mut files = Vec::new();
+            let mut file_sizes = HashMap::new();
+            let mut file_epochs = HashMap::new();
+            let mut file_key_groups = HashMap::new();
+            let mut file_value_versions = HashMap::new();
             let mut commit_data_by_subtask = HashMap::new();
-            for (subtask_index, subtask_meta) in subtask_metadata {
-                if let Some(file) = subtask_meta.file {
-                    files.push(file);
-                }
-                if let Some(commit_data) = subtask_meta.commit_data {
+            for (subtask_index, mut subtask_meta) in subtask_metadata {
+                if let Some(commit_data) = subtask_meta.commit_data.take() {
                     commit_data_by_subtask.insert(subtask_index, commit_data);
                 }
+                file_sizes.extend(subtask_file_sizes(&subtask_meta));
+                file_epochs.extend(std::mem::take(&mut subtask_meta.file_epochs));
+                file_key_groups.extend(std::mem::take(&mut subtask_meta.file_key_groups));
+                file_value_versions.extend(std::mem::take(&mut subtask_meta.file_value_versions));
+                files.extend(subtask_files(subtask_meta));
             }
             Ok(Some(GlobalKeyedTableTaskCheckpointMetadata {
-                files,
+                files: in_epoch_order(files, &file_epochs),
                 commit_data_by_subtask,
+                file_sizes,
+                value_codec,
+                file_epochs,
+                file_key_groups,
+                file_value_versions,
             }))
         } else {
+            let file_epochs: HashMap<_, _> = subtask_metadata
+                .values()
+                .flat_map(|subtask_meta| subtask_meta.file_epochs.clone())
+                .collect();
+            let file_key_groups = subtask_metadata
+                .values()
+                .flat_map(|subtask_meta| subtask_meta.file_key_groups.clone())
+                .collect();
+            let file_value_versions = subtask_metadata
+                .values()
+                .flat_map(|subtask_meta| subtask_meta.file_value_versions.clone())
+                .collect();
             Ok(Some(GlobalKeyedTableTaskCheckpointMetadata {
-                files: subtask_metadata
-                    .into_values()
-                    .filter_map(|subtask_meta| subtask_meta.file)
+                value_codec: subtasks_codec(&config.table_name, subtask_metadata.values())?,
+                file_sizes: subtask_metadata
+                    .values()
+                    .flat_map(subtask_file_sizes)
                     .collect(),
+                files: in_epoch_order(
+                    subtask_metadata
+                        .into_values()
+                        .flat_map(subtask_files)
+                        .collect(),
+                    &file_epochs,
+                ),
                 commit_data_by_subtask: HashMap::new(),
+                file_epochs,
+                file_key_groups,
+                file_value_versions,
             }))
         }
     }
 
     fn subtask_metadata_from_table(
         &self,
-        _table_metadata: Self::TableCheckpointMessage,
+        table_metadata: Self::TableCheckpointMessage,
     ) -> Result<Option<Self::TableSubtaskCheckpointMetadata>> {
-        // this method is to inherit data dependencies from previous epochs, but this table is regenerated every epoch.
-        Ok(None)
+        if !self.incremental {
+            // this method is to inherit data dependencies from previous epochs, but this table is regenerated every epoch.
+            return Ok(None);
+        }
+        // each subtask carries forward the files it restored: all of them, unless the table
+        // is partitioned
+        let mut table_metadata = table_metadata;
+        if self.partitioning != Partitioning::None {
+            table_metadata.files = self
+                .partitioning
+                .files_to_restore(&self.task_info, &table_metadata);
+            let restored: HashSet<_> = table_metadata.files.iter().cloned().collect();
+            table_metadata
+                .file_sizes
+                .retain(|file, _| restored.contains(file));
+            table_metadata
+                .file_epochs
+                .retain(|file, _| restored.contains(file));
+            table_metadata
+                .file_key_groups
+                .retain(|file, _| restored.contains(file));
+            table_metadata
+                .file_value_versions
+                .retain(|file, _| restored.contains(file));
+        }
+        Ok(Some(GlobalKeyedTableSubtaskCheckpointMetadata {
+            subtask_index: self.task_info.task_index as u32,
+            value_codec: table_metadata.value_codec,
+            file_epochs: table_metadata.file_epochs,
+            retained_files: table_metadata.files,
+            retained_file_sizes: table_metadata.file_sizes,
+            file_key_groups: table_metadata.file_key_groups,
+            file_value_versions: table_metadata.file_value_versions,
+            ..Default::default()
+        }))
     }
 
     fn table_type() -> TableEnum {
         TableEnum::GlobalKeyValue
     }
 
+    fn stores_table_type(table_type: TableEnum) -> bool {
+        matches!(
+            table_type,
+            TableEnum::GlobalKeyValue | TableEnum::KeyTimeMap
+        )
+    }
+
     fn task_info(&self) -> TaskInfoRef {
         self.task_info.clone()
     }
 
+    fn set_restore_progress(&mut self, progress: TableRestoreProgress) {
+        self.restore_progress = progress;
+    }
+
+    fn set_restore_policy(&mut self, policy: TableRestorePolicy) {
+        self.restore_policy = policy;
+    }
+
+    fn set_timestamp_policy(&mut self, policy: TableTimestampPolicy) {
+        self.timestamp_policy = policy;
+    }
+
     fn files_to_keep(
         _config: Self::ConfigMessage,
         checkpoint: Self::TableCheckpointMessage,
     ) -> Result<std::collections::HashSet<String>> {
         Ok(checkpoint.files.into_iter().collect())
     }
+
+    fn retained_files(
+        _config: Self::ConfigMessage,
+        mut checkpoint: Self::TableCheckpointMessage,
+        epoch: u32,
+    ) -> Result<Vec<RetainedFile>> {
+        // only incremental tables carry files over from earlier epochs, and record when
+        // each was written
+        Ok(checkpoint
+            .files
+            .into_iter()
+            .map(|file| RetainedFile {
+                bytes: checkpoint.file_sizes.remove(&file),
+                epoch: checkpoint.file_epochs.get(&file).copied().unwrap_or(epoch),
+                path: file,
+            })
+            .collect())
+    }
+
+    fn rename_files(
+        _config: Self::ConfigMessage,
+        checkpoint: Self::TableCheckpointMessage,
+        rename: &dyn Fn(&str) -> String,
+    ) -> Result<Self::TableCheckpointMessage> {
+        // the copies would commit the same data a second time
+        if !checkpoint.commit_data_by_subtask.is_empty() {
+            bail!("can't rename the files of a checkpoint with data still to be committed");
+        }
+        fn rename_keys<V>(
+            map: HashMap<String, V>,
+            rename: &dyn Fn(&str) -> String,
+        ) -> HashMap<String, V> {
+            map.into_iter()
+                .map(|(file, value)| (rename(&file), value))
+                .collect()
+        }
+        Ok(GlobalKeyedTableTaskCheckpointMetadata {
+            files: checkpoint.files.iter().map(|file| rename(file)).collect(),
+            commit_data_by_subtask: HashMap::new(),
+            file_sizes: rename_keys(checkpoint.file_sizes, rename),
+            value_codec: checkpoint.value_codec,
+            file_epochs: rename_keys(checkpoint.file_epochs, rename),
+            file_key_groups: rename_keys(checkpoint.file_key_groups, rename),
+            file_value_versions: rename_keys(checkpoint.file_value_versions, rename),
+        })
+    }
+
     fn committing_data(
         config: Self::ConfigMessage,
         table_metadata: Self::TableCheckpointMessage,
@@ -207,6 +955,7 @@ impl Table for GlobalKeyedTable {
 
     async fn compact_data(
         _config: Self::ConfigMessage,
+        _layout: &StateFileLayout,
         _compaction_config: &CompactionConfig,
         _operator_metadata: &OperatorMetadata,
         _current_metadata: Self::TableCheckpointMessage,
@@ -226,11 +975,145 @@ impl Table for GlobalKeyedTable {
 
 pub struct GlobalKeyedCheckpointer {
     table_name: String,
+    layout: StateFileLayout,
     epoch: u32,
     task_info: TaskInfoRef,
     storage_provider: StorageProviderRef,
-    latest_values: BTreeMap<Vec<u8>, Vec<u8>>,
+    // the latest value of each key written this epoch, or `None` if it was deleted
+    latest_values: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
     commit_data: Option<Vec<u8>>,
+    codec: StateCodec,
+    incremental: bool,
+    // for incremental tables, the files of earlier epochs that this one references
+    retained_files: Vec<String>,
+    retained_file_sizes: HashMap<String, u64>,
+    retained_file_epochs: HashMap<String, u32>,
+    retained_file_key_groups: HashMap<String, FileKeyGroups>,
+    // set if the table is partitioned by key group, in which case the keys are tagged with
+    // their group and each group is written as its own row group
+    key_groups: Option<u32>,
+    retained_file_value_versions: HashMap<String, u32>,
+    // the version of the value type, recorded for the files written if it isn't 0
+    value_version: u32,
+}
+
+impl GlobalKeyedCheckpointer {
+    /// Writes the latest values, split into files of about the target size, adding each
+    /// file and its size to `files` once it's written, and for tables partitioned by key
+    /// group its row groups' key groups to `file_key_groups`. Returns the number of bytes
+    /// written. Incremental tables also write deletes, as null values.
+    async fn write_files(
+        &self,
+        files: &mut Vec<(String, u64)>,
+        file_key_groups: &mut HashMap<String, FileKeyGroups>,
+    ) -> Result<u64> {
+        let path = self.layout.path(
+            &self.task_info.job_id,
+            &self.task_info.operator_id,
+            &self.table_name,
+            self.task_info.task_index,
+            self.epoch,
+            false,
+        );
+        let target_size = state_file_target_size();
+        let mut bytes = 0;
+        let mut entries = vec![];
+        let mut entries_size = 0;
+        for (key, value) in &self.latest_values {
+            let value = value.as_deref();
+            entries.push((key.as_slice(), value));
+            entries_size += key.len() + value.map(<[u8]>::len).unwrap_or_default();
+            if entries_size >= target_size {
+                let part_path = state_file_part_path(&path, files.len());
+                let (size, key_groups) = self
+                    .write_file(&part_path, std::mem::take(&mut entries))
+                    .await?;
+                bytes += size;
+                file_key_groups
+                    .extend(key_groups.map(|key_groups| (part_path.clone(), key_groups)));
+                files.push((part_path, size));
+                entries_size = 0;
+            }
+        }
+        // an empty table still writes a file, so that the epoch has a checkpoint, unless
+        // it's an incremental table whose checkpoint references earlier files
+        if !entries.is_empty() || (files.is_empty() && self.retained_files.is_empty()) {
+            let part_path = state_file_part_path(&path, files.len());
+            let (size, key_groups) = self.write_file(&part_path, entries).await?;
+            bytes += size;
+            file_key_groups.extend(key_groups.map(|key_groups| (part_path.clone(), key_groups)));
+            files.push((part_path, size));
+        }
+        Ok(bytes)
+    }
+
+    /// Writes a file of entries sorted by key. For tables partitioned by key group, each key
+    /// group is written as its own row group, and the key group of each row group is returned.
+    async fn write_file(
+        &self,
+        path: &str,
+        entries: Vec<(&[u8], Option<&[u8]>)>,
+    ) -> Result<(u64, Option<FileKeyGroups>)> {
+        let schema = if self.incremental {
+            GLOBAL_KEY_DELTA_SCHEMA.clone()
+        } else {
+            GLOBAL_KEY_VALUE_SCHEMA.clone()
+        };
+        let props = WriterProperties::builder()
+            .set_compression(state_file_compression()?)
+            .set_statistics_enabled(EnabledStatistics::None)
+            .build();
+        let cursor = Vec::new();
+        let mut writer = ArrowWriter::try_new(cursor, schema.clone(), Some(props))?;
+        let mut file_key_groups = self.key_groups.map(|_| FileKeyGroups::default());
+        let mut start = 0;
+        while start < entries.len() {
+            let end = match &mut file_key_groups {
+                Some(file_key_groups) => {
+                    // tagged keys sort by their key group
+                    let (key_group, _) = split_key_group(entries[start].0)?;
+                    file_key_groups.row_group_key_groups.push(key_group);
+                    let tag = key_group.to_be_bytes();
+                    start
+                        + entries[start..]
+                            .iter()
+                            .take_while(|(key, _)| key.starts_with(&tag))
+                            .count()
+                }
+                None => entries.len(),
+            };
+            let (keys, values): (Vec<_>, Vec<_>) = entries[start..end].iter().copied().unzip();
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(BinaryArray::from_vec(keys)),
+                    Arc::new(BinaryArray::from_opt_vec(values)),
+                ],
+            )?;
+            writer.write(&batch)?;
+            writer.flush()?;
+            start = end;
+        }
+        let uncompressed_bytes: i64 = writer
+            .flushed_row_groups()
+            .iter()
+            .map(|row_group| row_group.total_byte_size())
+            .sum();
+        let parquet_bytes = encrypt(writer.into_inner().unwrap())?;
+        let bytes = parquet_bytes.len() as u64;
+        record_checkpoint_bytes(
+            &self.task_info,
+            &self.table_name,
+            bytes,
+            uncompressed_bytes as u64,
+        );
+        let permit = UPLOAD_SCHEDULER.acquire(&self.task_info).await;
+        self.storage_provider
+            .put_in_parts(path, parquet_bytes, upload_part_size())
+            .await?;
+        permit.complete(bytes);
+        Ok((bytes, file_key_groups))
+    }
 }
 
 #[async_trait::async_trait]
@@ -251,7 +1134,14 @@ impl TableEpochCheckpointer for GlobalKeyedCheckpointer {
                 self.commit_data = Some(data);
             }
             TableData::KeyedData { key, value } => {
-                self.latest_values.insert(key, value);
+                self.latest_values.insert(key, Some(value));
+            }
+            TableData::KeyedDelete { key } if self.incremental => {
+                // the key may be in an earlier epoch's files, so the delete is written too
+                self.latest_values.insert(key, None);
+            }
+            TableData::KeyedDelete { key } => {
+                self.latest_values.remove(&key);
             }
         }
         Ok(())
@@ -261,44 +1151,49 @@ impl TableEpochCheckpointer for GlobalKeyedCheckpointer {
         self,
         _checkpoint: &CheckpointMessage,
     ) -> Result<Option<(Self::SubTableCheckpointMessage, usize)>> {
-        let _start_time = to_micros(SystemTime::now());
-        let (keys, values): (Vec<_>, Vec<_>) = self
-            .latest_values
-            .iter()
-            .map(|(k, v)| (k.as_slice(), v.as_slice()))
-            .unzip();
-        let key_array = BinaryArray::from_vec(keys);
-        let value_array = BinaryArray::from_vec(values);
-        let batch = RecordBatch::try_new(
-            GLOBAL_KEY_VALUE_SCHEMA.clone(),
-            vec![Arc::new(key_array), Arc::new(value_array)],
-        )?;
-
-        let props = WriterProperties::builder()
-            .set_compression(parquet::basic::Compression::ZSTD(ZstdLevel::default()))
-            .set_statistics_enabled(EnabledStatistics::None)
-            .build();
-        let cursor = Vec::new();
-        let mut writer = ArrowWriter::try_new(cursor, batch.schema(), Some(props))?;
-        writer.write(&batch)?;
-        writer.flush()?;
-        let parquet_bytes = writer.into_inner().unwrap();
-        let bytes = parquet_bytes.len() as u64;
-        let path = table_checkpoint_path(
-            &self.task_info.job_id,
-            &self.task_info.operator_id,
-            &self.table_name,
-            self.task_info.task_index,
-            self.epoch,
-            false,
-        );
-        self.storage_provider.put(&path, parquet_bytes).await?;
-        let _finish_time = to_micros(SystemTime::now());
+        let content_hash = hash_key(&self.latest_values);
+        let mut files = vec![];
+        let mut file_key_groups = self.retained_file_key_groups.clone();
+        let bytes = match self.write_files(&mut files, &mut file_key_groups).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                // don't leave the parts written so far behind
+                for (file, _) in &files {
+                    if let Err(delete_error) = self.storage_provider.delete_if_present(file).await {
+                        warn!(
+                            "failed to delete partial state file {}: {}",
+                            file, delete_error
+                        );
+                    }
+                }
+                return Err(e);
+            }
+        };
+        let (files, file_sizes): (Vec<_>, Vec<_>) = files.into_iter().unzip();
+        let mut file_epochs = self.retained_file_epochs;
+        if self.incremental {
+            file_epochs.extend(files.iter().map(|file| (file.clone(), self.epoch)));
+        }
+        let mut file_value_versions = self.retained_file_value_versions;
+        if self.value_version != 0 {
+            file_value_versions.extend(files.iter().map(|file| (file.clone(), self.value_version)));
+        }
+        let mut files = files.into_iter();
+        // only what was written this epoch counts towards its bytes
         Ok(Some((
             GlobalKeyedTableSubtaskCheckpointMetadata {
                 subtask_index: self.task_info.task_index as u32,
                 commit_data: self.commit_data,
-                file: Some(path),
+                file: files.next(),
+                content_hash: Some(content_hash),
+                split_files: files.collect(),
+                file_sizes,
+                value_codec: Some(self.codec.name().to_string()),
+                file_epochs,
+                retained_files: self.retained_files,
+                retained_file_sizes: self.retained_file_sizes,
+                file_key_groups,
+                file_value_versions,
             },
             bytes as usize,
         )))
@@ -313,39 +1208,1378 @@ impl TableEpochCheckpointer for GlobalKeyedCheckpointer {
     }
 }
 
+/// Position in a paginated scan of a [`GlobalKeyedView`]. Keys are ordered by their
+/// encoding with the table's codec, and the cursor holds the encoded last key of the previous page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageCursor(Vec<u8>);
+
+#[derive(Debug)]
+pub struct KeyedPage<'a, K: Key, V: Data> {
+    pub entries: Vec<(&'a K, &'a V)>,
+    /// Where the next page starts, or `None` if this was the last page.
+    pub next: Option<PageCursor>,
+}
+
 pub struct GlobalKeyedView<K: Key, V: Data> {
     table_name: String,
     data: HashMap<K, V>,
-    state_tx: Sender<StateMessage>,
+    codec: StateCodec,
+    state_tx: StateSender,
+    changelog: Option<Changelog>,
+    replica: Option<Replica<K, V>>,
+    size: Option<TableSize>,
+    // encoded size of each key and its value, and their total, tracked once `size` is set so
+    // that replacing or deleting a value doesn't re-encode it
+    entry_sizes: HashMap<K, usize>,
+    size_bytes: usize,
+    // set for tables partitioned by key group, whose keys are tagged with their group
+    key_groups: Option<u32>,
+    reads: Option<TableReads>,
+    // the keys ordered by their encoding, built by the first call to `page` and kept up to
+    // date by inserts and deletes after it
+    page_index: Option<BTreeMap<Vec<u8>, K>>,
 }
 
 impl<K: Key, V: Data> GlobalKeyedView<K, V> {
-    pub fn new(table_name: String, data: HashMap<K, V>, state_tx: Sender<StateMessage>) -> Self {
+    pub fn new(
+        table_name: String,
+        data: HashMap<K, V>,
+        codec: StateCodec,
+        state_tx: StateSender,
+    ) -> Self {
         Self {
             table_name,
             data,
+            codec,
             state_tx,
+            changelog: None,
+            replica: None,
+            size: None,
+            entry_sizes: HashMap::new(),
+            size_bytes: 0,
+            key_groups: None,
+            reads: None,
+            page_index: None,
         }
     }
-    pub async fn insert(&mut self, key: K, value: V) {
+
+    /// Tags the keys written to the table with their key group, out of `key_groups`.
+    pub(crate) fn set_key_groups(&mut self, key_groups: u32) {
+        self.key_groups = Some(key_groups);
+    }
+
+    pub(crate) fn set_reads(&mut self, reads: TableReads) {
+        self.reads = Some(reads);
+    }
+
+    fn record_hit(&self) {
+        if let Some(reads) = &self.reads {
+            reads.hit();
+        }
+    }
+
+    pub(crate) fn set_changelog(&mut self, changelog: Changelog) {
+        self.changelog = Some(changelog);
+    }
+
+    pub(crate) fn set_replica(&mut self, replica: Replica<K, V>) {
+        replica.fill(self.data.iter());
+        self.replica = Some(replica);
+    }
+
+    pub(crate) fn set_size(&mut self, size: TableSize) -> Result<()> {
+        self.entry_sizes = self
+            .data
+            .iter()
+            .map(|(key, value)| (key.clone(), self.encoded_len(key) + self.encoded_len(value)))
+            .collect();
+        self.size_bytes = self.entry_sizes.values().sum();
+        if let QuotaCheck::Exceeded(exceeded) = size.check(self.size_bytes, false) {
+            size.reject_restored(exceeded)?;
+        }
+        size.record(self.data.len(), self.size_bytes);
+        self.size = Some(size);
+        Ok(())
+    }
+
+    /// Inserts the value. If it puts the subtask over its state quota, it's written anyway
+    /// unless the quota's action is [`QuotaAction::FailJob`], in which case it fails with
+    /// [`StateQuotaExceeded`], which the operator should return to fail the job; use
+    /// [`GlobalKeyedView::try_insert`] to handle rejections instead.
+    ///
+    /// [`QuotaAction::FailJob`]: crate::quota::QuotaAction::FailJob
+    pub async fn insert(&mut self, key: K, value: V) -> Result<()> {
+        self.insert_checked(key, value, true).await
+    }
+
+    /// Inserts the value, unless doing so would put the subtask over its state quota, in
+    /// which case it fails with [`StateQuotaExceeded`].
+    pub async fn try_insert(&mut self, key: K, value: V) -> Result<()> {
+        self.insert_checked(key, value, false).await
+    }
+
+    async fn insert_checked(&mut self, key: K, value: V, must_admit: bool) -> Result<()> {
+        let key_bytes = self
+            .codec
+            .encode(&key)
+            .with_context(|| format!("failed to encode a key of table {}", self.table_name))?;
+        let value_bytes = self
+            .codec
+            .encode(&value)
+            .with_context(|| format!("failed to encode a value of table {}", self.table_name))?;
+        let entry_size = key_bytes.len() + value_bytes.len();
+        let mut size_bytes = self.size_bytes;
+        if let Some(size) = &self.size {
+            let replaced = self.entry_sizes.get(&key).copied().unwrap_or_default();
+            size_bytes = size_bytes - replaced + entry_size;
+            if let QuotaCheck::Exceeded(exceeded) = size.check(size_bytes, false) {
+                if !(must_admit && size.admit_insert(&exceeded)) {
+                    return Err(exceeded.into());
+                }
+            }
+        }
+        if let Some(changelog) = self.changelog.as_mut() {
+            changelog
+                .emit(
+                    ChangeKind::Insert,
+                    None,
+                    ChangeData::Keyed {
+                        key: key_bytes.clone(),
+                        value: Some(value_bytes.clone()),
+                    },
+                )
+                .await;
+        }
         self.state_tx
             .send(StateMessage::TableData {
                 table: self.table_name.clone(),
                 data: TableData::KeyedData {
-                    key: bincode::encode_to_vec(&key, config::standard()).unwrap(),
-                    value: bincode::encode_to_vec(&value, config::standard()).unwrap(),
+                    key: tag_key_group(self.key_groups, &key, key_bytes.clone()),
+                    value: value_bytes,
                 },
             })
             .await
-            .unwrap();
+            .with_context(|| format!("failed to write to table {}", self.table_name))?;
+        if let Some(replica) = self.replica.as_ref() {
+            replica.insert(key.clone(), value.clone());
+        }
+        if self.size.is_some() {
+            self.entry_sizes.insert(key.clone(), entry_size);
+            self.size_bytes = size_bytes;
+        }
+        if let Some(index) = &mut self.page_index {
+            index.insert(key_bytes, key.clone());
+        }
         self.data.insert(key, value);
+        self.record_size();
+        Ok(())
+    }
+
+    /// Removes the value for `key`, returning it if there was one.
+    pub async fn delete(&mut self, key: &K) -> Option<V> {
+        let value = self.data.remove(key)?;
+        let key_bytes = self.codec.encode(key).unwrap();
+        if let Some(changelog) = self.changelog.as_mut() {
+            changelog
+                .emit(
+                    ChangeKind::Delete,
+                    None,
+                    ChangeData::Keyed {
+                        key: key_bytes.clone(),
+                        value: None,
+                    },
+                )
+                .await;
+        }
+        if let Some(entry_size) = self.entry_sizes.remove(key) {
+            self.size_bytes -= entry_size;
+        }
+        if let Some(index) = &mut self.page_index {
+            index.remove(&key_bytes);
+        }
+        self.record_size();
+        self.state_tx
+            .send(StateMessage::TableData {
+                table: self.table_name.clone(),
+                data: TableData::KeyedDelete {
+                    key: tag_key_group(self.key_groups, key, key_bytes),
+                },
+            })
+            .await
+            .unwrap();
+        if let Some(replica) = self.replica.as_ref() {
+            replica.remove(key);
+        }
+        Some(value)
+    }
+
+    /// Replaces the value for `key` with the result of `f`, which is passed the current
+    /// value. If `f` returns `None` the key is deleted. A single write is sent to the table
+    /// (none if an absent key stays absent), so the update can't be interleaved with another
+    /// write to the key. The new value is inserted as by [`GlobalKeyedView::insert`].
+    pub async fn update<F: FnOnce(Option<V>) -> Option<V>>(&mut self, key: K, f: F) -> Result<()> {
+        match f(self.data.get(&key).cloned()) {
+            Some(value) => self.insert(key, value).await,
+            None => {
+                self.delete(&key).await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Inserts the value if there isn't one for `key`, returning whether it was inserted.
+    /// The value is inserted as by [`GlobalKeyedView::insert`].
+    pub async fn insert_if_absent(&mut self, key: K, value: V) -> Result<bool> {
+        if self.data.contains_key(&key) {
+            return Ok(false);
+        }
+        self.insert(key, value).await?;
+        Ok(true)
     }
 
     pub fn get_all(&self) -> &HashMap<K, V> {
+        self.record_hit();
         &self.data
     }
 
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns up to `limit` entries that sort after `after`, or from the start if it's
+    /// unset, ordered by encoded key. The first call builds an index of the encoded keys,
+    /// which inserts and deletes then keep sorted, so each page is read from the index in
+    /// time proportional to `limit` rather than to the size of the table. Fetching a page is
+    /// async so that it can be read from the state backend once the table's cache is bounded.
+    ///
+    /// The table may be modified between pages. Pages reflect the table as it is when they
+    /// are fetched: keys inserted or deleted after the cursor are seen or skipped, changes
+    /// to keys before it are not revisited, and no key is returned twice. `limit` must be
+    /// positive.
+    pub async fn page(
+        &mut self,
+        after: Option<&PageCursor>,
+        limit: usize,
+    ) -> Result<KeyedPage<'_, K, V>> {
+        if limit == 0 {
+            bail!(
+                "pages of table {} must hold at least one entry",
+                self.table_name
+            );
+        }
+        if self.page_index.is_none() {
+            let index = self
+                .data
+                .keys()
+                .map(|key| Ok((self.codec.encode(key)?, key.clone())))
+                .collect::<Result<BTreeMap<_, _>>>()
+                .with_context(|| format!("failed to encode a key of table {}", self.table_name))?;
+            self.page_index = Some(index);
+        }
+        let index = self.page_index.as_ref().expect("page index was just built");
+        let start = match after {
+            Some(cursor) => Bound::Excluded(cursor.0.as_slice()),
+            None => Bound::Unbounded,
+        };
+        let mut keys = index.range::<[u8], _>((start, Bound::Unbounded));
+        let mut entries = Vec::with_capacity(limit);
+        let mut last = None;
+        for (encoded, key) in keys.by_ref().take(limit) {
+            let Some(entry) = self.data.get_key_value(key) else {
+                bail!("the page index of table {} is out of date", self.table_name);
+            };
+            entries.push(entry);
+            last = Some(encoded);
+        }
+        let next = match (keys.next(), last) {
+            (Some(_), Some(last)) => Some(PageCursor(last.clone())),
+            _ => None,
+        };
+        self.record_hit();
+        Ok(KeyedPage { entries, next })
+    }
+
     pub fn get(&self, key: &K) -> Option<&V> {
+        self.record_hit();
         self.data.get(key)
     }
+
+    fn record_size(&self) {
+        if let Some(size) = &self.size {
+            size.record(self.data.len(), self.size_bytes);
+        }
+    }
+
+    fn encoded_len<T: bincode::Encode>(&self, value: &T) -> usize {
+        self.codec
+            .encode(value)
+            .map(|bytes| bytes.len())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::{TABLE_BYTES_GAUGE, TABLE_SIZE_GAUGE};
+    use crate::quota::{QuotaAction, StateQuota, StateQuotaConfig};
+    use crate::restore_progress::{RestoreProgress, TableRestoreStats};
+    use crate::test_storage::TempStorage;
+    use arrow_array::{ArrayRef, StringArray, UInt64Array};
+    use arroyo_rpc::get_hasher;
+    use arroyo_types::{
+        key_group_for_hash, key_groups_for_server, range_for_server, server_for_hash, TaskInfo,
+    };
+    use datafusion_common::hash_utils::create_hashes;
+    use std::time::SystemTime;
+    use tokio::sync::mpsc::{channel, Receiver};
+
+    /// Applies the writes sent by a view the way the checkpointer does, then decodes the
+    /// result as it would be read back on restore.
+    fn replay(rx: &mut Receiver<StateMessage>) -> HashMap<String, u64> {
+        let mut latest_values = HashMap::new();
+        while let Ok(message) = rx.try_recv() {
+            match message {
+                StateMessage::TableData {
+                    data: TableData::KeyedData { key, value },
+                    ..
+                } => {
+                    latest_values.insert(key, value);
+                }
+                StateMessage::TableData {
+                    data: TableData::KeyedDelete { key },
+                    ..
+                } => {
+                    latest_values.remove(&key);
+                }
+                message => panic!("unexpected message {:?}", message),
+            }
+        }
+        latest_values
+            .into_iter()
+            .map(|(key, value)| {
+                (
+                    StateCodec::default().decode(&key).unwrap(),
+                    StateCodec::default().decode(&value).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn broadcast_config(broadcast: bool) -> GlobalKeyedTableConfig {
+        GlobalKeyedTableConfig {
+            table_name: "rules".to_string(),
+            description: "rules".to_string(),
+            broadcast,
+            ..Default::default()
+        }
+    }
+
+    /// Checkpoint metadata for a table written identically by every subtask.
+    fn subtask_metadata(
+        parallelism: usize,
+        epoch: u32,
+    ) -> HashMap<u32, GlobalKeyedTableSubtaskCheckpointMetadata> {
+        let contents: BTreeMap<Vec<u8>, Vec<u8>> =
+            [(b"rule".to_vec(), b"on".to_vec())].into_iter().collect();
+        (0..parallelism)
+            .map(|subtask_index| {
+                (
+                    subtask_index as u32,
+                    GlobalKeyedTableSubtaskCheckpointMetadata {
+                        subtask_index: subtask_index as u32,
+                        file: Some(StateFileLayout::default().path(
+                            "job",
+                            "op",
+                            "rules",
+                            subtask_index,
+                            epoch,
+                            false,
+                        )),
+                        commit_data: None,
+                        content_hash: Some(hash_key(&contents)),
+                        split_files: vec![],
+                        file_sizes: vec![100],
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_broadcast_rescale() {
+        // every subtask restores all of a table's files, so a single canonical copy means
+        // every subtask restores the same contents whatever the new parallelism
+        for (old_parallelism, new_parallelism) in [(4, 2), (2, 4)] {
+            let first = GlobalKeyedTable::merge_checkpoint_metadata(
+                broadcast_config(true),
+                subtask_metadata(old_parallelism, 1),
+            )
+            .unwrap()
+            .unwrap();
+            assert_eq!(
+                first.files,
+                vec![StateFileLayout::default().path("job", "op", "rules", 0, 1, false)]
+            );
+
+            let second = GlobalKeyedTable::merge_checkpoint_metadata(
+                broadcast_config(true),
+                subtask_metadata(new_parallelism, 2),
+            )
+            .unwrap()
+            .unwrap();
+            assert_eq!(
+                second.files,
+                vec![StateFileLayout::default().path("job", "op", "rules", 0, 2, false)]
+            );
+        }
+
+        let partitioned = GlobalKeyedTable::merge_checkpoint_metadata(
+            broadcast_config(false),
+            subtask_metadata(4, 1),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(partitioned.files.len(), 4);
+    }
+
+    #[test]
+    fn test_broadcast_keeps_lowest_subtask_when_divergent() {
+        let mut metadata = subtask_metadata(3, 1);
+        metadata.remove(&0);
+        metadata.get_mut(&2).unwrap().content_hash = Some(0);
+        let merged = GlobalKeyedTable::merge_checkpoint_metadata(broadcast_config(true), metadata)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            merged.files,
+            vec![StateFileLayout::default().path("job", "op", "rules", 1, 1, false)]
+        );
+    }
+
+    #[test]
+    fn test_split_files_are_restored_in_order() {
+        let mut metadata = subtask_metadata(2, 1);
+        let first = metadata[&1].file.clone().unwrap();
+        metadata.get_mut(&1).unwrap().split_files = vec![
+            state_file_part_path(&first, 1),
+            state_file_part_path(&first, 2),
+        ];
+
+        let merged = GlobalKeyedTable::merge_checkpoint_metadata(broadcast_config(false), metadata)
+            .unwrap()
+            .unwrap();
+        assert_eq!(merged.files.len(), 4);
+        let start = merged.files.iter().position(|file| *file == first).unwrap();
+        assert_eq!(
+            merged.files[start..start + 3],
+            [
+                first.clone(),
+                format!("{}-part-001", first),
+                format!("{}-part-002", first)
+            ]
+        );
+
+        let broadcast = GlobalKeyedTable::merge_checkpoint_metadata(
+            broadcast_config(true),
+            subtask_metadata(2, 1)
+                .into_iter()
+                .map(|(index, mut meta)| {
+                    meta.split_files = vec![format!("{}-part-001", meta.file.clone().unwrap())];
+                    (index, meta)
+                })
+                .collect(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(broadcast.files.len(), 2);
+    }
+
+    #[test]
+    fn test_retained_files_have_recorded_sizes() {
+        let mut metadata = subtask_metadata(2, 3);
+        let first = metadata[&1].file.clone().unwrap();
+        let split = state_file_part_path(&first, 1);
+        let meta = metadata.get_mut(&1).unwrap();
+        meta.split_files = vec![split.clone()];
+        meta.file_sizes = vec![100, 50];
+        // written before sizes were recorded
+        metadata.get_mut(&0).unwrap().file_sizes = vec![];
+        let unsized_file = metadata[&0].file.clone().unwrap();
+
+        let merged = GlobalKeyedTable::merge_checkpoint_metadata(broadcast_config(false), metadata)
+            .unwrap()
+            .unwrap();
+        let mut retained =
+            GlobalKeyedTable::retained_files(broadcast_config(false), merged, 3).unwrap();
+        retained.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut expected = vec![
+            RetainedFile {
+                path: unsized_file,
+                epoch: 3,
+                bytes: None,
+            },
+            RetainedFile {
+                path: first,
+                epoch: 3,
+                bytes: Some(100),
+            },
+            RetainedFile {
+                path: split,
+                epoch: 3,
+                bytes: Some(50),
+            },
+        ];
+        expected.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(retained, expected);
+    }
+
+    #[tokio::test]
+    async fn test_update_matches_replay() {
+        let (tx, mut rx) = channel(100);
+        let mut view: GlobalKeyedView<String, u64> = GlobalKeyedView::new(
+            "t".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            StateSender::unbuffered(tx),
+        );
+
+        view.update("a".to_string(), |v| Some(v.unwrap_or_default() + 1))
+            .await
+            .unwrap();
+        view.update("a".to_string(), |v| Some(v.unwrap_or_default() + 1))
+            .await
+            .unwrap();
+        view.update("b".to_string(), |v| Some(v.unwrap_or_default() + 5))
+            .await
+            .unwrap();
+        view.update("b".to_string(), |_| None).await.unwrap();
+        view.update("c".to_string(), |v| v).await.unwrap();
+
+        assert_eq!(view.get(&"a".to_string()), Some(&2));
+        assert_eq!(view.get(&"b".to_string()), None);
+        assert_eq!(&replay(&mut rx), view.get_all());
+    }
+
+    #[tokio::test]
+    async fn test_pages_tolerate_modification() {
+        let (tx, _rx) = channel(1000);
+        let mut view: GlobalKeyedView<u64, u64> = GlobalKeyedView::new(
+            "t".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            StateSender::unbuffered(tx),
+        );
+        for i in 0..100 {
+            view.insert(i, i).await.unwrap();
+        }
+        assert_eq!(view.len(), 100);
+
+        let mut seen = vec![];
+        let mut cursor = None;
+        loop {
+            let page = view.page(cursor.as_ref(), 30).await.unwrap();
+            assert!(page.entries.len() <= 30);
+            seen.extend(page.entries.iter().map(|(k, _)| **k));
+            cursor = page.next;
+            if cursor.is_none() {
+                break;
+            }
+            // delete a key that's already been returned and insert a new one
+            let first = seen[0];
+            view.delete(&first).await;
+            view.insert(1000 + seen.len() as u64, 0).await.unwrap();
+        }
+        assert!(view.page(None, 0).await.is_err());
+
+        let mut deduped = seen.clone();
+        deduped.sort();
+        deduped.dedup();
+        assert_eq!(deduped.len(), seen.len());
+        assert!((0..100).all(|i| seen.contains(&i)));
+    }
+
+    #[tokio::test]
+    async fn test_insert_if_absent_matches_replay() {
+        let (tx, mut rx) = channel(100);
+        let mut view: GlobalKeyedView<String, u64> = GlobalKeyedView::new(
+            "t".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            StateSender::unbuffered(tx),
+        );
+
+        assert!(view.insert_if_absent("a".to_string(), 1).await);
+        assert!(!view.insert_if_absent("a".to_string(), 2).await);
+        view.delete(&"a".to_string()).await;
+        assert!(view.insert_if_absent("a".to_string(), 3).await);
+
+        assert_eq!(view.get(&"a".to_string()), Some(&3));
+        assert_eq!(&replay(&mut rx), view.get_all());
+    }
+
+    /// Writes `values` to a table through a view and a checkpointer, returning the table's
+    /// checkpoint metadata.
+    async fn checkpoint_table(
+        table: &GlobalKeyedTable,
+        values: &[(String, u64)],
+    ) -> GlobalKeyedTableTaskCheckpointMetadata {
+        let subtask_metadata = checkpoint_subtask(table, values).await;
+        GlobalKeyedTable::merge_checkpoint_metadata(
+            broadcast_config(false),
+            HashMap::from([(0, subtask_metadata)]),
+        )
+        .unwrap()
+        .unwrap()
+    }
+
+    /// Writes `values` to a table through a view and a checkpointer as epoch 1, returning the
+    /// subtask's checkpoint metadata.
+    async fn checkpoint_subtask(
+        table: &GlobalKeyedTable,
+        values: &[(String, u64)],
+    ) -> GlobalKeyedTableSubtaskCheckpointMetadata {
+        checkpoint_subtask_values(table, values).await
+    }
+
+    async fn checkpoint_subtask_values<V: Data>(
+        table: &GlobalKeyedTable,
+        values: &[(String, V)],
+    ) -> GlobalKeyedTableSubtaskCheckpointMetadata {
+        let (tx, mut rx) = channel(100);
+        let mut view: GlobalKeyedView<String, V> = GlobalKeyedView::new(
+            table.table_name.clone(),
+            HashMap::new(),
+            table.codec(),
+            StateSender::unbuffered(tx),
+        );
+        if let Some(key_groups) = table.key_groups() {
+            view.set_key_groups(key_groups);
+        }
+        for (key, value) in values {
+            view.insert(key.clone(), value.clone()).await.unwrap();
+        }
+        let mut checkpointer = table.epoch_checkpointer(1, None).unwrap();
+        while let Ok(StateMessage::TableData { data, .. }) = rx.try_recv() {
+            checkpointer.insert_data(data).await.unwrap();
+        }
+        let checkpoint = CheckpointMessage {
+            epoch: 1,
+            time: SystemTime::now(),
+            watermark: None,
+            then_stop: false,
+            in_flight: false,
+        };
+        let (subtask_metadata, _) = checkpointer.finish(&checkpoint).await.unwrap().unwrap();
+        subtask_metadata
+    }
+
+    #[derive(Debug, Clone, PartialEq, bincode::Encode, bincode::Decode)]
+    struct CountV1 {
+        count: u64,
+    }
+
+    #[derive(Debug, Clone, PartialEq, bincode::Encode, bincode::Decode)]
+    struct CountV2 {
+        count: u64,
+        last_seen: Option<u64>,
+    }
+
+    impl VersionedData for CountV2 {
+        const VERSION: u32 = 2;
+
+        fn decode_version(codec: &StateCodec, version: u32, bytes: &[u8]) -> Result<Self> {
+            match version {
+                1 => {
+                    let v1: CountV1 = codec.decode(bytes)?;
+                    Ok(CountV2 {
+                        count: v1.count,
+                        last_seen: None,
+                    })
+                }
+                version => bail!("can't read version {} of counts", version),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restore_upgrades_value_version() {
+        let temp_storage = TempStorage::new("version-tests").await;
+        let storage_provider = temp_storage.provider();
+        let table = |value_version, checkpoint| {
+            GlobalKeyedTable::from_config(
+                broadcast_config(false),
+                StateFileLayout::default(),
+                StateCodec::default(),
+                value_version,
+                Arc::new(TaskInfo::for_test("job", "op")),
+                storage_provider.clone(),
+                checkpoint,
+            )
+            .unwrap()
+        };
+
+        let v1 = table(1, None);
+        let subtask_metadata =
+            checkpoint_subtask_values(&v1, &[("a".to_string(), CountV1 { count: 3 })]).await;
+        let checkpoint = GlobalKeyedTable::merge_checkpoint_metadata(
+            broadcast_config(false),
+            HashMap::from([(0, subtask_metadata)]),
+        )
+        .unwrap()
+        .unwrap();
+        assert!(checkpoint
+            .files
+            .iter()
+            .all(|file| checkpoint.file_value_versions[file] == 1));
+
+        // restored as v2, with the field that was added left empty
+        let v2 = table(2, Some(checkpoint.clone()));
+        assert_eq!(
+            v2.read_all_versioned::<String, CountV2>().await.unwrap(),
+            HashMap::from([(
+                "a".to_string(),
+                CountV2 {
+                    count: 3,
+                    last_seen: None
+                }
+            )])
+        );
+        // values of another version can't be read without upgrading them
+        assert!(v2.read_all::<String, CountV2>().await.is_err());
+        // and the type must be the version the table is declared with
+        assert!(table(3, Some(checkpoint))
+            .read_all_versioned::<String, CountV2>()
+            .await
+            .is_err());
+
+        // files written as v2 are read as they are
+        let subtask_metadata = checkpoint_subtask_values(
+            &v2,
+            &[(
+                "b".to_string(),
+                CountV2 {
+                    count: 1,
+                    last_seen: Some(10),
+                },
+            )],
+        )
+        .await;
+        assert!(subtask_metadata
+            .file_value_versions
+            .values()
+            .all(|version| *version == 2));
+    }
+
+    #[tokio::test]
+    async fn test_restore_decodes_with_recorded_codec() {
+        let temp_storage = TempStorage::new("codec-tests").await;
+        let storage_provider = temp_storage.provider();
+        let task_info = Arc::new(TaskInfo::for_test("job", "op"));
+        let table = |name: &str, codec, checkpoint| {
+            GlobalKeyedTable::from_config(
+                GlobalKeyedTableConfig {
+                    table_name: name.to_string(),
+                    ..broadcast_config(false)
+                },
+                StateFileLayout::default(),
+                codec,
+                0,
+                task_info.clone(),
+                storage_provider.clone(),
+                checkpoint,
+            )
+            .unwrap()
+        };
+        let values = vec![("a".to_string(), 1), ("b".to_string(), u64::MAX)];
+
+        // two tables of the same operator, each with its own codec
+        let fixed =
+            checkpoint_table(&table("fixed", StateCodec::FixedIntBincode, None), &values).await;
+        let default = checkpoint_table(&table("default", StateCodec::Bincode, None), &values).await;
+        assert_eq!(fixed.value_codec.as_deref(), Some("bincode-fixint"));
+        assert_eq!(default.value_codec.as_deref(), Some("bincode"));
+
+        // restored with the recorded codec, even where the config now asks for another
+        let expected: HashMap<String, u64> = values.into_iter().collect();
+        for (name, checkpoint) in [("fixed", fixed), ("default", default)] {
+            let restored = table(name, StateCodec::Bincode, Some(checkpoint.clone()));
+            assert_eq!(restored.read_all::<String, u64>().await.unwrap(), expected);
+            let restored = table(name, StateCodec::FixedIntBincode, Some(checkpoint));
+            assert_eq!(restored.read_all::<String, u64>().await.unwrap(), expected);
+        }
+
+        // checkpoints from before codecs were recorded are bincode
+        let legacy = table("legacy", StateCodec::Bincode, None);
+        let mut checkpoint = checkpoint_table(&legacy, &[("a".to_string(), 1)]).await;
+        checkpoint.value_codec = None;
+        let restored = table("legacy", StateCodec::FixedIntBincode, Some(checkpoint));
+        assert_eq!(
+            restored.read_all::<String, u64>().await.unwrap(),
+            HashMap::from([("a".to_string(), 1)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_reads_repeated_files_once() {
+        let temp_storage = TempStorage::new("repeated-file-tests").await;
+        let storage_provider = temp_storage.provider();
+        let task_info = Arc::new(TaskInfo::for_test("job", "op"));
+        let table = |name: &str, checkpoint| {
+            GlobalKeyedTable::from_config(
+                GlobalKeyedTableConfig {
+                    table_name: name.to_string(),
+                    ..broadcast_config(false)
+                },
+                StateFileLayout::default(),
+                StateCodec::default(),
+                0,
+                task_info.clone(),
+                storage_provider.clone(),
+                checkpoint,
+            )
+            .unwrap()
+        };
+        let sum = |existing: &mut u64, value: u64| *existing += value;
+        let values = [("a".to_string(), 1)];
+        let first = checkpoint_table(&table("first", None), &values).await;
+        // the same value, written again on purpose
+        let second = checkpoint_table(&table("second", None), &values).await;
+        let (first_file, second_file) = (first.files[0].clone(), second.files[0].clone());
+        assert_ne!(first_file, second_file);
+
+        // a file listed twice, as when its write was retried, is merged once
+        let mut repeated = first.clone();
+        repeated.files = vec![first_file.clone(), first_file.clone()];
+        let restored = table("first", Some(repeated));
+        assert_eq!(restored.files, vec![first_file.clone()]);
+        assert_eq!(
+            restored.read_all_merged::<String, u64>(sum).await.unwrap(),
+            HashMap::from([("a".to_string(), 1)])
+        );
+
+        // while the same entry in two files is merged twice
+        let mut both = first;
+        both.files = vec![first_file, second_file];
+        let restored = table("first", Some(both));
+        assert_eq!(
+            restored.read_all_merged::<String, u64>(sum).await.unwrap(),
+            HashMap::from([("a".to_string(), 2)])
+        );
+    }
+
+    /// Applies `inserts` and `deletes` to a view of `table` and checkpoints them as `epoch`,
+    /// returning the subtask metadata, the merged table metadata and the bytes written.
+    async fn checkpoint_epoch(
+        table: &GlobalKeyedTable,
+        epoch: u32,
+        previous: Option<GlobalKeyedTableSubtaskCheckpointMetadata>,
+        inserts: &[(&str, u64)],
+        deletes: &[&str],
+    ) -> (
+        GlobalKeyedTableSubtaskCheckpointMetadata,
+        GlobalKeyedTableTaskCheckpointMetadata,
+        usize,
+    ) {
+        let (tx, mut rx) = channel(100);
+        let mut view: GlobalKeyedView<String, u64> = GlobalKeyedView::new(
+            table.table_name.clone(),
+            table.read_all().await.unwrap(),
+            table.codec(),
+            StateSender::unbuffered(tx),
+        );
+        for (key, value) in inserts {
+            view.insert(key.to_string(), *value).await.unwrap();
+        }
+        for key in deletes {
+            view.delete(&key.to_string()).await;
+        }
+        let mut checkpointer = table.epoch_checkpointer(epoch, previous).unwrap();
+        while let Ok(StateMessage::TableData { data, .. }) = rx.try_recv() {
+            checkpointer.insert_data(data).await.unwrap();
+        }
+        let checkpoint = CheckpointMessage {
+            epoch,
+            time: SystemTime::now(),
+            watermark: None,
+            then_stop: false,
+            in_flight: false,
+        };
+        let (subtask_metadata, bytes) = checkpointer.finish(&checkpoint).await.unwrap().unwrap();
+        let table_metadata = GlobalKeyedTable::merge_checkpoint_metadata(
+            GlobalKeyedTableConfig {
+                table_name: table.table_name.clone(),
+                incremental: table.incremental,
+                ..broadcast_config(false)
+            },
+            HashMap::from([(0, subtask_metadata.clone())]),
+        )
+        .unwrap()
+        .unwrap();
+        (subtask_metadata, table_metadata, bytes)
+    }
+
+    #[tokio::test]
+    async fn test_incremental_checkpoints_reference_earlier_files() {
+        let temp_storage = TempStorage::new("incremental-tests").await;
+        let storage_provider = temp_storage.provider();
+        let task_info = Arc::new(TaskInfo::for_test("job", "op"));
+        let table = |checkpoint| {
+            GlobalKeyedTable::from_config(
+                GlobalKeyedTableConfig {
+                    table_name: "counts".to_string(),
+                    incremental: true,
+                    ..broadcast_config(false)
+                },
+                StateFileLayout::default(),
+                StateCodec::default(),
+                0,
+                task_info.clone(),
+                storage_provider.clone(),
+                checkpoint,
+            )
+            .unwrap()
+        };
+
+        let first = table(None);
+        let (subtask_1, epoch_1, bytes_1) =
+            checkpoint_epoch(&first, 1, None, &[("a", 1), ("b", 2)], &[]).await;
+        assert!(bytes_1 > 0);
+
+        // the second epoch writes only its own changes, including the delete
+        let second = table(Some(epoch_1.clone()));
+        let (_, epoch_2, bytes_2) =
+            checkpoint_epoch(&second, 2, Some(subtask_1), &[("c", 3)], &["a"]).await;
+        assert_eq!(epoch_2.files.len(), 2);
+        assert_eq!(epoch_2.files[0], epoch_1.files[0]);
+        assert_eq!(epoch_2.file_epochs[&epoch_2.files[0]], 1);
+        assert_eq!(epoch_2.file_epochs[&epoch_2.files[1]], 2);
+        let retained =
+            GlobalKeyedTable::retained_files(GlobalKeyedTableConfig::default(), epoch_2.clone(), 2)
+                .unwrap();
+        assert_eq!(
+            retained.iter().filter_map(|file| file.bytes).sum::<u64>(),
+            (bytes_1 + bytes_2) as u64
+        );
+
+        // restores read the files in epoch order, applying the delete
+        let restored = table(Some(epoch_2.clone()));
+        assert_eq!(
+            restored.read_all::<String, u64>().await.unwrap(),
+            HashMap::from([("b".to_string(), 2), ("c".to_string(), 3)])
+        );
+        // even if the checkpoint lists the file with the delete before the one it applies to
+        let mut shuffled = epoch_2.clone();
+        shuffled.files.reverse();
+        assert_eq!(
+            table(Some(shuffled))
+                .read_all::<String, u64>()
+                .await
+                .unwrap(),
+            HashMap::from([("b".to_string(), 2), ("c".to_string(), 3)])
+        );
+
+        // the entries as stored include the delete, after the write it replaces
+        let mut entries = vec![];
+        restored
+            .for_each_entry(|entry| {
+                let key: String = restored.restored_codec.decode(entry.key)?;
+                entries.push((entry.file.to_string(), key, entry.value.is_some()));
+                Ok(())
+            })
+            .await
+            .unwrap();
+        entries.sort_by_key(|(file, key, _)| {
+            (epoch_2.files.iter().position(|f| f == file), key.clone())
+        });
+        assert_eq!(
+            entries
+                .iter()
+                .map(|(_, key, has_value)| (key.as_str(), *has_value))
+                .collect::<Vec<_>>(),
+            vec![("a", true), ("b", true), ("a", false), ("c", true)]
+        );
+
+        // an epoch without changes writes nothing, and still references everything
+        let previous = restored
+            .subtask_metadata_from_table(epoch_2.clone())
+            .unwrap();
+        let (subtask_3, epoch_3, bytes_3) =
+            checkpoint_epoch(&restored, 3, previous, &[], &[]).await;
+        assert_eq!(bytes_3, 0);
+        assert_eq!(subtask_3.file, None);
+        assert_eq!(epoch_3.files, epoch_2.files);
+        assert_eq!(
+            GlobalKeyedTable::files_to_keep(GlobalKeyedTableConfig::default(), epoch_3.clone())
+                .unwrap(),
+            epoch_2.files.iter().cloned().collect()
+        );
+        assert_eq!(
+            table(Some(epoch_3))
+                .read_all::<String, u64>()
+                .await
+                .unwrap(),
+            HashMap::from([("b".to_string(), 2), ("c".to_string(), 3)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_reports_progress() {
+        let temp_storage = TempStorage::new("restore-progress-tests").await;
+        let storage_provider = temp_storage.provider();
+        let task_info = Arc::new(TaskInfo::for_test("job", "op"));
+        let table = |checkpoint| {
+            GlobalKeyedTable::from_config(
+                GlobalKeyedTableConfig {
+                    table_name: "counts".to_string(),
+                    incremental: true,
+                    ..broadcast_config(false)
+                },
+                StateFileLayout::default(),
+                StateCodec::default(),
+                0,
+                task_info.clone(),
+                storage_provider.clone(),
+                checkpoint,
+            )
+            .unwrap()
+        };
+
+        // each epoch writes a file of its own
+        let epochs = 8;
+        let keys_per_epoch = 50;
+        let mut previous = None;
+        let mut metadata = None;
+        let mut total_bytes = 0;
+        for epoch in 1..=epochs {
+            let keys: Vec<_> = (0..keys_per_epoch)
+                .map(|i| (format!("{}-{}", epoch, i), i as u64))
+                .collect();
+            let inserts: Vec<_> = keys.iter().map(|(key, i)| (key.as_str(), *i)).collect();
+            let (subtask, table_metadata, bytes) =
+                checkpoint_epoch(&table(metadata.clone()), epoch, previous, &inserts, &[]).await;
+            previous = Some(subtask);
+            metadata = Some(table_metadata);
+            total_bytes += bytes as u64;
+        }
+
+        let updates = Arc::new(std::sync::Mutex::new(vec![]));
+        let progress = {
+            let updates = updates.clone();
+            RestoreProgress::with_callback(&task_info, move |table_name, stats| {
+                assert_eq!(table_name, "counts");
+                updates.lock().unwrap().push(*stats);
+            })
+        };
+        let mut restored = table(metadata);
+        Table::set_restore_progress(&mut restored, progress.table("counts"));
+        assert_eq!(
+            restored.read_all::<String, u64>().await.unwrap().len(),
+            (epochs * keys_per_epoch) as usize
+        );
+
+        let expected = TableRestoreStats {
+            files: epochs as u64,
+            tuples: (epochs * keys_per_epoch) as u64,
+            bytes: total_bytes,
+            skipped: 0,
+        };
+        let updates = updates.lock().unwrap();
+        assert!(updates.len() >= 2 * epochs as usize);
+        for (before, after) in updates.iter().zip(updates.iter().skip(1)) {
+            assert!(after.files >= before.files);
+            assert!(after.tuples >= before.tuples);
+            assert!(after.bytes >= before.bytes);
+            assert_ne!(before, after, "every update makes progress");
+        }
+        assert_eq!(updates.last(), Some(&expected));
+        assert_eq!(progress.snapshot().tables["counts"], expected);
+    }
+
+    #[tokio::test]
+    async fn test_restore_skips_corrupt_entries() {
+        let temp_storage = TempStorage::new("restore-policy-tests").await;
+        let storage_provider = temp_storage.provider();
+        let task_info = Arc::new(TaskInfo::for_test("job", "op"));
+        let table = |checkpoint| {
+            GlobalKeyedTable::from_config(
+                GlobalKeyedTableConfig {
+                    table_name: "flags".to_string(),
+                    ..broadcast_config(false)
+                },
+                StateFileLayout::default(),
+                StateCodec::default(),
+                0,
+                task_info.clone(),
+                storage_provider.clone(),
+                checkpoint,
+            )
+            .unwrap()
+        };
+
+        // read as bools, the values past 1 fail to decode
+        let keys: Vec<_> = (0..10).map(|i| (format!("key-{}", i), i as u64)).collect();
+        let inserts: Vec<_> = keys.iter().map(|(key, i)| (key.as_str(), *i)).collect();
+        let (_, metadata, _) = checkpoint_epoch(&table(None), 1, None, &inserts, &[]).await;
+
+        assert!(table(Some(metadata.clone()))
+            .read_all::<String, bool>()
+            .await
+            .is_err());
+
+        let progress = RestoreProgress::new(&task_info);
+        let mut restored = table(Some(metadata));
+        Table::set_restore_policy(&mut restored, TableRestorePolicy::SkipCorrupt);
+        Table::set_restore_progress(&mut restored, progress.table("flags"));
+        assert_eq!(
+            restored.read_all::<String, bool>().await.unwrap(),
+            HashMap::from([("key-0".to_string(), false), ("key-1".to_string(), true)])
+        );
+        let stats = progress.snapshot().tables["flags"];
+        assert_eq!(stats.skipped, 8);
+        assert_eq!(stats.tuples, 10);
+    }
+
+    #[tokio::test]
+    async fn test_key_group_rescale() {
+        let temp_storage = TempStorage::new("key-group-tests").await;
+        let storage_provider = temp_storage.provider();
+        let key_groups = 16;
+        let config = GlobalKeyedTableConfig {
+            table_name: "groups".to_string(),
+            partitioning: KeyPartitioning::KeyGroup.into(),
+            key_groups,
+            ..broadcast_config(false)
+        };
+        let table = |config: &GlobalKeyedTableConfig, task_index, parallelism, checkpoint| {
+            GlobalKeyedTable::from_config(
+                config.clone(),
+                StateFileLayout::default(),
+                StateCodec::default(),
+                0,
+                Arc::new(TaskInfo {
+                    task_index,
+                    parallelism,
+                    key_range: range_for_server(task_index, parallelism),
+                    ..TaskInfo::for_test("job", "op")
+                }),
+                storage_provider.clone(),
+                checkpoint,
+            )
+            .unwrap()
+        };
+        let values: Vec<(String, u64)> = (0..100).map(|i| (format!("key-{}", i), i)).collect();
+
+        // the hash the engine routes each key's records by
+        let keys: ArrayRef = Arc::new(StringArray::from_iter_values(
+            values.iter().map(|(key, _)| key),
+        ));
+        let mut hashes = vec![0; values.len()];
+        create_hashes(&[keys], &get_hasher(), &mut hashes).unwrap();
+        let routing_hashes: HashMap<_, _> = values
+            .iter()
+            .map(|(key, _)| key.clone())
+            .zip(hashes)
+            .collect();
+        for (key, hash) in &routing_hashes {
+            assert_eq!(hash_key(key), *hash);
+        }
+        let ints: ArrayRef = Arc::new(UInt64Array::from_iter_values(0..100));
+        let mut hashes = vec![0; 100];
+        create_hashes(&[ints], &get_hasher(), &mut hashes).unwrap();
+        for (i, hash) in hashes.into_iter().enumerate() {
+            assert_eq!(hash_key(&(i as u64)), hash);
+        }
+
+        for (old_parallelism, new_parallelism) in [(4, 2), (2, 4), (3, 2), (2, 3)] {
+            // each old subtask checkpoints the keys the engine routes to it
+            let mut subtask_metadata = HashMap::new();
+            for task_index in 0..old_parallelism {
+                let owned: Vec<_> = values
+                    .iter()
+                    .filter(|(key, _)| {
+                        server_for_hash(routing_hashes[key], old_parallelism) == task_index
+                    })
+                    .cloned()
+                    .collect();
+                let table = table(&config, task_index, old_parallelism, None);
+                subtask_metadata
+                    .insert(task_index as u32, checkpoint_subtask(&table, &owned).await);
+            }
+            let checkpoint =
+                GlobalKeyedTable::merge_checkpoint_metadata(config.clone(), subtask_metadata)
+                    .unwrap()
+                    .unwrap();
+            // each file has a row group per key group, in order
+            for file in &checkpoint.files {
+                let row_groups = &checkpoint.file_key_groups[file].row_group_key_groups;
+                assert!(!row_groups.is_empty());
+                assert!(row_groups.windows(2).all(|pair| pair[0] < pair[1]));
+            }
+
+            let mut restored = HashMap::new();
+            for task_index in 0..new_parallelism {
+                let groups = key_groups_for_server(task_index, new_parallelism, key_groups);
+                let table = table(
+                    &config,
+                    task_index,
+                    new_parallelism,
+                    Some(checkpoint.clone()),
+                );
+                // only the files of old subtasks that held some of the same groups
+                if old_parallelism % new_parallelism == 0 {
+                    assert_eq!(table.files.len(), old_parallelism / new_parallelism);
+                }
+
+                // exactly the keys the engine now routes to the subtask
+                for (key, value) in table.read_all::<String, u64>().await.unwrap() {
+                    let hash = routing_hashes[&key];
+                    assert!(groups.contains(&key_group_for_hash(hash, key_groups)));
+                    assert_eq!(server_for_hash(hash, new_parallelism), task_index);
+                    assert!(
+                        restored.insert(key, value).is_none(),
+                        "key restored by more than one subtask"
+                    );
+                }
+            }
+            assert_eq!(restored, values.iter().cloned().collect::<HashMap<_, _>>());
+
+            // tagged keys are still read by a table that's no longer partitioned
+            let unpartitioned = table(&broadcast_config(false), 0, 1, Some(checkpoint));
+            assert_eq!(
+                unpartitioned.read_all::<String, u64>().await.unwrap(),
+                values.iter().cloned().collect::<HashMap<_, _>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_subtasks_must_agree_on_codec() {
+        let mut metadata = subtask_metadata(2, 1);
+        metadata.get_mut(&0).unwrap().value_codec = Some("bincode".to_string());
+        metadata.get_mut(&1).unwrap().value_codec = Some("bincode-fixint".to_string());
+        assert!(
+            GlobalKeyedTable::merge_checkpoint_metadata(broadcast_config(false), metadata).is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_size_gauges_return_to_zero() {
+        let task_info = TaskInfo::for_test("job", "keyed-size");
+        let (tx, _rx) = channel(100);
+        let mut view: GlobalKeyedView<String, String> = GlobalKeyedView::new(
+            "s".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            StateSender::unbuffered(tx),
+        );
+        view.set_size(TableSize::new(&task_info, "s", None))
+            .unwrap();
+        let labels = [
+            task_info.operator_id.as_str(),
+            &task_info.task_index.to_string(),
+            "s",
+        ];
+        let entries = TABLE_SIZE_GAUGE.with_label_values(&labels);
+        let bytes = TABLE_BYTES_GAUGE.with_label_values(&labels);
+
+        for key in ["a", "b", "c"] {
+            view.insert(key.to_string(), "value".to_string())
+                .await
+                .unwrap();
+        }
+        // a replaced value stops counting once it's replaced
+        view.insert("a".to_string(), "a longer value".to_string())
+            .await
+            .unwrap();
+        let codec = StateCodec::default();
+        let expected: usize = [("a", "a longer value"), ("b", "value"), ("c", "value")]
+            .iter()
+            .map(|(key, value)| {
+                codec.encode(&key.to_string()).unwrap().len()
+                    + codec.encode(&value.to_string()).unwrap().len()
+            })
+            .sum();
+        assert_eq!(entries.get(), 3.0);
+        assert_eq!(bytes.get(), expected as f64);
+
+        for key in ["a", "b", "c"] {
+            assert!(view.delete(&key.to_string()).await.is_some());
+        }
+        assert!(view.delete(&"a".to_string()).await.is_none());
+        assert_eq!(entries.get(), 0.0);
+        assert_eq!(bytes.get(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_quota_rejects_inserts() {
+        let codec = StateCodec::default();
+        let entry_bytes = codec.encode(&"a".to_string()).unwrap().len()
+            + codec.encode(&"value".to_string()).unwrap().len();
+        for action in [QuotaAction::RejectInserts, QuotaAction::FailJob] {
+            let task_info = Arc::new(TaskInfo::for_test("job", "keyed-quota"));
+            let quota = StateQuota::new(
+                task_info.clone(),
+                StateQuotaConfig {
+                    soft_limit_bytes: None,
+                    hard_limit_bytes: Some(2 * entry_bytes),
+                    action,
+                },
+            );
+            let (tx, _rx) = channel(100);
+            let mut view: GlobalKeyedView<String, String> = GlobalKeyedView::new(
+                "s".to_string(),
+                HashMap::new(),
+                StateCodec::default(),
+                StateSender::unbuffered(tx),
+            );
+            view.set_size(TableSize::new(&task_info, "s", quota))
+                .unwrap();
+
+            for key in ["a", "b"] {
+                view.try_insert(key.to_string(), "value".to_string())
+                    .await
+                    .unwrap();
+            }
+            // replacing a value with one of the same size stays within the quota
+            view.try_insert("a".to_string(), "other".to_string())
+                .await
+                .unwrap();
+            let err = view
+                .try_insert("c".to_string(), "value".to_string())
+                .await
+                .unwrap_err();
+            let exceeded = err.downcast_ref::<StateQuotaExceeded>().unwrap();
+            assert_eq!(exceeded.task_index, task_info.task_index);
+            assert_eq!(exceeded.limit_bytes, 2 * entry_bytes);
+            assert_eq!(
+                exceeded.table_bytes,
+                vec![("s".to_string(), 3 * entry_bytes)]
+            );
+            assert_eq!(view.len(), 2);
+
+            // plain inserts are only refused if the quota is configured to fail the job
+            let insert = view.insert("c".to_string(), "value".to_string()).await;
+            match action {
+                QuotaAction::FailJob => {
+                    let err = insert.unwrap_err();
+                    assert!(
+                        err.downcast_ref::<StateQuotaExceeded>().is_some(),
+                        "{}",
+                        err
+                    );
+                    assert_eq!(view.len(), 2);
+                }
+                _ => {
+                    insert.unwrap();
+                    assert_eq!(view.len(), 3);
+                }
+            }
+        }
+    }
 }
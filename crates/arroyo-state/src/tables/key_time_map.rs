@@ -0,0 +1,807 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::RangeBounds;
+use std::time::{Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use arroyo_types::{Data, Key, TaskInfo};
+use prometheus::IntCounter;
+
+use crate::changelog::ChangeKind;
+use crate::metrics::{
+    KEYS_DELETED_COUNTER, TIME_RANGES_CLEARED_COUNTER, VALUES_DELETED_COUNTER,
+    VALUES_EXPIRED_COUNTER,
+};
+use crate::state_serde::StateCodec;
+use crate::tables::global_keyed_map::GlobalKeyedTable;
+use crate::tables::keyed_table_writer::{KeyedTableView, KeyedTableWriter};
+use crate::timestamps::time_range_bounds;
+use crate::write_buffer::StateSender;
+
+/// A map from each key to at most one value per timestamp, declared as a
+/// [`TableEnum::KeyTimeMap`](arroyo_rpc::grpc::TableEnum::KeyTimeMap) table, which is stored in
+/// the global keyed table format. Inserting a value for a key and timestamp that already has
+/// one replaces it.
+///
+/// Like [`super::processing_time_timers::ProcessingTimeTimerView`], the contents are
+/// rewritten in full on every call to [`KeyTimeMapView::flush`], which operators should
+/// call from their checkpoint handler.
+///
+/// Every read and write is answered from memory, after first reading the key's values from
+/// the table if they aren't resident. Views restored in full have every key resident.
+#[derive(Debug)]
+pub struct KeyTimeMapView<K: Key, V: Data> {
+    data: HashMap<K, BTreeMap<SystemTime, V>>,
+    residency: Residency<K>,
+    // the latest cutoff values were expired before, applied to values read later
+    expired_before: Option<SystemTime>,
+    churn: ChurnCounters,
+    writer: KeyedTableWriter,
+}
+
+/// Which keys have all of their values in memory.
+#[derive(Debug)]
+enum Residency<K> {
+    /// Every key, so a key that isn't in memory has no values.
+    All,
+    /// Only `resident`; the values of the others are read from `table`.
+    Keys {
+        resident: HashSet<K>,
+        table: GlobalKeyedTable,
+    },
+}
+
+/// Counts the values removed from a table, by how they were removed.
+#[derive(Debug, Clone)]
+struct ChurnCounters {
+    values_expired: IntCounter,
+    values_deleted: IntCounter,
+    keys_deleted: IntCounter,
+    time_ranges_cleared: IntCounter,
+}
+
+impl ChurnCounters {
+    fn new(task_info: &TaskInfo, table_name: &str) -> Self {
+        let labels = [
+            task_info.operator_id.as_str(),
+            &task_info.task_index.to_string(),
+            table_name,
+        ];
+        Self {
+            values_expired: VALUES_EXPIRED_COUNTER.with_label_values(&labels),
+            values_deleted: VALUES_DELETED_COUNTER.with_label_values(&labels),
+            keys_deleted: KEYS_DELETED_COUNTER.with_label_values(&labels),
+            time_ranges_cleared: TIME_RANGES_CLEARED_COUNTER.with_label_values(&labels),
+        }
+    }
+}
+
+impl<K: Key, V: Data> KeyTimeMapView<K, V> {
+    pub(crate) fn new(
+        table_name: String,
+        persisted: HashMap<K, Vec<(SystemTime, V)>>,
+        codec: StateCodec,
+        state_tx: StateSender,
+        task_info: &TaskInfo,
+    ) -> Self {
+        Self {
+            churn: ChurnCounters::new(task_info, &table_name),
+            data: persisted
+                .into_iter()
+                .map(|(key, values)| (key, values.into_iter().collect()))
+                .collect(),
+            residency: Residency::All,
+            expired_before: None,
+            writer: KeyedTableWriter::new(table_name, codec, state_tx),
+        }
+    }
+
+    /// Makes only the keys now in memory resident, reading the values of the others from
+    /// `table` when they're first accessed.
+    pub(crate) fn set_source(&mut self, table: GlobalKeyedTable) {
+        self.residency = Residency::Keys {
+            resident: self.data.keys().cloned().collect(),
+            table,
+        };
+    }
+
+    /// Reads the values of `key` from the table into memory, unless they're resident,
+    /// returning whether they were.
+    async fn make_resident(&mut self, key: &K) -> Result<bool> {
+        let Residency::Keys { resident, table } = &mut self.residency else {
+            return Ok(true);
+        };
+        if resident.contains(key) {
+            return Ok(true);
+        }
+        let start = Instant::now();
+        let values = table
+            .read_key::<K, Vec<(SystemTime, V)>>(key)
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to read a key of table {} from the state backend",
+                    self.writer.table_name()
+                )
+            })?;
+        resident.insert(key.clone());
+        self.writer.record_miss(start.elapsed());
+        let values: BTreeMap<_, _> = values
+            .into_iter()
+            .flatten()
+            .filter(|(timestamp, _)| {
+                self.expired_before
+                    .map_or(true, |cutoff| *timestamp >= cutoff)
+            })
+            .collect();
+        if !values.is_empty() {
+            self.data.insert(key.clone(), values);
+        }
+        Ok(false)
+    }
+
+    /// Makes `key` resident for a read, counting the read as a hit if it already was.
+    async fn prepare_read(&mut self, key: &K) -> Result<()> {
+        if self.make_resident(key).await? {
+            self.writer.record_hit();
+        }
+        Ok(())
+    }
+
+    /// Sets the value for `key` at `timestamp`, returning the value it replaced. Fails if
+    /// `timestamp` is outside the range state can store, unless the table clamps it; see
+    /// [`crate::timestamps::check_timestamp`]. Also fails, leaving the map as it was, if the
+    /// table's changelog is full or the insert would put the subtask over its state quota.
+    pub async fn insert(&mut self, key: K, timestamp: SystemTime, value: V) -> Result<Option<V>> {
+        let timestamp = self.writer.check_timestamp(timestamp)?;
+        self.make_resident(&key).await?;
+        let replaced = self
+            .data
+            .get(&key)
+            .and_then(|values| values.get(&timestamp));
+        self.writer
+            .insert(&(&key, timestamp), &value, replaced, Some(timestamp))?;
+        Ok(self.data.entry(key).or_default().insert(timestamp, value))
+    }
+
+    /// Waits until the changelog has delivered the events held for synchronous mutations.
+    pub async fn wait_for_changelog(&mut self) {
+        self.writer.wait_for_changelog().await;
+    }
+
+    pub async fn get(&mut self, key: &K, timestamp: SystemTime) -> Result<Option<&V>> {
+        self.prepare_read(key).await?;
+        Ok(self.data.get(key).and_then(|values| values.get(&timestamp)))
+    }
+
+    /// The value for `key` with the latest timestamp.
+    pub async fn get_latest(&mut self, key: &K) -> Result<Option<(SystemTime, &V)>> {
+        self.prepare_read(key).await?;
+        Ok(self
+            .data
+            .get(key)
+            .and_then(|values| values.last_key_value())
+            .map(|(timestamp, value)| (*timestamp, value)))
+    }
+
+    /// The value for `key` with the earliest timestamp.
+    pub async fn get_earliest(&mut self, key: &K) -> Result<Option<(SystemTime, &V)>> {
+        self.prepare_read(key).await?;
+        Ok(self
+            .data
+            .get(key)
+            .and_then(|values| values.first_key_value())
+            .map(|(timestamp, value)| (*timestamp, value)))
+    }
+
+    /// All of the values for `key`, in timestamp order.
+    pub async fn get_all(&mut self, key: &K) -> Result<Vec<(SystemTime, &V)>> {
+        self.get_time_range(key, ..).await
+    }
+
+    /// The values for `key` with timestamps in `range`, in timestamp order.
+    pub async fn get_time_range<R: RangeBounds<SystemTime>>(
+        &mut self,
+        key: &K,
+        range: R,
+    ) -> Result<Vec<(SystemTime, &V)>> {
+        self.prepare_read(key).await?;
+        Ok(match self.data.get(key) {
+            Some(values) => values
+                .range(time_range_bounds(&range))
+                .map(|(timestamp, value)| (*timestamp, value))
+                .collect(),
+            None => vec![],
+        })
+    }
+
+    /// Removes the value for `key` at `timestamp`, returning it if there was one.
+    pub async fn delete(&mut self, key: &K, timestamp: SystemTime) -> Result<Option<V>> {
+        self.make_resident(key).await?;
+        let Some(values) = self.data.get_mut(key) else {
+            return Ok(None);
+        };
+        let removed = values.remove(&timestamp);
+        if values.is_empty() {
+            self.data.remove(key);
+        }
+        if removed.is_some() {
+            self.churn.values_deleted.inc();
+            self.writer
+                .remove(ChangeKind::Delete, &(key, timestamp), Some(timestamp));
+        }
+        Ok(removed)
+    }
+
+    /// Removes `key` and all of its values, returning them by timestamp if it had any.
+    pub async fn delete_key(&mut self, key: &K) -> Result<Option<BTreeMap<SystemTime, V>>> {
+        self.make_resident(key).await?;
+        let Some(removed) = self.data.remove(key) else {
+            return Ok(None);
+        };
+        self.churn.keys_deleted.inc();
+        for timestamp in removed.keys() {
+            self.writer
+                .remove(ChangeKind::Delete, &(key, *timestamp), Some(*timestamp));
+        }
+        Ok(Some(removed))
+    }
+
+    /// Removes the values for `key` with timestamps in `range`, returning how many were
+    /// removed.
+    pub async fn clear_time_range<R: RangeBounds<SystemTime>>(
+        &mut self,
+        key: &K,
+        range: R,
+    ) -> Result<usize> {
+        self.make_resident(key).await?;
+        self.churn.time_ranges_cleared.inc();
+        let Some(values) = self.data.get_mut(key) else {
+            return Ok(0);
+        };
+        let timestamps: Vec<_> = values
+            .range(time_range_bounds(&range))
+            .map(|(timestamp, _)| *timestamp)
+            .collect();
+        for timestamp in &timestamps {
+            values.remove(timestamp);
+            self.writer
+                .remove(ChangeKind::Delete, &(key, *timestamp), Some(*timestamp));
+        }
+        if values.is_empty() {
+            self.data.remove(key);
+        }
+        Ok(timestamps.len())
+    }
+
+    /// Removes every value with a timestamp strictly before `cutoff`, returning how many were
+    /// removed. Values exactly at `cutoff` are kept, as they are by time-keyed tables. Values
+    /// of keys that aren't resident are dropped as they're read, and aren't counted.
+    pub fn expire_before(&mut self, cutoff: SystemTime) -> usize {
+        let mut expired = 0;
+        self.data.retain(|key, values| {
+            let retained = values.split_off(&cutoff);
+            expired += values.len();
+            for timestamp in values.keys() {
+                self.writer
+                    .remove(ChangeKind::Expire, &(key, *timestamp), Some(*timestamp));
+            }
+            *values = retained;
+            !values.is_empty()
+        });
+        self.expired_before = self.expired_before.max(Some(cutoff));
+        self.churn.values_expired.inc_by(expired as u64);
+        expired
+    }
+
+    /// The number of resident keys with values.
+    pub fn key_count(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Every key with values, in no particular order, first reading the keys that aren't
+    /// resident. Keys whose values have all been deleted or expired aren't included.
+    pub async fn keys(&mut self) -> Result<impl Iterator<Item = &K>> {
+        self.make_all_resident().await?;
+        Ok(self.data.keys())
+    }
+
+    /// Every key with values and the earliest of their timestamps, in no particular order,
+    /// first reading the keys that aren't resident.
+    pub async fn keys_with_earliest_timestamp(
+        &mut self,
+    ) -> Result<impl Iterator<Item = (&K, SystemTime)>> {
+        self.make_all_resident().await?;
+        Ok(self.data.iter().filter_map(|(key, values)| {
+            values
+                .first_key_value()
+                .map(|(timestamp, _)| (key, *timestamp))
+        }))
+    }
+
+    /// Writes every value to the table, first reading the keys that aren't resident, as the
+    /// table is rewritten in full.
+    pub async fn flush(&mut self) -> Result<()> {
+        self.make_all_resident().await?;
+        for (key, values) in &self.data {
+            let values: Vec<_> = values.iter().collect();
+            self.writer.write(key, key, &values).await?;
+        }
+        self.writer.finish_flush().await;
+        Ok(())
+    }
+
+    /// Reads the values of every key that isn't resident into memory.
+    async fn make_all_resident(&mut self) -> Result<()> {
+        if let Residency::Keys { resident, table } = &self.residency {
+            let start = Instant::now();
+            let stored = table
+                .read_all::<K, Vec<(SystemTime, V)>>()
+                .await
+                .with_context(|| {
+                    format!(
+                        "failed to read table {} from the state backend",
+                        self.writer.table_name()
+                    )
+                })?;
+            self.writer.record_miss(start.elapsed());
+            for (key, values) in stored {
+                if resident.contains(&key) {
+                    continue;
+                }
+                let values: BTreeMap<_, _> = values
+                    .into_iter()
+                    .filter(|(timestamp, _)| {
+                        self.expired_before
+                            .map_or(true, |cutoff| *timestamp >= cutoff)
+                    })
+                    .collect();
+                if !values.is_empty() {
+                    self.data.insert(key, values);
+                }
+            }
+            self.residency = Residency::All;
+        }
+        Ok(())
+    }
+}
+
+impl<K: Key, V: Data> KeyedTableView for KeyTimeMapView<K, V> {
+    fn writer(&mut self) -> &mut KeyedTableWriter {
+        &mut self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use arroyo_rpc::grpc::{GlobalKeyedTableConfig, TableTimestampPolicy};
+    use tokio::sync::mpsc::{channel, Receiver};
+
+    use super::*;
+    use crate::state_serde::StateSerde;
+    use crate::tables::{StateFileLayout, Table, TableEpochCheckpointer, TableReads};
+    use crate::test_storage::TempStorage;
+    use crate::timestamps::{max_state_timestamp, InvalidStateTimestamp};
+    use crate::{CheckpointMessage, StateMessage, TableData};
+
+    /// The value of the counter `name` for the table, as scraped from the registry.
+    fn scrape(name: &str, task_info: &TaskInfo, table_name: &str) -> u64 {
+        let labels = [
+            ("operator_id", task_info.operator_id.clone()),
+            ("task_id", task_info.task_index.to_string()),
+            ("table_char", table_name.to_string()),
+        ];
+        prometheus::gather()
+            .iter()
+            .filter(|family| family.get_name() == name)
+            .flat_map(|family| family.get_metric())
+            .filter(|metric| {
+                labels.iter().all(|(label, value)| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .any(|pair| pair.get_name() == *label && pair.get_value() == value)
+                })
+            })
+            .map(|metric| metric.get_counter().get_value() as u64)
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn test_churn_counters() {
+        let task_info = TaskInfo::for_test("job", "key-time-churn");
+        let (tx, _rx) = channel(100);
+        let mut view: KeyTimeMapView<String, u64> = KeyTimeMapView::new(
+            "m".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            StateSender::unbuffered(tx),
+            &task_info,
+        );
+        let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        for key in ["a", "b", "c"] {
+            for seconds in 0..10 {
+                view.insert(key.to_string(), at(seconds), seconds)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let (a, b, c) = ("a".to_string(), "b".to_string(), "c".to_string());
+        assert_eq!(view.delete(&a, at(9)).await.unwrap(), Some(9));
+        // deleting what isn't there isn't counted
+        assert_eq!(view.delete(&a, at(9)).await.unwrap(), None);
+        assert_eq!(view.delete_key(&b).await.unwrap().unwrap().len(), 10);
+        assert!(view.delete_key(&b).await.unwrap().is_none());
+        assert_eq!(view.clear_time_range(&c, at(5)..at(8)).await.unwrap(), 3);
+        assert_eq!(view.expire_before(at(2)), 4);
+
+        let scrape = |name| scrape(name, &task_info, "m");
+        assert_eq!(scrape("arroyo_worker_values_deleted_total"), 1);
+        assert_eq!(scrape("arroyo_worker_keys_deleted_total"), 1);
+        assert_eq!(scrape("arroyo_worker_time_ranges_cleared_total"), 1);
+        assert_eq!(scrape("arroyo_worker_values_expired_total"), 4);
+        assert_eq!(view.get_all(&c).await.unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_clear_time_range_then_expire() {
+        let task_info = TaskInfo::for_test("job", "key-time-clear");
+        let (tx, _rx) = channel(100);
+        let mut view: KeyTimeMapView<String, u64> = KeyTimeMapView::new(
+            "m".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            StateSender::unbuffered(tx),
+            &task_info,
+        );
+        let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        let (a, b) = ("a".to_string(), "b".to_string());
+        for seconds in 1..=5 {
+            view.insert(a.clone(), at(seconds), seconds).await.unwrap();
+            view.insert(b.clone(), at(seconds), seconds).await.unwrap();
+        }
+
+        // clearing a key's earliest values, or all of them, leaves nothing for expiration to
+        // trip over
+        assert_eq!(view.clear_time_range(&a, at(1)..at(3)).await.unwrap(), 2);
+        assert_eq!(view.clear_time_range(&b, ..).await.unwrap(), 5);
+        assert_eq!(view.key_count(), 1);
+        assert_eq!(view.get_latest(&b).await.unwrap(), None);
+
+        assert_eq!(view.expire_before(at(4)), 1);
+        assert_eq!(
+            view.get_all(&a).await.unwrap(),
+            vec![(at(4), &4), (at(5), &5)]
+        );
+        assert_eq!(view.expire_before(at(10)), 2);
+        assert_eq!(view.key_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_latest_and_earliest() {
+        let task_info = TaskInfo::for_test("job", "key-time-latest");
+        let (tx, _rx) = channel(100);
+        let mut view: KeyTimeMapView<String, u64> = KeyTimeMapView::new(
+            "m".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            StateSender::unbuffered(tx),
+            &task_info,
+        );
+        let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        let key = "a".to_string();
+        for seconds in [3, 1, 5, 2, 4] {
+            view.insert(key.clone(), at(seconds), seconds)
+                .await
+                .unwrap();
+        }
+        // a replaced value is the one returned
+        view.insert(key.clone(), at(5), 50).await.unwrap();
+        assert_eq!(view.get_earliest(&key).await.unwrap(), Some((at(1), &1)));
+        assert_eq!(view.get_latest(&key).await.unwrap(), Some((at(5), &50)));
+
+        // once the earliest values are cleared or expired, the next ones take their place
+        assert_eq!(view.clear_time_range(&key, ..at(2)).await.unwrap(), 1);
+        assert_eq!(view.get_earliest(&key).await.unwrap(), Some((at(2), &2)));
+        assert_eq!(view.expire_before(at(4)), 2);
+        assert_eq!(view.get_earliest(&key).await.unwrap(), Some((at(4), &4)));
+        assert_eq!(view.clear_time_range(&key, at(5)..).await.unwrap(), 1);
+        assert_eq!(view.get_latest(&key).await.unwrap(), Some((at(4), &4)));
+        assert_eq!(view.expire_before(at(10)), 1);
+        assert_eq!(view.get_earliest(&key).await.unwrap(), None);
+        assert_eq!(view.get_latest(&key).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_keys() {
+        let task_info = TaskInfo::for_test("job", "key-time-keys");
+        let (tx, _rx) = channel(100);
+        let mut view: KeyTimeMapView<String, u64> = KeyTimeMapView::new(
+            "m".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            StateSender::unbuffered(tx),
+            &task_info,
+        );
+        let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        let (a, b, c) = ("a".to_string(), "b".to_string(), "c".to_string());
+        view.insert(a.clone(), at(1), 1).await.unwrap();
+        view.insert(b.clone(), at(2), 2).await.unwrap();
+        view.insert(b.clone(), at(6), 6).await.unwrap();
+        view.insert(c.clone(), at(5), 5).await.unwrap();
+
+        // a has only expired values, and b loses its earliest
+        assert_eq!(view.expire_before(at(3)), 2);
+        let mut keys: Vec<_> = view.keys().await.unwrap().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, vec![b.clone(), c.clone()]);
+        let mut earliest: Vec<_> = view
+            .keys_with_earliest_timestamp()
+            .await
+            .unwrap()
+            .map(|(key, timestamp)| (key.clone(), timestamp))
+            .collect();
+        earliest.sort();
+        assert_eq!(earliest, vec![(b.clone(), at(6)), (c.clone(), at(5))]);
+
+        // and deleted keys are gone too
+        view.delete_key(&c).await.unwrap();
+        assert_eq!(view.keys().await.unwrap().collect::<Vec<_>>(), vec![&b]);
+    }
+
+    #[tokio::test]
+    async fn test_expire_boundary() {
+        let task_info = TaskInfo::for_test("job", "key-time-boundary");
+        let (tx, mut rx) = channel(100);
+        let mut view: KeyTimeMapView<String, u64> = KeyTimeMapView::new(
+            "m".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            StateSender::unbuffered(tx.clone()),
+            &task_info,
+        );
+        let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        let key = "a".to_string();
+        for seconds in 1..=3 {
+            view.insert(key.clone(), at(seconds), seconds)
+                .await
+                .unwrap();
+        }
+
+        // values are expired strictly before the cutoff
+        assert_eq!(view.expire_before(at(2)), 1);
+        assert_eq!(view.get(&key, at(2)).await.unwrap(), Some(&2));
+
+        // and what's written to the table, and so restored, matches what's in memory
+        view.flush().await.unwrap();
+        drop(view);
+        drop(tx);
+        let mut persisted = HashMap::new();
+        while let Some(message) = rx.recv().await {
+            let StateMessage::TableData {
+                data: TableData::KeyedData { key, value },
+                ..
+            } = message
+            else {
+                panic!("unexpected message {:?}", message);
+            };
+            let codec = StateCodec::default();
+            persisted.insert(
+                codec.decode::<String>(&key).unwrap(),
+                codec.decode::<Vec<(SystemTime, u64)>>(&value).unwrap(),
+            );
+        }
+        let (tx, _rx) = channel(100);
+        let mut restored: KeyTimeMapView<String, u64> = KeyTimeMapView::new(
+            "m".to_string(),
+            persisted,
+            StateCodec::default(),
+            StateSender::unbuffered(tx),
+            &task_info,
+        );
+        assert_eq!(
+            restored.get_all(&key).await.unwrap(),
+            vec![(at(2), &2), (at(3), &3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timestamps_out_of_range() {
+        let task_info = TaskInfo::for_test("job", "key-time-timestamps");
+        let (tx, _rx) = channel(100);
+        let mut view: KeyTimeMapView<String, u64> = KeyTimeMapView::new(
+            "m".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            StateSender::unbuffered(tx),
+            &task_info,
+        );
+        let key = "a".to_string();
+        let before_epoch = SystemTime::UNIX_EPOCH - Duration::from_secs(1);
+        let far_future = SystemTime::now() + Duration::from_secs(10_000 * 365 * 24 * 60 * 60);
+
+        for timestamp in [before_epoch, far_future] {
+            let err = view.insert(key.clone(), timestamp, 1).await.unwrap_err();
+            assert!(
+                err.downcast_ref::<InvalidStateTimestamp>().is_some(),
+                "{}",
+                err
+            );
+        }
+        assert_eq!(view.key_count(), 0);
+
+        view.writer()
+            .set_timestamp_policy(TableTimestampPolicy::Clamp);
+        view.insert(key.clone(), before_epoch, 1).await.unwrap();
+        view.insert(key.clone(), far_future, 2).await.unwrap();
+        assert_eq!(
+            view.get_all(&key).await.unwrap(),
+            vec![(SystemTime::UNIX_EPOCH, &1), (max_state_timestamp(), &2)]
+        );
+
+        // inverted ranges have no values, rather than panicking
+        let later = SystemTime::UNIX_EPOCH + Duration::from_secs(10);
+        assert!(view
+            .get_time_range(&key, later..SystemTime::UNIX_EPOCH)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            view.clear_time_range(&key, later..SystemTime::UNIX_EPOCH)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_metrics() {
+        let task_info = TaskInfo::for_test("job", "key-time-reads");
+        let (tx, _rx) = channel(100);
+        let mut view: KeyTimeMapView<String, u64> = KeyTimeMapView::new(
+            "m".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            StateSender::unbuffered(tx),
+            &task_info,
+        );
+        let reads = TableReads::new(&task_info, "m");
+        reads.miss(Duration::from_millis(5));
+        view.writer().set_reads(reads);
+
+        let key = "a".to_string();
+        view.insert(key.clone(), SystemTime::UNIX_EPOCH, 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            view.get(&key, SystemTime::UNIX_EPOCH).await.unwrap(),
+            Some(&1)
+        );
+        // reads of keys without values are still served from memory
+        assert!(view.get_latest(&"b".to_string()).await.unwrap().is_none());
+        assert_eq!(view.get_all(&key).await.unwrap().len(), 1);
+
+        let scrape = |name| scrape(name, &task_info, "m");
+        assert_eq!(scrape("arroyo_worker_state_read_hits_total"), 3);
+        assert_eq!(scrape("arroyo_worker_state_read_misses_total"), 1);
+    }
+
+    /// Decodes the values a view flushed, by key.
+    fn flushed(rx: &mut Receiver<StateMessage>) -> HashMap<String, Vec<(SystemTime, u64)>> {
+        let codec = StateCodec::default();
+        let mut flushed = HashMap::new();
+        while let Ok(message) = rx.try_recv() {
+            let StateMessage::TableData {
+                data: TableData::KeyedData { key, value },
+                ..
+            } = message
+            else {
+                panic!("unexpected message {:?}", message);
+            };
+            flushed.insert(codec.decode(&key).unwrap(), codec.decode(&value).unwrap());
+        }
+        flushed
+    }
+
+    #[tokio::test]
+    async fn test_reads_keys_that_are_not_resident() {
+        let temp_storage = TempStorage::new("key-time-map-tests").await;
+        let storage_provider = temp_storage.provider();
+        let task_info = Arc::new(TaskInfo::for_test("job", "key-time-residency"));
+        let config = GlobalKeyedTableConfig {
+            table_name: "m".to_string(),
+            description: "m".to_string(),
+            ..Default::default()
+        };
+        let table = |checkpoint| {
+            GlobalKeyedTable::from_config(
+                config.clone(),
+                StateFileLayout::default(),
+                StateCodec::default(),
+                0,
+                task_info.clone(),
+                storage_provider.clone(),
+                checkpoint,
+            )
+            .unwrap()
+        };
+        let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        let (a, b, c) = ("a".to_string(), "b".to_string(), "c".to_string());
+
+        // checkpoint values for three keys
+        let (tx, mut rx) = channel(100);
+        let mut view: KeyTimeMapView<String, u64> = KeyTimeMapView::new(
+            "m".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            StateSender::unbuffered(tx),
+            &task_info,
+        );
+        for seconds in 1..=3 {
+            view.insert(a.clone(), at(seconds), seconds).await.unwrap();
+        }
+        view.insert(b.clone(), at(1), 10).await.unwrap();
+        view.insert(c.clone(), at(5), 50).await.unwrap();
+        view.flush().await.unwrap();
+        let mut checkpointer = table(None).epoch_checkpointer(1, None).unwrap();
+        while let Ok(StateMessage::TableData { data, .. }) = rx.try_recv() {
+            checkpointer.insert_data(data).await.unwrap();
+        }
+        let checkpoint = CheckpointMessage {
+            epoch: 1,
+            time: SystemTime::now(),
+            watermark: None,
+            then_stop: false,
+            in_flight: false,
+        };
+        let (subtask_metadata, _) = checkpointer.finish(&checkpoint).await.unwrap().unwrap();
+        let checkpoint = GlobalKeyedTable::merge_checkpoint_metadata(
+            config.clone(),
+            [(0, subtask_metadata)].into(),
+        )
+        .unwrap();
+
+        // a view with none of them in memory still answers in full
+        let (tx, mut rx) = channel(100);
+        let mut view: KeyTimeMapView<String, u64> = KeyTimeMapView::new(
+            "m".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            StateSender::unbuffered(tx),
+            &task_info,
+        );
+        view.writer().set_reads(TableReads::new(&task_info, "m"));
+        view.set_source(table(checkpoint));
+        assert_eq!(
+            view.get_time_range(&a, at(2)..).await.unwrap(),
+            vec![(at(2), &2), (at(3), &3)]
+        );
+        assert_eq!(view.get_all(&a).await.unwrap().len(), 3);
+        assert!(view.get_all(&"d".to_string()).await.unwrap().is_empty());
+        let scrape = |name| scrape(name, &task_info, "m");
+        assert_eq!(scrape("arroyo_worker_state_read_misses_total"), 2);
+        assert_eq!(scrape("arroyo_worker_state_read_hits_total"), 1);
+
+        // writes apply on top of the stored values
+        view.insert(b.clone(), at(2), 20).await.unwrap();
+        assert_eq!(
+            view.get_all(&b).await.unwrap(),
+            vec![(at(1), &10), (at(2), &20)]
+        );
+
+        // and a flush writes the keys never read, without what's since expired
+        assert_eq!(view.expire_before(at(2)), 2);
+        view.flush().await.unwrap();
+        assert_eq!(
+            flushed(&mut rx),
+            HashMap::from([
+                (a, vec![(at(2), 2), (at(3), 3)]),
+                (b, vec![(at(2), 20)]),
+                (c, vec![(at(5), 50)]),
+            ])
+        );
+    }
+}
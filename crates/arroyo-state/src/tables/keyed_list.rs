@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use arroyo_types::{Data, Key};
+
+use crate::changelog::ChangeKind;
+use crate::state_serde::StateCodec;
+use crate::tables::keyed_table_writer::{KeyedTableView, KeyedTableWriter};
+use crate::write_buffer::StateSender;
+
+/// A per-key list that grows by appends and is read in insertion order, stored in a global
+/// keyed table.
+///
+/// Each key's list is written as a single value, so append order survives restore. Lists
+/// are rewritten in full on every call to [`KeyedListView::flush`], which operators should
+/// call from their checkpoint handler.
+#[derive(Debug)]
+pub struct KeyedListView<K: Key, V: Data> {
+    data: HashMap<K, Vec<V>>,
+    writer: KeyedTableWriter,
+}
+
+impl<K: Key, V: Data> KeyedListView<K, V> {
+    pub(crate) fn new(
+        table_name: String,
+        persisted: HashMap<K, Vec<V>>,
+        codec: StateCodec,
+        state_tx: StateSender,
+    ) -> Self {
+        Self {
+            data: persisted,
+            writer: KeyedTableWriter::new(table_name, codec, state_tx),
+        }
+    }
+
+    /// Appends `value` to the list for `key`. Fails, leaving the list as it was, if the
+    /// table's changelog is full or the append would put the subtask over its state quota.
+    pub fn append(&mut self, key: K, value: V) -> Result<()> {
+        self.writer.insert(&key, &value, None, None)?;
+        self.data.entry(key).or_default().push(value);
+        Ok(())
+    }
+
+    /// Appends each of `values` to the list for `key`, in order. Fails like
+    /// [`KeyedListView::append`], leaving the list with the values before the one refused.
+    pub fn append_all(&mut self, key: K, values: Vec<V>) -> Result<()> {
+        for value in values {
+            self.writer.insert(&key, &value, None, None)?;
+            self.data.entry(key.clone()).or_default().push(value);
+        }
+        Ok(())
+    }
+
+    /// The values appended for `key`, oldest first. Empty if there are none.
+    pub fn get(&self, key: &K) -> &[V] {
+        self.data
+            .get(key)
+            .map(|values| values.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Removes the list for `key`, returning its values.
+    pub fn clear(&mut self, key: &K) -> Vec<V> {
+        let Some(values) = self.data.remove(key) else {
+            return vec![];
+        };
+        self.writer.remove(ChangeKind::Delete, key, None);
+        values
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.data.keys()
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Waits until the changelog has delivered the events held for synchronous mutations.
+    pub async fn wait_for_changelog(&mut self) {
+        self.writer.wait_for_changelog().await;
+    }
+
+    /// Writes every list to the table.
+    pub async fn flush(&mut self) -> Result<()> {
+        for (key, values) in &self.data {
+            self.writer.write(key, key, values).await?;
+        }
+        self.writer.finish_flush().await;
+        Ok(())
+    }
+}
+
+impl<K: Key, V: Data> KeyedTableView for KeyedListView<K, V> {
+    fn writer(&mut self) -> &mut KeyedTableWriter {
+        &mut self.writer
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use arroyo_types::{Data, Key};
+
+use crate::changelog::ChangeKind;
+use crate::state_serde::StateCodec;
+use crate::tables::keyed_table_writer::{KeyedTableView, KeyedTableWriter};
+use crate::write_buffer::StateSender;
+
+/// A map of inner keys to values for each key, stored in a global keyed table.
+///
+/// Each `(key, inner key)` pair is written as its own entry, so large inner maps aren't
+/// encoded as a single value. Entries are rewritten in full on every call to
+/// [`KeyedMapView::flush`], which operators should call from their checkpoint handler.
+#[derive(Debug)]
+pub struct KeyedMapView<K: Key, IK: Key, V: Data> {
+    data: HashMap<K, HashMap<IK, V>>,
+    writer: KeyedTableWriter,
+}
+
+impl<K: Key, IK: Key, V: Data> KeyedMapView<K, IK, V> {
+    pub(crate) fn new(
+        table_name: String,
+        persisted: HashMap<(K, IK), V>,
+        codec: StateCodec,
+        state_tx: StateSender,
+    ) -> Self {
+        let mut data: HashMap<K, HashMap<IK, V>> = HashMap::new();
+        for ((key, inner_key), value) in persisted {
+            data.entry(key).or_default().insert(inner_key, value);
+        }
+        Self {
+            data,
+            writer: KeyedTableWriter::new(table_name, codec, state_tx),
+        }
+    }
+
+    /// Sets the value for `inner_key` under `key`, returning the value it replaced. Fails,
+    /// leaving the map as it was, if the table's changelog is full or the insert would put
+    /// the subtask over its state quota.
+    pub fn insert(&mut self, key: K, inner_key: IK, value: V) -> Result<Option<V>> {
+        let replaced = self.data.get(&key).and_then(|inner| inner.get(&inner_key));
+        self.writer
+            .insert(&(&key, &inner_key), &value, replaced, None)?;
+        Ok(self.data.entry(key).or_default().insert(inner_key, value))
+    }
+
+    pub fn get(&self, key: &K, inner_key: &IK) -> Option<&V> {
+        self.data.get(key)?.get(inner_key)
+    }
+
+    pub fn contains(&self, key: &K, inner_key: &IK) -> bool {
+        self.get(key, inner_key).is_some()
+    }
+
+    /// Removes the value for `inner_key` under `key`, returning it if there was one.
+    pub fn remove(&mut self, key: &K, inner_key: &IK) -> Option<V> {
+        let inner = self.data.get_mut(key)?;
+        let removed = inner.remove(inner_key)?;
+        if inner.is_empty() {
+            self.data.remove(key);
+        }
+        self.writer
+            .remove(ChangeKind::Delete, &(key, inner_key), None);
+        Some(removed)
+    }
+
+    /// Removes the whole inner map for `key`.
+    pub fn clear(&mut self, key: &K) -> Option<HashMap<IK, V>> {
+        let inner = self.data.remove(key)?;
+        for inner_key in inner.keys() {
+            self.writer
+                .remove(ChangeKind::Delete, &(key, inner_key), None);
+        }
+        Some(inner)
+    }
+
+    /// The inner keys and values for `key`, in no particular order.
+    pub fn iter_inner(&self, key: &K) -> impl Iterator<Item = (&IK, &V)> {
+        self.data.get(key).into_iter().flatten()
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Waits until the changelog has delivered the events held for synchronous mutations.
+    pub async fn wait_for_changelog(&mut self) {
+        self.writer.wait_for_changelog().await;
+    }
+
+    /// Writes every entry to the table.
+    pub async fn flush(&mut self) -> Result<()> {
+        for (key, inner) in &self.data {
+            for (inner_key, value) in inner {
+                self.writer.write(key, &(key, inner_key), value).await?;
+            }
+        }
+        self.writer.finish_flush().await;
+        Ok(())
+    }
+}
+
+impl<K: Key, IK: Key, V: Data> KeyedTableView for KeyedMapView<K, IK, V> {
+    fn writer(&mut self) -> &mut KeyedTableWriter {
+        &mut self.writer
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use arroyo_rpc::grpc::TableTimestampPolicy;
+use bincode::Encode;
+use tracing::warn;
+
+use crate::changelog::{ChangeData, ChangeKind, Changelog};
+use crate::quota::{QuotaCheck, TableSize};
+use crate::state_serde::{StateCodec, StateSerde};
+use crate::tables::{tag_key_group, TableReads};
+use crate::timestamps::check_timestamp;
+use crate::write_buffer::StateSender;
+use crate::{StateMessage, TableData};
+
+/// A view that holds a global keyed table's contents in memory and rewrites them on every
+/// flush through its [`KeyedTableWriter`], so that the table manager can attach the table's
+/// settings to it however it arranges its data.
+pub(crate) trait KeyedTableView: Send + 'static {
+    fn writer(&mut self) -> &mut KeyedTableWriter;
+}
+
+/// The number of entries in restored data and their encoded size in bytes, as a view
+/// restored from it would write them.
+pub(crate) fn encoded_size<K: Encode, V: Encode>(
+    codec: &StateCodec,
+    data: &HashMap<K, V>,
+) -> (usize, usize) {
+    let bytes = data
+        .iter()
+        .flat_map(|(key, value)| [codec.encode(key), codec.encode(value)])
+        .map(|encoded| encoded.map(|bytes| bytes.len()).unwrap_or_default())
+        .sum();
+    (data.len(), bytes)
+}
+
+/// Writes a view's entries to its global keyed table on flush, and applies the table's
+/// changelog, state quota and timestamp policy to the view's mutations as they're made.
+#[derive(Debug)]
+pub(crate) struct KeyedTableWriter {
+    table_name: String,
+    codec: StateCodec,
+    state_tx: StateSender,
+    // set for tables partitioned by key group, whose keys are tagged with their group
+    key_groups: Option<u32>,
+    timestamp_policy: TableTimestampPolicy,
+    changelog: Option<Changelog>,
+    reads: Option<TableReads>,
+    size: Option<TableSize>,
+    // the encoded size of what the last flush wrote, or of the restored data, adjusted by
+    // the inserts since; inserts are checked against the quota with it
+    size_bytes: usize,
+    // what the flush in progress has written
+    flushed_entries: usize,
+    flushed_bytes: usize,
+}
+
+impl KeyedTableWriter {
+    pub(crate) fn new(table_name: String, codec: StateCodec, state_tx: StateSender) -> Self {
+        Self {
+            table_name,
+            codec,
+            state_tx,
+            key_groups: None,
+            timestamp_policy: TableTimestampPolicy::Reject,
+            changelog: None,
+            reads: None,
+            size: None,
+            size_bytes: 0,
+            flushed_entries: 0,
+            flushed_bytes: 0,
+        }
+    }
+
+    pub(crate) fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Tags the keys written to the table with their key group, out of `key_groups`.
+    pub(crate) fn set_key_groups(&mut self, key_groups: u32) {
+        self.key_groups = Some(key_groups);
+    }
+
+    pub(crate) fn set_timestamp_policy(&mut self, policy: TableTimestampPolicy) {
+        self.timestamp_policy = policy;
+    }
+
+    pub(crate) fn set_changelog(&mut self, changelog: Changelog) {
+        self.changelog = Some(changelog);
+    }
+
+    pub(crate) fn set_reads(&mut self, reads: TableReads) {
+        self.reads = Some(reads);
+    }
+
+    /// Reports the size of the restored data, `entries` entries of `bytes` bytes, to the
+    /// table's metrics and quota, failing if it's over the quota and the quota's action is
+    /// to fail the job.
+    pub(crate) fn set_size(
+        &mut self,
+        size: TableSize,
+        (entries, bytes): (usize, usize),
+    ) -> Result<()> {
+        if let QuotaCheck::Exceeded(exceeded) = size.check(bytes, false) {
+            size.reject_restored(exceeded)?;
+        }
+        size.record(entries, bytes);
+        self.size_bytes = bytes;
+        self.size = Some(size);
+        Ok(())
+    }
+
+    pub(crate) fn record_hit(&self) {
+        if let Some(reads) = &self.reads {
+            reads.hit();
+        }
+    }
+
+    /// Records a read from the state backend that took `latency`.
+    pub(crate) fn record_miss(&self, latency: Duration) {
+        if let Some(reads) = &self.reads {
+            reads.miss(latency);
+        }
+    }
+
+    /// Checks a timestamp inserted into the table against its timestamp policy; see
+    /// [`crate::timestamps::check_timestamp`].
+    pub(crate) fn check_timestamp(&self, timestamp: SystemTime) -> Result<SystemTime> {
+        Ok(check_timestamp(
+            &self.table_name,
+            self.timestamp_policy,
+            timestamp,
+        )?)
+    }
+
+    /// Admits an insert of `value` for `key`, replacing `replaced` if it's set, which the
+    /// view applies once this returns. It fails with [`crate::quota::StateQuotaExceeded`] if the insert would put the operator
+    /// over its state quota, or with [`crate::changelog::ChangelogFull`] if the table's
+    /// changelog can't hold it, and is otherwise recorded in the changelog. The entry is
+    /// only encoded if the table has a changelog or quota.
+    pub(crate) fn insert<EK: Encode, V: Encode>(
+        &mut self,
+        key: &EK,
+        value: &V,
+        replaced: Option<&V>,
+        timestamp: Option<SystemTime>,
+    ) -> Result<()> {
+        if self.changelog.is_none() && self.size.is_none() {
+            return Ok(());
+        }
+        if let Some(changelog) = &self.changelog {
+            changelog.check_capacity()?;
+        }
+        let key = self.codec.encode(key)?;
+        let value = self.codec.encode(value)?;
+        if let Some(size) = &self.size {
+            let replaced_bytes = match replaced {
+                Some(replaced) => key.len() + self.codec.encode(replaced)?.len(),
+                None => 0,
+            };
+            let size_bytes =
+                (self.size_bytes + key.len() + value.len()).saturating_sub(replaced_bytes);
+            if let QuotaCheck::Exceeded(exceeded) = size.check(size_bytes, false) {
+                return Err(exceeded.into());
+            }
+            self.size_bytes = size_bytes;
+        }
+        if let Some(changelog) = &mut self.changelog {
+            changelog.record(
+                ChangeKind::Insert,
+                timestamp,
+                ChangeData::Keyed {
+                    key,
+                    value: Some(value),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Records the removal of `key`'s value in the table's changelog, as a `kind` change.
+    /// Removals are never refused; the space they free is counted at the next flush.
+    pub(crate) fn remove<EK: Encode>(
+        &mut self,
+        kind: ChangeKind,
+        key: &EK,
+        timestamp: Option<SystemTime>,
+    ) {
+        let Some(changelog) = &mut self.changelog else {
+            return;
+        };
+        match self.codec.encode(key) {
+            Ok(key) => changelog.record(kind, timestamp, ChangeData::Keyed { key, value: None }),
+            Err(e) => warn!(
+                "failed to encode a key removed from table {} for its changelog: {}",
+                self.table_name, e
+            ),
+        }
+    }
+
+    /// Writes `value` for `key` to the table as part of a flush. `partition_key` is the part
+    /// of the key that decides its key group.
+    pub(crate) async fn write<P: Hash + ?Sized, EK: Encode, V: Encode>(
+        &mut self,
+        partition_key: &P,
+        key: &EK,
+        value: &V,
+    ) -> Result<()> {
+        let key = self.codec.encode(key)?;
+        let value = self.codec.encode(value)?;
+        self.flushed_entries += 1;
+        self.flushed_bytes += key.len() + value.len();
+        self.state_tx
+            .send(StateMessage::TableData {
+                table: self.table_name.clone(),
+                data: TableData::KeyedData {
+                    key: tag_key_group(self.key_groups, partition_key, key),
+                    value,
+                },
+            })
+            .await
+    }
+
+    /// Deletes `key` from the table as part of a flush, so that it isn't restored from the
+    /// earlier epochs of incremental tables.
+    pub(crate) async fn write_delete<P: Hash + ?Sized, EK: Encode>(
+        &mut self,
+        partition_key: &P,
+        key: &EK,
+    ) -> Result<()> {
+        let key = self.codec.encode(key)?;
+        self.state_tx
+            .send(StateMessage::TableData {
+                table: self.table_name.clone(),
+                data: TableData::KeyedDelete {
+                    key: tag_key_group(self.key_groups, partition_key, key),
+                },
+            })
+            .await
+    }
+
+    /// Waits until the changelog has delivered the events held for synchronous mutations.
+    pub(crate) async fn wait_for_changelog(&mut self) {
+        if let Some(changelog) = &mut self.changelog {
+            changelog.drain().await;
+        }
+    }
+
+    /// Ends a flush: sends the changes the changelog is holding, and reports what the flush
+    /// wrote to the table's metrics and quota.
+    pub(crate) async fn finish_flush(&mut self) {
+        self.wait_for_changelog().await;
+        let entries = std::mem::take(&mut self.flushed_entries);
+        let bytes = std::mem::take(&mut self.flushed_bytes);
+        self.size_bytes = bytes;
+        if let Some(size) = &self.size {
+            size.record(entries, bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arroyo_types::TaskInfo;
+    use tokio::sync::mpsc::channel;
+
+    use super::*;
+    use crate::changelog::{ChangelogConfig, ChangelogOverflow};
+    use crate::quota::{QuotaAction, StateQuota, StateQuotaConfig, StateQuotaExceeded};
+    use crate::tables::sorted_keyed::SortedKeyedView;
+
+    #[tokio::test]
+    async fn test_views_apply_changelog_and_quota() {
+        let codec = StateCodec::default();
+        let entry_bytes = codec.encode(&"a".to_string()).unwrap().len()
+            + codec.encode(&"value".to_string()).unwrap().len();
+        let task_info = Arc::new(TaskInfo::for_test("job", "keyed-writer"));
+        let quota = StateQuota::new(
+            task_info.clone(),
+            StateQuotaConfig {
+                soft_limit_bytes: None,
+                hard_limit_bytes: Some(2 * entry_bytes),
+                action: QuotaAction::RejectInserts,
+            },
+        );
+        let (changelog, mut changes) = ChangelogConfig {
+            capacity: 10,
+            overflow: ChangelogOverflow::Block,
+        }
+        .channel(&task_info, "s");
+        let (tx, _rx) = channel(100);
+        let mut view: SortedKeyedView<String, String> = SortedKeyedView::new(
+            "s".to_string(),
+            HashMap::new(),
+            codec,
+            StateSender::unbuffered(tx),
+        );
+        view.writer().set_changelog(changelog);
+        view.writer()
+            .set_size(TableSize::new(&task_info, "s", quota), (0, 0))
+            .unwrap();
+
+        for key in ["a", "b"] {
+            view.insert(key.to_string(), "value".to_string()).unwrap();
+        }
+        // replacing a value with one of the same size stays within the quota
+        view.insert("a".to_string(), "other".to_string()).unwrap();
+        let err = view
+            .insert("c".to_string(), "value".to_string())
+            .unwrap_err();
+        let exceeded = err.downcast_ref::<StateQuotaExceeded>().unwrap();
+        assert_eq!(exceeded.limit_bytes, 2 * entry_bytes);
+        assert_eq!(view.len(), 2);
+
+        // removals make room once they're flushed
+        assert!(view.delete(&"b".to_string()).is_some());
+        view.flush().await.unwrap();
+        view.insert("c".to_string(), "value".to_string()).unwrap();
+
+        let mut recorded = vec![];
+        while let Ok(event) = changes.try_recv() {
+            let ChangeData::Keyed { key, value } = event.data else {
+                panic!("unexpected change {:?}", event.data);
+            };
+            let key: String = codec.decode(&key).unwrap();
+            let value: Option<String> = value.map(|value| codec.decode(&value).unwrap());
+            recorded.push((event.kind, key, value));
+        }
+        let value = |value: &str| Some(value.to_string());
+        assert_eq!(
+            recorded,
+            vec![
+                (ChangeKind::Insert, "a".to_string(), value("value")),
+                (ChangeKind::Insert, "b".to_string(), value("value")),
+                (ChangeKind::Insert, "a".to_string(), value("other")),
+                (ChangeKind::Delete, "b".to_string(), None),
+                (ChangeKind::Insert, "c".to_string(), value("value")),
+            ]
+        );
+    }
+}
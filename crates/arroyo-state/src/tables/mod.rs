@@ -1,49 +1,204 @@
-use crate::{CheckpointMessage, DataOperation, TableData};
+use crate::identifiers::encode_path_component;
+use crate::metrics::{
+    STATE_READ_HITS_COUNTER, STATE_READ_MISSES_COUNTER, STATE_READ_MISS_LATENCY_HISTOGRAM,
+};
+use crate::restore_progress::TableRestoreProgress;
+use crate::state_serde::StateCodec;
+use crate::{hash_key, CheckpointMessage, DataOperation, TableData};
 use anyhow::{bail, Result};
 use arroyo_rpc::grpc::{
-    OperatorMetadata, TableCheckpointMetadata, TableConfig, TableEnum,
-    TableSubtaskCheckpointMetadata,
+    OperatorMetadata, TableCheckpointMetadata, TableConfig, TableEnum, TableRestorePolicy,
+    TableSubtaskCheckpointMetadata, TableTimestampPolicy,
 };
 use arroyo_storage::StorageProviderRef;
-use arroyo_types::TaskInfoRef;
+use arroyo_types::{key_group_for_hash, TaskInfo, TaskInfoRef};
+use prometheus::{Histogram, IntCounter};
 use prost::Message;
 use std::any::Any;
 use std::collections::{HashMap, HashSet};
-use std::time::SystemTime;
+use std::hash::Hash;
+use std::time::{Duration, SystemTime};
 use tracing::debug;
 
 pub mod expiring_time_key_map;
 pub mod global_keyed_map;
+pub mod key_time_map;
+pub mod keyed_list;
+pub mod keyed_map;
+pub(crate) mod keyed_table_writer;
+pub mod processing_time_timers;
+pub mod reducing;
+pub mod replica;
+pub mod sorted_keyed;
 pub mod table_manager;
+pub mod timers;
+
+/// A state file that a table's checkpoint refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetainedFile {
+    pub path: String,
+    /// The epoch that wrote the file, which is earlier than the checkpoint's for files
+    /// carried over from previous epochs.
+    pub epoch: u32,
+    /// Unset if the checkpoint metadata doesn't record the file's size.
+    pub bytes: Option<u64>,
+}
 
 pub enum Compactor {
     TimeKeyMap,
     KeyTimeMultiMap,
 }
 
-pub(crate) fn table_checkpoint_path(
-    job_id: &str,
-    operator_id: &str,
-    table: &str,
-    subtask_index: usize,
-    epoch: u32,
-    compacted: bool,
-) -> String {
-    format!(
-        "{}/table-{}-{:0>3}{}",
-        operator_path(job_id, epoch, operator_id),
-        table,
-        subtask_index,
-        if compacted { "-compacted" } else { "" }
-    )
+pub const STATE_FILE_PATH_TEMPLATE_ENV: &str = "STATE_FILE_PATH_TEMPLATE";
+
+/// Puts each table's files next to the metadata for the epoch that wrote them.
+pub const DEFAULT_STATE_FILE_PATH_TEMPLATE: &str =
+    "{job_id}/checkpoints/checkpoint-{epoch}/operator-{operator_id}/table-{table}-{subtask}";
+
+const STATE_FILE_PATH_VARIABLES: [&str; 5] = ["job_id", "operator_id", "epoch", "table", "subtask"];
+
+/// Where a table's data files are written, relative to the checkpoint storage root. Paths
+/// come from a template, set for the job with `STATE_FILE_PATH_TEMPLATE`, under an optional
+/// per-table prefix from [`TableConfig::path_prefix`]. The template must use every one of
+/// `{job_id}`, `{operator_id}`, `{epoch}`, `{table}` and `{subtask}` so that files can't
+/// collide.
+///
+/// The layout only decides where new files go. Restore, compaction inputs and cleanup use
+/// the paths recorded in checkpoint metadata, so files written under an earlier template
+/// are still found and deleted once they fall below the min epoch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateFileLayout {
+    template: String,
+    prefix: Option<String>,
+}
+
+impl Default for StateFileLayout {
+    fn default() -> Self {
+        Self {
+            template: DEFAULT_STATE_FILE_PATH_TEMPLATE.to_string(),
+            prefix: None,
+        }
+    }
+}
+
+impl StateFileLayout {
+    pub fn new(template: impl Into<String>, prefix: Option<String>) -> Result<Self> {
+        let template: String = template.into();
+        let template = template.trim_matches('/').to_string();
+        let mut used = HashSet::new();
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                bail!("unclosed '{{' in state file path template {}", template);
+            };
+            let variable = &rest[start + 1..start + len];
+            if !STATE_FILE_PATH_VARIABLES.contains(&variable) {
+                bail!(
+                    "unknown variable {{{}}} in state file path template {}; expected one of {:?}",
+                    variable,
+                    template,
+                    STATE_FILE_PATH_VARIABLES
+                );
+            }
+            used.insert(variable);
+            rest = &rest[start + len + 1..];
+        }
+        if let Some(missing) = STATE_FILE_PATH_VARIABLES
+            .iter()
+            .find(|variable| !used.contains(*variable))
+        {
+            bail!(
+                "state file path template {} must use {{{}}}",
+                template,
+                missing
+            );
+        }
+
+        Ok(Self {
+            template,
+            prefix: prefix
+                .map(|prefix| prefix.trim_matches('/').to_string())
+                .filter(|prefix| !prefix.is_empty()),
+        })
+    }
+
+    /// The layout for a table, using the template from the environment and the table's
+    /// prefix.
+    pub fn for_table(config: &TableConfig) -> Result<Self> {
+        let template = std::env::var(STATE_FILE_PATH_TEMPLATE_ENV)
+            .unwrap_or_else(|_| DEFAULT_STATE_FILE_PATH_TEMPLATE.to_string());
+        Self::new(template, config.path_prefix.clone())
+    }
+
+    pub(crate) fn path(
+        &self,
+        job_id: &str,
+        operator_id: &str,
+        table: &str,
+        subtask_index: usize,
+        epoch: u32,
+        compacted: bool,
+    ) -> String {
+        // identifiers are escaped, so substituted values can't contain another variable
+        let path = self
+            .template
+            .replace("{job_id}", &encode_path_component(job_id))
+            .replace("{operator_id}", &encode_path_component(operator_id))
+            .replace("{epoch}", &format!("{:0>7}", epoch))
+            .replace("{table}", &encode_path_component(table))
+            .replace("{subtask}", &format!("{:0>3}", subtask_index));
+        let path = match &self.prefix {
+            Some(prefix) => format!("{}/{}", prefix, path),
+            None => path,
+        };
+        if compacted {
+            format!("{}-compacted", path)
+        } else {
+            path
+        }
+    }
+}
+
+/// The path of part `part` of a subtask's state file that was split because of its size.
+/// The first part keeps the unsplit path.
+pub(crate) fn state_file_part_path(path: &str, part: usize) -> String {
+    if part == 0 {
+        path.to_string()
+    } else {
+        format!("{}-part-{:0>3}", path, part)
+    }
 }
 
-fn operator_path(job_id: &str, epoch: u32, operator: &str) -> String {
-    format!("{}/operator-{}", base_path(job_id, epoch), operator)
+pub(crate) const KEY_GROUP_TAG_LEN: usize = 4;
+
+/// Prefixes the encoded `key` of an entry in a table partitioned into `key_groups` with the
+/// big-endian key group of its `partition_key`, so that entries sort by key group and can
+/// be restored a group at a time. Keys of other tables are left as they are.
+pub(crate) fn tag_key_group<P: Hash + ?Sized>(
+    key_groups: Option<u32>,
+    partition_key: &P,
+    key: Vec<u8>,
+) -> Vec<u8> {
+    let Some(key_groups) = key_groups else {
+        return key;
+    };
+    let key_group = key_group_for_hash(hash_key(&partition_key), key_groups);
+    let mut tagged = Vec::with_capacity(KEY_GROUP_TAG_LEN + key.len());
+    tagged.extend_from_slice(&key_group.to_be_bytes());
+    tagged.extend(key);
+    tagged
 }
 
-fn base_path(job_id: &str, epoch: u32) -> String {
-    format!("{}/checkpoints/checkpoint-{:0>7}", job_id, epoch)
+/// Splits a key tagged by [`tag_key_group`] into its key group and encoded key.
+pub(crate) fn split_key_group(key: &[u8]) -> Result<(u32, &[u8])> {
+    if key.len() < KEY_GROUP_TAG_LEN {
+        bail!(
+            "key of {} bytes is too short to be tagged with a key group",
+            key.len()
+        );
+    }
+    let (tag, key) = key.split_at(KEY_GROUP_TAG_LEN);
+    Ok((u32::from_be_bytes(tag.try_into().unwrap()), key))
 }
 
 pub struct DataTuple<K, V> {
@@ -80,10 +235,15 @@ pub(crate) trait Table: Send + Sync + 'static + Clone {
 
     // produce the Table based on the
     // * config: (table specific configuration, such as retention duration),
+    // * layout, codec and value version: where data files are written, how keyed values are
+    //   encoded and the version of their type, from the table's TableConfig
     // * task_info: subtask specific info, including job_id, operator_id, and subtask_index
     // * checkpoint_message: If restoring from a checkpoint, the checkpoint data for that checkpoint's epoch.
     fn from_config(
         config: Self::ConfigMessage,
+        layout: StateFileLayout,
+        codec: StateCodec,
+        value_version: u32,
         task_info: TaskInfoRef,
         storage_provider: StorageProviderRef,
         checkpoint_message: Option<Self::TableCheckpointMessage>,
@@ -122,15 +282,52 @@ pub(crate) trait Table: Send + Sync + 'static + Clone {
 
     fn table_type() -> TableEnum;
 
+    /// Whether the table can load configs and checkpoints of `table_type`, which tables that
+    /// store several table types in the same format extend.
+    fn stores_table_type(table_type: TableEnum) -> bool
+    where
+        Self: Sized,
+    {
+        table_type == Self::table_type()
+    }
+
     fn task_info(&self) -> TaskInfoRef;
 
+    /// Sets where the table reports its progress as it reads its checkpoint.
+    fn set_restore_progress(&mut self, progress: TableRestoreProgress);
+
+    /// Sets what the table does with checkpointed data that fails to decode. Tables that are
+    /// reset aren't given their checkpoint, so only need to distinguish the other policies.
+    fn set_restore_policy(&mut self, policy: TableRestorePolicy);
+
+    /// Sets what the table's views do with inserted timestamps outside the range the state
+    /// layer stores.
+    fn set_timestamp_policy(&mut self, policy: TableTimestampPolicy);
+
     fn files_to_keep(
         config: Self::ConfigMessage,
         checkpoint: Self::TableCheckpointMessage,
     ) -> Result<HashSet<String>>;
 
+    /// The files of the table's checkpoint for `epoch`, including those retained from
+    /// earlier epochs.
+    fn retained_files(
+        config: Self::ConfigMessage,
+        checkpoint: Self::TableCheckpointMessage,
+        epoch: u32,
+    ) -> Result<Vec<RetainedFile>>;
+
+    /// The table's checkpoint with every file it references renamed by `rename`, for when
+    /// the files are copied elsewhere.
+    fn rename_files(
+        config: Self::ConfigMessage,
+        checkpoint: Self::TableCheckpointMessage,
+        rename: &dyn Fn(&str) -> String,
+    ) -> Result<Self::TableCheckpointMessage>;
+
     async fn compact_data(
         config: Self::ConfigMessage,
+        layout: &StateFileLayout,
         compaction_config: &CompactionConfig,
         operator_metadata: &OperatorMetadata,
         current_metadata: Self::TableCheckpointMessage,
@@ -195,11 +392,21 @@ pub trait ErasedTable: Send + Sync + 'static {
     where
         Self: Sized;
 
+    fn stores_table_type(table_type: TableEnum) -> bool
+    where
+        Self: Sized;
+
+    fn set_restore_progress(&mut self, progress: TableRestoreProgress);
+
+    fn set_restore_policy(&mut self, policy: TableRestorePolicy);
+
+    fn set_timestamp_policy(&mut self, policy: TableTimestampPolicy);
+
     fn checked_proto_decode<M: Message + Default>(table_type: TableEnum, data: Vec<u8>) -> Result<M>
     where
         Self: Sized,
     {
-        if Self::table_type() != table_type {
+        if !Self::stores_table_type(table_type) {
             bail!(
                 "mismatched table type, expected type {:?}, got {:?}",
                 Self::table_type(),
@@ -216,6 +423,22 @@ pub trait ErasedTable: Send + Sync + 'static {
     where
         Self: Sized;
 
+    fn retained_files(
+        config: TableConfig,
+        checkpoint: TableCheckpointMetadata,
+        epoch: u32,
+    ) -> Result<Vec<RetainedFile>>
+    where
+        Self: Sized;
+
+    fn rename_files(
+        config: TableConfig,
+        checkpoint: TableCheckpointMetadata,
+        rename: &dyn Fn(&str) -> String,
+    ) -> Result<TableCheckpointMetadata>
+    where
+        Self: Sized;
+
     fn as_any(&self) -> &dyn Any;
 
     #[allow(async_fn_in_trait)]
@@ -253,6 +476,9 @@ impl<T: Table + Sized + 'static> ErasedTable for T {
     where
         Self: Sized,
     {
+        let layout = StateFileLayout::for_table(&config)?;
+        let codec = StateCodec::for_table(&config)?;
+        let value_version = config.value_version.unwrap_or_default();
         let config = Self::checked_proto_decode(config.table_type(), config.config)?;
         let checkpoint_message = checkpoint_message
             .map(|metadata| Self::checked_proto_decode(metadata.table_type(), metadata.data))
@@ -261,7 +487,15 @@ impl<T: Table + Sized + 'static> ErasedTable for T {
             "restoring from checkpoint message:\n{:#?}",
             checkpoint_message
         );
-        T::from_config(config, task_info, storage_provider, checkpoint_message)
+        T::from_config(
+            config,
+            layout,
+            codec,
+            value_version,
+            task_info,
+            storage_provider,
+            checkpoint_message,
+        )
     }
 
     fn epoch_checkpointer(
@@ -342,6 +576,25 @@ impl<T: Table + Sized + 'static> ErasedTable for T {
         T::table_type()
     }
 
+    fn stores_table_type(table_type: TableEnum) -> bool
+    where
+        Self: Sized,
+    {
+        T::stores_table_type(table_type)
+    }
+
+    fn set_restore_progress(&mut self, progress: TableRestoreProgress) {
+        Table::set_restore_progress(self, progress)
+    }
+
+    fn set_restore_policy(&mut self, policy: TableRestorePolicy) {
+        Table::set_restore_policy(self, policy)
+    }
+
+    fn set_timestamp_policy(&mut self, policy: TableTimestampPolicy) {
+        Table::set_timestamp_policy(self, policy)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -358,6 +611,41 @@ impl<T: Table + Sized + 'static> ErasedTable for T {
             Self::checked_proto_decode(T::table_type(), checkpoint.data)?,
         )
     }
+
+    fn retained_files(
+        config: TableConfig,
+        checkpoint: TableCheckpointMetadata,
+        epoch: u32,
+    ) -> Result<Vec<RetainedFile>>
+    where
+        Self: Sized,
+    {
+        T::retained_files(
+            Self::checked_proto_decode(T::table_type(), config.config)?,
+            Self::checked_proto_decode(T::table_type(), checkpoint.data)?,
+            epoch,
+        )
+    }
+
+    fn rename_files(
+        config: TableConfig,
+        checkpoint: TableCheckpointMetadata,
+        rename: &dyn Fn(&str) -> String,
+    ) -> Result<TableCheckpointMetadata>
+    where
+        Self: Sized,
+    {
+        let renamed = T::rename_files(
+            Self::checked_proto_decode(T::table_type(), config.config)?,
+            Self::checked_proto_decode(T::table_type(), checkpoint.data)?,
+            rename,
+        )?;
+        Ok(TableCheckpointMetadata {
+            table_type: T::table_type().into(),
+            data: renamed.encode_to_vec(),
+        })
+    }
+
     fn committing_data(
         config: TableConfig,
         table_metadata: &TableCheckpointMetadata,
@@ -378,9 +666,11 @@ impl<T: Table + Sized + 'static> ErasedTable for T {
         operator_metadata: &OperatorMetadata,
         current_metadata: TableCheckpointMetadata,
     ) -> Result<Option<TableCheckpointMetadata>> {
+        let layout = StateFileLayout::for_table(&config)?;
         let config = Self::checked_proto_decode(config.table_type(), config.config)?;
         let result = T::compact_data(
             config,
+            &layout,
             compaction_config,
             operator_metadata,
             Self::checked_proto_decode(current_metadata.table_type(), current_metadata.data)?,
@@ -441,3 +731,85 @@ impl<T: TableEpochCheckpointer + Sized> ErasedCheckpointer for T {
         }))
     }
 }
+
+/// Counts the reads of a table's view: hits, served from memory, and misses, which go to
+/// the state backend. The metric handles are resolved once, so counting a read is a single
+/// atomic increment.
+///
+/// Views hold every restored value in memory, so the only misses are the reads that first
+/// load them, and those of key-time maps with keys that aren't resident.
+#[derive(Debug, Clone)]
+pub(crate) struct TableReads {
+    hits: IntCounter,
+    misses: IntCounter,
+    miss_latency: Histogram,
+}
+
+impl TableReads {
+    pub(crate) fn new(task_info: &TaskInfo, table_name: &str) -> Self {
+        let labels = [
+            task_info.operator_id.as_str(),
+            &task_info.task_index.to_string(),
+            table_name,
+        ];
+        Self {
+            hits: STATE_READ_HITS_COUNTER.with_label_values(&labels),
+            misses: STATE_READ_MISSES_COUNTER.with_label_values(&labels),
+            miss_latency: STATE_READ_MISS_LATENCY_HISTOGRAM.with_label_values(&labels),
+        }
+    }
+
+    pub(crate) fn hit(&self) {
+        self.hits.inc();
+    }
+
+    /// Records a read from the state backend that took `latency`.
+    pub(crate) fn miss(&self, latency: Duration) {
+        self.misses.inc();
+        self.miss_latency.observe(latency.as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{global_table_config, with_path_prefix};
+
+    #[test]
+    fn test_template_must_use_every_variable() {
+        assert!(StateFileLayout::new(DEFAULT_STATE_FILE_PATH_TEMPLATE, None).is_ok());
+        assert!(StateFileLayout::new("{job_id}/{operator_id}/{table}-{subtask}", None).is_err());
+        assert!(StateFileLayout::new(
+            "{job_id}/{operator_id}/{epoch}/{table}-{subtask}/{other}",
+            None
+        )
+        .is_err());
+        assert!(
+            StateFileLayout::new("{job_id}/{operator_id}/{epoch}/{table}-{subtask", None).is_err()
+        );
+    }
+
+    #[test]
+    fn test_template_and_prefix() {
+        let layout = StateFileLayout::new(
+            "/{table}/{job_id}/{operator_id}/{epoch}-{subtask}/",
+            Some("/windows/".to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            layout.path("job", "op", "t/1", 2, 7, false),
+            "windows/t%2F1/job/op/0000007-002"
+        );
+        assert_eq!(
+            layout.path("job", "op", "t", 2, 7, true),
+            "windows/t/job/op/0000007-002-compacted"
+        );
+
+        let table = with_path_prefix(global_table_config("t", "")["t"].clone(), "cold");
+        assert_eq!(
+            StateFileLayout::for_table(&table).unwrap(),
+            StateFileLayout::new(DEFAULT_STATE_FILE_PATH_TEMPLATE, Some("cold".to_string()))
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use arroyo_types::Key;
+use bincode::{Decode, Encode};
+
+use crate::changelog::ChangeKind;
+use crate::state_serde::StateCodec;
+use crate::tables::keyed_table_writer::{KeyedTableView, KeyedTableWriter};
+use crate::write_buffer::StateSender;
+
+/// Determines how a processing-time timer that was captured in a checkpoint is scheduled
+/// when the operator is restored.
+#[derive(Debug, Clone, Copy, Encode, Decode, PartialEq, Eq, Hash)]
+pub enum ProcessingTimeRestoreMode {
+    /// Keep the original wall-clock deadline. Timers whose deadline passed while the job
+    /// was down fire as soon as the operator polls for expired timers.
+    FireIfOverdue,
+    /// Keep the delay that was remaining when the checkpoint was taken, measured from the
+    /// time the operator was restored.
+    RescheduleFromRestart,
+}
+
+/// The persisted form of a single timer. `remaining` is captured at checkpoint time so that
+/// `RescheduleFromRestart` timers can be rebuilt relative to the restore.
+#[derive(Debug, Clone, Encode, Decode, PartialEq)]
+pub struct PersistedProcessingTimeTimer {
+    pub fire_at: SystemTime,
+    pub remaining: Duration,
+    pub mode: ProcessingTimeRestoreMode,
+}
+
+/// Processing-time timers for an operator, stored in a global keyed table.
+///
+/// Timers are rewritten in full on every call to [`ProcessingTimeTimerView::flush`], which
+/// operators should call from their checkpoint handler, and keys left without timers are
+/// deleted, so cancelled and fired timers are not restored. The view should be loaded before
+/// the first record is processed (e.g., in `on_start`) so restored timers are rebuilt
+/// before any new ones are registered.
+#[derive(Debug)]
+pub struct ProcessingTimeTimerView<K: Key> {
+    timers: BTreeMap<SystemTime, HashMap<K, ProcessingTimeRestoreMode>>,
+    timers_by_key: HashMap<K, HashSet<SystemTime>>,
+    // keys whose last timer was cancelled or fired since the last flush
+    emptied: HashSet<K>,
+    writer: KeyedTableWriter,
+}
+
+impl<K: Key> ProcessingTimeTimerView<K> {
+    pub(crate) fn new(
+        table_name: String,
+        persisted: HashMap<K, Vec<PersistedProcessingTimeTimer>>,
+        restored_at: SystemTime,
+        codec: StateCodec,
+        state_tx: StateSender,
+    ) -> Self {
+        let mut view = Self {
+            timers: BTreeMap::new(),
+            timers_by_key: HashMap::new(),
+            emptied: HashSet::new(),
+            writer: KeyedTableWriter::new(table_name, codec, state_tx),
+        };
+        for (key, timers) in persisted {
+            for timer in timers {
+                let fire_at = match timer.mode {
+                    ProcessingTimeRestoreMode::FireIfOverdue => timer.fire_at,
+                    ProcessingTimeRestoreMode::RescheduleFromRestart => {
+                        restored_at + timer.remaining
+                    }
+                };
+                view.add(key.clone(), fire_at, timer.mode);
+            }
+        }
+        view
+    }
+
+    /// Registers a timer for `key` at `fire_at`. Registering the same key and time twice
+    /// is a no-op, except that the restore mode of the latest registration wins. Fails if
+    /// `fire_at` is outside the range state can store, unless the table clamps it; see
+    /// [`crate::timestamps::check_timestamp`]. Also fails, without registering the timer,
+    /// if the table's changelog is full or the timer would put the subtask over its state
+    /// quota.
+    pub fn register(
+        &mut self,
+        key: K,
+        fire_at: SystemTime,
+        mode: ProcessingTimeRestoreMode,
+    ) -> Result<()> {
+        let fire_at = self.writer.check_timestamp(fire_at)?;
+        let registered = self
+            .timers_by_key
+            .get(&key)
+            .is_some_and(|times| times.contains(&fire_at));
+        if !registered {
+            self.writer.insert(&key, &fire_at, None, Some(fire_at))?;
+        }
+        self.add(key, fire_at, mode);
+        Ok(())
+    }
+
+    fn add(&mut self, key: K, fire_at: SystemTime, mode: ProcessingTimeRestoreMode) {
+        self.emptied.remove(&key);
+        self.timers_by_key
+            .entry(key.clone())
+            .or_default()
+            .insert(fire_at);
+        self.timers.entry(fire_at).or_default().insert(key, mode);
+    }
+
+    /// Cancels the timer for `key` at `fire_at`, returning whether it was registered.
+    pub fn cancel(&mut self, key: &K, fire_at: SystemTime) -> bool {
+        let Some(times) = self.timers_by_key.get_mut(key) else {
+            return false;
+        };
+        if !times.remove(&fire_at) {
+            return false;
+        }
+        self.writer.remove(ChangeKind::Delete, key, Some(fire_at));
+        if times.is_empty() {
+            self.timers_by_key.remove(key);
+            self.emptied.insert(key.clone());
+        }
+        if let Some(keys) = self.timers.get_mut(&fire_at) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.timers.remove(&fire_at);
+            }
+        }
+        true
+    }
+
+    /// Removes and returns all timers with a deadline at or before `now`, in deadline order.
+    pub fn poll_expired(&mut self, now: SystemTime) -> Vec<(SystemTime, K)> {
+        let mut expired = vec![];
+        while let Some(entry) = self.timers.first_entry() {
+            if *entry.key() > now {
+                break;
+            }
+            let (fire_at, keys) = entry.remove_entry();
+            for key in keys.into_keys() {
+                self.writer.remove(ChangeKind::Expire, &key, Some(fire_at));
+                if let Some(times) = self.timers_by_key.get_mut(&key) {
+                    times.remove(&fire_at);
+                    if times.is_empty() {
+                        self.timers_by_key.remove(&key);
+                        self.emptied.insert(key.clone());
+                    }
+                }
+                expired.push((fire_at, key));
+            }
+        }
+        expired
+    }
+
+    /// The earliest registered deadline, useful for scheduling the next wakeup.
+    pub fn next_fire_time(&self) -> Option<SystemTime> {
+        self.timers.keys().next().copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Waits until the changelog has delivered the events held for synchronous mutations.
+    pub async fn wait_for_changelog(&mut self) {
+        self.writer.wait_for_changelog().await;
+    }
+
+    /// Writes every registered timer to the table, and deletes the keys left without any.
+    /// `checkpoint_time` is used to compute the remaining delay for timers restored with
+    /// `RescheduleFromRestart`.
+    pub async fn flush(&mut self, checkpoint_time: SystemTime) -> Result<()> {
+        for key in std::mem::take(&mut self.emptied) {
+            self.writer.write_delete(&key, &key).await?;
+        }
+        let mut by_key: HashMap<&K, Vec<PersistedProcessingTimeTimer>> = HashMap::new();
+        for (fire_at, keys) in &self.timers {
+            for (key, mode) in keys {
+                by_key
+                    .entry(key)
+                    .or_default()
+                    .push(PersistedProcessingTimeTimer {
+                        fire_at: *fire_at,
+                        remaining: fire_at
+                            .duration_since(checkpoint_time)
+                            .unwrap_or(Duration::ZERO),
+                        mode: *mode,
+                    });
+            }
+        }
+        for (key, timers) in by_key {
+            self.writer.write(key, key, &timers).await?;
+        }
+        self.writer.finish_flush().await;
+        Ok(())
+    }
+}
+
+impl<K: Key> KeyedTableView for ProcessingTimeTimerView<K> {
+    fn writer(&mut self) -> &mut KeyedTableWriter {
+        &mut self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arroyo_rpc::grpc::GlobalKeyedTableConfig;
+    use arroyo_types::TaskInfo;
+    use tokio::sync::mpsc::channel;
+
+    use super::*;
+    use crate::tables::global_keyed_map::GlobalKeyedTable;
+    use crate::tables::StateFileLayout;
+    use crate::test_storage::{checkpoint_keyed_table, TempStorage};
+
+    #[tokio::test]
+    async fn test_restore_round_trip() {
+        for incremental in [false, true] {
+            let temp_storage = TempStorage::new("processing-time-timer-tests").await;
+            let task_info = Arc::new(TaskInfo::for_test("job", "processing-time-timers"));
+            let config = GlobalKeyedTableConfig {
+                table_name: "p".to_string(),
+                description: "p".to_string(),
+                incremental,
+                ..Default::default()
+            };
+            let table = |checkpoint| {
+                GlobalKeyedTable::from_config(
+                    config.clone(),
+                    StateFileLayout::default(),
+                    StateCodec::default(),
+                    0,
+                    task_info.clone(),
+                    temp_storage.provider(),
+                    checkpoint,
+                )
+                .unwrap()
+            };
+            let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+            let (a, b, c) = ("a".to_string(), "b".to_string(), "c".to_string());
+
+            let (tx, mut rx) = channel(100);
+            let mut view: ProcessingTimeTimerView<String> = ProcessingTimeTimerView::new(
+                "p".to_string(),
+                HashMap::new(),
+                at(0),
+                StateCodec::default(),
+                StateSender::unbuffered(tx),
+            );
+            view.register(a.clone(), at(30), ProcessingTimeRestoreMode::FireIfOverdue)
+                .unwrap();
+            view.register(
+                b.clone(),
+                at(40),
+                ProcessingTimeRestoreMode::RescheduleFromRestart,
+            )
+            .unwrap();
+            view.register(c.clone(), at(50), ProcessingTimeRestoreMode::FireIfOverdue)
+                .unwrap();
+            view.flush(at(10)).await.unwrap();
+            let (subtask_metadata, _) =
+                checkpoint_keyed_table(&table(None), &config, 1, None, &mut rx).await;
+
+            // c's timer is cancelled before the next checkpoint, taken at 20
+            assert!(view.cancel(&c, at(50)));
+            view.flush(at(20)).await.unwrap();
+            let (_, checkpoint) =
+                checkpoint_keyed_table(&table(None), &config, 2, Some(subtask_metadata), &mut rx)
+                    .await;
+
+            // restored well after every deadline: a is overdue and fires straight away,
+            // while b keeps the 20 seconds it had left when the checkpoint was taken
+            let (tx, _rx) = channel(100);
+            let mut restored: ProcessingTimeTimerView<String> = ProcessingTimeTimerView::new(
+                "p".to_string(),
+                table(checkpoint).read_all().await.unwrap(),
+                at(100),
+                StateCodec::default(),
+                StateSender::unbuffered(tx),
+            );
+            assert_eq!(
+                restored.poll_expired(at(100)),
+                vec![(at(30), a)],
+                "incremental: {}",
+                incremental
+            );
+            assert_eq!(restored.next_fire_time(), Some(at(120)));
+            assert_eq!(restored.poll_expired(at(200)), vec![(at(120), b)]);
+            assert!(restored.is_empty());
+        }
+    }
+}
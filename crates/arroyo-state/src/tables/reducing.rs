@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use arroyo_types::{Data, Key};
+
+use crate::changelog::ChangeKind;
+use crate::state_serde::StateCodec;
+use crate::tables::keyed_table_writer::{KeyedTableView, KeyedTableWriter};
+use crate::write_buffer::StateSender;
+
+/// Combines a newly inserted value into the value accumulated so far for its key.
+pub type ReduceFn<V> = fn(&mut V, V);
+
+/// A single accumulated value per key, stored in a global keyed table. Inserts are merged
+/// into the existing value with the view's [`ReduceFn`], so only the running result is held
+/// in memory and written to the table.
+///
+/// Values are rewritten in full on every call to [`ReducingView::flush`], which operators
+/// should call from their checkpoint handler. On restore, values for the same key found in
+/// more than one file are merged with the same function.
+#[derive(Debug)]
+pub struct ReducingView<K: Key, V: Data> {
+    data: HashMap<K, V>,
+    reduce: ReduceFn<V>,
+    writer: KeyedTableWriter,
+}
+
+impl<K: Key, V: Data> ReducingView<K, V> {
+    pub(crate) fn new(
+        table_name: String,
+        persisted: HashMap<K, V>,
+        reduce: ReduceFn<V>,
+        codec: StateCodec,
+        state_tx: StateSender,
+    ) -> Self {
+        Self {
+            data: persisted,
+            reduce,
+            writer: KeyedTableWriter::new(table_name, codec, state_tx),
+        }
+    }
+
+    /// Merges `value` into the accumulated value for `key`, returning the result. Fails,
+    /// leaving the accumulated value as it was, if the table's changelog is full or the
+    /// result would put the subtask over its state quota.
+    pub fn insert(&mut self, key: K, value: V) -> Result<&V> {
+        match self.data.entry(key) {
+            std::collections::hash_map::Entry::Occupied(mut e) => {
+                let mut reduced = e.get().clone();
+                (self.reduce)(&mut reduced, value);
+                self.writer.insert(e.key(), &reduced, Some(e.get()), None)?;
+                *e.get_mut() = reduced;
+                Ok(e.into_mut())
+            }
+            std::collections::hash_map::Entry::Vacant(e) => {
+                self.writer.insert(e.key(), &value, None, None)?;
+                Ok(e.insert(value))
+            }
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.data.get(key)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let removed = self.data.remove(key)?;
+        self.writer.remove(ChangeKind::Delete, key, None);
+        Some(removed)
+    }
+
+    pub fn get_all(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.data.iter()
+    }
+
+    /// Waits until the changelog has delivered the events held for synchronous mutations.
+    pub async fn wait_for_changelog(&mut self) {
+        self.writer.wait_for_changelog().await;
+    }
+
+    /// Writes every accumulated value to the table.
+    pub async fn flush(&mut self) -> Result<()> {
+        for (key, value) in &self.data {
+            self.writer.write(key, key, value).await?;
+        }
+        self.writer.finish_flush().await;
+        Ok(())
+    }
+}
+
+impl<K: Key, V: Data> KeyedTableView for ReducingView<K, V> {
+    fn writer(&mut self) -> &mut KeyedTableWriter {
+        &mut self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_serde::StateSerde;
+    use crate::tables::global_keyed_map::merge_entry;
+    use crate::{StateMessage, TableData};
+    use tokio::sync::mpsc::{channel, Receiver};
+
+    fn sum(acc: &mut u64, value: u64) {
+        *acc += value;
+    }
+
+    /// Decodes everything flushed since the last call, as it would be read back from the
+    /// file written for that epoch.
+    fn drain(rx: &mut Receiver<StateMessage>) -> Vec<(String, u64)> {
+        let mut flushed = vec![];
+        while let Ok(message) = rx.try_recv() {
+            let StateMessage::TableData {
+                data: TableData::KeyedData { key, value },
+                ..
+            } = message
+            else {
+                panic!("unexpected message {:?}", message);
+            };
+            flushed.push((
+                StateCodec::default().decode(&key).unwrap(),
+                StateCodec::default().decode(&value).unwrap(),
+            ));
+        }
+        flushed
+    }
+
+    fn restore(files: Vec<Vec<(String, u64)>>) -> HashMap<String, u64> {
+        let mut merge = sum as ReduceFn<u64>;
+        let mut data = HashMap::new();
+        for file in files {
+            for (key, value) in file {
+                merge_entry(&mut data, key, value, &mut merge);
+            }
+        }
+        data
+    }
+
+    #[tokio::test]
+    async fn test_updates_across_epochs_restore() {
+        let (tx, mut rx) = channel(100);
+        let tx = StateSender::unbuffered(tx);
+        let mut view = ReducingView::new(
+            "r".to_string(),
+            HashMap::new(),
+            sum,
+            StateCodec::default(),
+            tx.clone(),
+        );
+
+        view.insert("a".to_string(), 1).unwrap();
+        view.insert("a".to_string(), 2).unwrap();
+        view.insert("b".to_string(), 5).unwrap();
+        view.flush().await.unwrap();
+        let epoch_1 = drain(&mut rx);
+        assert_eq!(epoch_1.len(), 2);
+
+        assert_eq!(*view.insert("a".to_string(), 10).unwrap(), 13);
+        view.flush().await.unwrap();
+        let epoch_2 = drain(&mut rx);
+
+        // each epoch's file holds the full accumulated values, so restoring from the latest
+        // checkpoint sees only the second epoch
+        let restored = ReducingView::new(
+            "r".to_string(),
+            restore(vec![epoch_2]),
+            sum,
+            StateCodec::default(),
+            tx,
+        );
+        assert_eq!(restored.get(&"a".to_string()), Some(&13));
+        assert_eq!(restored.get(&"b".to_string()), Some(&5));
+    }
+
+    #[tokio::test]
+    async fn test_restore_merges_across_files() {
+        let (tx, mut rx) = channel(100);
+        let tx = StateSender::unbuffered(tx);
+        let mut first = ReducingView::new(
+            "r".to_string(),
+            HashMap::new(),
+            sum,
+            StateCodec::default(),
+            tx.clone(),
+        );
+        let mut second = ReducingView::new(
+            "r".to_string(),
+            HashMap::new(),
+            sum,
+            StateCodec::default(),
+            tx.clone(),
+        );
+
+        first.insert("a".to_string(), 1).unwrap();
+        first.insert("b".to_string(), 2).unwrap();
+        first.flush().await.unwrap();
+        let first_file = drain(&mut rx);
+
+        second.insert("a".to_string(), 4).unwrap();
+        second.flush().await.unwrap();
+        let second_file = drain(&mut rx);
+
+        let forward = restore(vec![first_file.clone(), second_file.clone()]);
+        let backward = restore(vec![second_file, first_file]);
+        assert_eq!(forward, backward);
+        assert_eq!(forward.get("a"), Some(&5));
+        assert_eq!(forward.get("b"), Some(&2));
+    }
+}
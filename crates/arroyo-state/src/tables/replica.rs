@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use arroyo_types::{Data, Key, TaskInfoRef};
+use prometheus::Gauge;
+
+use crate::metrics::TABLE_SNAPSHOT_AGE_GAUGE;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReplicaConfig {
+    /// Snapshots older than this are not returned to readers. Unset means any snapshot
+    /// is acceptable.
+    pub max_staleness: Option<Duration>,
+}
+
+// changes to a table, with `None` for deleted keys
+type Layer<K, V> = Arc<HashMap<K, Option<Arc<V>>>>;
+
+/// An immutable copy of a table's contents as of a checkpoint.
+///
+/// A snapshot is a stack of layers of changes, each over the ones below it, that it shares
+/// with the snapshots before and after it. Publishing a snapshot pushes the changes since the
+/// last one as a new layer, merging it into the layers below while they're no more than
+/// twice its size, so publishing costs the size of the changes rather than of the table,
+/// amortized, and a key is found in at most a logarithmic number of layers.
+#[derive(Debug)]
+pub struct TableSnapshot<K: Key, V: Data> {
+    pub epoch: u32,
+    pub published_at: SystemTime,
+    // oldest first; the first layer holds no deletes
+    layers: Vec<Layer<K, V>>,
+    len: usize,
+}
+
+impl<K: Key, V: Data> TableSnapshot<K, V> {
+    fn change(&self, key: &K) -> Option<&Option<Arc<V>>> {
+        self.layers.iter().rev().find_map(|layer| layer.get(key))
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.change(key)?.as_deref()
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The snapshot's entries, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.layers.iter().enumerate().flat_map(move |(i, layer)| {
+            layer.iter().filter_map(move |(key, value)| {
+                // keys changed again in a later layer are returned from that one
+                if self.layers[i + 1..]
+                    .iter()
+                    .any(|later| later.contains_key(key))
+                {
+                    return None;
+                }
+                Some((key, value.as_deref()?))
+            })
+        })
+    }
+
+    /// The snapshot with `changes` applied on top of `previous`, or of an empty table.
+    fn publish(previous: Option<&Self>, epoch: u32, changes: HashMap<K, Option<Arc<V>>>) -> Self {
+        let mut len = previous.map_or(0, |previous| previous.len);
+        for (key, value) in &changes {
+            let existed = previous.is_some_and(|previous| previous.contains_key(key));
+            match (existed, value.is_some()) {
+                (false, true) => len += 1,
+                (true, false) => len -= 1,
+                _ => {}
+            }
+        }
+        let mut layers = previous
+            .map(|previous| previous.layers.clone())
+            .unwrap_or_default();
+        let mut top = changes;
+        while let Some(below) = layers.last() {
+            if below.len() > 2 * top.len() {
+                break;
+            }
+            let mut merged = (**below).clone();
+            merged.extend(top);
+            top = merged;
+            layers.pop();
+        }
+        if layers.is_empty() {
+            top.retain(|_, value| value.is_some());
+        }
+        if !top.is_empty() {
+            layers.push(Arc::new(top));
+        }
+        TableSnapshot {
+            epoch,
+            published_at: SystemTime::now(),
+            layers,
+            len,
+        }
+    }
+}
+
+type Slot<K, V> = Arc<RwLock<Option<Arc<TableSnapshot<K, V>>>>>;
+
+/// Read side of a table replica, which can be cloned and handed to query handlers
+/// running outside of the operator.
+#[derive(Debug, Clone)]
+pub struct SnapshotReader<K: Key, V: Data> {
+    slot: Slot<K, V>,
+    max_staleness: Option<Duration>,
+    age_gauge: Gauge,
+}
+
+impl<K: Key, V: Data> SnapshotReader<K, V> {
+    /// The most recently published snapshot, if there is one within the staleness bound.
+    pub fn latest(&self) -> Option<Arc<TableSnapshot<K, V>>> {
+        let snapshot = self.slot.read().unwrap().clone()?;
+        let age = SystemTime::now()
+            .duration_since(snapshot.published_at)
+            .unwrap_or_default();
+        self.age_gauge.set(age.as_secs_f64());
+        match self.max_staleness {
+            Some(max_staleness) if age > max_staleness => None,
+            _ => Some(snapshot),
+        }
+    }
+}
+
+/// Write side of a replica, owned by the table's view. Every mutation of the view is
+/// recorded here until the next snapshot is published, so publishing doesn't need access to
+/// the view itself. Only the latest change to each key is kept, and values are shared with
+/// the snapshots they're published to.
+#[derive(Debug)]
+pub(crate) struct Replica<K: Key, V: Data> {
+    changes: Arc<Mutex<HashMap<K, Option<Arc<V>>>>>,
+}
+
+impl<K: Key, V: Data> Replica<K, V> {
+    pub(crate) fn fill<'a>(&self, data: impl Iterator<Item = (&'a K, &'a V)>) {
+        let mut changes = self.changes.lock().unwrap();
+        for (key, value) in data {
+            changes.insert(key.clone(), Some(Arc::new(value.clone())));
+        }
+    }
+
+    pub(crate) fn insert(&self, key: K, value: V) {
+        self.changes
+            .lock()
+            .unwrap()
+            .insert(key, Some(Arc::new(value)));
+    }
+
+    pub(crate) fn remove(&self, key: &K) {
+        self.changes.lock().unwrap().insert(key.clone(), None);
+    }
+}
+
+pub(crate) trait ReplicaPublisher: Send {
+    fn publish(&self, epoch: u32);
+}
+
+struct Publisher<K: Key, V: Data> {
+    changes: Arc<Mutex<HashMap<K, Option<Arc<V>>>>>,
+    slot: Slot<K, V>,
+    age_gauge: Gauge,
+}
+
+impl<K: Key, V: Data> ReplicaPublisher for Publisher<K, V> {
+    fn publish(&self, epoch: u32) {
+        let changes = mem::take(&mut *self.changes.lock().unwrap());
+        // the publisher is the only writer of the slot, so the snapshot can't be replaced
+        // between reading it and publishing the next one
+        let previous = self.slot.read().unwrap().clone();
+        let snapshot = TableSnapshot::publish(previous.as_deref(), epoch, changes);
+        *self.slot.write().unwrap() = Some(Arc::new(snapshot));
+        self.age_gauge.set(0.0);
+    }
+}
+
+pub(crate) fn replica<K: Key, V: Data>(
+    task_info: &TaskInfoRef,
+    table: &str,
+    config: ReplicaConfig,
+) -> (
+    Replica<K, V>,
+    Box<dyn ReplicaPublisher>,
+    SnapshotReader<K, V>,
+) {
+    let changes = Arc::new(Mutex::new(HashMap::new()));
+    let slot: Slot<K, V> = Arc::new(RwLock::new(None));
+    let age_gauge = TABLE_SNAPSHOT_AGE_GAUGE.with_label_values(&[
+        &task_info.operator_id,
+        &task_info.task_index.to_string(),
+        table,
+    ]);
+    (
+        Replica {
+            changes: changes.clone(),
+        },
+        Box::new(Publisher {
+            changes,
+            slot: slot.clone(),
+            age_gauge: age_gauge.clone(),
+        }),
+        SnapshotReader {
+            slot,
+            max_staleness: config.max_staleness,
+            age_gauge,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use arroyo_types::get_test_task_info;
+
+    use super::*;
+
+    fn sorted(snapshot: &TableSnapshot<u32, String>) -> Vec<(u32, String)> {
+        let mut entries: Vec<_> = snapshot
+            .iter()
+            .map(|(key, value)| (*key, value.clone()))
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn test_snapshots_are_immutable() {
+        let (replica, publisher, reader) = replica::<u32, String>(
+            &Arc::new(get_test_task_info()),
+            "replica",
+            ReplicaConfig::default(),
+        );
+        assert!(reader.latest().is_none());
+
+        let restored = HashMap::from([(1, "a".to_string()), (2, "b".to_string())]);
+        replica.fill(restored.iter());
+        replica.insert(3, "c".to_string());
+        publisher.publish(1);
+        let first = reader.latest().unwrap();
+        assert_eq!(first.epoch, 1);
+        assert_eq!(
+            sorted(&first),
+            vec![(1, "a".into()), (2, "b".into()), (3, "c".into())]
+        );
+
+        replica.insert(1, "x".to_string());
+        replica.remove(&2);
+        replica.remove(&4);
+        replica.insert(5, "e".to_string());
+        publisher.publish(2);
+        let second = reader.latest().unwrap();
+        assert_eq!(second.epoch, 2);
+        assert_eq!(second.get(&1).unwrap(), "x");
+        assert!(!second.contains_key(&2));
+        assert!(!second.contains_key(&4));
+        assert_eq!(second.len(), 3);
+        assert_eq!(
+            sorted(&second),
+            vec![(1, "x".into()), (3, "c".into()), (5, "e".into())]
+        );
+
+        // readers holding the earlier snapshot still see the table as it was
+        assert_eq!(first.get(&1).unwrap(), "a");
+        assert_eq!(first.len(), 3);
+
+        // an epoch without changes publishes the same contents
+        publisher.publish(3);
+        let third = reader.latest().unwrap();
+        assert_eq!(third.epoch, 3);
+        assert_eq!(sorted(&third), sorted(&second));
+    }
+
+    #[test]
+    fn test_small_changes_share_the_table() {
+        let (replica, publisher, reader) = replica::<u32, String>(
+            &Arc::new(get_test_task_info()),
+            "replica-layers",
+            ReplicaConfig::default(),
+        );
+        let mut expected: HashMap<u32, String> = (0..1000).map(|i| (i, i.to_string())).collect();
+        replica.fill(expected.iter());
+        publisher.publish(1);
+        let base = reader.latest().unwrap().layers[0].clone();
+
+        for epoch in 2..200 {
+            let key = epoch * 7 % 1200;
+            if epoch % 3 == 0 {
+                replica.remove(&key);
+                expected.remove(&key);
+            } else {
+                replica.insert(key, epoch.to_string());
+                expected.insert(key, epoch.to_string());
+            }
+            publisher.publish(epoch);
+            let snapshot = reader.latest().unwrap();
+            assert_eq!(snapshot.len(), expected.len());
+            // lookups stay cheap as changes pile up
+            assert!(snapshot.layers.len() <= 10, "{}", snapshot.layers.len());
+        }
+
+        let snapshot = reader.latest().unwrap();
+        let mut expected: Vec<_> = expected.into_iter().collect();
+        expected.sort();
+        assert_eq!(sorted(&snapshot), expected);
+        // the table itself was never copied, only the changes made to it
+        assert!(Arc::ptr_eq(&snapshot.layers[0], &base));
+    }
+}
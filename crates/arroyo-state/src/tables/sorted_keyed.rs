@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Bound, RangeBounds};
+
+use anyhow::Result;
+use arroyo_types::{Data, Key};
+
+use crate::changelog::ChangeKind;
+use crate::state_serde::StateCodec;
+use crate::tables::keyed_table_writer::{KeyedTableView, KeyedTableWriter};
+use crate::write_buffer::StateSender;
+
+/// Values ordered by key, for range scans and nearest-key lookups, stored in a global keyed
+/// table.
+///
+/// The whole table is held in memory, so it must fit there. Entries are rewritten in full on
+/// every call to [`SortedKeyedView::flush`], which operators should call from their
+/// checkpoint handler. Lookups take `&mut self` so that a version with a bounded cache can
+/// fetch evicted ranges without changing callers.
+#[derive(Debug)]
+pub struct SortedKeyedView<K: Key + Ord, V: Data> {
+    data: BTreeMap<K, V>,
+    writer: KeyedTableWriter,
+}
+
+impl<K: Key + Ord, V: Data> SortedKeyedView<K, V> {
+    pub(crate) fn new(
+        table_name: String,
+        persisted: HashMap<K, V>,
+        codec: StateCodec,
+        state_tx: StateSender,
+    ) -> Self {
+        Self {
+            data: persisted.into_iter().collect(),
+            writer: KeyedTableWriter::new(table_name, codec, state_tx),
+        }
+    }
+
+    /// Sets the value for `key`, returning the value it replaced. Fails, leaving the table
+    /// as it was, if the table's changelog is full or the insert would put the subtask over
+    /// its state quota.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>> {
+        self.writer
+            .insert(&key, &value, self.data.get(&key), None)?;
+        Ok(self.data.insert(key, value))
+    }
+
+    pub fn delete(&mut self, key: &K) -> Option<V> {
+        let removed = self.data.remove(key)?;
+        self.writer.remove(ChangeKind::Delete, key, None);
+        Some(removed)
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.data.get(key)
+    }
+
+    /// Entries with keys in `range`, in key order.
+    pub fn range<R: RangeBounds<K>>(&mut self, range: R) -> impl Iterator<Item = (&K, &V)> {
+        self.data.range(range)
+    }
+
+    /// The entry with the greatest key less than or equal to `key`.
+    pub fn floor(&mut self, key: &K) -> Option<(&K, &V)> {
+        self.data
+            .range((Bound::Unbounded, Bound::Included(key)))
+            .next_back()
+    }
+
+    /// The entry with the least key greater than or equal to `key`.
+    pub fn ceiling(&mut self, key: &K) -> Option<(&K, &V)> {
+        self.data
+            .range((Bound::Included(key), Bound::Unbounded))
+            .next()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Waits until the changelog has delivered the events held for synchronous mutations.
+    pub async fn wait_for_changelog(&mut self) {
+        self.writer.wait_for_changelog().await;
+    }
+
+    /// Writes every entry to the table.
+    pub async fn flush(&mut self) -> Result<()> {
+        for (key, value) in &self.data {
+            self.writer.write(key, key, value).await?;
+        }
+        self.writer.finish_flush().await;
+        Ok(())
+    }
+}
+
+impl<K: Key + Ord, V: Data> KeyedTableView for SortedKeyedView<K, V> {
+    fn writer(&mut self) -> &mut KeyedTableWriter {
+        &mut self.writer
+    }
+}
@@ -1,18 +1,26 @@
 use std::any::Any;
 
-use std::{collections::HashMap, env, sync::Arc, time::SystemTime};
+use std::{
+    collections::HashMap,
+    env,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
 use anyhow::{anyhow, bail, Context, Result};
 use arroyo_rpc::CompactionResult;
 use arroyo_rpc::{
     grpc::{
-        OperatorCheckpointMetadata, SubtaskCheckpointMetadata, TableConfig, TableEnum,
-        TableSubtaskCheckpointMetadata,
+        GlobalKeyedTableConfig, OperatorCheckpointMetadata, SubtaskCheckpointMetadata, TableConfig,
+        TableEnum, TableRestorePolicy, TableSubtaskCheckpointMetadata,
     },
     CheckpointCompleted, ControlResp,
 };
 use arroyo_storage::{StorageProvider, StorageProviderRef};
-use arroyo_types::{to_micros, CheckpointBarrier, Data, Key, TaskInfoRef, CHECKPOINT_URL_ENV};
+use arroyo_types::{
+    to_micros, CheckpointBarrier, Data, Key, TaskInfo, TaskInfoRef, CHECKPOINT_URL_ENV,
+};
+use prost::Message;
 use tokio::sync::{
     mpsc::{self, Receiver, Sender},
     oneshot,
@@ -20,12 +28,33 @@ use tokio::sync::{
 
 use tracing::{debug, info, warn};
 
-use crate::{tables::global_keyed_map::GlobalKeyedTable, StateMessage};
-use crate::{CheckpointMessage, TableData};
+use crate::backpressure::{table_data_size, write_size, WriteBacklog};
+use crate::changelog::{ChangeEvent, Changelog, ChangelogConfig};
+use crate::identifiers::validate_identifier;
+use crate::in_flight::{load_in_flight, write_in_flight, InFlightBatches};
+use crate::quota::{StateQuota, StateQuotaConfig, TableSize};
+use crate::remapping::{retain_declared_tables, validate_key_groups};
+use crate::restore_policy::{
+    RestoreNotes, RestorePolicyOverrides, STATE_RESTORE_POLICY_OVERRIDES_ENV,
+};
+use crate::restore_progress::RestoreProgress;
+use crate::state_serde::VersionedData;
+use crate::write_buffer::{StateSender, WriteBufferConfig};
+use crate::{hash_key, CheckpointMessage, TableData};
+use crate::{tables::global_keyed_map::GlobalKeyedTable, StateBackendKind, StateMessage};
 
 use super::expiring_time_key_map::{ExpiringTimeKeyTable, ExpiringTimeKeyView, KeyTimeView};
 use super::global_keyed_map::GlobalKeyedView;
-use super::{ErasedCheckpointer, ErasedTable};
+use super::key_time_map::KeyTimeMapView;
+use super::keyed_list::KeyedListView;
+use super::keyed_map::KeyedMapView;
+use super::keyed_table_writer::{encoded_size, KeyedTableView};
+use super::processing_time_timers::{PersistedProcessingTimeTimer, ProcessingTimeTimerView};
+use super::reducing::{ReduceFn, ReducingView};
+use super::replica::{replica, Replica, ReplicaConfig, ReplicaPublisher, SnapshotReader};
+use super::sorted_keyed::SortedKeyedView;
+use super::timers::TimerView;
+use super::{ErasedCheckpointer, ErasedTable, TableReads};
 
 #[allow(unused)]
 pub struct TableManager {
@@ -37,10 +66,24 @@ pub struct TableManager {
     task_info: TaskInfoRef,
     storage: StorageProviderRef,
     caches: HashMap<String, Box<dyn Any + Send>>,
+    // changelogs that will be attached to their table's view when it's first accessed
+    changelogs: HashMap<String, Changelog>,
+    // replicas that will be attached to their table's view when it's first accessed
+    replicas: HashMap<String, Box<dyn Any + Send>>,
+    replica_publishers: HashMap<String, Box<dyn ReplicaPublisher>>,
+    // shared by the views of every table, which report their sizes to it; unset when no
+    // limits are configured
+    quota: Option<Arc<StateQuota>>,
+    // records captured by the unaligned checkpoint being restored, until they're replayed
+    in_flight: Vec<InFlightBatches>,
+    restore_progress: RestoreProgress,
+    // when the subtask was restored, which processing-time timers that keep their remaining
+    // delay are rescheduled from, however late their view is first accessed
+    restored_at: SystemTime,
 }
 
 pub struct BackendWriter {
-    sender: Sender<StateMessage>,
+    sender: StateSender,
     finish_rx: Option<oneshot::Receiver<()>>,
     // TODO: compaction
 }
@@ -57,6 +100,13 @@ pub struct BackendFlusher {
     table_checkpointers: HashMap<String, Box<dyn ErasedCheckpointer>>,
     current_epoch: u32,
     last_epoch_checkpoints: HashMap<String, TableSubtaskCheckpointMetadata>,
+    backend: StateBackendKind,
+    backlog: Arc<WriteBacklog>,
+    // the metadata of an unaligned checkpoint whose tables have been written, which
+    // completes once the records captured in flight arrive
+    awaiting_in_flight: Option<(u32, SubtaskCheckpointMetadata)>,
+    // the tables whose restore departed from the checkpoint, if restoring from one
+    restore_notes: Option<RestoreNotes>,
 }
 
 impl BackendFlusher {
@@ -87,8 +137,9 @@ impl BackendFlusher {
 
     async fn flush_iteration(&mut self) -> Result<bool> {
         let mut checkpoint_epoch = None;
+        let durable = self.backend != StateBackendKind::Memory;
 
-        for (table_name, checkpointer) in &self.tables {
+        for (table_name, checkpointer) in self.tables.iter().filter(|_| durable) {
             let epoch_checkpointer = checkpointer.epoch_checkpointer(
                 self.current_epoch,
                 self.last_epoch_checkpoints.remove(table_name),
@@ -110,10 +161,25 @@ impl BackendFlusher {
                         Some(StateMessage::Compaction(compacted_tables_message)) => {
                             compacted_tables = Some(compacted_tables_message);
                         }
+                        Some(StateMessage::InFlight { epoch, captured }) => {
+                            // writes for the next epoch may already have arrived, so the
+                            // unaligned checkpoint finishes in this iteration
+                            self.finish_unaligned_checkpoint(epoch, captured).await?;
+                        }
+                        Some(write @ (StateMessage::TableData { .. } | StateMessage::TableDataBatch(_))) if !durable => {
+                            // in-memory tables don't write anything at checkpoint time
+                            self.backlog.remove(write_size(&write));
+                        }
                         Some(StateMessage::TableData { table, data }) => {
-                            self.table_checkpointers
-                                .get_mut(&table).expect("checkpointer should be there")
-                                .insert_data(data).await?
+                            self.insert_data(table, data).await?
+                        },
+                        Some(StateMessage::TableDataBatch(writes)) => {
+                            for write in writes {
+                                let StateMessage::TableData { table, data } = write else {
+                                    bail!("unexpected message in table data batch: {:?}", write);
+                                };
+                                self.insert_data(table, data).await?;
+                            }
                         },
                         None => {
                             debug!("Parquet flusher closed");
@@ -126,12 +192,18 @@ impl BackendFlusher {
         let Some(cp) = checkpoint_epoch else {
             bail!("somehow exited loop without checkpoint_epoch being set");
         };
+        if self.awaiting_in_flight.is_some() {
+            bail!(
+                "received checkpoint for epoch {} before the in-flight records of the previous one",
+                cp.epoch
+            );
+        }
         let mut metadatas = HashMap::new();
-        let mut bytes = 0;
+        let mut table_bytes = HashMap::new();
         for (table_name, checkpointer) in self.table_checkpointers.drain() {
             if let Some((subtask_checkpoint_data, size)) = checkpointer.finish(&cp).await? {
                 metadatas.insert(table_name.clone(), subtask_checkpoint_data);
-                bytes += size;
+                table_bytes.insert(table_name, size as u64);
             }
         }
 
@@ -164,17 +236,24 @@ impl BackendFlusher {
             start_time: to_micros(cp.time),
             finish_time: to_micros(SystemTime::now()),
             watermark: cp.watermark.map(to_micros),
+            backend: self.backend.name().to_string(),
             table_metadata: metadatas,
             table_configs: self.table_configs.clone(),
-            bytes: bytes as u64,
+            bytes: table_bytes.values().sum(),
+            table_bytes,
+            peak_pending_write_bytes: self.backlog.take_peak(),
+            in_flight_files: vec![],
+            restore_notes: self
+                .restore_notes
+                .as_mut()
+                .map(RestoreNotes::take)
+                .unwrap_or_default(),
         };
-        self.control_tx
-            .send(ControlResp::CheckpointCompleted(CheckpointCompleted {
-                checkpoint_epoch: cp.epoch,
-                operator_id: self.task_info.operator_id.clone(),
-                subtask_metadata,
-            }))
-            .await?;
+        if cp.in_flight {
+            self.awaiting_in_flight = Some((cp.epoch, subtask_metadata));
+            return Ok(true);
+        }
+        self.send_completed(cp.epoch, subtask_metadata).await?;
         if cp.then_stop {
             self.finish_tx
                 .take()
@@ -185,6 +264,63 @@ impl BackendFlusher {
         }
         Ok(true)
     }
+
+    async fn finish_unaligned_checkpoint(
+        &mut self,
+        epoch: u32,
+        captured: Vec<InFlightBatches>,
+    ) -> Result<()> {
+        let Some((expected_epoch, mut subtask_metadata)) = self.awaiting_in_flight.take() else {
+            bail!(
+                "received in-flight records for epoch {} without an unaligned checkpoint",
+                epoch
+            );
+        };
+        if epoch != expected_epoch {
+            bail!(
+                "received in-flight records for epoch {} while checkpointing epoch {}",
+                epoch,
+                expected_epoch
+            );
+        }
+
+        // in-memory state can't be restored, so neither can the records
+        if self.backend != StateBackendKind::Memory {
+            for captured in captured.iter().filter(|c| !c.batches.is_empty()) {
+                subtask_metadata
+                    .in_flight_files
+                    .push(write_in_flight(&self.storage, &self.task_info, epoch, captured).await?);
+            }
+        }
+        subtask_metadata.finish_time = to_micros(SystemTime::now());
+        self.send_completed(epoch, subtask_metadata).await
+    }
+
+    async fn send_completed(
+        &mut self,
+        epoch: u32,
+        subtask_metadata: SubtaskCheckpointMetadata,
+    ) -> Result<()> {
+        self.control_tx
+            .send(ControlResp::CheckpointCompleted(CheckpointCompleted {
+                checkpoint_epoch: epoch,
+                operator_id: self.task_info.operator_id.clone(),
+                subtask_metadata,
+            }))
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_data(&mut self, table: String, data: TableData) -> Result<()> {
+        let size = table_data_size(&data);
+        self.table_checkpointers
+            .get_mut(&table)
+            .expect("checkpointer should be there")
+            .insert_data(data)
+            .await?;
+        self.backlog.remove(size);
+        Ok(())
+    }
 }
 
 impl BackendWriter {
@@ -196,9 +332,12 @@ impl BackendWriter {
         storage: StorageProviderRef,
         current_epoch: u32,
         last_epoch_checkpoints: HashMap<String, TableSubtaskCheckpointMetadata>,
+        backend: StateBackendKind,
+        restore_notes: Option<RestoreNotes>,
     ) -> Self {
         let (tx, rx) = mpsc::channel(1024 * 1024);
         let (finish_tx, finish_rx) = oneshot::channel();
+        let backlog = Arc::new(WriteBacklog::from_env());
 
         (BackendFlusher {
             queue: rx,
@@ -211,11 +350,15 @@ impl BackendWriter {
             current_epoch,
             table_checkpointers: HashMap::new(),
             last_epoch_checkpoints,
+            backend,
+            backlog: backlog.clone(),
+            awaiting_in_flight: None,
+            restore_notes,
         })
         .start();
 
         Self {
-            sender: tx,
+            sender: StateSender::new(tx, WriteBufferConfig::from_env(), backlog),
             finish_rx: Some(finish_rx),
         }
     }
@@ -237,6 +380,48 @@ async fn get_storage_provider() -> anyhow::Result<StorageProviderRef> {
     ))
 }
 
+fn uses_two_phase_commit(config: &TableConfig) -> bool {
+    GlobalKeyedTableConfig::decode(&mut config.config.as_slice())
+        .map(|config| config.uses_two_phase_commit)
+        .unwrap_or(false)
+}
+
+fn restore_error(task_info: &TaskInfo, table_name: &str) -> String {
+    format!(
+        "failed to restore table {} of operator {} (subtask {}) in job {}",
+        table_name, task_info.operator_id, task_info.task_index, task_info.job_id
+    )
+}
+
+/// Restores an operator checkpointed with the in-memory backend, whose tables start empty.
+/// That backend never writes table data, so a checkpoint that has some for it was written by
+/// something else and is refused rather than dropped.
+fn memory_backed_restore(
+    operator_id: &str,
+    metadata: OperatorCheckpointMetadata,
+) -> Result<OperatorCheckpointMetadata> {
+    if !metadata.table_checkpoint_metadata.is_empty() {
+        let mut tables: Vec<_> = metadata.table_checkpoint_metadata.keys().cloned().collect();
+        tables.sort();
+        bail!(
+            "operator {} was checkpointed with the in-memory backend, but its checkpoint has \
+            data for tables {}; refusing to drop it",
+            operator_id,
+            tables.join(", ")
+        );
+    }
+    if !metadata.table_configs.is_empty() {
+        let mut tables: Vec<_> = metadata.table_configs.keys().cloned().collect();
+        tables.sort();
+        warn!(
+            "operator {} was checkpointed with the in-memory backend; its tables {} start empty",
+            operator_id,
+            tables.join(", ")
+        );
+    }
+    Ok(metadata)
+}
+
 impl TableManager {
     pub async fn new(
         task_info: TaskInfoRef,
@@ -244,8 +429,132 @@ impl TableManager {
         tx: Sender<ControlResp>,
         checkpoint_metadata: Option<OperatorCheckpointMetadata>,
     ) -> Result<Self> {
+        let restore_progress = RestoreProgress::new(&task_info);
+        Self::new_with_restore_progress(
+            task_info,
+            table_configs,
+            tx,
+            checkpoint_metadata,
+            restore_progress,
+        )
+        .await
+    }
+
+    /// Like [`TableManager::new`], with the tables reporting how far they've got reading
+    /// the checkpoint to `restore_progress` as their views are first built.
+    pub async fn new_with_restore_progress(
+        task_info: TaskInfoRef,
+        table_configs: HashMap<String, TableConfig>,
+        tx: Sender<ControlResp>,
+        checkpoint_metadata: Option<OperatorCheckpointMetadata>,
+        restore_progress: RestoreProgress,
+    ) -> Result<Self> {
+        let restored_at = SystemTime::now();
+        validate_identifier("job id", &task_info.job_id)?;
+        validate_identifier("operator id", &task_info.operator_id)?;
+        for table_name in table_configs.keys() {
+            validate_identifier("table name", table_name)?;
+        }
+        let quota = match StateQuotaConfig::for_tables(&table_configs)? {
+            Some(quota) => quota,
+            None => StateQuotaConfig::from_env()?,
+        };
         let storage = get_storage_provider().await?;
 
+        let backend = StateBackendKind::for_tables(&table_configs)?;
+        if backend == StateBackendKind::Memory
+            && table_configs.values().any(|config| {
+                config.table_type() == TableEnum::GlobalKeyValue && uses_two_phase_commit(config)
+            })
+        {
+            bail!(
+                "operator {} uses two-phase commit, which requires a durable state backend",
+                task_info.operator_id
+            );
+        }
+        // state held by a non-durable backend can't be restored, so start from scratch
+        let checkpoint_metadata = match checkpoint_metadata {
+            Some(metadata)
+                if StateBackendKind::from_name(&metadata.backend)? == StateBackendKind::Memory =>
+            {
+                Some(memory_backed_restore(&task_info.operator_id, metadata)?)
+            }
+            // an operator that's become stateless has no use for the state it had
+            Some(metadata)
+                if table_configs.is_empty() && !metadata.table_checkpoint_metadata.is_empty() =>
+            {
+                info!(
+                    "operator {} no longer has any tables; not restoring table data",
+                    task_info.operator_id
+                );
+                Some(OperatorCheckpointMetadata {
+                    table_checkpoint_metadata: HashMap::new(),
+                    ..metadata
+                })
+            }
+            metadata => metadata,
+        };
+
+        let policy_overrides = RestorePolicyOverrides::from_env()?;
+        for table_name in policy_overrides.unknown_tables(&task_info.operator_id, &table_configs) {
+            warn!(
+                "{} sets a restore policy for table {}, which operator {} doesn't declare",
+                STATE_RESTORE_POLICY_OVERRIDES_ENV, table_name, task_info.operator_id
+            );
+        }
+        let restore_policies: HashMap<_, _> = table_configs
+            .iter()
+            .map(|(table_name, config)| {
+                (
+                    table_name.clone(),
+                    policy_overrides.policy(&task_info.operator_id, table_name, config),
+                )
+            })
+            .collect();
+        let restore_notes = checkpoint_metadata.as_ref().map(|metadata| {
+            RestoreNotes::new(
+                task_info.task_index as u32,
+                metadata
+                    .operator_metadata
+                    .as_ref()
+                    .map(|operator_metadata| operator_metadata.epoch)
+                    .unwrap_or_default(),
+                restore_policies
+                    .iter()
+                    .filter(|(table_name, _)| {
+                        metadata.table_checkpoint_metadata.contains_key(*table_name)
+                    })
+                    .map(|(table_name, policy)| (table_name.clone(), *policy))
+                    .collect(),
+                restore_progress.clone(),
+            )
+        });
+        // reset tables start empty, whatever their checkpoint holds
+        let checkpoint_metadata = checkpoint_metadata.map(|mut metadata| {
+            // tables the operator no longer declares are dropped with their state; state
+            // remapped from another operator is checked against its tables before this
+            for table_name in retain_declared_tables(&mut metadata, &table_configs) {
+                info!(
+                    "operator {} no longer declares table {}; not restoring it",
+                    task_info.operator_id, table_name
+                );
+            }
+            metadata.table_checkpoint_metadata.retain(|table_name, _| {
+                let reset = restore_policies.get(table_name) == Some(&TableRestorePolicy::Reset);
+                if reset {
+                    info!(
+                        "resetting table {} of operator {} rather than restoring it",
+                        table_name, task_info.operator_id
+                    );
+                }
+                !reset
+            });
+            metadata
+        });
+        if let Some(metadata) = &checkpoint_metadata {
+            validate_key_groups(metadata, &table_configs)?;
+        }
+
         let tables = table_configs
             .iter()
             .map(|(table_name, table_config)| {
@@ -253,25 +562,32 @@ impl TableManager {
                     .as_ref()
                     .map(|metadata| metadata.table_checkpoint_metadata.get(table_name).cloned())
                     .flatten();
-                let erased_table = match table_config.table_type() {
+                let mut erased_table = match table_config.table_type() {
                     TableEnum::MissingTableType => bail!("should have table type"),
-                    TableEnum::GlobalKeyValue => {
-                        Box::new(<GlobalKeyedTable as ErasedTable>::from_config(
+                    TableEnum::GlobalKeyValue | TableEnum::KeyTimeMap => Box::new(
+                        <GlobalKeyedTable as ErasedTable>::from_config(
                             table_config.clone(),
                             task_info.clone(),
                             storage.clone(),
                             table_restore_from,
-                        )?) as Box<dyn ErasedTable>
-                    }
-                    TableEnum::ExpiringKeyedTimeTable => {
-                        Box::new(<ExpiringTimeKeyTable as ErasedTable>::from_config(
+                        )
+                        .with_context(|| restore_error(&task_info, table_name))?,
+                    )
+                        as Box<dyn ErasedTable>,
+                    TableEnum::ExpiringKeyedTimeTable => Box::new(
+                        <ExpiringTimeKeyTable as ErasedTable>::from_config(
                             table_config.clone(),
                             task_info.clone(),
                             storage.clone(),
                             table_restore_from,
-                        )?) as Box<dyn ErasedTable>
-                    }
+                        )
+                        .with_context(|| restore_error(&task_info, table_name))?,
+                    )
+                        as Box<dyn ErasedTable>,
                 };
+                erased_table.set_restore_progress(restore_progress.table(table_name));
+                erased_table.set_restore_policy(restore_policies[table_name]);
+                erased_table.set_timestamp_policy(table_config.timestamp_policy());
                 Ok((table_name.to_string(), Arc::new(erased_table)))
             })
             .collect::<Result<HashMap<_, _>>>()?;
@@ -279,6 +595,7 @@ impl TableManager {
         let epoch;
         let min_epoch;
         let mut last_epoch_checkpoints = HashMap::new();
+        let mut in_flight = vec![];
         match checkpoint_metadata {
             Some(metadata) => {
                 // TODO: validate this logic.
@@ -287,6 +604,27 @@ impl TableManager {
                 };
                 epoch = operator_metadata.epoch + 1;
                 min_epoch = operator_metadata.epoch;
+                if !metadata.in_flight.is_empty() {
+                    // captured records belong to the subtask that received them
+                    if operator_metadata.parallelism != task_info.parallelism as u64 {
+                        bail!(
+                            "operator {} was checkpointed with records in flight at parallelism {}, so it can't be restored at parallelism {}",
+                            task_info.operator_id,
+                            operator_metadata.parallelism,
+                            task_info.parallelism
+                        );
+                    }
+                    if let Some(files) = metadata.in_flight.get(&(task_info.task_index as u32)) {
+                        in_flight = load_in_flight(&storage, &files.files)
+                            .await
+                            .with_context(|| {
+                                format!(
+                                    "failed to restore in-flight records of operator {} (subtask {})",
+                                    task_info.operator_id, task_info.task_index
+                                )
+                            })?;
+                    }
+                }
                 for (table, table_metadata) in metadata.table_checkpoint_metadata.clone() {
                     let table_implementation = tables
                         .get(&table)
@@ -312,19 +650,77 @@ impl TableManager {
             storage.clone(),
             epoch,
             last_epoch_checkpoints,
+            backend,
+            restore_notes,
         );
         Ok(Self {
             epoch,
             min_epoch,
             tables,
             writer,
-            task_info,
             storage,
             caches: HashMap::new(),
+            changelogs: HashMap::new(),
+            replicas: HashMap::new(),
+            replica_publishers: HashMap::new(),
+            quota: StateQuota::new(task_info.clone(), quota),
+            task_info,
+            in_flight,
+            restore_progress,
+            restored_at,
         })
     }
 
+    /// How far each table has got reading the checkpoint being restored.
+    pub fn restore_progress(&self) -> &RestoreProgress {
+        &self.restore_progress
+    }
+
+    /// Takes the records captured in flight by the unaligned checkpoint being restored,
+    /// which must be processed before anything read from the inputs.
+    pub fn take_in_flight(&mut self) -> Vec<InFlightBatches> {
+        std::mem::take(&mut self.in_flight)
+    }
+
     pub async fn checkpoint(&mut self, barrier: CheckpointBarrier, watermark: Option<SystemTime>) {
+        self.send_checkpoint(barrier, watermark, false).await;
+    }
+
+    /// Checkpoints the tables for an unaligned checkpoint, which completes once the records
+    /// still in flight are passed to [`Self::write_in_flight`].
+    pub async fn checkpoint_unaligned(
+        &mut self,
+        barrier: CheckpointBarrier,
+        watermark: Option<SystemTime>,
+    ) {
+        assert!(!barrier.then_stop, "stopping checkpoints must be aligned");
+        self.send_checkpoint(barrier, watermark, true).await;
+    }
+
+    pub async fn write_in_flight(&mut self, epoch: u32, captured: Vec<InFlightBatches>) {
+        self.writer
+            .sender
+            .send(StateMessage::InFlight { epoch, captured })
+            .await
+            .expect("should be able to send in-flight records");
+    }
+
+    async fn send_checkpoint(
+        &mut self,
+        barrier: CheckpointBarrier,
+        watermark: Option<SystemTime>,
+        in_flight: bool,
+    ) {
+        for publisher in self.replica_publishers.values() {
+            publisher.publish(barrier.epoch);
+        }
+
+        // writes made before the barrier belong to this checkpoint
+        self.writer
+            .sender
+            .flush()
+            .await
+            .expect("should be able to flush table writes");
         self.writer
             .sender
             .send(StateMessage::Checkpoint(CheckpointMessage {
@@ -332,6 +728,7 @@ impl TableManager {
                 time: barrier.timestamp,
                 watermark,
                 then_stop: barrier.then_stop,
+                in_flight,
             }))
             .await
             .expect("should be able to send checkpoint");
@@ -344,6 +741,23 @@ impl TableManager {
         }
     }
 
+    /// Bytes of table writes that haven't yet been handed to the state backend.
+    pub fn pending_write_bytes(&self) -> u64 {
+        self.writer.sender.backlog().pending_write_bytes()
+    }
+
+    /// Whether table writes are waiting for the state backend to catch up.
+    pub fn is_backpressured(&self) -> bool {
+        self.writer.sender.backlog().is_backpressured()
+    }
+
+    /// Waits until the state backend has caught up enough for writes to proceed without
+    /// waiting. Table writes already do this, so operators only need it to hold back other
+    /// work while state is behind.
+    pub async fn wait_for_capacity(&self) {
+        self.writer.sender.backlog().wait_for_capacity().await
+    }
+
     pub async fn load_compacted(&mut self, compacted: CompactionResult) -> Result<()> {
         if compacted.operator_id != self.task_info.operator_id {
             bail!("shouldn't be loading compaction for other operator");
@@ -366,39 +780,148 @@ impl TableManager {
         Ok(())
     }
 
+    /// Enables the changelog for a table, returning the receiving end of the channel that
+    /// every subsequent mutation is sent to. Must be called before the table is first
+    /// accessed, so that the restored contents aren't reported as changes.
+    pub fn enable_changelog(
+        &mut self,
+        table_name: &str,
+        config: ChangelogConfig,
+    ) -> Result<Receiver<ChangeEvent>> {
+        if !self.tables.contains_key(table_name) {
+            bail!("no registered table {}", table_name);
+        }
+        if self.caches.contains_key(table_name) {
+            bail!(
+                "changelog for table {} must be enabled before the table is accessed",
+                table_name
+            );
+        }
+        let (changelog, rx) = config.channel(&self.task_info, table_name);
+        self.changelogs.insert(table_name.to_string(), changelog);
+        Ok(rx)
+    }
+
+    /// Enables a read replica for a global keyed table. A snapshot of the table is published
+    /// at every checkpoint and can be read through the returned reader without coordinating
+    /// with the operator. Must be called before the table is first accessed.
+    pub fn enable_replica<K: Key, V: Data>(
+        &mut self,
+        table_name: &str,
+        config: ReplicaConfig,
+    ) -> Result<SnapshotReader<K, V>> {
+        let table = self
+            .tables
+            .get(table_name)
+            .ok_or_else(|| anyhow!("no registered table {}", table_name))?;
+        if table.as_any().downcast_ref::<GlobalKeyedTable>().is_none() {
+            bail!(
+                "read replicas are only supported for global keyed tables, not {}",
+                table_name
+            );
+        }
+        if self.caches.contains_key(table_name) {
+            bail!(
+                "replica for table {} must be enabled before the table is accessed",
+                table_name
+            );
+        }
+        let (replica, publisher, reader) = replica::<K, V>(&self.task_info, table_name, config);
+        self.replicas
+            .insert(table_name.to_string(), Box::new(replica));
+        self.replica_publishers
+            .insert(table_name.to_string(), publisher);
+        Ok(reader)
+    }
+
+    /// Overrides the operator's state quota, whether declared on its table configs or
+    /// configured for the worker. This subtask enforces its share of the limits; see
+    /// [`StateQuotaConfig`]. Must be called before any table is accessed, as restored state
+    /// is checked against the quota when it's loaded.
+    pub fn set_state_quota(&mut self, config: StateQuotaConfig) -> Result<()> {
+        if !self.caches.is_empty() {
+            bail!("state quota must be set before any table is accessed");
+        }
+        self.quota = StateQuota::new(self.task_info.clone(), config);
+        Ok(())
+    }
+
     pub async fn get_global_keyed_state<K: Key, V: Data>(
         &mut self,
         table_name: &str,
     ) -> Result<&mut GlobalKeyedView<K, V>> {
         // this is done because populating it is async, so can't use or_insert().
-        if let std::collections::hash_map::Entry::Vacant(e) =
-            self.caches.entry(table_name.to_string())
-        {
-            let table_implementation = self
-                .tables
-                .get(table_name)
-                .ok_or_else(|| anyhow!("no registered table {}", table_name))?;
-            let global_keyed_table = table_implementation
-                .as_any()
-                .downcast_ref::<GlobalKeyedTable>()
-                .ok_or_else(|| anyhow!("wrong table type for table {}", table_name))?;
-            let saved_data = global_keyed_table
+        if !self.caches.contains_key(table_name) {
+            let start = Instant::now();
+            let saved_data = self
+                .global_keyed_table(table_name)?
                 .memory_view::<K, V>(self.writer.sender.clone())
-                .await?;
-            let cache: Box<dyn Any + Send> = Box::new(saved_data);
-            e.insert(cache);
+                .await
+                .with_context(|| restore_error(&self.task_info, table_name))?;
+            self.cache_global_keyed_view(table_name, saved_data, start.elapsed())?;
         }
+        self.cached_view(table_name)
+    }
 
-        let cache = self.caches.get_mut(table_name).unwrap();
-        let cache: &mut GlobalKeyedView<K, V> = cache.downcast_mut().ok_or_else(|| {
-            anyhow!(
-                "Failed to downcast table {} to key type {} and value type {}",
-                table_name,
-                std::any::type_name::<K>(),
-                std::any::type_name::<V>()
-            )
-        })?;
-        Ok(cache)
+    /// Like [`TableManager::get_global_keyed_state`], for tables declared with the version
+    /// of `V` through [`crate::with_value_version`]. Values checkpointed as an earlier version
+    /// are upgraded as the table is restored.
+    pub async fn get_versioned_global_keyed_state<K: Key, V: VersionedData>(
+        &mut self,
+        table_name: &str,
+    ) -> Result<&mut GlobalKeyedView<K, V>> {
+        if !self.caches.contains_key(table_name) {
+            let start = Instant::now();
+            let saved_data = self
+                .global_keyed_table(table_name)?
+                .versioned_memory_view::<K, V>(self.writer.sender.clone())
+                .await
+                .with_context(|| restore_error(&self.task_info, table_name))?;
+            self.cache_global_keyed_view(table_name, saved_data, start.elapsed())?;
+        }
+        self.cached_view(table_name)
+    }
+
+    fn global_keyed_table(&self, table_name: &str) -> Result<&GlobalKeyedTable> {
+        self.tables
+            .get(table_name)
+            .ok_or_else(|| anyhow!("no registered table {}", table_name))?
+            .as_any()
+            .downcast_ref::<GlobalKeyedTable>()
+            .ok_or_else(|| anyhow!("wrong table type for table {}", table_name))
+    }
+
+    /// Attaches the table's changelog, quota, replica and read metrics to a restored view,
+    /// which took `load_time` to read, and caches it.
+    fn cache_global_keyed_view<K: Key, V: Data>(
+        &mut self,
+        table_name: &str,
+        mut saved_data: GlobalKeyedView<K, V>,
+        load_time: Duration,
+    ) -> Result<()> {
+        let reads = TableReads::new(&self.task_info, table_name);
+        reads.miss(load_time);
+        saved_data.set_reads(reads);
+        if let Some(changelog) = self.changelogs.remove(table_name) {
+            saved_data.set_changelog(changelog);
+        }
+        saved_data.set_size(TableSize::new(
+            &self.task_info,
+            table_name,
+            self.quota.clone(),
+        ))?;
+        if let Some(replica) = self.replicas.remove(table_name) {
+            let replica = replica.downcast::<Replica<K, V>>().map_err(|_| {
+                anyhow!(
+                    "replica for table {} was enabled with different key and value types",
+                    table_name
+                )
+            })?;
+            saved_data.set_replica(*replica);
+        }
+        let cache: Box<dyn Any + Send> = Box::new(saved_data);
+        self.caches.insert(table_name.to_string(), cache);
+        Ok(())
     }
 
     pub async fn get_expiring_time_key_table(
@@ -417,9 +940,18 @@ impl TableManager {
                 .as_any()
                 .downcast_ref::<ExpiringTimeKeyTable>()
                 .ok_or_else(|| anyhow!("wrong table type for table {}", table_name))?;
-            let saved_data = expiring_time_key_table
+            let mut saved_data = expiring_time_key_table
                 .get_view(self.writer.sender.clone(), watermark)
-                .await?;
+                .await
+                .with_context(|| restore_error(&self.task_info, table_name))?;
+            if let Some(changelog) = self.changelogs.remove(table_name) {
+                saved_data.set_changelog(changelog);
+            }
+            saved_data.set_size(TableSize::new(
+                &self.task_info,
+                table_name,
+                self.quota.clone(),
+            ))?;
             let cache: Box<dyn Any + Send> = Box::new(saved_data);
             e.insert(cache);
         }
@@ -446,9 +978,18 @@ impl TableManager {
                 .as_any()
                 .downcast_ref::<ExpiringTimeKeyTable>()
                 .ok_or_else(|| anyhow!("wrong table type for table {}", table_name))?;
-            let saved_data = expiring_time_key_table
+            let mut saved_data = expiring_time_key_table
                 .get_key_time_view(self.writer.sender.clone(), watermark)
-                .await?;
+                .await
+                .with_context(|| restore_error(&self.task_info, table_name))?;
+            if let Some(changelog) = self.changelogs.remove(table_name) {
+                saved_data.set_changelog(changelog);
+            }
+            saved_data.set_size(TableSize::new(
+                &self.task_info,
+                table_name,
+                self.quota.clone(),
+            ))?;
             let cache: Box<dyn Any + Send> = Box::new(saved_data);
             e.insert(cache);
         }
@@ -458,4 +999,253 @@ impl TableManager {
             .ok_or_else(|| anyhow!("Failed to downcast table {}", table_name))?;
         Ok(cache)
     }
+
+    /// The processing-time timers in `table_name`. Timers restored with
+    /// [`super::processing_time_timers::ProcessingTimeRestoreMode::RescheduleFromRestart`]
+    /// keep the delay they had left, counted from when this manager was created. Operators
+    /// should load the view in `on_start`, so restored timers are registered before the first
+    /// record is processed.
+    pub async fn get_processing_time_timers<K: Key>(
+        &mut self,
+        table_name: &str,
+    ) -> Result<&mut ProcessingTimeTimerView<K>> {
+        if !self.caches.contains_key(table_name) {
+            let start = Instant::now();
+            let table = self.global_keyed_table(table_name)?;
+            let persisted = table
+                .read_all::<K, Vec<PersistedProcessingTimeTimer>>()
+                .await
+                .with_context(|| restore_error(&self.task_info, table_name))?;
+            let restored = encoded_size(&table.codec(), &persisted);
+            let view = ProcessingTimeTimerView::new(
+                table_name.to_string(),
+                persisted,
+                self.restored_at,
+                table.codec(),
+                self.writer.sender.clone(),
+            );
+            self.cache_keyed_view(table_name, view, restored, start.elapsed())?;
+        }
+        self.cached_view(table_name)
+    }
+
+    pub async fn get_key_time_map<K: Key, V: Data>(
+        &mut self,
+        table_name: &str,
+    ) -> Result<&mut KeyTimeMapView<K, V>> {
+        if !self.caches.contains_key(table_name) {
+            let start = Instant::now();
+            let table = self.global_keyed_table(table_name)?;
+            let persisted = table
+                .read_all::<K, Vec<(SystemTime, V)>>()
+                .await
+                .with_context(|| restore_error(&self.task_info, table_name))?;
+            let restored = encoded_size(&table.codec(), &persisted);
+            let view = KeyTimeMapView::new(
+                table_name.to_string(),
+                persisted,
+                table.codec(),
+                self.writer.sender.clone(),
+                &self.task_info,
+            );
+            self.cache_keyed_view(table_name, view, restored, start.elapsed())?;
+        }
+        self.cached_view(table_name)
+    }
+
+    pub async fn get_keyed_list<K: Key, V: Data>(
+        &mut self,
+        table_name: &str,
+    ) -> Result<&mut KeyedListView<K, V>> {
+        if !self.caches.contains_key(table_name) {
+            let start = Instant::now();
+            let table = self.global_keyed_table(table_name)?;
+            let persisted = table
+                .read_all::<K, Vec<V>>()
+                .await
+                .with_context(|| restore_error(&self.task_info, table_name))?;
+            let restored = encoded_size(&table.codec(), &persisted);
+            let view = KeyedListView::new(
+                table_name.to_string(),
+                persisted,
+                table.codec(),
+                self.writer.sender.clone(),
+            );
+            self.cache_keyed_view(table_name, view, restored, start.elapsed())?;
+        }
+        self.cached_view(table_name)
+    }
+
+    /// Gets the view for a reducing table. `reduce` is used both for inserts and to merge
+    /// restored values, and should be the same function every time the table is accessed.
+    pub async fn get_reducing_table<K: Key, V: Data>(
+        &mut self,
+        table_name: &str,
+        reduce: ReduceFn<V>,
+    ) -> Result<&mut ReducingView<K, V>> {
+        if !self.caches.contains_key(table_name) {
+            let start = Instant::now();
+            let table = self.global_keyed_table(table_name)?;
+            let persisted = table
+                .read_all_merged::<K, V>(reduce)
+                .await
+                .with_context(|| restore_error(&self.task_info, table_name))?;
+            let restored = encoded_size(&table.codec(), &persisted);
+            let view = ReducingView::new(
+                table_name.to_string(),
+                persisted,
+                reduce,
+                table.codec(),
+                self.writer.sender.clone(),
+            );
+            self.cache_keyed_view(table_name, view, restored, start.elapsed())?;
+        }
+        self.cached_view(table_name)
+    }
+
+    pub async fn get_timers<K: Key>(&mut self, table_name: &str) -> Result<&mut TimerView<K>> {
+        if !self.caches.contains_key(table_name) {
+            let start = Instant::now();
+            let table = self.global_keyed_table(table_name)?;
+            let persisted = table
+                .read_all::<K, Vec<SystemTime>>()
+                .await
+                .with_context(|| restore_error(&self.task_info, table_name))?;
+            let restored = encoded_size(&table.codec(), &persisted);
+            let view = TimerView::new(
+                table_name.to_string(),
+                persisted,
+                table.codec(),
+                self.writer.sender.clone(),
+            );
+            self.cache_keyed_view(table_name, view, restored, start.elapsed())?;
+        }
+        self.cached_view(table_name)
+    }
+
+    pub async fn get_keyed_map<K: Key, IK: Key, V: Data>(
+        &mut self,
+        table_name: &str,
+    ) -> Result<&mut KeyedMapView<K, IK, V>> {
+        if !self.caches.contains_key(table_name) {
+            let start = Instant::now();
+            let table = self.global_keyed_table(table_name)?;
+            // partitioned by the outer key, so that all of a key's entries restore together
+            let persisted = table
+                .read_partitioned::<(K, IK), V>(|(key, _)| hash_key(key))
+                .await
+                .with_context(|| restore_error(&self.task_info, table_name))?;
+            let restored = encoded_size(&table.codec(), &persisted);
+            let view = KeyedMapView::new(
+                table_name.to_string(),
+                persisted,
+                table.codec(),
+                self.writer.sender.clone(),
+            );
+            self.cache_keyed_view(table_name, view, restored, start.elapsed())?;
+        }
+        self.cached_view(table_name)
+    }
+
+    pub async fn get_sorted_keyed_table<K: Key + Ord, V: Data>(
+        &mut self,
+        table_name: &str,
+    ) -> Result<&mut SortedKeyedView<K, V>> {
+        if !self.caches.contains_key(table_name) {
+            let start = Instant::now();
+            let table = self.global_keyed_table(table_name)?;
+            let persisted = table
+                .read_all::<K, V>()
+                .await
+                .with_context(|| restore_error(&self.task_info, table_name))?;
+            let restored = encoded_size(&table.codec(), &persisted);
+            let view = SortedKeyedView::new(
+                table_name.to_string(),
+                persisted,
+                table.codec(),
+                self.writer.sender.clone(),
+            );
+            self.cache_keyed_view(table_name, view, restored, start.elapsed())?;
+        }
+        self.cached_view(table_name)
+    }
+
+    /// Attaches the settings of the global keyed table `table_name` to a view restored from
+    /// it: its key groups, timestamp policy, changelog and quota, along with read metrics
+    /// for the `load_time` it took to read. `restored` is the number of entries the view was
+    /// restored with and their encoded size, as returned by [`encoded_size`]. The view is
+    /// then cached.
+    fn cache_keyed_view<T: KeyedTableView>(
+        &mut self,
+        table_name: &str,
+        mut view: T,
+        restored: (usize, usize),
+        load_time: Duration,
+    ) -> Result<()> {
+        let table = self.global_keyed_table(table_name)?;
+        let (key_groups, timestamp_policy) = (table.key_groups(), table.timestamp_policy());
+        let writer = view.writer();
+        if let Some(key_groups) = key_groups {
+            writer.set_key_groups(key_groups);
+        }
+        writer.set_timestamp_policy(timestamp_policy);
+        let reads = TableReads::new(&self.task_info, table_name);
+        reads.miss(load_time);
+        writer.set_reads(reads);
+        if let Some(changelog) = self.changelogs.remove(table_name) {
+            writer.set_changelog(changelog);
+        }
+        writer.set_size(
+            TableSize::new(&self.task_info, table_name, self.quota.clone()),
+            restored,
+        )?;
+        let cache: Box<dyn Any + Send> = Box::new(view);
+        self.caches.insert(table_name.to_string(), cache);
+        Ok(())
+    }
+
+    fn cached_view<T: Any>(&mut self, table_name: &str) -> Result<&mut T> {
+        let cache = self.caches.get_mut(table_name).unwrap();
+        cache.downcast_mut().ok_or_else(|| {
+            anyhow!(
+                "Failed to downcast table {} to {}",
+                table_name,
+                std::any::type_name::<T>()
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arroyo_rpc::grpc::TableCheckpointMetadata;
+
+    use super::*;
+    use crate::global_table_config;
+
+    #[test]
+    fn test_memory_backed_restore() {
+        let metadata = OperatorCheckpointMetadata {
+            backend: StateBackendKind::Memory.name().to_string(),
+            table_configs: global_table_config("t", "test"),
+            ..Default::default()
+        };
+        let restored = memory_backed_restore("op", metadata.clone()).unwrap();
+        assert!(restored.table_checkpoint_metadata.is_empty());
+        assert_eq!(restored.table_configs, metadata.table_configs);
+
+        // table data can't have come from the in-memory backend, so it isn't silently dropped
+        let metadata = OperatorCheckpointMetadata {
+            table_checkpoint_metadata: HashMap::from([(
+                "t".to_string(),
+                TableCheckpointMetadata {
+                    table_type: TableEnum::GlobalKeyValue.into(),
+                    data: vec![],
+                },
+            )]),
+            ..metadata
+        };
+        let err = memory_backed_restore("op", metadata).unwrap_err();
+        assert!(err.to_string().contains("data for tables t"), "{}", err);
+    }
 }
@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::SystemTime;
+
+use anyhow::Result;
+use arroyo_types::Key;
+
+use crate::changelog::ChangeKind;
+use crate::state_serde::StateCodec;
+use crate::tables::keyed_table_writer::{KeyedTableView, KeyedTableWriter};
+use crate::write_buffer::StateSender;
+
+/// Event-time timers for an operator, stored in a global keyed table. Timers fire once the
+/// watermark reaches their time.
+///
+/// Timers are rewritten in full on every call to [`TimerView::flush`], which operators
+/// should call from their checkpoint handler. Keys left without timers since the last flush
+/// are deleted from the table, so cancelled and fired timers are not restored, including
+/// from incremental tables that keep earlier epochs' entries.
+#[derive(Debug)]
+pub struct TimerView<K: Key> {
+    timers: BTreeMap<SystemTime, HashSet<K>>,
+    timers_by_key: HashMap<K, HashSet<SystemTime>>,
+    // keys whose last timer was cancelled or fired since the last flush
+    emptied: HashSet<K>,
+    writer: KeyedTableWriter,
+}
+
+impl<K: Key> TimerView<K> {
+    pub(crate) fn new(
+        table_name: String,
+        persisted: HashMap<K, Vec<SystemTime>>,
+        codec: StateCodec,
+        state_tx: StateSender,
+    ) -> Self {
+        let mut view = Self {
+            timers: BTreeMap::new(),
+            timers_by_key: HashMap::new(),
+            emptied: HashSet::new(),
+            writer: KeyedTableWriter::new(table_name, codec, state_tx),
+        };
+        for (key, times) in persisted {
+            for time in times {
+                view.add(key.clone(), time);
+            }
+        }
+        view
+    }
+
+    /// Registers a timer for `key` at `time`. Registering the same key and time twice is a
+    /// no-op. Fails if `time` is outside the range state can store, unless the table clamps
+    /// it; see [`crate::timestamps::check_timestamp`]. Also fails, without registering the
+    /// timer, if the table's changelog is full or the timer would put the subtask over its
+    /// state quota.
+    pub fn register(&mut self, key: K, time: SystemTime) -> Result<()> {
+        let time = self.writer.check_timestamp(time)?;
+        if self
+            .timers_by_key
+            .get(&key)
+            .is_some_and(|times| times.contains(&time))
+        {
+            return Ok(());
+        }
+        self.writer.insert(&key, &time, None, Some(time))?;
+        self.add(key, time);
+        Ok(())
+    }
+
+    fn add(&mut self, key: K, time: SystemTime) {
+        self.emptied.remove(&key);
+        self.timers_by_key
+            .entry(key.clone())
+            .or_default()
+            .insert(time);
+        self.timers.entry(time).or_default().insert(key);
+    }
+
+    /// Cancels the timer for `key` at `time`, returning whether it was registered.
+    pub fn cancel(&mut self, key: &K, time: SystemTime) -> bool {
+        let Some(times) = self.timers_by_key.get_mut(key) else {
+            return false;
+        };
+        if !times.remove(&time) {
+            return false;
+        }
+        self.writer.remove(ChangeKind::Delete, key, Some(time));
+        if times.is_empty() {
+            self.timers_by_key.remove(key);
+            self.emptied.insert(key.clone());
+        }
+        if let Some(keys) = self.timers.get_mut(&time) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.timers.remove(&time);
+            }
+        }
+        true
+    }
+
+    /// Removes and returns all timers at or before `watermark`, in time order.
+    pub fn poll_expired(&mut self, watermark: SystemTime) -> Vec<(SystemTime, K)> {
+        let mut expired = vec![];
+        while let Some(entry) = self.timers.first_entry() {
+            if *entry.key() > watermark {
+                break;
+            }
+            let (time, keys) = entry.remove_entry();
+            for key in keys {
+                self.writer.remove(ChangeKind::Expire, &key, Some(time));
+                if let Some(times) = self.timers_by_key.get_mut(&key) {
+                    times.remove(&time);
+                    if times.is_empty() {
+                        self.timers_by_key.remove(&key);
+                        self.emptied.insert(key.clone());
+                    }
+                }
+                expired.push((time, key));
+            }
+        }
+        expired
+    }
+
+    /// The earliest registered timer.
+    pub fn next_fire_time(&self) -> Option<SystemTime> {
+        self.timers.keys().next().copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Waits until the changelog has delivered the events held for synchronous mutations.
+    pub async fn wait_for_changelog(&mut self) {
+        self.writer.wait_for_changelog().await;
+    }
+
+    /// Writes every registered timer to the table, and deletes the keys left without any.
+    pub async fn flush(&mut self) -> Result<()> {
+        for key in std::mem::take(&mut self.emptied) {
+            self.writer.write_delete(&key, &key).await?;
+        }
+        for (key, times) in &self.timers_by_key {
+            let times: Vec<_> = times.iter().collect();
+            self.writer.write(key, key, &times).await?;
+        }
+        self.writer.finish_flush().await;
+        Ok(())
+    }
+}
+
+impl<K: Key> KeyedTableView for TimerView<K> {
+    fn writer(&mut self) -> &mut KeyedTableWriter {
+        &mut self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use arroyo_rpc::grpc::GlobalKeyedTableConfig;
+    use arroyo_types::TaskInfo;
+    use tokio::sync::mpsc::channel;
+
+    use super::*;
+    use crate::tables::global_keyed_map::GlobalKeyedTable;
+    use crate::tables::StateFileLayout;
+    use crate::test_storage::{checkpoint_keyed_table, TempStorage};
+
+    #[tokio::test]
+    async fn test_cancelled_timers_are_not_restored() {
+        for incremental in [false, true] {
+            let temp_storage = TempStorage::new("timer-tests").await;
+            let task_info = Arc::new(TaskInfo::for_test("job", "timers"));
+            let config = GlobalKeyedTableConfig {
+                table_name: "t".to_string(),
+                description: "t".to_string(),
+                incremental,
+                ..Default::default()
+            };
+            let table = |checkpoint| {
+                GlobalKeyedTable::from_config(
+                    config.clone(),
+                    StateFileLayout::default(),
+                    StateCodec::default(),
+                    0,
+                    task_info.clone(),
+                    temp_storage.provider(),
+                    checkpoint,
+                )
+                .unwrap()
+            };
+            let restore = |table: GlobalKeyedTable| async move {
+                let (tx, _rx) = channel(100);
+                TimerView::<String>::new(
+                    "t".to_string(),
+                    table.read_all().await.unwrap(),
+                    StateCodec::default(),
+                    StateSender::unbuffered(tx),
+                )
+            };
+            let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+            let (a, b, c) = ("a".to_string(), "b".to_string(), "c".to_string());
+
+            let (tx, mut rx) = channel(100);
+            let mut view: TimerView<String> = TimerView::new(
+                "t".to_string(),
+                HashMap::new(),
+                StateCodec::default(),
+                StateSender::unbuffered(tx),
+            );
+            view.register(a.clone(), at(1)).unwrap();
+            view.register(a.clone(), at(2)).unwrap();
+            view.register(b.clone(), at(3)).unwrap();
+            view.register(c.clone(), at(4)).unwrap();
+            view.flush().await.unwrap();
+            let (subtask_metadata, _) =
+                checkpoint_keyed_table(&table(None), &config, 1, None, &mut rx).await;
+
+            // cancel one of a's timers and all of c's, then fire b's
+            assert!(view.cancel(&a, at(2)));
+            assert!(view.cancel(&c, at(4)));
+            assert!(!view.cancel(&c, at(4)));
+            assert_eq!(
+                view.poll_expired(at(3)),
+                vec![(at(1), a.clone()), (at(3), b)]
+            );
+            view.register(a.clone(), at(5)).unwrap();
+            view.flush().await.unwrap();
+            let (_, checkpoint) =
+                checkpoint_keyed_table(&table(None), &config, 2, Some(subtask_metadata), &mut rx)
+                    .await;
+
+            let mut restored = restore(table(checkpoint)).await;
+            assert_eq!(
+                restored.poll_expired(at(10)),
+                vec![(at(5), a)],
+                "incremental: {}",
+                incremental
+            );
+            assert!(restored.is_empty());
+        }
+    }
+}
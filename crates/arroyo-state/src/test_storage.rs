@@ -0,0 +1,89 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use arroyo_rpc::grpc::{
+    GlobalKeyedTableConfig, GlobalKeyedTableSubtaskCheckpointMetadata,
+    GlobalKeyedTableTaskCheckpointMetadata,
+};
+use arroyo_storage::{StorageProvider, StorageProviderRef};
+use arroyo_types::to_nanos;
+use tokio::sync::mpsc::Receiver;
+
+use crate::tables::global_keyed_map::GlobalKeyedTable;
+use crate::tables::{Table, TableEpochCheckpointer};
+use crate::{CheckpointMessage, StateMessage};
+
+/// Checkpoint storage in a fresh temporary directory, for tests that write state files and
+/// restore from them. The directory is removed when this is dropped.
+///
+/// [`crate::in_memory::InMemoryBackingStore`] holds only metadata, so tests of table data
+/// need real storage.
+pub(crate) struct TempStorage {
+    root: PathBuf,
+    provider: StorageProviderRef,
+}
+
+impl TempStorage {
+    /// Storage in a new directory under `name` in the system's temporary directory.
+    pub(crate) async fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "arroyo-state-{}/{}",
+            name,
+            to_nanos(SystemTime::now())
+        ));
+        let provider = Arc::new(
+            StorageProvider::for_url(&format!("file://{}", root.to_str().unwrap()))
+                .await
+                .unwrap(),
+        );
+        Self { root, provider }
+    }
+
+    pub(crate) fn provider(&self) -> StorageProviderRef {
+        self.provider.clone()
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl Drop for TempStorage {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Writes the table data waiting in `rx` as checkpoint `epoch` of `table`, which follows the
+/// subtask's checkpoint `previous`. Returns the subtask's checkpoint, to pass to the next
+/// epoch, and the table's, to restore from.
+pub(crate) async fn checkpoint_keyed_table(
+    table: &GlobalKeyedTable,
+    config: &GlobalKeyedTableConfig,
+    epoch: u32,
+    previous: Option<GlobalKeyedTableSubtaskCheckpointMetadata>,
+    rx: &mut Receiver<StateMessage>,
+) -> (
+    GlobalKeyedTableSubtaskCheckpointMetadata,
+    Option<GlobalKeyedTableTaskCheckpointMetadata>,
+) {
+    let mut checkpointer = table.epoch_checkpointer(epoch, previous).unwrap();
+    while let Ok(StateMessage::TableData { data, .. }) = rx.try_recv() {
+        checkpointer.insert_data(data).await.unwrap();
+    }
+    let checkpoint = CheckpointMessage {
+        epoch,
+        time: SystemTime::now(),
+        watermark: None,
+        then_stop: false,
+        in_flight: false,
+    };
+    let (subtask_metadata, _) = checkpointer.finish(&checkpoint).await.unwrap().unwrap();
+    let table_metadata = GlobalKeyedTable::merge_checkpoint_metadata(
+        config.clone(),
+        [(0, subtask_metadata.clone())].into(),
+    )
+    .unwrap();
+    (subtask_metadata, table_metadata)
+}
@@ -0,0 +1,233 @@
+use std::fmt::{Display, Formatter};
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Result};
+use arrow::compute::kernels::aggregate;
+use arrow_array::cast::AsArray;
+use arrow_array::types::TimestampNanosecondType;
+use arrow_array::{ArrayRef, RecordBatch};
+use arroyo_rpc::grpc::TableTimestampPolicy;
+use arroyo_types::print_time;
+
+/// The latest timestamp the state layer stores: the last that's a whole number of
+/// nanoseconds since the UNIX epoch in an `i64`, as the timestamps of time-keyed tables are,
+/// in the year 2262. The earliest is the epoch itself.
+pub fn max_state_timestamp() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_nanos(i64::MAX as u64)
+}
+
+/// A timestamp outside the range the state layer stores, inserted into a table that
+/// rejects them.
+#[derive(Debug, Clone)]
+pub struct InvalidStateTimestamp {
+    pub table_name: String,
+    pub timestamp: SystemTime,
+}
+
+impl Display for InvalidStateTimestamp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "timestamp {} inserted into table {} is outside the range that can be stored, {} to {}",
+            print_time(self.timestamp),
+            self.table_name,
+            print_time(SystemTime::UNIX_EPOCH),
+            print_time(max_state_timestamp())
+        )
+    }
+}
+
+impl std::error::Error for InvalidStateTimestamp {}
+
+/// Checks that `timestamp`, inserted into `table_name`, is between the UNIX epoch and
+/// [`max_state_timestamp`]. One outside that range fails the insert or, for tables with
+/// [`TableTimestampPolicy::Clamp`], is replaced by the nearest timestamp in it.
+pub(crate) fn check_timestamp(
+    table_name: &str,
+    policy: TableTimestampPolicy,
+    timestamp: SystemTime,
+) -> Result<SystemTime, InvalidStateTimestamp> {
+    let clamped = timestamp.clamp(SystemTime::UNIX_EPOCH, max_state_timestamp());
+    if clamped != timestamp && policy == TableTimestampPolicy::Reject {
+        return Err(InvalidStateTimestamp {
+            table_name: table_name.to_string(),
+            timestamp,
+        });
+    }
+    Ok(clamped)
+}
+
+/// Like [`check_timestamp`], for the nanosecond timestamps in column `timestamp_index` of a
+/// batch. They can't be past [`max_state_timestamp`], but can be before the epoch.
+pub(crate) fn check_timestamp_column(
+    table_name: &str,
+    policy: TableTimestampPolicy,
+    batch: RecordBatch,
+    timestamp_index: usize,
+) -> Result<RecordBatch> {
+    let timestamps = batch
+        .column(timestamp_index)
+        .as_primitive_opt::<TimestampNanosecondType>()
+        .ok_or_else(|| anyhow!("failed to find timestamp column"))?;
+    match aggregate::min(timestamps) {
+        Some(earliest) if earliest < 0 => {
+            if policy == TableTimestampPolicy::Reject {
+                return Err(InvalidStateTimestamp {
+                    table_name: table_name.to_string(),
+                    timestamp: SystemTime::UNIX_EPOCH
+                        - Duration::from_nanos(earliest.unsigned_abs()),
+                }
+                .into());
+            }
+        }
+        _ => return Ok(batch),
+    }
+    let clamped: ArrayRef = Arc::new(
+        timestamps
+            .unary::<_, TimestampNanosecondType>(|timestamp| timestamp.max(0))
+            .with_timezone_opt(timestamps.timezone()),
+    );
+    let mut columns = batch.columns().to_vec();
+    columns[timestamp_index] = clamped;
+    Ok(RecordBatch::try_new(batch.schema(), columns)?)
+}
+
+/// The time of a stored nanosecond timestamp. Timestamps before the epoch, which are only
+/// stored by versions that didn't check them, are read as the epoch.
+pub(crate) fn from_timestamp_nanos(nanos: i64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos.max(0) as u64)
+}
+
+/// Microseconds since the epoch of `time`, clamped to the range the state layer stores, for
+/// the timestamps recorded in checkpoint metadata. Unlike [`arroyo_types::to_micros`], a
+/// watermark or cutoff outside the range neither panics nor wraps around.
+pub(crate) fn to_state_micros(time: SystemTime) -> u64 {
+    time.clamp(SystemTime::UNIX_EPOCH, max_state_timestamp())
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// The bounds of `range`, for looking it up in a `BTreeMap`. A range with no times, as when
+/// its start is after its end, is replaced by an empty one that a `BTreeMap` returns nothing
+/// for, rather than panicking.
+pub(crate) fn time_range_bounds<R: RangeBounds<SystemTime>>(
+    range: &R,
+) -> (Bound<SystemTime>, Bound<SystemTime>) {
+    let empty = match (range.start_bound(), range.end_bound()) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end))
+        | (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        _ => false,
+    };
+    if empty {
+        (
+            Bound::Included(SystemTime::UNIX_EPOCH),
+            Bound::Excluded(SystemTime::UNIX_EPOCH),
+        )
+    } else {
+        (range.start_bound().cloned(), range.end_bound().cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use arrow_array::TimestampNanosecondArray;
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+
+    use super::*;
+
+    #[test]
+    fn test_check_timestamp() {
+        let before_epoch = SystemTime::UNIX_EPOCH - Duration::from_secs(1);
+        let far_future = SystemTime::now() + Duration::from_secs(10_000 * 365 * 24 * 60 * 60);
+        let now = SystemTime::now();
+
+        for timestamp in [before_epoch, far_future] {
+            let err = check_timestamp("t", TableTimestampPolicy::Reject, timestamp).unwrap_err();
+            assert_eq!(err.timestamp, timestamp);
+            assert!(err.to_string().contains("table t"), "{}", err);
+        }
+        assert_eq!(
+            check_timestamp("t", TableTimestampPolicy::Clamp, before_epoch).unwrap(),
+            SystemTime::UNIX_EPOCH
+        );
+        assert_eq!(
+            check_timestamp("t", TableTimestampPolicy::Clamp, far_future).unwrap(),
+            max_state_timestamp()
+        );
+        for policy in [TableTimestampPolicy::Reject, TableTimestampPolicy::Clamp] {
+            assert_eq!(check_timestamp("t", policy, now).unwrap(), now);
+        }
+    }
+
+    #[test]
+    fn test_check_timestamp_column() {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Nanosecond, None),
+            true,
+        )]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![Arc::new(TimestampNanosecondArray::from(vec![
+                Some(-1_000_000_000),
+                None,
+                Some(5),
+            ]))],
+        )
+        .unwrap();
+
+        let err = check_timestamp_column("t", TableTimestampPolicy::Reject, batch.clone(), 0)
+            .unwrap_err()
+            .downcast::<InvalidStateTimestamp>()
+            .unwrap();
+        assert_eq!(
+            err.timestamp,
+            SystemTime::UNIX_EPOCH - Duration::from_secs(1)
+        );
+
+        let clamped = check_timestamp_column("t", TableTimestampPolicy::Clamp, batch, 0).unwrap();
+        let timestamps = clamped.column(0).as_primitive::<TimestampNanosecondType>();
+        assert_eq!(
+            timestamps.iter().collect::<Vec<_>>(),
+            vec![Some(0), None, Some(5)]
+        );
+        assert_eq!(from_timestamp_nanos(-1), SystemTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_to_state_micros() {
+        let before_epoch = SystemTime::UNIX_EPOCH - Duration::from_secs(1);
+        let far_future = SystemTime::UNIX_EPOCH + Duration::from_secs(u64::MAX / 2);
+        let now = SystemTime::now();
+
+        assert_eq!(to_state_micros(before_epoch), 0);
+        assert_eq!(to_state_micros(far_future), (i64::MAX / 1_000) as u64);
+        assert_eq!(to_state_micros(now), arroyo_types::to_micros(now));
+    }
+
+    #[test]
+    fn test_time_range_bounds() {
+        let (a, b) = (
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(2),
+        );
+        let map = BTreeMap::from([(a, 1), (b, 2)]);
+        let lookup = |bounds: (Bound<SystemTime>, Bound<SystemTime>)| {
+            map.range(bounds).map(|(_, v)| *v).collect::<Vec<_>>()
+        };
+        assert_eq!(lookup(time_range_bounds(&(a..b))), vec![1]);
+        assert_eq!(lookup(time_range_bounds(&(a..=a))), vec![1]);
+        assert_eq!(lookup(time_range_bounds(&(..))), vec![1, 2]);
+        // inverted ranges, which BTreeMap::range panics on
+        assert!(lookup(time_range_bounds(&(b..a))).is_empty());
+        assert!(lookup(time_range_bounds(&(b..=a))).is_empty());
+        assert!(lookup(time_range_bounds(&(Bound::Excluded(a), Bound::Excluded(a)))).is_empty());
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Bound;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use arroyo_types::{u32_config, TaskInfo};
+use once_cell::sync::Lazy;
+use tokio::sync::oneshot;
+
+use crate::metrics::{UPLOAD_BYTES_COUNTER, UPLOAD_QUEUE_DEPTH_GAUGE, UPLOAD_THROUGHPUT_GAUGE};
+use crate::store_writes::{StoreWrite, WriteOperation};
+
+pub const MAX_CONCURRENT_UPLOADS_ENV: &str = "STATE_MAX_CONCURRENT_UPLOADS";
+pub const MAX_UPLOAD_BYTES_PER_SECOND_ENV: &str = "STATE_MAX_UPLOAD_BYTES_PER_SECOND";
+
+/// The scheduler shared by every table on this worker.
+pub(crate) static UPLOAD_SCHEDULER: Lazy<UploadScheduler> =
+    Lazy::new(|| UploadScheduler::new(UploadSchedulerConfig::from_env()));
+
+#[derive(Debug, Clone, Copy)]
+pub struct UploadSchedulerConfig {
+    pub max_concurrent_uploads: usize,
+    /// Unset (or 0 in the environment) means uploads aren't rate limited.
+    pub max_bytes_per_second: Option<u64>,
+}
+
+impl UploadSchedulerConfig {
+    pub fn from_env() -> Self {
+        let max_bytes_per_second = u32_config(MAX_UPLOAD_BYTES_PER_SECOND_ENV, 0) as u64;
+        Self {
+            max_concurrent_uploads: u32_config(MAX_CONCURRENT_UPLOADS_ENV, 4).max(1) as usize,
+            max_bytes_per_second: (max_bytes_per_second > 0).then_some(max_bytes_per_second),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    running: usize,
+    // waiters for each subtask, served round-robin so that a subtask with many files
+    // can't starve the others
+    waiting: BTreeMap<String, VecDeque<oneshot::Sender<()>>>,
+    last_served: Option<String>,
+    // when the bandwidth already handed out will have been used up
+    bandwidth_free_at: Option<Instant>,
+}
+
+impl SchedulerState {
+    fn queue_depth(&self) -> usize {
+        self.waiting.values().map(|q| q.len()).sum()
+    }
+
+    fn next_waiter(&mut self) -> Option<oneshot::Sender<()>> {
+        loop {
+            let subtask = match &self.last_served {
+                Some(last) => self
+                    .waiting
+                    .range::<String, _>((Bound::Excluded(last), Bound::Unbounded))
+                    .next()
+                    .or_else(|| self.waiting.iter().next()),
+                None => self.waiting.iter().next(),
+            }
+            .map(|(subtask, _)| subtask.clone())?;
+
+            let queue = self.waiting.get_mut(&subtask).unwrap();
+            let waiter = queue.pop_front();
+            if queue.is_empty() {
+                self.waiting.remove(&subtask);
+            }
+            self.last_served = Some(subtask);
+            if waiter.is_some() {
+                return waiter;
+            }
+        }
+    }
+}
+
+/// Bounds the number of concurrent checkpoint uploads on a worker and the bandwidth they
+/// use in aggregate.
+pub struct UploadScheduler {
+    config: UploadSchedulerConfig,
+    state: Mutex<SchedulerState>,
+}
+
+impl UploadScheduler {
+    pub fn new(config: UploadSchedulerConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(SchedulerState::default()),
+        }
+    }
+
+    /// Waits for an upload slot for the subtask. The slot is held until the permit is
+    /// dropped.
+    pub async fn acquire(&self, task_info: &TaskInfo) -> UploadPermit<'_> {
+        let subtask = format!("{}-{}", task_info.operator_id, task_info.task_index);
+        let rx = {
+            let mut state = self.state.lock().unwrap();
+            if state.running < self.config.max_concurrent_uploads && state.waiting.is_empty() {
+                state.running += 1;
+                None
+            } else {
+                let (tx, rx) = oneshot::channel();
+                state.waiting.entry(subtask).or_default().push_back(tx);
+                UPLOAD_QUEUE_DEPTH_GAUGE.set(state.queue_depth() as i64);
+                Some(rx)
+            }
+        };
+        // slots are handed over directly by the releasing permit, so `running` is unchanged
+        if let Some(rx) = rx {
+            let mut waiter = QueuedWaiter {
+                scheduler: self,
+                rx,
+                granted: false,
+            };
+            (&mut waiter.rx)
+                .await
+                .expect("upload scheduler should not drop waiters");
+            waiter.granted = true;
+        }
+
+        // create the permit first so the slot is released if we're cancelled while waiting
+        let mut permit = UploadPermit {
+            scheduler: self,
+            started: Instant::now(),
+            write: None,
+        };
+        let free_at = self.state.lock().unwrap().bandwidth_free_at;
+        if let Some(free_at) = free_at {
+            tokio::time::sleep_until(free_at.into()).await;
+            permit.started = Instant::now();
+        }
+        permit.write = Some(StoreWrite::start(WriteOperation::StateFile));
+        permit
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match state.next_waiter() {
+                // a send fails if the waiter was cancelled, in which case we try the next one
+                Some(waiter) => {
+                    if waiter.send(()).is_ok() {
+                        break;
+                    }
+                }
+                None => {
+                    state.running -= 1;
+                    break;
+                }
+            }
+        }
+        UPLOAD_QUEUE_DEPTH_GAUGE.set(state.queue_depth() as i64);
+    }
+
+    fn record(&self, bytes: u64, elapsed: Duration) {
+        UPLOAD_BYTES_COUNTER.inc_by(bytes);
+        if !elapsed.is_zero() {
+            UPLOAD_THROUGHPUT_GAUGE.set(bytes as f64 / elapsed.as_secs_f64());
+        }
+        if let Some(rate) = self.config.max_bytes_per_second {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let start = state.bandwidth_free_at.unwrap_or(now).max(now);
+            state.bandwidth_free_at =
+                Some(start + Duration::from_secs_f64(bytes as f64 / rate as f64));
+        }
+    }
+}
+
+/// Hands the slot on if the waiting future is dropped after being granted one.
+struct QueuedWaiter<'a> {
+    scheduler: &'a UploadScheduler,
+    rx: oneshot::Receiver<()>,
+    granted: bool,
+}
+
+impl Drop for QueuedWaiter<'_> {
+    fn drop(&mut self) {
+        if !self.granted {
+            self.rx.close();
+            if self.rx.try_recv().is_ok() {
+                self.scheduler.release();
+            }
+        }
+    }
+}
+
+/// A held upload slot. Call [`UploadPermit::complete`] once the upload has finished so
+/// the bytes count against the worker's bandwidth limit; a permit dropped without being
+/// completed counts as an abandoned state write.
+pub struct UploadPermit<'a> {
+    scheduler: &'a UploadScheduler,
+    started: Instant,
+    write: Option<StoreWrite>,
+}
+
+impl UploadPermit<'_> {
+    pub fn complete(mut self, bytes: u64) {
+        self.scheduler.record(bytes, self.started.elapsed());
+        if let Some(write) = &mut self.write {
+            write.succeeded();
+        }
+    }
+}
+
+impl Drop for UploadPermit<'_> {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
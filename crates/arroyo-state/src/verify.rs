@@ -0,0 +1,415 @@
+//! Verification that a checkpoint could be restored, without restoring it.
+//!
+//! Every operator the checkpoint lists must have operator metadata, and every file that
+//! metadata references must exist, have the size it was recorded with, and decode. No
+//! checksums are recorded when files are written, so their contents are checked by decoding
+//! them: Parquet validates the structure and compression of every page it reads. Keys and
+//! values are only decoded as far as Parquet columns, as decoding them further needs the
+//! operator's types.
+//!
+//! Nothing is written. Files are read one at a time per unit of parallelism, and dropped once
+//! they've been decoded, so memory use is bounded by the parallelism and the largest files.
+
+use std::collections::HashSet;
+
+use arroyo_rpc::grpc::OperatorCheckpointMetadata;
+use arroyo_storage::StorageProvider;
+use arroyo_types::u32_config;
+use futures::StreamExt;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use tracing::{info, warn};
+
+use crate::encryption::decrypt;
+use crate::parquet::{
+    base_path, load_checkpoint_metadata_in, load_operator_metadata_in, metadata_path,
+    operator_path, operator_retained_files,
+};
+
+/// How many metadata objects and files a verification reads at once.
+pub const CHECKPOINT_VERIFY_PARALLELISM_ENV: &str = "CHECKPOINT_VERIFY_PARALLELISM";
+
+pub(crate) fn verify_parallelism() -> usize {
+    u32_config(CHECKPOINT_VERIFY_PARALLELISM_ENV, 8).max(1) as usize
+}
+
+/// What was wrong with a metadata object or file of a checkpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// Metadata that the checkpoint needs couldn't be found, read, or decoded.
+    MissingMetadata { reason: String },
+    /// A referenced file doesn't exist.
+    MissingFile,
+    /// A referenced file's size differs from the size its checkpoint recorded.
+    SizeMismatch { expected: u64, actual: u64 },
+    /// A referenced file exists, but reading it from the store failed.
+    ReadFailure { reason: String },
+    /// A referenced file was read, but its contents couldn't be decoded.
+    DecodeFailure { reason: String },
+}
+
+/// A problem found by a verification, and where it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerificationProblem {
+    /// The operator whose metadata or file has the problem; unset for the checkpoint's own
+    /// metadata.
+    pub operator_id: Option<String>,
+    /// The table the file belongs to; unset for metadata, and for files of in-flight records.
+    pub table: Option<String>,
+    pub path: String,
+    pub kind: ProblemKind,
+}
+
+/// The result of verifying a checkpoint.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckpointVerification {
+    pub job_id: String,
+    pub epoch: u32,
+    /// Operators whose metadata was read.
+    pub operators: u64,
+    /// Files that were checked.
+    pub files: u64,
+    /// Bytes of the files that were read, as stored.
+    pub bytes: u64,
+    /// Every problem found, ordered by path.
+    pub problems: Vec<VerificationProblem>,
+}
+
+impl CheckpointVerification {
+    /// Whether the checkpoint could be restored, as far as verification can tell.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// A file referenced by an operator's checkpoint.
+struct ReferencedFile {
+    operator_id: String,
+    table: Option<String>,
+    path: String,
+    // unset for files whose size wasn't recorded
+    expected_bytes: Option<u64>,
+}
+
+impl ReferencedFile {
+    fn problem(&self, kind: ProblemKind) -> VerificationProblem {
+        VerificationProblem {
+            operator_id: Some(self.operator_id.clone()),
+            table: self.table.clone(),
+            path: self.path.clone(),
+            kind,
+        }
+    }
+}
+
+/// Every file an operator's checkpoint references, by table and then in-flight records.
+fn referenced_files(
+    operator_id: &str,
+    metadata: &OperatorCheckpointMetadata,
+) -> anyhow::Result<Vec<ReferencedFile>> {
+    let mut seen = HashSet::new();
+    let mut files = vec![];
+    for (table, retained) in operator_retained_files(metadata)? {
+        for file in retained {
+            if seen.insert(file.path.clone()) {
+                files.push(ReferencedFile {
+                    operator_id: operator_id.to_string(),
+                    table: Some(table.clone()),
+                    path: file.path,
+                    expected_bytes: file.bytes,
+                });
+            }
+        }
+    }
+    for subtask in metadata.in_flight.values() {
+        for file in &subtask.files {
+            if seen.insert(file.file.clone()) {
+                // the recorded bytes are those of the records, not of the file
+                files.push(ReferencedFile {
+                    operator_id: operator_id.to_string(),
+                    table: None,
+                    path: file.file.clone(),
+                    expected_bytes: None,
+                });
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Reads and decodes a file, returning its size as stored and any problems with it.
+async fn verify_file(
+    storage: &StorageProvider,
+    file: &ReferencedFile,
+) -> (u64, Vec<VerificationProblem>) {
+    let data = match storage.get_if_present(file.path.clone()).await {
+        Ok(Some(data)) => data,
+        Ok(None) => return (0, vec![file.problem(ProblemKind::MissingFile)]),
+        Err(e) => {
+            return (
+                0,
+                vec![file.problem(ProblemKind::ReadFailure {
+                    reason: e.to_string(),
+                })],
+            )
+        }
+    };
+    let actual = data.len() as u64;
+    let mut problems = vec![];
+    if let Some(expected) = file.expected_bytes {
+        if expected != actual {
+            problems.push(file.problem(ProblemKind::SizeMismatch { expected, actual }));
+        }
+    }
+    let decoded = decrypt(&file.path, data).and_then(|data| -> anyhow::Result<()> {
+        for batch in ParquetRecordBatchReaderBuilder::try_new(data)?.build()? {
+            batch?;
+        }
+        Ok(())
+    });
+    if let Err(e) = decoded {
+        problems.push(file.problem(ProblemKind::DecodeFailure {
+            reason: format!("{:#}", e),
+        }));
+    }
+    (actual, problems)
+}
+
+/// Verifies a job's checkpoint in `storage`, reading up to `parallelism` metadata objects or
+/// files at once.
+pub(crate) async fn verify_checkpoint_in(
+    storage: &StorageProvider,
+    job_id: &str,
+    epoch: u32,
+    parallelism: usize,
+) -> CheckpointVerification {
+    let mut verification = CheckpointVerification {
+        job_id: job_id.to_string(),
+        epoch,
+        ..Default::default()
+    };
+    let checkpoint = match load_checkpoint_metadata_in(storage, job_id, epoch).await {
+        Ok(checkpoint) => checkpoint,
+        Err(e) => {
+            verification.problems.push(VerificationProblem {
+                operator_id: None,
+                table: None,
+                path: metadata_path(&base_path(job_id, epoch)),
+                kind: ProblemKind::MissingMetadata {
+                    reason: format!("{:#}", e),
+                },
+            });
+            return verification;
+        }
+    };
+    let parallelism = parallelism.max(1);
+
+    // stateless operators write no metadata of their own
+    let mut operators = futures::stream::iter(
+        checkpoint
+            .operator_ids
+            .iter()
+            .filter(|operator_id| !checkpoint.stateless_operators.contains_key(*operator_id)),
+    )
+    .map(|operator_id| async move {
+        let metadata = load_operator_metadata_in(storage, job_id, operator_id, epoch).await;
+        (operator_id, metadata)
+    })
+    .buffer_unordered(parallelism);
+    let mut files = vec![];
+    while let Some((operator_id, metadata)) = operators.next().await {
+        let path = metadata_path(&operator_path(job_id, epoch, operator_id));
+        let missing = |reason: String| VerificationProblem {
+            operator_id: Some(operator_id.clone()),
+            table: None,
+            path: path.clone(),
+            kind: ProblemKind::MissingMetadata { reason },
+        };
+        let metadata = match metadata {
+            Ok(Some(metadata)) => metadata,
+            Ok(None) => {
+                verification
+                    .problems
+                    .push(missing("no operator metadata was written".to_string()));
+                continue;
+            }
+            Err(e) => {
+                verification.problems.push(missing(format!("{:#}", e)));
+                continue;
+            }
+        };
+        verification.operators += 1;
+        match referenced_files(operator_id, &metadata) {
+            Ok(referenced) => files.extend(referenced),
+            Err(e) => verification.problems.push(missing(format!("{:#}", e))),
+        }
+    }
+
+    let mut verified = futures::stream::iter(&files)
+        .map(|file| verify_file(storage, file))
+        .buffer_unordered(parallelism);
+    while let Some((bytes, problems)) = verified.next().await {
+        verification.files += 1;
+        verification.bytes += bytes;
+        verification.problems.extend(problems);
+    }
+    verification.problems.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if verification.is_ok() {
+        info!(
+            message = "Verified checkpoint",
+            job_id,
+            epoch,
+            operators = verification.operators,
+            files = verification.files,
+            bytes = verification.bytes
+        );
+    } else {
+        warn!(
+            message = "Checkpoint failed verification",
+            job_id,
+            epoch,
+            problems = verification.problems.len()
+        );
+    }
+    verification
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch};
+    use arroyo_rpc::grpc::{
+        CheckpointMetadata, GlobalKeyedTableTaskCheckpointMetadata, OperatorMetadata,
+        TableCheckpointMetadata, TableEnum,
+    };
+    use parquet::arrow::ArrowWriter;
+    use prost::Message;
+
+    use super::*;
+    use crate::global_table_config;
+    use crate::test_storage::TempStorage;
+
+    fn parquet_file() -> Vec<u8> {
+        let batch =
+            RecordBatch::try_from_iter([("value", Arc::new(Int64Array::from(vec![1, 2, 3])) as _)])
+                .unwrap();
+        let mut writer = ArrowWriter::try_new(vec![], batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify_checkpoint() {
+        let temp_storage = TempStorage::new("verify-tests").await;
+        let storage = temp_storage.provider();
+        let job_id = "verified";
+        let valid = parquet_file();
+        let size = valid.len() as u64;
+        storage.put("good", valid.clone()).await.unwrap();
+        storage.put("resized", valid).await.unwrap();
+        storage
+            .put("corrupt", b"not parquet".to_vec())
+            .await
+            .unwrap();
+
+        let files = ["good", "resized", "corrupt", "missing"];
+        storage
+            .put(
+                metadata_path(&operator_path(job_id, 1, "op")),
+                OperatorCheckpointMetadata {
+                    operator_metadata: Some(OperatorMetadata {
+                        job_id: job_id.to_string(),
+                        operator_id: "op".to_string(),
+                        epoch: 1,
+                        parallelism: 1,
+                        ..Default::default()
+                    }),
+                    table_configs: global_table_config("t", "test"),
+                    table_checkpoint_metadata: HashMap::from([(
+                        "t".to_string(),
+                        TableCheckpointMetadata {
+                            table_type: TableEnum::GlobalKeyValue.into(),
+                            data: GlobalKeyedTableTaskCheckpointMetadata {
+                                files: files.iter().map(|file| file.to_string()).collect(),
+                                file_sizes: HashMap::from([
+                                    ("good".to_string(), size),
+                                    ("resized".to_string(), size + 1),
+                                ]),
+                                ..Default::default()
+                            }
+                            .encode_to_vec(),
+                        },
+                    )]),
+                    ..Default::default()
+                }
+                .encode_to_vec(),
+            )
+            .await
+            .unwrap();
+        storage
+            .put(
+                metadata_path(&base_path(job_id, 1)),
+                CheckpointMetadata {
+                    job_id: job_id.to_string(),
+                    epoch: 1,
+                    min_epoch: 1,
+                    operator_ids: vec![
+                        "op".to_string(),
+                        "lost".to_string(),
+                        "stateless".to_string(),
+                    ],
+                    stateless_operators: HashMap::from([(
+                        "stateless".to_string(),
+                        OperatorMetadata::default(),
+                    )]),
+                    ..Default::default()
+                }
+                .encode_to_vec(),
+            )
+            .await
+            .unwrap();
+
+        let verification = verify_checkpoint_in(&storage, job_id, 1, 2).await;
+        assert!(!verification.is_ok());
+        assert_eq!(verification.operators, 1);
+        assert_eq!(verification.files, 4);
+        // every problem is reported, not just the first
+        let problems: Vec<_> = verification
+            .problems
+            .iter()
+            .map(|problem| (problem.path.as_str(), &problem.kind))
+            .collect();
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert!(matches!(
+            problems[0],
+            ("corrupt", ProblemKind::DecodeFailure { .. })
+        ));
+        assert_eq!(problems[1], ("missing", &ProblemKind::MissingFile));
+        assert_eq!(
+            problems[2],
+            (
+                "resized",
+                &ProblemKind::SizeMismatch {
+                    expected: size + 1,
+                    actual: size
+                }
+            )
+        );
+        assert_eq!(
+            problems[3].0,
+            metadata_path(&operator_path(job_id, 1, "lost"))
+        );
+        assert!(matches!(problems[3].1, ProblemKind::MissingMetadata { .. }));
+        assert_eq!(
+            verification.problems[0].table.as_deref(),
+            Some("t"),
+            "file problems name their table"
+        );
+
+        let missing = verify_checkpoint_in(&storage, job_id, 2, 2).await;
+        assert_eq!(missing.problems.len(), 1);
+        assert_eq!(missing.problems[0].operator_id, None);
+    }
+}
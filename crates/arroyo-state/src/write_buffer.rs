@@ -0,0 +1,287 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use arroyo_types::{duration_millis_config, u32_config};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Mutex;
+
+use crate::backpressure::{write_size, WriteBacklog};
+use crate::StateMessage;
+
+pub const WRITE_BUFFER_SIZE_ENV: &str = "STATE_WRITE_BUFFER_SIZE";
+pub const WRITE_BUFFER_MAX_AGE_MS_ENV: &str = "STATE_WRITE_BUFFER_MAX_AGE_MS";
+
+#[derive(Debug, Clone, Copy)]
+pub struct WriteBufferConfig {
+    /// The number of table writes to buffer before sending them to the flusher. 0 disables
+    /// buffering.
+    pub max_writes: usize,
+    /// How long a buffered write may wait for more writes to join it. Checked when a write
+    /// is made, so an idle buffer is sent by the next write or checkpoint.
+    pub max_age: Duration,
+}
+
+impl WriteBufferConfig {
+    pub fn from_env() -> Self {
+        Self {
+            max_writes: u32_config(WRITE_BUFFER_SIZE_ENV, 1024) as usize,
+            max_age: duration_millis_config(
+                WRITE_BUFFER_MAX_AGE_MS_ENV,
+                Duration::from_millis(100),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct WriteBuffer {
+    writes: Vec<StateMessage>,
+    oldest: Option<Instant>,
+}
+
+/// Sends the writes of a subtask's table views to its flusher, coalescing them into batches
+/// so that operators don't wait on the flusher's queue for every record.
+///
+/// The views of every table share one buffer, so writes reach the checkpointers in the
+/// order they were made. Views update their own state as soon as a write is made, so
+/// buffering only changes when a write reaches the checkpointer, not what operators see.
+/// Checkpoint and compaction messages flush the buffer before they're sent, so a checkpoint
+/// includes every write made before its barrier; writes still buffered when the subtask
+/// fails are lost along with the rest of the uncheckpointed epoch.
+///
+/// Writes are counted in the subtask's [`WriteBacklog`] until the flusher has applied them,
+/// and wait for it to drain when it's over its limit.
+#[derive(Debug, Clone)]
+pub struct StateSender {
+    sender: Sender<StateMessage>,
+    config: WriteBufferConfig,
+    buffer: Arc<Mutex<WriteBuffer>>,
+    backlog: Arc<WriteBacklog>,
+}
+
+impl StateSender {
+    pub fn new(
+        sender: Sender<StateMessage>,
+        config: WriteBufferConfig,
+        backlog: Arc<WriteBacklog>,
+    ) -> Self {
+        Self {
+            sender,
+            config,
+            buffer: Arc::default(),
+            backlog,
+        }
+    }
+
+    /// A sender that passes every message straight through.
+    pub fn unbuffered(sender: Sender<StateMessage>) -> Self {
+        Self::new(
+            sender,
+            WriteBufferConfig {
+                max_writes: 0,
+                max_age: Duration::ZERO,
+            },
+            Arc::new(WriteBacklog::new(None)),
+        )
+    }
+
+    pub fn backlog(&self) -> &Arc<WriteBacklog> {
+        &self.backlog
+    }
+
+    pub async fn send(&self, message: StateMessage) -> Result<()> {
+        let is_write = matches!(message, StateMessage::TableData { .. });
+        if is_write && self.backlog.is_backpressured() {
+            // buffered writes count towards the backlog, so hand them to the flusher to drain
+            self.flush().await?;
+            tokio::select! {
+                _ = self.backlog.wait_for_capacity() => {}
+                _ = self.sender.closed() => bail!("state flusher has shut down"),
+            }
+        }
+
+        // holding the lock while sending keeps batches from concurrent views in order
+        let mut buffer = self.buffer.lock().await;
+        if !is_write {
+            self.send_buffered(&mut buffer).await?;
+            return self.send_one(message).await;
+        }
+        self.backlog.add(write_size(&message));
+        if self.config.max_writes == 0 {
+            return self.send_one(message).await;
+        }
+        buffer.writes.push(message);
+        let oldest = *buffer.oldest.get_or_insert_with(Instant::now);
+        if buffer.writes.len() >= self.config.max_writes || oldest.elapsed() >= self.config.max_age
+        {
+            self.send_buffered(&mut buffer).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends any buffered writes to the flusher.
+    pub async fn flush(&self) -> Result<()> {
+        let mut buffer = self.buffer.lock().await;
+        self.send_buffered(&mut buffer).await
+    }
+
+    async fn send_buffered(&self, buffer: &mut WriteBuffer) -> Result<()> {
+        buffer.oldest = None;
+        if buffer.writes.is_empty() {
+            return Ok(());
+        }
+        let writes = std::mem::take(&mut buffer.writes);
+        self.send_one(StateMessage::TableDataBatch(writes)).await
+    }
+
+    async fn send_one(&self, message: StateMessage) -> Result<()> {
+        self.sender
+            .send(message)
+            .await
+            .map_err(|_| anyhow!("state flusher has shut down"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+
+    use bincode::config;
+    use tokio::sync::mpsc::{channel, Receiver};
+
+    use super::*;
+    use crate::state_serde::StateCodec;
+    use crate::tables::global_keyed_map::GlobalKeyedView;
+    use crate::{CheckpointMessage, TableData};
+
+    fn buffered(rx_capacity: usize, max_writes: usize) -> (StateSender, Receiver<StateMessage>) {
+        let (tx, rx) = channel(rx_capacity);
+        (
+            StateSender::new(
+                tx,
+                WriteBufferConfig {
+                    max_writes,
+                    max_age: Duration::from_secs(3600),
+                },
+                Arc::new(WriteBacklog::new(None)),
+            ),
+            rx,
+        )
+    }
+
+    fn checkpoint(epoch: u32) -> StateMessage {
+        StateMessage::Checkpoint(CheckpointMessage {
+            epoch,
+            time: SystemTime::now(),
+            watermark: None,
+            then_stop: false,
+            in_flight: false,
+        })
+    }
+
+    /// Applies writes the way the flusher does, returning the state as of the last completed
+    /// checkpoint.
+    fn restore(rx: &mut Receiver<StateMessage>) -> HashMap<String, u64> {
+        fn apply(pending: &mut HashMap<Vec<u8>, Option<Vec<u8>>>, message: StateMessage) {
+            match message {
+                StateMessage::TableData {
+                    data: TableData::KeyedData { key, value },
+                    ..
+                } => {
+                    pending.insert(key, Some(value));
+                }
+                StateMessage::TableData {
+                    data: TableData::KeyedDelete { key },
+                    ..
+                } => {
+                    pending.insert(key, None);
+                }
+                StateMessage::TableDataBatch(writes) => {
+                    for write in writes {
+                        apply(pending, write);
+                    }
+                }
+                message => panic!("unexpected message {:?}", message),
+            }
+        }
+
+        let mut checkpointed = HashMap::new();
+        let mut pending = HashMap::new();
+        while let Ok(message) = rx.try_recv() {
+            match message {
+                StateMessage::Checkpoint(_) => {
+                    for (key, value) in pending.drain() {
+                        match value {
+                            Some(value) => checkpointed.insert(key, value),
+                            None => checkpointed.remove(&key),
+                        };
+                    }
+                }
+                message => apply(&mut pending, message),
+            }
+        }
+        checkpointed
+            .into_iter()
+            .map(|(key, value)| {
+                (
+                    bincode::decode_from_slice(&key, config::standard())
+                        .unwrap()
+                        .0,
+                    bincode::decode_from_slice(&value, config::standard())
+                        .unwrap()
+                        .0,
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_batched_in_order() {
+        let (sender, mut rx) = buffered(100, 3);
+        let mut view: GlobalKeyedView<String, u64> = GlobalKeyedView::new(
+            "t".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            sender.clone(),
+        );
+        view.insert("a".to_string(), 1).await.unwrap();
+        view.insert("a".to_string(), 2).await.unwrap();
+        assert!(rx.try_recv().is_err());
+        view.delete(&"a".to_string()).await;
+        assert!(matches!(
+            rx.try_recv().unwrap(),
+            StateMessage::TableDataBatch(writes) if writes.len() == 3
+        ));
+
+        view.insert("a".to_string(), 3).await.unwrap();
+        sender.send(checkpoint(1)).await.unwrap();
+        assert_eq!(view.get(&"a".to_string()), Some(&3));
+        assert_eq!(&restore(&mut rx), view.get_all());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_buffer_restores_last_checkpoint() {
+        let (sender, mut rx) = buffered(100, 1000);
+        let mut view: GlobalKeyedView<String, u64> = GlobalKeyedView::new(
+            "t".to_string(),
+            HashMap::new(),
+            StateCodec::default(),
+            sender.clone(),
+        );
+        view.insert("a".to_string(), 1).await.unwrap();
+        view.insert("b".to_string(), 2).await.unwrap();
+        sender.send(checkpoint(1)).await.unwrap();
+        let checkpointed = view.get_all().clone();
+
+        view.insert("a".to_string(), 10).await.unwrap();
+        view.delete(&"b".to_string()).await;
+        view.insert("c".to_string(), 3).await.unwrap();
+        // the subtask fails before its next checkpoint
+        drop(view);
+        drop(sender);
+
+        assert_eq!(restore(&mut rx), checkpointed);
+    }
+}
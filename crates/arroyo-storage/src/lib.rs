@@ -11,14 +11,18 @@ use aws::ArroyoCredentialProvider;
 use bytes::Bytes;
 use futures::{Stream, StreamExt};
 use object_store::aws::{AmazonS3ConfigKey, AwsCredential};
-use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::azure::{AzureConfigKey, MicrosoftAzureBuilder};
+use object_store::gcp::{GoogleCloudStorageBuilder, GoogleConfigKey};
 use object_store::multipart::PartId;
 use object_store::path::Path;
 use object_store::{aws::AmazonS3Builder, local::LocalFileSystem, ObjectStore};
-use object_store::{CredentialProvider, MultipartId};
+use object_store::{CredentialProvider, MultipartId, PutMode};
 use regex::{Captures, Regex};
 use thiserror::Error;
 mod aws;
+pub mod retry;
+
+pub use retry::RetryPolicy;
 
 /// A reference-counted reference to a [StorageProvider].
 pub type StorageProviderRef = Arc<StorageProvider>;
@@ -32,6 +36,7 @@ pub struct StorageProvider {
     // May require storage_options to properly instantiate
     object_store_base_url: String,
     storage_options: HashMap<String, String>,
+    retry: RetryPolicy,
 }
 
 #[derive(Error, Debug)]
@@ -78,10 +83,18 @@ const GCS_PATH: &str =
     r"^https://storage\.googleapis\.com/(?P<bucket>[a-z\d\-_\.]+)(/(?P<key>.+))?$";
 const GCS_URL: &str = r"^[gG][sS]://(?P<bucket>[a-z0-9\-\.]+)(/(?P<key>.+))?$";
 
+// abfss://CONTAINER@ACCOUNT.dfs.core.windows.net/OBJECT_NAME
+const AZURE_ABFS: &str = r"^[aA][bB][fF][sS][sS]?://(?P<container>[a-z0-9\-]+)@(?P<account>[a-z0-9]+)\.dfs\.core\.windows\.net(/(?P<key>.+))?$";
+// https://ACCOUNT.blob.core.windows.net/CONTAINER/OBJECT_NAME
+const AZURE_HTTPS: &str = r"^https://(?P<account>[a-z0-9]+)\.(blob|dfs)\.core\.windows\.net/(?P<container>[a-z0-9\-]+)(/(?P<key>.+))?$";
+// az://CONTAINER/OBJECT_NAME, with the account from AZURE_STORAGE_ACCOUNT_NAME
+const AZURE_URL: &str = r"^[aA][zZ]://(?P<container>[a-z0-9\-]+)(/(?P<key>.+))?$";
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Copy)]
 enum Backend {
     S3,
     GCS,
+    Azure,
     Local,
 }
 
@@ -109,6 +122,15 @@ fn matchers() -> &'static HashMap<Backend, Vec<Regex>> {
             ],
         );
 
+        m.insert(
+            Backend::Azure,
+            vec![
+                Regex::new(AZURE_ABFS).unwrap(),
+                Regex::new(AZURE_HTTPS).unwrap(),
+                Regex::new(AZURE_URL).unwrap(),
+            ],
+        );
+
         m.insert(
             Backend::Local,
             vec![
@@ -122,31 +144,6 @@ fn matchers() -> &'static HashMap<Backend, Vec<Regex>> {
     })
 }
 
-macro_rules! retry {
-    ($e:expr) => {{
-        use std::thread::sleep;
-        use std::time::Duration;
-        use tracing::error;
-        let mut retries = 0;
-        let max_retries: u32 = 10;
-        let backoff_factor: u64 = 2;
-        loop {
-            match $e {
-                Ok(value) => break Ok(value),
-                Err(e) if retries < max_retries => {
-                    retries += 1;
-                    error!("Error: {}. Retrying...", e);
-                    // exponential backoff, capped at 10 seconds.
-                    let backoff_time =
-                        Duration::from_millis(10_000.min(100 * backoff_factor.pow(retries)));
-                    sleep(backoff_time);
-                }
-                Err(e) => break Err(e),
-            }
-        }
-    }};
-}
-
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct S3Config {
     endpoint: Option<String>,
@@ -172,6 +169,13 @@ pub struct GCSConfig {
     key: Option<String>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AzureConfig {
+    account: Option<String>,
+    container: String,
+    key: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LocalConfig {
     pub path: String,
@@ -182,6 +186,7 @@ pub struct LocalConfig {
 pub enum BackendConfig {
     S3(S3Config),
     GCS(GCSConfig),
+    Azure(AzureConfig),
     Local(LocalConfig),
 }
 
@@ -192,6 +197,7 @@ impl BackendConfig {
                 return match k {
                     Backend::S3 => Self::parse_s3(matches),
                     Backend::GCS => Self::parse_gcs(matches),
+                    Backend::Azure => Self::parse_azure(matches),
                     Backend::Local => Self::parse_local(matches, with_key),
                 };
             }
@@ -261,6 +267,27 @@ impl BackendConfig {
         Ok(BackendConfig::GCS(GCSConfig { bucket, key }))
     }
 
+    fn parse_azure(matches: Captures) -> Result<Self, StorageError> {
+        let container = matches
+            .name("container")
+            .expect("container should always be available")
+            .as_str()
+            .to_string();
+
+        let account = last([
+            std::env::var("AZURE_STORAGE_ACCOUNT_NAME").ok(),
+            matches.name("account").map(|m| m.as_str().to_string()),
+        ]);
+
+        let key = matches.name("key").map(|r| r.as_str().to_string());
+
+        Ok(BackendConfig::Azure(AzureConfig {
+            account,
+            container,
+            key,
+        }))
+    }
+
     fn parse_local(matches: Captures, with_key: bool) -> Result<Self, StorageError> {
         let path = matches
             .name("path")
@@ -291,6 +318,7 @@ impl BackendConfig {
         match self {
             BackendConfig::S3(s3) => s3.key.as_ref(),
             BackendConfig::GCS(gcs) => gcs.key.as_ref(),
+            BackendConfig::Azure(azure) => azure.key.as_ref(),
             BackendConfig::Local(local) => local.key.as_ref(),
         }
     }
@@ -318,7 +346,8 @@ impl StorageProvider {
 
         match config {
             BackendConfig::S3(config) => Self::construct_s3(config, options).await,
-            BackendConfig::GCS(config) => Self::construct_gcs(config),
+            BackendConfig::GCS(config) => Self::construct_gcs(config, options),
+            BackendConfig::Azure(config) => Self::construct_azure(config, options),
             BackendConfig::Local(config) => Self::construct_local(config).await,
         }
     }
@@ -335,7 +364,8 @@ impl StorageProvider {
 
         let provider = match config {
             BackendConfig::S3(config) => Self::construct_s3(config, options).await,
-            BackendConfig::GCS(config) => Self::construct_gcs(config),
+            BackendConfig::GCS(config) => Self::construct_gcs(config, options),
+            BackendConfig::Azure(config) => Self::construct_azure(config, options),
             BackendConfig::Local(config) => Self::construct_local(config).await,
         }?;
 
@@ -349,6 +379,7 @@ impl StorageProvider {
         let key = match &config {
             BackendConfig::S3(s3) => s3.key.as_ref(),
             BackendConfig::GCS(gcs) => gcs.key.as_ref(),
+            BackendConfig::Azure(azure) => azure.key.as_ref(),
             BackendConfig::Local(local) => local.key.as_ref(),
         }
         .ok_or_else(|| StorageError::NoKeyInUrl)?;
@@ -435,13 +466,22 @@ impl StorageProvider {
                 .into_iter()
                 .map(|(k, v)| (k.as_ref().to_string(), v))
                 .collect(),
+            retry: RetryPolicy::from_env(),
         })
     }
 
-    fn construct_gcs(config: GCSConfig) -> Result<Self, StorageError> {
-        let gcs = GoogleCloudStorageBuilder::from_env()
-            .with_bucket_name(&config.bucket)
-            .build()?;
+    fn construct_gcs(
+        config: GCSConfig,
+        options: HashMap<String, String>,
+    ) -> Result<Self, StorageError> {
+        let mut builder = GoogleCloudStorageBuilder::from_env().with_bucket_name(&config.bucket);
+        for (key, value) in &options {
+            let gcs_config_key: GoogleConfigKey = key.parse().map_err(|_| {
+                StorageError::CredentialsError(format!("invalid GCS config key: {}", key))
+            })?;
+            builder = builder.with_config(gcs_config_key, value);
+        }
+        let gcs = builder.build()?;
 
         let mut canonical_url = format!("https://{}.storage.googleapis.com", config.bucket);
         if let Some(key) = &config.key {
@@ -455,7 +495,51 @@ impl StorageProvider {
             object_store: Arc::new(gcs),
             object_store_base_url,
             canonical_url,
-            storage_options: HashMap::new(),
+            storage_options: options,
+            retry: RetryPolicy::from_env(),
+        })
+    }
+
+    fn construct_azure(
+        config: AzureConfig,
+        mut options: HashMap<String, String>,
+    ) -> Result<Self, StorageError> {
+        let mut builder = MicrosoftAzureBuilder::from_env().with_container_name(&config.container);
+        if let Some(account) = &config.account {
+            builder = builder.with_account(account);
+            // object_store_base_url doesn't include the account, so carry it in the options
+            options
+                .entry(AzureConfigKey::AccountName.as_ref().to_string())
+                .or_insert_with(|| account.clone());
+        }
+        for (key, value) in &options {
+            let azure_config_key: AzureConfigKey = key.parse().map_err(|_| {
+                StorageError::CredentialsError(format!("invalid Azure config key: {}", key))
+            })?;
+            builder = builder.with_config(azure_config_key, value);
+        }
+        let azure = builder.build()?;
+
+        let mut canonical_url = match &config.account {
+            Some(account) => format!(
+                "https://{}.blob.core.windows.net/{}",
+                account, config.container
+            ),
+            None => format!("az://{}", config.container),
+        };
+        if let Some(key) = &config.key {
+            canonical_url = format!("{}/{}", canonical_url, key);
+        }
+
+        let object_store_base_url = format!("az://{}", config.container);
+
+        Ok(Self {
+            config: BackendConfig::Azure(config),
+            object_store: Arc::new(azure),
+            object_store_base_url,
+            canonical_url,
+            storage_options: options,
+            retry: RetryPolicy::from_env(),
         })
     }
 
@@ -480,6 +564,7 @@ impl StorageProvider {
             canonical_url,
             object_store_base_url,
             storage_options: HashMap::new(),
+            retry: RetryPolicy::from_env(),
         })
     }
 
@@ -516,14 +601,34 @@ impl StorageProvider {
         Ok(list)
     }
 
+    /// Lists the objects under `prefix`, returning their paths relative to the provider's
+    /// key, as accepted by [`StorageProvider::get`].
+    pub async fn list_prefix<P: Into<String>>(
+        &self,
+        prefix: P,
+    ) -> Result<Vec<String>, StorageError> {
+        let prefix = self.qualify_path(&prefix.into().into());
+        let key_part_count = self
+            .config
+            .key()
+            .map(|key| Path::from(key.to_string()).parts().count())
+            .unwrap_or_default();
+        let mut paths = vec![];
+        let mut list = self.object_store.list(Some(&prefix));
+        while let Some(metadata) = list.next().await {
+            let path: Path = metadata?.location.parts().skip(key_part_count).collect();
+            paths.push(path.to_string());
+        }
+        Ok(paths)
+    }
+
     pub async fn get<P: Into<String>>(&self, path: P) -> Result<Bytes, StorageError> {
-        let path: String = path.into();
+        let path = self.qualify_path(&path.into().into());
         let bytes = self
-            .object_store
-            .get(&self.qualify_path(&path.into()))
-            .await
-            .map_err(|e| Into::<StorageError>::into(e))?
-            .bytes()
+            .retry
+            .run("get", path.as_ref(), || async {
+                self.object_store.get(&path).await?.bytes().await
+            })
             .await?;
 
         Ok(bytes)
@@ -533,16 +638,15 @@ impl StorageProvider {
         &self,
         path: P,
     ) -> Result<Option<Bytes>, StorageError> {
-        let path: String = path.into();
+        let path = self.qualify_path(&path.into().into());
         match self
-            .object_store
-            .get(&self.qualify_path(&path.into()))
+            .retry
+            .run("get", path.as_ref(), || async {
+                self.object_store.get(&path).await?.bytes().await
+            })
             .await
         {
-            Ok(obj) => {
-                let bytes = obj.bytes().await?;
-                Ok(Some(bytes))
-            }
+            Ok(bytes) => Ok(Some(bytes)),
             Err(err) => {
                 if let object_store::Error::NotFound { .. } = &err {
                     return Ok(None);
@@ -553,8 +657,11 @@ impl StorageProvider {
     }
 
     pub async fn exists<P: Into<Path>>(&self, path: P) -> Result<bool, StorageError> {
-        let path: Path = path.into();
-        let exists = self.object_store.head(&self.qualify_path(&path)).await;
+        let path = self.qualify_path(&path.into());
+        let exists = self
+            .retry
+            .run("head", path.as_ref(), || self.object_store.head(&path))
+            .await;
 
         match exists {
             Ok(_) => Ok(true),
@@ -569,7 +676,10 @@ impl StorageProvider {
     ) -> Result<impl tokio::io::AsyncRead, StorageError> {
         let path: Path = path.into().into();
 
-        let bytes = retry!(self.object_store.get(&path).await)
+        let bytes = self
+            .retry
+            .run("get", path.as_ref(), || self.object_store.get(&path))
+            .await
             .map_err(|e| Into::<StorageError>::into(e))?
             .into_stream();
 
@@ -582,17 +692,57 @@ impl StorageProvider {
         bytes: Vec<u8>,
     ) -> Result<String, StorageError> {
         let path = path.into().into();
+        let qualified = self.qualify_path(&path);
         let bytes: Bytes = bytes.into();
-        retry!(
-            self.object_store
-                .put(&self.qualify_path(&path), bytes.clone())
-                .await
-        )?;
+        self.retry
+            .run("put", qualified.as_ref(), || {
+                self.object_store.put(&qualified, bytes.clone())
+            })
+            .await?;
 
         Ok(format!("{}/{}", self.canonical_url, path))
     }
 
-    fn qualify_path(&self, path: &Path) -> Path {
+    /// Writes `bytes` to `path` only if nothing exists there yet, returning whether it was
+    /// written. Uses the store's conditional put where it has one (GCS, Azure and the local
+    /// filesystem); on stores without one (S3) this falls back to checking for the object
+    /// before writing it, which doesn't protect against a concurrent writer.
+    ///
+    /// A put that succeeds but times out on the way back may be retried and then report that
+    /// the object already exists.
+    pub async fn put_if_absent<P: Into<String>>(
+        &self,
+        path: P,
+        bytes: Vec<u8>,
+    ) -> Result<bool, StorageError> {
+        let path: Path = path.into().into();
+        let qualified = self.qualify_path(&path);
+        let bytes: Bytes = bytes.into();
+        let result = self
+            .retry
+            .run("put if absent", qualified.as_ref(), || {
+                self.object_store
+                    .put_opts(&qualified, bytes.clone(), PutMode::Create.into())
+            })
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::AlreadyExists { .. }) => Ok(false),
+            Err(object_store::Error::NotImplemented) => {
+                if self.exists(path.clone()).await? {
+                    return Ok(false);
+                }
+                self.put(path.to_string(), bytes.to_vec()).await?;
+                Ok(true)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The path of `path` in the underlying object store, under the key of the URL the
+    /// provider was created for, if it has one.
+    pub fn qualify_path(&self, path: &Path) -> Path {
         match self.config.key() {
             Some(prefix) => {
                 let prefix_path: Path = prefix.to_string().into();
@@ -603,8 +753,12 @@ impl StorageProvider {
     }
 
     pub async fn delete_if_present<P: Into<String>>(&self, path: P) -> Result<(), StorageError> {
-        let path = path.into();
-        return match self.object_store.delete(&path.into()).await {
+        let path: Path = path.into().into();
+        return match self
+            .retry
+            .run("delete", path.as_ref(), || self.object_store.delete(&path))
+            .await
+        {
             Ok(_) => Ok(()),
             Err(object_store::Error::NotFound { .. }) => Ok(()),
             Err(e) => Err(e.into()),
@@ -612,11 +766,14 @@ impl StorageProvider {
     }
 
     pub async fn start_multipart(&self, path: &Path) -> Result<MultipartId, StorageError> {
-        Ok(
-            retry!(self.object_store.initiate_multipart_upload(path).await)
-                .map_err(|e| Into::<StorageError>::into(e))?
-                .0,
-        )
+        Ok(self
+            .retry
+            .run("initiate multipart upload", path.as_ref(), || {
+                self.object_store.initiate_multipart_upload(path)
+            })
+            .await
+            .map_err(|e| Into::<StorageError>::into(e))?
+            .0)
     }
 
     pub async fn add_multipart(
@@ -626,14 +783,17 @@ impl StorageProvider {
         part_number: usize,
         bytes: Bytes,
     ) -> Result<PartId, StorageError> {
-        Ok(retry!(
-            self.object_store
-                .get_put_part(path, multipart_id)
-                .await?
-                .put_part(bytes.clone(), part_number)
-                .await
-        )
-        .map_err(|e| Into::<StorageError>::into(e))?)
+        Ok(self
+            .retry
+            .run("put part", path.as_ref(), || async {
+                self.object_store
+                    .get_put_part(path, multipart_id)
+                    .await?
+                    .put_part(bytes.clone(), part_number)
+                    .await
+            })
+            .await
+            .map_err(|e| Into::<StorageError>::into(e))?)
     }
 
     pub async fn close_multipart(
@@ -642,14 +802,76 @@ impl StorageProvider {
         multipart_id: &MultipartId,
         parts: Vec<PartId>,
     ) -> Result<(), StorageError> {
-        Ok(retry!(
-            self.object_store
-                .get_put_part(path, multipart_id)
-                .await?
-                .complete(parts.clone())
-                .await
-        )
-        .map_err(|e| Into::<StorageError>::into(e))?)
+        Ok(self
+            .retry
+            .run("complete multipart upload", path.as_ref(), || async {
+                self.object_store
+                    .get_put_part(path, multipart_id)
+                    .await?
+                    .complete(parts.clone())
+                    .await
+            })
+            .await
+            .map_err(|e| Into::<StorageError>::into(e))?)
+    }
+
+    pub async fn abort_multipart(
+        &self,
+        path: &Path,
+        multipart_id: &MultipartId,
+    ) -> Result<(), StorageError> {
+        Ok(self
+            .retry
+            .run("abort multipart upload", path.as_ref(), || {
+                self.object_store.abort_multipart(path, multipart_id)
+            })
+            .await?)
+    }
+
+    /// Writes `bytes` to `path`, as a multipart upload of `part_size` parts if they don't fit
+    /// in one. If any part fails the upload is aborted, so that no parts are left behind.
+    pub async fn put_in_parts<P: Into<String>>(
+        &self,
+        path: P,
+        bytes: Vec<u8>,
+        part_size: usize,
+    ) -> Result<String, StorageError> {
+        let path: String = path.into();
+        if bytes.len() <= part_size {
+            return self.put(path, bytes).await;
+        }
+        let qualified = self.qualify_path(&path.clone().into());
+        let multipart_id = self.start_multipart(&qualified).await?;
+        let bytes = Bytes::from(bytes);
+        let result = async {
+            let mut parts = vec![];
+            for (part_number, chunk) in bytes.chunks(part_size).enumerate() {
+                parts.push(
+                    self.add_multipart(
+                        &qualified,
+                        &multipart_id,
+                        part_number,
+                        bytes.slice_ref(chunk),
+                    )
+                    .await?,
+                );
+            }
+            self.close_multipart(&qualified, &multipart_id, parts).await
+        }
+        .await;
+        if let Err(e) = result {
+            if let Err(abort_error) = self.abort_multipart(&qualified, &multipart_id).await {
+                tracing::warn!(
+                    "failed to abort multipart upload {} of {}: {}",
+                    multipart_id,
+                    qualified,
+                    abort_error
+                );
+            }
+            return Err(e);
+        }
+
+        Ok(format!("{}/{}", self.canonical_url, path))
     }
 
     /// Produces a URL representation of this path that can be read by other systems,
@@ -677,6 +899,12 @@ impl StorageProvider {
         &self.config
     }
 
+    /// The policy used to retry this provider's operations, for callers that use the
+    /// backing store directly.
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
     pub fn get_backing_store(&self) -> Arc<dyn ObjectStore> {
         self.object_store.clone()
     }
@@ -684,9 +912,13 @@ impl StorageProvider {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::time::SystemTime;
 
     use arroyo_types::to_nanos;
+    use futures::StreamExt;
+    use object_store::azure::AzureConfigKey;
+    use object_store::gcp::GoogleConfigKey;
 
     use crate::{matchers, BackendConfig, StorageProvider};
 
@@ -796,6 +1028,166 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_gcs_configs() {
+        assert_eq!(
+            BackendConfig::parse_url("gs://my-bucket/checkpoints/job-1", false).unwrap(),
+            BackendConfig::GCS(crate::GCSConfig {
+                bucket: "my-bucket".to_string(),
+                key: Some("checkpoints/job-1".to_string()),
+            })
+        );
+
+        assert_eq!(
+            BackendConfig::parse_url("https://my-bucket.storage.googleapis.com/a/b", false)
+                .unwrap(),
+            BackendConfig::GCS(crate::GCSConfig {
+                bucket: "my-bucket".to_string(),
+                key: Some("a/b".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_azure_configs() {
+        assert_eq!(
+            BackendConfig::parse_url(
+                "abfss://my-container@myaccount.dfs.core.windows.net/checkpoints/job-1",
+                false
+            )
+            .unwrap(),
+            BackendConfig::Azure(crate::AzureConfig {
+                account: Some("myaccount".to_string()),
+                container: "my-container".to_string(),
+                key: Some("checkpoints/job-1".to_string()),
+            })
+        );
+
+        assert_eq!(
+            BackendConfig::parse_url("abfs://my-container@myaccount.dfs.core.windows.net", false)
+                .unwrap(),
+            BackendConfig::Azure(crate::AzureConfig {
+                account: Some("myaccount".to_string()),
+                container: "my-container".to_string(),
+                key: None,
+            })
+        );
+
+        assert_eq!(
+            BackendConfig::parse_url(
+                "https://myaccount.blob.core.windows.net/my-container/a/b",
+                false
+            )
+            .unwrap(),
+            BackendConfig::Azure(crate::AzureConfig {
+                account: Some("myaccount".to_string()),
+                container: "my-container".to_string(),
+                key: Some("a/b".to_string()),
+            })
+        );
+
+        let BackendConfig::Azure(config) =
+            BackendConfig::parse_url("az://my-container/a/b", false).unwrap()
+        else {
+            panic!("expected an azure config");
+        };
+        assert_eq!(config.container, "my-container");
+        assert_eq!(config.key, Some("a/b".to_string()));
+    }
+
+    /// Runs the operations checkpointing relies on against `storage`.
+    async fn check_checkpoint_operations(storage: StorageProvider) {
+        let now = to_nanos(SystemTime::now());
+        let prefix = format!("storage-tests/{}", now);
+        let data = now.to_le_bytes().to_vec();
+
+        let key = format!("{}/data", prefix);
+        storage.put(&key, data.clone()).await.unwrap();
+        assert_eq!(storage.get(&key).await.unwrap(), data);
+        assert!(storage.exists(key.clone()).await.unwrap());
+
+        let metadata = format!("{}/metadata", prefix);
+        assert!(storage
+            .put_if_absent(&metadata, b"first".to_vec())
+            .await
+            .unwrap());
+        assert!(!storage
+            .put_if_absent(&metadata, b"second".to_vec())
+            .await
+            .unwrap());
+        assert_eq!(storage.get(&metadata).await.unwrap(), &b"first"[..]);
+
+        let mut listed: Vec<_> = storage
+            .list(true)
+            .await
+            .unwrap()
+            .map(|path| path.unwrap().to_string())
+            .filter(|path| std::future::ready(path.contains(&prefix)))
+            .collect()
+            .await;
+        listed.sort();
+        assert_eq!(listed.len(), 2);
+
+        for path in listed {
+            storage.delete_if_present(path).await.unwrap();
+        }
+        assert!(storage.get_if_present(&key).await.unwrap().is_none());
+        assert!(!storage.exists(metadata).await.unwrap());
+    }
+
+    /// The URL of a fake-gcs-server with an `arroyo-test` bucket, e.g.
+    /// `http://localhost:4443`, for `cargo test -- --ignored test_gcs_emulator`.
+    const GCS_EMULATOR_ENV: &str = "ARROYO_TEST_GCS_EMULATOR_URL";
+
+    /// The blob endpoint of an Azurite instance with an `arroyo-test` container, e.g.
+    /// `http://127.0.0.1:10000/devstoreaccount1`, for `cargo test -- --ignored test_azurite`.
+    const AZURITE_ENV: &str = "ARROYO_TEST_AZURITE_URL";
+
+    #[tokio::test]
+    #[ignore = "requires fake-gcs-server; set ARROYO_TEST_GCS_EMULATOR_URL"]
+    async fn test_gcs_emulator() {
+        let url = std::env::var(GCS_EMULATOR_ENV)
+            .unwrap_or_else(|_| panic!("{} must be set to run this test", GCS_EMULATOR_ENV));
+
+        // fake-gcs-server doesn't check credentials, so point a dummy service account at it
+        let service_account = format!(
+            r#"{{"gcs_base_url": "{}", "disable_oauth": true, "client_email": "", "private_key": "", "private_key_id": ""}}"#,
+            url
+        );
+        let storage = StorageProvider::for_url_with_options(
+            "gs://arroyo-test",
+            HashMap::from([(
+                GoogleConfigKey::ServiceAccountKey.as_ref().to_string(),
+                service_account,
+            )]),
+        )
+        .await
+        .unwrap();
+
+        check_checkpoint_operations(storage).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Azurite; set ARROYO_TEST_AZURITE_URL"]
+    async fn test_azurite() {
+        let url = std::env::var(AZURITE_ENV)
+            .unwrap_or_else(|_| panic!("{} must be set to run this test", AZURITE_ENV));
+
+        // the emulator uses the well-known development account and key
+        std::env::set_var("AZURITE_BLOB_STORAGE_URL", url);
+        let storage = StorageProvider::for_url_with_options(
+            "az://arroyo-test",
+            HashMap::from([(
+                AzureConfigKey::UseEmulator.as_ref().to_string(),
+                "true".to_string(),
+            )]),
+        )
+        .await
+        .unwrap();
+
+        check_checkpoint_operations(storage).await;
+    }
+
     #[tokio::test]
     async fn test_local_fs() {
         let storage = StorageProvider::for_url("file:///tmp/arroyo-testing/storage-tests")
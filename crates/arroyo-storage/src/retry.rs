@@ -0,0 +1,213 @@
+use std::future::Future;
+use std::time::Duration;
+
+use arroyo_types::{duration_millis_config, u32_config};
+use rand::Rng;
+use tracing::warn;
+
+pub const STORAGE_RETRY_MAX_RETRIES_ENV: &str = "STORAGE_RETRY_MAX_RETRIES";
+pub const STORAGE_RETRY_BASE_BACKOFF_MS_ENV: &str = "STORAGE_RETRY_BASE_BACKOFF_MS";
+pub const STORAGE_RETRY_MAX_BACKOFF_MS_ENV: &str = "STORAGE_RETRY_MAX_BACKOFF_MS";
+pub const STORAGE_RETRY_JITTER_PERCENT_ENV: &str = "STORAGE_RETRY_JITTER_PERCENT";
+
+/// How object store operations are retried. The backoff before retry `n` (starting at 1) is
+/// `base_backoff * 2^(n-1)`, capped at `max_backoff`, and then reduced by a random amount of
+/// up to `jitter` of itself so that many subtasks retrying together spread out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Between 0 (no jitter) and 1.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 10,
+            base_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(10),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            max_retries: u32_config(STORAGE_RETRY_MAX_RETRIES_ENV, default.max_retries),
+            base_backoff: duration_millis_config(
+                STORAGE_RETRY_BASE_BACKOFF_MS_ENV,
+                default.base_backoff,
+            ),
+            max_backoff: duration_millis_config(
+                STORAGE_RETRY_MAX_BACKOFF_MS_ENV,
+                default.max_backoff,
+            ),
+            jitter: u32_config(
+                STORAGE_RETRY_JITTER_PERCENT_ENV,
+                (default.jitter * 100.0) as u32,
+            )
+            .min(100) as f64
+                / 100.0,
+        }
+    }
+
+    /// A policy that makes a single attempt.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// The backoff before `retry` (starting at 1), without jitter.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.base_backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+
+    fn jittered_backoff(&self, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        if self.jitter <= 0.0 {
+            return backoff;
+        }
+        backoff.mul_f64(1.0 - rand::thread_rng().gen_range(0.0..=self.jitter.min(1.0)))
+    }
+
+    /// Runs `f` until it succeeds, fails with an error that isn't retryable, or runs out of
+    /// retries. `operation` and `path` are used to log each retry.
+    pub async fn run<T, F, Fut>(
+        &self,
+        operation: &str,
+        path: &str,
+        mut f: F,
+    ) -> Result<T, object_store::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, object_store::Error>>,
+    {
+        let mut retries = 0;
+        loop {
+            match f().await {
+                Ok(value) => return Ok(value),
+                Err(e) if retries < self.max_retries && is_retryable(&e) => {
+                    retries += 1;
+                    let backoff = self.jittered_backoff(retries);
+                    warn!(
+                        message = "retrying storage operation",
+                        operation,
+                        path,
+                        retry = retries,
+                        max_retries = self.max_retries,
+                        backoff_ms = backoff.as_millis() as u64,
+                        error = %e
+                    );
+                    tokio::time::sleep(backoff).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Whether an operation that failed with `error` may succeed if tried again. Errors that
+/// describe the request or the object (a missing object, a bad path, an unsupported
+/// operation) won't change on retry; everything else, including errors from the store's
+/// HTTP client, is treated as transient.
+pub fn is_retryable(error: &object_store::Error) -> bool {
+    !matches!(
+        error,
+        object_store::Error::NotFound { .. }
+            | object_store::Error::InvalidPath { .. }
+            | object_store::Error::NotSupported { .. }
+            | object_store::Error::AlreadyExists { .. }
+            | object_store::Error::Precondition { .. }
+            | object_store::Error::NotModified { .. }
+            | object_store::Error::NotImplemented
+            | object_store::Error::UnknownConfigurationKey { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            jitter: 0.5,
+        }
+    }
+
+    fn transient() -> object_store::Error {
+        object_store::Error::Generic {
+            store: "test",
+            source: "503 Slow Down".into(),
+        }
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let policy = policy();
+        let backoffs: Vec<_> = (1..=5).map(|retry| policy.backoff(retry)).collect();
+        assert_eq!(
+            backoffs,
+            [1, 2, 4, 4, 4].map(Duration::from_millis).to_vec()
+        );
+        for retry in 1..=5 {
+            let jittered = policy.jittered_backoff(retry);
+            assert!(jittered <= policy.backoff(retry));
+            assert!(jittered >= policy.backoff(retry) / 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_errors() {
+        let attempts = AtomicU32::new(0);
+        let result = policy()
+            .run("put", "a", || async {
+                if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(transient())
+                } else {
+                    Ok(())
+                }
+            })
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = policy()
+            .run("put", "a", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(transient())
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_permanent_errors() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = policy()
+            .run("get", "a", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(object_store::Error::NotFound {
+                    path: "a".to_string(),
+                    source: "missing".into(),
+                })
+            })
+            .await;
+        assert!(matches!(result, Err(object_store::Error::NotFound { .. })));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -117,6 +117,10 @@ pub const API_METRICS_RATE_ENV: &str = "API_METRICS_RATE";
 pub const S3_ENDPOINT_ENV: &str = "S3_ENDPOINT";
 pub const S3_REGION_ENV: &str = "S3_REGION";
 pub const CHECKPOINT_URL_ENV: &str = "CHECKPOINT_URL";
+pub const CHECKPOINT_SLA_MAX_DURATION_MS_ENV: &str = "CHECKPOINT_SLA_MAX_DURATION_MS";
+pub const CHECKPOINT_SLA_MAX_INTERVAL_MS_ENV: &str = "CHECKPOINT_SLA_MAX_INTERVAL_MS";
+pub const CHECKPOINT_ALIGNMENT_TIMEOUT_MS_ENV: &str = "CHECKPOINT_ALIGNMENT_TIMEOUT_MS";
+pub const ALLOW_NON_RESTORED_STATE_ENV: &str = "ALLOW_NON_RESTORED_STATE";
 
 // compiler service
 pub const ARTIFACT_URL_ENV: &str = "ARTIFACT_URL";
@@ -747,6 +751,9 @@ pub struct CheckpointBarrier {
     pub min_epoch: u32,
     pub timestamp: SystemTime,
     pub then_stop: bool,
+    /// Operators checkpoint on the first barrier they receive instead of waiting for all of
+    /// their inputs, capturing the records that arrive ahead of the remaining barriers.
+    pub unaligned: bool,
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Hash, Serialize)]
@@ -874,6 +881,28 @@ pub fn range_for_server(i: usize, n: usize) -> RangeInclusive<u64> {
     start..=end
 }
 
+/// The number of key groups that keyed state is hashed into by default. It bounds the
+/// parallelism keyed state can be spread over, and can't be changed for a job once it has
+/// state.
+pub const DEFAULT_KEY_GROUPS: u32 = 128;
+
+/// The key group, out of `key_groups`, of a key with hash `x`.
+pub fn key_group_for_hash(x: u64, key_groups: u32) -> u32 {
+    server_for_hash(x, key_groups as usize) as u32
+}
+
+/// The contiguous range of key groups holding the hashes in `range`.
+pub fn key_groups_for_range(range: &RangeInclusive<u64>, key_groups: u32) -> Range<u32> {
+    key_group_for_hash(*range.start(), key_groups)..key_group_for_hash(*range.end(), key_groups) + 1
+}
+
+/// The key groups holding the keys routed to subtask `i` of `n`, by [`server_for_hash`].
+/// Unless `n` divides the number of groups, the groups at either end are shared with the
+/// neighbouring subtasks, which hold the rest of their keys.
+pub fn key_groups_for_server(i: usize, n: usize, key_groups: u32) -> Range<u32> {
+    key_groups_for_range(&range_for_server(i, n), key_groups)
+}
+
 pub fn should_flush(size: usize, time: Instant) -> bool {
     static FLUSH_SIZE: OnceLock<usize> = OnceLock::new();
     let flush_size =
@@ -924,6 +953,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_key_groups_for_server() {
+        for (n, key_groups) in [(1, 128), (3, 128), (4, 128), (7, 10), (5, 3)] {
+            let mut next = 0;
+            for i in 0..n {
+                let groups = key_groups_for_server(i, n, key_groups);
+                assert!(
+                    groups.start == next || groups.start + 1 == next,
+                    "groups not contiguous"
+                );
+                next = groups.end;
+            }
+            assert_eq!(next, key_groups, "not every group is held");
+
+            // every key is in one of the groups of the subtask it's routed to
+            for x in (0..1000u64).map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15)) {
+                let groups = key_groups_for_server(server_for_hash(x, n), n, key_groups);
+                assert!(groups.contains(&key_group_for_hash(x, key_groups)));
+            }
+        }
+        assert_eq!(key_groups_for_server(1, 4, 128), 32..64);
+
+        assert_eq!(key_group_for_hash(0, 128), 0);
+        assert_eq!(key_group_for_hash(u64::MAX, 128), 127);
+    }
+
     #[test]
     fn test_server_for_hash() {
         let n = 2;
@@ -123,7 +123,7 @@ impl InstantJoin {
             .downcast_ref::<TimestampNanosecondArray>()
             .expect("should have timestamp column");
         let max_timestamp = max(time_column).expect("should have max timestamp");
-        table.insert(from_nanos(max_timestamp as u128), batch.clone());
+        table.insert(from_nanos(max_timestamp as u128), batch.clone())?;
         let min_timestamp = min(time_column).expect("should have min timestamp");
         if ctx
             .last_present_watermark()
@@ -842,7 +842,9 @@ impl ArrowOperator for SessionAggregatingWindowFunc {
             .downcast_ref::<TimestampNanosecondArray>()
             .expect("should have max timestamp"))
         .unwrap();
-        table.insert(from_nanos(max_timestamp as u128), sorted.clone());
+        table
+            .insert(from_nanos(max_timestamp as u128), sorted.clone())
+            .expect("should be able to insert into state");
 
         self.add_at_watermark(sorted, current_watermark)
             .await
@@ -871,7 +873,8 @@ impl ArrowOperator for SessionAggregatingWindowFunc {
             .await
             .unwrap()
             .insert(ctx.task_info.task_index, self.earliest_batch_time())
-            .await;
+            .await
+            .unwrap();
     }
 
     fn tables(&self) -> HashMap<String, TableConfig> {
@@ -143,7 +143,7 @@ impl SlidingAggregatingWindowFunc<SystemTime> {
                     columns.push(timestamp_array);
                     let state_batch =
                         RecordBatch::try_new(self.partial_schema.schema.clone(), columns).unwrap();
-                    partial_table.insert(bin_start, state_batch);
+                    partial_table.insert(bin_start, state_batch)?;
                     bin_exec.finished_batches.push(batch);
                 }
             }
@@ -693,7 +693,9 @@ impl ArrowOperator for SlidingAggregatingWindowFunc<SystemTime> {
                 columns.push(timestamp_array);
                 let state_batch =
                     RecordBatch::try_new(self.partial_schema.schema.clone(), columns).unwrap();
-                table.insert(*bin, state_batch);
+                table
+                    .insert(*bin, state_batch)
+                    .expect("should be able to insert into state");
                 exec.finished_batches.push(batch);
             }
         }
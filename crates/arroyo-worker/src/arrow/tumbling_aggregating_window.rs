@@ -417,7 +417,9 @@ impl ArrowOperator for TumblingAggregatingWindowFunc<SystemTime> {
                     self.partial_schema.schema.clone(),
                 )
                 .expect("should be able to add timestamp");
-                table.insert(*bin, state_batch);
+                table
+                    .insert(*bin, state_batch)
+                    .expect("should be able to insert into state");
                 exec.finished_batches.push(batch);
             }
         }
@@ -148,7 +148,9 @@ impl ArrowOperator for WindowFunctionOperator {
             .filter_and_split_batches(batch, current_watermark)
             .unwrap()
         {
-            table.insert(timestamp, batch.clone());
+            table
+                .insert(timestamp, batch.clone())
+                .expect("should be able to insert into state");
             let bin_exec = self.get_or_insert_exec(timestamp).await;
             bin_exec.sender.send(batch).unwrap();
         }
@@ -338,7 +338,8 @@ impl FileSystemSourceFunc {
                         .await
                         .unwrap()
                         .insert(file.clone(), (file.clone(), read_state.clone()))
-                        .await;
+                        .await
+                        .unwrap();
                 }
                 // checkpoint our state
                 if self.start_checkpoint(c, ctx).await {
@@ -11,7 +11,7 @@ use arroyo_rpc::df::ArroyoSchema;
 use bincode::{Decode, Encode};
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
 
 use crate::arrow::instant_join::InstantJoinConstructor;
 use crate::arrow::join_with_expiration::JoinWithExpirationConstructor;
@@ -32,7 +32,7 @@ use arroyo_operator::operator::Registry;
 use arroyo_operator::ErasedConstructor;
 use arroyo_rpc::grpc::{api, CheckpointMetadata, TaskAssignment};
 use arroyo_rpc::{ControlMessage, ControlResp};
-use arroyo_state::{BackingStore, StateBackend};
+use arroyo_state::remapping::load_checkpoint_for_restore;
 use arroyo_types::{
     range_for_server, u32_config, Key, TaskInfo, WorkerId, DEFAULT_QUEUE_SIZE, QUEUE_SIZE_ENV,
 };
@@ -472,7 +472,7 @@ impl Engine {
         let checkpoint_metadata = if let Some(epoch) = config.restore_epoch {
             info!("Restoring checkpoint {} for job {}", epoch, self.job_id);
             Some(
-                StateBackend::load_checkpoint_metadata(&self.job_id, epoch)
+                load_checkpoint_for_restore(&self.job_id, epoch)
                     .await
                     .expect(&format!(
                         "failed to load checkpoint metadata for epoch {}",
@@ -723,6 +723,31 @@ impl Engine {
             tables,
         )
         .await;
+        let ctx = match ctx {
+            Ok(ctx) => ctx,
+            Err(e) => {
+                error!(
+                    message = "failed to start subtask",
+                    operator_id = %operator_id,
+                    task_index,
+                    error = format!("{:#}", e)
+                );
+                control_tx
+                    .send(ControlResp::TaskFailed {
+                        operator_id,
+                        task_index,
+                        error: format!("{:#}", e),
+                    })
+                    .await
+                    .ok();
+                // don't hold back the other local subtasks, which wait for all of them to
+                // start; the controller restarts the job on the failure sent above
+                tokio::spawn(async move {
+                    ready.wait().await;
+                });
+                return;
+            }
+        };
 
         let operator = Box::new(node.node);
         let join_task = tokio::spawn(async move {
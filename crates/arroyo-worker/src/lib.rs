@@ -313,6 +313,8 @@ impl WorkerServer {
                                         subtask_index: c.subtask_index,
                                         epoch: c.checkpoint_epoch,
                                         event_type: c.event_type as i32,
+                                        in_flight_records: c.in_flight_records,
+                                        in_flight_bytes: c.in_flight_bytes,
                                     }
                                 )).await.err()
                             }
@@ -520,6 +522,7 @@ impl WorkerGrpc for WorkerServer {
             min_epoch: req.min_epoch,
             timestamp: from_millis(req.timestamp),
             then_stop: req.then_stop,
+            unaligned: req.unaligned,
         };
 
         for n in &senders {
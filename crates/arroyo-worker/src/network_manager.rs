@@ -585,6 +585,7 @@ mod test {
             min_epoch: 3,
             timestamp: SystemTime::now(),
             then_stop: false,
+            unaligned: false,
         }));
 
         client_tx.send(message.clone()).await.unwrap();
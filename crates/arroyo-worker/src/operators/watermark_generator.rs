@@ -176,7 +176,9 @@ impl ArrowOperator for WatermarkGenerator {
             .await
             .expect("state");
 
-        gs.insert(ctx.task_info.task_index, self.state_cache).await;
+        gs.insert(ctx.task_info.task_index, self.state_cache)
+            .await
+            .expect("state");
     }
 
     async fn handle_tick(&mut self, _: u64, ctx: &mut ArrowContext) {
@@ -221,6 +221,9 @@ pub async fn main() {
             checkpoint_interval_micros: None,
             parallelism: None,
             stop: Some(Some(StopType::Checkpoint)),
+            unaligned_checkpoints: None,
+            checkpoint_sla_max_duration_micros: None,
+            checkpoint_sla_max_interval_micros: None,
         },
     )
     .await
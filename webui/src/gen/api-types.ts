@@ -423,6 +423,7 @@ export interface components {
       bytes: number;
       operatorId: string;
       subtasks: (components["schemas"]["SubtaskCheckpointGroup"])[];
+      tables: (components["schemas"]["TableStorageUsage"])[];
     };
     OperatorCheckpointGroupCollection: {
       data: (components["schemas"]["OperatorCheckpointGroup"])[];
@@ -487,9 +488,24 @@ export interface components {
     PipelinePatch: {
       /** Format: int64 */
       checkpointIntervalMicros?: number | null;
+      /**
+       * Format: int64
+       * @description Checkpoints must complete within this long of starting; 0 disables the limit
+       */
+      checkpointSlaMaxDurationMicros?: number | null;
+      /**
+       * Format: int64
+       * @description A checkpoint must complete at least this often; 0 disables the limit
+       */
+      checkpointSlaMaxIntervalMicros?: number | null;
       /** Format: int64 */
       parallelism?: number | null;
       stop?: components["schemas"]["StopType"] | null;
+      /**
+       * @description Checkpoint without waiting for barrier alignment, capturing the records still in
+       * flight instead
+       */
+      unalignedCheckpoints?: boolean | null;
     };
     PipelinePost: {
       name: string;
@@ -534,17 +550,38 @@ export interface components {
       name?: string | null;
     };
     SubtaskCheckpointGroup: {
+      alignmentTimedOut: boolean;
       /** Format: int64 */
       bytes: number;
       eventSpans: (components["schemas"]["CheckpointEventSpan"])[];
+      files: (components["schemas"]["SubtaskStateFile"])[];
       /** Format: int32 */
       index: number;
+      /** Format: int64 */
+      omittedFileBytes: number;
+      /** Format: int64 */
+      omittedFiles: number;
     };
     SubtaskMetrics: {
       /** Format: int32 */
       index: number;
       metrics: (components["schemas"]["Metric"])[];
     };
+    SubtaskStateFile: {
+      /** Format: int64 */
+      bytes?: number | null;
+      path: string;
+      tableName: string;
+    };
+    TableStorageUsage: {
+      /** Format: int64 */
+      bytes: number;
+      /** Format: int64 */
+      files: number;
+      /** Format: int32 */
+      oldestEpoch?: number | null;
+      tableName: string;
+    };
     TestSourceMessage: {
       done: boolean;
       error: boolean;